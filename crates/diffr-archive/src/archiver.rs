@@ -1,59 +1,145 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use diffr_core::models::archive::{ArchiveEntry, ArchiveReason, CompressionFormat};
-use diffr_core::models::drive::{Drive, DriveRole};
-use std::path::{Path, PathBuf};
+use diffr_core::models::drive::{Drive, DriveId};
+use std::io::{Read, Write};
+use std::path::Path;
 use uuid::Uuid;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Files are read, hashed and compressed this many bytes at a time, so
+/// memory use stays about this plus zstd's window whatever their size.
+pub(crate) const BUF_SIZE: usize = 1024 * 1024;
+
+/// Archives of files at least this big report progress as they go.
+pub const PROGRESS_MIN_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Called with bytes done and total bytes while a large file is archived.
+pub type Progress<'a> = &'a mut dyn FnMut(u64, u64);
 
 /// Archive a file before it is overwritten or deleted.
 pub fn archive_file(
     drive: &Drive,
     rel_path: &Path,
     reason: ArchiveReason,
+) -> anyhow::Result<ArchiveEntry> {
+    archive_file_with_progress(drive, rel_path, reason, &mut |_, _| {})
+}
+
+/// [`archive_file`], reporting progress for files of at least
+/// [`PROGRESS_MIN_BYTES`].
+pub fn archive_file_with_progress(
+    drive: &Drive,
+    rel_path: &Path,
+    reason: ArchiveReason,
+    progress: Progress,
 ) -> anyhow::Result<ArchiveEntry> {
     let source_path = drive.effective_root().join(rel_path);
     if !source_path.exists() {
         anyhow::bail!("source file does not exist: {}", source_path.display());
     }
+    store_version(drive, &source_path, rel_path, reason, Utc::now(), progress)
+}
 
-    let metadata = std::fs::metadata(&source_path)?;
-    let original_size = metadata.len();
+/// Archive a file on `drive` into `store`'s archive store instead, as a
+/// cluster with a central archive drive does, with `compression`. The
+/// entry records `drive` as where the file was taken from, so it restores
+/// there.
+pub fn archive_file_to(
+    drive: &Drive,
+    store: &Drive,
+    rel_path: &Path,
+    reason: ArchiveReason,
+    compression: CompressionFormat,
+    progress: Progress,
+) -> anyhow::Result<ArchiveEntry> {
+    let source_path = drive.effective_root().join(rel_path);
+    if !source_path.exists() {
+        anyhow::bail!("source file does not exist: {}", source_path.display());
+    }
+    if store.id == drive.id {
+        return store_tagged(drive, &source_path, rel_path, "", reason, Utc::now(), compression, progress);
+    }
+    // Several drives can archive the same path in the same second; the
+    // source drive in the name keeps their versions apart.
+    let tag = drive_tag(&drive.id);
+    let mut entry = store_tagged(store, &source_path, rel_path, &tag, reason, Utc::now(), compression, progress)?;
+    entry.relocated_from = Some(drive.id.clone());
+    Ok(entry)
+}
 
-    // Determine compression based on drive role
-    let compression = match drive.role {
-        DriveRole::ArchiveOnly | DriveRole::ArchiveAssist => CompressionFormat::Zstd,
-        DriveRole::Normal => CompressionFormat::Zstd,
-    };
+/// Compress `source_path` into the drive's archive store as a version of
+/// `rel_path` taken at `archived_at`. The source can be anywhere, such as a
+/// folder of old backups.
+pub fn store_version(
+    drive: &Drive,
+    source_path: &Path,
+    rel_path: &Path,
+    reason: ArchiveReason,
+    archived_at: DateTime<Utc>,
+    progress: Progress,
+) -> anyhow::Result<ArchiveEntry> {
+    store_tagged(drive, source_path, rel_path, "", reason, archived_at, CompressionFormat::Zstd, progress)
+}
 
-    // Build archive path: .diffr/archive/<rel_path>/<timestamp>.zst
-    let archive_id = Uuid::now_v7();
-    let timestamp = Utc::now().format("%Y%m%dT%H%M%S");
+/// The tag that names `drive` in the file name of an archive of one of its
+/// files stored on another drive.
+pub(crate) fn drive_tag(drive: &DriveId) -> String {
+    format!("-{}", &drive.0.simple().to_string()[..8])
+}
+
+/// An archive's file name: when it was taken, `tag`, and the extension for
+/// its compression.
+pub(crate) fn version_file_name(archived_at: DateTime<Utc>, tag: &str, compression: &CompressionFormat) -> String {
     let ext = match compression {
         CompressionFormat::Zstd => ".zst",
         CompressionFormat::None => "",
     };
-    let archive_rel = PathBuf::from(".diffr")
-        .join("archive")
+    format!("{}{}{}", archived_at.format("%Y%m%dT%H%M%S"), tag, ext)
+}
+
+/// [`store_version`], with `tag` added to the archive's file name after
+/// the timestamp, stored with `compression`.
+#[allow(clippy::too_many_arguments)]
+fn store_tagged(
+    drive: &Drive,
+    source_path: &Path,
+    rel_path: &Path,
+    tag: &str,
+    reason: ArchiveReason,
+    archived_at: DateTime<Utc>,
+    compression: CompressionFormat,
+    progress: Progress,
+) -> anyhow::Result<ArchiveEntry> {
+    let metadata = std::fs::metadata(source_path)?;
+    let original_size = metadata.len();
+
+    // Build archive path: .diffr/archive/<rel_path>/<timestamp>.zst
+    let archive_id = Uuid::now_v7();
+    let archive_path = crate::gc::archive_dir(drive)
         .join(rel_path)
-        .join(format!("{}{}", timestamp, ext));
-    let archive_path = drive.effective_root().join(&archive_rel);
+        .join(version_file_name(archived_at, tag, &compression));
+    let archive_rel = drive.stored_path(&archive_path);
 
     // Create archive directory
     if let Some(parent) = archive_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
 
-    // Compress and write
-    let compressed_size = match compression {
-        CompressionFormat::Zstd => compress_zstd(&source_path, &archive_path)?,
+    // Compress and write, hashing the original for verification on the way
+    let output = std::fs::File::create(&archive_path)?;
+    let xxh3_hash = match compression {
+        CompressionFormat::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(output, 3)?;
+            let hash = copy_hashed(source_path, &mut encoder, original_size, progress)?;
+            encoder.finish()?;
+            hash
+        }
         CompressionFormat::None => {
-            std::fs::copy(&source_path, &archive_path)?;
-            original_size
+            let mut output = output;
+            copy_hashed(source_path, &mut output, original_size, progress)?
         }
     };
-
-    // Compute hash of original file for verification
-    let data = std::fs::read(&source_path)?;
-    let xxh3_hash = format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&data));
+    let compressed_size = std::fs::metadata(&archive_path)?.len();
 
     Ok(ArchiveEntry {
         id: archive_id,
@@ -65,17 +151,49 @@ pub fn archive_file(
         compression,
         xxh3_hash,
         reason,
-        archived_at: Utc::now(),
+        archived_at,
+        relocated_from: None,
+        relocated_at: None,
     })
 }
 
-/// Compress a file using zstd.
-fn compress_zstd(src: &Path, dst: &Path) -> anyhow::Result<u64> {
-    let input = std::fs::read(src)?;
-    let compressed = zstd::encode_all(input.as_slice(), 3)?;
-    let size = compressed.len() as u64;
-    std::fs::write(dst, &compressed)?;
-    Ok(size)
+/// Copy `src` into `out` a buffer at a time, returning the XXH3 hash of
+/// what was copied. Progress is reported for files of at least
+/// [`PROGRESS_MIN_BYTES`].
+fn copy_hashed(src: &Path, out: &mut impl Write, total: u64, progress: Progress) -> anyhow::Result<String> {
+    let mut input = std::fs::File::open(src)?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; BUF_SIZE];
+    let mut done = 0u64;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        done += n as u64;
+        if total >= PROGRESS_MIN_BYTES {
+            progress(done, total);
+        }
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// The XXH3 hash of everything `reader` yields, and how many bytes that was.
+pub(crate) fn hash_reader(mut reader: impl Read) -> std::io::Result<(String, u64)> {
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; BUF_SIZE];
+    let mut len = 0u64;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+    Ok((format!("{:016x}", hasher.digest()), len))
 }
 
 #[cfg(test)]
@@ -98,7 +216,7 @@ mod tests {
         let entry = archive_file(&drive, Path::new("test.txt"), ArchiveReason::BeforeOverwrite)
             .unwrap();
 
-        assert_eq!(entry.original_path, PathBuf::from("test.txt"));
+        assert_eq!(entry.original_path, std::path::PathBuf::from("test.txt"));
         assert!(entry.compressed_size > 0);
         assert_eq!(entry.compression, CompressionFormat::Zstd);
 
@@ -106,4 +224,30 @@ mod tests {
         let archive_full = dir.path().join(&entry.archive_path);
         assert!(archive_full.exists());
     }
+
+    #[test]
+    fn test_archive_streams_across_buffers() {
+        let dir = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..BUF_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join("big.bin"), &content).unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+
+        let mut calls = 0;
+        let entry = archive_file_with_progress(
+            &drive,
+            Path::new("big.bin"),
+            ArchiveReason::Manual,
+            &mut |_, _| calls += 1,
+        )
+        .unwrap();
+        // Too small to report progress for.
+        assert_eq!(calls, 0);
+        assert_eq!(entry.original_size, content.len() as u64);
+        assert_eq!(entry.xxh3_hash, format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&content)));
+        assert!(entry.compressed_size < entry.original_size);
+
+        let dest = dir.path().join("restored.bin");
+        crate::retriever::restore_file(&drive, &entry, Some(&dest)).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), content);
+    }
 }
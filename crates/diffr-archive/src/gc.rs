@@ -0,0 +1,273 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diffr_core::models::archive::{ArchiveEntry, ArchiveReason, CompressionFormat};
+use diffr_core::models::drive::Drive;
+use diffr_db::ops;
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::archiver::hash_reader;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// How long an unrecorded archive file is left alone, since a sync running
+/// now may be about to record it.
+pub const GRACE_PERIOD: Duration = Duration::hours(1);
+
+/// A file in a drive's archive directory that no archive record points to,
+/// left behind by a crash between writing it and recording it, or by edits
+/// to the database.
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    /// Where the file is, as stored (see [`Drive::stored_path`]).
+    pub archive_path: PathBuf,
+    /// The file it's a version of, going by where it's stored.
+    pub original_path: PathBuf,
+    /// When it was archived, going by its name.
+    pub archived_at: Option<DateTime<Utc>>,
+    pub size: u64,
+}
+
+/// Totals from [`collect`].
+#[derive(Debug, Default)]
+pub struct GcResult {
+    pub removed: usize,
+    pub bytes_freed: u64,
+    pub imported: usize,
+    pub errors: Vec<String>,
+}
+
+/// The archive store for a drive: `archive` in its `.diffr` directory.
+pub fn archive_dir(drive: &Drive) -> PathBuf {
+    drive.diffr_dir().join("archive")
+}
+
+/// The stored path on `to` for the archive file at `archive_path` on
+/// `from`: the same place under `to`'s archive store.
+pub fn rebase_archive_path(archive_path: &Path, from: &Drive, to: &Drive) -> PathBuf {
+    let full = from.effective_root().join(archive_path);
+    match full.strip_prefix(archive_dir(from)) {
+        Ok(rel) => to.stored_path(&archive_dir(to).join(rel)),
+        Err(_) => archive_path.to_path_buf(),
+    }
+}
+
+/// Find the files under the drive's archive directory with no archive
+/// record on that drive, leaving out any modified within `grace`.
+pub fn find_orphans(conn: &Connection, drive: &Drive, grace: Duration) -> anyhow::Result<Vec<Orphan>> {
+    let dir = archive_dir(drive);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut known: HashSet<PathBuf> = ops::list_archives_for_drive(conn, &drive.id)?
+        .into_iter()
+        .map(|a| a.archive_path)
+        .collect();
+    known.extend(
+        ops::list_archive_parts_for_drive(conn, &drive.id)?
+            .into_iter()
+            .map(|p| p.part_path),
+    );
+
+    let cutoff = Utc::now() - grace;
+    let mut orphans = Vec::new();
+    for entry in walkdir::WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let archive_path = drive.stored_path(entry.path());
+        if known.contains(&archive_path) {
+            continue;
+        }
+        let modified: Option<DateTime<Utc>> = entry.metadata().ok().and_then(|m| m.modified().ok()).map(Into::into);
+        if modified.is_none_or(|m| m > cutoff) {
+            continue;
+        }
+        // Versions are stored as `<original path>/<timestamp>[-<drive>][.zst]`,
+        // the drive only when archived centrally.
+        let original_path = entry
+            .path()
+            .parent()
+            .and_then(|p| p.strip_prefix(&dir).ok())
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        let archived_at = entry
+            .path()
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.get(..s.find('-').unwrap_or(s.len())))
+            .and_then(|s| NaiveDateTime::parse_from_str(s, TIMESTAMP_FORMAT).ok())
+            .map(|t| t.and_utc());
+        orphans.push(Orphan {
+            archive_path,
+            original_path,
+            archived_at,
+            size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+        });
+    }
+    orphans.sort_by(|a, b| a.archive_path.cmp(&b.archive_path));
+    Ok(orphans)
+}
+
+/// Remove orphans from disk, or with `import` record them as archives of
+/// the file they're stored under. Imported orphans are recorded with the
+/// Manual reason since what prompted them is lost; ones that can't be read
+/// back are left where they are.
+pub fn collect(
+    conn: &Connection,
+    drive: &Drive,
+    orphans: &[Orphan],
+    import: bool,
+) -> anyhow::Result<GcResult> {
+    let root = drive.effective_root();
+    let mut result = GcResult::default();
+    for orphan in orphans {
+        let path = root.join(&orphan.archive_path);
+        if import {
+            match import_orphan(drive, orphan).and_then(|e| ops::insert_archive(conn, &e)) {
+                Ok(()) => result.imported += 1,
+                Err(e) => result
+                    .errors
+                    .push(format!("{}: {}", orphan.archive_path.display(), e)),
+            }
+        } else {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    result.removed += 1;
+                    result.bytes_freed += orphan.size;
+                }
+                Err(e) => result.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+    if !import {
+        remove_empty_dirs(&archive_dir(drive));
+    }
+    Ok(result)
+}
+
+/// Build the record for an orphan, checking that it decompresses.
+fn import_orphan(drive: &Drive, orphan: &Orphan) -> anyhow::Result<ArchiveEntry> {
+    if orphan.original_path.as_os_str().is_empty() {
+        anyhow::bail!("not stored under an original path");
+    }
+    let path = drive.effective_root().join(&orphan.archive_path);
+    let stored = std::fs::File::open(&path)?;
+    let compressed_size = stored.metadata()?.len();
+    let compression = match path.extension().and_then(|e| e.to_str()) {
+        Some("zst") => CompressionFormat::Zstd,
+        _ => CompressionFormat::None,
+    };
+    let (xxh3_hash, original_size) = match compression {
+        CompressionFormat::Zstd => hash_reader(zstd::stream::read::Decoder::new(stored)?)?,
+        CompressionFormat::None => hash_reader(stored)?,
+    };
+    let archived_at = match orphan.archived_at {
+        Some(t) => t,
+        None => std::fs::metadata(&path)?.modified()?.into(),
+    };
+    Ok(ArchiveEntry {
+        id: Uuid::now_v7(),
+        original_path: orphan.original_path.clone(),
+        archive_path: orphan.archive_path.clone(),
+        drive_id: drive.id.clone(),
+        original_size,
+        compressed_size,
+        compression,
+        xxh3_hash,
+        reason: ArchiveReason::Manual,
+        archived_at,
+        relocated_from: None,
+        relocated_at: None,
+    })
+}
+
+/// Remove directories under `dir` left empty, deepest first.
+fn remove_empty_dirs(dir: &Path) {
+    let dirs: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+        .map(|e| e.into_path())
+        .collect();
+    for d in dirs {
+        // Fails, as it should, for directories that still have files.
+        let _ = std::fs::remove_dir(d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver;
+    use diffr_core::models::drive::DriveIdentity;
+    use diffr_db::open_memory_db;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_gc_orphans() {
+        let dir = TempDir::new().unwrap();
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        ops::insert_drive(&conn, &drive).unwrap();
+        std::fs::write(dir.path().join("kept.txt"), "recorded").unwrap();
+        std::fs::write(dir.path().join("lost.txt"), "never recorded").unwrap();
+
+        let kept = archiver::archive_file(&drive, Path::new("kept.txt"), ArchiveReason::BeforeOverwrite)
+            .unwrap();
+        ops::insert_archive(&conn, &kept).unwrap();
+        // Written but never recorded, as when a sync dies in between.
+        let lost = archiver::archive_file(&drive, Path::new("lost.txt"), ArchiveReason::BeforeDelete)
+            .unwrap();
+
+        // Too new to be sure no sync is about to record it.
+        assert!(find_orphans(&conn, &drive, GRACE_PERIOD).unwrap().is_empty());
+        let orphans = find_orphans(&conn, &drive, Duration::zero()).unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].archive_path, lost.archive_path);
+        assert_eq!(orphans[0].original_path, PathBuf::from("lost.txt"));
+        assert!(orphans[0].archived_at.is_some());
+
+        let result = collect(&conn, &drive, &orphans, true).unwrap();
+        assert_eq!((result.imported, result.errors.len()), (1, 0));
+        let imported = ops::list_archives_for_path(&conn, Path::new("lost.txt")).unwrap();
+        assert_eq!(imported[0].xxh3_hash, lost.xxh3_hash);
+        assert_eq!(imported[0].original_size, lost.original_size);
+        assert!(find_orphans(&conn, &drive, Duration::zero()).unwrap().is_empty());
+
+        // Once its record is gone again, removing deletes the file and its
+        // now-empty directory.
+        ops::delete_archive(&conn, &imported[0].id).unwrap();
+        let orphans = find_orphans(&conn, &drive, Duration::zero()).unwrap();
+        let result = collect(&conn, &drive, &orphans, false).unwrap();
+        assert_eq!(result.removed, 1);
+        assert!(!dir.path().join(".diffr/archive/lost.txt").exists());
+        assert!(dir.path().join(&kept.archive_path).exists());
+    }
+
+    #[test]
+    fn test_external_metadata_dir() {
+        let (root, meta, other) = (TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap());
+        let conn = open_memory_db().unwrap();
+        let mut drive = Drive::new(DriveIdentity::new_synthetic(), root.path().to_path_buf());
+        drive.metadata_dir = Some(meta.path().to_path_buf());
+        ops::insert_drive(&conn, &drive).unwrap();
+        std::fs::write(root.path().join("a.txt"), "old").unwrap();
+
+        // Versions go to the metadata directory and are recorded by full path.
+        let entry = archiver::archive_file(&drive, Path::new("a.txt"), ArchiveReason::Manual).unwrap();
+        ops::insert_archive(&conn, &entry).unwrap();
+        assert!(entry.archive_path.starts_with(meta.path().join("archive")));
+        assert!(!root.path().join(".diffr").exists());
+        assert!(find_orphans(&conn, &drive, Duration::zero()).unwrap().is_empty());
+
+        // Moved to a drive that keeps its own, they land under its root.
+        let to = Drive::new(DriveIdentity::new_synthetic(), other.path().to_path_buf());
+        let rebased = rebase_archive_path(&entry.archive_path, &drive, &to);
+        assert!(rebased.starts_with(".diffr/archive/a.txt"));
+        assert_eq!(rebase_archive_path(&rebased, &to, &drive), entry.archive_path);
+    }
+}
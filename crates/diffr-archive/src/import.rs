@@ -0,0 +1,127 @@
+use chrono::{DateTime, Utc};
+use diffr_core::models::archive::ArchiveReason;
+use diffr_core::models::drive::Drive;
+use diffr_db::ops;
+use rusqlite::Connection;
+use std::path::Path;
+
+use crate::archiver;
+
+/// Totals from [`import_dir`].
+#[derive(Debug, Default)]
+pub struct ImportResult {
+    pub imported: usize,
+    /// Bytes imported, before compression.
+    pub bytes: u64,
+    /// Files skipped because the same content was already archived for
+    /// that path.
+    pub duplicates: usize,
+    pub errors: Vec<String>,
+}
+
+/// Archive every file under `dir` on `drive` as an old version of the file
+/// at the same place under `original_prefix`. Each version is dated
+/// `archived_at` if given, or by the file's mtime.
+///
+/// Importing the same folder again only adds what's new.
+pub fn import_dir(
+    conn: &Connection,
+    drive: &Drive,
+    dir: &Path,
+    original_prefix: &Path,
+    archived_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<ImportResult> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    let mut result = ImportResult::default();
+    let walk = walkdir::WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".diffr");
+    for file in walk.filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+        let rel_path = original_prefix.join(file.path().strip_prefix(dir).unwrap_or(file.path()));
+        match import_file(conn, drive, file.path(), &rel_path, archived_at) {
+            Ok(Some(size)) => {
+                result.imported += 1;
+                result.bytes += size;
+            }
+            Ok(None) => result.duplicates += 1,
+            Err(e) => result.errors.push(format!("{}: {}", file.path().display(), e)),
+        }
+    }
+    Ok(result)
+}
+
+/// Archive one file, returning its size, or `None` if that content is
+/// already archived for `rel_path`.
+fn import_file(
+    conn: &Connection,
+    drive: &Drive,
+    source: &Path,
+    rel_path: &Path,
+    archived_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<Option<u64>> {
+    let archived_at = match archived_at {
+        Some(t) => t,
+        None => std::fs::metadata(source)?.modified()?.into(),
+    };
+    let (hash, _) = archiver::hash_reader(std::fs::File::open(source)?)?;
+    let existing = ops::list_archives_for_path(conn, rel_path)?;
+    if existing.iter().any(|a| a.xxh3_hash == hash) {
+        return Ok(None);
+    }
+    // Versions are stored by the second; don't write over one already there.
+    let second = archived_at.format("%Y%m%dT%H%M%S").to_string();
+    if existing
+        .iter()
+        .any(|a| a.archived_at.format("%Y%m%dT%H%M%S").to_string() == second)
+    {
+        anyhow::bail!("a different version from {} is already archived", archived_at);
+    }
+
+    let entry = archiver::store_version(
+        drive,
+        source,
+        rel_path,
+        ArchiveReason::Manual,
+        archived_at,
+        &mut |_, _| {},
+    )?;
+    ops::insert_archive(conn, &entry)?;
+    Ok(Some(entry.original_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use diffr_db::open_memory_db;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_import_dir() {
+        let (root, backup) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), root.path().to_path_buf());
+        ops::insert_drive(&conn, &drive).unwrap();
+        std::fs::create_dir_all(backup.path().join("sub")).unwrap();
+        std::fs::write(backup.path().join("a.txt"), "old a").unwrap();
+        std::fs::write(backup.path().join("sub/b.txt"), "old b").unwrap();
+
+        let result = import_dir(&conn, &drive, backup.path(), Path::new("Projects"), None).unwrap();
+        assert_eq!((result.imported, result.bytes, result.errors.len()), (2, 10, 0));
+        let b = ops::list_archives_for_path(&conn, Path::new("Projects/sub/b.txt")).unwrap();
+        assert_eq!(b[0].reason, ArchiveReason::Manual);
+        assert_eq!(b[0].original_path, PathBuf::from("Projects/sub/b.txt"));
+        assert!(root.path().join(&b[0].archive_path).exists());
+
+        // A second run only picks up what changed.
+        std::fs::write(backup.path().join("a.txt"), "older a").unwrap();
+        let when = Utc::now() - chrono::Duration::days(400);
+        let result = import_dir(&conn, &drive, backup.path(), Path::new("Projects"), Some(when)).unwrap();
+        assert_eq!((result.imported, result.duplicates), (1, 1));
+        assert_eq!(ops::list_archives_for_path(&conn, Path::new("Projects/a.txt")).unwrap().len(), 2);
+    }
+}
@@ -1,3 +1,8 @@
 pub mod archiver;
+pub mod gc;
+pub mod import;
+pub mod placement;
+pub mod replication;
 pub mod retention;
 pub mod retriever;
+pub mod trash;
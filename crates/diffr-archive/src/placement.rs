@@ -0,0 +1,386 @@
+use chrono::Utc;
+use diffr_core::models::archive::{ArchiveEntry, ArchivePart};
+use diffr_core::models::drive::{Drive, DriveId, DriveRole};
+use diffr_db::ops;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Pick a drive to receive `bytes` of relocated archives from `source`.
+///
+/// Only connected archive-only/archive-assist drives are considered. Drives
+/// with known free space must have room (and stay under `cap` if set);
+/// among those, the one with the most free space wins. Drives with unknown
+/// free space are used only as a last resort.
+pub fn choose_target<'a>(
+    conn: &Connection,
+    source: &Drive,
+    candidates: &'a [Drive],
+    bytes: u64,
+    cap: Option<u64>,
+) -> anyhow::Result<Option<&'a Drive>> {
+    let mut eligible = Vec::new();
+    for drive in candidates {
+        if !can_hold_archives(drive, source) {
+            continue;
+        }
+        if drive.free_bytes.is_some_and(|free| free < bytes) {
+            continue;
+        }
+        if let Some(cap) = cap {
+            if ops::get_total_archive_size(conn, &drive.id)? + bytes > cap {
+                continue;
+            }
+        }
+        eligible.push(drive);
+    }
+
+    eligible.sort_by_key(|d| std::cmp::Reverse(d.free_bytes.map(|f| f as u128 + 1).unwrap_or(0)));
+    Ok(eligible.into_iter().next())
+}
+
+/// Pick the archive drive a cluster archiving centrally writes `bytes` of
+/// `source`'s files to: the connected archive drive with the most known free
+/// space that has room, or one with unknown free space if that's all there
+/// is. `archived` is what's been written to each drive since its free space
+/// was read, and counts against it. `None` means archive on `source` itself.
+pub fn central_target<'a>(
+    source: &Drive,
+    candidates: impl IntoIterator<Item = &'a Drive>,
+    bytes: u64,
+    archived: &HashMap<DriveId, u64>,
+) -> Option<&'a Drive> {
+    let free = |d: &Drive| {
+        d.free_bytes
+            .map(|f| f.saturating_sub(archived.get(&d.id).copied().unwrap_or(0)))
+    };
+    candidates
+        .into_iter()
+        .filter(|d| can_hold_archives(d, source))
+        .filter(|d| free(d).is_none_or(|free| free >= bytes))
+        .max_by_key(|d| (free(d).map(|f| f as u128 + 1).unwrap_or(0), std::cmp::Reverse(d.id.0)))
+}
+
+/// Whether `drive` is a connected, writable archive drive other than
+/// `source`.
+fn can_hold_archives(drive: &Drive, source: &Drive) -> bool {
+    drive.id != source.id
+        && !drive.read_only
+        && matches!(drive.role, DriveRole::ArchiveOnly | DriveRole::ArchiveAssist)
+        && drive.effective_root().exists()
+}
+
+/// Split an archive from `from` into parts across archive drives in
+/// `candidates`, for one too big for any of them alone. Only drives with
+/// known free space are used, the roomiest first, each filled up to its
+/// free space (or what's left under `cap`). Returns `None`, leaving the
+/// archive where it is, when they don't have enough room between them.
+pub fn span_archive(
+    conn: &Connection,
+    entry: &ArchiveEntry,
+    from: &Drive,
+    candidates: &[Drive],
+    cap: Option<u64>,
+) -> anyhow::Result<Option<Vec<ArchivePart>>> {
+    let mut rooms = Vec::new();
+    for drive in candidates.iter().filter(|d| can_hold_archives(d, from)) {
+        let Some(mut room) = drive.free_bytes else {
+            continue;
+        };
+        if let Some(cap) = cap {
+            room = room.min(cap.saturating_sub(ops::get_total_archive_size(conn, &drive.id)?));
+        }
+        if room > 0 {
+            rooms.push((drive, room));
+        }
+    }
+    rooms.sort_by_key(|(_, room)| std::cmp::Reverse(*room));
+    if rooms.iter().map(|(_, room)| room).sum::<u64>() < entry.compressed_size {
+        return Ok(None);
+    }
+
+    let src = from.effective_root().join(&entry.archive_path);
+    let mut input = std::fs::File::open(&src)?;
+    let mut parts = Vec::new();
+    let mut remaining = entry.compressed_size;
+    for (index, (drive, room)) in rooms.into_iter().enumerate() {
+        if remaining == 0 {
+            break;
+        }
+        let size = room.min(remaining);
+        let stored = crate::gc::rebase_archive_path(&entry.archive_path, from, drive);
+        let part_path = PathBuf::from(format!("{}.part{:03}", stored.display(), index));
+        let part = ArchivePart {
+            archive_id: entry.id,
+            index: index as u32,
+            drive_id: drive.id.clone(),
+            part_path,
+            size,
+        };
+        if let Err(e) = write_part(&mut input, drive, &part) {
+            remove_parts(&parts, candidates);
+            return Err(e);
+        }
+        parts.push(part);
+        remaining -= size;
+    }
+
+    // Every part is recorded, and the archive moved to them, or none is:
+    // parts recorded without the move would be deleted with the original.
+    let mut moved = entry.clone();
+    moved.drive_id = parts[0].drive_id.clone();
+    moved.relocated_from = Some(entry.relocated_from.clone().unwrap_or_else(|| from.id.clone()));
+    moved.relocated_at = Some(Utc::now());
+    let recorded = (|| -> anyhow::Result<()> {
+        let tx = conn.unchecked_transaction()?;
+        for part in &parts {
+            ops::insert_archive_part(&tx, part)?;
+        }
+        ops::update_archive_location(&tx, &moved)?;
+        tx.commit()?;
+        Ok(())
+    })();
+    if let Err(e) = recorded {
+        remove_parts(&parts, candidates);
+        return Err(e);
+    }
+
+    std::fs::remove_file(&src)?;
+    Ok(Some(parts))
+}
+
+/// Copy the next `part.size` bytes of `input` to the part's drive. The
+/// part is written beside its place and renamed into it once complete and
+/// flushed, so a crash never leaves a torn part under the part's name.
+fn write_part(input: &mut std::fs::File, drive: &Drive, part: &ArchivePart) -> anyhow::Result<()> {
+    let dst = drive.effective_root().join(&part.part_path);
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = PathBuf::from(format!("{}.tmp", dst.display()));
+    let written = (|| {
+        let mut out = std::fs::File::create(&temp)?;
+        let copied = std::io::copy(&mut input.take(part.size), &mut out)?;
+        if copied != part.size {
+            anyhow::bail!(
+                "short read writing {}: expected {} bytes, got {}",
+                dst.display(),
+                part.size,
+                copied
+            );
+        }
+        out.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, &dst)?;
+    Ok(())
+}
+
+/// Delete the files of `parts` from whichever of `drives` hold them.
+/// Returns one error message per part that couldn't be removed.
+pub fn remove_parts(parts: &[ArchivePart], drives: &[Drive]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for part in parts {
+        let Some(drive) = drives.iter().find(|d| d.id == part.drive_id) else {
+            errors.push(format!("part {} is on a drive that isn't registered", part.part_path.display()));
+            continue;
+        };
+        let path = drive.effective_root().join(&part.part_path);
+        if let Err(e) = std::fs::remove_file(&path) {
+            if path.exists() {
+                errors.push(format!("failed to delete {}: {}", path.display(), e));
+            }
+        }
+    }
+    errors
+}
+
+/// Move an archive file from `from` to `to` and record the new location.
+/// The file keeps the same path under the target's archive store.
+pub fn relocate_archive(
+    conn: &Connection,
+    entry: &ArchiveEntry,
+    from: &Drive,
+    to: &Drive,
+) -> anyhow::Result<ArchiveEntry> {
+    let src = from.effective_root().join(&entry.archive_path);
+    let archive_path = crate::gc::rebase_archive_path(&entry.archive_path, from, to);
+    let dst = to.effective_root().join(&archive_path);
+    if !src.exists() {
+        anyhow::bail!("archive file does not exist: {}", src.display());
+    }
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let copied = std::fs::copy(&src, &dst)?;
+    if copied != entry.compressed_size {
+        let _ = std::fs::remove_file(&dst);
+        anyhow::bail!(
+            "size mismatch relocating {}: expected {}, copied {}",
+            src.display(),
+            entry.compressed_size,
+            copied
+        );
+    }
+
+    let mut moved = entry.clone();
+    moved.drive_id = to.id.clone();
+    moved.archive_path = archive_path;
+    moved.relocated_from = Some(entry.relocated_from.clone().unwrap_or_else(|| from.id.clone()));
+    moved.relocated_at = Some(Utc::now());
+    ops::update_archive_location(conn, &moved)?;
+
+    std::fs::remove_file(&src)?;
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver;
+    use diffr_core::models::archive::{ArchiveReason, CompressionFormat};
+    use diffr_core::models::drive::DriveIdentity;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relocate_to_archive_drive() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        std::fs::write(src_dir.path().join("a.txt"), "version one").unwrap();
+
+        let source = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let mut normal = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        normal.free_bytes = Some(u64::MAX);
+        let mut archive_drive = normal.clone();
+        archive_drive.id = diffr_core::models::drive::DriveId::new();
+        archive_drive.identity = DriveIdentity::new_synthetic();
+        archive_drive.role = DriveRole::ArchiveOnly;
+        for d in [&source, &normal, &archive_drive] {
+            ops::insert_drive(&conn, d).unwrap();
+        }
+
+        let entry =
+            archiver::archive_file(&source, Path::new("a.txt"), ArchiveReason::Manual).unwrap();
+        ops::insert_archive(&conn, &entry).unwrap();
+
+        let candidates = vec![normal, archive_drive.clone()];
+        let target = choose_target(&conn, &source, &candidates, entry.compressed_size, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(target.id, archive_drive.id);
+
+        let moved = relocate_archive(&conn, &entry, &source, target).unwrap();
+        assert!(!src_dir.path().join(&entry.archive_path).exists());
+        assert!(dst_dir.path().join(&entry.archive_path).exists());
+
+        let stored = ops::get_archive_by_id(&conn, &entry.id).unwrap().unwrap();
+        assert_eq!(stored.drive_id, archive_drive.id);
+        assert_eq!(stored.relocated_from, Some(source.id.clone()));
+        assert_eq!(moved.relocated_from, stored.relocated_from);
+    }
+
+    #[test]
+    fn test_span_archive() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let src_dir = TempDir::new().unwrap();
+        let (dir_a, dir_b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        // Incompressible, so the archive stays about this size.
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let content: Vec<u8> = (0..4000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        std::fs::write(src_dir.path().join("big.bin"), &content).unwrap();
+
+        let source = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let mut targets = Vec::new();
+        for dir in [&dir_a, &dir_b] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.role = DriveRole::ArchiveOnly;
+            drive.free_bytes = Some(3000);
+            targets.push(drive);
+        }
+        for d in std::iter::once(&source).chain(&targets) {
+            ops::insert_drive(&conn, d).unwrap();
+        }
+        let entry =
+            archiver::archive_file(&source, Path::new("big.bin"), ArchiveReason::Manual).unwrap();
+        ops::insert_archive(&conn, &entry).unwrap();
+        assert!(entry.compressed_size > 3000);
+
+        assert!(choose_target(&conn, &source, &targets, entry.compressed_size, None)
+            .unwrap()
+            .is_none());
+        let parts = span_archive(&conn, &entry, &source, &targets, None).unwrap().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts.iter().map(|p| p.size).sum::<u64>(), entry.compressed_size);
+        assert!(!src_dir.path().join(&entry.archive_path).exists());
+        assert_eq!(ops::get_total_archive_size(&conn, &targets[1].id).unwrap(), parts[1].size);
+        // Each part was written under a temporary name and renamed into place.
+        for (part, dir) in parts.iter().zip([&dir_a, &dir_b]) {
+            let written = dir.path().join(&part.part_path);
+            assert_eq!(std::fs::metadata(&written).unwrap().len(), part.size);
+            assert!(!PathBuf::from(format!("{}.tmp", written.display())).exists());
+        }
+
+        let stored = ops::get_archive_by_id(&conn, &entry.id).unwrap().unwrap();
+        let dest = src_dir.path().join("restored.bin");
+        crate::retriever::restore_archive(&conn, &targets, &stored, Some(&dest)).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), content);
+
+        // Asking for more room than the drives have between them leaves
+        // the archive alone.
+        let bigger = ArchiveEntry {
+            compressed_size: 7000,
+            ..entry
+        };
+        assert!(span_archive(&conn, &bigger, &source, &targets, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_central_archive() {
+        let (src_dir, archive_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src_dir.path().join("a.txt"), "version one").unwrap();
+        let source = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let mut archive_drive = Drive::new(DriveIdentity::new_synthetic(), archive_dir.path().to_path_buf());
+        archive_drive.role = DriveRole::ArchiveAssist;
+
+        // No archive drive connected: archive where the file is.
+        let gone = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().join("missing"));
+        let none = HashMap::new();
+        assert!(central_target(&source, [&source, &gone], 11, &none).is_none());
+
+        // What this run already archived there counts against its room.
+        archive_drive.free_bytes = Some(100);
+        let full = HashMap::from([(archive_drive.id.clone(), 95)]);
+        assert!(central_target(&source, [&source, &archive_drive], 11, &full).is_none());
+
+        let store = central_target(&source, [&source, &archive_drive], 11, &none).unwrap();
+        assert_eq!(store.id, archive_drive.id);
+        let entry = archiver::archive_file_to(
+            &source,
+            store,
+            Path::new("a.txt"),
+            ArchiveReason::BeforeDelete,
+            CompressionFormat::Zstd,
+            &mut |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(entry.drive_id, archive_drive.id);
+        assert_eq!(entry.relocated_from, Some(source.id.clone()));
+        assert!(archive_dir.path().join(&entry.archive_path).exists());
+        assert!(!src_dir.path().join(".diffr/archive").exists());
+    }
+}
@@ -0,0 +1,216 @@
+use chrono::Utc;
+use diffr_core::models::archive::ArchiveEntry;
+use diffr_core::models::drive::{Drive, DriveId, DriveRole};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::archiver::{drive_tag, version_file_name};
+
+/// What [`replicate_archives`] copied.
+#[derive(Debug, Default)]
+pub struct ReplicationResult {
+    /// One entry per copy made, for the caller to record like any other
+    /// archive.
+    pub copies: Vec<ArchiveEntry>,
+    pub bytes: u64,
+    /// Copies that weren't made, and why.
+    pub errors: Vec<String>,
+}
+
+/// Whether sync copies archives onto `drive`: a connected, writable,
+/// archive-only drive.
+fn is_replica_target(drive: &Drive) -> bool {
+    drive.role == DriveRole::ArchiveOnly
+        && !drive.read_only
+        && !drive.locked
+        && drive.effective_root().exists()
+}
+
+/// Copy each of `archives` onto every archive-only drive in `drives` that's
+/// connected, so versions a sync took on a drive survive losing it. A copy
+/// is an archive of its own, stored on the archive-only drive and recorded
+/// as taken from the drive the original was, so it restores there.
+/// Archives already on an archive-only drive aren't copied again.
+pub fn replicate_archives(archives: &[ArchiveEntry], drives: &[Drive]) -> ReplicationResult {
+    let targets: Vec<&Drive> = drives.iter().filter(|d| is_replica_target(d)).collect();
+    let originals = archives.iter().filter(|a| {
+        drives
+            .iter()
+            .find(|d| d.id == a.drive_id)
+            .is_some_and(|from| from.role != DriveRole::ArchiveOnly)
+    });
+    copy_archives(originals, drives, &targets)
+}
+
+/// Copy each of `archives` onto each of `targets`, whatever their role, as
+/// [`replicate_archives`] does for archive-only drives. Targets that are
+/// read-only, locked or not connected are reported as errors, and versions
+/// a target already holds are left alone.
+pub fn replicate_archives_to(archives: &[ArchiveEntry], drives: &[Drive], targets: &[Drive]) -> ReplicationResult {
+    let mut unavailable = Vec::new();
+    let mut writable = Vec::new();
+    for target in targets {
+        if target.read_only || target.locked || !target.effective_root().exists() {
+            unavailable.push(format!(
+                "{} is read-only, locked or not connected",
+                target.identity.identity_string()
+            ));
+        } else {
+            writable.push(target);
+        }
+    }
+    let mut result = copy_archives(archives, drives, &writable);
+    result.errors.splice(0..0, unavailable);
+    result
+}
+
+fn copy_archives<'a>(
+    archives: impl IntoIterator<Item = &'a ArchiveEntry>,
+    drives: &[Drive],
+    targets: &[&Drive],
+) -> ReplicationResult {
+    let mut result = ReplicationResult::default();
+    let mut room: HashMap<&DriveId, Option<u64>> = targets.iter().map(|d| (&d.id, d.free_bytes)).collect();
+
+    for archive in archives {
+        let Some(from) = drives.iter().find(|d| d.id == archive.drive_id) else {
+            continue;
+        };
+        let taken_from = archive.relocated_from.clone().unwrap_or_else(|| archive.drive_id.clone());
+        let src = from.effective_root().join(&archive.archive_path);
+        for target in targets.iter().filter(|t| t.id != archive.drive_id) {
+            // Named for the drive it was taken from, as central archives
+            // are, so copies from different drives can't collide.
+            let dest = crate::gc::archive_dir(target)
+                .join(&archive.original_path)
+                .join(version_file_name(archive.archived_at, &drive_tag(&taken_from), &archive.compression));
+            if dest.exists() {
+                continue;
+            }
+            let free = room.get_mut(&target.id).expect("every target has a room entry");
+            if free.is_some_and(|f| f < archive.compressed_size) {
+                result.errors.push(format!(
+                    "{}: no room on {}",
+                    archive.original_path.display(),
+                    target.identity.identity_string()
+                ));
+                continue;
+            }
+            if let Err(e) = copy_file(&src, &dest) {
+                result.errors.push(format!(
+                    "{}: copy to {} failed: {}",
+                    archive.original_path.display(),
+                    target.identity.identity_string(),
+                    e
+                ));
+                continue;
+            }
+            if let Some(f) = free {
+                *f -= archive.compressed_size;
+            }
+            result.bytes += archive.compressed_size;
+            result.copies.push(ArchiveEntry {
+                id: Uuid::now_v7(),
+                archive_path: target.stored_path(&dest),
+                drive_id: target.id.clone(),
+                relocated_from: Some(taken_from.clone()),
+                relocated_at: Some(Utc::now()),
+                ..archive.clone()
+            });
+        }
+    }
+    result
+}
+
+/// Write a snapshot of the catalog database to every connected archive-only
+/// drive in `drives`, replacing the last one, so a lost machine's catalog
+/// can be recovered. Returns where each snapshot was written.
+pub fn snapshot_catalog(conn: &Connection, drives: &[Drive]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for drive in drives.iter().filter(|d| is_replica_target(d)) {
+        let dest = drive.diffr_dir().join("catalog.db");
+        let temp = dest.with_extension("db.tmp");
+        std::fs::create_dir_all(drive.diffr_dir())?;
+        if temp.exists() {
+            std::fs::remove_file(&temp)?;
+        }
+        let temp_str = temp
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("path isn't valid UTF-8: {}", temp.display()))?;
+        conn.execute("VACUUM INTO ?1", [temp_str])?;
+        std::fs::rename(&temp, &dest)?;
+        written.push(dest);
+    }
+    Ok(written)
+}
+
+/// Copy `src` to `dest`, leaving nothing at `dest` if it fails.
+fn copy_file(src: &Path, dest: &Path) -> std::io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(src, dest).map(|_| ()).inspect_err(|_| {
+        let _ = std::fs::remove_file(dest);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver;
+    use diffr_core::models::archive::ArchiveReason;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_replicate_to_archive_only_drives() {
+        let (src_dir, archive_dir, full_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src_dir.path().join("a.txt"), "version one").unwrap();
+        let source = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let mut archive_drive = Drive::new(DriveIdentity::new_synthetic(), archive_dir.path().to_path_buf());
+        archive_drive.role = DriveRole::ArchiveOnly;
+        let mut full = Drive::new(DriveIdentity::new_synthetic(), full_dir.path().to_path_buf());
+        full.role = DriveRole::ArchiveOnly;
+        full.free_bytes = Some(1);
+        let entry = archiver::archive_file(&source, Path::new("a.txt"), ArchiveReason::Manual).unwrap();
+
+        let drives = vec![source.clone(), archive_drive.clone(), full];
+        let result = replicate_archives(std::slice::from_ref(&entry), &drives);
+        assert_eq!(result.copies.len(), 1);
+        assert_eq!(result.errors.len(), 1, "{:?}", result.errors);
+        let copy = &result.copies[0];
+        assert_eq!(copy.drive_id, archive_drive.id);
+        assert_eq!(copy.relocated_from, Some(source.id.clone()));
+        assert_eq!((copy.xxh3_hash.clone(), copy.archived_at), (entry.xxh3_hash.clone(), entry.archived_at));
+        let stored = archive_dir.path().join(&copy.archive_path);
+        assert_eq!(std::fs::read(stored).unwrap(), std::fs::read(src_dir.path().join(&entry.archive_path)).unwrap());
+
+        // A copy isn't copied again.
+        assert!(replicate_archives(&result.copies, &drives).copies.is_empty());
+    }
+
+    #[test]
+    fn test_replicate_to_chosen_drives() {
+        let (src_dir, offsite_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src_dir.path().join("a.txt"), "version one").unwrap();
+        let source = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let offsite = Drive::new(DriveIdentity::new_synthetic(), offsite_dir.path().to_path_buf());
+        let mut read_only = Drive::new(DriveIdentity::new_synthetic(), offsite_dir.path().join("ro"));
+        read_only.read_only = true;
+        let entry = archiver::archive_file(&source, Path::new("a.txt"), ArchiveReason::Manual).unwrap();
+
+        let drives = vec![source.clone(), offsite.clone(), read_only.clone()];
+        let targets = [source.clone(), offsite.clone(), read_only];
+        let result = replicate_archives_to(std::slice::from_ref(&entry), &drives, &targets);
+        // Not onto the drive it's already on, nor the read-only one.
+        assert_eq!(result.copies.len(), 1);
+        assert_eq!(result.errors.len(), 1, "{:?}", result.errors);
+        assert_eq!(result.copies[0].drive_id, offsite.id);
+
+        // Running it again copies nothing.
+        let again = replicate_archives_to(std::slice::from_ref(&entry), &drives, &targets[..2]);
+        assert!(again.copies.is_empty() && again.errors.is_empty());
+    }
+}
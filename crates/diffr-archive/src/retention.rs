@@ -1,16 +1,26 @@
 use chrono::Utc;
 use diffr_core::models::archive::{ArchiveEntry, RetentionPolicy};
-use diffr_core::models::drive::DriveId;
+use diffr_core::models::drive::{Drive, DriveId};
 use diffr_db::ops;
 use rusqlite::Connection;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::placement;
 
 /// Result of enforcing retention policies.
 #[derive(Debug, Default)]
 pub struct RetentionResult {
     pub entries_pruned: usize,
     pub bytes_freed: u64,
+    /// Entries moved to another drive instead of being pruned for the size cap.
+    pub entries_relocated: usize,
+    pub bytes_relocated: u64,
+    /// Of those, entries split across several drives.
+    pub entries_split: usize,
+    /// Archives that no drive had room for when relocating, and so were
+    /// pruned.
+    pub unplaced: Vec<PathBuf>,
     pub errors: Vec<String>,
 }
 
@@ -20,26 +30,50 @@ pub fn enforce_retention(
     drive_id: &DriveId,
     drive_root: &Path,
     policy: &RetentionPolicy,
+) -> anyhow::Result<RetentionResult> {
+    enforce(conn, drive_id, drive_root, policy, None)
+}
+
+/// Enforce retention, relocating archives over the size cap to one of
+/// `targets` when `policy.relocate_on_cap` is set. Entries that can't be
+/// placed anywhere are pruned as usual.
+pub fn enforce_retention_with_relocation(
+    conn: &Connection,
+    drive: &Drive,
+    policy: &RetentionPolicy,
+    targets: &[Drive],
+) -> anyhow::Result<RetentionResult> {
+    let relocation = policy.relocate_on_cap.then_some((drive, targets));
+    enforce(conn, &drive.id, drive.effective_root(), policy, relocation)
+}
+
+fn enforce(
+    conn: &Connection,
+    drive_id: &DriveId,
+    drive_root: &Path,
+    policy: &RetentionPolicy,
+    relocation: Option<(&Drive, &[Drive])>,
 ) -> anyhow::Result<RetentionResult> {
     let archives = ops::list_archives_for_drive(conn, drive_id)?;
     let mut result = RetentionResult::default();
+    // Targets' free space goes down as archives are moved onto them.
+    let mut relocation = relocation.map(|(source, targets)| (source, targets.to_vec()));
 
     // Group archives by original path
-    let mut by_path: HashMap<String, Vec<ArchiveEntry>> = HashMap::new();
+    let mut by_path: HashMap<PathBuf, Vec<ArchiveEntry>> = HashMap::new();
     for entry in archives {
-        let key = entry.original_path.to_string_lossy().to_string();
-        by_path.entry(key).or_default().push(entry);
+        by_path.entry(entry.original_path.clone()).or_default().push(entry);
     }
 
     // Sort each group by archived_at descending (newest first)
     for entries in by_path.values_mut() {
-        entries.sort_by(|a, b| b.archived_at.cmp(&a.archived_at));
+        entries.sort_by_key(|e| std::cmp::Reverse(e.archived_at));
     }
 
     let now = Utc::now();
     let mut to_delete: Vec<ArchiveEntry> = Vec::new();
 
-    for (_path, entries) in &by_path {
+    for entries in by_path.values() {
         for (i, entry) in entries.iter().enumerate() {
             let mut should_prune = false;
 
@@ -74,7 +108,7 @@ pub fn enforce_retention(
                 .flatten()
                 .cloned()
                 .collect();
-            all_entries.sort_by(|a, b| a.archived_at.cmp(&b.archived_at));
+            all_entries.sort_by_key(|e| e.archived_at);
 
             let mut freed = 0u64;
             let excess = current_total - max_total;
@@ -82,18 +116,50 @@ pub fn enforce_retention(
                 if freed >= excess {
                     break;
                 }
-                if !to_delete.iter().any(|d| d.id == entry.id) {
-                    freed += entry.compressed_size;
-                    to_delete.push(entry);
+                if to_delete.iter().any(|d| d.id == entry.id) {
+                    continue;
+                }
+                if let Some((source, targets)) = relocation.as_mut() {
+                    match try_relocate(conn, &entry, source, targets, policy) {
+                        Ok(Some(split)) => {
+                            freed += entry.compressed_size;
+                            result.entries_relocated += 1;
+                            result.bytes_relocated += entry.compressed_size;
+                            result.entries_split += split as usize;
+                            continue;
+                        }
+                        Ok(None) => result.unplaced.push(entry.original_path.clone()),
+                        Err(e) => result.errors.push(format!(
+                            "failed to relocate {}: {}",
+                            entry.archive_path.display(),
+                            e
+                        )),
+                    }
                 }
+                freed += entry.compressed_size;
+                to_delete.push(entry);
             }
         }
     }
 
     // Execute deletions
+    let mut all_drives = None;
     for entry in &to_delete {
+        let parts = ops::list_archive_parts(conn, &entry.id)?;
+        if !parts.is_empty() {
+            let drives = match &all_drives {
+                Some(drives) => drives,
+                None => all_drives.insert(ops::list_all_drives(conn)?),
+            };
+            let errors = placement::remove_parts(&parts, drives);
+            if !errors.is_empty() {
+                result.errors.extend(errors);
+                continue;
+            }
+            result.bytes_freed += entry.compressed_size;
+        }
         let archive_full = drive_root.join(&entry.archive_path);
-        if archive_full.exists() {
+        if parts.is_empty() && archive_full.exists() {
             match std::fs::remove_file(&archive_full) {
                 Ok(()) => {
                     result.bytes_freed += entry.compressed_size;
@@ -123,3 +189,85 @@ pub fn enforce_retention(
 
     Ok(result)
 }
+
+/// Move one archive to the best target, if any has room, or split it
+/// across several when the policy allows. Returns whether it was split, or
+/// `None` if it couldn't be placed. Archives already split stay put.
+fn try_relocate(
+    conn: &Connection,
+    entry: &ArchiveEntry,
+    source: &Drive,
+    targets: &mut [Drive],
+    policy: &RetentionPolicy,
+) -> anyhow::Result<Option<bool>> {
+    if !ops::list_archive_parts(conn, &entry.id)?.is_empty() {
+        return Ok(None);
+    }
+    let cap = policy.max_total_bytes;
+    if let Some(target) = placement::choose_target(conn, source, targets, entry.compressed_size, cap)? {
+        placement::relocate_archive(conn, entry, source, target)?;
+        let target = target.id.clone();
+        debit(targets, &target, entry.compressed_size);
+        return Ok(Some(false));
+    }
+    if policy.span_on_relocate {
+        if let Some(parts) = placement::span_archive(conn, entry, source, targets, cap)? {
+            for part in &parts {
+                debit(targets, &part.drive_id, part.size);
+            }
+            return Ok(Some(true));
+        }
+    }
+    Ok(None)
+}
+
+/// Take `bytes` just written to a drive off its known free space.
+fn debit(drives: &mut [Drive], id: &DriveId, bytes: u64) {
+    if let Some(free) = drives.iter_mut().find(|d| &d.id == id).and_then(|d| d.free_bytes.as_mut()) {
+        *free = free.saturating_sub(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver;
+    use diffr_core::models::archive::ArchiveReason;
+    use diffr_core::models::drive::{DriveIdentity, DriveRole};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relocation_counts_what_it_moved_against_free_space() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap()];
+        std::fs::write(dirs[0].path().join("a.txt"), "version one").unwrap();
+        std::fs::write(dirs[0].path().join("b.txt"), "version one").unwrap();
+        let source = Drive::new(DriveIdentity::new_synthetic(), dirs[0].path().to_path_buf());
+        let a = archiver::archive_file(&source, Path::new("a.txt"), ArchiveReason::Manual).unwrap();
+        let b = archiver::archive_file(&source, Path::new("b.txt"), ArchiveReason::Manual).unwrap();
+        let size = a.compressed_size;
+
+        // The roomier drive can hold one of the two, not both.
+        let mut targets = Vec::new();
+        for (dir, free) in dirs[1..].iter().zip([size * 3 / 2, size * 5 / 4]) {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.role = DriveRole::ArchiveOnly;
+            drive.free_bytes = Some(free);
+            targets.push(drive);
+        }
+        for drive in std::iter::once(&source).chain(&targets) {
+            ops::insert_drive(&conn, drive).unwrap();
+        }
+        for entry in [&a, &b] {
+            ops::insert_archive(&conn, entry).unwrap();
+        }
+
+        let policy = RetentionPolicy::default();
+        for entry in [&a, &b] {
+            assert_eq!(try_relocate(&conn, entry, &source, &mut targets, &policy).unwrap(), Some(false));
+        }
+        assert_eq!(ops::get_archive_by_id(&conn, &a.id).unwrap().unwrap().drive_id, targets[0].id);
+        assert_eq!(ops::get_archive_by_id(&conn, &b.id).unwrap().unwrap().drive_id, targets[1].id);
+        assert_eq!(targets[0].free_bytes, Some(size * 3 / 2 - size));
+    }
+}
@@ -1,6 +1,14 @@
-use diffr_core::models::archive::{ArchiveEntry, CompressionFormat};
+use chrono::{DateTime, Utc};
+use diffr_core::models::archive::{ArchiveEntry, ArchivePart, CompressionFormat};
 use diffr_core::models::drive::Drive;
-use std::path::Path;
+use diffr_db::ops;
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::archiver::BUF_SIZE;
 
 /// Restore a file from the archive to its original location.
 pub fn restore_file(
@@ -21,21 +29,87 @@ pub fn restore_file(
         None => drive.effective_root().join(&entry.original_path),
     };
 
-    // Ensure target directory exists
+    write_restored(std::fs::File::open(&archive_full)?, entry, &target)
+}
+
+/// Restore an archive from whichever of `drives` hold it, reassembling it
+/// first if it was split across several.
+pub fn restore_archive(
+    conn: &Connection,
+    drives: &[Drive],
+    entry: &ArchiveEntry,
+    dest_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    let holder = drives
+        .iter()
+        .find(|d| d.id == entry.drive_id)
+        .ok_or_else(|| anyhow::anyhow!("archive drive {} is not registered", entry.drive_id))?;
+    let parts = ops::list_archive_parts(conn, &entry.id)?;
+    if parts.is_empty() {
+        return restore_file(holder, entry, dest_path);
+    }
+    let target = match dest_path {
+        Some(p) => p.to_path_buf(),
+        None => holder.effective_root().join(&entry.original_path),
+    };
+    restore_parts(drives, entry, &parts, &target)
+}
+
+/// Join an archive's parts back together and decompress them to `target`.
+pub fn restore_parts(
+    drives: &[Drive],
+    entry: &ArchiveEntry,
+    parts: &[ArchivePart],
+    target: &Path,
+) -> anyhow::Result<()> {
+    let mut joined: Box<dyn Read> = Box::new(std::io::empty());
+    for part in parts {
+        let drive = drives
+            .iter()
+            .find(|d| d.id == part.drive_id)
+            .filter(|d| d.effective_root().exists())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "part {} of {} is on drive {}, which isn't connected",
+                    part.index + 1,
+                    parts.len(),
+                    part.drive_id
+                )
+            })?;
+        let path = drive.effective_root().join(&part.part_path);
+        let file = std::fs::File::open(&path)
+            .map_err(|e| anyhow::anyhow!("archive part {}: {}", path.display(), e))?;
+        if file.metadata()?.len() != part.size {
+            anyhow::bail!("archive part {} is not {} bytes", path.display(), part.size);
+        }
+        joined = Box::new(joined.chain(file));
+    }
+    write_restored(joined, entry, target)
+}
+
+/// Decompress `stored` to `target` a buffer at a time, checking what's
+/// written against the hash recorded when it was archived.
+fn write_restored(stored: impl Read, entry: &ArchiveEntry, target: &Path) -> anyhow::Result<()> {
     if let Some(parent) = target.parent() {
         std::fs::create_dir_all(parent)?;
     }
-
-    match entry.compression {
-        CompressionFormat::Zstd => decompress_zstd(&archive_full, &target)?,
-        CompressionFormat::None => {
-            std::fs::copy(&archive_full, &target)?;
+    let mut reader: Box<dyn Read> = match entry.compression {
+        CompressionFormat::Zstd => Box::new(zstd::stream::read::Decoder::new(stored)?),
+        CompressionFormat::None => Box::new(stored),
+    };
+    let mut out = std::fs::File::create(target)?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; BUF_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
         }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
     }
 
-    // Verify hash if possible
-    let restored_data = std::fs::read(&target)?;
-    let hash = format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&restored_data));
+    let hash = format!("{:016x}", hasher.digest());
     if hash != entry.xxh3_hash {
         anyhow::bail!(
             "hash mismatch after restore: expected {}, got {}",
@@ -43,15 +117,114 @@ pub fn restore_file(
             hash
         );
     }
-
     Ok(())
 }
 
-/// Decompress a zstd file.
-fn decompress_zstd(src: &Path, dst: &Path) -> anyhow::Result<()> {
-    let compressed = std::fs::read(src)?;
-    let decompressed = zstd::decode_all(compressed.as_slice())?;
-    std::fs::write(dst, &decompressed)?;
+/// Where a file's content as of some earlier time comes from.
+#[derive(Debug)]
+pub enum TreeSource {
+    /// An archived version. Archives hold what a sync replaced or deleted,
+    /// so the first one taken after that time is the version that was
+    /// there at it.
+    Archive(ArchiveEntry),
+    /// The live file, which hasn't changed since.
+    Current(PathBuf),
+}
+
+/// How a directory tree would be put back together as of some time.
+#[derive(Debug, Default)]
+pub struct TreePlan {
+    /// Each file's original path and where its content comes from.
+    pub files: BTreeMap<PathBuf, TreeSource>,
+    /// Files that are newer than that time with no archive of what they
+    /// replaced: created since, or changed without archiving.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Work out the version of each file under `prefix` as of `as_of`, from
+/// `archives` (already narrowed to `prefix`) and the live files on
+/// `drives`. Drives that aren't connected contribute only their archives.
+pub fn plan_tree(
+    drives: &[Drive],
+    archives: &[ArchiveEntry],
+    prefix: &Path,
+    as_of: DateTime<Utc>,
+) -> TreePlan {
+    let mut plan = TreePlan::default();
+    for entry in archives.iter().filter(|a| a.archived_at > as_of) {
+        match plan.files.get(&entry.original_path) {
+            Some(TreeSource::Archive(seen)) if seen.archived_at <= entry.archived_at => {}
+            _ => {
+                plan.files
+                    .insert(entry.original_path.clone(), TreeSource::Archive(entry.clone()));
+            }
+        }
+    }
+
+    let mut newer = Vec::new();
+    for drive in drives {
+        let root = drive.effective_root();
+        let walk = walkdir::WalkDir::new(root.join(prefix))
+            .into_iter()
+            .filter_entry(|e| e.file_name() != ".diffr");
+        for file in walk.filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+            let Ok(rel_path) = file.path().strip_prefix(root) else {
+                continue;
+            };
+            if plan.files.contains_key(rel_path) {
+                continue;
+            }
+            let unchanged = file
+                .metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|t| DateTime::<Utc>::from(t) <= as_of);
+            if unchanged {
+                plan.files
+                    .insert(rel_path.to_path_buf(), TreeSource::Current(file.path().to_path_buf()));
+            } else {
+                newer.push(rel_path.to_path_buf());
+            }
+        }
+    }
+    newer.retain(|p| !plan.files.contains_key(p));
+    newer.sort();
+    newer.dedup();
+    plan.skipped = newer;
+    plan
+}
+
+/// Write every file in `plan` under `dest`, at its path relative to
+/// `prefix`. Returns one error message per file that couldn't be restored.
+pub fn restore_tree(
+    conn: &Connection,
+    drives: &[Drive],
+    plan: &TreePlan,
+    prefix: &Path,
+    dest: &Path,
+) -> anyhow::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for (rel_path, source) in &plan.files {
+        let target = dest.join(rel_path.strip_prefix(prefix).unwrap_or(rel_path));
+        let restored = match source {
+            TreeSource::Archive(entry) => restore_archive(conn, drives, entry, Some(&target)),
+            TreeSource::Current(path) => copy_current(path, &target),
+        };
+        if let Err(e) = restored {
+            errors.push(format!("{}: {}", rel_path.display(), e));
+        }
+    }
+    Ok(errors)
+}
+
+/// Copy a live file, keeping its mtime.
+fn copy_current(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(src, dst)?;
+    let mtime = std::fs::metadata(src)?.modified()?;
+    std::fs::File::options().write(true).open(dst)?.set_modified(mtime)?;
     Ok(())
 }
 
@@ -89,4 +262,46 @@ mod tests {
         let restored = std::fs::read_to_string(dir.path().join("test.txt")).unwrap();
         assert_eq!(restored, original_content);
     }
+
+    #[test]
+    fn test_plan_and_restore_tree() {
+        let dir = TempDir::new().unwrap();
+        let projects = dir.path().join("Projects");
+        std::fs::create_dir_all(&projects).unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+
+        // Edited since, with the old version archived.
+        std::fs::write(projects.join("plan.txt"), "v1").unwrap();
+        let archived =
+            archiver::archive_file(&drive, Path::new("Projects/plan.txt"), ArchiveReason::BeforeOverwrite)
+                .unwrap();
+        std::fs::write(projects.join("plan.txt"), "v2").unwrap();
+        // Untouched for an hour.
+        std::fs::write(projects.join("notes.txt"), "notes").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(projects.join("notes.txt"))
+            .unwrap()
+            .set_modified(an_hour_ago.into())
+            .unwrap();
+        // Created since.
+        std::fs::write(projects.join("new.txt"), "new").unwrap();
+
+        let as_of = Utc::now() - chrono::Duration::minutes(1);
+        let drives = [drive];
+        let plan = plan_tree(&drives, &[archived], Path::new("Projects"), as_of);
+        assert_eq!(plan.files.len(), 2);
+        assert!(matches!(plan.files[Path::new("Projects/plan.txt")], TreeSource::Archive(_)));
+        assert!(matches!(plan.files[Path::new("Projects/notes.txt")], TreeSource::Current(_)));
+        assert_eq!(plan.skipped, [PathBuf::from("Projects/new.txt")]);
+
+        let dest = TempDir::new().unwrap();
+        let conn = diffr_db::open_memory_db().unwrap();
+        let errors = restore_tree(&conn, &drives, &plan, Path::new("Projects"), dest.path()).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(std::fs::read_to_string(dest.path().join("plan.txt")).unwrap(), "v1");
+        assert_eq!(std::fs::read_to_string(dest.path().join("notes.txt")).unwrap(), "notes");
+        assert!(!dest.path().join("new.txt").exists());
+    }
 }
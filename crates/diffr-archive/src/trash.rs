@@ -0,0 +1,181 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diffr_core::models::drive::Drive;
+use std::path::{Path, PathBuf};
+
+/// Batch directories are named for the time their sync started, to the
+/// millisecond so syncs moments apart keep separate batches. Parsing also
+/// takes names without the fraction, which batches used to have.
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+
+/// A file moved aside by a sync instead of being deleted.
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    /// Path relative to the sync root at the time it was trashed.
+    pub rel_path: PathBuf,
+    /// Where the file now lives.
+    pub trash_path: PathBuf,
+    pub trashed_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// Totals removed by [`empty_trash`].
+#[derive(Debug, Default)]
+pub struct EmptyResult {
+    pub items_removed: usize,
+    pub bytes_freed: u64,
+}
+
+/// The trash staging area for a drive: `trash` in its `.diffr` directory.
+pub fn trash_dir(drive: &Drive) -> PathBuf {
+    drive.diffr_dir().join("trash")
+}
+
+/// The name of the batch a sync started at `started_at` trashes files into.
+pub fn batch_name(started_at: DateTime<Utc>) -> String {
+    started_at.format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Move a file into the drive's trash, under the directory for `batch`
+/// (see [`batch_name`]): `.diffr/trash/<batch>/<rel_path>`.
+pub fn move_to_trash(drive: &Drive, rel_path: &Path, batch: &str) -> anyhow::Result<PathBuf> {
+    let source_path = drive.effective_root().join(rel_path);
+    if !source_path.exists() {
+        anyhow::bail!("file does not exist: {}", source_path.display());
+    }
+
+    let trash_path = trash_dir(drive).join(batch).join(rel_path);
+    if let Some(parent) = trash_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Trash lives on the same root, so a rename normally works; fall back to
+    // copy + remove if the root spans filesystems.
+    if std::fs::rename(&source_path, &trash_path).is_err() {
+        std::fs::copy(&source_path, &trash_path)?;
+        std::fs::remove_file(&source_path)?;
+    }
+    Ok(trash_path)
+}
+
+/// List everything in a drive's trash, oldest first.
+pub fn list_trash(drive: &Drive) -> anyhow::Result<Vec<TrashItem>> {
+    let dir = trash_dir(drive);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut items = Vec::new();
+    for batch in std::fs::read_dir(&dir)? {
+        let batch = batch?;
+        let name = batch.file_name().to_string_lossy().to_string();
+        let Ok(naive) = NaiveDateTime::parse_from_str(&name, TIMESTAMP_FORMAT) else {
+            continue;
+        };
+        let trashed_at = naive.and_utc();
+        for entry in walkdir::WalkDir::new(batch.path()).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel_path = entry
+                .path()
+                .strip_prefix(batch.path())
+                .unwrap_or(entry.path())
+                .to_path_buf();
+            items.push(TrashItem {
+                rel_path,
+                trash_path: entry.path().to_path_buf(),
+                trashed_at,
+                size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            });
+        }
+    }
+    items.sort_by_key(|item| item.trashed_at);
+    Ok(items)
+}
+
+/// Permanently delete trashed files. With `max_age_days`, only items older
+/// than that are removed; otherwise the whole trash is emptied.
+pub fn empty_trash(drive: &Drive, max_age_days: Option<u32>) -> anyhow::Result<EmptyResult> {
+    let cutoff = max_age_days.map(|days| Utc::now() - chrono::Duration::days(days as i64));
+    let mut result = EmptyResult::default();
+
+    for item in list_trash(drive)? {
+        if cutoff.is_some_and(|cutoff| item.trashed_at >= cutoff) {
+            continue;
+        }
+        std::fs::remove_file(&item.trash_path)?;
+        result.items_removed += 1;
+        result.bytes_freed += item.size;
+    }
+
+    // Drop batch directories that are now empty.
+    let dir = trash_dir(drive);
+    if dir.exists() {
+        for batch in std::fs::read_dir(&dir)? {
+            let path = batch?.path();
+            let has_files = walkdir::WalkDir::new(&path)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .any(|e| e.file_type().is_file());
+            if !has_files {
+                std::fs::remove_dir_all(&path)?;
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trash_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/old.txt"), "bye").unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+
+        let trashed = move_to_trash(&drive, Path::new("docs/old.txt"), &batch_name(Utc::now())).unwrap();
+        assert!(!dir.path().join("docs/old.txt").exists());
+        assert!(trashed.exists());
+
+        let items = list_trash(&drive).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].rel_path, PathBuf::from("docs/old.txt"));
+        assert_eq!(items[0].size, 3);
+
+        // Nothing is old enough to expire yet.
+        assert_eq!(empty_trash(&drive, Some(30)).unwrap().items_removed, 0);
+        let emptied = empty_trash(&drive, None).unwrap();
+        assert_eq!(emptied.items_removed, 1);
+        assert_eq!(emptied.bytes_freed, 3);
+        assert!(list_trash(&drive).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_syncs_in_the_same_second_keep_separate_batches() {
+        let dir = TempDir::new().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let first = DateTime::parse_from_rfc3339("2026-03-01T12:00:00.250Z").unwrap().to_utc();
+        let second = first + chrono::Duration::milliseconds(500);
+
+        std::fs::write(dir.path().join("a.txt"), "one").unwrap();
+        let one = move_to_trash(&drive, Path::new("a.txt"), &batch_name(first)).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "two").unwrap();
+        let two = move_to_trash(&drive, Path::new("a.txt"), &batch_name(second)).unwrap();
+        assert_ne!(one, two);
+        assert_eq!(std::fs::read_to_string(&one).unwrap(), "one");
+
+        // Batches named before the fraction was added still list.
+        let old = trash_dir(&drive).join("20260301T110000");
+        std::fs::create_dir_all(&old).unwrap();
+        std::fs::write(old.join("b.txt"), "old").unwrap();
+
+        let items = list_trash(&drive).unwrap();
+        let times: Vec<_> = items.iter().map(|i| i.trashed_at).collect();
+        assert_eq!(times, vec![first - chrono::Duration::hours(1) - chrono::Duration::milliseconds(250), first, second]);
+    }
+}
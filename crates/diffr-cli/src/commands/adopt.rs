@@ -0,0 +1,84 @@
+use clap::Args;
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::manifest::DriveManifest;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct AdoptArgs {
+    /// Mount point or sync root of a drive that has been synced before
+    path: PathBuf,
+
+    /// Adopt even if the manifest signature doesn't match
+    #[arg(long)]
+    force: bool,
+}
+
+pub fn run(args: AdoptArgs, json: bool) -> anyhow::Result<()> {
+    let path = crate::commands::init::simplified_canonicalize(&args.path)
+        .map_err(|_| anyhow::anyhow!("path does not exist: {}", args.path.display()))?;
+
+    let (root, manifest) = find_manifest(&path)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "no diffr manifest found at {} (has this drive been synced?)",
+            path.display()
+        )
+    })?;
+
+    if !manifest.verify()? {
+        if !args.force {
+            anyhow::bail!(
+                "manifest signature mismatch in {} (use --force to adopt anyway)",
+                DriveManifest::path_in(&root).display()
+            );
+        }
+        tracing::warn!("adopting manifest with mismatched signature");
+    }
+
+    let spec = manifest.own_spec().ok_or_else(|| {
+        anyhow::anyhow!("manifest does not list the drive it was found on")
+    })?;
+
+    // The manifest sits in the sync root; strip the relative sync root to get
+    // back to this machine's mount point.
+    let mount_point = match &spec.sync_root {
+        Some(rel) if rel.is_relative() => strip_suffix(&root, rel).unwrap_or_else(|| root.clone()),
+        _ => root.clone(),
+    };
+
+    let discovery = super::drive::discovery()?;
+    let mut connected = discovery.discover_drives().unwrap_or_default();
+    connected.retain(|d| d.identity != manifest.drive);
+    connected.push(spec.to_drive(&manifest.manifest.cluster.id, &mount_point));
+
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+    crate::commands::cluster::import_manifest(&conn, &manifest.manifest, &connected, json)
+}
+
+/// Look for a manifest at `path`, then in its immediate subdirectories
+/// (for when the mount point is given but the sync root is one level down).
+fn find_manifest(path: &Path) -> anyhow::Result<Option<(PathBuf, DriveManifest)>> {
+    if let Some(m) = DriveManifest::read_from(path)? {
+        return Ok(Some((path.to_path_buf(), m)));
+    }
+    for entry in std::fs::read_dir(path)?.flatten() {
+        let child = entry.path();
+        if child.is_dir() {
+            if let Some(m) = DriveManifest::read_from(&child)? {
+                return Ok(Some((child, m)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn strip_suffix(path: &Path, suffix: &Path) -> Option<PathBuf> {
+    let mut base = path.to_path_buf();
+    for component in suffix.components().rev() {
+        if base.file_name()? != component.as_os_str() {
+            return None;
+        }
+        base.pop();
+    }
+    Some(base)
+}
@@ -1,7 +1,12 @@
+use chrono::{DateTime, Utc};
 use clap::Subcommand;
 use diffr_core::config::DiffrConfig;
-use diffr_core::models::drive::DriveIdentity;
+use diffr_core::models::drive::{DriveId, DriveIdentity};
+use diffr_core::units::format_bytes;
 use diffr_db::ops;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Subcommand)]
 pub enum ArchiveAction {
@@ -22,6 +27,72 @@ pub enum ArchiveAction {
         #[arg(long)]
         dest: Option<String>,
     },
+    /// Rebuild a directory as it was at some earlier time, from the
+    /// archives taken since and the files that haven't changed
+    RestoreTree {
+        /// Directory to rebuild, relative to the drive root
+        #[arg(long)]
+        path: String,
+        /// The time to go back to: a date (YYYY-MM-DD [HH:MM], UTC) or an age such as 7d
+        #[arg(long, value_parser = super::parse_time)]
+        as_of: DateTime<Utc>,
+        /// Directory to write the rebuilt tree into
+        #[arg(long)]
+        dest: String,
+        /// Drive the directory is on (defaults to the drives its archives were taken from)
+        #[arg(long)]
+        drive: Option<String>,
+    },
+    /// Summarize archive sizes, compression by file type and room left
+    /// under retention.max_total_bytes
+    Stats {
+        /// Only this drive's archives
+        #[arg(long)]
+        drive: Option<String>,
+        /// File types to list
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Add a folder of old file versions, such as a backup from before
+    /// diffr, to a drive's archive
+    Import {
+        /// Drive to store the archives on
+        drive: String,
+        /// Folder of old versions
+        directory: String,
+        /// Path on the drive the folder's contents were versions of
+        /// (defaults to the drive root)
+        #[arg(long = "as", value_name = "PATH", default_value = "")]
+        original_path: String,
+        /// Date every version instead of using file mtimes: a date
+        /// (YYYY-MM-DD [HH:MM], UTC) or an age such as 30d
+        #[arg(long, value_parser = super::parse_time)]
+        date: Option<DateTime<Utc>>,
+    },
+    /// Find files in a drive's archive directory that no archive record
+    /// points to, and remove them
+    Gc {
+        /// Drive whose archive directory to check
+        drive: String,
+        /// Remove the orphans
+        #[arg(long, conflicts_with = "import")]
+        delete: bool,
+        /// Record orphans as archives of the file they're stored under
+        #[arg(long)]
+        import: bool,
+    },
+    /// Copy archives onto other drives, such as offsite ones, so they
+    /// survive losing the drive they were taken on
+    Replicate {
+        /// Drives to copy onto: identities, or `tag:<name>` for every drive
+        /// with a tag
+        #[arg(long, required = true, value_delimiter = ',')]
+        to: Vec<String>,
+        /// Drives whose archives to copy, named the same way (defaults to
+        /// every drive not copied onto)
+        #[arg(long, value_delimiter = ',')]
+        from: Option<Vec<String>>,
+    },
     /// Prune old archives according to retention policy
     Prune {
         /// Drive identity to prune archives from
@@ -36,7 +107,7 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
     match action {
         ArchiveAction::List { path, drive } => {
             let archives = if let Some(path) = &path {
-                ops::list_archives_for_path(&conn, path)?
+                ops::list_archives_for_path(&conn, Path::new(path))?
             } else if let Some(drive_serial) = &drive {
                 let identity = DriveIdentity::Hardware {
                     serial: drive_serial.clone(),
@@ -48,13 +119,31 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
                 anyhow::bail!("specify --path or --drive to filter archives");
             };
 
+            // Drives holding each split archive's parts.
+            let mut split = HashMap::new();
+            for a in &archives {
+                let mut drives: Vec<_> = ops::list_archive_parts(&conn, &a.id)?
+                    .into_iter()
+                    .map(|p| p.drive_id)
+                    .collect();
+                drives.dedup();
+                if !drives.is_empty() {
+                    split.insert(a.id, drives.len());
+                }
+            }
+
             if json {
                 let items: Vec<_> = archives
                     .iter()
                     .map(|a| {
                         format!(
-                            "{{\"id\": \"{}\", \"path\": \"{}\", \"size\": {}, \"compressed\": {}, \"archived_at\": \"{}\"}}",
-                            a.id, a.original_path.display(), a.original_size, a.compressed_size, a.archived_at
+                            "{{\"id\": \"{}\", \"path\": \"{}\", \"size\": {}, \"compressed\": {}, \"archived_at\": \"{}\", \"split_drives\": {}}}",
+                            a.id,
+                            a.original_path.display(),
+                            a.original_size,
+                            a.compressed_size,
+                            a.archived_at,
+                            split.get(&a.id).copied().unwrap_or(0)
                         )
                     })
                     .collect();
@@ -69,12 +158,16 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
                     );
                     for a in &archives {
                         println!(
-                            "{:<36} {:<30} {:>10} {:>10} {:<20}",
+                            "{:<36} {:<30} {:>10} {:>10} {:<20}{}",
                             a.id,
                             a.original_path.display(),
                             a.original_size,
                             a.compressed_size,
-                            a.archived_at.format("%Y-%m-%d %H:%M:%S")
+                            a.archived_at.format("%Y-%m-%d %H:%M:%S"),
+                            split
+                                .get(&a.id)
+                                .map(|n| format!(" (split across {} drives)", n))
+                                .unwrap_or_default()
                         );
                     }
                 }
@@ -84,23 +177,26 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
         ArchiveAction::Restore { id, dest } => {
             let archive_id: uuid::Uuid = id.parse()?;
 
-            // Find the archive entry (search all drives)
+            let entry = ops::get_archive_by_id(&conn, &archive_id)?
+                .ok_or_else(|| anyhow::anyhow!("archive entry '{}' not found", id))?;
             let drives = ops::list_all_drives(&conn)?;
-            let mut found = None;
-            for drive in &drives {
-                let archives = ops::list_archives_for_drive(&conn, &drive.id)?;
-                if let Some(entry) = archives.into_iter().find(|a| a.id == archive_id) {
-                    found = Some((drive.clone(), entry));
-                    break;
-                }
+            if !drives.iter().any(|d| d.id == entry.drive_id) {
+                anyhow::bail!("drive for archive entry '{}' not found", id);
             }
 
-            let (drive, entry) = found
-                .ok_or_else(|| anyhow::anyhow!("archive entry '{}' not found", id))?;
-
-            let dest_path = dest.map(std::path::PathBuf::from);
-            diffr_archive::retriever::restore_file(
-                &drive,
+            // An archive stored on another drive restores to the one it was taken from.
+            let original_drive = match &entry.relocated_from {
+                Some(from) => ops::get_drive_by_id(&conn, from)?,
+                None => None,
+            };
+            let dest_path = dest.map(std::path::PathBuf::from).or_else(|| {
+                original_drive
+                    .as_ref()
+                    .map(|d| d.effective_root().join(&entry.original_path))
+            });
+            diffr_archive::retriever::restore_archive(
+                &conn,
+                &drives,
                 &entry,
                 dest_path.as_deref(),
             )?;
@@ -115,6 +211,380 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
             );
             Ok(())
         }
+        ArchiveAction::RestoreTree {
+            path,
+            as_of,
+            dest,
+            drive,
+        } => {
+            let prefix = PathBuf::from(path.trim_end_matches('/'));
+            let dest = PathBuf::from(dest);
+            let mut archives = ops::list_archives_under(&conn, &prefix)?;
+            let all_drives = ops::list_all_drives(&conn)?;
+            // An archive stored on another drive belongs to the one it was taken from.
+            let taken_from = |a: &diffr_core::models::archive::ArchiveEntry| {
+                a.relocated_from.clone().unwrap_or_else(|| a.drive_id.clone())
+            };
+            let sources: Vec<_> = match drive {
+                Some(name) => {
+                    let drive = super::drive::lookup_drive(&conn, &name)?;
+                    archives.retain(|a| taken_from(a) == drive.id);
+                    vec![drive]
+                }
+                None => {
+                    if archives.is_empty() {
+                        anyhow::bail!(
+                            "no archives under '{}'; pass --drive to rebuild it from current files",
+                            path
+                        );
+                    }
+                    all_drives
+                        .iter()
+                        .filter(|d| archives.iter().any(|a| taken_from(a) == d.id))
+                        .cloned()
+                        .collect()
+                }
+            };
+            // Live files only come from drives that are plugged in.
+            let connected: Vec<_> = sources
+                .into_iter()
+                .filter(|d| d.effective_root().exists())
+                .collect();
+
+            let plan = diffr_archive::retriever::plan_tree(&connected, &archives, &prefix, as_of);
+            let errors =
+                diffr_archive::retriever::restore_tree(&conn, &all_drives, &plan, &prefix, &dest)?;
+            let from_archive = plan
+                .files
+                .values()
+                .filter(|s| matches!(s, diffr_archive::retriever::TreeSource::Archive(_)))
+                .count();
+            let restored = plan.files.len() - errors.len();
+
+            if json {
+                let skipped: Vec<String> = plan
+                    .skipped
+                    .iter()
+                    .map(|p| format!("\"{}\"", p.display()))
+                    .collect();
+                let errors: Vec<String> = errors.iter().map(|e| format!("\"{}\"", e)).collect();
+                println!(
+                    "{{\"path\": \"{}\", \"as_of\": \"{}\", \"dest\": \"{}\", \"restored\": {}, \"from_archive\": {}, \"from_current\": {}, \"skipped\": [{}], \"errors\": [{}]}}",
+                    prefix.display(),
+                    as_of,
+                    dest.display(),
+                    restored,
+                    from_archive,
+                    plan.files.len() - from_archive,
+                    skipped.join(", "),
+                    errors.join(", ")
+                );
+                return Ok(());
+            }
+
+            println!(
+                "Rebuilt {} as of {} in {}: {} files ({} from archives, {} unchanged since)",
+                display_prefix(&prefix),
+                as_of.format("%Y-%m-%d %H:%M"),
+                dest.display(),
+                restored,
+                from_archive,
+                plan.files.len() - from_archive
+            );
+            if !plan.skipped.is_empty() {
+                println!(
+                    "\nSkipped {} files changed or created since, with no archived earlier version:",
+                    plan.skipped.len()
+                );
+                for p in &plan.skipped {
+                    println!("  {}", p.display());
+                }
+            }
+            if !errors.is_empty() {
+                println!("\n{} files couldn't be restored:", errors.len());
+                for e in &errors {
+                    println!("  {}", e);
+                }
+            }
+            Ok(())
+        }
+        ArchiveAction::Stats { drive, limit } => {
+            let drive = drive
+                .map(|d| super::drive::lookup_drive(&conn, &d))
+                .transpose()?;
+            let drive_id = drive.as_ref().map(|d| &d.id);
+            let by_drive = ops::archive_stats_by_drive(&conn, drive_id)?;
+            let by_extension = ops::archive_stats_by_extension(&conn, drive_id)?;
+            let config = DiffrConfig::load()?;
+            let max_total = config.retention.max_total_bytes;
+
+            let all_drives = ops::list_all_drives(&conn)?;
+            let names: HashMap<_, _> = all_drives
+                .iter()
+                .map(|d| (d.id.clone(), d.identity.identity_string().to_string()))
+                .collect();
+            let name = |id| names.get(id).cloned().unwrap_or_else(|| "(unknown)".to_string());
+            // Each drive's cap is its cluster's, or the global one.
+            let clusters: HashMap<_, _> = ops::list_clusters(&conn)?
+                .into_iter()
+                .map(|c| (c.id.clone(), c))
+                .collect();
+            let drive_max = |id: &DriveId| {
+                let cluster = all_drives
+                    .iter()
+                    .find(|d| d.id == *id)
+                    .and_then(|d| d.cluster_id.as_ref())
+                    .and_then(|c| clusters.get(c));
+                config.retention_for(cluster).max_total_bytes
+            };
+            // What's left under the cap; negative once a drive is over it.
+            let headroom = |s: &ops::ArchiveStats<DriveId>| {
+                drive_max(&s.key).map(|max| max as i64 - s.compressed_bytes as i64)
+            };
+
+            if json {
+                // Two decimals, as the table shows.
+                let ratio = |r: f64| (r * 100.0).round() / 100.0;
+                let drives: Vec<_> = by_drive
+                    .iter()
+                    .map(|s| {
+                        json!({
+                            "drive": name(&s.key),
+                            "archives": s.archives,
+                            "original_bytes": s.original_bytes,
+                            "compressed_bytes": s.compressed_bytes,
+                            "ratio": ratio(s.ratio()),
+                            "max_total_bytes": drive_max(&s.key),
+                            "headroom_bytes": headroom(s),
+                        })
+                    })
+                    .collect();
+                let extensions: Vec<_> = by_extension
+                    .iter()
+                    .map(|s| {
+                        json!({
+                            "extension": s.key,
+                            "archives": s.archives,
+                            "original_bytes": s.original_bytes,
+                            "compressed_bytes": s.compressed_bytes,
+                            "ratio": ratio(s.ratio()),
+                        })
+                    })
+                    .collect();
+                println!(
+                    "{}",
+                    json!({"max_total_bytes": max_total, "drives": drives, "extensions": extensions})
+                );
+                return Ok(());
+            }
+
+            if by_drive.is_empty() {
+                println!("No archived versions found.");
+                return Ok(());
+            }
+            println!(
+                "{:<24} {:>8} {:>10} {:>10} {:>6} {:>12}",
+                "DRIVE", "ARCHIVES", "ORIGINAL", "COMPRESSED", "RATIO", "HEADROOM"
+            );
+            for s in &by_drive {
+                let room = match headroom(s) {
+                    None => "unlimited".to_string(),
+                    Some(h) if h < 0 => format!("over {}", format_bytes(h.unsigned_abs())),
+                    Some(h) => format_bytes(h as u64),
+                };
+                println!(
+                    "{:<24} {:>8} {:>10} {:>10} {:>5.1}x {:>12}",
+                    name(&s.key),
+                    s.archives,
+                    format_bytes(s.original_bytes),
+                    format_bytes(s.compressed_bytes),
+                    s.ratio(),
+                    room
+                );
+            }
+
+            println!(
+                "\n{:<24} {:>8} {:>10} {:>10} {:>6}",
+                "EXTENSION", "ARCHIVES", "ORIGINAL", "COMPRESSED", "RATIO"
+            );
+            for s in by_extension.iter().take(limit) {
+                println!(
+                    "{:<24} {:>8} {:>10} {:>10} {:>5.1}x",
+                    if s.key.is_empty() { "(none)" } else { &s.key },
+                    s.archives,
+                    format_bytes(s.original_bytes),
+                    format_bytes(s.compressed_bytes),
+                    s.ratio()
+                );
+            }
+            if by_extension.len() > limit {
+                println!("... and {} more", by_extension.len() - limit);
+            }
+            Ok(())
+        }
+        ArchiveAction::Import {
+            drive,
+            directory,
+            original_path,
+            date,
+        } => {
+            let drive = super::drive::lookup_drive(&conn, &drive)?;
+            if !drive.effective_root().exists() {
+                anyhow::bail!(
+                    "drive '{}' is not connected ({} not found)",
+                    drive.identity.identity_string(),
+                    drive.effective_root().display()
+                );
+            }
+            let prefix = PathBuf::from(original_path.trim_matches('/'));
+            let result = diffr_archive::import::import_dir(
+                &conn,
+                &drive,
+                Path::new(&directory),
+                &prefix,
+                date,
+            )?;
+
+            if json {
+                println!(
+                    "{{\"imported\": {}, \"bytes\": {}, \"duplicates\": {}, \"errors\": {}}}",
+                    result.imported,
+                    result.bytes,
+                    result.duplicates,
+                    result.errors.len()
+                );
+                return Ok(());
+            }
+            println!(
+                "Imported {} versions ({}) as archives of {}",
+                result.imported,
+                format_bytes(result.bytes),
+                display_prefix(&prefix)
+            );
+            if result.duplicates > 0 {
+                println!("Skipped {} already archived", result.duplicates);
+            }
+            for e in &result.errors {
+                println!("  Error: {}", e);
+            }
+            Ok(())
+        }
+        ArchiveAction::Gc { drive, delete, import } => {
+            let drive = super::drive::lookup_drive(&conn, &drive)?;
+            if !drive.effective_root().exists() {
+                anyhow::bail!(
+                    "drive '{}' is not connected ({} not found)",
+                    drive.identity.identity_string(),
+                    drive.effective_root().display()
+                );
+            }
+            let orphans = diffr_archive::gc::find_orphans(&conn, &drive, diffr_archive::gc::GRACE_PERIOD)?;
+            let orphan_bytes: u64 = orphans.iter().map(|o| o.size).sum();
+            let listing = !delete && !import;
+            let result = if listing {
+                diffr_archive::gc::GcResult::default()
+            } else {
+                diffr_archive::gc::collect(&conn, &drive, &orphans, import)?
+            };
+
+            if json {
+                let items: Vec<String> = orphans
+                    .iter()
+                    .map(|o| {
+                        format!(
+                            "{{\"archive_path\": \"{}\", \"original_path\": \"{}\", \"size\": {}}}",
+                            o.archive_path.display(),
+                            o.original_path.display(),
+                            o.size
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{{\"orphans\": [{}], \"removed\": {}, \"bytes_freed\": {}, \"imported\": {}, \"errors\": {}}}",
+                    items.join(", "),
+                    result.removed,
+                    result.bytes_freed,
+                    result.imported,
+                    result.errors.len()
+                );
+                return Ok(());
+            }
+
+            if orphans.is_empty() {
+                println!("No orphaned archive files.");
+                return Ok(());
+            }
+            println!(
+                "{} orphaned archive files ({}):",
+                orphans.len(),
+                format_bytes(orphan_bytes)
+            );
+            for o in &orphans {
+                println!(
+                    "  {:<60} {:>10}",
+                    o.archive_path.display(),
+                    format_bytes(o.size)
+                );
+            }
+            if listing {
+                println!("\nRun with --delete to remove them, or --import to record them.");
+                return Ok(());
+            }
+            if import {
+                println!("\nImported {} as archive entries", result.imported);
+            } else {
+                println!(
+                    "\nRemoved {} files, freed {}",
+                    result.removed,
+                    format_bytes(result.bytes_freed)
+                );
+            }
+            for e in &result.errors {
+                println!("  Error: {}", e);
+            }
+            Ok(())
+        }
+        ArchiveAction::Replicate { to, from } => {
+            let targets = super::drive::resolve_drives(&conn, &to)?;
+            let drives = ops::list_all_drives(&conn)?;
+            let sources = match &from {
+                Some(specs) => super::drive::resolve_drives(&conn, specs)?,
+                None => drives
+                    .iter()
+                    .filter(|d| !targets.iter().any(|t| t.id == d.id))
+                    .cloned()
+                    .collect(),
+            };
+            let mut archives = Vec::new();
+            for drive in &sources {
+                archives.extend(ops::list_archives_for_drive(&conn, &drive.id)?);
+            }
+
+            let result = diffr_archive::replication::replicate_archives_to(&archives, &drives, &targets);
+            for entry in &result.copies {
+                ops::insert_archive(&conn, entry)?;
+            }
+
+            if json {
+                println!(
+                    "{{\"copied\": {}, \"bytes\": {}, \"errors\": {}}}",
+                    result.copies.len(),
+                    result.bytes,
+                    result.errors.len()
+                );
+            } else {
+                println!(
+                    "Copied {} archives ({}) onto {} drives",
+                    result.copies.len(),
+                    format_bytes(result.bytes),
+                    targets.len()
+                );
+                for e in &result.errors {
+                    println!("  Error: {}", e);
+                }
+            }
+            Ok(())
+        }
         ArchiveAction::Prune { drive } => {
             let identity = DriveIdentity::Hardware {
                 serial: drive.clone(),
@@ -123,18 +593,34 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
                 .ok_or_else(|| anyhow::anyhow!("drive '{}' not found", drive))?;
 
             let config = DiffrConfig::load()?;
-            let result = diffr_archive::retention::enforce_retention(
+            let (cluster, mut targets) = match &drive_obj.cluster_id {
+                Some(cluster_id) => (
+                    ops::get_cluster_by_id(&conn, cluster_id)?,
+                    ops::list_drives_for_cluster(&conn, cluster_id)?,
+                ),
+                None => (None, Vec::new()),
+            };
+            // Relocation goes by free space, which the database only has
+            // as of the last time each drive was seen.
+            for target in &mut targets {
+                diffr_discovery::refresh_space(target);
+            }
+            let result = diffr_archive::retention::enforce_retention_with_relocation(
                 &conn,
-                &drive_obj.id,
-                drive_obj.effective_root(),
-                &config.retention,
+                &drive_obj,
+                config.retention_for(cluster.as_ref()),
+                &targets,
             )?;
 
             if json {
                 println!(
-                    "{{\"pruned\": {}, \"bytes_freed\": {}, \"errors\": {}}}",
+                    "{{\"pruned\": {}, \"bytes_freed\": {}, \"relocated\": {}, \"bytes_relocated\": {}, \"split\": {}, \"unplaced\": {}, \"errors\": {}}}",
                     result.entries_pruned,
                     result.bytes_freed,
+                    result.entries_relocated,
+                    result.bytes_relocated,
+                    result.entries_split,
+                    result.unplaced.len(),
                     result.errors.len()
                 );
             } else {
@@ -142,6 +628,27 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
                     "Pruned {} archive entries, freed {} bytes",
                     result.entries_pruned, result.bytes_freed
                 );
+                if result.entries_relocated > 0 {
+                    println!(
+                        "Relocated {} archive entries ({} bytes) to archive drives",
+                        result.entries_relocated, result.bytes_relocated
+                    );
+                }
+                if result.entries_split > 0 {
+                    println!(
+                        "  {} of them too big for one drive, split across several",
+                        result.entries_split
+                    );
+                }
+                if !result.unplaced.is_empty() {
+                    println!(
+                        "No archive drive had room for {} archives, so they were pruned:",
+                        result.unplaced.len()
+                    );
+                    for path in &result.unplaced {
+                        println!("  {}", path.display());
+                    }
+                }
                 if !result.errors.is_empty() {
                     for e in &result.errors {
                         println!("  Error: {}", e);
@@ -152,3 +659,11 @@ pub fn run(action: ArchiveAction, json: bool) -> anyhow::Result<()> {
         }
     }
 }
+
+fn display_prefix(prefix: &Path) -> String {
+    if prefix.as_os_str().is_empty() {
+        "the drive root".to_string()
+    } else {
+        prefix.display().to_string()
+    }
+}
@@ -0,0 +1,95 @@
+use clap::Subcommand;
+use diffr_core::config::DiffrConfig;
+use diffr_db::ops;
+use std::collections::HashMap;
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Show how many hashes are cached for each drive
+    Stats,
+    /// Delete cached hashes so the next sync rehashes from scratch
+    Clear {
+        /// Only clear this drive's entries
+        #[arg(long)]
+        drive: Option<String>,
+    },
+}
+
+pub fn run(action: CacheAction, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    match action {
+        CacheAction::Stats => {
+            let names: HashMap<_, _> = ops::list_all_drives(&conn)?
+                .into_iter()
+                .map(|d| (d.id.clone(), d.identity.identity_string().to_string()))
+                .collect();
+            let stats = ops::hash_cache_stats(&conn)?;
+            let name = |id| names.get(id).cloned().unwrap_or_else(|| "(unknown)".to_string());
+
+            if json {
+                let rows: Vec<String> = stats
+                    .iter()
+                    .map(|s| {
+                        format!(
+                            "{{\"drive\": \"{}\", \"entries\": {}, \"orphaned\": {}, \"oldest\": {}}}",
+                            name(&s.drive_id),
+                            s.entries,
+                            s.orphaned,
+                            s.oldest
+                                .map(|t| format!("\"{}\"", t))
+                                .unwrap_or_else(|| "null".to_string())
+                        )
+                    })
+                    .collect();
+                println!("{{\"drives\": [{}]}}", rows.join(", "));
+                return Ok(());
+            }
+
+            if stats.is_empty() {
+                println!("The hash cache is empty.");
+                return Ok(());
+            }
+            println!("{:<24} {:>10} {:>10}  OLDEST", "DRIVE", "ENTRIES", "ORPHANED");
+            for s in &stats {
+                println!(
+                    "{:<24} {:>10} {:>10}  {}",
+                    name(&s.drive_id),
+                    s.entries,
+                    s.orphaned,
+                    s.oldest
+                        .map(|t| t.format("%Y-%m-%d").to_string())
+                        .unwrap_or_else(|| "-".to_string())
+                );
+            }
+            let orphaned: u64 = stats.iter().map(|s| s.orphaned).sum();
+            if orphaned > 0 {
+                println!(
+                    "\n{} entries are for files no longer in the catalog; the next sync of their drives prunes them.",
+                    orphaned
+                );
+            }
+        }
+        CacheAction::Clear { drive } => {
+            let drive = drive
+                .map(|identity| super::drive::lookup_drive(&conn, &identity))
+                .transpose()?;
+            let removed = ops::clear_hash_cache(&conn, drive.as_ref().map(|d| &d.id))?;
+
+            if json {
+                println!("{{\"removed\": {}}}", removed);
+            } else {
+                match &drive {
+                    Some(d) => println!(
+                        "Cleared {} cached hashes for drive '{}'",
+                        removed,
+                        d.identity.identity_string()
+                    ),
+                    None => println!("Cleared {} cached hashes", removed),
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use clap::Args;
+use diffr_core::cancel::Cancelled;
+use diffr_core::config::DiffrConfig;
+use diffr_core::power::PowerGuard;
+use diffr_core::models::cluster::ClusterId;
+use diffr_core::models::drive::Drive;
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::SyncPlan;
+use diffr_core::progress::Progress;
+use diffr_discovery::mtp::MtpStore;
+use diffr_scan::scanner::{scan_directory, ScanConfig};
+use diffr_sync::diff::{compute_diff, diff_summary, DiffKind, DiffSummary};
+use diffr_sync::executor::{execute_plan, ExecConfig};
+use diffr_sync::topology::generate_one_way_plan;
+use std::path::{Path, PathBuf};
+
+use super::drive::resolve_drive_or_dir;
+use diffr_core::models::cluster::NestedRepos;
+
+#[derive(Args)]
+pub struct CloneArgs {
+    /// Source drive identity, or a directory
+    source: String,
+
+    /// Target drive identity, or a directory (must be empty unless --wipe)
+    target: String,
+
+    /// Delete everything on the target that isn't on the source
+    #[arg(long)]
+    wipe: bool,
+
+    /// Dry run — show what would happen without making changes
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Copy no faster than this on average, such as 50MB/s
+    #[arg(long, value_parser = diffr_core::units::parse_rate)]
+    throttle: Option<u64>,
+}
+
+/// Mirror one drive onto another as a one-off primary → replica pass with
+/// SHA-256 verification, then rescan both sides and report whether they match.
+pub fn run(args: CloneArgs, json: bool) -> anyhow::Result<()> {
+    let source = resolve_drive_or_dir(&args.source)?;
+    let target = resolve_drive_or_dir(&args.target)?;
+    let (src_root, dst_root) = (source.effective_root(), target.effective_root());
+
+    if target.read_only {
+        anyhow::bail!("target drive '{}' is read-only", args.target);
+    }
+    if !src_root.is_dir() {
+        anyhow::bail!("source is not connected: {}", src_root.display());
+    }
+    if dst_root.starts_with(src_root) || src_root.starts_with(dst_root) {
+        anyhow::bail!(
+            "source and target must not contain each other: {} -> {}",
+            src_root.display(),
+            dst_root.display()
+        );
+    }
+    if !args.dry_run {
+        std::fs::create_dir_all(dst_root)?;
+    }
+
+    if !json {
+        println!("Cloning {} -> {}", src_root.display(), dst_root.display());
+        if args.dry_run {
+            println!("  [DRY RUN]");
+        }
+    }
+
+    let config = DiffrConfig::load()?;
+    let power = config.power_guard(diffr_discovery::host::power_reading);
+    let plan = plan_clone(&source, &target, args.wipe, crate::progress::for_output(json), power)?;
+    if !json {
+        println!(
+            "\nClone plan: {} operations, {} bytes total",
+            plan.op_count(),
+            plan.total_bytes
+        );
+    }
+
+    let exec_config = ExecConfig {
+        dry_run: args.dry_run,
+        verify: true,
+        verify_hash: config.verify_hash,
+        verify_readback: config.verify_readback,
+        durability: config.durability,
+        archive: false,
+        central_archive: false,
+        progress: crate::progress::for_output(json),
+        trash: false,
+        ownership: false,
+        cancel: crate::interrupt::token(),
+        attributes: Default::default(),
+        power,
+        merge_commands: Default::default(),
+        max_rate: args.throttle,
+        open_mtp: MtpStore::open,
+    };
+    let result = execute_plan(&plan, &[source.clone(), target.clone()], &exec_config)?;
+    let record = result.record;
+
+    if args.dry_run {
+        if json {
+            println!(
+                "{{\"status\": \"dry_run\", \"operations\": {}, \"bytes\": {}}}",
+                plan.op_count(),
+                plan.total_bytes
+            );
+        }
+        return Ok(());
+    }
+
+    let report = check_clone(&source, &target, power)?;
+    let verified = report.passed(&record.errors);
+
+    if json {
+        println!(
+            "{{\"status\": \"{}\", \"verified\": {}, \"files_copied\": {}, \"bytes_transferred\": {}, \"identical\": {}, \"mismatched\": {}, \"errors\": {}}}",
+            record.status,
+            verified,
+            record.files_synced,
+            record.bytes_transferred,
+            report.identical,
+            report.mismatched.len(),
+            record.errors.len()
+        );
+    } else {
+        println!("\nClone complete:");
+        println!("  Status:   {}", record.status);
+        println!("  Files:    {}", record.files_synced);
+        println!("  Bytes:    {}", record.bytes_transferred);
+        println!("\nIntegrity report:");
+        println!("  {}", report.summary);
+        if record.errors.is_empty() {
+            println!("  Verified: every copied file matched its source SHA-256");
+        } else {
+            println!("  Verified: FAILED, {} errors:", record.errors.len());
+            for e in &record.errors {
+                println!("    - {}", e);
+            }
+        }
+        if report.mismatched.is_empty() {
+            println!("  Result:   target matches source ({} files)", report.identical);
+        } else {
+            println!("  Result:   {} files differ:", report.mismatched.len());
+            for d in report.mismatched.iter().take(20) {
+                println!("    - {} ({:?})", d.0.display(), d.1);
+            }
+        }
+    }
+
+    if !verified {
+        if !json {
+            eprintln!("Clone failed verification; see the report above.");
+        }
+        return Err(super::Exit(1).into());
+    }
+    Ok(())
+}
+
+/// What a rescan of both sides found after a clone.
+struct Integrity {
+    summary: DiffSummary,
+    identical: usize,
+    /// Files that are missing from the target, extra on it, or differ,
+    /// and how.
+    mismatched: Vec<(PathBuf, DiffKind)>,
+}
+
+impl Integrity {
+    /// Whether the clone can be trusted: nothing failed while copying or
+    /// verifying, and the target now matches the source.
+    fn passed(&self, errors: &[String]) -> bool {
+        errors.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Scan both sides and plan the copies that make `target` a mirror of
+/// `source`, refusing a target that has files unless `wipe` is set.
+fn plan_clone(
+    source: &Drive,
+    target: &Drive,
+    wipe: bool,
+    progress: Progress,
+    power: Option<PowerGuard>,
+) -> anyhow::Result<SyncPlan> {
+    let source_entries = scan(source, progress.clone(), power)?;
+    let target_entries = scan(target, progress, power)?;
+    let existing = target_entries
+        .iter()
+        .filter(|e| e.rel_path != Path::new(".diffrignore"))
+        .count();
+    if existing > 0 && !wipe {
+        anyhow::bail!(
+            "target is not empty ({} entries at {}); pass --wipe to replace its contents",
+            existing,
+            target.effective_root().display()
+        );
+    }
+
+    // The source acts as primary for this run only; no cluster is recorded.
+    let diffs = compute_diff(&source_entries, &target_entries);
+    Ok(generate_one_way_plan(ClusterId::new(), source, target, &diffs, wipe))
+}
+
+/// Rescan both sides and compare them file by file.
+fn check_clone(source: &Drive, target: &Drive, power: Option<PowerGuard>) -> anyhow::Result<Integrity> {
+    let (source_entries, target_entries) = (scan(source, Progress::none(), power)?, scan(target, Progress::none(), power)?);
+    let after = compute_diff(&source_entries, &target_entries);
+    let (identical, mismatched): (Vec<_>, Vec<_>) = after
+        .iter()
+        .filter(|d| !d.is_dir())
+        .filter(|d| d.rel_path != Path::new(".diffrignore"))
+        .map(|d| (d.rel_path.to_path_buf(), d.kind.clone()))
+        .partition(|(_, kind)| *kind == DiffKind::Identical);
+    Ok(Integrity {
+        summary: diff_summary(&after),
+        identical: identical.len(),
+        mismatched,
+    })
+}
+
+fn scan(drive: &Drive, progress: Progress, power: Option<PowerGuard>) -> anyhow::Result<Vec<FileEntry>> {
+    let root = drive.effective_root();
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let config = ScanConfig {
+        root: root.to_path_buf(),
+        drive_id: drive.id.clone(),
+        follow_symlinks: false,
+        skip_hidden: false,
+        skip_system: false,
+        nested_repos: NestedRepos::Descend,
+        ignore: Vec::new(),
+        max_errors: None,
+        progress,
+        full_hashes: true,
+        strong_hash: None,
+        cancel: crate::interrupt::token(),
+        power,
+    };
+    let result = scan_directory(&config)?;
+    if result.cancelled {
+        return Err(Cancelled.into());
+    }
+    Ok(result.entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    fn drive(dir: &TempDir) -> Drive {
+        Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf())
+    }
+
+    #[test]
+    fn test_clone_mirrors_the_source_and_checks_it() {
+        let (src, dst) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::create_dir(src.path().join("photos")).unwrap();
+        std::fs::write(src.path().join("photos/a.jpg"), b"first").unwrap();
+        std::fs::write(src.path().join("notes.txt"), b"second").unwrap();
+        let (source, target) = (drive(&src), drive(&dst));
+
+        let plan = plan_clone(&source, &target, false, Progress::none(), None).unwrap();
+        let exec_config = ExecConfig {
+            verify: true,
+            archive: false,
+            trash: false,
+            progress: Progress::none(),
+            ..Default::default()
+        };
+        let result = execute_plan(&plan, &[source.clone(), target.clone()], &exec_config).unwrap();
+        assert_eq!(result.record.files_synced, 2);
+
+        let report = check_clone(&source, &target, None).unwrap();
+        assert_eq!(report.identical, 2);
+        assert!(report.passed(&result.record.errors));
+        assert_eq!(std::fs::read(dst.path().join("photos/a.jpg")).unwrap(), b"first");
+
+        // A target with files on it is only replaced when asked to be.
+        assert!(plan_clone(&source, &target, false, Progress::none(), None).is_err());
+        assert!(plan_clone(&source, &target, true, Progress::none(), None).is_ok());
+    }
+
+    #[test]
+    fn test_clone_fails_when_the_target_differs_or_errors_were_seen() {
+        let (src, dst) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src.path().join("a.txt"), b"source").unwrap();
+        std::fs::write(dst.path().join("a.txt"), b"damaged").unwrap();
+
+        let report = check_clone(&drive(&src), &drive(&dst), None).unwrap();
+        assert_eq!(report.mismatched.len(), 1);
+        assert!(!report.passed(&[]));
+
+        // Matching sides still fail if copying reported errors.
+        let (src, dst) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let report = check_clone(&drive(&src), &drive(&dst), None).unwrap();
+        assert!(report.passed(&[]));
+        assert!(!report.passed(&["a.txt: hash mismatch after copy".to_string()]));
+    }
+}
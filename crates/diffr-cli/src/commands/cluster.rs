@@ -1,7 +1,14 @@
 use clap::Subcommand;
 use diffr_core::config::DiffrConfig;
 use diffr_core::models::cluster::{Cluster, ConflictStrategy, Topology};
+use diffr_core::models::drive::Drive;
+use diffr_core::models::manifest::ClusterManifest;
 use diffr_db::ops;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// A drive's own ignore patterns, in its sync root.
+const IGNORE_FILE: &str = ".diffrignore";
 
 #[derive(Subcommand)]
 pub enum ClusterAction {
@@ -9,13 +16,21 @@ pub enum ClusterAction {
     Create {
         /// Cluster name
         name: String,
-        /// Sync topology: mesh or primary-replica
-        #[arg(long, default_value = "mesh")]
-        topology: String,
+        /// Sync topology: mesh or primary-replica (default: the preset's,
+        /// or mesh)
+        #[arg(long)]
+        topology: Option<String>,
         /// Conflict strategy: newest-wins, keep-both, or interactive
-        #[arg(long, default_value = "newest-wins")]
-        conflict: String,
+        /// (default: the preset's, or newest-wins)
+        #[arg(long)]
+        conflict: Option<String>,
+        /// Start from a preset: a `[preset.<name>]` in config.toml, a file
+        /// in ~/.diffr/presets/, or the path to a preset file
+        #[arg(long)]
+        preset: Option<String>,
     },
+    /// List the presets `cluster create --preset` can use
+    Presets,
     /// List all clusters
     List,
     /// Show detailed cluster info
@@ -23,11 +38,33 @@ pub enum ClusterAction {
         /// Cluster name
         name: String,
     },
+    /// Change a cluster setting (see `cluster info` for the list)
+    Set {
+        /// Cluster name
+        name: String,
+        /// Setting name, e.g. size_order or large_file_threshold
+        key: String,
+        /// New value; `none` clears an optional setting
+        value: String,
+    },
     /// Remove a cluster
     Remove {
         /// Cluster name
         name: String,
     },
+    /// Export a cluster's settings and drive memberships to a TOML file
+    Export {
+        /// Cluster name
+        name: String,
+        /// Output file (prints to stdout if omitted)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Recreate a cluster from an exported TOML file
+    Import {
+        /// File produced by `diffr cluster export`
+        file: PathBuf,
+    },
 }
 
 pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
@@ -39,20 +76,29 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
             name,
             topology,
             conflict,
+            preset,
         } => {
-            let topo: Topology = topology
-                .parse()
-                .map_err(|e: String| anyhow::anyhow!(e))?;
-            let strategy: ConflictStrategy = conflict
-                .parse()
-                .map_err(|e: String| anyhow::anyhow!(e))?;
+            let config = DiffrConfig::load()?;
+            let preset = match &preset {
+                Some(name) => config.find_preset(name)?,
+                None => Default::default(),
+            };
+            let topo: Topology = match topology {
+                Some(t) => t.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+                None => preset.topology.unwrap_or(Topology::Mesh),
+            };
+            let strategy: ConflictStrategy = match conflict {
+                Some(c) => c.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+                None => preset.conflict_strategy.unwrap_or(ConflictStrategy::NewestWins),
+            };
 
             // Check if cluster already exists
             if ops::get_cluster_by_name(&conn, &name)?.is_some() {
                 anyhow::bail!("cluster '{}' already exists", name);
             }
 
-            let cluster = Cluster::new(name.clone(), topo, strategy);
+            let mut cluster = Cluster::new(name.clone(), topo, strategy);
+            cluster.settings = preset.settings;
             ops::insert_cluster(&conn, &cluster)?;
 
             if json {
@@ -65,19 +111,39 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
             }
             Ok(())
         }
+        ClusterAction::Presets => {
+            let config = DiffrConfig::load()?;
+            let names = config.preset_names()?;
+            if json {
+                let items: Vec<String> = names.iter().map(|n| format!("\"{}\"", n)).collect();
+                println!("[{}]", items.join(", "));
+            } else if names.is_empty() {
+                println!(
+                    "No presets. Add a [preset.<name>] section to config.toml or a file to {}",
+                    DiffrConfig::presets_dir()?.display()
+                );
+            } else {
+                for name in &names {
+                    println!("{}", name);
+                }
+            }
+            Ok(())
+        }
         ClusterAction::List => {
             let clusters = ops::list_clusters(&conn)?;
             if json {
                 let items: Vec<_> = clusters
                     .iter()
                     .map(|c| {
-                        format!(
-                            "{{\"id\": \"{}\", \"name\": \"{}\", \"topology\": \"{}\", \"conflict_strategy\": \"{}\"}}",
-                            c.id, c.name, c.topology, c.conflict_strategy
-                        )
+                        json!({
+                            "id": c.id.to_string(),
+                            "name": c.name,
+                            "topology": c.topology.to_string(),
+                            "conflict_strategy": c.conflict_strategy.to_string(),
+                        })
                     })
                     .collect();
-                println!("[{}]", items.join(", "));
+                println!("{}", json!(items));
             } else {
                 if clusters.is_empty() {
                     println!("No clusters found. Create one with: diffr cluster create <name>");
@@ -96,9 +162,22 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
             let drives = ops::list_drives_for_cluster(&conn, &cluster.id)?;
 
             if json {
+                let settings: serde_json::Map<_, _> = cluster
+                    .settings
+                    .entries()
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.into()))
+                    .collect();
                 println!(
-                    "{{\"id\": \"{}\", \"name\": \"{}\", \"topology\": \"{}\", \"conflict_strategy\": \"{}\", \"drives\": {}}}",
-                    cluster.id, cluster.name, cluster.topology, cluster.conflict_strategy, drives.len()
+                    "{}",
+                    json!({
+                        "id": cluster.id.to_string(),
+                        "name": cluster.name,
+                        "topology": cluster.topology.to_string(),
+                        "conflict_strategy": cluster.conflict_strategy.to_string(),
+                        "settings": settings,
+                        "drives": drives.len(),
+                    })
                 );
             } else {
                 println!("Cluster: {}", cluster.name);
@@ -106,6 +185,10 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
                 println!("  Topology: {}", cluster.topology);
                 println!("  Conflict: {}", cluster.conflict_strategy);
                 println!("  Created:  {}", cluster.created_at);
+                println!("  Settings:");
+                for (key, value) in cluster.settings.entries() {
+                    println!("    {:<22} {}", key, value);
+                }
                 println!("  Drives:   {}", drives.len());
                 for d in &drives {
                     println!(
@@ -119,6 +202,26 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
             }
             Ok(())
         }
+        ClusterAction::Set { name, key, value } => {
+            let mut cluster = ops::get_cluster_by_name(&conn, &name)?
+                .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", name))?;
+            cluster
+                .settings
+                .set(&key, &value)
+                .map_err(|e| anyhow::anyhow!(e))?;
+            cluster.updated_at = chrono::Utc::now();
+            ops::update_cluster(&conn, &cluster)?;
+
+            if json {
+                println!(
+                    "{{\"cluster\": \"{}\", \"key\": \"{}\", \"value\": \"{}\"}}",
+                    cluster.name, key, value
+                );
+            } else {
+                println!("Set {} = {} on cluster '{}'", key, value, cluster.name);
+            }
+            Ok(())
+        }
         ClusterAction::Remove { name } => {
             let cluster = ops::get_cluster_by_name(&conn, &name)?
                 .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", name))?;
@@ -126,5 +229,208 @@ pub fn run(action: ClusterAction, json: bool) -> anyhow::Result<()> {
             println!("Removed cluster '{}'", name);
             Ok(())
         }
+        ClusterAction::Export { name, out } => {
+            let cluster = ops::get_cluster_by_name(&conn, &name)?
+                .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", name))?;
+            let manifest = export_manifest(&conn, &cluster)?;
+            let content = toml::to_string_pretty(&manifest)?;
+
+            match out {
+                Some(path) => {
+                    std::fs::write(&path, content)?;
+                    if json {
+                        println!(
+                            "{{\"cluster\": \"{}\", \"drives\": {}, \"out\": \"{}\"}}",
+                            cluster.name,
+                            manifest.drives.len(),
+                            path.display()
+                        );
+                    } else {
+                        println!(
+                            "Exported cluster '{}' ({} drives) to {}",
+                            cluster.name,
+                            manifest.drives.len(),
+                            path.display()
+                        );
+                    }
+                }
+                None => print!("{}", content),
+            }
+            Ok(())
+        }
+        ClusterAction::Import { file } => {
+            let content = std::fs::read_to_string(&file)?;
+            let manifest: ClusterManifest = toml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("invalid cluster file {}: {}", file.display(), e))?;
+            let discovery = super::drive::discovery()?;
+            let connected = discovery.discover_drives().unwrap_or_default();
+            import_manifest(&conn, &manifest, &connected, json)
+        }
+    }
+}
+
+/// Describe a cluster and its drives for `cluster export`, with the
+/// `.diffrignore` patterns of the drives that are connected.
+pub fn export_manifest(conn: &rusqlite::Connection, cluster: &Cluster) -> anyhow::Result<ClusterManifest> {
+    let drives = ops::list_drives_for_cluster(conn, &cluster.id)?;
+    let mut manifest = ClusterManifest::new(cluster, &drives);
+    for (spec, drive) in manifest.drives.iter_mut().zip(&drives) {
+        spec.ignore = read_ignore_file(drive.effective_root());
+    }
+    Ok(manifest)
+}
+
+/// Create (or update) the cluster described by a manifest and associate its
+/// drives by identity. Drives found in `connected` pick up their current mount
+/// point; the rest keep the exported mount as a placeholder until they are seen.
+pub fn import_manifest(
+    conn: &rusqlite::Connection,
+    manifest: &ClusterManifest,
+    connected: &[Drive],
+    json: bool,
+) -> anyhow::Result<()> {
+    let cluster = manifest.to_cluster();
+
+    match ops::get_cluster_by_name(conn, &cluster.name)? {
+        Some(existing) if existing.id != cluster.id => {
+            anyhow::bail!(
+                "a different cluster named '{}' already exists ({})",
+                existing.name,
+                existing.id
+            );
+        }
+        // Importing again brings the settings in line with the file.
+        Some(existing) => {
+            let mut updated = cluster.clone();
+            updated.created_at = existing.created_at;
+            ops::update_cluster(conn, &updated)?;
+        }
+        None => ops::insert_cluster(conn, &cluster)?,
+    }
+
+    let mut connected_count = 0;
+    for spec in &manifest.drives {
+        let found = connected.iter().find(|d| d.identity == spec.identity);
+        let mount_point = found
+            .map(|d| d.mount_point.clone())
+            .unwrap_or_else(|| spec.mount_point.clone());
+        let mut drive = spec.to_drive(&cluster.id, &mount_point);
+        if let Some(d) = found {
+            drive.total_bytes = d.total_bytes;
+            drive.free_bytes = d.free_bytes;
+            connected_count += 1;
+            restore_ignore_file(drive.effective_root(), &spec.ignore)?;
+        }
+
+        match ops::get_drive_by_identity(conn, &spec.identity)? {
+            Some(existing) => {
+                drive.id = existing.id;
+                drive.created_at = existing.created_at;
+                if found.is_none() {
+                    // Keep whatever this machine already knows about where it mounts.
+                    drive.mount_point = existing.mount_point;
+                    drive.sync_root = existing.sync_root.or(drive.sync_root);
+                    drive.last_seen = existing.last_seen;
+                }
+                ops::update_drive(conn, &drive)?;
+            }
+            None => ops::insert_drive(conn, &drive)?,
+        }
+
+        if !json {
+            println!(
+                "  {} {} ({}){}",
+                if found.is_some() { "+" } else { "-" },
+                spec.identity.identity_string(),
+                spec.role,
+                if spec.is_primary { " [PRIMARY]" } else { "" }
+            );
+        }
+    }
+
+    if json {
+        println!(
+            "{{\"id\": \"{}\", \"name\": \"{}\", \"drives\": {}, \"connected\": {}}}",
+            cluster.id,
+            cluster.name,
+            manifest.drives.len(),
+            connected_count
+        );
+    } else {
+        println!(
+            "Imported cluster '{}' ({} drives, {} connected)",
+            cluster.name,
+            manifest.drives.len(),
+            connected_count
+        );
+    }
+    Ok(())
+}
+
+/// The patterns in the `.diffrignore` under `root`, leaving out blank lines
+/// and comments. Empty if the drive isn't connected or has no such file.
+fn read_ignore_file(root: &Path) -> Vec<String> {
+    std::fs::read_to_string(root.join(IGNORE_FILE))
+        .map(|content| {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Write exported ignore patterns to a connected drive that has no
+/// `.diffrignore` of its own; one already there is left as it is.
+fn restore_ignore_file(root: &Path, patterns: &[String]) -> anyhow::Result<()> {
+    let path = root.join(IGNORE_FILE);
+    if patterns.is_empty() || path.exists() || !root.is_dir() {
+        return Ok(());
+    }
+    std::fs::write(path, format!("{}\n", patterns.join("\n")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+
+    #[test]
+    fn test_export_then_import_round_trips_settings_and_filters() {
+        let (here, there) = (tempfile::TempDir::new().unwrap(), tempfile::TempDir::new().unwrap());
+        std::fs::write(here.path().join(IGNORE_FILE), "# build output\n*.tmp\n\ntarget/\n").unwrap();
+        let source = diffr_db::open_memory_db().unwrap();
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        cluster.settings.ignore = vec!["Thumbs.db".into()];
+        cluster.settings.min_copies = Some(2);
+        ops::insert_cluster(&source, &cluster).unwrap();
+        let mut drive = Drive::new(DriveIdentity::new_hardware("SN1".into()), here.path().to_path_buf());
+        drive.cluster_id = Some(cluster.id.clone());
+        ops::insert_drive(&source, &drive).unwrap();
+
+        let text = toml::to_string_pretty(&export_manifest(&source, &cluster).unwrap()).unwrap();
+        let mut manifest: ClusterManifest = toml::from_str(&text).unwrap();
+        assert_eq!(manifest.drives[0].ignore, vec!["*.tmp", "target/"]);
+
+        // On the other machine the drive is mounted elsewhere.
+        let target = diffr_db::open_memory_db().unwrap();
+        let connected = [Drive::new(drive.identity.clone(), there.path().to_path_buf())];
+        import_manifest(&target, &manifest, &connected, true).unwrap();
+        let imported = ops::get_cluster_by_name(&target, "c").unwrap().unwrap();
+        assert_eq!(imported.settings, cluster.settings);
+        assert_eq!(
+            std::fs::read_to_string(there.path().join(IGNORE_FILE)).unwrap(),
+            "*.tmp\ntarget/\n"
+        );
+
+        // Importing a changed file again updates the existing cluster.
+        manifest.cluster.settings.min_copies = Some(3);
+        import_manifest(&target, &manifest, &connected, true).unwrap();
+        let imported = ops::get_cluster_by_name(&target, "c").unwrap().unwrap();
+        assert_eq!(imported.settings.min_copies, Some(3));
+        assert_eq!(ops::list_drives_for_cluster(&target, &imported.id).unwrap().len(), 1);
     }
 }
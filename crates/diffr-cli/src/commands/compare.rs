@@ -0,0 +1,151 @@
+use clap::Args;
+use diffr_core::cancel::Cancelled;
+use diffr_core::config::DiffrConfig;
+use diffr_core::units::format_bytes;
+use diffr_scan::scanner::{scan_directory_cached, ScanConfig};
+use diffr_sync::diff::{breakdown_by_top_level, compute_diff, diff_summary};
+
+use crate::commands::drive::lookup_drive;
+use diffr_core::models::cluster::NestedRepos;
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// First drive (serial number or synthetic ID)
+    drive_a: String,
+
+    /// Second drive (serial number or synthetic ID)
+    drive_b: String,
+
+    /// Follow symlinks while scanning (cycles are skipped)
+    #[arg(long)]
+    follow_symlinks: bool,
+}
+
+pub fn run(args: CompareArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    let left_drive = lookup_drive(&conn, &args.drive_a)?;
+    let right_drive = lookup_drive(&conn, &args.drive_b)?;
+
+    let power = DiffrConfig::load()?.power_guard(diffr_discovery::host::power_reading);
+    let mut scans = Vec::new();
+    for drive in [&left_drive, &right_drive] {
+        let root = drive.effective_root();
+        if !root.exists() {
+            anyhow::bail!(
+                "sync root does not exist: {} (drive {})",
+                root.display(),
+                drive.identity.identity_string()
+            );
+        }
+        let config = ScanConfig {
+            root: root.to_path_buf(),
+            drive_id: drive.id.clone(),
+            follow_symlinks: args.follow_symlinks,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: crate::progress::for_output(json),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: crate::interrupt::token(),
+            power,
+        };
+        let result = scan_directory_cached(&config, &conn)?;
+        if result.cancelled {
+            return Err(Cancelled.into());
+        }
+        scans.push(result.entries);
+    }
+
+    let diffs = compute_diff(&scans[0], &scans[1]);
+    let summary = diff_summary(&diffs);
+    let breakdown = breakdown_by_top_level(&diffs);
+
+    let total_files = diffs
+        .iter()
+        .filter(|d| !d.is_dir())
+        .count();
+    let differing_files: usize = breakdown.iter().map(|b| b.files).sum();
+    let differing_bytes: u64 = breakdown.iter().map(|b| b.bytes).sum();
+    let percent = |part: f64, whole: f64| if whole > 0.0 { part * 100.0 / whole } else { 0.0 };
+
+    if json {
+        let dirs: Vec<_> = breakdown
+            .iter()
+            .map(|b| {
+                format!(
+                    "{{\"dir\": {}, \"files\": {}, \"bytes\": {}}}",
+                    b.dir
+                        .as_ref()
+                        .map(|d| format!("\"{}\"", d.display()))
+                        .unwrap_or_else(|| "null".to_string()),
+                    b.files,
+                    b.bytes
+                )
+            })
+            .collect();
+        println!(
+            "{{\"identical\": {}, \"only_left\": {}, \"only_right\": {}, \"modified\": {}, \"conflicts\": {}, \"total_files\": {}, \"differing_files\": {}, \"differing_bytes\": {}, \"dirs\": [{}]}}",
+            summary.identical,
+            summary.only_left,
+            summary.only_right,
+            summary.modified,
+            summary.conflicts,
+            total_files,
+            differing_files,
+            differing_bytes,
+            dirs.join(", ")
+        );
+        return Ok(());
+    }
+
+    println!(
+        "Comparing {} ({}) with {} ({})",
+        left_drive.identity.identity_string(),
+        left_drive.effective_root().display(),
+        right_drive.identity.identity_string(),
+        right_drive.effective_root().display()
+    );
+    println!("  {}", summary);
+
+    if !summary.has_changes() {
+        println!("\nDrives are in sync.");
+        return Ok(());
+    }
+
+    println!(
+        "  {} of {} files differ ({:.1}%), {} to transfer",
+        differing_files,
+        total_files,
+        percent(differing_files as f64, total_files as f64),
+        format_bytes(differing_bytes)
+    );
+
+    println!("\nDifferences by top-level folder:");
+    for (i, b) in breakdown.iter().enumerate() {
+        let branch = if i + 1 == breakdown.len() { "└──" } else { "├──" };
+        let name = b
+            .dir
+            .as_ref()
+            .map(|d| format!("{}/", d.display()))
+            .unwrap_or_else(|| "(root)".to_string());
+        println!(
+            "  {} {:<30} {:>8} files {:>12} {:>6.1}%",
+            branch,
+            name,
+            b.files,
+            format_bytes(b.bytes),
+            percent(b.bytes as f64, differing_bytes as f64)
+        );
+    }
+
+    if summary.conflicts > 0 {
+        println!("\n{} conflicts would need resolution.", summary.conflicts);
+    }
+
+    Ok(())
+}
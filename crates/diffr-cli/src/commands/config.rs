@@ -1,5 +1,6 @@
 use clap::Subcommand;
-use diffr_core::config::DiffrConfig;
+use diffr_core::config::{check_config, find_key_line, ConfigIssue, DiffrConfig};
+use std::path::PathBuf;
 
 #[derive(Subcommand)]
 pub enum ConfigAction {
@@ -7,6 +8,12 @@ pub enum ConfigAction {
     Init,
     /// Show current configuration
     Show,
+    /// Check config.toml for unknown keys and invalid values
+    Validate {
+        /// Config file to check (default: ~/.diffr/config.toml)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
 }
 
 pub fn run(action: ConfigAction) -> anyhow::Result<()> {
@@ -28,5 +35,44 @@ pub fn run(action: ConfigAction) -> anyhow::Result<()> {
             println!("{}", toml);
             Ok(())
         }
+        ConfigAction::Validate { file } => {
+            let path = match file {
+                Some(path) => path,
+                None => DiffrConfig::config_path()?,
+            };
+            if !path.exists() {
+                println!("No config file at {}; the defaults are in use.", path.display());
+                return Ok(());
+            }
+            let content = std::fs::read_to_string(&path)?;
+            let mut issues = check_config(&content);
+            if issues.iter().all(|i| i.warning) {
+                let config: DiffrConfig = toml::from_str(&content)?;
+                let backends = diffr_discovery::registry::names();
+                if let Some(name) = config.discovery_backend.filter(|n| !backends.contains(n)) {
+                    issues.push(ConfigIssue {
+                        key: Some("discovery_backend".to_string()),
+                        line: find_key_line(&content, "", "discovery_backend"),
+                        message: format!(
+                            "unknown backend '{}' (allowed: {})",
+                            name,
+                            backends.join(", ")
+                        ),
+                        warning: false,
+                    });
+                }
+            }
+
+            for issue in &issues {
+                let level = if issue.warning { "warning" } else { "error" };
+                println!("{}: {}", level, issue);
+            }
+            let errors = issues.iter().filter(|i| !i.warning).count();
+            if errors > 0 {
+                anyhow::bail!("{} has {} error(s)", path.display(), errors);
+            }
+            println!("{} is valid", path.display());
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,218 @@
+use clap::Args;
+use diffr_core::cancel::Cancelled;
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::cluster::ClusterId;
+use diffr_core::models::drive::{Drive, DriveIdentity};
+use diffr_discovery::mtp::MtpStore;
+use diffr_scan::scanner::{scan_directory, ScanConfig};
+use diffr_sync::diff::{compute_diff, diff_summary};
+use diffr_sync::executor::{execute_plan, ExecConfig};
+use diffr_sync::topology::generate_one_way_plan;
+use std::path::{Path, PathBuf};
+use diffr_core::models::cluster::NestedRepos;
+
+#[derive(Args)]
+pub struct CopyArgs {
+    /// Source directory
+    src: PathBuf,
+
+    /// Destination directory (created if missing)
+    dst: PathBuf,
+
+    /// Delete files in the destination that don't exist in the source
+    #[arg(long)]
+    mirror: bool,
+
+    /// Dry run — show what would happen without making changes
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Verify file integrity after copy with SHA-256
+    #[arg(long)]
+    verify: bool,
+
+    /// Skip archiving before overwrite/delete
+    #[arg(long)]
+    no_archive: bool,
+
+    /// Move mirrored deletes to the destination's .diffr/trash instead of removing them
+    #[arg(long)]
+    trash: bool,
+
+    /// Follow symlinks while scanning (cycles are skipped)
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Copy no faster than this on average, such as 50MB/s
+    #[arg(long, value_parser = diffr_core::units::parse_rate)]
+    throttle: Option<u64>,
+}
+
+/// Resolve `dst` the way `src` was, even if it doesn't exist yet: canonicalize
+/// its nearest existing ancestor and put the missing components back on.
+fn resolve_dst(dst: &Path) -> anyhow::Result<PathBuf> {
+    let absolute = std::path::absolute(dst)?;
+    let existing = absolute
+        .ancestors()
+        .find(|a| a.exists())
+        .ok_or_else(|| anyhow::anyhow!("destination has no existing ancestor: {}", dst.display()))?;
+    let missing = absolute.strip_prefix(existing)?;
+    Ok(crate::commands::init::simplified_canonicalize(existing)?.join(missing))
+}
+
+pub fn run(args: CopyArgs, json: bool) -> anyhow::Result<()> {
+    let src = crate::commands::init::simplified_canonicalize(&args.src)
+        .map_err(|_| anyhow::anyhow!("source does not exist: {}", args.src.display()))?;
+    if !src.is_dir() {
+        anyhow::bail!("source is not a directory: {}", src.display());
+    }
+    let dst = resolve_dst(&args.dst)?;
+    if dst.starts_with(&src) || src.starts_with(&dst) {
+        anyhow::bail!(
+            "source and destination must not contain each other: {} -> {}",
+            src.display(),
+            dst.display()
+        );
+    }
+    if !args.dry_run {
+        std::fs::create_dir_all(&dst)?;
+    }
+
+    // Ad-hoc drives with throwaway identities; nothing is registered in the DB.
+    let source = Drive::new(DriveIdentity::new_synthetic(), src.clone());
+    let target = Drive::new(DriveIdentity::new_synthetic(), dst.clone());
+
+    if !json {
+        println!("Copying {} -> {}", src.display(), dst.display());
+        if args.dry_run {
+            println!("  [DRY RUN]");
+        }
+    }
+
+    let config = DiffrConfig::load()?;
+    let power = config.power_guard(diffr_discovery::host::power_reading);
+    let mut scans = Vec::new();
+    for drive in [&source, &target] {
+        let root = drive.effective_root();
+        if !root.exists() {
+            scans.push(Vec::new());
+            continue;
+        }
+        if !json {
+            println!("  Scanning {}...", root.display());
+        }
+        let config = ScanConfig {
+            root: root.to_path_buf(),
+            drive_id: drive.id.clone(),
+            follow_symlinks: args.follow_symlinks,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: crate::progress::for_output(json),
+            full_hashes: args.verify,
+            strong_hash: None,
+            cancel: crate::interrupt::token(),
+            power,
+        };
+        let result = scan_directory(&config)?;
+        if result.cancelled {
+            return Err(Cancelled.into());
+        }
+        scans.push(result.entries);
+    }
+
+    let diffs = compute_diff(&scans[0], &scans[1]);
+    if !json {
+        println!("  {}", diff_summary(&diffs));
+    }
+
+    let plan = generate_one_way_plan(ClusterId::new(), &source, &target, &diffs, args.mirror);
+
+    if !json {
+        println!(
+            "\nCopy plan: {} operations, {} bytes total",
+            plan.op_count(),
+            plan.total_bytes
+        );
+    }
+
+    if plan.operations.is_empty() {
+        if json {
+            println!("{{\"status\": \"up_to_date\"}}");
+        } else {
+            println!("Everything is up to date!");
+        }
+        return Ok(());
+    }
+
+    let exec_config = ExecConfig {
+        dry_run: args.dry_run,
+        verify: args.verify,
+        verify_hash: config.verify_hash,
+        verify_readback: config.verify_readback,
+        durability: config.durability,
+        archive: !args.no_archive,
+        central_archive: false,
+        progress: crate::progress::for_output(json),
+        trash: args.trash,
+        ownership: false,
+        cancel: crate::interrupt::token(),
+        attributes: Default::default(),
+        power,
+        merge_commands: Default::default(),
+        max_rate: args.throttle,
+        open_mtp: MtpStore::open,
+    };
+
+    let result = execute_plan(&plan, &[source, target], &exec_config)?;
+    let record = result.record;
+
+    if json {
+        println!(
+            "{{\"status\": \"{}\", \"files_synced\": {}, \"bytes_transferred\": {}, \"archived\": {}, \"errors\": {}}}",
+            record.status,
+            record.files_synced,
+            record.bytes_transferred,
+            result.archives.len(),
+            record.errors.len()
+        );
+    } else {
+        println!("\nCopy complete:");
+        println!("  Status:   {}", record.status);
+        println!("  Files:    {}", record.files_synced);
+        println!("  Bytes:    {}", record.bytes_transferred);
+        if !result.archives.is_empty() {
+            println!(
+                "  Archived: {} (under {})",
+                result.archives.len(),
+                dst.join(".diffr").join("archive").display()
+            );
+        }
+        if !record.errors.is_empty() {
+            println!("  Errors:   {}", record.errors.len());
+            for e in &record.errors {
+                println!("    - {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_missing_dst_inside_src_resolves_under_src() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        let src = crate::commands::init::simplified_canonicalize(dir.path()).unwrap();
+        let dst = resolve_dst(&dir.path().join("a/../new/deeper")).unwrap();
+        assert_eq!(dst, src.join("new/deeper"));
+        assert!(dst.starts_with(&src));
+    }
+}
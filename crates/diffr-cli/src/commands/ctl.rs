@@ -0,0 +1,100 @@
+use clap::Subcommand;
+use diffr_core::config::DiffrConfig;
+use serde_json::{json, Value};
+
+use super::daemon;
+
+#[derive(Subcommand)]
+pub enum CtlAction {
+    /// Show what the running daemon is syncing, and when
+    Status,
+    /// Hold back the daemon's scheduled syncs of a cluster, or of every cluster
+    Pause {
+        /// Cluster name (default: every cluster)
+        cluster: Option<String>,
+    },
+    /// Let the daemon sync a paused cluster again, or every cluster
+    Resume {
+        /// Cluster name (default: every cluster)
+        cluster: Option<String>,
+    },
+    /// Have the daemon sync a cluster now, even if paused or outside the schedule
+    SyncNow {
+        /// Cluster name
+        cluster: String,
+    },
+}
+
+pub fn run(action: CtlAction, json: bool) -> anyhow::Result<()> {
+    let (method, params) = match &action {
+        CtlAction::Status => ("status", json!({})),
+        CtlAction::Pause { cluster } => ("pause", json!({"cluster": cluster})),
+        CtlAction::Resume { cluster } => ("resume", json!({"cluster": cluster})),
+        CtlAction::SyncNow { cluster } => ("sync-now", json!({"cluster": cluster})),
+    };
+    let status = daemon::request(&DiffrConfig::home_dir()?, method, params)?;
+
+    if json {
+        println!("{}", status);
+        return Ok(());
+    }
+    match &action {
+        CtlAction::Status => {}
+        CtlAction::Pause { cluster: Some(c) } => println!("Paused '{}'.", c),
+        CtlAction::Pause { cluster: None } => println!("Paused every cluster."),
+        CtlAction::Resume { cluster: Some(c) } => println!("Resumed '{}'.", c),
+        CtlAction::Resume { cluster: None } => println!("Resumed every cluster."),
+        CtlAction::SyncNow { cluster } => println!("Syncing '{}' next.", cluster),
+    }
+    print_status(&status);
+    Ok(())
+}
+
+fn print_status(status: &Value) {
+    println!(
+        "Daemon (pid {}) syncs every {}{}",
+        status["pid"],
+        status["every"].as_str().unwrap_or("-"),
+        if status["paused"] == true { "; paused" } else { "" }
+    );
+    let clusters = status["clusters"].as_array().cloned().unwrap_or_default();
+    if clusters.is_empty() {
+        println!("No clusters to sync.");
+        return;
+    }
+    println!("{:<20} {:<10} {:<18} LAST RUN", "CLUSTER", "STATE", "NEXT RUN");
+    for c in &clusters {
+        let state = if c["running"] == true {
+            "syncing"
+        } else if c["queued"] == true {
+            "queued"
+        } else if c["paused"] == true || status["paused"] == true {
+            "paused"
+        } else {
+            "waiting"
+        };
+        let last = match &c["last_run"] {
+            Value::Null => "-".to_string(),
+            last => {
+                let detail = last["detail"].as_str().map(|d| format!(" ({})", d)).unwrap_or_default();
+                format!("{} {}{}", local_time(&last["finished"]), last["status"].as_str().unwrap_or("-"), detail)
+            }
+        };
+        println!(
+            "{:<20} {:<10} {:<18} {}",
+            c["cluster"].as_str().unwrap_or("-"),
+            state,
+            local_time(&c["next_run"]),
+            last
+        );
+    }
+}
+
+/// An RFC 3339 time from the daemon as local time.
+fn local_time(value: &Value) -> String {
+    value
+        .as_str()
+        .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".into())
+}
@@ -0,0 +1,751 @@
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use diffr_core::cancel::CancellationToken;
+use diffr_core::config::DiffrConfig;
+use diffr_core::units::{format_duration, parse_duration};
+use diffr_db::ops;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::serve::{
+    error_response, run_json, RpcError, BUSY, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR,
+};
+use super::service::Manager;
+
+/// How often the daemon checks for work and for Ctrl-C while idle.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How often the daemon looks for clusters added since it started, when it
+/// syncs every cluster.
+const RELIST_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Subcommand)]
+pub enum DaemonAction {
+    /// Run scheduled syncs in the foreground, taking commands from `diffr ctl`
+    Run(RunArgs),
+    /// Run the daemon in the background from login on, as a systemd user
+    /// unit, a launchd agent or a scheduled task, and start it now
+    Install(RunArgs),
+    /// Start the installed daemon
+    Start,
+    /// Stop the installed daemon until it's started again or you next log in
+    Stop,
+    /// Stop the installed daemon and remove it
+    Uninstall,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Clusters to sync (default: every cluster, including ones added later)
+    clusters: Vec<String>,
+
+    /// How long to wait after a cluster's sync before syncing it again
+    #[arg(long, default_value = "1h", value_parser = parse_duration)]
+    every: Duration,
+}
+
+/// How one sync the daemon ran turned out.
+#[derive(Debug, Clone)]
+struct LastRun {
+    finished: DateTime<Utc>,
+    /// The sync's `status`: `success`, `up_to_date`, `skipped` when the
+    /// schedule held it back, `failed`, and so on.
+    status: String,
+    /// Why it was skipped or failed.
+    detail: Option<String>,
+}
+
+#[derive(Debug)]
+struct ClusterState {
+    paused: bool,
+    running: bool,
+    /// Asked for with `sync-now`: run at the next chance, paused or not.
+    queued: bool,
+    next_run: DateTime<Utc>,
+    last: Option<LastRun>,
+}
+
+impl ClusterState {
+    fn new(next_run: DateTime<Utc>) -> Self {
+        ClusterState {
+            paused: false,
+            running: false,
+            queued: false,
+            next_run,
+            last: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Every cluster is held back.
+    paused: bool,
+    clusters: BTreeMap<String, ClusterState>,
+}
+
+/// What the scheduler and the control connections share.
+struct Daemon {
+    state: Mutex<State>,
+    /// Woken when a control command may have made a sync due.
+    wake: Condvar,
+    every: Duration,
+}
+
+impl Daemon {
+    fn new(clusters: &[String], every: Duration) -> Self {
+        let now = Utc::now();
+        let state = State {
+            paused: false,
+            clusters: clusters.iter().map(|c| (c.clone(), ClusterState::new(now))).collect(),
+        };
+        Daemon {
+            state: Mutex::new(state),
+            wake: Condvar::new(),
+            every,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sync `names` from now on, and stop syncing clusters not among them.
+    /// New ones are due at once.
+    fn set_clusters(&self, names: &[String]) {
+        let mut state = self.lock();
+        state.clusters.retain(|name, c| c.running || names.contains(name));
+        for name in names {
+            state
+                .clusters
+                .entry(name.clone())
+                .or_insert_with(|| ClusterState::new(Utc::now()));
+        }
+    }
+
+    /// The cluster to sync next, if one is due, and whether it was asked for
+    /// with `sync-now`. Marks it running.
+    fn take_due(&self, now: DateTime<Utc>) -> Option<(String, bool)> {
+        let mut state = self.lock();
+        let paused = state.paused;
+        let (name, c) = state
+            .clusters
+            .iter_mut()
+            .filter(|(_, c)| !c.running)
+            .filter(|(_, c)| c.queued || (!paused && !c.paused && c.next_run <= now))
+            .min_by_key(|(_, c)| (!c.queued, c.next_run))?;
+        let forced = std::mem::take(&mut c.queued);
+        c.running = true;
+        Some((name.clone(), forced))
+    }
+
+    fn finish(&self, name: &str, last: LastRun) {
+        let mut state = self.lock();
+        if let Some(c) = state.clusters.get_mut(name) {
+            c.running = false;
+            c.next_run = last.finished + chrono::Duration::from_std(self.every).unwrap_or(chrono::Duration::MAX);
+            c.last = Some(last);
+        }
+    }
+
+    /// Run the sync that's due, if any, with `sync`. Returns whether one ran.
+    fn run_due(&self, sync: impl FnOnce(&str, bool) -> LastRun) -> bool {
+        let Some((name, forced)) = self.take_due(Utc::now()) else {
+            return false;
+        };
+        let last = sync(&name, forced);
+        self.finish(&name, last);
+        true
+    }
+
+    fn status(&self) -> Value {
+        let state = self.lock();
+        let clusters: Vec<Value> = state
+            .clusters
+            .iter()
+            .map(|(name, c)| {
+                json!({
+                    "cluster": name,
+                    "paused": c.paused,
+                    "running": c.running,
+                    "queued": c.queued,
+                    "next_run": c.next_run.to_rfc3339(),
+                    "last_run": c.last.as_ref().map(|l| json!({
+                        "finished": l.finished.to_rfc3339(),
+                        "status": l.status,
+                        "detail": l.detail,
+                    })),
+                })
+            })
+            .collect();
+        json!({
+            "pid": std::process::id(),
+            "paused": state.paused,
+            "every": format_duration(self.every),
+            "clusters": clusters,
+        })
+    }
+
+    /// Carry out a control request. Every method but `status` changes the
+    /// state and answers with the new status.
+    fn control(&self, method: &str, params: &Map<String, Value>) -> Result<Value, RpcError> {
+        let cluster = match params.get("cluster") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(name)) => Some(name.as_str()),
+            Some(_) => return Err(RpcError::new(INVALID_PARAMS, "param 'cluster' must be a string")),
+        };
+        let unknown = |name: &str| RpcError::new(INVALID_PARAMS, format!("the daemon doesn't sync cluster '{}'", name));
+        {
+            let mut state = self.lock();
+            match (method, cluster) {
+                ("status", _) => {}
+                ("pause" | "resume", Some(name)) => {
+                    let c = state.clusters.get_mut(name).ok_or_else(|| unknown(name))?;
+                    c.paused = method == "pause";
+                }
+                ("pause", None) => state.paused = true,
+                ("resume", None) => {
+                    state.paused = false;
+                    for c in state.clusters.values_mut() {
+                        c.paused = false;
+                    }
+                }
+                ("sync-now", Some(name)) => {
+                    let c = state.clusters.get_mut(name).ok_or_else(|| unknown(name))?;
+                    if c.running {
+                        return Err(RpcError::new(BUSY, format!("cluster '{}' is already being synced", name)));
+                    }
+                    c.queued = true;
+                }
+                ("sync-now", None) => return Err(RpcError::new(INVALID_PARAMS, "missing param 'cluster'")),
+                _ => return Err(RpcError::new(METHOD_NOT_FOUND, format!("no method '{}'", method))),
+            }
+        }
+        self.wake.notify_all();
+        Ok(self.status())
+    }
+
+    /// Sleep until woken by a control request, or for at most `timeout`.
+    fn idle(&self, timeout: Duration) {
+        let state = self.lock();
+        let _ = self.wake.wait_timeout(state, timeout);
+    }
+}
+
+pub fn run(action: DaemonAction, json: bool) -> anyhow::Result<()> {
+    match action {
+        DaemonAction::Run(args) => run_daemon(args, json),
+        DaemonAction::Install(args) => {
+            check_clusters(&diffr_db::open_db(&DiffrConfig::db_path()?)?, &args.clusters)?;
+            let manager = Manager::here()?;
+            let file = manager.install(&service_command(&args)?)?;
+            if json {
+                println!("{}", json!({"action": "install", "service": manager.name(), "file": file}));
+            } else {
+                println!("Installed the daemon as {} ({}) and started it.", manager.describe(), file.display());
+                println!("It starts whenever you log in. Control it with `diffr ctl`.");
+            }
+            Ok(())
+        }
+        DaemonAction::Start => {
+            let manager = Manager::here()?;
+            manager.start()?;
+            if json {
+                println!("{}", json!({"action": "start", "service": manager.name()}));
+            } else {
+                println!("Started the daemon.");
+            }
+            Ok(())
+        }
+        DaemonAction::Stop => {
+            let manager = Manager::here()?;
+            manager.stop()?;
+            if json {
+                println!("{}", json!({"action": "stop", "service": manager.name()}));
+            } else {
+                println!("Stopped the daemon. It starts again when you log in, or with `diffr daemon start`.");
+            }
+            Ok(())
+        }
+        DaemonAction::Uninstall => {
+            let manager = Manager::here()?;
+            let file = manager.uninstall()?;
+            if json {
+                println!("{}", json!({"action": "uninstall", "service": manager.name(), "file": file}));
+            } else {
+                println!("Stopped the daemon and removed {}.", file.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The command line an installed daemon runs: this `diffr`, with the same
+/// profile, running `args`.
+fn service_command(args: &RunArgs) -> anyhow::Result<Vec<String>> {
+    let mut command = vec![std::env::current_exe()?.display().to_string()];
+    if let Some(profile) = DiffrConfig::active_profile() {
+        command.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    command.extend(["daemon".to_string(), "run".to_string()]);
+    command.extend(args.clusters.iter().cloned());
+    command.extend(["--every".to_string(), format!("{}s", args.every.as_secs())]);
+    Ok(command)
+}
+
+fn check_clusters(conn: &rusqlite::Connection, names: &[String]) -> anyhow::Result<()> {
+    for name in names {
+        if ops::get_cluster_by_name(conn, name)?.is_none() {
+            anyhow::bail!("cluster '{}' not found", name);
+        }
+    }
+    Ok(())
+}
+
+fn run_daemon(args: RunArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+    let list = || -> anyhow::Result<Vec<String>> {
+        if args.clusters.is_empty() {
+            Ok(ops::list_clusters(&conn)?.into_iter().map(|c| c.name).collect())
+        } else {
+            Ok(args.clusters.clone())
+        }
+    };
+    check_clusters(&conn, &args.clusters)?;
+
+    let daemon = Arc::new(Daemon::new(&list()?, args.every));
+    let endpoint = endpoint::Endpoint::bind(&DiffrConfig::home_dir()?)?;
+    if json {
+        println!(
+            "{}",
+            json!({"event": "started", "endpoint": endpoint.to_string(), "every": format_duration(args.every)})
+        );
+    } else {
+        let which = if args.clusters.is_empty() {
+            "every cluster".to_string()
+        } else {
+            args.clusters.join(", ")
+        };
+        println!(
+            "Syncing {} every {}. Control it with `diffr ctl` (listening on {}).",
+            which,
+            format_duration(args.every),
+            endpoint
+        );
+        println!("Press Ctrl-C to stop.");
+    }
+    let listener = endpoint.listener()?;
+    let served = Arc::clone(&daemon);
+    std::thread::spawn(move || serve(listener, &served));
+
+    let cancel = crate::interrupt::token();
+    let mut listed = Instant::now();
+    while !cancel.is_cancelled() {
+        if args.clusters.is_empty() && listed.elapsed() >= RELIST_EVERY {
+            match list() {
+                Ok(names) => daemon.set_clusters(&names),
+                Err(e) => tracing::warn!("listing clusters failed: {}", e),
+            }
+            listed = Instant::now();
+        }
+        let ran = daemon.run_due(|name, forced| {
+            let last = sync(name, forced, &cancel);
+            report(name, &last, json);
+            last
+        });
+        if !ran {
+            daemon.idle(POLL);
+        }
+    }
+    Ok(())
+}
+
+/// Sync `cluster` as its own `diffr sync` process, under the schedule
+/// unless `forced`.
+fn sync(cluster: &str, forced: bool, cancel: &CancellationToken) -> LastRun {
+    let mut args = vec!["sync".to_string(), cluster.to_string()];
+    if !forced {
+        args.push("--scheduled".into());
+    }
+    let output = run_json(&args, |_| {});
+    let finished = Utc::now();
+    match output {
+        Ok(output) if output.success => LastRun {
+            finished,
+            status: output.result["status"].as_str().unwrap_or("success").to_string(),
+            detail: output.result["reason"].as_str().map(String::from),
+        },
+        // Ctrl-C reaches the sync too, which stops as cancelled.
+        Ok(_) if cancel.is_cancelled() => LastRun {
+            finished,
+            status: "cancelled".into(),
+            detail: None,
+        },
+        Ok(output) => LastRun {
+            finished,
+            status: "failed".into(),
+            detail: Some(output.error_message(&args)),
+        },
+        Err(e) => LastRun {
+            finished,
+            status: "failed".into(),
+            detail: Some(e.to_string()),
+        },
+    }
+}
+
+fn report(cluster: &str, last: &LastRun, json: bool) {
+    if json {
+        println!(
+            "{}",
+            json!({"event": "synced", "cluster": cluster, "status": last.status, "detail": last.detail})
+        );
+    } else {
+        let detail = last.detail.as_deref().map(|d| format!(" ({})", d)).unwrap_or_default();
+        println!(
+            "{} {}: {}{}",
+            last.finished.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+            cluster,
+            last.status,
+            detail
+        );
+    }
+}
+
+/// Answer control connections until the process exits.
+fn serve(listener: endpoint::Listener, daemon: &Arc<Daemon>) {
+    loop {
+        let stream = match endpoint::accept(&listener) {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("accepting a control connection failed: {}", e);
+                continue;
+            }
+        };
+        let daemon = Arc::clone(daemon);
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &daemon) {
+                tracing::debug!("control connection closed: {}", e);
+            }
+        });
+    }
+}
+
+/// Answer JSON-RPC requests from one client, one per line, as `diffr
+/// serve` does.
+fn serve_connection(stream: endpoint::Stream, daemon: &Daemon) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle(&request, daemon),
+            Err(e) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        if let Some(response) = response {
+            writeln!(writer, "{}", response)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle(request: &Value, daemon: &Daemon) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let empty = Map::new();
+    let result = match (request.get("method").and_then(Value::as_str), request.get("params")) {
+        (None, _) => Err(RpcError::new(INVALID_REQUEST, "request has no method")),
+        (Some(method), None | Some(Value::Null)) => daemon.control(method, &empty),
+        (Some(method), Some(Value::Object(params))) => daemon.control(method, params),
+        (Some(_), Some(_)) => Err(RpcError::new(INVALID_PARAMS, "params must be an object")),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error_response(id, e),
+    })
+}
+
+/// Send one request to the daemon whose endpoint is in `home` and return
+/// its result.
+pub fn request(home: &Path, method: &str, params: Value) -> anyhow::Result<Value> {
+    let stream = endpoint::connect(home).map_err(|e| {
+        anyhow::anyhow!("no daemon is running ({}); start one with `diffr daemon run`", e)
+    })?;
+    let mut writer = stream.try_clone()?;
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    writeln!(writer, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line)
+        .map_err(|_| anyhow::anyhow!("the daemon closed the connection without answering"))?;
+    match response.get("error") {
+        Some(error) => anyhow::bail!("{}", error["message"].as_str().unwrap_or("the daemon refused the request")),
+        None => Ok(response["result"].clone()),
+    }
+}
+
+/// Where the daemon listens. On Unix, a socket in the diffr home that only
+/// its owner can open.
+#[cfg(unix)]
+mod endpoint {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+
+    pub type Listener = UnixListener;
+    pub type Stream = UnixStream;
+
+    pub fn socket_path(home: &Path) -> PathBuf {
+        home.join("daemon.sock")
+    }
+
+    /// The bound socket, removed again when dropped.
+    pub struct Endpoint {
+        path: PathBuf,
+        listener: UnixListener,
+    }
+
+    impl Endpoint {
+        pub fn bind(home: &Path) -> anyhow::Result<Self> {
+            std::fs::create_dir_all(home)?;
+            let path = socket_path(home);
+            if path.exists() {
+                if UnixStream::connect(&path).is_ok() {
+                    anyhow::bail!("a daemon is already running (listening on {})", path.display());
+                }
+                // Left behind by a daemon that didn't exit cleanly.
+                std::fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            Ok(Endpoint { path, listener })
+        }
+
+        pub fn listener(&self) -> std::io::Result<Listener> {
+            self.listener.try_clone()
+        }
+    }
+
+    impl std::fmt::Display for Endpoint {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.path.display())
+        }
+    }
+
+    impl Drop for Endpoint {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    pub fn accept(listener: &Listener) -> std::io::Result<Stream> {
+        listener.accept().map(|(stream, _)| stream)
+    }
+
+    pub fn connect(home: &Path) -> std::io::Result<Stream> {
+        UnixStream::connect(socket_path(home))
+    }
+}
+
+/// Where the daemon listens. Elsewhere, a loopback port written with a
+/// token to `daemon.addr` in the diffr home; clients send the token as
+/// their first line, so only those who can read the file get in.
+#[cfg(not(unix))]
+mod endpoint {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    pub type Stream = TcpStream;
+
+    pub struct Listener {
+        listener: TcpListener,
+        token: String,
+    }
+
+    pub fn addr_path(home: &Path) -> PathBuf {
+        home.join("daemon.addr")
+    }
+
+    /// The bound port, its file removed again when dropped.
+    pub struct Endpoint {
+        path: PathBuf,
+        addr: SocketAddr,
+        listener: TcpListener,
+        token: String,
+    }
+
+    impl Endpoint {
+        pub fn bind(home: &Path) -> anyhow::Result<Self> {
+            std::fs::create_dir_all(home)?;
+            let path = addr_path(home);
+            if connect(home).is_ok() {
+                anyhow::bail!("a daemon is already running (see {})", path.display());
+            }
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let addr = listener.local_addr()?;
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            std::fs::write(&path, format!("{}\n{}\n", addr, token))?;
+            Ok(Endpoint {
+                path,
+                addr,
+                listener,
+                token,
+            })
+        }
+
+        pub fn listener(&self) -> std::io::Result<Listener> {
+            Ok(Listener {
+                listener: self.listener.try_clone()?,
+                token: self.token.clone(),
+            })
+        }
+    }
+
+    impl std::fmt::Display for Endpoint {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.addr)
+        }
+    }
+
+    impl Drop for Endpoint {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// The next client that sends the token.
+    pub fn accept(listener: &Listener) -> std::io::Result<Stream> {
+        loop {
+            let (stream, _) = listener.listener.accept()?;
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_ok()
+                && super::super::serve::tokens_match(line.trim(), &listener.token)
+            {
+                stream.set_read_timeout(None)?;
+                return Ok(stream);
+            }
+        }
+    }
+
+    pub fn connect(home: &Path) -> std::io::Result<Stream> {
+        let text = std::fs::read_to_string(addr_path(home))?;
+        let mut lines = text.lines();
+        let (Some(addr), Some(token)) = (lines.next(), lines.next()) else {
+            return Err(std::io::Error::other("daemon.addr is malformed"));
+        };
+        let addr: SocketAddr = addr.parse().map_err(std::io::Error::other)?;
+        let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+        writeln!(stream, "{}", token)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon() -> Daemon {
+        Daemon::new(&["docs".to_string(), "photos".to_string()], Duration::from_secs(3600))
+    }
+
+    fn ran(status: &str) -> LastRun {
+        LastRun {
+            finished: Utc::now(),
+            status: status.to_string(),
+            detail: None,
+        }
+    }
+
+    fn control(daemon: &Daemon, method: &str, cluster: Option<&str>) -> Result<Value, RpcError> {
+        let mut params = Map::new();
+        if let Some(cluster) = cluster {
+            params.insert("cluster".into(), cluster.into());
+        }
+        daemon.control(method, &params)
+    }
+
+    #[test]
+    fn test_runs_each_cluster_when_due_unless_paused() {
+        let daemon = daemon();
+        control(&daemon, "pause", Some("docs")).ok().unwrap();
+
+        let mut synced = Vec::new();
+        while daemon.run_due(|name, forced| {
+            synced.push((name.to_string(), forced));
+            ran("success")
+        }) {}
+        assert_eq!(synced, vec![("photos".to_string(), false)]);
+
+        // Synced clusters wait out the interval; sync-now doesn't, even
+        // for a paused one.
+        let status = control(&daemon, "sync-now", Some("docs")).ok().unwrap();
+        assert_eq!(status["clusters"][0]["queued"], true);
+        assert!(daemon.run_due(|name, forced| {
+            assert_eq!((name, forced), ("docs", true));
+            ran("up_to_date")
+        }));
+        assert!(!daemon.run_due(|_, _| unreachable!()));
+
+        let status = daemon.status();
+        assert_eq!(status["clusters"][0]["last_run"]["status"], "up_to_date");
+        assert_eq!(status["clusters"][1]["paused"], false);
+        assert_eq!(status["every"], "1h 00m");
+    }
+
+    #[test]
+    fn test_pausing_everything_holds_back_due_syncs() {
+        let daemon = daemon();
+        control(&daemon, "pause", None).ok().unwrap();
+        assert!(daemon.take_due(Utc::now()).is_none());
+        let status = control(&daemon, "resume", None).ok().unwrap();
+        assert_eq!(status["paused"], false);
+        assert_eq!(daemon.take_due(Utc::now()).map(|(name, _)| name).as_deref(), Some("docs"));
+
+        // docs is running now.
+        let busy = error_response(Value::Null, control(&daemon, "sync-now", Some("docs")).err().unwrap());
+        assert_eq!(busy["error"]["message"], "cluster 'docs' is already being synced");
+        assert!(control(&daemon, "pause", Some("music")).is_err());
+        assert!(control(&daemon, "sync-now", None).is_err());
+        assert!(control(&daemon, "stop", None).is_err());
+    }
+
+    #[test]
+    fn test_cluster_list_follows_the_catalog() {
+        let daemon = daemon();
+        assert!(daemon.take_due(Utc::now()).is_some());
+        daemon.set_clusters(&["music".to_string()]);
+        let names: Vec<String> = daemon.lock().clusters.keys().cloned().collect();
+        // docs is still syncing, so it stays until it's done.
+        assert_eq!(names, vec!["docs", "music"]);
+    }
+
+    #[test]
+    fn test_ctl_talks_to_the_daemon_over_its_endpoint() {
+        let home = tempfile::TempDir::new().unwrap();
+        let daemon = Arc::new(daemon());
+        let endpoint = endpoint::Endpoint::bind(home.path()).unwrap();
+        assert!(endpoint::Endpoint::bind(home.path()).is_err(), "a second daemon shouldn't start");
+        let listener = endpoint.listener().unwrap();
+        let served = Arc::clone(&daemon);
+        std::thread::spawn(move || serve(listener, &served));
+
+        let status = request(home.path(), "pause", json!({"cluster": "photos"})).unwrap();
+        assert_eq!(status["clusters"][1]["paused"], true);
+        assert!(daemon.lock().clusters["photos"].paused);
+        let err = request(home.path(), "sync-now", json!({"cluster": "music"})).unwrap_err();
+        assert_eq!(err.to_string(), "the daemon doesn't sync cluster 'music'");
+
+        drop(endpoint);
+        assert!(request(home.path(), "status", json!({})).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use clap::Subcommand;
+use diffr_core::config::DiffrConfig;
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum DbAction {
+    /// Merge another machine's diffr.db into the local database
+    Merge {
+        /// Path to the other database file
+        other: PathBuf,
+        /// Report what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub fn run(action: DbAction, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    match action {
+        DbAction::Merge { other, dry_run } => {
+            let report = diffr_db::merge::merge_database_file(&conn, &other, dry_run)?;
+
+            if json {
+                println!(
+                    "{{\"dry_run\": {}, \"clusters_added\": {}, \"clusters_updated\": {}, \"drives_added\": {}, \"drives_updated\": {}, \"sync_records_added\": {}, \"archives_added\": {}, \"conflicts\": {}}}",
+                    dry_run,
+                    report.clusters_added,
+                    report.clusters_updated,
+                    report.drives_added,
+                    report.drives_updated,
+                    report.sync_records_added,
+                    report.archives_added,
+                    report.conflicts.len()
+                );
+            } else {
+                println!(
+                    "{} {}",
+                    if dry_run { "Would merge" } else { "Merged" },
+                    other.display()
+                );
+                println!(
+                    "  Clusters:     {} added, {} updated",
+                    report.clusters_added, report.clusters_updated
+                );
+                println!(
+                    "  Drives:       {} added, {} updated",
+                    report.drives_added, report.drives_updated
+                );
+                println!("  Sync history: {} records added", report.sync_records_added);
+                println!("  Archives:     {} entries added", report.archives_added);
+                if !report.conflicts.is_empty() {
+                    println!("  Skipped:");
+                    for c in &report.conflicts {
+                        println!("    - {}", c);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
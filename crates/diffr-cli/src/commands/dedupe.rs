@@ -0,0 +1,175 @@
+use clap::Subcommand;
+use diffr_core::config::DiffrConfig;
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+use diffr_sync::dedupe::build_report;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// How many directories the text report lists, most duplicated first.
+const TOP_GROUPS: usize = 20;
+
+#[derive(Subcommand)]
+pub enum DedupeAction {
+    /// Show how much stored data is unique and how much is duplicated,
+    /// from the catalog each sync saves
+    Report {
+        /// Report on one cluster
+        #[arg(long, required_unless_present = "all")]
+        cluster: Option<String>,
+        /// Report across every drive and cluster
+        #[arg(long, conflicts_with = "cluster")]
+        all: bool,
+        /// Directory levels to group files by
+        #[arg(long, default_value = "1")]
+        depth: usize,
+        /// Also write the per-directory rows to this CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+}
+
+pub fn run(action: DedupeAction, json: bool) -> anyhow::Result<()> {
+    match action {
+        DedupeAction::Report {
+            cluster,
+            all,
+            depth,
+            csv,
+        } => {
+            // clap wants one or the other: every cluster, or the one named.
+            let cluster = if all { None } else { cluster };
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+
+            let names: HashMap<_, _> = ops::list_clusters(&conn)?
+                .into_iter()
+                .map(|c| (c.id, c.name))
+                .collect();
+            if let Some(name) = &cluster {
+                if !names.values().any(|n| n == name) {
+                    anyhow::bail!("cluster '{}' not found", name);
+                }
+            }
+            let files: Vec<(String, _)> = ops::list_cataloged_files(&conn)?
+                .into_iter()
+                .map(|(id, entry)| {
+                    let name = id
+                        .and_then(|id| names.get(&id).cloned())
+                        .unwrap_or_else(|| "(none)".to_string());
+                    (name, entry)
+                })
+                .filter(|(name, _)| cluster.as_ref().is_none_or(|c| c == name))
+                .collect();
+            let report = build_report(&files, depth);
+
+            if let Some(path) = &csv {
+                let mut out = String::from("cluster,directory,files,bytes,duplicate_bytes\n");
+                for ((cluster, dir), totals) in &report.groups {
+                    out.push_str(&format!(
+                        "{},{},{},{},{}\n",
+                        csv_field(cluster),
+                        csv_field(&dir.display().to_string()),
+                        totals.files,
+                        totals.bytes,
+                        totals.duplicate_bytes
+                    ));
+                }
+                std::fs::write(path, out)?;
+            }
+
+            if json {
+                let groups: Vec<String> = report
+                    .groups
+                    .iter()
+                    .map(|((cluster, dir), t)| {
+                        format!(
+                            "{{\"cluster\": \"{}\", \"directory\": \"{}\", \"files\": {}, \"bytes\": {}, \"duplicate_bytes\": {}}}",
+                            cluster,
+                            dir.display(),
+                            t.files,
+                            t.bytes,
+                            t.duplicate_bytes
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{{\"files\": {}, \"bytes\": {}, \"unique_bytes\": {}, \"duplicate_bytes\": {}, \"replica_bytes\": {}, \"cross_cluster_bytes\": {}, \"unhashed_files\": {}, \"unhashed_bytes\": {}, \"groups\": [{}]}}",
+                    report.total.files,
+                    report.total.bytes,
+                    report.unique_bytes,
+                    report.total.duplicate_bytes,
+                    report.replica_bytes,
+                    report.cross_cluster_bytes,
+                    report.unhashed.files,
+                    report.unhashed.bytes,
+                    groups.join(", ")
+                );
+                return Ok(());
+            }
+
+            if report.total.files == 0 {
+                println!("No hashed files in the catalog.");
+            } else {
+                println!(
+                    "Stored:     {} in {} files",
+                    format_bytes(report.total.bytes),
+                    report.total.files
+                );
+                println!("Unique:     {}", format_bytes(report.unique_bytes));
+                println!(
+                    "Duplicated: {} ({} replicas within a cluster, {} shared between clusters)",
+                    format_bytes(report.total.duplicate_bytes),
+                    format_bytes(report.replica_bytes),
+                    format_bytes(report.cross_cluster_bytes)
+                );
+
+                let mut groups: Vec<_> = report
+                    .groups
+                    .iter()
+                    .filter(|(_, t)| t.duplicate_bytes > 0)
+                    .collect();
+                groups.sort_by_key(|(_, t)| std::cmp::Reverse(t.duplicate_bytes));
+                if !groups.is_empty() {
+                    println!(
+                        "\n{:<16} {:<32} {:>8} {:>10} {:>10}",
+                        "CLUSTER", "DIRECTORY", "FILES", "BYTES", "DUPLICATE"
+                    );
+                    for ((cluster, dir), t) in groups.iter().take(TOP_GROUPS) {
+                        println!(
+                            "{:<16} {:<32} {:>8} {:>10} {:>10}",
+                            cluster,
+                            dir.display().to_string(),
+                            t.files,
+                            format_bytes(t.bytes),
+                            format_bytes(t.duplicate_bytes)
+                        );
+                    }
+                    if groups.len() > TOP_GROUPS {
+                        println!("... and {} more directories", groups.len() - TOP_GROUPS);
+                    }
+                }
+            }
+            if report.unhashed.files > 0 {
+                println!(
+                    "\n{} files ({}) weren't hashed and are left out; set hash = \"xxh3\" in a repo's .diffr/repo.toml to include them.",
+                    report.unhashed.files,
+                    format_bytes(report.unhashed.bytes)
+                );
+            }
+            if let Some(path) = &csv {
+                println!("\nWrote {}", path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,201 @@
+use clap::Args;
+use diffr_core::config::{DiffrConfig, RepoConfig};
+use diffr_core::models::drive::Drive;
+use diffr_db::ops;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use super::init::{drives_for_root, simplified_canonicalize};
+
+#[derive(Args)]
+pub struct DeinitArgs {
+    /// Repo to un-initialize (defaults to current directory)
+    path: Option<PathBuf>,
+
+    /// Don't ask for confirmation
+    #[arg(long, short)]
+    yes: bool,
+}
+
+pub fn run(args: DeinitArgs, json: bool) -> anyhow::Result<()> {
+    let raw_path = args.path.unwrap_or_else(|| PathBuf::from("."));
+    let root = simplified_canonicalize(&raw_path)
+        .map_err(|_| anyhow::anyhow!("path does not exist: {}", raw_path.display()))?;
+
+    if RepoConfig::load(&root)?.is_none() {
+        anyhow::bail!("{} is not a diffr repo", root.display());
+    }
+    let diffr_dir = root.join(".diffr");
+    let drives = drives_for_root(&root)?;
+    let drive_list: Vec<&Drive> = drives.iter().map(|(d, _)| d).collect();
+    let external = external_metadata_dirs(&root, &drive_list);
+
+    let archive_dir = diffr_dir.join("archive");
+    let archived_files = count_files(&archive_dir);
+    let external_archived: usize = external.iter().map(|dir| count_files(&dir.join("archive"))).sum();
+
+    if json && !args.yes {
+        anyhow::bail!("--json needs --yes, since it cannot prompt for confirmation");
+    }
+    if !args.yes {
+        if archived_files > 0 {
+            println!(
+                "Warning: {} contains {} archived file versions that will be deleted.",
+                archive_dir.display(),
+                archived_files
+            );
+        }
+        for dir in &external {
+            println!("This repo's drive keeps its metadata outside it, in {}.", dir.display());
+        }
+        if external_archived > 0 {
+            println!(
+                "Warning: {} archived file versions there will be deleted too.",
+                external_archived
+            );
+        }
+        if !super::confirm(&format!("Remove diffr metadata from {}?", root.display()))? {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    std::fs::remove_dir_all(&diffr_dir)?;
+    for dir in &external {
+        std::fs::remove_dir_all(dir)?;
+    }
+    // The archives kept in those dirs are gone; drop their records too.
+    let forgotten = if drive_list.is_empty() {
+        0
+    } else {
+        forget_archives(&diffr_db::open_db(&DiffrConfig::db_path()?)?, &drive_list)?
+    };
+
+    if json {
+        let external: Vec<String> = external.iter().map(|d| d.display().to_string()).collect();
+        println!(
+            "{{\"path\": \"{}\", \"removed\": true, \"archived_files_removed\": {}, \"archive_records_removed\": {}, \"external_removed\": {}, \"registered_drives\": {}}}",
+            root.display(),
+            archived_files + external_archived,
+            forgotten,
+            serde_json::to_string(&external)?,
+            drives.len()
+        );
+    } else {
+        println!("Removed {}", diffr_dir.display());
+        for dir in &external {
+            println!("Removed {}", dir.display());
+        }
+        if forgotten > 0 {
+            println!("  Removed {} archive records whose files were there.", forgotten);
+        }
+        println!("  .diffrignore was left in place.");
+        for (drive, _) in &drives {
+            println!(
+                "  Note: drive '{}' still uses this path; remove it with `diffr drive remove {}`",
+                drive.identity.identity_string(),
+                drive.identity.identity_string()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Metadata directories kept outside `root` for `drives`, with `diffr drive
+/// set <drive> metadata external`, that are there to remove.
+fn external_metadata_dirs(root: &Path, drives: &[&Drive]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = drives
+        .iter()
+        .filter_map(|d| d.metadata_dir.clone())
+        .filter(|dir| !dir.starts_with(root) && dir.is_dir())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Drop the catalog's records of archives kept in the metadata of
+/// `drives`, which deinit has just removed: those stored there, and split
+/// ones with a part there. Returns how many were dropped.
+fn forget_archives(conn: &rusqlite::Connection, drives: &[&Drive]) -> anyhow::Result<usize> {
+    let mut ids = HashSet::new();
+    for drive in drives {
+        ids.extend(ops::list_archives_for_drive(conn, &drive.id)?.into_iter().map(|a| a.id));
+        ids.extend(ops::list_archive_parts_for_drive(conn, &drive.id)?.into_iter().map(|p| p.archive_id));
+    }
+    let tx = conn.unchecked_transaction()?;
+    for id in &ids {
+        ops::delete_archive(&tx, id)?;
+    }
+    tx.commit()?;
+    Ok(ids.len())
+}
+
+fn count_files(dir: &Path) -> usize {
+    if !dir.exists() {
+        return 0;
+    }
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_external_metadata_dirs() {
+        let (repo, home) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let mut inside = Drive::new(DriveIdentity::new_synthetic(), repo.path().to_path_buf());
+        inside.metadata_dir = Some(repo.path().join(".diffr"));
+        let mut outside = Drive::new(DriveIdentity::new_synthetic(), repo.path().to_path_buf());
+        outside.metadata_dir = Some(home.path().join("drives").join(outside.id.to_string()));
+        std::fs::create_dir_all(repo.path().join(".diffr")).unwrap();
+        assert!(external_metadata_dirs(repo.path(), &[&inside, &outside]).is_empty());
+
+        std::fs::create_dir_all(outside.metadata_dir.as_ref().unwrap().join("archive")).unwrap();
+        std::fs::write(outside.metadata_dir.as_ref().unwrap().join("archive/a.txt"), "old").unwrap();
+        let found = external_metadata_dirs(repo.path(), &[&inside, &outside]);
+        assert_eq!(found, vec![outside.metadata_dir.clone().unwrap()]);
+        assert_eq!(count_files(&found[0].join("archive")), 1);
+    }
+
+    #[test]
+    fn test_forget_archives_kept_on_the_drive() {
+        use diffr_core::models::archive::{ArchiveEntry, ArchiveReason, CompressionFormat};
+
+        let conn = diffr_db::open_memory_db().unwrap();
+        let (repo, other) = (
+            Drive::new(DriveIdentity::new_synthetic(), "/mnt/repo".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/mnt/other".into()),
+        );
+        ops::insert_drive(&conn, &repo).unwrap();
+        ops::insert_drive(&conn, &other).unwrap();
+        let archive = |drive: &Drive| ArchiveEntry {
+            id: uuid::Uuid::now_v7(),
+            original_path: "a.txt".into(),
+            archive_path: ".diffr/archive/a.txt.zst".into(),
+            drive_id: drive.id.clone(),
+            original_size: 3,
+            compressed_size: 3,
+            compression: CompressionFormat::Zstd,
+            xxh3_hash: "00".into(),
+            reason: ArchiveReason::BeforeOverwrite,
+            archived_at: chrono::Utc::now(),
+            relocated_from: None,
+            relocated_at: None,
+        };
+        let (here, elsewhere) = (archive(&repo), archive(&other));
+        ops::insert_archive(&conn, &here).unwrap();
+        ops::insert_archive(&conn, &elsewhere).unwrap();
+
+        assert_eq!(forget_archives(&conn, &[&repo]).unwrap(), 1);
+        assert!(ops::get_archive_by_id(&conn, &here.id).unwrap().is_none());
+        assert!(ops::get_archive_by_id(&conn, &elsewhere.id).unwrap().is_some());
+    }
+}
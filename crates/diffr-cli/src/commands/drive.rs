@@ -1,12 +1,21 @@
 use clap::Subcommand;
 use diffr_core::config::DiffrConfig;
-use diffr_core::models::drive::{Drive, DriveIdentity, DriveRole};
-use diffr_db::ops;
+use diffr_core::models::drive::{BusType, Drive, DriveId, DriveIdentity, DriveRole, MTP_URL_PREFIX};
+use diffr_core::units::{format_bytes, parse_bytes};
+use diffr_db::ops::{self, IndexChangeKind};
+use serde_json::json;
 
 #[derive(Subcommand)]
 pub enum DriveAction {
     /// Scan for connected drives
-    Scan,
+    Scan {
+        /// Only list USB, Thunderbolt and card-reader drives
+        #[arg(long, conflicts_with = "include_internal")]
+        removable_only: bool,
+        /// Also list internal drives such as the system disk
+        #[arg(long)]
+        include_internal: bool,
+    },
     /// Add a drive to a cluster
     Add {
         /// Drive serial number or synthetic ID
@@ -23,12 +32,63 @@ pub enum DriveAction {
         /// Path to a diffr repo (must have been initialized with `diffr init`)
         #[arg(long)]
         path: Option<std::path::PathBuf>,
+        /// Use the drive as a sync source only; nothing is ever written to it
+        #[arg(long)]
+        read_only: bool,
+    },
+    /// Change a drive setting: read_only, role, primary, label, quota (a
+    /// size such as 500GB), or metadata (`drive`, `external`, or a directory)
+    Set {
+        /// Drive serial number or synthetic ID
+        identity: String,
+        /// Setting name
+        key: String,
+        /// New value (`none` clears the label or quota)
+        value: String,
     },
     /// Remove a drive from its cluster
     Remove {
         /// Drive serial number or synthetic ID
         identity: String,
     },
+    /// Show what would be lost if a drive were retired: files and archives
+    /// with no copy elsewhere, and clusters left unable to sync
+    SimulateRemove {
+        /// Drive serial number or synthetic ID
+        identity: String,
+    },
+    /// Show what changed in a drive's catalog since an earlier sync, and the
+    /// catalogs remembered for it
+    Changes {
+        /// Drive serial number or synthetic ID
+        identity: String,
+        /// Catalog generation to compare against (default: the one before
+        /// the latest)
+        #[arg(long)]
+        since: Option<u64>,
+    },
+    /// Tag a drive, so commands can name every drive with the tag as
+    /// `tag:<name>`
+    Tag {
+        /// Drive serial number or synthetic ID
+        identity: String,
+        /// Tags to add, such as offsite or travel
+        #[arg(required = true, value_parser = parse_tag)]
+        tags: Vec<String>,
+    },
+    /// Take tags off a drive
+    Untag {
+        /// Drive serial number or synthetic ID
+        identity: String,
+        /// Tags to remove
+        #[arg(required = true, value_parser = parse_tag)]
+        tags: Vec<String>,
+    },
+    /// List every tag in use and the drives that have it
+    Tags,
+    /// Update registered drives from the ones connected now, and list
+    /// connected drives that aren't registered
+    Refresh,
     /// List all known drives
     List,
     /// Show detailed drive info
@@ -38,51 +98,219 @@ pub enum DriveAction {
     },
 }
 
+/// Look up a registered drive by hardware serial or synthetic ID.
+pub fn lookup_drive(conn: &rusqlite::Connection, identity: &str) -> anyhow::Result<Drive> {
+    let candidates = [
+        DriveIdentity::Hardware {
+            serial: identity.to_string(),
+        },
+        DriveIdentity::Synthetic {
+            id: identity.to_string(),
+        },
+    ];
+    for candidate in &candidates {
+        if let Some(drive) = ops::get_drive_by_identity(conn, candidate)? {
+            return Ok(drive);
+        }
+    }
+    anyhow::bail!("drive '{}' not found", identity)
+}
+
+/// Check a tag name: letters, digits, `-`, `_` and `.` only, so it can't
+/// be confused with a list of drives or a `tag:` prefix.
+pub fn parse_tag(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("a tag can't be empty".to_string());
+    }
+    if let Some(c) = s.chars().find(|c| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))) {
+        return Err(format!("'{}' can't be in a tag", c));
+    }
+    Ok(s.to_string())
+}
+
+/// The drives named by `specs`, each a drive identity or `tag:<name>` for
+/// every drive with that tag, in order and without repeats.
+pub fn resolve_drives(conn: &rusqlite::Connection, specs: &[String]) -> anyhow::Result<Vec<Drive>> {
+    let mut drives: Vec<Drive> = Vec::new();
+    for spec in specs {
+        let found = match spec.strip_prefix("tag:") {
+            Some(tag) => {
+                let tagged = ops::list_drives_with_tag(conn, tag)?;
+                if tagged.is_empty() {
+                    anyhow::bail!("no drives are tagged '{}'", tag);
+                }
+                tagged
+            }
+            None => vec![lookup_drive(conn, spec)?],
+        };
+        for drive in found {
+            if !drives.iter().any(|d| d.id == drive.id) {
+                drives.push(drive);
+            }
+        }
+    }
+    Ok(drives)
+}
+
+/// Refuse a membership change that would break a cluster rule (two
+/// primaries, overlapping sync roots). `changed` is the drive as it would be
+/// saved. Rules a half-built cluster can't meet yet are left for sync.
+fn check_membership(conn: &rusqlite::Connection, changed: &Drive) -> anyhow::Result<()> {
+    let Some(cluster_id) = &changed.cluster_id else {
+        return Ok(());
+    };
+    let Some(cluster) = ops::get_cluster_by_id(conn, cluster_id)? else {
+        return Ok(());
+    };
+    let mut all = ops::list_all_drives(conn)?;
+    all.retain(|d| d.id != changed.id && d.identity != changed.identity);
+    all.push(changed.clone());
+    let members: Vec<Drive> = all
+        .iter()
+        .filter(|d| d.cluster_id.as_ref() == Some(cluster_id))
+        .cloned()
+        .collect();
+
+    let violations: Vec<String> = diffr_sync::validate::validate_cluster(&cluster, &members, &all)
+        .iter()
+        .filter(|v| !v.is_incomplete())
+        .map(|v| format!("  - {}", v))
+        .collect();
+    if !violations.is_empty() {
+        anyhow::bail!("cluster '{}' would be invalid:\n{}", cluster.name, violations.join("\n"));
+    }
+    Ok(())
+}
+
+/// The drive discovery backend chosen in the config (`discovery_backend`).
+pub fn discovery() -> anyhow::Result<Box<dyn diffr_discovery::DriveDiscovery>> {
+    let config = DiffrConfig::load()?;
+    diffr_discovery::registry::select(config.discovery_backend.as_deref())
+}
+
+/// Reconcile `drives` with what's connected and save the ones that were
+/// seen. Discovery failures are logged rather than returned, so a broken
+/// lsblk never blocks a sync.
+pub fn refresh_registered(
+    conn: &rusqlite::Connection,
+    drives: &mut [Drive],
+) -> anyhow::Result<diffr_discovery::RefreshResult> {
+    let discovered = match discovery()?.discover_drives() {
+        Ok(discovered) => discovered,
+        Err(e) => {
+            tracing::warn!("drive discovery failed: {}", e);
+            return Ok(Default::default());
+        }
+    };
+    save_refresh(conn, drives, discovered)
+}
+
+/// Reconcile `drives` with the `discovered` ones and save those seen.
+fn save_refresh(
+    conn: &rusqlite::Connection,
+    drives: &mut [Drive],
+    discovered: Vec<Drive>,
+) -> anyhow::Result<diffr_discovery::RefreshResult> {
+    let result = diffr_discovery::refresh_drives(drives, discovered);
+    for &idx in &result.seen {
+        ops::update_drive(conn, &drives[idx])?;
+    }
+    for &idx in &result.reidentified {
+        ops::update_drive_identity(conn, &drives[idx])?;
+    }
+    Ok(result)
+}
+
+/// A registered drive by identity, or a throwaway drive for a plain directory.
+pub fn resolve_drive_or_dir(arg: &str) -> anyhow::Result<Drive> {
+    let db_path = DiffrConfig::db_path()?;
+    if db_path.exists() {
+        let conn = diffr_db::open_db(&db_path)?;
+        if let Ok(drive) = lookup_drive(&conn, arg) {
+            return Ok(drive);
+        }
+    }
+    let path = std::path::Path::new(arg);
+    if !path.is_absolute() && !path.exists() {
+        anyhow::bail!("no registered drive or directory named '{}'", arg);
+    }
+    let root = if path.exists() {
+        super::init::simplified_canonicalize(path)?
+    } else {
+        path.to_path_buf()
+    };
+    Ok(Drive::new(DriveIdentity::new_synthetic(), root))
+}
+
 pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
     match action {
-        DriveAction::Scan => {
-            let discovery = diffr_discovery::platform::get_discovery();
-            let drives = discovery.discover_drives()?;
+        DriveAction::Scan {
+            removable_only,
+            include_internal,
+        } => {
+            let discovery = discovery()?;
+            let mut drives = discovery.discover_drives()?;
+            let found = drives.len();
+            if removable_only {
+                drives.retain(|d| d.bus.is_removable());
+            } else if !include_internal {
+                drives.retain(|d| d.bus != BusType::Internal);
+            }
+            let hidden = found - drives.len();
 
             if json {
                 let items: Vec<_> = drives
                     .iter()
                     .map(|d| {
-                        format!(
-                            "{{\"identity\": \"{}\", \"mount\": \"{}\", \"label\": {}}}",
-                            d.identity.identity_string(),
-                            d.mount_point.display(),
-                            d.label
-                                .as_ref()
-                                .map(|l| format!("\"{}\"", l))
-                                .unwrap_or_else(|| "null".to_string())
-                        )
+                        json!({
+                            "identity": d.identity.identity_string(),
+                            "mount": d.mount_point.display().to_string(),
+                            "label": d.label,
+                            "filesystem": d.filesystem,
+                            "locked": d.locked,
+                            "bus": d.bus.to_string(),
+                        })
                     })
                     .collect();
-                println!("[{}]", items.join(", "));
+                println!("{}", json!(items));
             } else {
                 if drives.is_empty() {
                     println!("No drives detected.");
                 } else {
                     println!(
-                        "{:<30} {:<20} {:<15} {:>12} {:>12}",
-                        "IDENTITY", "MOUNT", "LABEL", "TOTAL", "FREE"
+                        "{:<30} {:<20} {:<15} {:<8} {:<12} {:>12} {:>12}",
+                        "IDENTITY", "MOUNT", "LABEL", "FS", "BUS", "TOTAL", "FREE"
                     );
                     for d in &drives {
+                        let fs = if d.locked {
+                            "locked".to_string()
+                        } else {
+                            d.filesystem.clone().unwrap_or_else(|| "-".to_string())
+                        };
                         println!(
-                            "{:<30} {:<20} {:<15} {:>12} {:>12}",
+                            "{:<30} {:<20} {:<15} {:<8} {:<12} {:>12} {:>12}",
                             d.identity.identity_string(),
                             d.mount_point.display(),
                             d.label.as_deref().unwrap_or("-"),
+                            fs,
+                            d.bus.to_string(),
                             d.total_bytes
-                                .map(|b| format_bytes(b))
+                                .map(format_bytes)
                                 .unwrap_or_else(|| "-".to_string()),
                             d.free_bytes
-                                .map(|b| format_bytes(b))
+                                .map(format_bytes)
                                 .unwrap_or_else(|| "-".to_string()),
                         );
                     }
                 }
+                if hidden > 0 {
+                    let (what, hint) = if removable_only {
+                        ("non-removable", "drop --removable-only")
+                    } else {
+                        ("internal", "use --include-internal")
+                    };
+                    println!("\n{} {} drives hidden ({} to list them)", hidden, what, hint);
+                }
             }
             Ok(())
         }
@@ -92,6 +320,7 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
             role,
             primary,
             path,
+            read_only,
         } => {
             let db_path = DiffrConfig::db_path()?;
             let conn = diffr_db::open_db(&db_path)?;
@@ -101,11 +330,16 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
 
             let role: DriveRole = role.parse().map_err(|e: String| anyhow::anyhow!(e))?;
 
-            // Validate and canonicalize sync root path if provided
-            let sync_root = if let Some(ref p) = path {
+            // Validate and canonicalize sync root path if provided. A
+            // folder on a device reached through libmtp, such as
+            // `mtp://<serial>/Internal shared storage/DCIM`, has no path
+            // here to check.
+            let sync_root = if let Some(p) = path.as_ref().filter(|p| p.to_str().is_some_and(|p| p.starts_with(MTP_URL_PREFIX))) {
+                Some(p.clone())
+            } else if let Some(ref p) = path {
                 let canon = crate::commands::init::simplified_canonicalize(p)
                     .map_err(|_| anyhow::anyhow!("path does not exist: {}", p.display()))?;
-                let repo_toml = canon.join(".diffr").join("repo.toml");
+                let repo_toml = diffr_core::config::RepoConfig::path_in(&canon);
                 if !repo_toml.exists() {
                     anyhow::bail!(
                         "diffr repo not initialized at {} (run `diffr init {}`)",
@@ -119,7 +353,7 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
             };
 
             // Try to find the drive by discovery first
-            let discovery = diffr_discovery::platform::get_discovery();
+            let discovery = discovery()?;
             let discovered = discovery.find_by_serial(&identity)?;
 
             let mut drive = match discovered {
@@ -139,6 +373,24 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
             drive.role = role;
             drive.is_primary = primary;
             drive.sync_root = sync_root;
+            drive.read_only = read_only;
+
+            check_membership(&conn, &drive)?;
+
+            // Discovery doesn't write to drives, so a drive without a
+            // serial gets its identity stored only now it's registered.
+            let unmarked = matches!(drive.identity, DriveIdentity::Synthetic { .. })
+                && drive.mtp_device().is_none()
+                && diffr_discovery::read_synthetic_id(&drive.mount_point).is_none();
+            if unmarked {
+                if let Err(e) = diffr_discovery::store_synthetic_id(&drive.mount_point, &drive.identity) {
+                    tracing::warn!(
+                        "can't store the drive's identity on {}, so it may not be recognised at another mount point: {}",
+                        drive.mount_point.display(),
+                        e
+                    );
+                }
+            }
 
             // Check if already registered
             if ops::get_drive_by_identity(&conn, &drive.identity)?.is_some() {
@@ -147,14 +399,76 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
                 ops::update_drive_cluster(&conn, &existing.id, Some(&cluster_obj.id))?;
                 println!(
                     "Updated drive '{}' -> cluster '{}'",
-                    identity, cluster
+                    drive.identity.identity_string(),
+                    cluster
                 );
             } else {
                 ops::insert_drive(&conn, &drive)?;
                 println!(
                     "Added drive '{}' to cluster '{}'",
-                    identity, cluster
+                    drive.identity.identity_string(),
+                    cluster
+                );
+            }
+            Ok(())
+        }
+        DriveAction::Set {
+            identity,
+            key,
+            value,
+        } => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let mut drive = lookup_drive(&conn, &identity)?;
+
+            match key.as_str() {
+                "read_only" | "read-only" => drive.read_only = parse_bool(&value)?,
+                "primary" => drive.is_primary = parse_bool(&value)?,
+                "role" => drive.role = value.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+                "label" => drive.label = (value != "none").then(|| value.clone()),
+                "quota" | "quota_bytes" => {
+                    drive.quota_bytes = match value.as_str() {
+                        "none" => None,
+                        v => Some(parse_bytes(v).map_err(|e| anyhow::anyhow!("invalid quota: {}", e))?),
+                    }
+                }
+                "metadata" | "metadata_dir" => {
+                    drive.metadata_dir = match value.as_str() {
+                        "drive" | "none" => None,
+                        "external" => Some(DiffrConfig::external_metadata_dir(&drive.id)?),
+                        dir => {
+                            let dir = std::path::PathBuf::from(dir);
+                            if !dir.is_absolute() {
+                                anyhow::bail!("metadata directory must be an absolute path: {}", dir.display());
+                            }
+                            // The scan only skips directories named `.diffr`.
+                            if dir.starts_with(drive.effective_root()) {
+                                anyhow::bail!(
+                                    "{} is inside the drive's sync root and would be synced; use `drive` to keep it there as .diffr",
+                                    dir.display()
+                                );
+                            }
+                            Some(dir)
+                        }
+                    };
+                    if let Some(dir) = &drive.metadata_dir {
+                        std::fs::create_dir_all(dir).map_err(|e| {
+                            anyhow::anyhow!("can't create metadata directory {}: {}", dir.display(), e)
+                        })?;
+                    }
+                }
+                _ => anyhow::bail!("unknown drive setting: {}", key),
+            }
+            check_membership(&conn, &drive)?;
+            ops::update_drive(&conn, &drive)?;
+
+            if json {
+                println!(
+                    "{{\"identity\": \"{}\", \"key\": \"{}\", \"value\": \"{}\"}}",
+                    identity, key, value
                 );
+            } else {
+                println!("Set {} = {} on drive '{}'", key, value, identity);
             }
             Ok(())
         }
@@ -172,28 +486,160 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
             println!("Removed drive '{}'", identity);
             Ok(())
         }
-        DriveAction::List => {
+        DriveAction::SimulateRemove { identity } => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let drive = lookup_drive(&conn, &identity)?;
+            simulate_remove(&conn, &drive, json)
+        }
+        DriveAction::Changes { identity, since } => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let drive = lookup_drive(&conn, &identity)?;
+            show_changes(&conn, &drive, since, json)
+        }
+        DriveAction::Tag { identity, tags } => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let drive = lookup_drive(&conn, &identity)?;
+            for tag in &tags {
+                if ops::add_drive_tag(&conn, &drive.id, tag)? {
+                    println!("Tagged drive '{}' {}", identity, tag);
+                } else {
+                    println!("Drive '{}' is already tagged {}", identity, tag);
+                }
+            }
+            Ok(())
+        }
+        DriveAction::Untag { identity, tags } => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let drive = lookup_drive(&conn, &identity)?;
+            for tag in &tags {
+                if ops::remove_drive_tag(&conn, &drive.id, tag)? {
+                    println!("Removed tag {} from drive '{}'", tag, identity);
+                } else {
+                    println!("Drive '{}' isn't tagged {}", identity, tag);
+                }
+            }
+            Ok(())
+        }
+        DriveAction::Tags => {
             let db_path = DiffrConfig::db_path()?;
             let conn = diffr_db::open_db(&db_path)?;
             let drives = ops::list_all_drives(&conn)?;
+            let identity = |id: &DriveId| {
+                drives
+                    .iter()
+                    .find(|d| &d.id == id)
+                    .map(|d| d.identity.identity_string().to_string())
+                    .unwrap_or_else(|| id.to_string())
+            };
+            let tags = ops::list_tags(&conn)?;
 
             if json {
-                let items: Vec<_> = drives
+                let items: Vec<_> = tags
                     .iter()
-                    .map(|d| {
+                    .map(|(tag, ids)| {
+                        let names: Vec<String> = ids.iter().map(|id| format!("\"{}\"", identity(id))).collect();
+                        format!("{{\"tag\": \"{}\", \"drives\": [{}]}}", tag, names.join(", "))
+                    })
+                    .collect();
+                println!("[{}]", items.join(", "));
+            } else if tags.is_empty() {
+                println!("No drives are tagged.");
+            } else {
+                for (tag, ids) in &tags {
+                    let names: Vec<String> = ids.iter().map(identity).collect();
+                    println!("{:<20} {}", tag, names.join(", "));
+                }
+            }
+            Ok(())
+        }
+        DriveAction::Refresh => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let mut drives = ops::list_all_drives(&conn)?;
+            let result = refresh_registered(&conn, &mut drives)?;
+
+            if json {
+                let seen: Vec<_> = result
+                    .seen
+                    .iter()
+                    .map(|&i| {
+                        let d = &drives[i];
                         format!(
-                            "{{\"identity\": \"{}\", \"mount\": \"{}\", \"cluster\": {}, \"role\": \"{}\"}}",
+                            "{{\"identity\": \"{}\", \"mount\": \"{}\", \"moved\": {}}}",
                             d.identity.identity_string(),
                             d.mount_point.display(),
-                            d.cluster_id
-                                .as_ref()
-                                .map(|c| format!("\"{}\"", c))
-                                .unwrap_or_else(|| "null".to_string()),
-                            d.role
+                            result.moved.contains(&i)
                         )
                     })
                     .collect();
-                println!("[{}]", items.join(", "));
+                let new: Vec<_> = result
+                    .unregistered
+                    .iter()
+                    .map(|d| {
+                        format!(
+                            "{{\"identity\": \"{}\", \"mount\": \"{}\"}}",
+                            d.identity.identity_string(),
+                            d.mount_point.display()
+                        )
+                    })
+                    .collect();
+                println!(
+                    "{{\"refreshed\": [{}], \"unregistered\": [{}]}}",
+                    seen.join(", "),
+                    new.join(", ")
+                );
+            } else {
+                println!(
+                    "Refreshed {} of {} registered drives",
+                    result.seen.len(),
+                    drives.len()
+                );
+                for &i in &result.seen {
+                    let d = &drives[i];
+                    println!(
+                        "  {} {}{}",
+                        d.identity.identity_string(),
+                        d.mount_point.display(),
+                        if result.moved.contains(&i) { " (moved)" } else { "" }
+                    );
+                }
+                if !result.unregistered.is_empty() {
+                    println!("\nConnected but not registered:");
+                    for d in &result.unregistered {
+                        println!(
+                            "  {} {} ({})",
+                            d.identity.identity_string(),
+                            d.mount_point.display(),
+                            d.bus
+                        );
+                    }
+                }
+            }
+            Ok(())
+        }
+        DriveAction::List => {
+            let db_path = DiffrConfig::db_path()?;
+            let conn = diffr_db::open_db(&db_path)?;
+            let drives = ops::list_all_drives(&conn)?;
+
+            if json {
+                let items: Vec<_> = drives
+                    .iter()
+                    .map(|d| {
+                        json!({
+                            "identity": d.identity.identity_string(),
+                            "mount": d.mount_point.display().to_string(),
+                            "cluster": d.cluster_id.as_ref().map(|c| c.to_string()),
+                            "role": d.role.to_string(),
+                            "read_only": d.read_only,
+                        })
+                    })
+                    .collect();
+                println!("{}", json!(items));
             } else {
                 if drives.is_empty() {
                     println!("No drives registered.");
@@ -212,7 +658,11 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
                             d.identity.identity_string(),
                             d.mount_point.display(),
                             sync_root_display,
-                            d.role,
+                            if d.read_only {
+                                format!("{} (ro)", d.role)
+                            } else {
+                                d.role.to_string()
+                            },
                             if d.is_primary { "yes" } else { "no" }
                         );
                     }
@@ -230,10 +680,13 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
             let drive = ops::get_drive_by_identity(&conn, &drive_identity)?
                 .ok_or_else(|| anyhow::anyhow!("drive '{}' not found", identity))?;
 
+            let tags = ops::list_drive_tags(&conn, &drive.id)?;
+
             if json {
+                let tag_items: Vec<String> = tags.iter().map(|t| format!("\"{}\"", t)).collect();
                 println!(
-                    "{{\"id\": \"{}\", \"identity\": \"{}\", \"mount\": \"{}\", \"role\": \"{}\", \"primary\": {}}}",
-                    drive.id, drive.identity.identity_string(), drive.mount_point.display(), drive.role, drive.is_primary
+                    "{{\"id\": \"{}\", \"identity\": \"{}\", \"mount\": \"{}\", \"role\": \"{}\", \"primary\": {}, \"read_only\": {}, \"tags\": [{}]}}",
+                    drive.id, drive.identity.identity_string(), drive.mount_point.display(), drive.role, drive.is_primary, drive.read_only, tag_items.join(", ")
                 );
             } else {
                 println!("Drive: {}", drive.identity.identity_string());
@@ -245,6 +698,26 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
                 println!("  Label:     {}", drive.label.as_deref().unwrap_or("-"));
                 println!("  Role:      {}", drive.role);
                 println!("  Primary:   {}", drive.is_primary);
+                println!("  Read-only: {}", drive.read_only);
+                if !tags.is_empty() {
+                    println!("  Tags:      {}", tags.join(", "));
+                }
+                if let Some(ref fs) = drive.filesystem {
+                    println!("  Format:    {}", fs);
+                }
+                if let Some(ref guid) = drive.volume_guid {
+                    println!("  Volume:    {}", guid);
+                }
+                if let Some(ref dir) = drive.metadata_dir {
+                    println!("  Metadata:  {}", dir.display());
+                }
+                if let Some(quota) = drive.quota_bytes {
+                    println!(
+                        "  Quota:     {} of {} used",
+                        drive.used_bytes.map(format_bytes).unwrap_or_else(|| "?".to_string()),
+                        format_bytes(quota)
+                    );
+                }
                 println!(
                     "  Cluster:   {}",
                     drive
@@ -260,21 +733,249 @@ pub fn run(action: DriveAction, json: bool) -> anyhow::Result<()> {
     }
 }
 
-fn format_bytes(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    const TB: u64 = GB * 1024;
-
-    if bytes >= TB {
-        format!("{:.1} TB", bytes as f64 / TB as f64)
-    } else if bytes >= GB {
-        format!("{:.1} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.1} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.1} KB", bytes as f64 / KB as f64)
+/// How many files the text reports list before summarizing.
+const SIMULATE_LIST_LIMIT: usize = 20;
+
+/// Report what retiring `drive` would cost, from the catalog saved by the
+/// last sync of each drive. Nothing is changed.
+fn simulate_remove(conn: &rusqlite::Connection, drive: &Drive, json: bool) -> anyhow::Result<()> {
+    let all = ops::list_all_drives(conn)?;
+    let index = ops::get_file_entries_for_drive(conn, &drive.id)?;
+    let mut siblings = Vec::new();
+    let mut others = Vec::new();
+    for other in all.iter().filter(|d| d.id != drive.id) {
+        let entries = ops::get_file_entries_for_drive(conn, &other.id)?;
+        if other.cluster_id.is_some() && other.cluster_id == drive.cluster_id {
+            siblings.extend(entries);
+        } else {
+            others.extend(entries);
+        }
+    }
+    let lost = diffr_sync::removal::last_copies(&index, &siblings, &others);
+    let lost_bytes: u64 = lost.iter().map(|e| e.size).sum();
+
+    let archives = ops::list_archives_for_drive(conn, &drive.id)?;
+    let archive_bytes: u64 = archives.iter().map(|a| a.compressed_size).sum();
+
+    let mut cluster_name = None;
+    let mut violations = Vec::new();
+    let cluster = match &drive.cluster_id {
+        Some(id) => ops::get_cluster_by_id(conn, id)?,
+        None => None,
+    };
+    if let Some(cluster) = cluster {
+        let cluster_id = &cluster.id;
+        let remaining: Vec<Drive> = all.iter().filter(|d| d.id != drive.id).cloned().collect();
+        let members: Vec<Drive> = remaining
+            .iter()
+            .filter(|d| d.cluster_id.as_ref() == Some(cluster_id))
+            .cloned()
+            .collect();
+        violations = diffr_sync::validate::validate_cluster(&cluster, &members, &remaining)
+            .into_iter()
+            .filter(|v| v.is_incomplete())
+            .map(|v| v.to_string())
+            .collect();
+        cluster_name = Some(cluster.name);
+    }
+
+    let identity = drive.identity.identity_string();
+    if json {
+        let files: Vec<String> = lost
+            .iter()
+            .map(|e| format!("{{\"path\": \"{}\", \"size\": {}}}", e.rel_path.display(), e.size))
+            .collect();
+        let problems: Vec<String> = violations.iter().map(|v| format!("\"{}\"", v)).collect();
+        println!(
+            "{{\"identity\": \"{}\", \"cluster\": {}, \"indexed_files\": {}, \"last_copy_files\": [{}], \"last_copy_bytes\": {}, \"archives\": {}, \"archive_bytes\": {}, \"cluster_problems\": [{}]}}",
+            identity,
+            cluster_name
+                .as_ref()
+                .map(|n| format!("\"{}\"", n))
+                .unwrap_or_else(|| "null".to_string()),
+            index.iter().filter(|e| !e.is_dir).count(),
+            files.join(", "),
+            lost_bytes,
+            archives.len(),
+            archive_bytes,
+            problems.join(", ")
+        );
+        return Ok(());
+    }
+
+    println!("Removing drive '{}' would:", identity);
+    if index.is_empty() {
+        println!("  (no catalog for this drive yet; run a sync with it connected to index it)");
+    } else if lost.is_empty() {
+        println!("  lose no files: every file has a copy on another drive");
     } else {
-        format!("{} B", bytes)
+        println!(
+            "  lose the only copy of {} file(s), {}",
+            lost.len(),
+            format_bytes(lost_bytes)
+        );
+        for entry in lost.iter().take(SIMULATE_LIST_LIMIT) {
+            println!("    {} ({})", entry.rel_path.display(), format_bytes(entry.size));
+        }
+        if lost.len() > SIMULATE_LIST_LIMIT {
+            println!("    ... and {} more", lost.len() - SIMULATE_LIST_LIMIT);
+        }
+    }
+    if !archives.is_empty() {
+        println!(
+            "  lose {} archived version(s) stored on it, {}",
+            archives.len(),
+            format_bytes(archive_bytes)
+        );
+    }
+    match (&cluster_name, violations.is_empty()) {
+        (Some(name), false) => {
+            println!("  leave cluster '{}' unable to sync:", name);
+            for v in &violations {
+                println!("    - {}", v);
+            }
+        }
+        (Some(name), true) => println!("  leave cluster '{}' able to sync", name),
+        (None, _) => {}
+    }
+    Ok(())
+}
+
+/// Report how a drive's catalog changed since generation `since`, from the
+/// entries the catalog keeps after they're deleted.
+fn show_changes(conn: &rusqlite::Connection, drive: &Drive, since: Option<u64>, json: bool) -> anyhow::Result<()> {
+    let generations = ops::list_index_generations(conn, &drive.id)?;
+    let current = ops::current_generation(conn, &drive.id)?;
+    let since = since.unwrap_or(current.saturating_sub(1));
+    if since > current {
+        anyhow::bail!("generation {} is newer than the latest, {}", since, current);
+    }
+    // Generation 0 is the empty catalog before the first, as long as
+    // nothing has been forgotten since.
+    let remembered = generations.iter().any(|g| g.generation == since)
+        || (since == 0 && generations.last().is_none_or(|g| g.generation == 1));
+    if !remembered {
+        let oldest = generations.last().map(|g| g.generation).unwrap_or(0);
+        anyhow::bail!("generation {} is no longer remembered; the oldest is {}", since, oldest);
+    }
+    let changes = ops::file_index_changes_since(conn, &drive.id, since)?;
+    let count = |kind| changes.iter().filter(|c| c.kind == kind).count();
+    let identity = drive.identity.identity_string();
+
+    if json {
+        let items: Vec<String> = changes
+            .iter()
+            .map(|c| {
+                format!(
+                    "{{\"path\": \"{}\", \"change\": \"{}\", \"is_dir\": {}, \"size\": {}}}",
+                    c.entry.rel_path.display(),
+                    c.kind,
+                    c.entry.is_dir,
+                    c.entry.size
+                )
+            })
+            .collect();
+        let history: Vec<String> = generations
+            .iter()
+            .map(|g| {
+                format!(
+                    "{{\"generation\": {}, \"indexed_at\": \"{}\", \"entries\": {}}}",
+                    g.generation,
+                    g.indexed_at.to_rfc3339(),
+                    g.entries
+                )
+            })
+            .collect();
+        println!(
+            "{{\"identity\": \"{}\", \"generation\": {}, \"since\": {}, \"changes\": [{}], \"generations\": [{}]}}",
+            identity,
+            current,
+            since,
+            items.join(", "),
+            history.join(", ")
+        );
+        return Ok(());
+    }
+
+    if generations.is_empty() {
+        println!("Drive '{}' has no catalog yet; run a sync with it connected to index it.", identity);
+        return Ok(());
+    }
+    println!("Catalog of '{}', generation {}:", identity, current);
+    if changes.is_empty() {
+        println!("  no changes since generation {}", since);
+    } else {
+        println!(
+            "  since generation {}: {} added, {} modified, {} deleted",
+            since,
+            count(IndexChangeKind::Added),
+            count(IndexChangeKind::Modified),
+            count(IndexChangeKind::Deleted)
+        );
+        for change in changes.iter().take(SIMULATE_LIST_LIMIT) {
+            let mark = match change.kind {
+                IndexChangeKind::Added => '+',
+                IndexChangeKind::Modified => '~',
+                IndexChangeKind::Deleted => '-',
+            };
+            println!("    {} {}", mark, change.entry.rel_path.display());
+        }
+        if changes.len() > SIMULATE_LIST_LIMIT {
+            println!("    ... and {} more", changes.len() - SIMULATE_LIST_LIMIT);
+        }
+    }
+    println!("  Generations:");
+    for g in &generations {
+        println!(
+            "    {:>4}  {}  {} entries",
+            g.generation,
+            g.indexed_at.format("%Y-%m-%d %H:%M"),
+            g.entries
+        );
+    }
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => anyhow::bail!("expected true or false, got '{}'", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_refresh_saves_what_discovery_saw() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let mut seen = Drive::new(DriveIdentity::new_hardware("WD-1".into()), PathBuf::from("/mnt/a"));
+        seen.last_seen = chrono::Utc::now() - chrono::Duration::days(3);
+        let away = Drive::new(DriveIdentity::new_hardware("WD-2".into()), PathBuf::from("/mnt/b"));
+        ops::insert_drive(&conn, &seen).unwrap();
+        ops::insert_drive(&conn, &away).unwrap();
+
+        let mut found = Drive::new(DriveIdentity::new_hardware("WD-1".into()), PathBuf::from("/media/a"));
+        found.label = Some("Photos".into());
+        found.total_bytes = Some(1000);
+        found.free_bytes = Some(400);
+        let new = Drive::new(DriveIdentity::new_hardware("WD-3".into()), PathBuf::from("/mnt/c"));
+
+        let mut drives = vec![seen.clone(), away.clone()];
+        let result = save_refresh(&conn, &mut drives, vec![found.clone(), new]).unwrap();
+        assert_eq!((result.seen, result.moved), (vec![0], vec![0]));
+        assert_eq!(result.unregistered.len(), 1);
+        assert_eq!(result.unregistered[0].identity.identity_string(), "WD-3");
+
+        let saved = ops::get_drive_by_identity(&conn, &seen.identity).unwrap().unwrap();
+        assert_eq!(saved.mount_point, PathBuf::from("/media/a"));
+        assert_eq!(saved.label.as_deref(), Some("Photos"));
+        assert_eq!((saved.total_bytes, saved.free_bytes), (Some(1000), Some(400)));
+        assert_eq!(saved.last_seen.timestamp(), found.last_seen.timestamp());
+        let untouched = ops::get_drive_by_identity(&conn, &away.identity).unwrap().unwrap();
+        assert_eq!(untouched.last_seen.timestamp(), away.last_seen.timestamp());
     }
 }
@@ -0,0 +1,129 @@
+use clap::Args;
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+use diffr_sync::usage::{breakdown, UsageRow};
+use std::collections::BTreeMap;
+
+#[derive(Args)]
+pub struct DuArgs {
+    /// Drive identity or cluster name
+    target: String,
+
+    /// Directory levels to break down
+    #[arg(long, default_value = "1")]
+    depth: usize,
+
+    /// Rows to show in each table
+    #[arg(long, default_value = "20")]
+    limit: usize,
+}
+
+/// Space used by extension and by directory, from the catalog, so it works
+/// for drives that aren't connected.
+pub fn run(args: DuArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    // A cluster holds each path once however many drives it's on, so its
+    // breakdown uses the largest copy of each path.
+    let (label, entries) = match super::drive::lookup_drive(&conn, &args.target) {
+        Ok(drive) => (
+            format!("drive '{}'", drive.identity.identity_string()),
+            ops::get_file_entries_for_drive(&conn, &drive.id)?,
+        ),
+        Err(_) => {
+            let cluster = ops::get_cluster_by_name(&conn, &args.target)?.ok_or_else(|| {
+                anyhow::anyhow!("no drive or cluster named '{}'", args.target)
+            })?;
+            let mut paths: BTreeMap<_, FileEntry> = BTreeMap::new();
+            for drive in ops::list_drives_for_cluster(&conn, &cluster.id)? {
+                for entry in ops::get_file_entries_for_drive(&conn, &drive.id)? {
+                    match paths.get(&entry.rel_path) {
+                        Some(seen) if seen.size >= entry.size => {}
+                        _ => {
+                            paths.insert(entry.rel_path.clone(), entry);
+                        }
+                    }
+                }
+            }
+            (format!("cluster '{}'", cluster.name), paths.into_values().collect())
+        }
+    };
+    let usage = breakdown(&entries, args.depth);
+
+    if json {
+        let rows = |rows: Vec<(String, u64, u64)>| {
+            rows.iter()
+                .map(|(key, files, bytes)| {
+                    format!("{{\"name\": \"{}\", \"files\": {}, \"bytes\": {}}}", key, files, bytes)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let indexed_at = entries
+            .iter()
+            .map(|e| e.indexed_at)
+            .max()
+            .map(|t| format!("\"{}\"", t))
+            .unwrap_or_else(|| "null".to_string());
+        println!(
+            "{{\"target\": \"{}\", \"indexed_at\": {}, \"files\": {}, \"bytes\": {}, \"by_directory\": [{}], \"by_extension\": [{}]}}",
+            args.target,
+            indexed_at,
+            usage.files,
+            usage.bytes,
+            rows(display_rows(&usage.by_directory, |k| k.display().to_string())),
+            rows(display_rows(&usage.by_extension, |k| k.clone()))
+        );
+        return Ok(());
+    }
+
+    if usage.files == 0 {
+        println!("No catalog for {} yet; sync it to index its files.", label);
+        return Ok(());
+    }
+    let indexed_at = entries.iter().map(|e| e.indexed_at).max().unwrap_or_default();
+    println!(
+        "{}: {} in {} files (as of {})",
+        label,
+        format_bytes(usage.bytes),
+        usage.files,
+        indexed_at.format("%Y-%m-%d %H:%M")
+    );
+    print_table(
+        "DIRECTORY",
+        display_rows(&usage.by_directory, |k| k.display().to_string()),
+        usage.bytes,
+        args.limit,
+    );
+    print_table(
+        "EXTENSION",
+        display_rows(&usage.by_extension, |k| k.clone()),
+        usage.bytes,
+        args.limit,
+    );
+    Ok(())
+}
+
+fn display_rows<K>(rows: &[UsageRow<K>], name: impl Fn(&K) -> String) -> Vec<(String, u64, u64)> {
+    rows.iter().map(|r| (name(&r.key), r.files, r.bytes)).collect()
+}
+
+fn print_table(heading: &str, rows: Vec<(String, u64, u64)>, total: u64, limit: usize) {
+    println!("\n{:>10} {:>6} {:>8}  {}", "SIZE", "", "FILES", heading);
+    for (name, files, bytes) in rows.iter().take(limit) {
+        println!(
+            "{:>10} {:>5.1}% {:>8}  {}",
+            format_bytes(*bytes),
+            *bytes as f64 * 100.0 / total.max(1) as f64,
+            files,
+            name
+        );
+    }
+    if rows.len() > limit {
+        let rest: u64 = rows[limit..].iter().map(|(_, _, b)| b).sum();
+        println!("{:>10} {:>6} {:>8}  ... {} more", format_bytes(rest), "", "", rows.len() - limit);
+    }
+}
@@ -0,0 +1,238 @@
+use clap::Args;
+use diffr_core::config::{DiffrConfig, HashAlgorithm};
+use diffr_core::models::drive::Drive;
+use diffr_db::ops;
+use diffr_scan::cache::{self, HashCache};
+use diffr_scan::hasher;
+use diffr_scan::scanner::modified_time;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct HashArgs {
+    /// File to hash, or a directory to hash every file under
+    path: PathBuf,
+
+    /// Also compute SHA-256
+    #[arg(long)]
+    sha256: bool,
+
+    /// Also compute BLAKE3
+    #[arg(long, conflicts_with = "sha256")]
+    blake3: bool,
+
+    /// Store the hashes in the hash cache of the registered drive the path
+    /// is on, replacing what's cached
+    #[arg(long)]
+    cache: bool,
+}
+
+impl HashArgs {
+    pub fn writes(&self) -> bool {
+        self.cache
+    }
+}
+
+/// One file's hashes, and what the drive's hash cache held for it.
+struct Hashed {
+    path: PathBuf,
+    size: u64,
+    xxh3: String,
+    strong: Option<String>,
+    /// The cached XXH3 hash, when the cache had one for this size and
+    /// modification time that doesn't match the file.
+    stale: Option<String>,
+}
+
+/// Hash files on the spot, as a scan or a verified copy would, for checking
+/// a file by hand or chasing a mismatch.
+pub fn run(args: HashArgs, json: bool) -> anyhow::Result<()> {
+    let root = super::init::simplified_canonicalize(&args.path)
+        .map_err(|_| anyhow::anyhow!("path does not exist: {}", args.path.display()))?;
+    let strong = if args.sha256 {
+        Some(HashAlgorithm::Sha256)
+    } else if args.blake3 {
+        Some(HashAlgorithm::Blake3)
+    } else {
+        None
+    };
+
+    let db_path = DiffrConfig::db_path()?;
+    let conn = if db_path.exists() {
+        Some(diffr_db::open_db(&db_path)?)
+    } else {
+        None
+    };
+    let drive = match &conn {
+        Some(conn) => drive_containing(&ops::list_all_drives(conn)?, &root),
+        None => None,
+    };
+    if args.cache && drive.is_none() {
+        anyhow::bail!("{} isn't on a registered drive, so there's no cache to fill", root.display());
+    }
+
+    let files: Vec<PathBuf> = if root.is_dir() {
+        walkdir::WalkDir::new(&root)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| e.file_name() != ".diffr")
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    } else {
+        vec![root.clone()]
+    };
+
+    let mut hashed = Vec::new();
+    let mut errors = Vec::new();
+    for file in &files {
+        let shown = file.strip_prefix(&root).ok().filter(|p| !p.as_os_str().is_empty());
+        let shown = shown.map(Path::to_path_buf).unwrap_or_else(|| args.path.clone());
+        match hash_one(file, strong, args.cache, conn.as_ref().zip(drive.as_ref())) {
+            Ok((size, result, cached)) => hashed.push(Hashed {
+                path: shown,
+                size,
+                stale: cached.filter(|h| *h != result.xxh3_hex),
+                strong: match strong {
+                    Some(HashAlgorithm::Sha256) => result.sha256_hex,
+                    Some(HashAlgorithm::Blake3) => result.blake3_hex,
+                    None => None,
+                },
+                xxh3: result.xxh3_hex,
+            }),
+            Err(e) => errors.push(format!("{}: {}", shown.display(), e)),
+        }
+    }
+
+    if json {
+        let rows: Vec<String> = hashed
+            .iter()
+            .map(|h| {
+                format!(
+                    "{{\"path\": \"{}\", \"size\": {}, \"xxh3\": \"{}\"{}, \"stale_cache\": {}}}",
+                    h.path.display(),
+                    h.size,
+                    h.xxh3,
+                    strong
+                        .zip(h.strong.as_ref())
+                        .map(|(a, s)| format!(", \"{}\": \"{}\"", a, s))
+                        .unwrap_or_default(),
+                    h.stale
+                        .as_ref()
+                        .map(|s| format!("\"{}\"", s))
+                        .unwrap_or_else(|| "null".into())
+                )
+            })
+            .collect();
+        let errors: Vec<String> = errors.iter().map(|e| format!("\"{}\"", e)).collect();
+        println!(
+            "{{\"files\": [{}], \"drive\": {}, \"cached\": {}, \"errors\": [{}]}}",
+            rows.join(", "),
+            drive
+                .as_ref()
+                .map(|d| format!("\"{}\"", d.identity.identity_string()))
+                .unwrap_or_else(|| "null".into()),
+            args.cache,
+            errors.join(", ")
+        );
+    } else {
+        for h in &hashed {
+            match &h.strong {
+                Some(strong) => println!("{}  {}  {}", h.xxh3, strong, h.path.display()),
+                None => println!("{}  {}", h.xxh3, h.path.display()),
+            }
+            if let Some(cached) = &h.stale {
+                let fix = if args.cache { "replaced" } else { "rehash with --cache to replace it" };
+                println!("  ! cached as {} for the same size and time; {}", cached, fix);
+            }
+        }
+        for e in &errors {
+            eprintln!("error: {}", e);
+        }
+        if args.cache {
+            if let Some(drive) = &drive {
+                println!(
+                    "Cached {} hashes for drive '{}'",
+                    hashed.len(),
+                    drive.identity.identity_string()
+                );
+            }
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{} of {} files couldn't be hashed", errors.len(), files.len());
+    }
+    Ok(())
+}
+
+/// Hash `file`, returning its size, its hashes and the XXH3 hash cached for
+/// it at the same size and modification time, if any. With `store`, the
+/// hashes replace what's cached.
+fn hash_one(
+    file: &Path,
+    strong: Option<HashAlgorithm>,
+    store: bool,
+    drive: Option<(&rusqlite::Connection, &Drive)>,
+) -> anyhow::Result<(u64, hasher::HashResult, Option<String>)> {
+    let metadata = std::fs::metadata(file)?;
+    let (size, mtime) = (metadata.len(), modified_time(&metadata));
+    let Some((conn, drive)) = drive else {
+        return Ok((size, hasher::hash_file(file, strong)?, None));
+    };
+    let root = drive.effective_root();
+    let rel_path = file.strip_prefix(root)?;
+    let (result, previous) = if store {
+        let file_id = cache::file_id(file, &metadata);
+        HashCache::new(conn, drive.id.clone()).rehash(root, rel_path, size, mtime, file_id.as_deref(), strong)?
+    } else {
+        let previous = ops::get_hash_cache_entry(conn, &drive.id, rel_path)?;
+        (hasher::hash_file(file, strong)?, previous)
+    };
+    let cached = previous.filter(|e| e.is_valid(size, mtime)).map(|e| e.xxh3_hash);
+    Ok((size, result, cached))
+}
+
+/// The registered drive whose sync root holds `path`, the innermost if
+/// several do.
+fn drive_containing(drives: &[Drive], path: &Path) -> Option<Drive> {
+    drives
+        .iter()
+        .filter(|d| path.starts_with(d.effective_root()))
+        .max_by_key(|d| d.effective_root().components().count())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hash_fills_the_cache_of_the_drive_its_on() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("inner")).unwrap();
+        let file = dir.path().join("inner/a.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let conn = diffr_db::open_memory_db().unwrap();
+        let outer = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let inner = Drive::new(DriveIdentity::new_synthetic(), dir.path().join("inner"));
+        assert!(drive_containing(std::slice::from_ref(&outer), Path::new("/elsewhere")).is_none());
+        let drive = drive_containing(&[outer, inner.clone()], &file).unwrap();
+        assert_eq!(drive.id, inner.id);
+        ops::insert_drive(&conn, &drive).unwrap();
+
+        let (size, result, cached) = hash_one(&file, Some(HashAlgorithm::Sha256), false, Some((&conn, &drive))).unwrap();
+        assert_eq!(size, 5);
+        assert_eq!(result.xxh3_hex, hasher::hash_file(&file, None).unwrap().xxh3_hex);
+        assert!(result.sha256_hex.is_some());
+        assert_eq!(cached, None);
+        assert!(ops::get_hash_cache_entry(&conn, &drive.id, Path::new("a.txt")).unwrap().is_none());
+
+        hash_one(&file, None, true, Some((&conn, &drive))).unwrap();
+        let entry = ops::get_hash_cache_entry(&conn, &drive.id, Path::new("a.txt")).unwrap().unwrap();
+        assert_eq!(entry.xxh3_hash, result.xxh3_hex);
+        let (_, _, cached) = hash_one(&file, None, false, Some((&conn, &drive))).unwrap();
+        assert_eq!(cached, Some(result.xxh3_hex));
+    }
+}
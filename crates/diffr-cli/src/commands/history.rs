@@ -1,6 +1,9 @@
 use clap::Args;
 use diffr_core::config::DiffrConfig;
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::sync_state::SyncRecord;
 use diffr_db::ops;
+use serde_json::{json, Value};
 
 #[derive(Args)]
 pub struct HistoryArgs {
@@ -10,6 +13,11 @@ pub struct HistoryArgs {
     /// Maximum number of entries to show
     #[arg(long, default_value = "20")]
     limit: u32,
+
+    /// List the operations one sync carried out, and why each was planned:
+    /// a sync ID, or `last`
+    #[arg(long)]
+    sync: Option<String>,
 }
 
 pub fn run(args: HistoryArgs, json: bool) -> anyhow::Result<()> {
@@ -19,39 +27,153 @@ pub fn run(args: HistoryArgs, json: bool) -> anyhow::Result<()> {
     let cluster = ops::get_cluster_by_name(&conn, &args.cluster)?
         .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", args.cluster))?;
 
+    if let Some(sync) = &args.sync {
+        return print_sync_ops(&conn, &cluster, sync, json);
+    }
+
     let history = ops::list_sync_history(&conn, &cluster.id, args.limit)?;
 
     if json {
         let items: Vec<_> = history
             .iter()
             .map(|s| {
-                format!(
-                    "{{\"id\": \"{}\", \"started\": \"{}\", \"finished\": \"{}\", \"status\": \"{}\", \"files\": {}, \"bytes\": {}}}",
-                    s.id, s.started_at, s.finished_at, s.status, s.files_synced, s.bytes_transferred
-                )
+                json!({
+                    "id": s.id.to_string(),
+                    "started": s.started_at.to_string(),
+                    "finished": s.finished_at.to_string(),
+                    "status": s.status.to_string(),
+                    "files": s.files_synced,
+                    "bytes": s.bytes_transferred,
+                    "host": s.host,
+                    "user": s.user,
+                    "version": s.diffr_version,
+                    "message": s.message,
+                })
             })
             .collect();
-        println!("[{}]", items.join(", "));
+        println!("{}", json!(items));
     } else {
         if history.is_empty() {
             println!("No sync history for cluster '{}'", cluster.name);
         } else {
             println!(
-                "{:<24} {:<16} {:>8} {:>12} {:>8}",
-                "FINISHED", "STATUS", "FILES", "BYTES", "ERRORS"
+                "{:<36} {:<24} {:<16} {:>8} {:>12} {:>8}",
+                "ID", "FINISHED", "STATUS", "FILES", "BYTES", "ERRORS"
             );
             for s in &history {
                 println!(
-                    "{:<24} {:<16} {:>8} {:>12} {:>8}",
+                    "{:<36} {:<24} {:<16} {:>8} {:>12} {:>8}",
+                    s.id,
                     s.finished_at.format("%Y-%m-%d %H:%M:%S"),
                     s.status,
                     s.files_synced,
                     s.bytes_transferred,
                     s.errors.len()
                 );
+                if let Some(session) = describe_session(s) {
+                    println!("    {}", session);
+                }
             }
         }
     }
 
     Ok(())
 }
+
+/// One line saying where, by whom and why a sync ran, if any of it was
+/// recorded: `alice@laptop, diffr 0.1.0: "pre-vacation backup"`.
+pub fn describe_session(record: &SyncRecord) -> Option<String> {
+    let mut parts = Vec::new();
+    match (&record.user, &record.host) {
+        (Some(user), Some(host)) => parts.push(format!("{}@{}", user, host)),
+        (Some(who), None) | (None, Some(who)) => parts.push(who.clone()),
+        (None, None) => {}
+    }
+    if let Some(version) = &record.diffr_version {
+        parts.push(format!("diffr {}", version));
+    }
+    let mut line = parts.join(", ");
+    if let Some(message) = &record.message {
+        if !line.is_empty() {
+            line.push_str(": ");
+        }
+        line.push_str(&format!("\"{}\"", message));
+    }
+    (!line.is_empty()).then_some(line)
+}
+
+/// The journaled operations of `sync`, a sync ID or `last`, in the order
+/// they ran. Undone operations are no longer journaled.
+fn print_sync_ops(conn: &rusqlite::Connection, cluster: &Cluster, sync: &str, json: bool) -> anyhow::Result<()> {
+    let record = if sync == "last" {
+        ops::list_sync_history(conn, &cluster.id, 1)?.into_iter().next()
+    } else {
+        let id: uuid::Uuid = sync.parse().map_err(|_| anyhow::anyhow!("invalid sync ID: {}", sync))?;
+        ops::get_sync_record(conn, &id)?.filter(|r| r.cluster_id == cluster.id)
+    };
+    let record = record.ok_or_else(|| anyhow::anyhow!("no sync '{}' in cluster '{}'", sync, cluster.name))?;
+    let journal = ops::list_journal(conn, &record.id)?;
+    let drives = ops::list_all_drives(conn)?;
+
+    if json {
+        let items: Vec<Value> = journal.iter().map(|e| super::plan::op_json(&e.op, &drives)).collect();
+        println!("{}", json!({"id": record.id.to_string(), "operations": items}));
+        return Ok(());
+    }
+    println!(
+        "Sync {} (finished {}): {} operations",
+        record.id,
+        record.finished_at.format("%Y-%m-%d %H:%M:%S"),
+        journal.len()
+    );
+    for entry in &journal {
+        super::plan::print_op(&entry.op, &drives);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::{ConflictStrategy, Topology};
+    use diffr_core::models::sync_state::SyncStatus;
+
+    #[test]
+    fn test_session_is_saved_and_described() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let at = chrono::Utc::now();
+        let mut record = SyncRecord {
+            id: uuid::Uuid::now_v7(),
+            cluster_id: cluster.id.clone(),
+            started_at: at,
+            finished_at: at,
+            files_synced: 1,
+            bytes_transferred: 10,
+            conflicts_resolved: 0,
+            errors: vec![],
+            status: SyncStatus::Success,
+            host: Some("laptop".into()),
+            user: Some("alice".into()),
+            diffr_version: Some("0.1.0".into()),
+            message: Some("pre-vacation backup".into()),
+        };
+        ops::insert_sync_record(&conn, &record).unwrap();
+
+        let saved = &ops::list_sync_history(&conn, &cluster.id, 1).unwrap()[0];
+        assert_eq!(saved.host.as_deref(), Some("laptop"));
+        assert_eq!(saved.message.as_deref(), Some("pre-vacation backup"));
+        assert_eq!(
+            describe_session(saved).as_deref(),
+            Some("alice@laptop, diffr 0.1.0: \"pre-vacation backup\"")
+        );
+
+        record.user = None;
+        record.diffr_version = None;
+        record.message = None;
+        assert_eq!(describe_session(&record).as_deref(), Some("laptop"));
+        record.host = None;
+        assert_eq!(describe_session(&record), None);
+    }
+}
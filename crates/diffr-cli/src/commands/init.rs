@@ -1,11 +1,78 @@
-use chrono::Utc;
-use clap::Args;
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use diffr_core::config::{DiffrConfig, RepoConfig};
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::drive::Drive;
+use diffr_db::ops;
+use std::path::{Path, PathBuf};
 
 #[derive(Args)]
 pub struct InitArgs {
     /// Path to initialize as a diffr repo (defaults to current directory)
     path: Option<PathBuf>,
+
+    /// Seed .diffrignore with patterns suited to this kind of content
+    #[arg(long, value_enum)]
+    template: Option<Template>,
+
+    /// Show whether the path is an initialized repo instead of initializing it
+    #[arg(long, conflicts_with = "template")]
+    status: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Template {
+    Photos,
+    Code,
+    Documents,
+}
+
+impl Template {
+    fn name(self) -> &'static str {
+        match self {
+            Template::Photos => "photos",
+            Template::Code => "code",
+            Template::Documents => "documents",
+        }
+    }
+
+    /// Ignore patterns for generated or machine-local files.
+    fn ignore_patterns(self) -> &'static [&'static str] {
+        match self {
+            Template::Photos => &[
+                ".DS_Store",
+                "Thumbs.db",
+                "desktop.ini",
+                ".picasa.ini",
+                "@eaDir",
+                ".thumbnails",
+                "*.lrdata",
+                "*.tmp",
+            ],
+            Template::Code => &[
+                "target/",
+                "node_modules/",
+                "__pycache__/",
+                "*.pyc",
+                ".venv/",
+                "build/",
+                "dist/",
+                ".gradle/",
+                "*.o",
+                "*.swp",
+                ".DS_Store",
+            ],
+            Template::Documents => &[
+                "~$*",
+                ".~lock.*#",
+                "*.tmp",
+                "*.bak",
+                ".DS_Store",
+                "Thumbs.db",
+                "desktop.ini",
+            ],
+        }
+    }
 }
 
 /// Canonicalize a path, stripping the `\\?\` extended-path prefix on Windows.
@@ -21,14 +88,25 @@ pub fn simplified_canonicalize(path: &std::path::Path) -> std::io::Result<PathBu
     Ok(canon)
 }
 
-pub fn run(args: InitArgs) -> anyhow::Result<()> {
+pub fn run(args: InitArgs, json: bool) -> anyhow::Result<()> {
+    if args.status {
+        return show_status(&args.path.unwrap_or_else(|| PathBuf::from(".")), json);
+    }
+
     let raw_path = args.path.unwrap_or_else(|| PathBuf::from("."));
+    let path = init_repo(&raw_path, args.template)?;
+    println!("Initialized diffr repo at {}", path.display());
+    Ok(())
+}
+
+/// Create `.diffr/repo.toml` and a `.diffrignore` at `raw_path`, creating the
+/// directory if needed. Returns the canonical repo root.
+pub fn init_repo(raw_path: &Path, template: Option<Template>) -> anyhow::Result<PathBuf> {
     // Create the directory if it doesn't exist (like git init)
-    std::fs::create_dir_all(&raw_path)?;
-    let path = simplified_canonicalize(&raw_path)?;
+    std::fs::create_dir_all(raw_path)?;
+    let path = simplified_canonicalize(raw_path)?;
 
-    let diffr_dir = path.join(".diffr");
-    let repo_toml = diffr_dir.join("repo.toml");
+    let repo_toml = RepoConfig::path_in(&path);
 
     if repo_toml.exists() {
         anyhow::bail!(
@@ -37,23 +115,155 @@ pub fn run(args: InitArgs) -> anyhow::Result<()> {
         );
     }
 
-    std::fs::create_dir_all(&diffr_dir)?;
-
-    let content = format!(
-        "[repo]\ninitialized_at = \"{}\"\n",
-        Utc::now().to_rfc3339()
-    );
-    std::fs::write(&repo_toml, content)?;
+    RepoConfig::new(template.map(|t| t.name().to_string())).save(&path)?;
 
     // Create .diffrignore template if absent
     let ignore_path = path.join(".diffrignore");
     if !ignore_path.exists() {
-        std::fs::write(
-            &ignore_path,
-            "# Diffr ignore patterns (one per line, gitignore syntax)\n.diffr/\n",
-        )?;
+        let mut content = String::from(
+            "# Diffr ignore patterns (one per line; * and ? wildcards)\n.diffr/\n",
+        );
+        if let Some(template) = template {
+            content.push_str(&format!("\n# {} template\n", template.name()));
+            for pattern in template.ignore_patterns() {
+                content.push_str(pattern);
+                content.push('\n');
+            }
+        }
+        std::fs::write(&ignore_path, content)?;
     }
 
-    println!("Initialized diffr repo at {}", path.display());
+    Ok(path)
+}
+
+/// Drives registered with `root` as their sync root.
+pub fn drives_for_root(root: &Path) -> anyhow::Result<Vec<(Drive, Option<Cluster>)>> {
+    let db_path = DiffrConfig::db_path()?;
+    if !db_path.exists() {
+        return Ok(Vec::new());
+    }
+    let conn = diffr_db::open_db(&db_path)?;
+    let mut found = Vec::new();
+    for drive in ops::list_all_drives(&conn)? {
+        if drive.effective_root() != root {
+            continue;
+        }
+        let cluster = match &drive.cluster_id {
+            Some(id) => ops::get_cluster_by_id(&conn, id)?,
+            None => None,
+        };
+        found.push((drive, cluster));
+    }
+    Ok(found)
+}
+
+fn show_status(path: &Path, json: bool) -> anyhow::Result<()> {
+    let root = simplified_canonicalize(path)
+        .map_err(|_| anyhow::anyhow!("path does not exist: {}", path.display()))?;
+    let config = RepoConfig::load(&root)?;
+    let drives = drives_for_root(&root)?;
+
+    // Drives are only found through the database, so there is one.
+    let last_indexed = if drives.is_empty() {
+        None
+    } else {
+        last_indexed(&diffr_db::open_db(&DiffrConfig::db_path()?)?, &drives)?
+    };
+
+    if json {
+        let drive_items: Vec<_> = drives
+            .iter()
+            .map(|(d, c)| {
+                format!(
+                    "{{\"identity\": \"{}\", \"cluster\": {}}}",
+                    d.identity.identity_string(),
+                    c.as_ref()
+                        .map(|c| format!("\"{}\"", c.name))
+                        .unwrap_or_else(|| "null".to_string())
+                )
+            })
+            .collect();
+        println!(
+            "{{\"path\": \"{}\", \"initialized\": {}, \"initialized_at\": {}, \"drives\": [{}], \"last_indexed\": {}}}",
+            root.display(),
+            config.is_some(),
+            config
+                .as_ref()
+                .map(|c| format!("\"{}\"", c.repo.initialized_at))
+                .unwrap_or_else(|| "null".to_string()),
+            drive_items.join(", "),
+            last_indexed
+                .map(|t| format!("\"{}\"", t))
+                .unwrap_or_else(|| "null".to_string())
+        );
+        return Ok(());
+    }
+
+    let Some(config) = config else {
+        println!("{} is not a diffr repo (run `diffr init {}`)", root.display(), root.display());
+        return Ok(());
+    };
+    println!("Repo: {}", root.display());
+    println!("  Initialized: {}", config.repo.initialized_at);
+    if let Some(template) = &config.repo.template {
+        println!("  Template:    {}", template);
+    }
+    println!("  Hash mode:   {}", config.settings.hash);
+    if drives.is_empty() {
+        println!("  Drive:       not registered (use `diffr drive add <id> --cluster <name> --path {}`)", root.display());
+    }
+    for (drive, cluster) in &drives {
+        println!(
+            "  Drive:       {} (cluster: {})",
+            drive.identity.identity_string(),
+            cluster.as_ref().map(|c| c.name.as_str()).unwrap_or("none")
+        );
+    }
+    println!(
+        "  Indexed:     {}",
+        last_indexed
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| "never".to_string())
+    );
     Ok(())
 }
+
+/// When a scan last saved the catalog of any of `drives`.
+fn last_indexed(
+    conn: &rusqlite::Connection,
+    drives: &[(Drive, Option<Cluster>)],
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let mut latest = None;
+    for (drive, _) in drives {
+        if let Some(generation) = ops::list_index_generations(conn, &drive.id)?.into_iter().next() {
+            latest = latest.max(Some(generation.indexed_at));
+        }
+    }
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+
+    #[test]
+    fn test_last_indexed_is_the_latest_scan_of_the_root() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let (a, b) = (
+            Drive::new(DriveIdentity::new_synthetic(), "/mnt/a".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/mnt/a".into()),
+        );
+        ops::insert_drive(&conn, &a).unwrap();
+        ops::insert_drive(&conn, &b).unwrap();
+        let drives = vec![(a.clone(), None), (b.clone(), None)];
+        assert_eq!(last_indexed(&conn, &drives).unwrap(), None);
+
+        ops::replace_file_index(&conn, &a.id, &[]).unwrap();
+        let first = last_indexed(&conn, &drives).unwrap().unwrap();
+        ops::replace_file_index(&conn, &b.id, &[]).unwrap();
+        let latest = ops::list_index_generations(&conn, &b.id).unwrap()[0].indexed_at;
+        assert!(latest >= first);
+        assert_eq!(last_indexed(&conn, &drives).unwrap(), Some(latest));
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use clap::Args;
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::sync_state::{SyncRecord, SyncStatus};
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::history::describe_session;
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Only show syncs newer than this: an age like 30m, 12h, 7d or 2w, or a
+    /// date (YYYY-MM-DD)
+    #[arg(long, value_parser = super::parse_time)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only show syncs with this outcome: success, partial, failed or
+    /// cancelled
+    #[arg(long, value_parser = parse_status)]
+    status: Option<SyncStatus>,
+
+    /// Only show syncs that copied to or from, or changed files on, this
+    /// drive
+    #[arg(long)]
+    drive: Option<String>,
+
+    /// Maximum number of entries to show
+    #[arg(long, default_value = "50")]
+    limit: usize,
+}
+
+/// Recent syncs across every cluster, newest first.
+pub fn run(args: LogArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    let names: HashMap<_, _> = ops::list_clusters(&conn)?
+        .into_iter()
+        .map(|c| (c.id, c.name))
+        .collect();
+    let drive_syncs = match &args.drive {
+        Some(identity) => {
+            let drive = super::drive::lookup_drive(&conn, identity)?;
+            Some(ops::list_sync_ids_for_drive(&conn, &drive.id)?)
+        }
+        None => None,
+    };
+
+    let records = select(ops::list_all_sync_history(&conn, u32::MAX)?, &args, drive_syncs.as_ref());
+    let name = |s: &SyncRecord| {
+        names
+            .get(&s.cluster_id)
+            .cloned()
+            .unwrap_or_else(|| "(removed)".to_string())
+    };
+
+    if json {
+        let items: Vec<_> = records
+            .iter()
+            .map(|s| {
+                json!({
+                    "id": s.id.to_string(),
+                    "cluster": name(s),
+                    "started": s.started_at.to_string(),
+                    "finished": s.finished_at.to_string(),
+                    "status": s.status.to_string(),
+                    "files": s.files_synced,
+                    "bytes": s.bytes_transferred,
+                    "errors": s.errors.len(),
+                })
+            })
+            .collect();
+        println!("{}", json!(items));
+    } else if records.is_empty() {
+        println!("No syncs match.");
+    } else {
+        println!(
+            "{:<20} {:<16} {:<16} {:>8} {:>10} {:>7}  {:<36}",
+            "FINISHED", "CLUSTER", "STATUS", "FILES", "BYTES", "ERRORS", "ID"
+        );
+        for s in &records {
+            println!(
+                "{:<20} {:<16} {:<16} {:>8} {:>10} {:>7}  {:<36}",
+                s.finished_at.format("%Y-%m-%d %H:%M:%S"),
+                name(s),
+                s.status.to_string(),
+                s.files_synced,
+                format_bytes(s.bytes_transferred),
+                s.errors.len(),
+                s.id
+            );
+            if let Some(session) = describe_session(s) {
+                println!("    {}", session);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The syncs among `records` that `args` asks for, where `drive_syncs` are
+/// the syncs that touched `--drive`.
+fn select(records: Vec<SyncRecord>, args: &LogArgs, drive_syncs: Option<&HashSet<Uuid>>) -> Vec<SyncRecord> {
+    records
+        .into_iter()
+        .filter(|s| args.since.is_none_or(|since| s.started_at >= since))
+        .filter(|s| args.status.as_ref().is_none_or(|status| &s.status == status))
+        .filter(|s| drive_syncs.is_none_or(|ids| ids.contains(&s.id)))
+        .take(args.limit)
+        .collect()
+}
+
+fn parse_status(s: &str) -> Result<SyncStatus, String> {
+    match s {
+        "success" | "ok" => Ok(SyncStatus::Success),
+        "partial" | "partial_success" => Ok(SyncStatus::PartialSuccess),
+        "failed" | "failure" => Ok(SyncStatus::Failed),
+        "cancelled" | "canceled" => Ok(SyncStatus::Cancelled),
+        _ => Err(format!("unknown status '{}' (use success, partial, failed or cancelled)", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::{Cluster, ConflictStrategy, Topology};
+    use diffr_core::models::drive::DriveId;
+    use diffr_core::models::sync_state::{JournalEntry, SyncOp, SyncOpKind};
+
+    fn record(cluster: &Cluster, status: SyncStatus, minutes_ago: i64) -> SyncRecord {
+        let at = Utc::now() - chrono::Duration::minutes(minutes_ago);
+        SyncRecord {
+            id: Uuid::now_v7(),
+            cluster_id: cluster.id.clone(),
+            started_at: at,
+            finished_at: at,
+            files_synced: 1,
+            bytes_transferred: 10,
+            conflicts_resolved: 0,
+            errors: vec![],
+            status,
+            host: None,
+            user: None,
+            diffr_version: None,
+            message: None,
+        }
+    }
+
+    fn copy(sync: &SyncRecord, source: &DriveId, target: &DriveId) -> JournalEntry {
+        JournalEntry {
+            sync_id: sync.id,
+            op: SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: "a.txt".into(),
+                source_drive: Some(source.clone()),
+                target_drive: target.clone(),
+                size_bytes: 10,
+                source_path: None,
+                reason: None,
+                expect: None,
+            },
+            archive_id: None,
+            trash_path: None,
+        }
+    }
+
+    #[test]
+    fn test_drive_filter_goes_by_what_each_sync_touched() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        // Both syncs are of the same cluster; only the first involved `c`.
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a, b, c) = (DriveId::new(), DriveId::new(), DriveId::new());
+        let older = record(&cluster, SyncStatus::Success, 60);
+        let newer = record(&cluster, SyncStatus::Failed, 5);
+        for (sync, journal) in [(&older, copy(&older, &a, &c)), (&newer, copy(&newer, &a, &b))] {
+            ops::insert_sync_record(&conn, sync).unwrap();
+            ops::insert_journal(&conn, &[journal]).unwrap();
+        }
+        let all = ops::list_all_sync_history(&conn, u32::MAX).unwrap();
+        let args = LogArgs {
+            since: None,
+            status: None,
+            drive: Some("c".into()),
+            limit: 50,
+        };
+
+        let touched_c = ops::list_sync_ids_for_drive(&conn, &c).unwrap();
+        let ids: Vec<Uuid> = select(all.clone(), &args, Some(&touched_c)).iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![older.id]);
+        let touched_a = ops::list_sync_ids_for_drive(&conn, &a).unwrap();
+        assert_eq!(select(all.clone(), &args, Some(&touched_a)).len(), 2);
+
+        let failed = LogArgs {
+            status: Some(SyncStatus::Failed),
+            since: Some(Utc::now() - chrono::Duration::minutes(30)),
+            ..args
+        };
+        let ids: Vec<Uuid> = select(all, &failed, None).iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![newer.id]);
+    }
+}
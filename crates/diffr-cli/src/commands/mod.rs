@@ -1,16 +1,40 @@
+pub mod adopt;
 pub mod archive;
+pub mod cache;
+pub mod clone;
 pub mod cluster;
+pub mod compare;
 pub mod config;
+pub mod copy;
+pub mod ctl;
+pub mod daemon;
+pub mod db;
+pub mod dedupe;
+pub mod deinit;
 pub mod drive;
+pub mod du;
+pub mod hash;
 pub mod history;
 pub mod init;
+pub mod log;
+pub mod plan;
+pub mod report;
+pub mod serve;
+pub mod service;
+pub mod setup;
+pub mod skip;
 pub mod status;
 pub mod sync;
+pub mod trash;
+pub mod undo;
+pub mod web;
 
 use clap::Subcommand;
 
 #[derive(Subcommand)]
 pub enum Command {
+    /// Interactive first-run setup: pick drives, create a cluster, preview a sync
+    Setup(setup::SetupArgs),
     /// Initialize or manage Diffr configuration
     Config {
         #[command(subcommand)]
@@ -28,28 +52,255 @@ pub enum Command {
     },
     /// Sync a cluster
     Sync(sync::SyncArgs),
+    /// Leave paths out of a cluster's syncs for a while
+    Skip {
+        #[command(subcommand)]
+        action: skip::SkipAction,
+    },
+    /// Inspect sync plans without running them
+    Plan {
+        #[command(subcommand)]
+        action: plan::PlanAction,
+    },
+    /// One-shot copy between two directories, without a cluster
+    Copy(copy::CopyArgs),
+    /// Mirror one drive onto an empty (or wiped) drive and verify the result
+    Clone(clone::CloneArgs),
+    /// Compare two drives without syncing
+    Compare(compare::CompareArgs),
+    /// Reconstruct cluster and drive records from a drive's on-disk manifest
+    Adopt(adopt::AdoptArgs),
     /// Show cluster status
     Status(status::StatusArgs),
     /// Show sync history
     History(history::HistoryArgs),
+    /// Show recent syncs across all clusters
+    Log(log::LogArgs),
+    /// Show space used by directory and file type, from the catalog
+    Du(du::DuArgs),
+    /// Write a Markdown or HTML report of a cluster's drives, syncs and archives
+    Report(report::ReportArgs),
+    /// Reverse a sync using its journal, archives and trash
+    Undo(undo::UndoArgs),
     /// Initialize a diffr repo at a directory
     Init(init::InitArgs),
+    /// Remove diffr metadata (.diffr/) from a repo
+    Deinit(deinit::DeinitArgs),
     /// Manage archives
     Archive {
         #[command(subcommand)]
         action: archive::ArchiveAction,
     },
+    /// Inspect or empty the trash that sync moves deleted files into
+    Trash {
+        #[command(subcommand)]
+        action: trash::TrashAction,
+    },
+    /// Find data stored more than once, from the catalog
+    Dedupe {
+        #[command(subcommand)]
+        action: dedupe::DedupeAction,
+    },
+    /// Print the hashes of a file or of every file in a directory
+    Hash(hash::HashArgs),
+    /// Inspect or clear the file hash cache
+    Cache {
+        #[command(subcommand)]
+        action: cache::CacheAction,
+    },
+    /// Database maintenance
+    Db {
+        #[command(subcommand)]
+        action: db::DbAction,
+    },
+    /// Serve clusters, drives, history and syncs over JSON-RPC
+    Serve(serve::ServeArgs),
+    /// Serve a dashboard of cluster status, sync history and archive usage
+    Web(web::WebArgs),
+    /// Run scheduled syncs in the background, or install them as a login service
+    Daemon {
+        #[command(subcommand)]
+        action: daemon::DaemonAction,
+    },
+    /// Control a running daemon: status, pause, resume, sync-now
+    Ctl {
+        #[command(subcommand)]
+        action: ctl::CtlAction,
+    },
+}
+
+impl Command {
+    /// Whether the command changes drives or the database, and so must not
+    /// run alongside another that does.
+    pub fn writes(&self) -> bool {
+        match self {
+            Command::Sync(args) => !args.is_dry_run(),
+            Command::Cluster { action } => !matches!(
+                action,
+                cluster::ClusterAction::List
+                    | cluster::ClusterAction::Presets
+                    | cluster::ClusterAction::Info { .. }
+                    | cluster::ClusterAction::Export { .. }
+            ),
+            Command::Drive { action } => !matches!(
+                action,
+                drive::DriveAction::Scan { .. }
+                    | drive::DriveAction::SimulateRemove { .. }
+                    | drive::DriveAction::List
+                    | drive::DriveAction::Tags
+                    | drive::DriveAction::Info { .. }
+            ),
+            Command::Archive { action } => !matches!(
+                action,
+                archive::ArchiveAction::List { .. } | archive::ArchiveAction::Stats { .. }
+            ),
+            Command::Trash { action } => !matches!(action, trash::TrashAction::List { .. }),
+            Command::Cache { action } => !matches!(action, cache::CacheAction::Stats),
+            Command::Hash(args) => args.writes(),
+            Command::Skip { action } => action.writes(),
+            Command::Db { .. }
+            | Command::Setup(_)
+            | Command::Init(_)
+            | Command::Deinit(_)
+            | Command::Copy(_)
+            | Command::Clone(_)
+            | Command::Adopt(_)
+            | Command::Undo(_) => true,
+            // `serve`, `web` and `daemon` run each request or sync as its own
+            // command, which locks for itself.
+            Command::Config { .. }
+            | Command::Plan { .. }
+            | Command::Compare(_)
+            | Command::Status(_)
+            | Command::History(_)
+            | Command::Log(_)
+            | Command::Du(_)
+            | Command::Report(_)
+            | Command::Dedupe { .. }
+            | Command::Serve(_)
+            | Command::Web(_)
+            | Command::Daemon { .. }
+            | Command::Ctl { .. } => false,
+        }
+    }
+}
+
+/// Take the lock that keeps commands which change drives or the database
+/// from running at once, waiting for whoever holds it unless `wait` is
+/// false.
+pub fn lock(wait: bool) -> anyhow::Result<diffr_core::lock::InstanceLock> {
+    use diffr_core::config::DiffrConfig;
+    use diffr_core::lock::InstanceLock;
+
+    let path = DiffrConfig::lock_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let holder = format!("pid {}: diffr {}", std::process::id(), args.join(" "));
+    if let Some(lock) = InstanceLock::try_acquire(&path, &holder)? {
+        return Ok(lock);
+    }
+    let other = InstanceLock::holder(&path).unwrap_or_else(|| "another diffr command".to_string());
+    if !wait {
+        anyhow::bail!("{} is running; not waiting for it (--no-wait)", other);
+    }
+    eprintln!("Waiting for {} to finish...", other);
+    Ok(InstanceLock::acquire(&path, &holder)?)
 }
 
+/// Returned by a command that has reported its outcome and only needs the
+/// process to exit with its code, such as `status --check`. Unlike calling
+/// `process::exit`, returning it lets locks and other guards drop first.
+#[derive(Debug, thiserror::Error)]
+#[error("exit code {0}")]
+pub struct Exit(pub u8);
+
 pub fn run(cmd: Command, json: bool) -> anyhow::Result<()> {
     match cmd {
         Command::Config { action } => config::run(action),
         Command::Cluster { action } => cluster::run(action, json),
         Command::Drive { action } => drive::run(action, json),
-        Command::Init(args) => init::run(args),
+        Command::Setup(args) => setup::run(args, json),
+        Command::Init(args) => init::run(args, json),
+        Command::Deinit(args) => deinit::run(args, json),
         Command::Sync(args) => sync::run(args, json),
+        Command::Skip { action } => skip::run(action, json),
+        Command::Plan { action } => plan::run(action, json),
+        Command::Copy(args) => copy::run(args, json),
+        Command::Clone(args) => clone::run(args, json),
+        Command::Compare(args) => compare::run(args, json),
+        Command::Adopt(args) => adopt::run(args, json),
         Command::Status(args) => status::run(args, json),
         Command::History(args) => history::run(args, json),
+        Command::Log(args) => log::run(args, json),
+        Command::Du(args) => du::run(args, json),
+        Command::Report(args) => report::run(args, json),
+        Command::Undo(args) => undo::run(args, json),
         Command::Archive { action } => archive::run(action, json),
+        Command::Trash { action } => trash::run(action, json),
+        Command::Dedupe { action } => dedupe::run(action, json),
+        Command::Hash(args) => hash::run(args, json),
+        Command::Cache { action } => cache::run(action, json),
+        Command::Db { action } => db::run(action, json),
+        Command::Serve(args) => serve::run(args, json),
+        Command::Web(args) => web::run(args, json),
+        Command::Daemon { action } => daemon::run(action, json),
+        Command::Ctl { action } => ctl::run(action, json),
     }
 }
+
+/// Parse an age such as `30m`, `12h`, `7d` or `2w`. Unlike durations in
+/// settings, a bare number isn't taken as seconds: `--since 7` more likely
+/// means days, so it needs a unit.
+pub fn parse_age(s: &str) -> Result<chrono::Duration, String> {
+    if s.trim().chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("give '{}' a unit, such as {}d", s, s.trim()));
+    }
+    let age = diffr_core::units::parse_duration(s)?;
+    chrono::Duration::from_std(age).map_err(|_| format!("'{}' is too long", s))
+}
+
+/// Parse a point in time: a UTC date (`2024-03-01`), a date and time
+/// (`2024-03-01 14:30`), or an age back from now such as `7d`.
+pub fn parse_time(s: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+        return Ok(time.and_utc());
+    }
+    let age = parse_age(s).map_err(|e| format!("{} or a date (YYYY-MM-DD [HH:MM])", e))?;
+    Ok(chrono::Utc::now() - age)
+}
+
+/// Ask for a line of input, falling back to `default` when left blank.
+pub fn prompt(question: &str, default: &str) -> anyhow::Result<String> {
+    use std::io::Write;
+
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    if std::io::stdin().read_line(&mut input)? == 0 {
+        anyhow::bail!("input closed");
+    }
+    let answer = input.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+/// Ask a yes/no question on the terminal. Anything but `y`/`yes` is a no.
+pub fn confirm(question: &str) -> anyhow::Result<bool> {
+    use std::io::Write;
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,140 @@
+use clap::Subcommand;
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::drive::Drive;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use diffr_db::ops;
+use diffr_sync::engine::drive_root_display;
+use diffr_sync::plan_diff::diff_plans;
+use serde_json::{json, Value};
+
+use super::sync::SyncArgs;
+
+#[derive(Subcommand)]
+pub enum PlanAction {
+    /// Build the plan a sync would run now and list the operations that
+    /// weren't in the plan saved by the last sync or dry run
+    Diff {
+        /// Cluster name
+        cluster: String,
+        /// New operations to list
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
+}
+
+pub fn run(action: PlanAction, json: bool) -> anyhow::Result<()> {
+    match action {
+        PlanAction::Diff { cluster, limit } => {
+            super::sync::run(SyncArgs::plan_diff(cluster, limit), json)
+        }
+    }
+}
+
+/// Print how `plan` differs from the last one saved for `cluster`. Called by
+/// sync once the plan is built.
+pub fn print_diff(
+    conn: &rusqlite::Connection,
+    cluster: &Cluster,
+    drives: &[Drive],
+    plan: &SyncPlan,
+    limit: usize,
+    json: bool,
+) -> anyhow::Result<()> {
+    let Some(previous) = ops::get_last_plan(conn, &cluster.id)? else {
+        if json {
+            println!("{}", json!({"cluster": cluster.name, "previous_at": null}));
+        } else {
+            println!(
+                "\nNo saved plan for cluster '{}' yet; run `diffr sync {} --dry-run` to save one.",
+                cluster.name, cluster.name
+            );
+        }
+        return Ok(());
+    };
+    let diff = diff_plans(&previous, plan);
+
+    if json {
+        let added: Vec<Value> = diff.added.iter().map(|op| op_json(op, drives)).collect();
+        println!(
+            "{}",
+            json!({
+                "cluster": cluster.name,
+                "previous_at": previous.created_at.to_string(),
+                "unchanged": diff.unchanged,
+                "removed": diff.removed.len(),
+                "added": added,
+            })
+        );
+        return Ok(());
+    }
+
+    println!(
+        "\nCompared with the plan from {} ({} operations):",
+        previous.created_at.format("%Y-%m-%d %H:%M"),
+        previous.op_count()
+    );
+    println!(
+        "  {} new, {} no longer planned, {} unchanged",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.unchanged
+    );
+    if diff.added.is_empty() {
+        return Ok(());
+    }
+
+    let mut by_target: Vec<_> = diff.added_by_target().into_iter().collect();
+    by_target.sort_by_key(|((drive, kind), _)| (drive_root_display(drives, drive), kind.clone()));
+    println!("\nNew operations by drive:");
+    for ((drive, kind), count) in &by_target {
+        println!("  {:<40} {:>6} {}", drive_root_display(drives, drive), count, kind);
+    }
+
+    println!();
+    // Deletes and overwrites come first; they're the ones that lose data.
+    for op in diff.added.iter().take(limit) {
+        print_op(op, drives);
+    }
+    if diff.added.len() > limit {
+        println!("... and {} more", diff.added.len() - limit);
+    }
+    Ok(())
+}
+
+/// `!` for operations that replace or remove what's on the target, `+`
+/// for the rest.
+fn op_marker(kind: &SyncOpKind) -> &'static str {
+    match kind {
+        SyncOpKind::Delete
+        | SyncOpKind::RemoveDir
+        | SyncOpKind::Overwrite
+        | SyncOpKind::ResolveConflict
+        | SyncOpKind::Merge => "!",
+        _ => "+",
+    }
+}
+
+/// One operation, with why it was planned on the line below.
+pub fn print_op(op: &SyncOp, drives: &[Drive]) {
+    println!(
+        "{} {:<16} {} -> {}",
+        op_marker(&op.kind),
+        op.kind.to_string(),
+        op.rel_path.display(),
+        drive_root_display(drives, &op.target_drive)
+    );
+    if let Some(reason) = &op.reason {
+        println!("    {}", reason);
+    }
+}
+
+/// One operation as a JSON object.
+pub fn op_json(op: &SyncOp, drives: &[Drive]) -> Value {
+    json!({
+        "kind": op.kind.to_string(),
+        "path": op.rel_path.display().to_string(),
+        "target": drive_root_display(drives, &op.target_drive),
+        "bytes": op.size_bytes,
+        "reason": op.reason,
+    })
+}
@@ -0,0 +1,363 @@
+use clap::{Args, ValueEnum};
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::sync_state::SyncStatus;
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct ReportArgs {
+    /// Cluster name
+    cluster: String,
+
+    /// Output format
+    #[arg(long, value_enum, default_value = "md")]
+    format: ReportFormat,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    out: Option<PathBuf>,
+
+    /// Number of recent syncs to include
+    #[arg(long, default_value = "10")]
+    limit: u32,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    #[value(alias = "markdown")]
+    Md,
+}
+
+/// A titled table, rendered the same way in every format.
+struct Section {
+    title: &'static str,
+    headers: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+    /// Shown in place of the table when there are no rows.
+    empty: &'static str,
+}
+
+pub fn run(args: ReportArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+    let body = build_report(&conn, &args.cluster, args.limit, args.format)?;
+
+    match &args.out {
+        Some(path) => {
+            std::fs::write(path, body)?;
+            if json {
+                println!("{{\"written\": \"{}\"}}", path.display());
+            } else {
+                println!("Wrote report to {}", path.display());
+            }
+        }
+        None => print!("{}", body),
+    }
+    Ok(())
+}
+
+/// The report on `cluster_name` and its last `limit` syncs, in `format`.
+fn build_report(
+    conn: &rusqlite::Connection,
+    cluster_name: &str,
+    limit: u32,
+    format: ReportFormat,
+) -> anyhow::Result<String> {
+    let cluster = ops::get_cluster_by_name(conn, cluster_name)?
+        .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", cluster_name))?;
+    let drives = ops::list_drives_for_cluster(conn, &cluster.id)?;
+    let history = ops::list_sync_history(conn, &cluster.id, limit)?;
+
+    let mut drive_rows = Vec::new();
+    let mut archive_rows = Vec::new();
+    for d in &drives {
+        let root = d.effective_root();
+        let connected = root.exists();
+        let mut flags = vec![d.role.to_string()];
+        if d.is_primary {
+            flags.push("primary".into());
+        }
+        if d.read_only {
+            flags.push("read-only".into());
+        }
+        let usage = match (d.used_bytes, d.quota_bytes) {
+            (used, Some(quota)) => format!(
+                "{} of {} quota",
+                format_bytes(used.unwrap_or(0)),
+                format_bytes(quota)
+            ),
+            (Some(used), None) => format_bytes(used),
+            (None, None) => "-".into(),
+        };
+        drive_rows.push(vec![
+            d.label
+                .clone()
+                .unwrap_or_else(|| d.identity.identity_string().to_string()),
+            root.display().to_string(),
+            if connected { "connected" } else { "offline" }.into(),
+            flags.join(", "),
+            usage,
+            d.last_seen.format("%Y-%m-%d %H:%M").to_string(),
+        ]);
+
+        // An archive whose file has gone missing can no longer be restored.
+        let archives = ops::list_archives_for_drive(conn, &d.id)?;
+        if !archives.is_empty() {
+            let stored: u64 = archives.iter().map(|a| a.compressed_size).sum();
+            let original: u64 = archives.iter().map(|a| a.original_size).sum();
+            let integrity = if connected {
+                let missing = archives
+                    .iter()
+                    .filter(|a| !root.join(&a.archive_path).exists())
+                    .count();
+                if missing == 0 {
+                    "all present".to_string()
+                } else {
+                    format!("{} missing", missing)
+                }
+            } else {
+                "not checked (offline)".to_string()
+            };
+            archive_rows.push(vec![
+                d.identity.identity_string().to_string(),
+                archives.len().to_string(),
+                format_bytes(original),
+                format_bytes(stored),
+                integrity,
+            ]);
+        }
+    }
+
+    let sync_rows: Vec<Vec<String>> = history
+        .iter()
+        .map(|s| {
+            vec![
+                s.finished_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                s.status.to_string(),
+                s.files_synced.to_string(),
+                format_bytes(s.bytes_transferred),
+                s.conflicts_resolved.to_string(),
+                s.errors.len().to_string(),
+            ]
+        })
+        .collect();
+
+    // Verification failures are recorded as sync errors by the executor.
+    let problem_rows: Vec<Vec<String>> = history
+        .iter()
+        .flat_map(|s| {
+            s.errors.iter().map(move |e| {
+                let kind = if e.contains("verification failed") {
+                    "verification"
+                } else {
+                    "error"
+                };
+                vec![
+                    s.finished_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    kind.to_string(),
+                    e.clone(),
+                ]
+            })
+        })
+        .collect();
+
+    let summary = vec![
+        ("Cluster", cluster.name.clone()),
+        ("Topology", cluster.topology.to_string()),
+        ("Conflict strategy", cluster.conflict_strategy.to_string()),
+        (
+            "Drives",
+            format!(
+                "{} ({} connected)",
+                drives.len(),
+                drives.iter().filter(|d| d.effective_root().exists()).count()
+            ),
+        ),
+        (
+            "Last sync",
+            match history.first() {
+                Some(s) => format!("{} ({})", s.finished_at.format("%Y-%m-%d %H:%M:%S"), s.status),
+                None => "never".into(),
+            },
+        ),
+        (
+            "Health",
+            match history.first().map(|s| &s.status) {
+                Some(SyncStatus::Success) if problem_rows.is_empty() => "OK".into(),
+                Some(_) => format!("{} problem(s) in recent syncs", problem_rows.len()),
+                None => "never synced".into(),
+            },
+        ),
+    ];
+
+    let sections = [
+        Section {
+            title: "Drives",
+            headers: &["Drive", "Sync root", "State", "Role", "Usage", "Last seen"],
+            rows: drive_rows,
+            empty: "No drives in this cluster.",
+        },
+        Section {
+            title: "Recent syncs",
+            headers: &["Finished", "Status", "Files", "Bytes", "Conflicts", "Errors"],
+            rows: sync_rows,
+            empty: "No syncs recorded.",
+        },
+        Section {
+            title: "Errors and verification",
+            headers: &["Sync", "Kind", "Detail"],
+            rows: problem_rows,
+            empty: "No errors or verification failures in recent syncs.",
+        },
+        Section {
+            title: "Archives",
+            headers: &["Drive", "Versions", "Original size", "Stored size", "Integrity"],
+            rows: archive_rows,
+            empty: "No archived versions.",
+        },
+    ];
+
+    let generated = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+    let title = format!("Diffr report: {}", cluster.name);
+    Ok(match format {
+        ReportFormat::Md => render_markdown(&title, &generated, &summary, &sections),
+        ReportFormat::Html => render_html(&title, &generated, &summary, &sections),
+    })
+}
+
+fn render_markdown(
+    title: &str,
+    generated: &str,
+    summary: &[(&str, String)],
+    sections: &[Section],
+) -> String {
+    let cell = |s: &str| s.replace('|', "\\|");
+    let mut out = format!("# {}\n\nGenerated {}\n\n", title, generated);
+    for (key, value) in summary {
+        out.push_str(&format!("- **{}:** {}\n", key, cell(value)));
+    }
+    for section in sections {
+        out.push_str(&format!("\n## {}\n\n", section.title));
+        if section.rows.is_empty() {
+            out.push_str(&format!("{}\n", section.empty));
+            continue;
+        }
+        out.push_str(&format!("| {} |\n", section.headers.join(" | ")));
+        out.push_str(&format!("|{}\n", "---|".repeat(section.headers.len())));
+        for row in &section.rows {
+            let cells: Vec<String> = row.iter().map(|c| cell(c)).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+    }
+    out
+}
+
+fn render_html(
+    title: &str,
+    generated: &str,
+    summary: &[(&str, String)],
+    sections: &[Section],
+) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+         th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}\n\
+         th {{ background: #f0f0f0; }}\n\
+         </style>\n</head>\n<body>\n<h1>{0}</h1>\n<p>Generated {1}</p>\n<ul>\n",
+        escape_html(title),
+        escape_html(generated)
+    );
+    for (key, value) in summary {
+        out.push_str(&format!(
+            "<li><strong>{}:</strong> {}</li>\n",
+            escape_html(key),
+            escape_html(value)
+        ));
+    }
+    out.push_str("</ul>\n");
+    for section in sections {
+        out.push_str(&format!("<h2>{}</h2>\n", escape_html(section.title)));
+        if section.rows.is_empty() {
+            out.push_str(&format!("<p>{}</p>\n", escape_html(section.empty)));
+            continue;
+        }
+        out.push_str("<table>\n<tr>");
+        for h in section.headers {
+            out.push_str(&format!("<th>{}</th>", escape_html(h)));
+        }
+        out.push_str("</tr>\n");
+        for row in &section.rows {
+            out.push_str("<tr>");
+            for c in row {
+                out.push_str(&format!("<td>{}</td>", escape_html(c)));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::{Cluster, ConflictStrategy, Topology};
+    use diffr_core::models::drive::{Drive, DriveIdentity};
+    use diffr_core::models::sync_state::SyncRecord;
+
+    #[test]
+    fn test_report_covers_drives_syncs_and_their_errors() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let mut drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/gone".into());
+        drive.label = Some("<backup>".into());
+        drive.cluster_id = Some(cluster.id.clone());
+        ops::insert_drive(&conn, &drive).unwrap();
+        let at = chrono::Utc::now();
+        ops::insert_sync_record(
+            &conn,
+            &SyncRecord {
+                id: uuid::Uuid::now_v7(),
+                cluster_id: cluster.id.clone(),
+                started_at: at,
+                finished_at: at,
+                files_synced: 3,
+                bytes_transferred: 10,
+                conflicts_resolved: 0,
+                errors: vec!["a|b.txt: verification failed".into()],
+                status: SyncStatus::PartialSuccess,
+                host: None,
+                user: None,
+                diffr_version: None,
+                message: None,
+            },
+        )
+        .unwrap();
+
+        let md = build_report(&conn, "c", 10, ReportFormat::Md).unwrap();
+        assert!(md.starts_with("# Diffr report: c\n"));
+        assert!(md.contains("- **Drives:** 1 (0 connected)"));
+        assert!(md.contains("- **Health:** 1 problem(s) in recent syncs"));
+        assert!(md.contains("| <backup> | /mnt/gone | offline |"));
+        assert!(md.contains("| verification | a\\|b.txt: verification failed |"));
+        assert!(md.contains("No archived versions."));
+
+        let html = build_report(&conn, "c", 10, ReportFormat::Html).unwrap();
+        assert!(html.contains("<td>&lt;backup&gt;</td>"));
+        assert!(!html.contains("<backup>"));
+
+        assert!(build_report(&conn, "missing", 10, ReportFormat::Md).is_err());
+    }
+}
@@ -0,0 +1,536 @@
+use clap::Args;
+use diffr_core::config::DiffrConfig;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:7411")]
+    listen: SocketAddr,
+
+    /// Token clients must authenticate with; a random one is made if not
+    /// given
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Allow listening on an address other than loopback. Anyone who can
+    /// reach it and has the token can run syncs
+    #[arg(long)]
+    allow_remote: bool,
+}
+
+// JSON-RPC 2.0 error codes, plus two of our own.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The command behind the method failed; `data` has its exit code.
+const COMMAND_FAILED: i64 = -32000;
+/// Another client is already syncing that cluster.
+pub const BUSY: i64 = -32001;
+/// The connection hasn't authenticated, or gave the wrong token.
+const UNAUTHORIZED: i64 = -32002;
+
+/// Each method and the params it takes. Results are what the matching
+/// command prints with `--json`.
+const METHODS: &[(&str, &[&str])] = &[
+    ("auth", &["token"]),
+    ("methods", &[]),
+    ("clusters.list", &[]),
+    ("clusters.info", &["cluster"]),
+    ("drives.list", &[]),
+    ("drives.scan", &["removable_only", "include_internal"]),
+    ("status", &["cluster"]),
+    ("history", &["cluster", "limit"]),
+    ("log", &["since", "status", "drive", "limit"]),
+    (
+        "sync",
+        &[
+            "cluster",
+            "dry_run",
+            "verify",
+            "no_deletes",
+            "no_overwrites",
+            "allow_mass_change",
+            "message",
+        ],
+    ),
+];
+
+pub struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+/// Clusters being synced by some connection, so two clients can't sync the
+/// same one at once.
+type Syncing = Mutex<HashSet<String>>;
+
+pub fn run(args: ServeArgs, json: bool) -> anyhow::Result<()> {
+    if !args.listen.ip().is_loopback() && !args.allow_remote {
+        anyhow::bail!(
+            "{} isn't a loopback address; pass --allow-remote to let other machines connect",
+            args.listen
+        );
+    }
+    let listener = TcpListener::bind(args.listen)?;
+    let addr = listener.local_addr()?;
+    let token: Arc<str> = args
+        .token
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string())
+        .into();
+    if json {
+        println!("{}", json!({"listen": addr.to_string(), "token": &*token}));
+    } else {
+        println!("Serving JSON-RPC on {} (one request per line). Press Ctrl-C to stop.", addr);
+        println!("Clients authenticate first with the token {}", token);
+    }
+
+    let syncing: Arc<Syncing> = Arc::default();
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("warning: accepting a connection failed: {}", e);
+                continue;
+            }
+        };
+        let (token, syncing) = (Arc::clone(&token), Arc::clone(&syncing));
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &token, &syncing) {
+                tracing::debug!("connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer requests from one client, one per line, in order. The first
+/// must be `auth` with the right token, or the connection is closed.
+fn serve_connection(stream: TcpStream, token: &str, syncing: &Syncing) -> std::io::Result<()> {
+    let writer = Mutex::new(stream.try_clone()?);
+    let mut authenticated = false;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !authenticated {
+            // A web page can make a browser send an HTTP request here; it
+            // gets nothing back.
+            if is_http_request_line(&line) {
+                return Ok(());
+            }
+            let request = serde_json::from_str::<Value>(&line).unwrap_or(Value::Null);
+            let id = request.get("id").cloned().unwrap_or(Value::Null);
+            if !authenticates(&request, token) {
+                let error = RpcError::new(UNAUTHORIZED, "authenticate first with the server's token");
+                return send(&writer, &error_response(id, error));
+            }
+            authenticated = true;
+            send(&writer, &json!({"jsonrpc": "2.0", "id": id, "result": true}))?;
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle(&request, &writer, syncing),
+            Err(e) => Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        if let Some(response) = response {
+            send(&writer, &response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `line` starts an HTTP request, like `POST / HTTP/1.1`.
+fn is_http_request_line(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or_default();
+    let version = words.nth(1).unwrap_or_default();
+    version.starts_with("HTTP/")
+        || ["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE"].contains(&method)
+}
+
+/// Whether `request` is an `auth` call with `token`.
+fn authenticates(request: &Value, token: &str) -> bool {
+    request.get("method").and_then(Value::as_str) == Some("auth")
+        && request
+            .pointer("/params/token")
+            .and_then(Value::as_str)
+            .is_some_and(|presented| tokens_match(presented, token))
+}
+
+/// Compare a presented token with the real one in time that doesn't
+/// depend on where they differ.
+pub fn tokens_match(presented: &str, token: &str) -> bool {
+    let (a, b) = (presented.as_bytes(), token.as_bytes());
+    let differ = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    a.len() == b.len() && differ == 0
+}
+
+fn send(writer: &Mutex<TcpStream>, message: &Value) -> std::io::Result<()> {
+    let mut stream = writer.lock().unwrap_or_else(|e| e.into_inner());
+    writeln!(stream, "{}", message)
+}
+
+/// Run a request and build its response. Notifications, requests without
+/// an `id`, are run but get none.
+fn handle(request: &Value, writer: &Mutex<TcpStream>, syncing: &Syncing) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let result = call(request, id.as_ref(), writer, syncing);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error_response(id, e),
+    })
+}
+
+pub fn error_response(id: Value, error: RpcError) -> Value {
+    let mut body = json!({"code": error.code, "message": error.message});
+    if let Some(data) = error.data {
+        body["data"] = data;
+    }
+    json!({"jsonrpc": "2.0", "id": id, "error": body})
+}
+
+fn call(
+    request: &Value,
+    id: Option<&Value>,
+    writer: &Mutex<TcpStream>,
+    syncing: &Syncing,
+) -> Result<Value, RpcError> {
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_REQUEST, "request has no method"))?;
+    let empty = Map::new();
+    let params = match request.get("params") {
+        None | Some(Value::Null) => &empty,
+        Some(Value::Object(params)) => params,
+        Some(_) => return Err(RpcError::new(INVALID_PARAMS, "params must be an object")),
+    };
+    let Some((_, accepted)) = METHODS.iter().find(|(name, _)| *name == method) else {
+        return Err(RpcError::new(METHOD_NOT_FOUND, format!("no method '{}'", method)));
+    };
+    if let Some(unknown) = params.keys().find(|k| !accepted.contains(&k.as_str())) {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("'{}' takes no param '{}' (takes: {})", method, unknown, accepted.join(", ")),
+        ));
+    }
+
+    if method == "auth" {
+        return Ok(Value::Bool(true));
+    }
+    if method == "methods" {
+        let list: Vec<Value> = METHODS
+            .iter()
+            .map(|(name, params)| json!({"method": name, "params": params}))
+            .collect();
+        return Ok(Value::Array(list));
+    }
+
+    let args = command_args(method, params)?;
+    let _guard = match method {
+        "sync" => Some(SyncGuard::acquire(syncing, &args[1])?),
+        _ => None,
+    };
+    run_command(&args, id, writer)
+}
+
+/// The diffr command line a method runs.
+fn command_args(method: &str, params: &Map<String, Value>) -> Result<Vec<String>, RpcError> {
+    let words = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let mut args = match method {
+        "clusters.list" => words("cluster list"),
+        "clusters.info" => words("cluster info"),
+        "drives.list" => words("drive list"),
+        "drives.scan" => words("drive scan"),
+        "status" => words("status"),
+        "history" => words("history"),
+        "log" => words("log"),
+        "sync" => words("sync"),
+        _ => return Err(RpcError::new(METHOD_NOT_FOUND, format!("no method '{}'", method))),
+    };
+
+    // Positional first, then options as `--name=value` so no value can be
+    // taken for a flag.
+    let required = matches!(method, "clusters.info" | "history" | "sync");
+    match (string_param(params, "cluster")?, required) {
+        (Some(cluster), _) if cluster.starts_with('-') => {
+            return Err(RpcError::new(INVALID_PARAMS, format!("bad cluster name '{}'", cluster)))
+        }
+        (Some(cluster), _) => args.push(cluster),
+        (None, true) => return Err(RpcError::new(INVALID_PARAMS, "missing param 'cluster'")),
+        (None, false) => {}
+    }
+    for (name, value) in params {
+        if name == "cluster" {
+            continue;
+        }
+        let flag = format!("--{}", name.replace('_', "-"));
+        match value {
+            Value::Bool(true) => args.push(flag),
+            Value::Bool(false) | Value::Null => {}
+            _ => {
+                let value = string_param(params, name)?.unwrap_or_default();
+                args.push(format!("{}={}", flag, value));
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// A string or number param as text.
+fn string_param(params: &Map<String, Value>, name: &str) -> Result<Option<String>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(Value::Number(n)) => Ok(Some(n.to_string())),
+        Some(_) => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("param '{}' must be a string or number", name),
+        )),
+    }
+}
+
+/// Marks a cluster as syncing until dropped.
+struct SyncGuard<'a> {
+    syncing: &'a Syncing,
+    cluster: String,
+}
+
+impl<'a> SyncGuard<'a> {
+    fn acquire(syncing: &'a Syncing, cluster: &str) -> Result<Self, RpcError> {
+        let mut running = syncing.lock().unwrap_or_else(|e| e.into_inner());
+        if !running.insert(cluster.to_string()) {
+            return Err(RpcError::new(
+                BUSY,
+                format!("cluster '{}' is already being synced", cluster),
+            ));
+        }
+        Ok(SyncGuard {
+            syncing,
+            cluster: cluster.to_string(),
+        })
+    }
+}
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        let mut running = self.syncing.lock().unwrap_or_else(|e| e.into_inner());
+        running.remove(&self.cluster);
+    }
+}
+
+/// Run a method's command, sending what it logs to the client as
+/// `progress` notifications while it runs: `{"id", "event"}` for its
+/// progress events, `{"id", "message"}` for anything else.
+fn run_command(args: &[String], id: Option<&Value>, writer: &Mutex<TcpStream>) -> Result<Value, RpcError> {
+    let id = id.cloned().unwrap_or(Value::Null);
+    let output = run_json(args, |line| {
+        let params = match progress_event(line) {
+            Some(event) => json!({"id": id, "event": event}),
+            None => json!({"id": id, "message": line}),
+        };
+        let note = json!({
+            "jsonrpc": "2.0",
+            "method": "progress",
+            "params": params,
+        });
+        // A client that hung up still gets the command finished.
+        let _ = send(writer, &note);
+    })
+    .map_err(|e| RpcError::new(COMMAND_FAILED, e.to_string()))?;
+    if output.success {
+        return Ok(output.result);
+    }
+    Err(RpcError {
+        code: COMMAND_FAILED,
+        message: output.error_message(args),
+        data: Some(json!({"exit_code": output.exit_code, "result": output.result})),
+    })
+}
+
+/// What a `diffr <args> --json` run printed.
+pub struct JsonOutput {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    /// Its output as JSON: one value, an array when it printed one per
+    /// line, or a string when it isn't JSON.
+    pub result: Value,
+    /// The error it exited with, or else the last line other than a
+    /// progress event it wrote to stderr.
+    pub last_error: Option<String>,
+}
+
+impl JsonOutput {
+    /// Why the command failed, going by the last thing it logged.
+    pub fn error_message(&self, args: &[String]) -> String {
+        match &self.last_error {
+            Some(line) => line.trim_start_matches("Error: ").to_string(),
+            None => format!("diffr {} failed", args.join(" ")),
+        }
+    }
+}
+
+/// Run `diffr <args> --json` as a child process, passing each line it
+/// writes to stderr to `on_stderr` as it arrives.
+pub fn run_json(args: &[String], on_stderr: impl FnMut(&str) + Send) -> std::io::Result<JsonOutput> {
+    let mut command = Command::new(std::env::current_exe()?);
+    if let Some(profile) = DiffrConfig::active_profile() {
+        command.arg("--profile").arg(profile);
+    }
+    let mut child = command
+        .args(args)
+        .arg("--json")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (Some(mut stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(std::io::Error::other("command output unavailable"));
+    };
+
+    let mut on_stderr = on_stderr;
+    let (output, last_error) = std::thread::scope(|scope| {
+        let forward = scope.spawn(move || {
+            let (mut error, mut last) = (None, None);
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                on_stderr(&line);
+                // What main returned; a backtrace may follow it.
+                if line.starts_with("Error: ") {
+                    error = Some(line.clone());
+                }
+                if progress_event(&line).is_none() {
+                    last = Some(line);
+                }
+            }
+            error.or(last)
+        });
+        let mut output = String::new();
+        let read = stdout.read_to_string(&mut output);
+        (read.map(|_| output), forward.join().unwrap_or(None))
+    });
+    let output = output?;
+    let status = child.wait()?;
+
+    Ok(JsonOutput {
+        success: status.success(),
+        exit_code: status.code(),
+        result: parse_output(&output).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("diffr {} printed output that isn't JSON: {}", args.join(" "), e),
+            )
+        })?,
+        last_error,
+    })
+}
+
+/// A line of stderr as the progress event it reports, if it's one.
+fn progress_event(line: &str) -> Option<Value> {
+    if !line.starts_with("{\"event\"") {
+        return None;
+    }
+    serde_json::from_str(line).ok()
+}
+
+/// A command's `--json` output: one value, or an array of one per line.
+/// Anything else is a bug in the command and fails rather than reaching
+/// clients as a string.
+fn parse_output(output: &str) -> serde_json::Result<Value> {
+    let text = output.trim();
+    if text.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(text).or_else(|_| text.lines().map(serde_json::from_str::<Value>).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve one connection on a fresh port, and connect to it.
+    fn connect() -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = serve_connection(stream, "secret", &Syncing::default());
+        });
+        TcpStream::connect(addr).unwrap()
+    }
+
+    fn exchange(stream: &mut TcpStream, line: &str) -> Option<Value> {
+        writeln!(stream, "{}", line).unwrap();
+        let mut response = String::new();
+        BufReader::new(stream.try_clone().unwrap()).read_line(&mut response).unwrap();
+        serde_json::from_str(&response).ok()
+    }
+
+    fn closed(stream: &mut TcpStream) -> bool {
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).is_ok() && rest.is_empty()
+    }
+
+    #[test]
+    fn test_http_request_gets_nothing() {
+        let mut stream = connect();
+        assert_eq!(exchange(&mut stream, "POST / HTTP/1.1\r"), None);
+        assert!(closed(&mut stream));
+        assert!(is_http_request_line("GET /?x=1 HTTP/1.0"));
+        assert!(!is_http_request_line(r#"{"method": "auth"}"#));
+    }
+
+    #[test]
+    fn test_requests_need_the_token() {
+        let mut stream = connect();
+        let response = exchange(&mut stream, r#"{"jsonrpc": "2.0", "id": 1, "method": "methods"}"#).unwrap();
+        assert_eq!(response["error"]["code"], UNAUTHORIZED);
+        assert!(closed(&mut stream));
+
+        let mut stream = connect();
+        let wrong = r#"{"jsonrpc": "2.0", "id": 1, "method": "auth", "params": {"token": "secreT"}}"#;
+        assert_eq!(exchange(&mut stream, wrong).unwrap()["error"]["code"], UNAUTHORIZED);
+        assert!(closed(&mut stream));
+
+        let mut stream = connect();
+        let right = r#"{"jsonrpc": "2.0", "id": 1, "method": "auth", "params": {"token": "secret"}}"#;
+        assert_eq!(exchange(&mut stream, right).unwrap()["result"], true);
+        let methods = exchange(&mut stream, r#"{"jsonrpc": "2.0", "id": 2, "method": "methods"}"#).unwrap();
+        assert!(methods["result"].as_array().is_some_and(|m| !m.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_output_takes_json_only() {
+        assert_eq!(parse_output("").unwrap(), Value::Null);
+        assert_eq!(parse_output(r#"{"a": "E:\\"}"#).unwrap(), json!({"a": "E:\\"}));
+        assert_eq!(parse_output("{\"a\": 1}\n{\"a\": 2}\n").unwrap(), json!([{"a": 1}, {"a": 2}]));
+        assert!(parse_output(r#"{"mount": "E:\"}"#).is_err());
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secre", "secret"));
+        assert!(!tokens_match("secrex", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
@@ -0,0 +1,403 @@
+//! Running `diffr daemon run` in the background for the logged-in user: a
+//! systemd user unit on Linux, a launchd agent on macOS and a Task
+//! Scheduler task on Windows. Each runs as the user, so the daemon sees the
+//! same `~/.diffr` as the user's own commands.
+
+use diffr_core::config::DiffrConfig;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const UNIT: &str = "diffr.service";
+const LABEL: &str = "com.github.crussella0129.diffr";
+const TASK: &str = "Diffr daemon";
+
+/// The service manager that runs the daemon on this platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    Systemd,
+    Launchd,
+    TaskScheduler,
+}
+
+impl Manager {
+    pub fn here() -> anyhow::Result<Self> {
+        if cfg!(target_os = "linux") {
+            Ok(Manager::Systemd)
+        } else if cfg!(target_os = "macos") {
+            Ok(Manager::Launchd)
+        } else if cfg!(windows) {
+            Ok(Manager::TaskScheduler)
+        } else {
+            anyhow::bail!(
+                "diffr can't install a service on {}; start `diffr daemon run` from your own startup scripts",
+                std::env::consts::OS
+            )
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Manager::Systemd => "systemd",
+            Manager::Launchd => "launchd",
+            Manager::TaskScheduler => "task-scheduler",
+        }
+    }
+
+    /// What the daemon is installed as, for messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Manager::Systemd => "a systemd user unit",
+            Manager::Launchd => "a launchd agent",
+            Manager::TaskScheduler => "a scheduled task",
+        }
+    }
+
+    /// Where the generated unit, plist or task definition is written.
+    pub fn file(self) -> anyhow::Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("could not determine home directory"))?;
+        Ok(match self {
+            Manager::Systemd => dirs::config_dir()
+                .unwrap_or_else(|| home.join(".config"))
+                .join("systemd")
+                .join("user")
+                .join(UNIT),
+            Manager::Launchd => home
+                .join("Library")
+                .join("LaunchAgents")
+                .join(format!("{}.plist", LABEL)),
+            Manager::TaskScheduler => DiffrConfig::home_dir()?.join("daemon-task.xml"),
+        })
+    }
+
+    /// Write the service for `command` (the program and its arguments), set
+    /// it to start at login and (re)start it now. Returns the file written.
+    pub fn install(self, command: &[String]) -> anyhow::Result<PathBuf> {
+        let file = self.file()?;
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if let Err(e) = self.register(command, &file) {
+            // Don't leave a definition behind that was never registered.
+            let _ = std::fs::remove_file(&file);
+            return Err(e);
+        }
+        Ok(file)
+    }
+
+    fn register(self, command: &[String], file: &Path) -> anyhow::Result<()> {
+        match self {
+            Manager::Systemd => {
+                std::fs::write(file, systemd_unit(command))?;
+                systemctl(&["daemon-reload"])?;
+                systemctl(&["enable", UNIT])?;
+                systemctl(&["restart", UNIT])
+            }
+            Manager::Launchd => {
+                let log = DiffrConfig::home_dir()?.join("logs").join("daemon.log");
+                if let Some(parent) = log.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // Reinstalling: unload the old definition first.
+                let _ = tool("launchctl", &["bootout", &launchd_target()]);
+                std::fs::write(file, launchd_plist(command, &log.display().to_string()))?;
+                tool("launchctl", &["bootstrap", &launchd_domain(), &file.display().to_string()])
+            }
+            Manager::TaskScheduler => {
+                // Task Scheduler reads UTF-16 task files most reliably.
+                let xml = task_xml(&windows_user(), command);
+                let mut bytes = vec![0xFF, 0xFE];
+                bytes.extend(xml.encode_utf16().flat_map(|u| u.to_le_bytes()));
+                std::fs::write(file, bytes)?;
+                let _ = tool("schtasks", &["/End", "/TN", TASK]);
+                tool("schtasks", &["/Create", "/F", "/TN", TASK, "/XML", &file.display().to_string()])?;
+                tool("schtasks", &["/Run", "/TN", TASK])
+            }
+        }
+    }
+
+    pub fn start(self) -> anyhow::Result<()> {
+        self.installed()?;
+        match self {
+            Manager::Systemd => systemctl(&["start", UNIT]),
+            Manager::Launchd => tool("launchctl", &["kickstart", &launchd_target()]),
+            Manager::TaskScheduler => tool("schtasks", &["/Run", "/TN", TASK]),
+        }
+    }
+
+    /// Stop the daemon until it's started again or the user next logs in.
+    pub fn stop(self) -> anyhow::Result<()> {
+        self.installed()?;
+        match self {
+            Manager::Systemd => systemctl(&["stop", UNIT]),
+            // It exits cleanly on SIGTERM, so launchd doesn't restart it.
+            Manager::Launchd => tool("launchctl", &["kill", "SIGTERM", &launchd_target()]),
+            Manager::TaskScheduler => tool("schtasks", &["/End", "/TN", TASK]),
+        }
+    }
+
+    /// Stop the daemon, unregister it and remove its file. Returns the file
+    /// removed.
+    pub fn uninstall(self) -> anyhow::Result<PathBuf> {
+        let file = self.installed()?;
+        match self {
+            Manager::Systemd => systemctl(&["disable", "--now", UNIT])?,
+            // Fails only if it isn't loaded, which is fine.
+            Manager::Launchd => {
+                let _ = tool("launchctl", &["bootout", &launchd_target()]);
+            }
+            Manager::TaskScheduler => {
+                let _ = tool("schtasks", &["/End", "/TN", TASK]);
+                tool("schtasks", &["/Delete", "/F", "/TN", TASK])?;
+            }
+        }
+        std::fs::remove_file(&file)?;
+        if self == Manager::Systemd {
+            systemctl(&["daemon-reload"])?;
+        }
+        Ok(file)
+    }
+
+    fn installed(self) -> anyhow::Result<PathBuf> {
+        let file = self.file()?;
+        if !file.exists() {
+            anyhow::bail!("the daemon isn't installed; install it with `diffr daemon install`");
+        }
+        Ok(file)
+    }
+}
+
+/// Run a service manager's command, failing with what it printed if it
+/// fails.
+fn tool(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("couldn't run {}: {}", program, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let said = if stderr.trim().is_empty() { stdout } else { stderr };
+        anyhow::bail!("`{} {}` failed: {}", program, args.join(" "), said.trim());
+    }
+    Ok(())
+}
+
+fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    tool("systemctl", &[&["--user"], args].concat())
+}
+
+fn launchd_domain() -> String {
+    #[cfg(unix)]
+    // SAFETY: getuid has no preconditions and can't fail.
+    let uid = unsafe { libc::getuid() };
+    #[cfg(not(unix))]
+    let uid = 0;
+    format!("gui/{}", uid)
+}
+
+fn launchd_target() -> String {
+    format!("{}/{}", launchd_domain(), LABEL)
+}
+
+fn windows_user() -> String {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    match std::env::var("USERDOMAIN") {
+        Ok(domain) if !domain.is_empty() => format!("{}\\{}", domain, user),
+        _ => user,
+    }
+}
+
+/// The systemd user unit that runs `command`. Its output goes to the
+/// journal.
+pub fn systemd_unit(command: &[String]) -> String {
+    let exec: Vec<String> = command
+        .iter()
+        .map(|arg| {
+            let escaped = arg
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('%', "%%")
+                .replace('$', "$$");
+            format!("\"{}\"", escaped)
+        })
+        .collect();
+    format!(
+        "[Unit]\n\
+         Description=Diffr scheduled syncs\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=60\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exec.join(" ")
+    )
+}
+
+/// The launchd agent that runs `command` at login, restarted if it fails,
+/// with its output appended to `log`.
+pub fn launchd_plist(command: &[String], log: &str) -> String {
+    let args: String = command
+        .iter()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{args}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ThrottleInterval</key>
+    <integer>60</integer>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LABEL,
+        args = args,
+        log = xml_escape(log)
+    )
+}
+
+/// The Task Scheduler task that runs `command` when `user` logs in, with
+/// no time limit and whatever the power source.
+pub fn task_xml(user: &str, command: &[String]) -> String {
+    let (program, args) = command.split_first().map(|(p, a)| (p.as_str(), a)).unwrap_or(("", &[]));
+    let args: Vec<String> = args.iter().map(|arg| windows_quote(arg)).collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>Diffr scheduled syncs</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <LogonTrigger>
+      <Enabled>true</Enabled>
+      <UserId>{user}</UserId>
+    </LogonTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{user}</UserId>
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <RestartOnFailure>
+      <Interval>PT1M</Interval>
+      <Count>3</Count>
+    </RestartOnFailure>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{program}</Command>
+      <Arguments>{args}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        user = xml_escape(user),
+        program = xml_escape(program),
+        args = xml_escape(&args.join(" "))
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Quote `arg` for a Windows command line, as the C runtime splits it.
+fn windows_quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // Backslashes only escape when they come before a quote.
+        let escapes = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> Vec<String> {
+        ["/opt/my tools/diffr", "daemon", "run", "photos & \"raw\"", "--every", "3600s"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_systemd_unit_quotes_each_argument() {
+        let unit = systemd_unit(&command());
+        assert!(unit.contains(
+            "ExecStart=\"/opt/my tools/diffr\" \"daemon\" \"run\" \"photos & \\\"raw\\\"\" \"--every\" \"3600s\"\n"
+        ));
+        assert!(unit.contains("WantedBy=default.target"));
+        // Specifiers and variables are taken literally.
+        let unit = systemd_unit(&["diffr".into(), "100%$HOME".into()]);
+        assert!(unit.contains("\"100%%$$HOME\""));
+    }
+
+    #[test]
+    fn test_launchd_plist_escapes_arguments_and_log() {
+        let plist = launchd_plist(&command(), "/Users/a&b/.diffr/logs/daemon.log");
+        assert!(plist.contains("<string>/opt/my tools/diffr</string>"));
+        assert!(plist.contains("<string>photos &amp; &quot;raw&quot;</string>"));
+        assert!(plist.contains("<string>/Users/a&amp;b/.diffr/logs/daemon.log</string>"));
+        assert!(plist.contains(&format!("<string>{}</string>", LABEL)));
+    }
+
+    #[test]
+    fn test_task_runs_the_command_line_at_logon() {
+        let xml = task_xml("HOST\\me", &command());
+        assert!(xml.contains("<UserId>HOST\\me</UserId>"));
+        assert!(xml.contains("<Command>/opt/my tools/diffr</Command>"));
+        assert!(xml.contains(
+            "<Arguments>daemon run &quot;photos &amp; \\&quot;raw\\&quot;&quot; --every 3600s</Arguments>"
+        ));
+        assert_eq!(windows_quote(""), "\"\"");
+        assert_eq!(windows_quote(r"C:\a b\"), r#""C:\a b\\""#);
+        assert_eq!(windows_quote(r#"a\"b"#), r#""a\\\"b""#);
+    }
+}
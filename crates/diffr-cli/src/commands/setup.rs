@@ -0,0 +1,215 @@
+use clap::Args;
+use diffr_core::config::{DiffrConfig, RepoConfig};
+use diffr_core::models::cluster::{Cluster, ConflictStrategy, Topology};
+use diffr_core::models::drive::{Drive, DriveIdentity};
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+use std::path::PathBuf;
+
+use super::init::{init_repo, simplified_canonicalize};
+use super::{confirm, prompt};
+
+#[derive(Args)]
+pub struct SetupArgs {}
+
+/// Interactive walk through config init, drive selection, repo init, cluster
+/// creation and a first dry run.
+pub fn run(_args: SetupArgs, json: bool) -> anyhow::Result<()> {
+    if json {
+        anyhow::bail!("setup is interactive and does not support --json");
+    }
+
+    DiffrConfig::init()?;
+    let config = DiffrConfig::load()?;
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    println!("Step 1/5: Scanning for drives...");
+    let discovery = super::drive::discovery()?;
+    let discovered = discovery.discover_drives().unwrap_or_default();
+    if discovered.is_empty() {
+        println!("  No drives detected. You can still enter directory paths.");
+    }
+    for (i, d) in discovered.iter().enumerate() {
+        println!(
+            "  [{}] {:<30} {:<20} {:<15} {:>10}",
+            i + 1,
+            d.identity.identity_string(),
+            d.mount_point.display(),
+            d.label.as_deref().unwrap_or("-"),
+            d.total_bytes.map(format_bytes).unwrap_or_else(|| "-".to_string())
+        );
+    }
+
+    println!("\nStep 2/5: Choose what to sync");
+    let mut chosen: Vec<Drive> = loop {
+        let answer = prompt(
+            "Drive numbers or directory paths, comma-separated (at least 2)",
+            "",
+        )?;
+        match pick_drives(&answer, &discovered) {
+            Ok(drives) if drives.len() >= 2 => break drives,
+            Ok(_) => println!("  Pick at least two."),
+            Err(e) => println!("  {}", e),
+        }
+    };
+
+    for drive in &mut chosen {
+        if drive.sync_root.is_none() {
+            let default = drive.mount_point.display().to_string();
+            let root = prompt(
+                &format!("Sync root on {}", drive.identity.identity_string()),
+                &default,
+            )?;
+            drive.sync_root = Some(PathBuf::from(root));
+        }
+        let root = drive.sync_root.clone().unwrap_or_default();
+        let root = if RepoConfig::load(&root)?.is_some() {
+            simplified_canonicalize(&root)?
+        } else {
+            let root = init_repo(&root, None)?;
+            println!("  Initialized diffr repo at {}", root.display());
+            root
+        };
+        drive.sync_root = Some(root);
+    }
+
+    println!("\nStep 3/5: Create a cluster");
+    let name = loop {
+        let name = prompt("Cluster name", "my-cluster")?;
+        if ops::get_cluster_by_name(&conn, &name)?.is_some() {
+            println!("  Cluster '{}' already exists.", name);
+        } else {
+            break name;
+        }
+    };
+    let topology: Topology = ask_parsed(
+        "Topology (mesh, primary-replica)",
+        &config.default_topology.to_string(),
+    )?;
+    let conflict: ConflictStrategy = ask_parsed(
+        "Conflict strategy (newest-wins, keep-both, interactive)",
+        &config.default_conflict_strategy.to_string(),
+    )?;
+
+    if topology == Topology::PrimaryReplica {
+        for (i, d) in chosen.iter().enumerate() {
+            println!("  [{}] {}", i + 1, d.effective_root().display());
+        }
+        let primary = loop {
+            let answer = prompt("Which one is the primary", "1")?;
+            match answer.parse::<usize>() {
+                Ok(n) if (1..=chosen.len()).contains(&n) => break n - 1,
+                _ => println!("  Enter a number from 1 to {}.", chosen.len()),
+            }
+        };
+        chosen[primary].is_primary = true;
+    }
+
+    println!("\nStep 4/5: Saving");
+    let cluster = save_cluster(&conn, Cluster::new(name.clone(), topology, conflict), &mut chosen)?;
+    for drive in &chosen {
+        println!(
+            "  Added {} -> {}",
+            drive.identity.identity_string(),
+            drive.effective_root().display()
+        );
+    }
+    println!("  Created cluster '{}' ({})", cluster.name, cluster.id);
+
+    println!("\nStep 5/5: First sync");
+    if confirm("Run a dry run now to preview the first sync?")? {
+        super::sync::run(super::sync::SyncArgs::dry_run(name.clone()), false)?;
+    }
+    println!("\nDone. Run `diffr sync {}` when you're ready.", name);
+    Ok(())
+}
+
+/// Record `cluster` with `drives` as its members. Drives already registered
+/// keep their IDs and are moved into the cluster; the rest are added.
+fn save_cluster(conn: &rusqlite::Connection, cluster: Cluster, drives: &mut [Drive]) -> anyhow::Result<Cluster> {
+    let tx = conn.unchecked_transaction()?;
+    ops::insert_cluster(&tx, &cluster)?;
+    for drive in drives.iter_mut() {
+        drive.cluster_id = Some(cluster.id.clone());
+        match ops::get_drive_by_identity(&tx, &drive.identity)? {
+            Some(existing) => {
+                drive.id = existing.id;
+                drive.created_at = existing.created_at;
+                ops::update_drive(&tx, drive)?;
+            }
+            None => ops::insert_drive(&tx, drive)?,
+        }
+    }
+    tx.commit()?;
+    Ok(cluster)
+}
+
+/// Turn the user's answer into drives: numbers pick discovered drives, and
+/// anything else is a directory treated as its own (synthetic) drive.
+fn pick_drives(answer: &str, discovered: &[Drive]) -> anyhow::Result<Vec<Drive>> {
+    let mut picked: Vec<Drive> = Vec::new();
+    for token in answer.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let drive = match token.parse::<usize>() {
+            Ok(n) => discovered
+                .get(n.wrapping_sub(1))
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no drive numbered {}", n))?,
+            Err(_) => {
+                std::fs::create_dir_all(token)?;
+                let path = simplified_canonicalize(std::path::Path::new(token))?;
+                let mut drive = Drive::new(DriveIdentity::new_synthetic(), path.clone());
+                drive.sync_root = Some(path);
+                drive
+            }
+        };
+        // Directories get a fresh identity each time, so compare roots too.
+        let same = |d: &Drive| d.identity == drive.identity || (d.sync_root.is_some() && d.sync_root == drive.sync_root);
+        if picked.iter().any(same) {
+            anyhow::bail!("{} was picked twice", token);
+        }
+        picked.push(drive);
+    }
+    Ok(picked)
+}
+
+fn ask_parsed<T: std::str::FromStr<Err = String>>(question: &str, default: &str) -> anyhow::Result<T> {
+    loop {
+        match prompt(question, default)?.parse() {
+            Ok(value) => return Ok(value),
+            Err(e) => println!("  {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_picked_directories_become_a_cluster() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let b_new = b.path().join("new");
+        let answer = format!("{}, {}", a.path().display(), b_new.display());
+
+        let mut chosen = pick_drives(&answer, &[]).unwrap();
+        assert_eq!(chosen.len(), 2);
+        assert!(b_new.is_dir());
+        assert!(pick_drives("1", &[]).is_err());
+        assert!(pick_drives(&format!("{0},{0}", a.path().display()), &[]).is_err());
+
+        let conn = diffr_db::open_memory_db().unwrap();
+        // One of them was registered before setup ran.
+        ops::insert_drive(&conn, &chosen[0]).unwrap();
+        let registered = chosen[0].id.clone();
+        chosen[0].id = Default::default();
+
+        let cluster = Cluster::new("photos".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        let cluster = save_cluster(&conn, cluster, &mut chosen).unwrap();
+        assert_eq!(chosen[0].id, registered);
+        let members = ops::list_drives_for_cluster(&conn, &cluster.id).unwrap();
+        assert_eq!(members.len(), 2);
+        assert!(ops::get_cluster_by_name(&conn, "photos").unwrap().is_some());
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::Utc;
+use clap::Subcommand;
+use diffr_core::config::DiffrConfig;
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::sync_state::PathSkip;
+use diffr_db::ops;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand)]
+pub enum SkipAction {
+    /// Leave a path, and everything under it, out of a cluster's syncs for a while
+    Add {
+        /// Cluster name
+        cluster: String,
+        /// Path relative to the sync root, or a path on one of the cluster's drives
+        path: PathBuf,
+        /// How long to skip it, such as 12h or 7d
+        #[arg(long = "for", value_parser = super::parse_age)]
+        duration: chrono::Duration,
+    },
+    /// Show the paths syncs are skipping and when each skip runs out
+    List {
+        /// Only this cluster's skips
+        cluster: Option<String>,
+    },
+    /// Sync a skipped path again before its skip runs out
+    Clear {
+        /// Cluster name
+        cluster: String,
+        /// The path to clear (default: every skip in the cluster)
+        path: Option<PathBuf>,
+    },
+}
+
+impl SkipAction {
+    pub fn writes(&self) -> bool {
+        !matches!(self, SkipAction::List { .. })
+    }
+}
+
+pub fn run(action: SkipAction, json: bool) -> anyhow::Result<()> {
+    let conn = diffr_db::open_db(&DiffrConfig::db_path()?)?;
+
+    match action {
+        SkipAction::Add {
+            cluster,
+            path,
+            duration,
+        } => {
+            let cluster = find_cluster(&conn, &cluster)?;
+            let rel_path = relative_to_cluster(&conn, &cluster, &path)?;
+            let now = Utc::now();
+            let skip = PathSkip {
+                cluster_id: cluster.id.clone(),
+                rel_path,
+                added_at: now,
+                expires_at: now + duration,
+            };
+            ops::add_skip(&conn, &skip)?;
+            if json {
+                println!(
+                    "{{\"cluster\": \"{}\", \"path\": \"{}\", \"expires_at\": \"{}\"}}",
+                    cluster.name,
+                    skip.rel_path.display(),
+                    skip.expires_at.to_rfc3339()
+                );
+            } else {
+                println!(
+                    "Skipping {} in '{}' until {}",
+                    skip.rel_path.display(),
+                    cluster.name,
+                    skip.expires_at.format("%Y-%m-%d %H:%M UTC")
+                );
+            }
+        }
+        SkipAction::List { cluster } => {
+            let clusters = match cluster {
+                Some(name) => vec![find_cluster(&conn, &name)?],
+                None => ops::list_clusters(&conn)?,
+            };
+            let now = Utc::now();
+            let mut skips = Vec::new();
+            for cluster in &clusters {
+                for skip in ops::list_skips(&conn, &cluster.id, now)? {
+                    skips.push((cluster.name.as_str(), skip));
+                }
+            }
+
+            if json {
+                let rows: Vec<String> = skips
+                    .iter()
+                    .map(|(cluster, s)| {
+                        format!(
+                            "{{\"cluster\": \"{}\", \"path\": \"{}\", \"added_at\": \"{}\", \"expires_at\": \"{}\"}}",
+                            cluster,
+                            s.rel_path.display(),
+                            s.added_at.to_rfc3339(),
+                            s.expires_at.to_rfc3339()
+                        )
+                    })
+                    .collect();
+                println!("[{}]", rows.join(", "));
+            } else if skips.is_empty() {
+                println!("Nothing is being skipped.");
+            } else {
+                println!("{:<16} {:<40} {:<20}", "CLUSTER", "PATH", "UNTIL");
+                for (cluster, s) in &skips {
+                    println!(
+                        "{:<16} {:<40} {:<20}",
+                        cluster,
+                        s.rel_path.display(),
+                        s.expires_at.format("%Y-%m-%d %H:%M UTC")
+                    );
+                }
+            }
+        }
+        SkipAction::Clear { cluster, path } => {
+            let cluster = find_cluster(&conn, &cluster)?;
+            let rel_path = path
+                .map(|p| relative_to_cluster(&conn, &cluster, &p))
+                .transpose()?;
+            let removed = ops::remove_skips(&conn, &cluster.id, rel_path.as_deref())?;
+            if let (Some(path), 0) = (&rel_path, removed) {
+                anyhow::bail!("{} isn't skipped in '{}'", path.display(), cluster.name);
+            }
+            if json {
+                println!("{{\"removed\": {}}}", removed);
+            } else {
+                println!("Cleared {} skips in '{}'", removed, cluster.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find_cluster(conn: &Connection, name: &str) -> anyhow::Result<Cluster> {
+    ops::get_cluster_by_name(conn, name)?.ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", name))
+}
+
+/// `path` relative to the sync root: as given if it's relative, or with the
+/// root of whichever of the cluster's drives it's on taken off.
+fn relative_to_cluster(conn: &Connection, cluster: &Cluster, path: &Path) -> anyhow::Result<PathBuf> {
+    if path.is_relative() {
+        let rel: PathBuf = path.components().collect();
+        if rel.as_os_str().is_empty() || rel.starts_with("..") {
+            anyhow::bail!("{} isn't a path under the sync root", path.display());
+        }
+        return Ok(rel);
+    }
+    let path = super::init::simplified_canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    ops::list_drives_for_cluster(conn, &cluster.id)?
+        .iter()
+        .find_map(|d| path.strip_prefix(d.effective_root()).ok().map(Path::to_path_buf))
+        .filter(|rel| !rel.as_os_str().is_empty())
+        .ok_or_else(|| anyhow::anyhow!("{} isn't under any of '{}''s drives", path.display(), cluster.name))
+}
@@ -1,11 +1,36 @@
 use clap::Args;
 use diffr_core::config::DiffrConfig;
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::cluster::ClusterId;
+use diffr_core::models::sync_state::{SyncPlan, SyncStatus, Throughput};
+use diffr_core::units::{format_bytes, format_duration};
 use diffr_db::ops;
+use serde_json::json;
+use diffr_sync::estimate::estimate_duration;
+use std::time::Duration;
 
 #[derive(Args)]
 pub struct StatusArgs {
     /// Cluster name (shows all clusters if omitted)
     cluster: Option<String>,
+
+    /// Exit non-zero if a cluster is stale, a drive is missing, or the last sync failed
+    #[arg(long)]
+    check: bool,
+
+    /// With --check: how long since the last sync that didn't fail counts as stale,
+    /// e.g. 12h or 7d (default: stale_after_hours in config.toml, or 24h)
+    #[arg(long, value_parser = super::parse_age, requires = "check")]
+    max_age: Option<chrono::Duration>,
+}
+
+/// A finding from `status --check`.
+struct Problem {
+    cluster: String,
+    kind: &'static str,
+    detail: String,
+    /// Critical problems exit 2, warnings exit 1.
+    critical: bool,
 }
 
 pub fn run(args: StatusArgs, json: bool) -> anyhow::Result<()> {
@@ -21,19 +46,36 @@ pub fn run(args: StatusArgs, json: bool) -> anyhow::Result<()> {
         None => ops::list_clusters(&conn)?,
     };
 
+    // Record which drives are connected, and where, before reporting.
+    super::drive::refresh_registered(&conn, &mut ops::list_all_drives(&conn)?)?;
+    if args.check {
+        let config = DiffrConfig::load()?;
+        let max_age = args.max_age.unwrap_or_else(|| {
+            chrono::Duration::hours(config.stale_after_hours.unwrap_or(24) as i64)
+        });
+        return match check(&conn, &clusters, max_age, json)? {
+            0 => Ok(()),
+            code => Err(super::Exit(code).into()),
+        };
+    }
+    let throughput = ops::list_throughput(&conn)?;
+
     for cluster in &clusters {
         let drives = ops::list_drives_for_cluster(&conn, &cluster.id)?;
         let history = ops::list_sync_history(&conn, &cluster.id, 1)?;
         let last_sync = history.first();
+        let pending = pending_plan(&conn, &cluster.id, last_sync.map(|s| s.finished_at), &throughput)?;
 
         if json {
             println!(
-                "{{\"cluster\": \"{}\", \"drives\": {}, \"last_sync\": {}}}",
-                cluster.name,
-                drives.len(),
-                last_sync
-                    .map(|s| format!("\"{}\"", s.finished_at))
-                    .unwrap_or_else(|| "null".to_string())
+                "{}",
+                json!({
+                    "cluster": cluster.name,
+                    "drives": drives.len(),
+                    "last_sync": last_sync.map(|s| s.finished_at.to_string()),
+                    "pending_ops": pending.as_ref().map(|(plan, _)| plan.op_count()),
+                    "estimate_secs": pending.as_ref().and_then(|(_, estimate)| *estimate).map(|d| d.as_secs()),
+                })
             );
         } else {
             println!("Cluster: {}", cluster.name);
@@ -57,19 +99,43 @@ pub fn run(args: StatusArgs, json: bool) -> anyhow::Result<()> {
                     d.mount_point.display(),
                     sync_info,
                 );
+                if let Some(quota) = d.quota_bytes {
+                    let used = d.used_bytes.unwrap_or(0);
+                    println!(
+                        "      quota: {} of {} used ({:.0}%)",
+                        format_bytes(used),
+                        format_bytes(quota),
+                        used as f64 / quota.max(1) as f64 * 100.0
+                    );
+                }
             }
             match last_sync {
                 Some(s) => {
                     println!("  Last sync: {} ({})", s.finished_at, s.status);
                     println!(
-                        "    {} files, {} bytes transferred",
-                        s.files_synced, s.bytes_transferred
+                        "    {} files, {} bytes transferred in {}",
+                        s.files_synced,
+                        s.bytes_transferred,
+                        format_duration((s.finished_at - s.started_at).to_std().unwrap_or_default())
                     );
                 }
                 None => {
                     println!("  Last sync: never");
                 }
             }
+
+            if let Some((plan, estimate)) = &pending {
+                let estimate = estimate
+                    .map(|d| format!(", about {}", format_duration(d)))
+                    .unwrap_or_default();
+                println!(
+                    "  Pending:   {} operations, {} bytes{} (dry run at {})",
+                    plan.op_count(),
+                    plan.total_bytes,
+                    estimate,
+                    plan.created_at
+                );
+            }
             println!();
         }
     }
@@ -80,3 +146,170 @@ pub fn run(args: StatusArgs, json: bool) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// The plan of a dry run since the cluster's last sync, which the next
+/// sync would run much of, and how long it should take at the throughput
+/// measured between the drives.
+fn pending_plan(
+    conn: &rusqlite::Connection,
+    cluster_id: &ClusterId,
+    last_synced: Option<chrono::DateTime<chrono::Utc>>,
+    throughput: &[Throughput],
+) -> anyhow::Result<Option<(SyncPlan, Option<Duration>)>> {
+    let Some(plan) = ops::get_last_plan(conn, cluster_id)? else {
+        return Ok(None);
+    };
+    // A sync saves its plan as it starts, so a plan older than the last
+    // sync's end is one that has run.
+    if plan.operations.is_empty() || last_synced.is_some_and(|t| plan.created_at <= t) {
+        return Ok(None);
+    }
+    let estimate = estimate_duration(&plan, throughput).filter(|_| plan.total_bytes > 0);
+    Ok(Some((plan, estimate)))
+}
+
+/// Monitoring check, with Nagios-style exit codes: 0 when every cluster is
+/// healthy, 1 when a drive is missing, and 2 when a cluster has gone
+/// `max_age` without a sync that didn't fail, or its last sync failed.
+fn check(
+    conn: &rusqlite::Connection,
+    clusters: &[Cluster],
+    max_age: chrono::Duration,
+    json: bool,
+) -> anyhow::Result<u8> {
+    let now = chrono::Utc::now();
+    let mut problems = Vec::new();
+    for cluster in clusters {
+        let mut problem = |kind, detail: String, critical| {
+            problems.push(Problem {
+                cluster: cluster.name.clone(),
+                kind,
+                detail,
+                critical,
+            })
+        };
+
+        let history = ops::list_sync_history(conn, &cluster.id, u32::MAX)?;
+        if history.first().is_some_and(|s| s.status == SyncStatus::Failed) {
+            problem("failed", "last sync failed".into(), true);
+        }
+        match history.iter().find(|s| s.status != SyncStatus::Failed) {
+            None => problem("stale", "never synced".into(), true),
+            Some(s) if now - s.finished_at > max_age => problem(
+                "stale",
+                format!("last synced {}", s.finished_at.format("%Y-%m-%d %H:%M:%S")),
+                true,
+            ),
+            Some(_) => {}
+        }
+
+        for d in ops::list_drives_for_cluster(conn, &cluster.id)? {
+            if !d.effective_root().exists() {
+                problem(
+                    "missing_drive",
+                    format!("drive {} is not connected", d.identity.identity_string()),
+                    false,
+                );
+            }
+        }
+    }
+
+    let code: u8 = if problems.iter().any(|p| p.critical) {
+        2
+    } else if !problems.is_empty() {
+        1
+    } else {
+        0
+    };
+
+    if json {
+        let items: Vec<_> = problems
+            .iter()
+            .map(|p| {
+                json!({
+                    "cluster": p.cluster,
+                    "kind": p.kind,
+                    "detail": p.detail,
+                    "critical": p.critical,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            json!({
+                "ok": problems.is_empty(),
+                "exit_code": code,
+                "clusters": clusters.len(),
+                "problems": items,
+            })
+        );
+    } else {
+        let label = ["OK", "WARNING", "CRITICAL"][code as usize];
+        if problems.is_empty() {
+            println!("{}: {} clusters healthy", label, clusters.len());
+        } else {
+            println!("{}: {} problems", label, problems.len());
+            for p in &problems {
+                println!("  {}: {}", p.cluster, p.detail);
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::{ConflictStrategy, Topology};
+    use diffr_core::models::drive::DriveId;
+    use diffr_core::models::sync_state::{SyncOp, SyncOpKind};
+
+    #[test]
+    fn test_pending_plan_is_estimated_from_throughput() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a, b) = (DriveId::new(), DriveId::new());
+        let op = SyncOp {
+            id: uuid::Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: "big.iso".into(),
+            source_drive: Some(a.clone()),
+            target_drive: b.clone(),
+            size_bytes: 1000,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(cluster.id.clone(), vec![op]);
+        ops::save_last_plan(&conn, &plan).unwrap();
+        let throughput = [Throughput {
+            source_drive: a,
+            target_drive: b,
+            bytes: 500,
+            seconds: 5.0,
+        }];
+
+        let (pending, estimate) = pending_plan(&conn, &cluster.id, None, &throughput).unwrap().unwrap();
+        assert_eq!(pending.op_count(), 1);
+        assert_eq!(estimate, Some(Duration::from_secs(10)));
+
+        // Once a sync has run since, the plan is done with.
+        let synced = Some(plan.created_at + chrono::Duration::seconds(30));
+        assert!(pending_plan(&conn, &cluster.id, synced, &throughput).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_check_returns_its_exit_code() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let max_age = chrono::Duration::hours(24);
+        assert_eq!(check(&conn, &[], max_age, true).unwrap(), 0);
+
+        // A cluster that has never synced is critical; its name goes into
+        // the JSON escaped.
+        let cluster = Cluster::new("say \"hi\"".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        assert_eq!(check(&conn, &[cluster], max_age, true).unwrap(), 2);
+    }
+}
@@ -1,15 +1,29 @@
 use clap::Args;
-use diffr_core::config::DiffrConfig;
-use diffr_core::models::drive::{Drive, DriveRole};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use diffr_core::cancel::{Cancelled, CancellationToken};
+use diffr_core::config::{DiffrConfig, Durability};
+use diffr_core::models::cluster::Cluster;
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::sync_state::{SkipReason, SkippedFile, SyncOpKind, SyncRecord, SyncStatus};
+use diffr_core::schedule::Schedule;
+use diffr_core::units::format_duration;
 use diffr_db::ops;
-use diffr_scan::scanner::{ScanConfig, scan_directory};
-use diffr_sync::diff::{compute_diff, diff_summary, DiffEntry};
-use diffr_sync::executor::{ExecConfig, execute_plan};
-use diffr_sync::topology::generate_plan;
+use diffr_scan::errors::{permission_hint, ScanErrorKind};
+use diffr_sync::engine::{drive_root_display, kind_counts, SyncEngine, SyncEvent, SyncOptions, SyncOutcome};
+use diffr_sync::filter::{parse_kind, OpFilter};
+use serde_json::{json, Value};
 
-use diffr_core::models::file_entry::FileEntry;
+/// How many errors the report lists before summarizing.
+const SHORT_LIST_LIMIT: usize = 10;
 
-#[derive(Args)]
+/// How often a `--scheduled` sync checks that its schedule still allows it.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Args, Default)]
 pub struct SyncArgs {
     /// Cluster name to sync
     cluster: String,
@@ -25,140 +39,651 @@ pub struct SyncArgs {
     /// Skip archiving before overwrite/delete
     #[arg(long)]
     no_archive: bool,
-}
 
-pub fn run(args: SyncArgs, json: bool) -> anyhow::Result<()> {
-    let db_path = DiffrConfig::db_path()?;
-    let conn = diffr_db::open_db(&db_path)?;
+    /// Include files above the cluster's large_file_threshold
+    #[arg(long)]
+    large_files: bool,
 
-    let cluster = ops::get_cluster_by_name(&conn, &args.cluster)?
-        .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", args.cluster))?;
+    /// Only run these operation kinds: copies, overwrites, deletes, conflicts,
+    /// permissions
+    #[arg(long, value_delimiter = ',', value_parser = parse_kind)]
+    only: Option<Vec<SyncOpKind>>,
 
-    let drives = ops::list_drives_for_cluster(&conn, &cluster.id)?;
-    if drives.len() < 2 {
-        anyhow::bail!(
-            "cluster '{}' needs at least 2 drives to sync (has {})",
-            cluster.name,
-            drives.len()
-        );
-    }
+    /// Only sync these drives: identities, or `tag:<name>` for every drive
+    /// with a tag. Drives outside the cluster are left out.
+    #[arg(long, value_delimiter = ',')]
+    drives: Option<Vec<String>>,
 
-    // Filter to syncable drives (not ArchiveOnly)
-    let sync_drives: Vec<&Drive> = drives
-        .iter()
-        .filter(|d| d.role != DriveRole::ArchiveOnly)
-        .collect();
+    /// Never delete files on any drive
+    #[arg(long)]
+    no_deletes: bool,
+
+    /// Never overwrite existing files (also skips conflict resolution)
+    #[arg(long)]
+    no_overwrites: bool,
+
+    /// Proceed even if the plan exceeds the cluster's mass_change_percent
+    #[arg(long)]
+    allow_mass_change: bool,
+
+    /// Proceed without asking even if a source drive's files changed more
+    /// than the cluster's anomaly_percent since the last sync
+    #[arg(long)]
+    allow_anomaly: bool,
+
+    /// Move deleted files to .diffr/trash instead of removing them
+    #[arg(long)]
+    trash: bool,
+
+    /// List every skipped file and why, not just how many
+    #[arg(long, short)]
+    verbose: bool,
 
-    if sync_drives.len() < 2 {
-        anyhow::bail!("cluster '{}' needs at least 2 syncable drives", cluster.name);
+    /// When to flush copies to disk: none, per_file or periodic (default:
+    /// the config's durability, or per_file on removable drives)
+    #[arg(long)]
+    durability: Option<Durability>,
+
+    /// Copy no faster than this on average, such as 50MB/s. Checked
+    /// between files
+    #[arg(long, value_parser = diffr_core::units::parse_rate)]
+    throttle: Option<u64>,
+
+    /// Abort if scanning produces more than this many errors
+    #[arg(long)]
+    max_scan_errors: Option<usize>,
+
+    /// For files that couldn't be read because the OS protects their folder,
+    /// say how to grant access or leave the folder out
+    #[arg(long)]
+    permission_hints: bool,
+
+    /// Follow symlinks while scanning (default: the cluster's follow_symlinks setting)
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Note to record with this sync in history, e.g. "pre-vacation backup"
+    #[arg(long, short)]
+    message: Option<String>,
+
+    /// Run as a scheduled sync: don't start unless the config's [schedule]
+    /// allows it now, and stop once it no longer does
+    #[arg(long)]
+    scheduled: bool,
+
+    /// Set by `diffr plan diff`: stop once the plan is built and compare it
+    /// with the saved one, listing at most this many new operations.
+    #[arg(skip)]
+    plan_diff: Option<usize>,
+}
+
+impl SyncArgs {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
     }
 
-    if !json {
-        println!(
-            "Syncing cluster '{}' ({} drives)...",
-            cluster.name,
-            sync_drives.len()
-        );
-        if args.dry_run {
-            println!("  [DRY RUN]");
+    /// Arguments for a plain dry run of `cluster`.
+    pub fn dry_run(cluster: String) -> Self {
+        Self {
+            cluster,
+            dry_run: true,
+            ..Default::default()
         }
     }
 
-    // Scan all drives
-    let mut scans: Vec<(usize, Vec<FileEntry>)> = Vec::new();
-    for (idx, drive) in sync_drives.iter().enumerate() {
-        let scan_root = drive.effective_root();
-        if !scan_root.exists() {
-            anyhow::bail!(
-                "sync root does not exist: {} (drive {})",
-                scan_root.display(),
-                drive.identity.identity_string()
-            );
-        }
-        if !json {
-            println!("  Scanning {}...", scan_root.display());
+    /// Arguments for `diffr plan diff` on `cluster`.
+    pub fn plan_diff(cluster: String, limit: usize) -> Self {
+        Self {
+            plan_diff: Some(limit),
+            ..Self::dry_run(cluster)
         }
-        let config = ScanConfig {
-            root: scan_root.to_path_buf(),
-            drive_id: drive.id.clone(),
-            follow_symlinks: false,
-            show_progress: !json,
-        };
-        let result = scan_directory(&config)?;
-        scans.push((idx, result.entries));
     }
+}
 
-    // Compute diffs for each pair
-    let mut plan_diffs: Vec<(&Drive, &Drive, Vec<DiffEntry>)> = Vec::new();
-    for i in 0..scans.len() {
-        for j in (i + 1)..scans.len() {
-            let left_drive = sync_drives[scans[i].0];
-            let right_drive = sync_drives[scans[j].0];
-            let diffs = compute_diff(&scans[i].1, &scans[j].1);
-            let summary = diff_summary(&diffs);
+pub fn run(args: SyncArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+    let config = DiffrConfig::load()?;
 
-            if !json {
-                println!(
-                    "  {} vs {}: {}",
-                    left_drive.effective_root().display(),
-                    right_drive.effective_root().display(),
-                    summary
-                );
+    if args.scheduled {
+        if let Some(reason) = config.schedule.blocks_start(&diffr_discovery::host::host_state()) {
+            if json {
+                println!("{}", json!({"status": "skipped", "reason": reason}));
+            } else {
+                println!("Not syncing '{}' now: {}", args.cluster, reason);
             }
-
-            plan_diffs.push((left_drive, right_drive, diffs));
+            return Ok(());
         }
     }
 
-    let plan = generate_plan(&cluster, &drives, &plan_diffs);
+    let cluster = ops::get_cluster_by_name(&conn, &args.cluster)?
+        .ok_or_else(|| anyhow::anyhow!("cluster '{}' not found", args.cluster))?;
 
+    // Drives are re-resolved so a changed drive letter, mount or adapter
+    // doesn't strand them, and so locked volumes are caught before writing.
+    let mut drives = ops::list_drives_for_cluster(&conn, &cluster.id)?;
+    let refreshed = super::drive::refresh_registered(&conn, &mut drives)?;
     if !json {
-        println!(
-            "\nSync plan: {} operations, {} bytes total",
-            plan.op_count(),
-            plan.total_bytes
-        );
+        for &idx in &refreshed.moved {
+            println!(
+                "  Drive {} is now mounted at {}",
+                drives[idx].identity.identity_string(),
+                drives[idx].mount_point.display()
+            );
+        }
     }
 
-    if plan.operations.is_empty() {
-        if json {
-            println!("{{\"status\": \"up_to_date\"}}");
-        } else {
-            println!("Everything is up to date!");
+    let only_drives = match &args.drives {
+        Some(specs) => {
+            let chosen: Vec<DriveId> = super::drive::resolve_drives(&conn, specs)?
+                .into_iter()
+                .filter(|d| d.cluster_id.as_ref() == Some(&cluster.id))
+                .map(|d| d.id)
+                .collect();
+            if chosen.is_empty() {
+                anyhow::bail!("none of the drives given are in cluster '{}'", cluster.name);
+            }
+            Some(chosen)
         }
-        return Ok(());
-    }
+        None => None,
+    };
 
-    // Execute
-    let exec_config = ExecConfig {
+    let options = SyncOptions {
         dry_run: args.dry_run,
         verify: args.verify,
-        archive: !args.no_archive,
-        show_progress: !json,
+        no_archive: args.no_archive,
+        large_files: args.large_files,
+        filter: OpFilter {
+            only: args.only.clone(),
+            no_deletes: args.no_deletes,
+            no_overwrites: args.no_overwrites,
+        },
+        drives: only_drives,
+        allow_mass_change: args.allow_mass_change,
+        allow_anomaly: args.allow_anomaly,
+        trash: args.trash,
+        durability: args.durability,
+        max_scan_errors: args.max_scan_errors,
+        follow_symlinks: args.follow_symlinks,
+        message: args.message.clone(),
+        host: sysinfo::System::host_name(),
+        diffr_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        plan_only: args.plan_diff.is_some(),
+        progress: crate::progress::for_output(json),
+        cancel: crate::interrupt::token(),
+        max_rate: args.throttle,
+        // Files still being written aren't waited for past the window.
+        deadline: config.schedule.window.filter(|_| args.scheduled).map(|window| {
+            let now = chrono::Local::now().naive_local();
+            chrono::Utc::now() + (window.closes_after(now) - now)
+        }),
+        power: config.power_guard(diffr_discovery::host::power_reading),
     };
+    let stopped = if args.scheduled && !config.schedule.is_unconstrained() {
+        watch_schedule(config.schedule.clone(), options.cancel.clone())
+    } else {
+        Arc::default()
+    };
+    let mut engine = SyncEngine::new(&conn, &config)
+        .on_event(|event| print_event(event, &args, &cluster, &drives, json))
+        .on_confirm(|question| {
+            if json || !std::io::stdin().is_terminal() {
+                return Ok(false);
+            }
+            super::confirm(question)
+        });
+    // Conflicts to ask about are left alone when there's nobody to ask.
+    if !json && !args.dry_run && std::io::stdin().is_terminal() {
+        engine = engine.on_conflict(crate::conflict::prompt_choice);
+    }
+    let outcome = match engine.run(&cluster, &drives, &options) {
+        Err(e) if e.is::<Cancelled>() => {
+            let Some(reason) = stopped.get() else {
+                anyhow::bail!("interrupted before changing anything");
+            };
+            if json {
+                println!("{}", json!({"status": "stopped", "reason": reason}));
+            } else {
+                println!("Stopped before changing anything: {}", reason);
+            }
+            return Ok(());
+        }
+        result => result?,
+    };
+    let SyncOutcome {
+        drives,
+        plan,
+        record,
+        scan_errors,
+        error_kinds,
+        denied,
+        skipped,
+        deferred,
+        held,
+        under_replicated,
+        replication,
+        ..
+    } = outcome;
+    // A window that closes once every operation has run stopped nothing.
+    let stopped = stopped
+        .get()
+        .filter(|_| record.as_ref().is_some_and(|r| r.status == SyncStatus::Cancelled));
 
-    let record = execute_plan(&plan, &drives, &exec_config)?;
+    // `plan diff` stops once the plan is built.
+    if let Some(limit) = args.plan_diff {
+        return super::plan::print_diff(&conn, &cluster, &drives, &plan, limit, json);
+    }
 
-    // Save sync record
-    ops::insert_sync_record(&conn, &record)?;
+    let Some(record) = record else {
+        if json {
+            println!(
+                "{}",
+                json!({
+                    "status": "up_to_date",
+                    "deferred": deferred.len(),
+                    "under_replicated": under_replicated.len(),
+                    "scan_errors": scan_errors.len(),
+                    "scan_error_kinds": kinds_json(&error_kinds),
+                    "skipped": skipped_json(&skipped, &drives),
+                })
+            );
+        } else {
+            if deferred.is_empty() {
+                println!("Everything is up to date!");
+            }
+            print_scan_errors(&scan_errors, &error_kinds, args.verbose);
+            print_permission_hints(&denied, &drives, args.permission_hints);
+            print_skipped(&skipped, &drives, args.verbose);
+        }
+        return Ok(());
+    };
+
+    // A dry run's record isn't kept, so neither is its log.
+    let log_path = if args.dry_run {
+        None
+    } else {
+        match write_sync_log(&cluster, &record, &skipped, &drives) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                eprintln!("Warning: failed to write the sync log: {}", e);
+                None
+            }
+        }
+    };
 
     if json {
         println!(
-            "{{\"status\": \"{}\", \"files_synced\": {}, \"bytes_transferred\": {}, \"errors\": {}}}",
-            record.status, record.files_synced, record.bytes_transferred, record.errors.len()
+            "{}",
+            json!({
+                "status": record.status.to_string(),
+                "stopped": stopped,
+                "files_synced": record.files_synced,
+                "bytes_transferred": record.bytes_transferred,
+                "deferred": deferred.len(),
+                "held": held.len(),
+                "archives_replicated": replication.copies.len(),
+                "under_replicated": under_replicated.len(),
+                "scan_errors": scan_errors.len(),
+                "scan_error_kinds": kinds_json(&error_kinds),
+                "errors": record.errors.len(),
+                "skipped": skipped_json(&skipped, &drives),
+                "log": log_path.as_ref().map(|p| p.display().to_string()),
+            })
         );
     } else {
         println!("\nSync complete:");
         println!("  Status:   {}", record.status);
+        if let Some(reason) = stopped {
+            println!("  Stopped:  {}", reason);
+        }
         println!("  Files:    {}", record.files_synced);
         println!("  Bytes:    {}", record.bytes_transferred);
+        if !replication.copies.is_empty() {
+            println!(
+                "  Archives: {} copied to archive-only drives ({} bytes)",
+                replication.copies.len(),
+                replication.bytes
+            );
+        }
         if !record.errors.is_empty() {
+            // Scan errors come first and can run to thousands of the same
+            // kind; the log has them all.
             println!("  Errors:   {}", record.errors.len());
-            for e in &record.errors {
+            if !scan_errors.is_empty() {
+                println!("    {} while scanning ({})", scan_errors.len(), kind_counts(&error_kinds));
+            }
+            let limit = if args.verbose { usize::MAX } else { SHORT_LIST_LIMIT };
+            for e in record.errors.iter().take(limit) {
                 println!("    - {}", e);
             }
+            if record.errors.len() > limit {
+                println!("    ... and {} more", record.errors.len() - limit);
+            }
+            print_permission_hints(&denied, &drives, args.permission_hints);
+        }
+        if !held.is_empty() {
+            println!("  Still being written, left for the next sync:");
+            for h in &held {
+                println!("    - {}", h.rel_path.display());
+            }
+        }
+        print_skipped(&skipped, &drives, args.verbose);
+        if let Some(path) = &log_path {
+            println!("  Log:      {}", path.display());
         }
     }
 
     Ok(())
 }
+
+/// Cancel a scheduled sync once `schedule` stops allowing it, checking
+/// every [`SCHEDULE_CHECK_INTERVAL`]. The returned cell gets the reason.
+fn watch_schedule(schedule: Schedule, cancel: CancellationToken) -> Arc<OnceLock<String>> {
+    let stopped: Arc<OnceLock<String>> = Arc::default();
+    let theirs = Arc::clone(&stopped);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SCHEDULE_CHECK_INTERVAL);
+        if cancel.is_cancelled() {
+            return;
+        }
+        if let Some(reason) = schedule.blocks_running(&diffr_discovery::host::host_state()) {
+            let _ = theirs.set(reason);
+            cancel.cancel();
+            return;
+        }
+    });
+    stopped
+}
+
+/// Show what the sync is doing as it goes. Warnings go to stderr, so they
+/// appear even with `--json`; the rest is text output only.
+fn print_event(event: SyncEvent<'_>, args: &SyncArgs, cluster: &Cluster, drives: &[Drive], json: bool) {
+    match event {
+        SyncEvent::Warning(message) => eprintln!("Warning: {}", message),
+        SyncEvent::TooManyScanErrors { errors, denied } => {
+            for e in errors.iter().take(SHORT_LIST_LIMIT) {
+                eprintln!("  {}", e);
+            }
+            if errors.len() > SHORT_LIST_LIMIT {
+                eprintln!("  ... and {} more", errors.len() - SHORT_LIST_LIMIT);
+            }
+            print_permission_hints(denied, drives, args.permission_hints);
+        }
+        _ if json => {}
+        SyncEvent::Started(sync_drives) => {
+            println!(
+                "{} cluster '{}' ({} drives)...",
+                if args.plan_diff.is_some() { "Planning" } else { "Syncing" },
+                cluster.name,
+                sync_drives.len()
+            );
+            if args.dry_run && args.plan_diff.is_none() {
+                println!("  [DRY RUN]");
+            }
+        }
+        SyncEvent::Scanning(drive) => println!("  Scanning {}...", drive.effective_root().display()),
+        SyncEvent::Scanned { result, kinds, .. } => {
+            if !result.errors.is_empty() {
+                println!("    {} scan errors ({})", result.errors.len(), kind_counts(kinds));
+            }
+            if !result.symlink_cycles.is_empty() {
+                println!("    skipped {} symlink cycles", result.symlink_cycles.len());
+            }
+            if !result.nested_repos.is_empty() {
+                println!("    stopped at {} nested repos", result.nested_repos.len());
+            }
+            if result.skipped_hidden + result.skipped_system > 0 {
+                println!(
+                    "    skipped {} hidden and {} system entries",
+                    result.skipped_hidden, result.skipped_system
+                );
+            }
+        }
+        SyncEvent::Diffed { left, right, summary } => println!(
+            "  {} vs {}: {}",
+            left.effective_root().display(),
+            right.effective_root().display(),
+            summary
+        ),
+        SyncEvent::Planned {
+            plan,
+            estimate,
+            filtered,
+            deferred,
+            deferred_bytes,
+            held,
+        } => {
+            let estimate = estimate
+                .map(|d| format!(", about {}", format_duration(d)))
+                .unwrap_or_default();
+            println!(
+                "\nSync plan: {} operations, {} bytes total{}",
+                plan.op_count(),
+                plan.total_bytes,
+                estimate
+            );
+            if filtered > 0 {
+                println!("  Skipped {} operations excluded by filters", filtered);
+            }
+            if !deferred.is_empty() {
+                println!(
+                    "  Deferred {} large files ({} bytes); run again with --large-files",
+                    deferred.len(),
+                    deferred_bytes
+                );
+            }
+            if held > 0 {
+                println!(
+                    "  Holding back {} files modified in the last {}s; retrying after the rest of the sync",
+                    held,
+                    cluster.settings.stability_window.unwrap_or(0)
+                );
+            }
+            // A dry run shows what it would do and why.
+            if args.dry_run && args.plan_diff.is_none() && plan.op_count() > 0 {
+                let limit = if args.verbose { usize::MAX } else { SHORT_LIST_LIMIT };
+                println!();
+                for op in plan.operations.iter().take(limit) {
+                    super::plan::print_op(&op, drives);
+                }
+                if plan.op_count() > limit {
+                    println!("... and {} more (--verbose lists them all)", plan.op_count() - limit);
+                }
+            }
+        }
+        SyncEvent::Waiting(wait) => {
+            println!("Waiting {} for recently modified files to settle...", format_duration(wait))
+        }
+    }
+}
+
+/// How many files were skipped for each reason, most common first.
+fn skip_counts(skipped: &[SkippedFile]) -> Vec<(SkipReason, usize)> {
+    let mut counts: BTreeMap<SkipReason, usize> = BTreeMap::new();
+    for s in skipped {
+        *counts.entry(s.reason).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    counts
+}
+
+/// Print how many files were skipped and why, and with `verbose`, each one.
+fn print_skipped(skipped: &[SkippedFile], drives: &[Drive], verbose: bool) {
+    if skipped.is_empty() {
+        return;
+    }
+    let counts: Vec<String> = skip_counts(skipped)
+        .iter()
+        .map(|(reason, n)| format!("{} {}", n, reason))
+        .collect();
+    println!("  Skipped:  {} ({})", skipped.len(), counts.join(", "));
+    if verbose {
+        for s in skipped {
+            println!(
+                "    - {}: {}",
+                s.reason,
+                Path::new(&drive_root_display(drives, &s.drive_id)).join(&s.rel_path).display()
+            );
+        }
+    } else {
+        println!("    (pass --verbose to list them)");
+    }
+}
+
+fn skipped_json(skipped: &[SkippedFile], drives: &[Drive]) -> Value {
+    skipped
+        .iter()
+        .map(|s| {
+            json!({
+                "path": s.rel_path.display().to_string(),
+                "drive": drive_root_display(drives, &s.drive_id),
+                "reason": s.reason.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// How many sync logs are kept in `~/.diffr/logs`; older ones are removed
+/// as new ones are written.
+const SYNC_LOGS_KEPT: usize = 200;
+
+/// Write the sync's outcome, errors and skipped files to its log under
+/// `~/.diffr/logs`, returning the log's path.
+fn write_sync_log(
+    cluster: &Cluster,
+    record: &SyncRecord,
+    skipped: &[SkippedFile],
+    drives: &[Drive],
+) -> anyhow::Result<PathBuf> {
+    let path = DiffrConfig::sync_log_path(&record.id)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut log = format!(
+        "sync {} of cluster {}\nstarted:  {}\nfinished: {}\nstatus:   {}\nfiles:    {}\nbytes:    {}\n",
+        record.id,
+        cluster.name,
+        record.started_at.to_rfc3339(),
+        record.finished_at.to_rfc3339(),
+        record.status,
+        record.files_synced,
+        record.bytes_transferred
+    );
+    if !record.errors.is_empty() {
+        log.push_str(&format!("\nerrors ({}):\n", record.errors.len()));
+        for e in &record.errors {
+            log.push_str(&format!("  {}\n", e));
+        }
+    }
+    if !skipped.is_empty() {
+        log.push_str(&format!("\nskipped ({}):\n", skipped.len()));
+        for s in skipped {
+            log.push_str(&format!(
+                "  {}\t{}\n",
+                s.reason,
+                Path::new(&drive_root_display(drives, &s.drive_id)).join(&s.rel_path).display()
+            ));
+        }
+    }
+    std::fs::write(&path, log)?;
+    if let Some(dir) = path.parent() {
+        if let Err(e) = prune_sync_logs(dir, SYNC_LOGS_KEPT) {
+            tracing::warn!("failed to prune old sync logs: {}", e);
+        }
+    }
+    Ok(path)
+}
+
+/// Remove all but the `keep` newest logs in `dir`. Returns how many were
+/// removed.
+fn prune_sync_logs(dir: &Path, keep: usize) -> std::io::Result<usize> {
+    let mut logs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "log") {
+            logs.push((entry.metadata()?.modified()?, path));
+        }
+    }
+    logs.sort_by(|a, b| b.cmp(a));
+    let mut removed = 0;
+    for (_, path) in logs.iter().skip(keep) {
+        std::fs::remove_file(path)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+fn print_scan_errors(errors: &[String], kinds: &BTreeMap<ScanErrorKind, usize>, verbose: bool) {
+    if errors.is_empty() {
+        return;
+    }
+    println!("  Scan errors: {} ({})", errors.len(), kind_counts(kinds));
+    let limit = if verbose { usize::MAX } else { SHORT_LIST_LIMIT };
+    for e in errors.iter().take(limit) {
+        println!("    - {}", e);
+    }
+    if errors.len() > limit {
+        println!("    ... and {} more", errors.len() - limit);
+    }
+}
+
+fn kinds_json(kinds: &BTreeMap<ScanErrorKind, usize>) -> Value {
+    let counts: serde_json::Map<_, _> = kinds.iter().map(|(kind, n)| (kind.to_string(), Value::from(*n))).collect();
+    counts.into()
+}
+
+/// Group files that couldn't be read by the protected folder they're in.
+/// With `show`, say what to do about each folder; otherwise just mention
+/// that there's advice.
+fn print_permission_hints(denied: &[(DriveId, PathBuf)], drives: &[Drive], show: bool) {
+    let mut folders: BTreeMap<PathBuf, (usize, &str)> = BTreeMap::new();
+    for (drive_id, rel_path) in denied {
+        let Some(drive) = drives.iter().find(|d| &d.id == drive_id) else {
+            continue;
+        };
+        if let Some((folder, hint)) = permission_hint(rel_path) {
+            folders.entry(drive.effective_root().join(folder)).or_insert((0, hint)).0 += 1;
+        }
+    }
+    if folders.is_empty() {
+        return;
+    }
+    if !show {
+        println!(
+            "  {} of the unreadable files are in {} folders the OS protects; pass --permission-hints for what to do",
+            folders.values().map(|(n, _)| n).sum::<usize>(),
+            folders.len()
+        );
+        return;
+    }
+    println!("  Protected folders:");
+    for (folder, (n, hint)) in &folders {
+        println!("    {} ({} unreadable): {}", folder.display(), n, hint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_prune_sync_logs_keeps_the_newest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["a.log", "b.log", "c.log"].iter().enumerate() {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "").unwrap();
+            let age = Duration::from_secs(3600 * (3 - i as u64));
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(now - age).unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        assert_eq!(prune_sync_logs(dir.path(), 2).unwrap(), 1);
+        assert!(!dir.path().join("a.log").exists());
+        assert!(dir.path().join("b.log").exists() && dir.path().join("c.log").exists());
+        assert!(dir.path().join("notes.txt").exists());
+    }
+}
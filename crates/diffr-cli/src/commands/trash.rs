@@ -0,0 +1,97 @@
+use clap::Subcommand;
+use diffr_archive::trash;
+use diffr_core::config::DiffrConfig;
+use diffr_core::units::format_bytes;
+use diffr_db::ops;
+
+use super::drive::resolve_drive_or_dir;
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// List files moved to a drive's trash by sync
+    List {
+        /// Drive identity, or a sync root directory
+        drive: String,
+    },
+    /// Permanently delete trashed files
+    Empty {
+        /// Drive identity, or a sync root directory
+        drive: String,
+        /// Only remove items older than the retention policy's trash_days
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+pub fn run(action: TrashAction, json: bool) -> anyhow::Result<()> {
+    match action {
+        TrashAction::List { drive } => {
+            let drive = resolve_drive_or_dir(&drive)?;
+            let items = trash::list_trash(&drive)?;
+
+            if json {
+                let entries: Vec<_> = items
+                    .iter()
+                    .map(|item| {
+                        format!(
+                            "{{\"path\": \"{}\", \"size\": {}, \"trashed_at\": \"{}\"}}",
+                            item.rel_path.display(),
+                            item.size,
+                            item.trashed_at
+                        )
+                    })
+                    .collect();
+                println!("[{}]", entries.join(", "));
+            } else if items.is_empty() {
+                println!("Trash is empty.");
+            } else {
+                println!("{:<40} {:>10} {:<20}", "PATH", "SIZE", "TRASHED");
+                for item in &items {
+                    println!(
+                        "{:<40} {:>10} {:<20}",
+                        item.rel_path.display(),
+                        format_bytes(item.size),
+                        item.trashed_at.format("%Y-%m-%d %H:%M:%S")
+                    );
+                }
+                let total: u64 = items.iter().map(|i| i.size).sum();
+                println!("\n{} items, {}", items.len(), format_bytes(total));
+            }
+            Ok(())
+        }
+        TrashAction::Empty { drive, expired } => {
+            let drive = resolve_drive_or_dir(&drive)?;
+            if drive.read_only {
+                anyhow::bail!("drive is read-only: {}", drive.effective_root().display());
+            }
+            let max_age = if expired {
+                let config = DiffrConfig::load()?;
+                let cluster = match &drive.cluster_id {
+                    Some(id) => ops::get_cluster_by_id(&diffr_db::open_db(&DiffrConfig::db_path()?)?, id)?,
+                    None => None,
+                };
+                match config.retention_for(cluster.as_ref()).trash_days {
+                    Some(days) => Some(days),
+                    None => anyhow::bail!("retention.trash_days is not set; nothing expires"),
+                }
+            } else {
+                None
+            };
+            let result = trash::empty_trash(&drive, max_age)?;
+
+            if json {
+                println!(
+                    "{{\"removed\": {}, \"bytes_freed\": {}}}",
+                    result.items_removed, result.bytes_freed
+                );
+            } else {
+                println!(
+                    "Removed {} trashed files, freed {}",
+                    result.items_removed,
+                    format_bytes(result.bytes_freed)
+                );
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use clap::Args;
+use diffr_core::config::DiffrConfig;
+use diffr_db::ops;
+use diffr_sync::undo::{apply_undo_step, plan_undo, UndoAction};
+use std::collections::HashMap;
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// Sync ID (from `diffr history`), or `last`
+    sync: String,
+
+    /// Only reverse changes made to this drive
+    #[arg(long)]
+    drive: Option<String>,
+
+    /// Show what would be reversed without touching any files
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Reverse a sync from its journal: remove files it copied in and restore
+/// overwritten or deleted files from the archive or trash.
+pub fn run(args: UndoArgs, json: bool) -> anyhow::Result<()> {
+    let db_path = DiffrConfig::db_path()?;
+    let conn = diffr_db::open_db(&db_path)?;
+
+    let record = if args.sync == "last" {
+        ops::get_last_undoable_sync(&conn)?
+            .ok_or_else(|| anyhow::anyhow!("no sync with changes left to undo"))?
+    } else {
+        let id: uuid::Uuid = args
+            .sync
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid sync ID: {}", args.sync))?;
+        ops::get_sync_record(&conn, &id)?
+            .ok_or_else(|| anyhow::anyhow!("sync '{}' not found", args.sync))?
+    };
+
+    let mut journal = ops::list_journal(&conn, &record.id)?;
+    if let Some(identity) = &args.drive {
+        let drive = super::drive::lookup_drive(&conn, identity)?;
+        journal.retain(|e| e.op.target_drive == drive.id);
+    }
+    if journal.is_empty() {
+        anyhow::bail!("sync {} has no journaled changes to undo", record.id);
+    }
+
+    // Archives may have been relocated to drives outside the cluster.
+    let drives = ops::list_all_drives(&conn)?;
+    let mut archives = HashMap::new();
+    for id in journal.iter().filter_map(|e| e.archive_id) {
+        if let Some(entry) = ops::get_archive_by_id(&conn, &id)? {
+            archives.insert(id, entry);
+        }
+    }
+    let steps = plan_undo(&journal, &drives, &archives, record.finished_at);
+
+    if !json {
+        println!(
+            "Undoing sync {} (finished {})",
+            record.id,
+            record.finished_at.format("%Y-%m-%d %H:%M:%S")
+        );
+        if args.dry_run {
+            println!("  [DRY RUN]");
+        }
+    }
+
+    let (mut removed, mut restored) = (0usize, 0usize);
+    let mut unrecoverable = Vec::new();
+    for step in &steps {
+        let outcome = if args.dry_run {
+            match &step.action {
+                UndoAction::Unrecoverable(reason) => Err(anyhow::anyhow!("{}", reason)),
+                _ => Ok(()),
+            }
+        } else {
+            apply_undo_step(&conn, step, &drives)
+        };
+
+        match outcome {
+            Ok(()) => {
+                let verb = match step.action {
+                    UndoAction::Remove => {
+                        removed += 1;
+                        "remove"
+                    }
+                    _ => {
+                        restored += 1;
+                        "restore"
+                    }
+                };
+                if !json {
+                    println!("  {:<8} {}", verb, step.rel_path.display());
+                }
+                if !args.dry_run {
+                    ops::delete_journal_entry(&conn, &step.op_id)?;
+                }
+            }
+            Err(e) => {
+                if !json {
+                    println!("  {:<8} {}: {}", "skip", step.rel_path.display(), e);
+                }
+                unrecoverable.push(format!("{}: {}", step.rel_path.display(), e));
+            }
+        }
+    }
+
+    if json {
+        let items: Vec<_> = unrecoverable.iter().map(|u| format!("\"{}\"", u)).collect();
+        println!(
+            "{{\"sync_id\": \"{}\", \"dry_run\": {}, \"removed\": {}, \"restored\": {}, \"unrecoverable\": [{}]}}",
+            record.id,
+            args.dry_run,
+            removed,
+            restored,
+            items.join(", ")
+        );
+    } else {
+        println!(
+            "\n{} removed, {} restored, {} unrecoverable",
+            removed,
+            restored,
+            unrecoverable.len()
+        );
+    }
+
+    Ok(())
+}
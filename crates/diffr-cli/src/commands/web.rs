@@ -0,0 +1,399 @@
+use clap::Args;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Take, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::serve::{run_json, tokens_match};
+
+/// The dashboard page; it has no data of its own and fetches everything
+/// from the API below.
+const DASHBOARD: &str = include_str!("../../assets/dashboard.html");
+
+/// The cookie the dashboard's token is kept in once the page is opened.
+const TOKEN_COOKIE: &str = "diffr_token";
+
+/// The most of a request line and headers read before giving up on it.
+const MAX_REQUEST_HEAD: u64 = 16 * 1024;
+
+#[derive(Args)]
+pub struct WebArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:7412")]
+    listen: SocketAddr,
+
+    /// Token clients must send; a random one is made if not given
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Let the dashboard start dry runs (which scan drives and save the plan,
+    /// but change no files)
+    #[arg(long)]
+    allow_dry_run: bool,
+}
+
+struct Server {
+    token: String,
+    allow_dry_run: bool,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    cookie: Option<String>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The token sent as a bearer token or in the dashboard's cookie. One in
+    /// the query string isn't taken: URLs end up in logs and history.
+    fn token(&self) -> Option<&str> {
+        let bearer = self.authorization.as_deref().and_then(|h| h.strip_prefix("Bearer "));
+        let cookie = self.cookie.as_deref().and_then(|c| {
+            c.split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(name, _)| *name == TOKEN_COOKIE)
+                .map(|(_, value)| value)
+        });
+        bearer.or(cookie)
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<String>,
+    body: String,
+}
+
+impl Response {
+    fn text(status: u16, content_type: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    fn json(status: u16, body: &Value) -> Self {
+        Self::text(status, "application/json", &body.to_string())
+    }
+}
+
+pub fn run(args: WebArgs, json: bool) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(args.listen)?;
+    let addr = listener.local_addr()?;
+    let token = args
+        .token
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let url = format!("http://{}/?token={}", addr, token);
+    if json {
+        println!(
+            "{{\"listen\": \"{}\", \"url\": \"{}\", \"allow_dry_run\": {}}}",
+            addr, url, args.allow_dry_run
+        );
+    } else {
+        println!("Dashboard at {}", url);
+        if !args.allow_dry_run {
+            println!("Read-only; pass --allow-dry-run to start dry runs from the page.");
+        }
+        println!("Press Ctrl-C to stop.");
+    }
+
+    let server = Arc::new(Server {
+        token,
+        allow_dry_run: args.allow_dry_run,
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("accepting a connection failed: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &server) {
+                tracing::debug!("connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer one request and close the connection.
+fn serve_connection(mut stream: TcpStream, server: &Server) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let response = match read_request(&stream) {
+        Ok(Some(request)) => handle(&request, server),
+        Ok(None) => Response::text(400, "text/plain", "bad request"),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Response::text(400, "text/plain", &e.to_string()),
+        Err(e) => return Err(e),
+    };
+    respond(&mut stream, &response)
+}
+
+fn handle(request: &Request, server: &Server) -> Response {
+    if request.method == "GET" && request.path == "/" {
+        // The URL printed at startup carries the token. Swap it for a
+        // cookie and send the browser on to the bare page, so the token
+        // doesn't stay in the address bar or history.
+        if let Some(token) = request.param("token") {
+            if !tokens_match(token, &server.token) {
+                return Response::text(401, "text/plain", "wrong token");
+            }
+            let mut response = Response::text(303, "text/plain", "");
+            response.headers.push("Location: /".to_string());
+            response.headers.push(format!(
+                "Set-Cookie: {}={}; Path=/; HttpOnly; SameSite=Strict",
+                TOKEN_COOKIE, server.token
+            ));
+            return response;
+        }
+        return Response::text(200, "text/html; charset=utf-8", DASHBOARD);
+    }
+    if !request.path.starts_with("/api/") {
+        return Response::text(404, "text/plain", "not found");
+    }
+    if !request.token().is_some_and(|presented| tokens_match(presented, &server.token)) {
+        return Response::json(401, &json!({"error": "missing or wrong token"}));
+    }
+
+    let (status, body) = api(request, server);
+    Response::json(status, &body)
+}
+
+/// Route an API request to the command behind it.
+fn api(request: &Request, server: &Server) -> (u16, Value) {
+    let cluster = request.param("cluster").map(String::from);
+    let args: Vec<String> = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/api/config") => {
+            return (200, json!({"allow_dry_run": server.allow_dry_run}));
+        }
+        ("GET", "/api/clusters") => vec!["cluster".into(), "list".into()],
+        ("GET", "/api/status") => vec!["status".into()],
+        ("GET", "/api/drives") => vec!["drive".into(), "list".into()],
+        ("GET", "/api/archives") => vec!["archive".into(), "stats".into()],
+        ("GET", "/api/history") => {
+            let limit = request.param("limit").unwrap_or("30");
+            match cluster {
+                Some(c) if !c.starts_with('-') => {
+                    vec!["history".into(), c, format!("--limit={}", limit)]
+                }
+                _ => return (400, json!({"error": "a cluster is needed"})),
+            }
+        }
+        ("POST", "/api/dry-run") => {
+            if !server.allow_dry_run {
+                return (403, json!({"error": "dry runs are off; start diffr web with --allow-dry-run"}));
+            }
+            match cluster {
+                Some(c) if !c.starts_with('-') => vec!["sync".into(), c, "--dry-run".into()],
+                _ => return (400, json!({"error": "a cluster is needed"})),
+            }
+        }
+        _ => return (404, json!({"error": "not found"})),
+    };
+
+    match run_json(&args, |_| {}) {
+        Ok(output) if output.success => (200, output.result),
+        Ok(output) => (500, json!({"error": output.error_message(&args)})),
+        Err(e) => (500, json!({"error": e.to_string()})),
+    }
+}
+
+/// Read the request line and headers; any body is ignored. A head over
+/// `MAX_REQUEST_HEAD` fails with `InvalidData`.
+fn read_request(stream: impl Read) -> std::io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD));
+    let mut line = String::new();
+    read_head_line(&mut reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+            .collect(),
+        authorization: None,
+        cookie: None,
+    };
+    loop {
+        let mut header = String::new();
+        if read_head_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                request.authorization = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("cookie") {
+                request.cookie = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok(Some(request))
+}
+
+/// Read one line of the head, failing if the size cap cut it off.
+fn read_head_line(reader: &mut BufReader<Take<impl Read>>, line: &mut String) -> std::io::Result<usize> {
+    let n = reader.read_line(line)?;
+    if !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("request head is over {} bytes", MAX_REQUEST_HEAD),
+        ));
+    }
+    Ok(n)
+}
+
+/// Decode `%XX` escapes and `+` in a query string component.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn respond(stream: &mut TcpStream, response: &Response) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        303 => "See Other",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    );
+    for header in &response.headers {
+        head.push_str(header);
+        head.push_str("\r\n");
+    }
+    write!(stream, "{}\r\n{}", head, response.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        Server {
+            token: "secret".to_string(),
+            allow_dry_run: false,
+        }
+    }
+
+    fn get(target: &str) -> Request {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            authorization: None,
+            cookie: None,
+        }
+    }
+
+    #[test]
+    fn test_api_takes_the_token_from_a_header_or_cookie_only() {
+        let server = server();
+        assert_eq!(handle(&get("/api/config"), &server).status, 401);
+        assert_eq!(handle(&get("/api/config?token=secret"), &server).status, 401);
+
+        let mut request = get("/api/config");
+        request.authorization = Some("Bearer secret".to_string());
+        assert_eq!(handle(&request, &server).status, 200);
+        request.authorization = Some("Bearer secreT".to_string());
+        assert_eq!(handle(&request, &server).status, 401);
+
+        let mut request = get("/api/config");
+        request.cookie = Some(format!("theme=dark; {}=secret", TOKEN_COOKIE));
+        let response = handle(&request, &server);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"allow_dry_run":false}"#);
+    }
+
+    #[test]
+    fn test_dashboard_trades_the_url_token_for_a_cookie() {
+        let server = server();
+        let response = handle(&get("/?token=secret"), &server);
+        assert_eq!(response.status, 303);
+        assert!(response.headers.contains(&"Location: /".to_string()));
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.starts_with("Set-Cookie: diffr_token=secret;") && h.contains("HttpOnly")));
+
+        assert_eq!(handle(&get("/?token=guess"), &server).status, 401);
+        let response = handle(&get("/"), &server);
+        assert_eq!(response.status, 200);
+        assert!(response.headers.is_empty());
+    }
+
+    #[test]
+    fn test_read_request_rejects_an_oversized_head() {
+        let request = read_request(&b"GET /api/status HTTP/1.1\r\nCookie: a=b\r\n\r\n"[..])
+            .unwrap()
+            .unwrap();
+        assert_eq!((request.path.as_str(), request.cookie.as_deref()), ("/api/status", Some("a=b")));
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_HEAD as usize));
+        let err = read_request(long_line.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let endless_headers = format!("GET / HTTP/1.1\r\n{}", "X-Pad: 0\r\n".repeat(4096));
+        assert!(read_request(endless_headers.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_dry_runs_need_allowing() {
+        let mut request = get("/api/dry-run?cluster=photos");
+        request.method = "POST".to_string();
+        request.authorization = Some("Bearer secret".to_string());
+        assert_eq!(handle(&request, &server()).status, 403);
+    }
+}
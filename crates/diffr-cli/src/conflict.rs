@@ -0,0 +1,163 @@
+//! Asking at the terminal how to resolve conflicts, for clusters that
+//! resolve them interactively.
+
+use diffr_core::models::drive::Drive;
+use diffr_sync::conflict::ConflictChoice;
+use diffr_sync::diff::DiffEntry;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Ask at the terminal which version to keep. Returns the choice and
+/// whether it should be applied to every remaining conflict.
+pub fn prompt_choice(
+    entry: &DiffEntry,
+    left_drive: &Drive,
+    right_drive: &Drive,
+) -> anyhow::Result<(ConflictChoice, bool)> {
+    let left_path = left_drive.effective_root().join(entry.rel_path);
+    let right_path = right_drive.effective_root().join(entry.rel_path);
+
+    println!("\nConflict: {}", entry.rel_path.display());
+    if let Some(left) = entry.left {
+        println!(
+            "  [L] {} — size: {}, modified: {}",
+            left_drive.effective_root().display(),
+            left.size,
+            left.mtime
+        );
+    }
+    if let Some(right) = entry.right {
+        println!(
+            "  [R] {} — size: {}, modified: {}",
+            right_drive.effective_root().display(),
+            right.size,
+            right.mtime
+        );
+    }
+    if let (Some(left), Some(right)) = (read_text(&left_path), read_text(&right_path)) {
+        for line in preview_diff(&left, &right, PREVIEW_LINES) {
+            println!("    {}", line);
+        }
+    }
+
+    loop {
+        print!("Choose [L]eft, [R]ight, [B]oth, or [E]dit (add ! to apply to all, e.g. L!): ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            println!("\nNo input, defaulting to keep-both");
+            return Ok((ConflictChoice::Both, false));
+        }
+        let input = input.trim().to_lowercase();
+        let (answer, all) = match input.strip_suffix('!') {
+            Some(rest) => (rest.trim(), true),
+            None => (input.as_str(), false),
+        };
+
+        let choice = match answer {
+            "l" | "left" => ConflictChoice::Left,
+            "r" | "right" => ConflictChoice::Right,
+            "b" | "both" => ConflictChoice::Both,
+            "e" | "edit" => {
+                if let Err(e) = open_in_editor(&left_path, &right_path) {
+                    println!("Could not open editor: {}", e);
+                }
+                continue;
+            }
+            _ => {
+                println!("Invalid choice, defaulting to keep-both");
+                ConflictChoice::Both
+            }
+        };
+        return Ok((choice, all));
+    }
+}
+
+/// Most lines shown from each side in a conflict preview.
+const PREVIEW_LINES: usize = 5;
+
+/// Files larger than this aren't previewed.
+const PREVIEW_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Read a file for previewing, or `None` if it's missing, large or binary.
+fn read_text(path: &Path) -> Option<String> {
+    if std::fs::metadata(path).ok()?.len() > PREVIEW_MAX_BYTES {
+        return None;
+    }
+    let text = String::from_utf8(std::fs::read(path).ok()?).ok()?;
+    (!text.contains('\0')).then_some(text)
+}
+
+/// A small line diff: skip the lines both versions share at the start and
+/// end, and show up to `max_lines` of what's left on each side as `-`
+/// (left) and `+` (right) lines, numbered from the first difference.
+fn preview_diff(left: &str, right: &str, max_lines: usize) -> Vec<String> {
+    let left: Vec<&str> = left.lines().collect();
+    let right: Vec<&str> = right.lines().collect();
+
+    let prefix = left.iter().zip(&right).take_while(|(l, r)| l == r).count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(l, r)| l == r)
+        .count();
+    let removed = &left[prefix..left.len() - suffix];
+    let added = &right[prefix..right.len() - suffix];
+    if removed.is_empty() && added.is_empty() {
+        return Vec::new();
+    }
+
+    let mut out = vec![format!("@@ line {} @@", prefix + 1)];
+    for (sign, lines) in [('-', removed), ('+', added)] {
+        for line in lines.iter().take(max_lines) {
+            out.push(format!("{} {}", sign, line));
+        }
+        if lines.len() > max_lines {
+            out.push(format!("{} ... {} more line(s)", sign, lines.len() - max_lines));
+        }
+    }
+    out
+}
+
+/// Open both versions side by side in `$DIFFR_DIFF_TOOL`, falling back to
+/// `$EDITOR` and then `vi`, and wait for it to exit.
+fn open_in_editor(left: &Path, right: &Path) -> anyhow::Result<()> {
+    let tool = std::env::var("DIFFR_DIFF_TOOL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut parts = tool.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no editor configured"))?;
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(left)
+        .arg(right)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_diff() {
+        let left = "a\nb\nc\nd\n";
+        let right = "a\nB\nC\nd\n";
+        assert_eq!(
+            preview_diff(left, right, 5),
+            vec!["@@ line 2 @@", "- b", "- c", "+ B", "+ C"]
+        );
+        assert_eq!(
+            preview_diff(left, "a\nx\n", 1),
+            vec!["@@ line 2 @@", "- b", "- ... 2 more line(s)", "+ x"]
+        );
+        assert!(preview_diff(left, left, 5).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! Ctrl-C and SIGTERM as cancellation. Commands that can stop cleanly take
+//! [`token`], and the first interrupt cancels it instead of killing the
+//! process: scans and syncs stop at their next file and report what they
+//! got done. A second interrupt exits at once.
+
+use diffr_core::cancel::CancellationToken;
+use std::sync::OnceLock;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// The exit status of a process stopped by a second interrupt.
+const INTERRUPTED: i32 = 130;
+
+/// The token interrupts cancel. The first call starts catching them; until
+/// then Ctrl-C kills the process as usual.
+pub fn token() -> CancellationToken {
+    TOKEN
+        .get_or_init(|| {
+            install();
+            CancellationToken::new()
+        })
+        .clone()
+}
+
+/// Cancel the token, returning false if it already was and the process
+/// should exit. Only touches atomics, so it's safe in a signal handler.
+fn interrupted() -> bool {
+    match TOKEN.get() {
+        Some(token) if !token.is_cancelled() => {
+            token.cancel();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn install() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        if !interrupted() {
+            // SAFETY: `_exit` is async-signal-safe, unlike `process::exit`.
+            unsafe { libc::_exit(INTERRUPTED) }
+        }
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only loads and stores atomics, or exits.
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(windows)]
+fn install() {
+    use windows_sys::Win32::Foundation::{BOOL, TRUE};
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    // Called on a thread of its own, not in a signal handler.
+    unsafe extern "system" fn on_ctrl(_: u32) -> BOOL {
+        if !interrupted() {
+            std::process::exit(INTERRUPTED);
+        }
+        TRUE
+    }
+    // SAFETY: registering a handler that only touches atomics or exits.
+    unsafe {
+        SetConsoleCtrlHandler(Some(on_ctrl), TRUE);
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn install() {}
@@ -1,6 +1,11 @@
 mod commands;
+mod conflict;
+mod interrupt;
+mod progress;
 
 use clap::Parser;
+use std::io::IsTerminal;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "diffr", version, about = "Local disk diff & sync management")]
@@ -11,13 +16,45 @@ struct Cli {
     /// Output as JSON instead of human-readable text
     #[arg(long, global = true)]
     json: bool,
+
+    /// Apply a `[profile.<name>]` section of config.toml over the defaults
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Fail instead of waiting when another command that changes drives or
+    /// the database is running
+    #[arg(long, global = true)]
+    no_wait: bool,
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => match e.downcast_ref::<commands::Exit>() {
+            Some(exit) => ExitCode::from(exit.0),
+            None => {
+                eprintln!("Error: {:?}", e);
+                ExitCode::FAILURE
+            }
+        },
+    }
 }
 
-fn main() -> anyhow::Result<()> {
+fn try_main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
         .init();
 
     let cli = Cli::parse();
+    if let Some(name) = &cli.profile {
+        diffr_core::config::DiffrConfig::activate_profile(name);
+        // Fail on an unknown profile before doing any work.
+        diffr_core::config::DiffrConfig::load()?;
+    }
+    let _lock = match cli.command.writes() {
+        true => Some(commands::lock(!cli.no_wait)?),
+        false => None,
+    };
     commands::run(cli.command, cli.json)
 }
@@ -0,0 +1,116 @@
+use diffr_core::progress::{JsonProgress, Progress, ProgressEvent, ProgressSink};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Mutex;
+
+/// How a command reports progress: as JSON lines on stderr under `--json`,
+/// which `diffr serve` passes on to its clients, and as progress bars
+/// otherwise.
+pub fn for_output(json: bool) -> Progress {
+    if json {
+        Progress::new(JsonProgress::stderr())
+    } else {
+        Progress::new(IndicatifProgress::default())
+    }
+}
+
+/// Shows a spinner while scanning and a bar while hashing or running a
+/// plan.
+#[derive(Default)]
+pub struct IndicatifProgress {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl ProgressSink for IndicatifProgress {
+    fn event(&self, event: &ProgressEvent<'_>) {
+        let mut bar = self.bar.lock().unwrap_or_else(|e| e.into_inner());
+        match *event {
+            ProgressEvent::ScanStarted { .. } => {
+                let pb = ProgressBar::new_spinner();
+                pb.set_style(
+                    ProgressStyle::default_spinner()
+                        .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                        .unwrap(),
+                );
+                pb.set_message("Scanning files...");
+                *bar = Some(pb);
+            }
+            ProgressEvent::ScanProgress { files, dirs } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_message(format!("{} files, {} dirs scanned", files, dirs));
+                    pb.tick();
+                }
+            }
+            ProgressEvent::ScanFinished { files, dirs, bytes } => {
+                if let Some(pb) = bar.take() {
+                    pb.finish_with_message(format!("Scanned {} files, {} dirs ({} bytes)", files, dirs, bytes));
+                }
+            }
+            ProgressEvent::HashStarted { files } => {
+                let pb = ProgressBar::new(files);
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files hashed ({eta})")
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                *bar = Some(pb);
+            }
+            ProgressEvent::HashProgress { done, .. } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_position(done);
+                }
+            }
+            ProgressEvent::HashFinished { .. } => {
+                if let Some(pb) = bar.take() {
+                    pb.finish_with_message("Hashing complete");
+                }
+            }
+            // A plan with nothing in it gets no bar.
+            ProgressEvent::ExecStarted { ops } if ops > 0 => {
+                let pb = ProgressBar::new(ops);
+                pb.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                *bar = Some(pb);
+            }
+            ProgressEvent::ExecStarted { .. } => {}
+            ProgressEvent::OpStarted { op, .. } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_message(format!("{}", op.rel_path.display()));
+                }
+            }
+            ProgressEvent::OpBytes { op, done, total } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_message(format!(
+                        "archiving {} ({}%)",
+                        op.rel_path.display(),
+                        done * 100 / total.max(1)
+                    ));
+                }
+            }
+            ProgressEvent::OpFinished { .. } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.inc(1);
+                }
+            }
+            ProgressEvent::ExecPaused { reason } => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_message(format!("paused: {}", reason));
+                }
+            }
+            ProgressEvent::ExecResumed => {
+                if let Some(pb) = bar.as_ref() {
+                    pb.set_message("");
+                }
+            }
+            ProgressEvent::ExecFinished { .. } => {
+                if let Some(pb) = bar.take() {
+                    pb.finish_with_message("Sync complete");
+                }
+            }
+        }
+    }
+}
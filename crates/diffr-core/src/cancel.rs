@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often [`CancellationToken::sleep`] checks whether it's been
+/// cancelled.
+const SLEEP_POLL: Duration = Duration::from_millis(250);
+
+/// Asks a long-running scan, hash or sync to stop. Clones share one flag,
+/// so the caller keeps a clone and cancels it from another thread. Work
+/// checks it between files, never in the middle of one, and returns what
+/// it got done marked as cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sleep for `duration`, waking early if cancelled. Returns whether it
+    /// was.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            std::thread::sleep(left.min(SLEEP_POLL));
+        }
+        true
+    }
+
+    /// `Err(Cancelled)` once cancelled, for stopping with `?`.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error for work stopped by a [`CancellationToken`]. Callers can tell
+/// it from a failure with `anyhow::Error::is::<Cancelled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep_wakes_when_cancelled() {
+        let token = CancellationToken::new();
+        assert!(!token.sleep(Duration::from_millis(10)));
+
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        assert!(token.sleep(Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
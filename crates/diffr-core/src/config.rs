@@ -1,9 +1,15 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use crate::error::DiffrError;
 use crate::models::archive::RetentionPolicy;
-use crate::models::cluster::{ConflictStrategy, Topology};
+use crate::models::cluster::{Cluster, ClusterSettings, ConflictStrategy, Topology};
+use crate::models::drive::DriveId;
+use crate::power::{BatteryPolicy, PowerGuard, PowerReading};
+use crate::schedule::{Schedule, SyncWindow};
 
 /// Top-level Diffr configuration, stored at `~/.diffr/config.toml`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,9 +30,126 @@ pub struct DiffrConfig {
     #[serde(default)]
     pub hash_by_default: bool,
 
-    /// Whether to verify with SHA-256 after sync.
+    /// Whether to verify copies by hashing both ends after sync.
     #[serde(default)]
     pub verify_after_sync: bool,
+
+    /// Hash used to verify copies.
+    #[serde(default)]
+    pub verify_hash: HashAlgorithm,
+
+    /// Whether verifying a copy reads the destination back to hash it.
+    /// Without it, only the source is hashed as it's copied and the copy
+    /// is checked for length.
+    #[serde(default = "default_true")]
+    pub verify_readback: bool,
+
+    /// When copies are flushed to disk. Unset means `per_file` on
+    /// removable drives and `none` elsewhere.
+    #[serde(default)]
+    pub durability: Option<Durability>,
+
+    /// Whether sync archives files before overwriting or deleting them.
+    /// `diffr sync --no-archive` turns it off for one run.
+    #[serde(default = "default_true")]
+    pub archive_on_change: bool,
+
+    /// Whether deletes move files to `.diffr/trash` instead of archiving
+    /// and removing them.
+    #[serde(default)]
+    pub delete_to_trash: bool,
+
+    /// Drive discovery backend, by registered name. Unset means the
+    /// built-in backend for this OS.
+    #[serde(default)]
+    pub discovery_backend: Option<String>,
+
+    /// How long a cluster can go without a successful sync before
+    /// `diffr status --check` reports it as stale. Unset means 24 hours.
+    #[serde(default)]
+    pub stale_after_hours: Option<u32>,
+
+    /// What syncs, copies and clones do while the machine runs on battery.
+    #[serde(default)]
+    pub on_battery: BatteryPolicy,
+
+    /// Pause syncs, copies and clones while the CPU is hotter than this,
+    /// in °C, where its temperature can be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpu_temp: Option<u32>,
+
+    /// Run syncs, copies, clones and the hashing in scans at about half
+    /// speed while the CPU is hotter than this, in °C.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throttle_cpu_temp: Option<u32>,
+
+    /// When `diffr sync --scheduled` may run.
+    #[serde(default, skip_serializing_if = "Schedule::is_unconstrained")]
+    pub schedule: Schedule,
+
+    /// Commands that merge the two versions of a conflicting file, by
+    /// extension. Each is run with both versions' paths and prints the
+    /// merged file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub merge: BTreeMap<String, String>,
+
+    /// Named overrides, chosen with `diffr --profile <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile: BTreeMap<String, Profile>,
+
+    /// Named settings for new clusters, chosen with
+    /// `diffr cluster create --preset <name>`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub preset: BTreeMap<String, ClusterPreset>,
+}
+
+/// Settings a profile can override. Unset fields keep the value from the
+/// top of `config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_after_sync: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_on_change: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_to_trash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_battery: Option<BatteryPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cpu_temp: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttle_cpu_temp: Option<u32>,
+}
+
+/// Settings a new cluster starts with, from `[preset.<name>]` in
+/// `config.toml` or a TOML file with the same keys. Unset topology and
+/// conflict strategy fall back to the config's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterPreset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topology: Option<Topology>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict_strategy: Option<ConflictStrategy>,
+    pub settings: ClusterSettings,
+}
+
+impl ClusterPreset {
+    /// Load a preset shipped as its own TOML file.
+    pub fn load_from(path: &Path) -> Result<Self, DiffrError> {
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| DiffrError::Config {
+            message: format!("{}: {}", path.display(), e.message()),
+        })
+    }
+}
+
+/// The profile `load` applies, set once from `--profile`.
+static ACTIVE_PROFILE: OnceLock<String> = OnceLock::new();
+
+fn default_true() -> bool {
+    true
 }
 
 fn default_topology() -> Topology {
@@ -45,6 +168,20 @@ impl Default for DiffrConfig {
             retention: RetentionPolicy::default(),
             hash_by_default: false,
             verify_after_sync: false,
+            verify_hash: HashAlgorithm::default(),
+            verify_readback: true,
+            durability: None,
+            archive_on_change: true,
+            delete_to_trash: false,
+            discovery_backend: None,
+            stale_after_hours: None,
+            on_battery: BatteryPolicy::default(),
+            max_cpu_temp: None,
+            throttle_cpu_temp: None,
+            schedule: Schedule::default(),
+            merge: BTreeMap::new(),
+            profile: BTreeMap::new(),
+            preset: BTreeMap::new(),
         }
     }
 }
@@ -68,19 +205,165 @@ impl DiffrConfig {
         Ok(Self::home_dir()?.join("diffr.db"))
     }
 
-    /// Load config from the default location, or return defaults if not found.
+    /// Returns the path to the lock file that commands which change drives
+    /// or the database hold while they run.
+    pub fn lock_path() -> Result<PathBuf, DiffrError> {
+        Ok(Self::home_dir()?.join("lock"))
+    }
+
+    /// Where a sync's log, with its errors and skipped files, is written
+    /// (`~/.diffr/logs/<sync id>.log`).
+    pub fn sync_log_path(sync_id: &uuid::Uuid) -> Result<PathBuf, DiffrError> {
+        Ok(Self::home_dir()?.join("logs").join(format!("{}.log", sync_id)))
+    }
+
+    /// Where a drive's metadata is kept when it's stored off the drive
+    /// (`~/.diffr/drives/<id>/`).
+    pub fn external_metadata_dir(drive_id: &DriveId) -> Result<PathBuf, DiffrError> {
+        Ok(Self::home_dir()?.join("drives").join(drive_id.to_string()))
+    }
+
+    /// Where preset files are looked for by name (`~/.diffr/presets/`).
+    pub fn presets_dir() -> Result<PathBuf, DiffrError> {
+        Ok(Self::home_dir()?.join("presets"))
+    }
+
+    /// The preset `name`: a path to a preset file, a `[preset.<name>]`
+    /// section, or `<name>.toml` in [`presets_dir`](Self::presets_dir).
+    pub fn find_preset(&self, name: &str) -> Result<ClusterPreset, DiffrError> {
+        let path = Path::new(name);
+        if path.extension().is_some_and(|e| e == "toml") || path.components().count() > 1 {
+            return ClusterPreset::load_from(path);
+        }
+        if let Some(preset) = self.preset.get(name) {
+            return Ok(preset.clone());
+        }
+        let file = Self::presets_dir()?.join(format!("{}.toml", name));
+        if file.exists() {
+            return ClusterPreset::load_from(&file);
+        }
+        let defined = self.preset_names()?;
+        Err(DiffrError::Config {
+            message: if defined.is_empty() {
+                format!("unknown preset '{}' (none are defined)", name)
+            } else {
+                format!("unknown preset '{}' (defined: {})", name, defined.join(", "))
+            },
+        })
+    }
+
+    /// Every preset that can be named: those in `config.toml`, then the
+    /// files in [`presets_dir`](Self::presets_dir) not shadowed by one.
+    pub fn preset_names(&self) -> Result<Vec<String>, DiffrError> {
+        let mut names: Vec<String> = self.preset.keys().cloned().collect();
+        let dir = Self::presets_dir()?;
+        if dir.is_dir() {
+            let mut files: Vec<String> = std::fs::read_dir(&dir)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+                .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+                .filter(|name| !self.preset.contains_key(name))
+                .collect();
+            files.sort();
+            names.extend(files);
+        }
+        Ok(names)
+    }
+
+    /// The retention policy for `cluster`'s drives: its own if it has one,
+    /// and the config's otherwise.
+    pub fn retention_for<'a>(&'a self, cluster: Option<&'a Cluster>) -> &'a RetentionPolicy {
+        cluster
+            .and_then(|c| c.settings.retention.as_ref())
+            .unwrap_or(&self.retention)
+    }
+
+    /// The battery and heat limits for copying, with `read` to take the
+    /// machine's readings, or `None` when there are none.
+    pub fn power_guard(&self, read: fn() -> PowerReading) -> Option<PowerGuard> {
+        let guard = PowerGuard {
+            on_battery: self.on_battery,
+            max_cpu_temp: self.max_cpu_temp,
+            throttle_cpu_temp: self.throttle_cpu_temp,
+            read,
+        };
+        guard.is_active().then_some(guard)
+    }
+
+    /// Load config from the default location, or return defaults if not
+    /// found, with the active profile applied.
     pub fn load() -> Result<Self, DiffrError> {
         let path = Self::config_path()?;
-        if path.exists() {
-            Self::load_from(&path)
+        let config = if path.exists() {
+            Self::load_from(&path)?
         } else {
-            Ok(Self::default())
+            Self::default()
+        };
+        match ACTIVE_PROFILE.get() {
+            Some(name) => config.with_profile(name),
+            None => Ok(config),
         }
     }
 
-    /// Load config from a specific path.
+    /// Make every later `load` apply the profile `name`. Only the first
+    /// call has any effect.
+    pub fn activate_profile(name: &str) {
+        let _ = ACTIVE_PROFILE.set(name.to_string());
+    }
+
+    /// The profile set by [`activate_profile`](Self::activate_profile), if any.
+    pub fn active_profile() -> Option<&'static str> {
+        ACTIVE_PROFILE.get().map(|s| s.as_str())
+    }
+
+    /// This config with the profile `name` merged over it.
+    pub fn with_profile(mut self, name: &str) -> Result<Self, DiffrError> {
+        let Some(profile) = self.profile.get(name).cloned() else {
+            let defined: Vec<&str> = self.profile.keys().map(|k| k.as_str()).collect();
+            return Err(DiffrError::Config {
+                message: if defined.is_empty() {
+                    format!("unknown profile '{}' (config.toml defines none)", name)
+                } else {
+                    format!("unknown profile '{}' (defined: {})", name, defined.join(", "))
+                },
+            });
+        };
+        if let Some(verify) = profile.verify_after_sync {
+            self.verify_after_sync = verify;
+        }
+        if let Some(archive) = profile.archive_on_change {
+            self.archive_on_change = archive;
+        }
+        if let Some(trash) = profile.delete_to_trash {
+            self.delete_to_trash = trash;
+        }
+        if let Some(policy) = profile.on_battery {
+            self.on_battery = policy;
+        }
+        if let Some(temp) = profile.max_cpu_temp {
+            self.max_cpu_temp = Some(temp);
+        }
+        if let Some(temp) = profile.throttle_cpu_temp {
+            self.throttle_cpu_temp = Some(temp);
+        }
+        Ok(self)
+    }
+
+    /// Load config from a specific path. Invalid values are errors that
+    /// name the key and line; unknown keys are ignored.
     pub fn load_from(path: &Path) -> Result<Self, DiffrError> {
         let content = std::fs::read_to_string(path)?;
+        let errors: Vec<String> = check_config(&content)
+            .iter()
+            .filter(|issue| !issue.warning)
+            .map(|issue| format!("  {}", issue))
+            .collect();
+        if !errors.is_empty() {
+            return Err(DiffrError::Config {
+                message: format!("{} is invalid:\n{}", path.display(), errors.join("\n")),
+            });
+        }
         toml::from_str(&content).map_err(|e| DiffrError::Serialization(e.to_string()))
     }
 
@@ -115,6 +398,423 @@ impl DiffrConfig {
     }
 }
 
+/// A problem found in `config.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Dotted key, e.g. `retention.max_age_days`; `None` for syntax errors.
+    pub key: Option<String>,
+    /// 1-based line number, when it can be found.
+    pub line: Option<usize>,
+    pub message: String,
+    /// Unknown keys are ignored when loading, so they only warn.
+    pub warning: bool,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+type ValueCheck = fn(&toml::Value) -> Result<(), String>;
+
+/// Every key `config.toml` accepts, by table (`""` for the top level).
+const CONFIG_KEYS: &[(&str, &str, ValueCheck)] = &[
+    ("", "default_topology", |v| parse_as::<Topology>(v)),
+    ("", "default_conflict_strategy", |v| parse_as::<ConflictStrategy>(v)),
+    ("", "hash_by_default", expect_bool),
+    ("", "verify_after_sync", expect_bool),
+    ("", "verify_hash", |v| parse_as::<HashAlgorithm>(v)),
+    ("", "verify_readback", expect_bool),
+    ("", "durability", |v| parse_as::<Durability>(v)),
+    ("", "archive_on_change", expect_bool),
+    ("", "delete_to_trash", expect_bool),
+    ("", "discovery_backend", expect_string),
+    ("", "stale_after_hours", |v| expect_integer(v, u32::MAX as i64)),
+    ("", "on_battery", |v| parse_as::<BatteryPolicy>(v)),
+    ("", "max_cpu_temp", |v| expect_integer(v, 150)),
+    ("", "throttle_cpu_temp", |v| expect_integer(v, 150)),
+    ("retention", "max_age_days", |v| expect_integer(v, u32::MAX as i64)),
+    ("retention", "max_versions", |v| expect_integer(v, u32::MAX as i64)),
+    ("retention", "max_total_bytes", expect_bytes),
+    ("retention", "relocate_on_cap", expect_bool),
+    ("retention", "span_on_relocate", expect_bool),
+    ("retention", "trash_days", |v| expect_integer(v, u32::MAX as i64)),
+    ("schedule", "window", |v| parse_as::<SyncWindow>(v)),
+    ("schedule", "require_ac_power", expect_bool),
+    ("schedule", "require_idle", expect_bool),
+    ("profile", "verify_after_sync", expect_bool),
+    ("profile", "archive_on_change", expect_bool),
+    ("profile", "delete_to_trash", expect_bool),
+    ("profile", "on_battery", |v| parse_as::<BatteryPolicy>(v)),
+    ("profile", "max_cpu_temp", |v| expect_integer(v, 150)),
+    ("profile", "throttle_cpu_temp", |v| expect_integer(v, 150)),
+    ("preset", "topology", |v| parse_as::<Topology>(v)),
+    ("preset", "conflict_strategy", |v| parse_as::<ConflictStrategy>(v)),
+    ("preset", "settings", |v| {
+        v.clone()
+            .try_into::<ClusterSettings>()
+            .map(|_| ())
+            .map_err(|e| e.message().to_string())
+    }),
+];
+
+/// Check the text of a `config.toml` against the keys and value types
+/// `DiffrConfig` accepts.
+pub fn check_config(content: &str) -> Vec<ConfigIssue> {
+    let table: toml::Table = match content.parse() {
+        Ok(table) => table,
+        Err(e) => {
+            let e: toml::de::Error = e;
+            return vec![ConfigIssue {
+                key: None,
+                line: e.span().map(|span| line_at(content, span.start)),
+                message: e.message().to_string(),
+                warning: false,
+            }];
+        }
+    };
+
+    let mut issues = Vec::new();
+    check_table(content, "", &table, &mut issues);
+    for section in ["retention", "schedule"] {
+        match table.get(section) {
+            Some(toml::Value::Table(inner)) => check_table(content, section, inner, &mut issues),
+            Some(_) => issues.push(key_issue(content, "", section, "expected a table".into(), false)),
+            None => {}
+        }
+    }
+    // Merge commands are keyed by extension, so any key goes.
+    match table.get("merge") {
+        Some(toml::Value::Table(commands)) => {
+            for (ext, command) in commands {
+                if let Err(message) = expect_string(command) {
+                    issues.push(key_issue(content, "merge", ext, message, false));
+                }
+            }
+        }
+        Some(_) => issues.push(key_issue(content, "", "merge", "expected a table".into(), false)),
+        None => {}
+    }
+    for kind in ["profile", "preset"] {
+        match table.get(kind) {
+            Some(toml::Value::Table(named)) => {
+                for (name, value) in named {
+                    let section = format!("{}.{}", kind, name);
+                    match value {
+                        toml::Value::Table(value) => check_table(content, &section, value, &mut issues),
+                        _ => issues.push(key_issue(content, kind, name, "expected a table".into(), false)),
+                    }
+                }
+            }
+            Some(_) => issues.push(key_issue(content, "", kind, "expected a table".into(), false)),
+            None => {}
+        }
+    }
+    issues.sort_by_key(|issue| issue.line.unwrap_or(usize::MAX));
+    issues
+}
+
+fn check_table(content: &str, section: &str, table: &toml::Table, issues: &mut Vec<ConfigIssue>) {
+    // Every profile accepts the same keys, as does every preset.
+    let kind = match section.split_once('.') {
+        Some((kind @ ("profile" | "preset"), _)) => kind,
+        _ => section,
+    };
+    for (key, value) in table {
+        if section.is_empty() && matches!(key.as_str(), "retention" | "schedule" | "merge" | "profile" | "preset") {
+            continue;
+        }
+        match CONFIG_KEYS.iter().find(|(s, k, _)| *s == kind && k == key) {
+            Some((_, _, check)) => {
+                if let Err(message) = check(value) {
+                    issues.push(key_issue(content, section, key, message, false));
+                }
+            }
+            None => {
+                let known: Vec<&str> = CONFIG_KEYS
+                    .iter()
+                    .filter(|(s, _, _)| *s == kind)
+                    .map(|(_, k, _)| *k)
+                    .collect();
+                let message = format!("unknown key, ignored (known keys: {})", known.join(", "));
+                issues.push(key_issue(content, section, key, message, true));
+            }
+        }
+    }
+}
+
+fn key_issue(content: &str, section: &str, key: &str, message: String, warning: bool) -> ConfigIssue {
+    ConfigIssue {
+        key: Some(if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", section, key)
+        }),
+        line: find_key_line(content, section, key),
+        message,
+        warning,
+    }
+}
+
+/// The 1-based line that sets `key` in `section` (`""` for the top level).
+pub fn find_key_line(content: &str, section: &str, key: &str) -> Option<usize> {
+    let mut current = String::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = header.trim().to_string();
+        } else if current == section
+            && line
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+fn parse_as<T: std::str::FromStr<Err = String>>(value: &toml::Value) -> Result<(), String> {
+    let s = expect_str(value)?;
+    s.parse::<T>().map(|_| ())
+}
+
+fn expect_str(value: &toml::Value) -> Result<&str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected a quoted string, got {}", value.type_str()))
+}
+
+fn expect_string(value: &toml::Value) -> Result<(), String> {
+    expect_str(value).map(|_| ())
+}
+
+fn expect_bool(value: &toml::Value) -> Result<(), String> {
+    match value {
+        toml::Value::Boolean(_) => Ok(()),
+        toml::Value::String(s) => Err(format!("expected true or false without quotes, got \"{}\"", s)),
+        other => Err(format!("expected true or false, got {}", other.type_str())),
+    }
+}
+
+fn expect_bytes(value: &toml::Value) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => crate::units::parse_bytes(s).map(|_| ()),
+        _ => expect_integer(value, i64::MAX).map_err(|e| format!("{} or a size like \"200GB\"", e)),
+    }
+}
+
+fn expect_integer(value: &toml::Value, max: i64) -> Result<(), String> {
+    match value.as_integer() {
+        Some(n) if (0..=max).contains(&n) => Ok(()),
+        Some(n) => Err(format!("{} is out of range (0 to {})", n, max)),
+        None => Err(format!("expected a whole number, got {}", value.type_str())),
+    }
+}
+
+/// How files under a repo are compared.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashMode {
+    /// Compare size and modification time only.
+    #[default]
+    Metadata,
+    /// Hash every file with XXH3 during scans.
+    Xxh3,
+    /// Like `Xxh3`, but large files are hashed from samples of their start,
+    /// middle and end. Much faster on big media files, at the cost of
+    /// missing edits that fall between the samples. Verified syncs hash in
+    /// full.
+    Sampled,
+}
+
+impl std::fmt::Display for HashMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashMode::Metadata => write!(f, "metadata"),
+            HashMode::Xxh3 => write!(f, "xxh3"),
+            HashMode::Sampled => write!(f, "sampled"),
+        }
+    }
+}
+
+/// Strong hash used to verify copies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "String")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Faster than SHA-256, especially on large files.
+    Blake3,
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(format!(
+                "unknown hash '{s}' (allowed: {})",
+                HashAlgorithm::VALUES.join(", ")
+            )),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// Accepted spellings, as written in config files.
+    pub const VALUES: [&'static str; 2] = ["sha256", "blake3"];
+
+    pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+}
+
+impl TryFrom<String> for HashAlgorithm {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How hard a sync works to get copies onto the disk before moving on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "String")]
+pub enum Durability {
+    /// Leave it to the OS; fastest, but a copy can still be in memory when
+    /// the drive is unplugged or the power goes.
+    None,
+    /// Flush each file and its directory before the next copy.
+    PerFile,
+    /// Flush what's been written every so often and at the end of a sync.
+    Periodic,
+}
+
+impl std::fmt::Display for Durability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Durability::None => write!(f, "none"),
+            Durability::PerFile => write!(f, "per_file"),
+            Durability::Periodic => write!(f, "periodic"),
+        }
+    }
+}
+
+impl std::str::FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Durability::None),
+            "per_file" | "per-file" => Ok(Durability::PerFile),
+            "periodic" => Ok(Durability::Periodic),
+            _ => Err(format!(
+                "unknown durability '{s}' (allowed: {})",
+                Durability::VALUES.join(", ")
+            )),
+        }
+    }
+}
+
+impl Durability {
+    /// Accepted spellings, as written in config files.
+    pub const VALUES: [&'static str; 3] = ["none", "per_file", "periodic"];
+}
+
+impl TryFrom<String> for Durability {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Per-repo metadata and settings, stored at `<root>/.diffr/repo.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoConfig {
+    pub repo: RepoInfo,
+    #[serde(default)]
+    pub settings: RepoSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoInfo {
+    pub initialized_at: DateTime<Utc>,
+    /// Template used by `diffr init --template`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+/// Settings the scanner applies when this root is synced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoSettings {
+    pub hash: HashMode,
+    /// Ignore patterns added to those in `.diffrignore`.
+    pub ignore: Vec<String>,
+}
+
+impl RepoConfig {
+    pub fn new(template: Option<String>) -> Self {
+        Self {
+            repo: RepoInfo {
+                initialized_at: Utc::now(),
+                template,
+            },
+            settings: RepoSettings::default(),
+        }
+    }
+
+    /// Location of `repo.toml` for a repo rooted at `root`.
+    pub fn path_in(root: &Path) -> PathBuf {
+        root.join(".diffr").join("repo.toml")
+    }
+
+    /// Load the repo config for `root`, or `None` if it is not a diffr repo.
+    pub fn load(root: &Path) -> Result<Option<Self>, DiffrError> {
+        let path = Self::path_in(root);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|e| DiffrError::Serialization(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), DiffrError> {
+        let path = Self::path_in(root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content =
+            toml::to_string_pretty(self).map_err(|e| DiffrError::Serialization(e.to_string()))?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,4 +829,121 @@ mod tests {
             deserialized.default_topology
         );
     }
+
+    #[test]
+    fn test_repo_config_reads_legacy_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join(".diffr")).unwrap();
+        std::fs::write(
+            RepoConfig::path_in(dir.path()),
+            "[repo]\ninitialized_at = \"2024-01-01T00:00:00+00:00\"\n",
+        )
+        .unwrap();
+
+        let config = RepoConfig::load(dir.path()).unwrap().unwrap();
+        assert_eq!(config.settings.hash, HashMode::Metadata);
+        assert!(config.settings.ignore.is_empty());
+        assert!(RepoConfig::load(&dir.path().join("missing")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let content = "verify_after_sync = true\n\n[profile.fast]\nverify_after_sync = false\n\n[profile.paranoid]\narchive_on_change = true\n\n[profile.laptop]\non_battery = \"pause\"\nmax_cpu_temp = 80\nthrottle_cpu_temp = 70\n";
+        let config: DiffrConfig = toml::from_str(content).unwrap();
+
+        let fast = config.clone().with_profile("fast").unwrap();
+        assert!(!fast.verify_after_sync);
+        // Settings the profile leaves out keep their base values.
+        let paranoid = config.clone().with_profile("paranoid").unwrap();
+        assert!(paranoid.verify_after_sync);
+        assert!(paranoid.archive_on_change);
+        assert!(paranoid.power_guard(PowerReading::default).is_none());
+        let laptop = config.clone().with_profile("laptop").unwrap();
+        let guard = laptop.power_guard(PowerReading::default).unwrap();
+        assert_eq!((guard.on_battery, guard.max_cpu_temp), (BatteryPolicy::Pause, Some(80)));
+        assert_eq!(guard.throttle_cpu_temp, Some(70));
+
+        let err = config.with_profile("quick").unwrap_err().to_string();
+        assert!(err.contains("defined: fast, laptop, paranoid"), "{}", err);
+    }
+
+    #[test]
+    fn test_presets() {
+        let content = "[preset.archive-heavy]\nconflict_strategy = \"keep_both\"\n\n\
+                       [preset.archive-heavy.settings]\nmin_copies = 3\nverify_after_sync = true\nignore = [\"*.tmp\"]\n\n\
+                       [preset.archive-heavy.settings.retention]\nmax_versions = 50\nmax_total_bytes = \"1TB\"\n";
+        assert!(check_config(content).is_empty(), "{:?}", check_config(content));
+        let config: DiffrConfig = toml::from_str(content).unwrap();
+        let preset = config.find_preset("archive-heavy").unwrap();
+        assert_eq!(preset.topology, None);
+        assert_eq!(preset.conflict_strategy, Some(ConflictStrategy::KeepBoth));
+        assert_eq!(preset.settings.min_copies, Some(3));
+        assert!(preset.settings.verify_after_sync);
+        let retention = preset.settings.retention.as_ref().unwrap();
+        assert_eq!((retention.max_versions, retention.max_total_bytes), (Some(50), Some(1 << 40)));
+        // Unset settings keep their defaults.
+        assert_eq!(preset.settings.mass_change_percent, Some(50));
+
+        // The same keys work as a file of their own.
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("photos.toml");
+        std::fs::write(&file, "topology = \"primary_replica\"\n\n[settings]\nskip_hidden = true\n").unwrap();
+        let from_file = config.find_preset(file.to_str().unwrap()).unwrap();
+        assert_eq!(from_file.topology, Some(Topology::PrimaryReplica));
+        assert!(from_file.settings.skip_hidden);
+
+        let bad = "[preset.x]\ntopology = \"ring\"\n\n[preset.x.settings]\nmin_copies = \"many\"\n";
+        let issues: Vec<String> = check_config(bad).iter().map(|i| i.to_string()).collect();
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert!(issues[0].starts_with("line 2: preset.x.topology"), "{:?}", issues);
+    }
+
+    #[test]
+    fn test_check_config() {
+        let content = "default_topology = \"primary-replica\"\nverify_after_sync = \"yes\"\ncolour = 1\n\n[retention]\nmax_age_days = -1\n";
+        let issues: Vec<String> = check_config(content).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            issues,
+            vec![
+                "line 2: verify_after_sync: expected true or false without quotes, got \"yes\"",
+                "line 3: colour: unknown key, ignored (known keys: default_topology, default_conflict_strategy, hash_by_default, verify_after_sync, verify_hash, verify_readback, durability, archive_on_change, delete_to_trash, discovery_backend, stale_after_hours, on_battery, max_cpu_temp, throttle_cpu_temp)",
+                "line 6: retention.max_age_days: -1 is out of range (0 to 4294967295)",
+            ]
+        );
+
+        let issues = check_config("default_topology = \"ring\"\n");
+        assert_eq!(
+            issues[0].to_string(),
+            "line 1: default_topology: unknown topology 'ring' (allowed: mesh, primary_replica)"
+        );
+        assert!(check_config("[retention]\nmax_total_bytes = \"200GB\"\n").is_empty());
+        let config: DiffrConfig = toml::from_str("[retention]\nmax_total_bytes = \"2KB\"\n").unwrap();
+        assert_eq!(config.retention.max_total_bytes, Some(2048));
+
+        let issues = check_config("\nhash_by_default = \n");
+        assert_eq!(issues[0].line, Some(2));
+
+        let issues = check_config("[profile.fast]\nverify_after_sync = 1\n");
+        assert_eq!(
+            issues[0].to_string(),
+            "line 2: profile.fast.verify_after_sync: expected true or false, got integer"
+        );
+
+        let issues = check_config("[schedule]\nwindow = \"1am-6am\"\n");
+        assert_eq!(
+            issues[0].to_string(),
+            "line 2: schedule.window: expected a time like 01:00: 1am"
+        );
+        let config: DiffrConfig = toml::from_str("[schedule]\nwindow = \"01:00-06:00\"\n").unwrap();
+        assert_eq!(config.schedule.window.map(|w| w.to_string()).as_deref(), Some("01:00-06:00"));
+
+        // Merge commands take any extension as a key, but only strings.
+        let issues = check_config("[merge]\nics = \"ics-merge\"\n\"tar.gz\" = 1\n");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key.as_deref(), Some("merge.tar.gz"));
+
+        // The aliases that pass the check also load.
+        let config: DiffrConfig = toml::from_str("default_topology = \"primary-replica\"").unwrap();
+        assert_eq!(config.default_topology, Topology::PrimaryReplica);
+    }
 }
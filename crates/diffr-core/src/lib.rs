@@ -1,3 +1,12 @@
+pub mod cancel;
 pub mod config;
 pub mod error;
+pub mod lock;
 pub mod models;
+pub mod path_encoding;
+pub mod power;
+pub mod progress;
+pub mod schedule;
+pub mod spill;
+pub mod task;
+pub mod units;
@@ -0,0 +1,79 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::error::DiffrError;
+
+/// An exclusive advisory lock on a file, held until dropped. The holder
+/// writes a description of itself into the file so others can say what
+/// they're waiting for.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock, waiting for whoever holds it.
+    pub fn acquire(path: &Path, holder: &str) -> Result<Self, DiffrError> {
+        let file = open(path)?;
+        file.lock()?;
+        Self::hold(file, holder)
+    }
+
+    /// Take the lock, or return `None` if another process holds it.
+    pub fn try_acquire(path: &Path, holder: &str) -> Result<Option<Self>, DiffrError> {
+        let file = open(path)?;
+        match file.try_lock() {
+            Ok(()) => Self::hold(file, holder).map(Some),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// What the process holding the lock at `path` said about itself, if
+    /// it's readable. Stale after the holder exits.
+    pub fn holder(path: &Path) -> Option<String> {
+        let mut text = String::new();
+        File::open(path).ok()?.read_to_string(&mut text).ok()?;
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    fn hold(file: File, holder: &str) -> Result<Self, DiffrError> {
+        file.set_len(0)?;
+        (&file).write_all(holder.as_bytes())?;
+        Ok(InstanceLock { _file: file })
+    }
+}
+
+/// Open without truncating: the holder's description must survive others
+/// opening the file to wait on it.
+fn open(path: &Path) -> Result<File, DiffrError> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_excludes_others() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("lock");
+
+        let held = InstanceLock::try_acquire(&path, "pid 1: diffr sync c").unwrap();
+        assert!(held.is_some());
+        assert!(InstanceLock::try_acquire(&path, "pid 2").unwrap().is_none());
+        assert_eq!(InstanceLock::holder(&path).as_deref(), Some("pid 1: diffr sync c"));
+
+        drop(held);
+        let again = InstanceLock::try_acquire(&path, "pid 2").unwrap();
+        assert!(again.is_some());
+        assert_eq!(InstanceLock::holder(&path).as_deref(), Some("pid 2"));
+    }
+}
@@ -27,6 +27,27 @@ pub struct ArchiveEntry {
     pub reason: ArchiveReason,
     /// When this version was archived.
     pub archived_at: DateTime<Utc>,
+    /// The drive the file was archived from, when the archive is stored
+    /// elsewhere: relocated to `drive_id` to free space, or written there
+    /// by a cluster that archives centrally.
+    #[serde(default)]
+    pub relocated_from: Option<DriveId>,
+    /// When the archive was last relocated.
+    #[serde(default)]
+    pub relocated_at: Option<DateTime<Utc>>,
+}
+
+/// One piece of an archive too big for any single drive, split across
+/// several. Concatenated in `index` order the parts are the archive file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivePart {
+    pub archive_id: Uuid,
+    pub index: u32,
+    /// The drive holding this part.
+    pub drive_id: DriveId,
+    /// Where the part is stored, relative to that drive's root.
+    pub part_path: PathBuf,
+    pub size: u64,
 }
 
 /// Why a file was archived.
@@ -69,14 +90,32 @@ impl std::fmt::Display for CompressionFormat {
 }
 
 /// Policy governing archive retention.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RetentionPolicy {
     /// Maximum age of archived versions in days. None = keep forever.
     pub max_age_days: Option<u32>,
     /// Maximum number of versions to keep per file. None = unlimited.
     pub max_versions: Option<u32>,
-    /// Maximum total archive size in bytes. None = unlimited.
+    /// Maximum total archive size in bytes, or a size such as `"200GB"`.
+    /// None = unlimited.
+    #[serde(default, with = "crate::units::serde_opt_bytes")]
     pub max_total_bytes: Option<u64>,
+    /// When the size cap is exceeded, move the oldest archives to an
+    /// archive-only/archive-assist drive with room instead of deleting them.
+    #[serde(default)]
+    pub relocate_on_cap: bool,
+    /// When relocating, split an archive too big for any one drive into
+    /// parts across several. Without it such archives are pruned.
+    #[serde(default)]
+    pub span_on_relocate: bool,
+    /// Days to keep files in `.diffr/trash` before a sync purges them.
+    /// None = keep until `diffr trash empty`.
+    #[serde(default = "default_trash_days")]
+    pub trash_days: Option<u32>,
+}
+
+fn default_trash_days() -> Option<u32> {
+    Some(30)
 }
 
 impl Default for RetentionPolicy {
@@ -85,6 +124,9 @@ impl Default for RetentionPolicy {
             max_age_days: Some(90),
             max_versions: Some(10),
             max_total_bytes: None,
+            relocate_on_cap: false,
+            span_on_relocate: false,
+            trash_days: default_trash_days(),
         }
     }
 }
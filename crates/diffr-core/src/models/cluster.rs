@@ -1,7 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
 use uuid::Uuid;
 
+use crate::models::archive::RetentionPolicy;
+
 /// Unique identifier for a cluster.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClusterId(pub Uuid);
@@ -16,6 +20,12 @@ impl ClusterId {
     }
 }
 
+impl Default for ClusterId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for ClusterId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -24,7 +34,7 @@ impl std::fmt::Display for ClusterId {
 
 /// Sync topology for a cluster.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case", try_from = "String")]
 pub enum Topology {
     /// All drives are equal peers; changes flow in all directions.
     Mesh,
@@ -48,14 +58,30 @@ impl std::str::FromStr for Topology {
         match s {
             "mesh" => Ok(Topology::Mesh),
             "primary_replica" | "primary-replica" => Ok(Topology::PrimaryReplica),
-            _ => Err(format!("unknown topology: {s}")),
+            _ => Err(format!(
+                "unknown topology '{s}' (allowed: {})",
+                Topology::VALUES.join(", ")
+            )),
         }
     }
 }
 
+impl Topology {
+    /// Accepted spellings, as written in config files.
+    pub const VALUES: [&'static str; 2] = ["mesh", "primary_replica"];
+}
+
+impl TryFrom<String> for Topology {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 /// Strategy for resolving file conflicts during sync.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case", try_from = "String")]
 pub enum ConflictStrategy {
     /// The file with the newest modification time wins.
     NewestWins,
@@ -83,11 +109,415 @@ impl std::str::FromStr for ConflictStrategy {
             "newest_wins" | "newest-wins" => Ok(ConflictStrategy::NewestWins),
             "keep_both" | "keep-both" => Ok(ConflictStrategy::KeepBoth),
             "interactive" => Ok(ConflictStrategy::Interactive),
-            _ => Err(format!("unknown conflict strategy: {s}")),
+            _ => Err(format!(
+                "unknown conflict strategy '{s}' (allowed: {})",
+                ConflictStrategy::VALUES.join(", ")
+            )),
         }
     }
 }
 
+impl ConflictStrategy {
+    /// Accepted spellings, as written in config files.
+    pub const VALUES: [&'static str; 3] = ["newest_wins", "keep_both", "interactive"];
+}
+
+impl TryFrom<String> for ConflictStrategy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Order in which a sync plan's operations are executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeOrder {
+    /// Keep the order the planner produced.
+    #[default]
+    Planned,
+    /// Transfer small files first for quick progress, then large ones.
+    SmallFirst,
+}
+
+impl std::fmt::Display for SizeOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SizeOrder::Planned => write!(f, "planned"),
+            SizeOrder::SmallFirst => write!(f, "small_first"),
+        }
+    }
+}
+
+impl std::str::FromStr for SizeOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "planned" => Ok(SizeOrder::Planned),
+            "small_first" | "small-first" => Ok(SizeOrder::SmallFirst),
+            _ => Err(format!("unknown size order: {s}")),
+        }
+    }
+}
+
+/// What to do when a sync would push a drive past its quota.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaMode {
+    /// Abort the sync.
+    #[default]
+    Fail,
+    /// Drop the operations that don't fit and sync the rest.
+    Trim,
+}
+
+impl std::fmt::Display for QuotaMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaMode::Fail => write!(f, "fail"),
+            QuotaMode::Trim => write!(f, "trim"),
+        }
+    }
+}
+
+impl std::str::FromStr for QuotaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(QuotaMode::Fail),
+            "trim" => Ok(QuotaMode::Trim),
+            _ => Err(format!("unknown quota mode: {s}")),
+        }
+    }
+}
+
+/// Whether scans descend into repos nested inside a sync root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NestedRepos {
+    /// Scan them like any other directory.
+    #[default]
+    Descend,
+    /// Stop at directories that are diffr repos of their own
+    /// (`.diffr/repo.toml`), which sync separately.
+    SkipDiffr,
+    /// Stop at nested diffr repos and git repos (`.git`), as git does at
+    /// submodules.
+    SkipAll,
+}
+
+impl std::fmt::Display for NestedRepos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NestedRepos::Descend => write!(f, "descend"),
+            NestedRepos::SkipDiffr => write!(f, "skip_diffr"),
+            NestedRepos::SkipAll => write!(f, "skip_all"),
+        }
+    }
+}
+
+impl std::str::FromStr for NestedRepos {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "descend" => Ok(NestedRepos::Descend),
+            "skip_diffr" | "skip-diffr" => Ok(NestedRepos::SkipDiffr),
+            "skip_all" | "skip-all" => Ok(NestedRepos::SkipAll),
+            _ => Err(format!("unknown nested repo mode: {s} (descend, skip_diffr, skip_all)")),
+        }
+    }
+}
+
+/// Where files are archived before a sync overwrites or deletes them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveStrategy {
+    /// On the drive being changed.
+    #[default]
+    PerDrive,
+    /// On one of the cluster's archive drives when one is connected and has
+    /// room, and on the drive being changed otherwise.
+    Central,
+}
+
+impl std::fmt::Display for ArchiveStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveStrategy::PerDrive => write!(f, "per_drive"),
+            ArchiveStrategy::Central => write!(f, "central"),
+        }
+    }
+}
+
+impl std::str::FromStr for ArchiveStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per_drive" | "per-drive" => Ok(ArchiveStrategy::PerDrive),
+            "central" => Ok(ArchiveStrategy::Central),
+            _ => Err(format!("unknown archive strategy: {s} (per_drive, central)")),
+        }
+    }
+}
+
+/// Optional per-cluster sync settings. Unset fields take their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterSettings {
+    /// Execution order for planned operations.
+    pub size_order: SizeOrder,
+    /// Files larger than this many bytes are deferred to a `--large-files` pass.
+    #[serde(with = "crate::units::serde_opt_bytes")]
+    pub large_file_threshold: Option<u64>,
+    /// Abort a sync that would delete or overwrite more than this percentage
+    /// of a drive's files, unless `--allow-mass-change` is given.
+    pub mass_change_percent: Option<u8>,
+    /// Pause a sync when more than this percentage of a source drive's
+    /// files changed content since the last sync, as ransomware would
+    /// cause, until the user confirms or passes `--allow-anomaly`. Off
+    /// unless set.
+    pub anomaly_percent: Option<u8>,
+    /// Behaviour when incoming files exceed a drive's quota.
+    pub quota_mode: QuotaMode,
+    /// Follow symlinks when scanning this cluster's drives.
+    pub follow_symlinks: bool,
+    /// Leave out dotfiles and Windows hidden files when scanning.
+    pub skip_hidden: bool,
+    /// Leave out files with the Windows system attribute when scanning.
+    pub skip_system: bool,
+    /// Treat nested repo roots as boundaries when scanning.
+    pub nested_repos: NestedRepos,
+    /// Where files are archived before they're overwritten or deleted.
+    pub archive_strategy: ArchiveStrategy,
+    /// Also copy the catalog database onto connected archive-only drives
+    /// after each sync.
+    pub archive_catalog: bool,
+    /// Every file should end up on at least this many drives; sync adds
+    /// copies to reach it and reports files that can't.
+    pub min_copies: Option<u32>,
+    /// Sync permission bits, so a chmod alone is copied to other drives.
+    pub sync_permissions: bool,
+    /// Sync file owner and group too. Changing them usually needs root.
+    pub sync_ownership: bool,
+    /// Hold back files modified less than this many seconds before the
+    /// scan, retrying them once the sync's other work is done.
+    #[serde(with = "crate::units::serde_opt_secs")]
+    pub stability_window: Option<u64>,
+    /// Verify every copy, as `diffr sync --verify` does, even if the
+    /// config's `verify_after_sync` is off.
+    pub verify_after_sync: bool,
+    /// Ignore patterns added to each drive's `.diffrignore` when scanning.
+    pub ignore: Vec<String>,
+    /// Retention for archives and trash on this cluster's drives, in place
+    /// of the config's `retention`.
+    pub retention: Option<RetentionPolicy>,
+    /// Conflict strategies by file extension (`sqlite`, or `tar.gz`), used
+    /// in place of the cluster's for files with that extension.
+    pub conflict_rules: BTreeMap<String, ConflictStrategy>,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        Self {
+            size_order: SizeOrder::default(),
+            large_file_threshold: None,
+            mass_change_percent: Some(50),
+            anomaly_percent: None,
+            quota_mode: QuotaMode::default(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::default(),
+            archive_strategy: ArchiveStrategy::default(),
+            archive_catalog: false,
+            min_copies: None,
+            sync_permissions: false,
+            sync_ownership: false,
+            stability_window: None,
+            verify_after_sync: false,
+            ignore: Vec::new(),
+            retention: None,
+            conflict_rules: BTreeMap::new(),
+        }
+    }
+}
+
+impl ClusterSettings {
+    /// Set a setting by name, as used by `diffr cluster set`.
+    /// `none` clears an optional setting.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "size_order" | "size-order" => self.size_order = value.parse()?,
+            "large_file_threshold" | "large-file-threshold" => {
+                self.large_file_threshold = parse_optional_with(value, crate::units::parse_bytes)?;
+            }
+            "mass_change_percent" | "mass-change-percent" => {
+                let percent = parse_optional(value)?;
+                if percent.is_some_and(|p: u8| p > 100) {
+                    return Err(format!("percentage must be 0-100: {value}"));
+                }
+                self.mass_change_percent = percent;
+            }
+            "anomaly_percent" | "anomaly-percent" => {
+                let percent = parse_optional(value)?;
+                if percent.is_some_and(|p: u8| p > 100) {
+                    return Err(format!("percentage must be 0-100: {value}"));
+                }
+                self.anomaly_percent = percent;
+            }
+            "quota_mode" | "quota-mode" => self.quota_mode = value.parse()?,
+            "follow_symlinks" | "follow-symlinks" => self.follow_symlinks = parse_bool(value)?,
+            "skip_hidden" | "skip-hidden" => self.skip_hidden = parse_bool(value)?,
+            "skip_system" | "skip-system" => self.skip_system = parse_bool(value)?,
+            "nested_repos" | "nested-repos" => self.nested_repos = value.parse()?,
+            "archive_strategy" | "archive-strategy" => self.archive_strategy = value.parse()?,
+            "archive_catalog" | "archive-catalog" => self.archive_catalog = parse_bool(value)?,
+            "min_copies" | "min-copies" => {
+                let copies = parse_optional(value)?;
+                if copies == Some(0) {
+                    return Err(format!("min_copies must be at least 1: {value}"));
+                }
+                self.min_copies = copies;
+            }
+            "sync_permissions" | "sync-permissions" => self.sync_permissions = parse_bool(value)?,
+            "sync_ownership" | "sync-ownership" => self.sync_ownership = parse_bool(value)?,
+            "stability_window" | "stability-window" => {
+                self.stability_window = parse_optional_with(value, |v| crate::units::parse_duration(v).map(|d| d.as_secs()))?;
+            }
+            "verify_after_sync" | "verify-after-sync" => self.verify_after_sync = parse_bool(value)?,
+            "ignore" => {
+                self.ignore = match value {
+                    "none" => Vec::new(),
+                    _ => value.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect(),
+                };
+            }
+            "conflict_rules" | "conflict-rules" => {
+                self.conflict_rules = BTreeMap::new();
+                if value != "none" {
+                    for rule in value.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                        let (ext, strategy) = rule
+                            .split_once(['=', ':'])
+                            .ok_or_else(|| format!("expected extension=strategy, such as .sqlite=keep_both: {rule}"))?;
+                        let ext = ext.trim().trim_start_matches('.').to_lowercase();
+                        if ext.is_empty() {
+                            return Err(format!("missing extension: {rule}"));
+                        }
+                        self.conflict_rules.insert(ext, strategy.trim().parse()?);
+                    }
+                }
+            }
+            // Retention comes as a whole from a preset or `cluster import`.
+            "retention" if value == "none" => self.retention = None,
+            "retention" => return Err("retention can only be cleared with `none`; set it with a preset".to_string()),
+            _ => return Err(format!("unknown cluster setting: {key}")),
+        }
+        Ok(())
+    }
+
+    /// All settings as `(name, value)` pairs for display.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("size_order", self.size_order.to_string()),
+            ("large_file_threshold", display_optional(&self.large_file_threshold)),
+            ("mass_change_percent", display_optional(&self.mass_change_percent)),
+            ("anomaly_percent", display_optional(&self.anomaly_percent)),
+            ("quota_mode", self.quota_mode.to_string()),
+            ("follow_symlinks", self.follow_symlinks.to_string()),
+            ("skip_hidden", self.skip_hidden.to_string()),
+            ("skip_system", self.skip_system.to_string()),
+            ("nested_repos", self.nested_repos.to_string()),
+            ("archive_strategy", self.archive_strategy.to_string()),
+            ("archive_catalog", self.archive_catalog.to_string()),
+            ("min_copies", display_optional(&self.min_copies)),
+            ("sync_permissions", self.sync_permissions.to_string()),
+            ("sync_ownership", self.sync_ownership.to_string()),
+            ("stability_window", display_optional(&self.stability_window)),
+            ("verify_after_sync", self.verify_after_sync.to_string()),
+            (
+                "ignore",
+                if self.ignore.is_empty() { "none".to_string() } else { self.ignore.join(",") },
+            ),
+            ("retention", self.retention.as_ref().map(display_retention).unwrap_or_else(|| "none".to_string())),
+            (
+                "conflict_rules",
+                if self.conflict_rules.is_empty() {
+                    "none".to_string()
+                } else {
+                    let rules: Vec<String> =
+                        self.conflict_rules.iter().map(|(ext, s)| format!(".{}={}", ext, s)).collect();
+                    rules.join(",")
+                },
+            ),
+        ]
+    }
+
+    /// The conflict rule for `rel_path`'s extension, if there is one. The
+    /// longest matching extension wins, so `tar.gz` beats `gz`.
+    pub fn conflict_rule(&self, rel_path: &Path) -> Option<&ConflictStrategy> {
+        by_extension(&self.conflict_rules, rel_path).map(|(_, strategy)| strategy)
+    }
+}
+
+/// The extension in `by_ext` that `rel_path` has, as written there, and its
+/// value. Extensions match without case and with or without a leading dot;
+/// the longest that matches wins.
+pub fn by_extension<'a, T>(by_ext: &'a BTreeMap<String, T>, rel_path: &Path) -> Option<(&'a str, &'a T)> {
+    let name = rel_path.file_name()?.to_string_lossy().to_lowercase();
+    by_ext
+        .iter()
+        .map(|(key, value)| (key, key.trim_start_matches('.').to_lowercase(), value))
+        .filter(|(_, ext, _)| !ext.is_empty() && name.len() > ext.len() + 1 && name.ends_with(&format!(".{}", ext)))
+        .max_by_key(|(_, ext, _)| ext.len())
+        .map(|(key, _, value)| (key.as_str(), value))
+}
+
+fn parse_optional<T: std::str::FromStr>(value: &str) -> Result<Option<T>, String> {
+    if value == "none" {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("invalid value: {value}"))
+}
+
+fn parse_optional_with<T>(value: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+    if value == "none" {
+        return Ok(None);
+    }
+    parse(value).map(Some)
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("expected true or false: {value}")),
+    }
+}
+
+fn display_retention(retention: &RetentionPolicy) -> String {
+    format!(
+        "max_versions {}, max_age_days {}, max_total_bytes {}, trash_days {}",
+        display_optional(&retention.max_versions),
+        display_optional(&retention.max_age_days),
+        display_optional(&retention.max_total_bytes.map(crate::units::format_bytes)),
+        display_optional(&retention.trash_days)
+    )
+}
+
+fn display_optional<T: std::fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "none".to_string())
+}
+
 /// A cluster groups drives that sync together.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cluster {
@@ -95,6 +525,8 @@ pub struct Cluster {
     pub name: String,
     pub topology: Topology,
     pub conflict_strategy: ConflictStrategy,
+    #[serde(default)]
+    pub settings: ClusterSettings,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -107,8 +539,75 @@ impl Cluster {
             name,
             topology,
             conflict_strategy,
+            settings: ClusterSettings::default(),
             created_at: now,
             updated_at: now,
         }
     }
+
+    /// How to resolve a conflict over `rel_path`: the rule for its
+    /// extension, or the cluster's strategy.
+    pub fn conflict_strategy_for(&self, rel_path: &Path) -> &ConflictStrategy {
+        self.settings.conflict_rule(rel_path).unwrap_or(&self.conflict_strategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_settings_set() {
+        let mut settings = ClusterSettings::default();
+        settings.set("size-order", "small-first").unwrap();
+        settings.set("large_file_threshold", "1048576").unwrap();
+        assert_eq!(settings.size_order, SizeOrder::SmallFirst);
+        assert_eq!(settings.large_file_threshold, Some(1048576));
+
+        settings.set("large_file_threshold", "4GB").unwrap();
+        assert_eq!(settings.large_file_threshold, Some(4 << 30));
+        settings.set("stability_window", "10m").unwrap();
+        assert_eq!(settings.stability_window, Some(600));
+
+        settings.set("large_file_threshold", "none").unwrap();
+        assert_eq!(settings.large_file_threshold, None);
+        assert!(settings.set("bogus", "1").is_err());
+        assert!(settings.set("large_file_threshold", "big").is_err());
+        assert!(settings.set("mass_change_percent", "150").is_err());
+        assert!(settings.set("min_copies", "0").is_err());
+
+        settings.set("follow-symlinks", "yes").unwrap();
+        assert!(settings.follow_symlinks);
+        assert!(settings.set("follow_symlinks", "maybe").is_err());
+
+        settings.set("ignore", "*.tmp, build/").unwrap();
+        assert_eq!(settings.ignore, vec!["*.tmp", "build/"]);
+        settings.set("ignore", "none").unwrap();
+        assert!(settings.ignore.is_empty());
+        assert!(settings.set("retention", "50").is_err());
+    }
+
+    #[test]
+    fn test_conflict_rules() {
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        cluster
+            .settings
+            .set("conflict_rules", ".sqlite=keep-both, .PST=interactive, tar.gz=keep_both, gz=newest_wins")
+            .unwrap();
+        let strategy = |path: &str| cluster.conflict_strategy_for(Path::new(path)).clone();
+        assert_eq!(strategy("db/app.sqlite"), ConflictStrategy::KeepBoth);
+        assert_eq!(strategy("mail/Outlook.pst"), ConflictStrategy::Interactive);
+        assert_eq!(strategy("backup.tar.gz"), ConflictStrategy::KeepBoth);
+        assert_eq!(strategy("log.gz"), ConflictStrategy::NewestWins);
+        assert_eq!(strategy("notes.txt"), ConflictStrategy::NewestWins);
+        // A dotfile named after an extension has no extension.
+        assert_eq!(strategy(".sqlite"), ConflictStrategy::NewestWins);
+
+        let shown = cluster.settings.entries().into_iter().find(|(k, _)| *k == "conflict_rules").unwrap().1;
+        assert_eq!(shown, ".gz=newest_wins,.pst=interactive,.sqlite=keep_both,.tar.gz=keep_both");
+        assert!(cluster.settings.set("conflict_rules", ".txt=oldest").is_err());
+        assert!(cluster.settings.set("conflict_rules", "txt").is_err());
+        cluster.settings.set("conflict_rules", "none").unwrap();
+        assert!(cluster.settings.conflict_rules.is_empty());
+    }
 }
@@ -19,6 +19,12 @@ impl DriveId {
     }
 }
 
+impl Default for DriveId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for DriveId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -89,6 +95,47 @@ impl std::str::FromStr for DriveRole {
     }
 }
 
+/// Filesystem type recorded on drives reached over MTP (phones, cameras).
+pub const MTP_FILESYSTEM: &str = "mtp";
+
+/// How the mount point of an MTP device reached through libmtp, rather
+/// than a mount, starts: `mtp://<serial>`, then the path on the device.
+pub const MTP_URL_PREFIX: &str = "mtp://";
+
+/// How a drive is attached, as reported by discovery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusType {
+    Usb,
+    Thunderbolt,
+    /// SD or other memory card reader.
+    Sd,
+    /// SATA, NVMe or virtual disks fixed inside the machine.
+    Internal,
+    /// Discovery couldn't tell.
+    #[default]
+    Unknown,
+}
+
+impl BusType {
+    /// Plugged in from outside the machine: USB, Thunderbolt or a card.
+    pub fn is_removable(&self) -> bool {
+        matches!(self, BusType::Usb | BusType::Thunderbolt | BusType::Sd)
+    }
+}
+
+impl std::fmt::Display for BusType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusType::Usb => write!(f, "usb"),
+            BusType::Thunderbolt => write!(f, "thunderbolt"),
+            BusType::Sd => write!(f, "sd"),
+            BusType::Internal => write!(f, "internal"),
+            BusType::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
 /// A drive known to Diffr.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Drive {
@@ -105,6 +152,42 @@ pub struct Drive {
     pub free_bytes: Option<u64>,
     pub last_seen: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    /// A read-only drive is a sync source only; nothing is ever written to it.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Maximum bytes this cluster may store under the sync root. Useful when
+    /// one physical drive hosts sync roots for several clusters.
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    /// Bytes under the sync root as of the last sync.
+    #[serde(default)]
+    pub used_bytes: Option<u64>,
+    /// Windows volume GUID path (`\\?\Volume{...}\`), which stays the same
+    /// when the drive letter changes.
+    #[serde(default)]
+    pub volume_guid: Option<String>,
+    /// Filesystem type reported at discovery, e.g. `NTFS` or `ext4`.
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    /// Other identifiers discovery reported for this drive, such as
+    /// `wwn-0x...`, `partuuid-...` and `uuid-...`. A drive whose primary
+    /// identity changes (say, behind a different USB bridge) is still
+    /// recognised by any of these.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Where this drive's `.diffr` directory (archives, trash, manifest)
+    /// is kept, if not in its sync root: for read-only media or shares
+    /// diffr can't write to the top of.
+    #[serde(default)]
+    pub metadata_dir: Option<PathBuf>,
+    /// The volume was BitLocker-locked when last discovered. Not stored in
+    /// the database; refreshed before each sync.
+    #[serde(default)]
+    pub locked: bool,
+    /// How the drive was attached when last discovered. Not stored in the
+    /// database.
+    #[serde(default)]
+    pub bus: BusType,
 }
 
 impl Drive {
@@ -123,6 +206,15 @@ impl Drive {
             free_bytes: None,
             last_seen: now,
             created_at: now,
+            read_only: false,
+            quota_bytes: None,
+            used_bytes: None,
+            volume_guid: None,
+            filesystem: None,
+            aliases: Vec::new(),
+            metadata_dir: None,
+            locked: false,
+            bus: BusType::Unknown,
         }
     }
 
@@ -131,4 +223,50 @@ impl Drive {
     pub fn effective_root(&self) -> &Path {
         self.sync_root.as_deref().unwrap_or(&self.mount_point)
     }
+
+    /// This drive's `.diffr` directory: `metadata_dir` if set, otherwise
+    /// `.diffr` in the sync root.
+    pub fn diffr_dir(&self) -> PathBuf {
+        self.metadata_dir
+            .clone()
+            .unwrap_or_else(|| self.effective_root().join(".diffr"))
+    }
+
+    /// `path` (under [`Drive::diffr_dir`]) as stored in the database:
+    /// relative to the sync root when it's under it, otherwise absolute.
+    pub fn stored_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(self.effective_root())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// An MTP device, which can't rename files into place or keep mtimes.
+    pub fn is_mtp(&self) -> bool {
+        self.filesystem.as_deref() == Some(MTP_FILESYSTEM)
+    }
+
+    /// For an MTP device reached through libmtp rather than a mount, its
+    /// serial and the path on it that's synced: empty for the whole device,
+    /// or starting with the name of one of its storages.
+    pub fn mtp_device(&self) -> Option<(&str, &Path)> {
+        let rest = self.effective_root().to_str()?.strip_prefix(MTP_URL_PREFIX)?;
+        let (serial, path) = rest.split_once('/').unwrap_or((rest, ""));
+        Some((serial, Path::new(path)))
+    }
+
+    /// Whether `id` is this drive's identity or one of its aliases.
+    pub fn matches_id(&self, id: &str) -> bool {
+        self.identity.identity_string() == id || self.aliases.iter().any(|a| a == id)
+    }
+
+    /// Move the drive to a new mount point, carrying a sync root that lived
+    /// under the old one along with it.
+    pub fn remount(&mut self, mount_point: PathBuf) {
+        if let Some(root) = &self.sync_root {
+            if let Ok(rel) = root.strip_prefix(&self.mount_point) {
+                self.sync_root = Some(mount_point.join(rel));
+            }
+        }
+        self.mount_point = mount_point;
+    }
 }
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 use super::drive::DriveId;
+use crate::config::HashAlgorithm;
 
 /// A file or directory entry in the index.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,12 +20,72 @@ pub struct FileEntry {
     pub mtime: DateTime<Utc>,
     /// XXH3-64 hash for fast change detection (hex string).
     pub xxh3_hash: Option<String>,
+    /// XXH3-64 of the size and samples of the start, middle and end, for
+    /// large files in repos set to `hash = "sampled"` (hex string).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampled_hash: Option<String>,
     /// SHA-256 hash for verification (hex string).
     pub sha256_hash: Option<String>,
+    /// BLAKE3 hash for verification (hex string).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3_hash: Option<String>,
+    /// Permission bits and ownership, where the filesystem has them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<FilePermissions>,
     /// When this entry was last indexed.
     pub indexed_at: DateTime<Utc>,
 }
 
+/// Unix permission bits and ownership of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePermissions {
+    /// Permission bits, including setuid, setgid and sticky (`mode & 0o7777`).
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Last status change (ctime), which chmod and chown update. In a mesh
+    /// the side changed most recently wins.
+    pub changed_at: DateTime<Utc>,
+}
+
+impl FilePermissions {
+    /// Read from a file's metadata. `None` off Unix.
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Option<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Some(Self {
+                mode: metadata.mode() & 0o7777,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                changed_at: DateTime::from_timestamp(metadata.ctime(), metadata.ctime_nsec() as u32)
+                    .unwrap_or_default(),
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = metadata;
+            None
+        }
+    }
+
+    /// Whether the parts being synced differ: the mode, plus owner and
+    /// group if `ownership` is set.
+    pub fn differs(&self, other: &Self, ownership: bool) -> bool {
+        self.mode != other.mode || (ownership && (self.uid, self.gid) != (other.uid, other.gid))
+    }
+}
+
+impl FileEntry {
+    /// The entry's verification hash with `algorithm`, if it was computed.
+    pub fn strong_hash(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        match algorithm {
+            HashAlgorithm::Sha256 => self.sha256_hash.as_deref(),
+            HashAlgorithm::Blake3 => self.blake3_hash.as_deref(),
+        }
+    }
+}
+
 /// Cached hash entry for avoiding re-hashing unchanged files.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashCacheEntry {
@@ -35,10 +96,16 @@ pub struct HashCacheEntry {
     pub size: u64,
     /// Modification time at time of hashing.
     pub mtime: DateTime<Utc>,
+    /// Filesystem file ID (inode, or NTFS file index), which survives renames.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
     /// XXH3-64 hash (hex string).
     pub xxh3_hash: String,
     /// SHA-256 hash if computed (hex string).
     pub sha256_hash: Option<String>,
+    /// BLAKE3 hash if computed (hex string).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3_hash: Option<String>,
     /// When this cache entry was created.
     pub cached_at: DateTime<Utc>,
 }
@@ -48,4 +115,12 @@ impl HashCacheEntry {
     pub fn is_valid(&self, size: u64, mtime: DateTime<Utc>) -> bool {
         self.size == size && self.mtime == mtime
     }
+
+    /// The cached verification hash with `algorithm`, if it was computed.
+    pub fn strong_hash(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        match algorithm {
+            HashAlgorithm::Sha256 => self.sha256_hash.as_deref(),
+            HashAlgorithm::Blake3 => self.blake3_hash.as_deref(),
+        }
+    }
 }
@@ -0,0 +1,224 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use super::cluster::{Cluster, ClusterId, ClusterSettings, ConflictStrategy, Topology};
+use super::drive::{Drive, DriveIdentity, DriveRole};
+use crate::error::DiffrError;
+
+/// File name of the on-drive manifest inside a root's `.diffr` directory.
+pub const DRIVE_MANIFEST_FILE: &str = "manifest.toml";
+
+/// Portable description of a cluster and its member drives, independent of
+/// the machine it was created on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterManifest {
+    pub cluster: ClusterSpec,
+    #[serde(default)]
+    pub drives: Vec<DriveSpec>,
+    pub exported_at: DateTime<Utc>,
+}
+
+/// Cluster settings carried by a manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterSpec {
+    pub id: ClusterId,
+    pub name: String,
+    pub topology: Topology,
+    pub conflict_strategy: ConflictStrategy,
+    #[serde(default)]
+    pub settings: ClusterSettings,
+}
+
+/// A drive's identity and membership, without machine-specific state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveSpec {
+    pub identity: DriveIdentity,
+    pub label: Option<String>,
+    pub role: DriveRole,
+    pub is_primary: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    /// Sync root relative to the mount point, or absolute if it lies elsewhere.
+    pub sync_root: Option<PathBuf>,
+    /// Mount point on the exporting machine; only a hint on import.
+    pub mount_point: PathBuf,
+    /// Patterns in the drive's `.diffrignore` when it was exported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+}
+
+impl ClusterManifest {
+    pub fn new(cluster: &Cluster, drives: &[Drive]) -> Self {
+        Self {
+            cluster: ClusterSpec {
+                id: cluster.id.clone(),
+                name: cluster.name.clone(),
+                topology: cluster.topology.clone(),
+                conflict_strategy: cluster.conflict_strategy.clone(),
+                settings: cluster.settings.clone(),
+            },
+            drives: drives.iter().map(DriveSpec::from_drive).collect(),
+            exported_at: Utc::now(),
+        }
+    }
+
+    /// Rebuild the cluster record. Keeps the original ID so the same cluster
+    /// is recognised across machines.
+    pub fn to_cluster(&self) -> Cluster {
+        let mut cluster = Cluster::new(
+            self.cluster.name.clone(),
+            self.cluster.topology.clone(),
+            self.cluster.conflict_strategy.clone(),
+        );
+        cluster.id = self.cluster.id.clone();
+        cluster.settings = self.cluster.settings.clone();
+        cluster
+    }
+}
+
+impl DriveSpec {
+    pub fn from_drive(drive: &Drive) -> Self {
+        let sync_root = drive.sync_root.as_ref().map(|root| {
+            root.strip_prefix(&drive.mount_point)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| root.clone())
+        });
+        Self {
+            identity: drive.identity.clone(),
+            label: drive.label.clone(),
+            role: drive.role.clone(),
+            is_primary: drive.is_primary,
+            read_only: drive.read_only,
+            quota_bytes: drive.quota_bytes,
+            sync_root,
+            mount_point: drive.mount_point.clone(),
+            ignore: Vec::new(),
+        }
+    }
+
+    /// Build a drive record for `cluster_id`, mounted at `mount_point`
+    /// (the discovered mount if the drive is connected, else the hint).
+    pub fn to_drive(&self, cluster_id: &ClusterId, mount_point: &Path) -> Drive {
+        let mut drive = Drive::new(self.identity.clone(), mount_point.to_path_buf());
+        drive.label = self.label.clone();
+        drive.role = self.role.clone();
+        drive.is_primary = self.is_primary;
+        drive.read_only = self.read_only;
+        drive.quota_bytes = self.quota_bytes;
+        drive.cluster_id = Some(cluster_id.clone());
+        drive.sync_root = self.sync_root.as_ref().map(|root| mount_point.join(root));
+        drive
+    }
+}
+
+/// Manifest stored on a drive, so another machine can reconstruct the cluster
+/// from the drive alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveManifest {
+    /// Identity of the drive this manifest lives on.
+    pub drive: DriveIdentity,
+    pub manifest: ClusterManifest,
+    /// SHA-256 over the serialized `drive` and `manifest`. Detects corruption
+    /// and hand edits; it is not a secret-keyed signature.
+    pub signature: String,
+}
+
+#[derive(Serialize)]
+struct SignedBody<'a> {
+    drive: &'a DriveIdentity,
+    manifest: &'a ClusterManifest,
+}
+
+impl DriveManifest {
+    pub fn new(drive: DriveIdentity, manifest: ClusterManifest) -> Result<Self, DiffrError> {
+        let signature = Self::sign(&drive, &manifest)?;
+        Ok(Self {
+            drive,
+            manifest,
+            signature,
+        })
+    }
+
+    fn sign(drive: &DriveIdentity, manifest: &ClusterManifest) -> Result<String, DiffrError> {
+        let body = toml::to_string(&SignedBody { drive, manifest })
+            .map_err(|e| DiffrError::Serialization(e.to_string()))?;
+        Ok(format!("{:x}", Sha256::digest(body.as_bytes())))
+    }
+
+    /// Check the signature against the current contents.
+    pub fn verify(&self) -> Result<bool, DiffrError> {
+        Ok(Self::sign(&self.drive, &self.manifest)? == self.signature)
+    }
+
+    /// The spec for the drive this manifest lives on, if listed.
+    pub fn own_spec(&self) -> Option<&DriveSpec> {
+        self.manifest.drives.iter().find(|d| d.identity == self.drive)
+    }
+
+    /// Path of the manifest under a drive root.
+    pub fn path_in(root: &Path) -> PathBuf {
+        root.join(".diffr").join(DRIVE_MANIFEST_FILE)
+    }
+
+    /// Write the manifest into a drive's `.diffr` directory (see
+    /// [`Drive::diffr_dir`]).
+    pub fn write_to(&self, diffr_dir: &Path) -> Result<(), DiffrError> {
+        let path = diffr_dir.join(DRIVE_MANIFEST_FILE);
+        std::fs::create_dir_all(diffr_dir)?;
+        let content =
+            toml::to_string_pretty(self).map_err(|e| DiffrError::Serialization(e.to_string()))?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Read the manifest from `<root>/.diffr/`, if present.
+    pub fn read_from(root: &Path) -> Result<Option<Self>, DiffrError> {
+        let path = Self::path_in(root);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let manifest =
+            toml::from_str(&content).map_err(|e| DiffrError::Serialization(e.to_string()))?;
+        Ok(Some(manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_toml_roundtrip_relocates_sync_root() {
+        let cluster = Cluster::new("photos".into(), Topology::Mesh, ConflictStrategy::KeepBoth);
+        let mut drive = Drive::new(DriveIdentity::new_hardware("SN1".into()), "/mnt/a".into());
+        drive.sync_root = Some("/mnt/a/photos".into());
+
+        let manifest = ClusterManifest::new(&cluster, &[drive]);
+        let text = toml::to_string_pretty(&manifest).unwrap();
+        let parsed: ClusterManifest = toml::from_str(&text).unwrap();
+
+        assert_eq!(parsed.to_cluster().id, cluster.id);
+        let moved = parsed.drives[0].to_drive(&cluster.id, Path::new("/media/b"));
+        assert_eq!(moved.sync_root, Some(PathBuf::from("/media/b/photos")));
+        assert_eq!(moved.identity.identity_string(), "SN1");
+    }
+
+    #[test]
+    fn test_drive_manifest_signature_detects_edits() {
+        let cluster = Cluster::new("docs".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/a".into());
+        let manifest = ClusterManifest::new(&cluster, std::slice::from_ref(&drive));
+
+        let mut signed = DriveManifest::new(drive.identity.clone(), manifest).unwrap();
+        assert!(signed.verify().unwrap());
+        assert!(signed.own_spec().is_some());
+
+        signed.manifest.cluster.name = "tampered".into();
+        assert!(!signed.verify().unwrap());
+    }
+}
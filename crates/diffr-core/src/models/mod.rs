@@ -2,4 +2,5 @@ pub mod archive;
 pub mod cluster;
 pub mod drive;
 pub mod file_entry;
+pub mod manifest;
 pub mod sync_state;
@@ -5,16 +5,67 @@ use uuid::Uuid;
 
 use super::cluster::ClusterId;
 use super::drive::DriveId;
+use super::file_entry::FileEntry;
+use crate::spill::SpillVec;
 
 /// A single sync operation to be performed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncOp {
     pub id: Uuid,
     pub kind: SyncOpKind,
+    /// Saved plans store paths encoded, see [`crate::path_encoding`].
+    #[serde(with = "crate::path_encoding::serde_path")]
     pub rel_path: PathBuf,
     pub source_drive: Option<DriveId>,
     pub target_drive: DriveId,
     pub size_bytes: u64,
+    /// For [`SyncOpKind::LocalCopy`]: the file on the target drive to copy
+    /// from, relative to its sync root.
+    #[serde(default, with = "crate::path_encoding::serde_opt_path")]
+    pub source_path: Option<PathBuf>,
+    /// Why the op was planned, e.g. `modified: source is newer (...)` or
+    /// the conflict rule that decided it. `None` for ops saved before
+    /// reasons were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// What the plan saw of the files the op reads and replaces, checked
+    /// again just before it runs. `None` for ops that aren't checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Precondition>,
+}
+
+/// The state a plan expects a copy, overwrite or delete to find its files
+/// in. Size and modification time are compared, not hashes, so the check
+/// costs a stat per file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Precondition {
+    /// The file being copied, as scanned.
+    pub source: Option<FileStamp>,
+    /// The file being replaced or deleted, as scanned. `None` when the
+    /// target had no file there, so one turning up since is a change too.
+    pub target: Option<FileStamp>,
+}
+
+/// A file's size and modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: DateTime<Utc>,
+}
+
+impl FileStamp {
+    pub fn of(entry: &FileEntry) -> Self {
+        FileStamp {
+            size: entry.size,
+            mtime: entry.mtime,
+        }
+    }
+}
+
+impl std::fmt::Display for FileStamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes modified {}", self.size, self.mtime.to_rfc3339())
+    }
 }
 
 /// The kind of sync operation.
@@ -29,6 +80,21 @@ pub enum SyncOpKind {
     Delete,
     /// Resolve a conflict according to the cluster's strategy.
     ResolveConflict,
+    /// Copy a file to a new name on the same drive (`source_path` ->
+    /// `rel_path`), e.g. to keep both sides of a conflict.
+    LocalCopy,
+    /// Create a directory on the target, so empty directories sync too.
+    CreateDir,
+    /// Remove an empty directory from the target, after the files in it
+    /// have been deleted.
+    RemoveDir,
+    /// Copy permission bits (and ownership, if the cluster syncs it) from
+    /// the source to the target's existing file, leaving its content alone.
+    SetPermissions,
+    /// Merge a conflicting file's two versions with the config's merge
+    /// command for its extension, and write the result to both the source
+    /// and the target.
+    Merge,
 }
 
 impl std::fmt::Display for SyncOpKind {
@@ -38,6 +104,11 @@ impl std::fmt::Display for SyncOpKind {
             SyncOpKind::Overwrite => write!(f, "overwrite"),
             SyncOpKind::Delete => write!(f, "delete"),
             SyncOpKind::ResolveConflict => write!(f, "resolve_conflict"),
+            SyncOpKind::LocalCopy => write!(f, "local_copy"),
+            SyncOpKind::CreateDir => write!(f, "create_dir"),
+            SyncOpKind::RemoveDir => write!(f, "remove_dir"),
+            SyncOpKind::SetPermissions => write!(f, "set_permissions"),
+            SyncOpKind::Merge => write!(f, "merge"),
         }
     }
 }
@@ -47,13 +118,15 @@ impl std::fmt::Display for SyncOpKind {
 pub struct SyncPlan {
     pub id: Uuid,
     pub cluster_id: ClusterId,
-    pub operations: Vec<SyncOp>,
+    /// In order. A plan for a huge tree keeps them on disk.
+    pub operations: SpillVec<SyncOp>,
     pub total_bytes: u64,
     pub created_at: DateTime<Utc>,
 }
 
 impl SyncPlan {
-    pub fn new(cluster_id: ClusterId, operations: Vec<SyncOp>) -> Self {
+    pub fn new(cluster_id: ClusterId, operations: impl Into<SpillVec<SyncOp>>) -> Self {
+        let operations = operations.into();
         let total_bytes = operations.iter().map(|op| op.size_bytes).sum();
         Self {
             id: Uuid::now_v7(),
@@ -81,6 +154,66 @@ pub struct SyncRecord {
     pub conflicts_resolved: u64,
     pub errors: Vec<String>,
     pub status: SyncStatus,
+    /// Machine the sync ran on.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// OS user who ran it.
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub diffr_version: Option<String>,
+    /// Free-form note from `diffr sync --message`.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Bytes copied from one drive to another and the time spent copying them,
+/// used to estimate how long future syncs between the pair will take.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Throughput {
+    pub source_drive: DriveId,
+    pub target_drive: DriveId,
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+impl Throughput {
+    pub fn bytes_per_sec(&self) -> f64 {
+        if self.seconds > 0.0 {
+            self.bytes as f64 / self.seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A path a cluster's syncs leave alone until `expires_at`, set with
+/// `diffr skip add`. It covers everything under it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathSkip {
+    pub cluster_id: ClusterId,
+    pub rel_path: PathBuf,
+    pub added_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl PathSkip {
+    pub fn covers(&self, rel_path: &std::path::Path) -> bool {
+        rel_path.starts_with(&self.rel_path)
+    }
+}
+
+/// An operation that a sync actually carried out, kept so the sync can be
+/// undone later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sync_id: Uuid,
+    pub op: SyncOp,
+    /// Archive of the target file taken before the op replaced or removed it.
+    pub archive_id: Option<Uuid>,
+    /// Where a trashed delete moved the file.
+    #[serde(default, with = "crate::path_encoding::serde_opt_path")]
+    pub trash_path: Option<PathBuf>,
 }
 
 /// Status of a completed sync.
@@ -90,6 +223,8 @@ pub enum SyncStatus {
     Success,
     PartialSuccess,
     Failed,
+    /// Stopped partway by the caller; what was done before then was kept.
+    Cancelled,
 }
 
 impl std::fmt::Display for SyncStatus {
@@ -98,6 +233,77 @@ impl std::fmt::Display for SyncStatus {
             SyncStatus::Success => write!(f, "success"),
             SyncStatus::PartialSuccess => write!(f, "partial_success"),
             SyncStatus::Failed => write!(f, "failed"),
+            SyncStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// A file a sync left alone, and why, for the sync's skip report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub rel_path: PathBuf,
+    /// The drive the file is on, or for a skipped operation, the drive it
+    /// would have changed.
+    pub drive_id: DriveId,
+    pub reason: SkipReason,
+}
+
+/// Why a file was left out of a sync. A skipped directory stands for
+/// everything under it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Matched `.diffrignore` or the repo's ignore patterns.
+    Ignored,
+    /// Left out by `skip_hidden`.
+    Hidden,
+    /// Left out by `skip_system`.
+    System,
+    /// A nested repo the scan stopped at, per `nested_repos`.
+    NestedRepo,
+    /// A symlink pointing back to one of its ancestors.
+    SymlinkCycle,
+    /// Couldn't be read while scanning.
+    Unreadable,
+    /// Held open or locked by another program, while scanning or copying.
+    Locked,
+    /// Excluded by `--only`, `--no-deletes` or `--no-overwrites`.
+    Filtered,
+    /// Under a path on the cluster's skip list.
+    SkipListed,
+    /// Above `large_file_threshold`, left for `--large-files`.
+    Deferred,
+    /// Wouldn't fit in the target drive's quota.
+    OverQuota,
+    /// Still being written when the sync finished.
+    Unstable,
+    /// Planned, but the file it copies or replaces had changed by the
+    /// time the operation ran.
+    Changed,
+    /// Planned, but the operation failed or was refused.
+    Failed,
+    /// Planned, but the sync was cancelled before it got to it.
+    Cancelled,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Ignored => write!(f, "ignored"),
+            SkipReason::Hidden => write!(f, "hidden"),
+            SkipReason::System => write!(f, "system"),
+            SkipReason::NestedRepo => write!(f, "nested_repo"),
+            SkipReason::SymlinkCycle => write!(f, "symlink_cycle"),
+            SkipReason::Unreadable => write!(f, "unreadable"),
+            SkipReason::Locked => write!(f, "locked"),
+            SkipReason::Filtered => write!(f, "filtered"),
+            SkipReason::SkipListed => write!(f, "skip_listed"),
+            SkipReason::Deferred => write!(f, "deferred"),
+            SkipReason::OverQuota => write!(f, "over_quota"),
+            SkipReason::Unstable => write!(f, "unstable"),
+            SkipReason::Changed => write!(f, "changed"),
+            SkipReason::Failed => write!(f, "failed"),
+            SkipReason::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -0,0 +1,130 @@
+//! Paths as text that survives any file name.
+//!
+//! File names on Linux are bytes, not necessarily UTF-8, and a database or
+//! JSON column holding `to_string_lossy()` would turn an invalid one into
+//! `U+FFFD` for good. Encoded paths keep valid UTF-8 as it is, except for
+//! `%`, and write `%` and every byte that isn't valid UTF-8 as `%XX`, so the
+//! original bytes come back. A directory's encoding is a prefix of its
+//! files', so prefix queries on encoded columns still work.
+//!
+//! On Windows, where names are UTF-16, an unpaired surrogate is escaped the
+//! same way but can't be restored, and decodes to `U+FFFD`.
+
+use std::path::{Path, PathBuf};
+
+/// Encode a path for storage.
+pub fn encode(path: &Path) -> String {
+    let mut out = String::with_capacity(path.as_os_str().len());
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' => out.push_str("%25"),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Decode a path written by [`encode`]. A `%` not followed by two hex
+/// digits is kept as it is.
+pub fn decode(s: &str) -> PathBuf {
+    if !s.contains('%') {
+        return PathBuf::from(s);
+    }
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    from_bytes(out)
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// `#[serde(with = "path_encoding::serde_path")]` for a `PathBuf` field,
+/// which serde would otherwise refuse to serialize if it isn't UTF-8.
+pub mod serde_path {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::encode(path))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(super::decode(&String::deserialize(deserializer)?))
+    }
+}
+
+/// [`serde_path`] for an `Option<PathBuf>` field.
+pub mod serde_opt_path {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::path::PathBuf;
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(p) => serializer.serialize_some(&super::encode(p)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(|s| super::decode(&s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_round_trip() {
+        for s in ["plain/file.txt", "100%/done", "%41", "caf\u{e9}/", ""] {
+            assert_eq!(decode(&encode(Path::new(s))), PathBuf::from(s));
+        }
+        assert_eq!(encode(Path::new("100%")), "100%25");
+        assert_eq!(encode(Path::new("a/b")), "a/b");
+        // Hand-written or old values with a stray `%` decode as written.
+        assert_eq!(decode("50%"), PathBuf::from("50%"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let raw = Path::new(OsStr::from_bytes(b"dir/latin1-\xe9t\xe9.txt"));
+            let encoded = encode(raw);
+            assert_eq!(encoded, "dir/latin1-%E9t%E9.txt");
+            assert!(encoded.starts_with(&encode(Path::new("dir"))));
+            assert_eq!(decode(&encoded), raw);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::cancel::CancellationToken;
+use crate::schedule::PowerSource;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What a sync does while the machine runs on battery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryPolicy {
+    /// Carry on as on AC power.
+    #[default]
+    Run,
+    /// Rest after each operation for as long as it took, running at about
+    /// half speed.
+    Throttle,
+    /// Wait for AC power before the next operation.
+    Pause,
+}
+
+impl std::str::FromStr for BatteryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "run" => Ok(BatteryPolicy::Run),
+            "throttle" => Ok(BatteryPolicy::Throttle),
+            "pause" => Ok(BatteryPolicy::Pause),
+            _ => Err(format!("unknown battery policy '{}' (allowed: run, throttle, pause)", s)),
+        }
+    }
+}
+
+impl fmt::Display for BatteryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BatteryPolicy::Run => "run",
+            BatteryPolicy::Throttle => "throttle",
+            BatteryPolicy::Pause => "pause",
+        })
+    }
+}
+
+/// How many degrees a hot CPU has to cool below `max_cpu_temp` before a
+/// paused sync goes on, so it doesn't start and stop on every reading.
+pub const COOL_DOWN_DEGREES: f32 = 5.0;
+
+/// How often a [`Pacer`] takes readings, and how often a paused one checks
+/// whether it's been cancelled.
+const POWER_CHECK_EVERY: Duration = Duration::from_secs(10);
+const PAUSE_POLL: Duration = Duration::from_millis(250);
+
+/// The machine's power source and hottest CPU sensor, in °C. `None` means
+/// it couldn't be read, which never slows a sync down.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PowerReading {
+    pub power: Option<PowerSource>,
+    pub cpu_temp: Option<f32>,
+}
+
+/// How a sync should go on after a [`PowerReading`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pace {
+    Full,
+    Throttled,
+    /// Wait, for the reason given.
+    Paused(String),
+}
+
+/// The battery and heat limits a sync runs under, and how to read the
+/// machine's state. Executing a plan checks them between operations, and
+/// scanning between the files it hashes.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerGuard {
+    pub on_battery: BatteryPolicy,
+    /// Pause while the CPU is hotter than this, in °C.
+    pub max_cpu_temp: Option<u32>,
+    /// Run at about half speed while the CPU is hotter than this, in °C.
+    pub throttle_cpu_temp: Option<u32>,
+    pub read: fn() -> PowerReading,
+}
+
+impl PowerGuard {
+    /// Whether any limit is set, so that readings are worth taking.
+    pub fn is_active(&self) -> bool {
+        self.on_battery != BatteryPolicy::Run || self.max_cpu_temp.is_some() || self.throttle_cpu_temp.is_some()
+    }
+
+    /// The pace for `reading`. `paused` says whether the sync is paused
+    /// already, in which case a hot CPU has to cool by
+    /// [`COOL_DOWN_DEGREES`] before it goes on.
+    pub fn pace(&self, reading: &PowerReading, paused: bool) -> Pace {
+        if let (Some(max), Some(temp)) = (self.max_cpu_temp, reading.cpu_temp) {
+            let limit = if paused { max as f32 - COOL_DOWN_DEGREES } else { max as f32 };
+            if temp > limit {
+                return Pace::Paused(format!("CPU at {:.0}°C (limit {}°C)", temp, max));
+            }
+        }
+        let hot = matches!((self.throttle_cpu_temp, reading.cpu_temp), (Some(limit), Some(temp)) if temp > limit as f32);
+        match (reading.power, self.on_battery) {
+            (Some(PowerSource::Battery), BatteryPolicy::Pause) => Pace::Paused("running on battery".into()),
+            (Some(PowerSource::Battery), BatteryPolicy::Throttle) => Pace::Throttled,
+            _ if hot => Pace::Throttled,
+            _ => Pace::Full,
+        }
+    }
+}
+
+/// Holds work back under a [`PowerGuard`]: waiting before each piece of
+/// work while paused, and resting after one while throttled.
+#[derive(Debug)]
+pub struct Pacer {
+    guard: Option<PowerGuard>,
+    pace: Pace,
+    read_at: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(guard: Option<PowerGuard>) -> Self {
+        Pacer {
+            guard,
+            pace: Pace::Full,
+            read_at: None,
+        }
+    }
+
+    /// Take a reading if the last is more than [`POWER_CHECK_EVERY`] old.
+    fn refresh(&mut self, guard: &PowerGuard) {
+        if self.read_at.is_some_and(|t| t.elapsed() < POWER_CHECK_EVERY) {
+            return;
+        }
+        let paused = matches!(self.pace, Pace::Paused(_));
+        self.pace = guard.pace(&(guard.read)(), paused);
+        self.read_at = Some(Instant::now());
+    }
+
+    /// Return once the next piece of work can run, or `cancel` is
+    /// cancelled. `on_pause` is told why before waiting. Returns whether
+    /// it waited.
+    pub fn wait(&mut self, cancel: &CancellationToken, on_pause: impl FnOnce(&str)) -> bool {
+        let Some(guard) = self.guard else { return false };
+        self.refresh(&guard);
+        let Pace::Paused(reason) = &self.pace else { return false };
+        on_pause(reason);
+        while matches!(self.pace, Pace::Paused(_)) && !cancel.is_cancelled() {
+            std::thread::sleep(PAUSE_POLL);
+            self.refresh(&guard);
+        }
+        true
+    }
+
+    /// Rest after work that took `took`, while throttled.
+    pub fn rest(&self, took: Duration, cancel: &CancellationToken) {
+        if self.pace == Pace::Throttled {
+            cancel.sleep(took.min(POWER_CHECK_EVERY));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pace() {
+        let guard = PowerGuard {
+            on_battery: BatteryPolicy::Throttle,
+            max_cpu_temp: Some(85),
+            throttle_cpu_temp: Some(75),
+            read: PowerReading::default,
+        };
+        let reading = |power, cpu_temp| PowerReading { power, cpu_temp };
+        assert_eq!(guard.pace(&reading(None, None), false), Pace::Full);
+        assert_eq!(guard.pace(&reading(Some(PowerSource::Battery), Some(60.0)), false), Pace::Throttled);
+        assert_eq!(
+            guard.pace(&reading(Some(PowerSource::Ac), Some(90.0)), false),
+            Pace::Paused("CPU at 90°C (limit 85°C)".into())
+        );
+        // A paused sync waits for the CPU to cool a little below the limit.
+        assert!(matches!(guard.pace(&reading(None, Some(83.0)), true), Pace::Paused(_)));
+        assert_eq!(guard.pace(&reading(None, Some(83.0)), false), Pace::Throttled);
+        assert_eq!(guard.pace(&reading(Some(PowerSource::Ac), Some(70.0)), false), Pace::Full);
+
+        let pause = PowerGuard {
+            on_battery: BatteryPolicy::Pause,
+            max_cpu_temp: None,
+            throttle_cpu_temp: None,
+            ..guard
+        };
+        assert_eq!(
+            pause.pace(&reading(Some(PowerSource::Battery), Some(99.0)), false),
+            Pace::Paused("running on battery".into())
+        );
+        assert!(!PowerGuard { on_battery: BatteryPolicy::Run, ..pause }.is_active());
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::sync_state::SyncOp;
+
+/// How far along a scan, a bulk hash or a plan's execution is.
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressEvent<'a> {
+    ScanStarted { root: &'a Path },
+    /// Another entry was scanned; the counts are for the scan so far.
+    ScanProgress { files: u64, dirs: u64 },
+    ScanFinished { files: u64, dirs: u64, bytes: u64 },
+    HashStarted { files: u64 },
+    HashProgress { done: u64, files: u64 },
+    HashFinished { files: u64 },
+    ExecStarted { ops: u64 },
+    /// The `index`th operation of the plan is starting.
+    OpStarted { index: u64, op: &'a SyncOp },
+    /// How much of the file an operation replaces has been archived.
+    OpBytes { op: &'a SyncOp, done: u64, total: u64 },
+    /// An operation is done with, whether it succeeded (`ok`) or failed or
+    /// was refused.
+    OpFinished { index: u64, op: &'a SyncOp, ok: bool },
+    /// Execution is waiting between operations, for the reason given, such
+    /// as a hot CPU.
+    ExecPaused { reason: &'a str },
+    ExecResumed,
+    ExecFinished { ops: u64 },
+}
+
+/// Where scans, hashing and execution report their progress: a terminal's
+/// progress bars, the CLI's JSON output, or nowhere. Called from whichever
+/// thread is doing the work.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: &ProgressEvent<'_>);
+}
+
+/// A [`ProgressSink`] to hand to scans and syncs, reporting nowhere by
+/// default. Clones report to the same sink.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn ProgressSink>);
+
+impl Progress {
+    pub fn new(sink: impl ProgressSink + 'static) -> Self {
+        Progress(Arc::new(sink))
+    }
+
+    /// Progress that isn't reported.
+    pub fn none() -> Self {
+        Self::new(NoProgress)
+    }
+
+    pub fn event(&self, event: ProgressEvent<'_>) {
+        self.0.event(&event)
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn event(&self, _: &ProgressEvent<'_>) {}
+}
+
+/// How often [`JsonProgress`] writes the events that come once per file.
+const JSON_PROGRESS_EVERY: Duration = Duration::from_secs(1);
+
+/// Writes each event as a line of JSON, such as
+/// `{"event": "op_started", "index": 3, "kind": "copy_new", "path": "a.txt"}`.
+/// Events that come once per entry or chunk (`scan_progress`,
+/// `hash_progress`, `op_bytes`) are written at most once a second.
+pub struct JsonProgress {
+    out: Mutex<(Box<dyn Write + Send>, Option<Instant>)>,
+}
+
+impl JsonProgress {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        JsonProgress {
+            out: Mutex::new((Box::new(out), None)),
+        }
+    }
+
+    /// Write to stderr, keeping stdout for the command's own output.
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+}
+
+impl ProgressSink for JsonProgress {
+    fn event(&self, event: &ProgressEvent<'_>) {
+        let frequent = matches!(
+            event,
+            ProgressEvent::ScanProgress { .. } | ProgressEvent::HashProgress { .. } | ProgressEvent::OpBytes { .. }
+        );
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let (writer, last) = &mut *out;
+        if frequent {
+            if last.is_some_and(|t| t.elapsed() < JSON_PROGRESS_EVERY) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        // Progress is best-effort; a closed stderr mustn't stop a sync.
+        let _ = writeln!(writer, "{}", json_line(event));
+    }
+}
+
+fn json_line(event: &ProgressEvent<'_>) -> String {
+    let op_fields = |op: &SyncOp| {
+        format!(
+            "\"kind\": \"{}\", \"path\": \"{}\"",
+            op.kind,
+            json_escape(&crate::path_encoding::encode(&op.rel_path))
+        )
+    };
+    match event {
+        ProgressEvent::ScanStarted { root } => format!(
+            "{{\"event\": \"scan_started\", \"root\": \"{}\"}}",
+            json_escape(&crate::path_encoding::encode(root))
+        ),
+        ProgressEvent::ScanProgress { files, dirs } => {
+            format!("{{\"event\": \"scan_progress\", \"files\": {}, \"dirs\": {}}}", files, dirs)
+        }
+        ProgressEvent::ScanFinished { files, dirs, bytes } => format!(
+            "{{\"event\": \"scan_finished\", \"files\": {}, \"dirs\": {}, \"bytes\": {}}}",
+            files, dirs, bytes
+        ),
+        ProgressEvent::HashStarted { files } => format!("{{\"event\": \"hash_started\", \"files\": {}}}", files),
+        ProgressEvent::HashProgress { done, files } => {
+            format!("{{\"event\": \"hash_progress\", \"done\": {}, \"files\": {}}}", done, files)
+        }
+        ProgressEvent::HashFinished { files } => format!("{{\"event\": \"hash_finished\", \"files\": {}}}", files),
+        ProgressEvent::ExecStarted { ops } => format!("{{\"event\": \"exec_started\", \"ops\": {}}}", ops),
+        ProgressEvent::OpStarted { index, op } => {
+            format!("{{\"event\": \"op_started\", \"index\": {}, {}}}", index, op_fields(op))
+        }
+        ProgressEvent::OpBytes { op, done, total } => format!(
+            "{{\"event\": \"op_bytes\", {}, \"done\": {}, \"total\": {}}}",
+            op_fields(op),
+            done,
+            total
+        ),
+        ProgressEvent::OpFinished { index, op, ok } => format!(
+            "{{\"event\": \"op_finished\", \"index\": {}, {}, \"ok\": {}}}",
+            index,
+            op_fields(op),
+            ok
+        ),
+        ProgressEvent::ExecPaused { reason } => {
+            format!("{{\"event\": \"exec_paused\", \"reason\": \"{}\"}}", json_escape(reason))
+        }
+        ProgressEvent::ExecResumed => "{\"event\": \"exec_resumed\"}".to_string(),
+        ProgressEvent::ExecFinished { ops } => format!("{{\"event\": \"exec_finished\", \"ops\": {}}}", ops),
+    }
+}
+
+/// Quote `s` for a JSON string. Unlike the CLI's other output, event lines
+/// are read by programs as they arrive, so a quote in a file name mustn't
+/// break one.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::drive::DriveId;
+    use crate::models::sync_state::SyncOpKind;
+
+    /// A writer whose output the test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_progress() {
+        let out = Shared::default();
+        let progress = Progress::new(JsonProgress::new(out.clone()));
+        let op = SyncOp {
+            id: uuid::Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: "say \"hi\".txt".into(),
+            source_drive: None,
+            target_drive: DriveId::new(),
+            size_bytes: 2,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        progress.event(ProgressEvent::ScanProgress { files: 1, dirs: 0 });
+        progress.event(ProgressEvent::ScanProgress { files: 2, dirs: 0 });
+        progress.event(ProgressEvent::OpStarted { index: 0, op: &op });
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "{\"event\": \"scan_progress\", \"files\": 1, \"dirs\": 0}",
+                "{\"event\": \"op_started\", \"index\": 0, \"kind\": \"copy_new\", \"path\": \"say \\\"hi\\\".txt\"}",
+            ]
+        );
+    }
+}
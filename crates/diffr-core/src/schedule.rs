@@ -0,0 +1,206 @@
+use chrono::{NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Hours of the day, in local time, when scheduled syncs may run, written
+/// `"01:00-06:00"`. A window that ends before it starts runs past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SyncWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl SyncWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// The first time after `now` that the window closes.
+    pub fn closes_after(&self, now: NaiveDateTime) -> NaiveDateTime {
+        let today = now.date().and_time(self.end);
+        if today > now {
+            today
+        } else {
+            today + chrono::Duration::days(1)
+        }
+    }
+}
+
+impl std::str::FromStr for SyncWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once(['-', '–'])
+            .ok_or_else(|| format!("expected a window like \"01:00-06:00\": {s}"))?;
+        let time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|_| format!("expected a time like 01:00: {}", t.trim()))
+        };
+        let window = SyncWindow {
+            start: time(start)?,
+            end: time(end)?,
+        };
+        if window.start == window.end {
+            return Err(format!("the window is empty: {s}"));
+        }
+        Ok(window)
+    }
+}
+
+impl fmt::Display for SyncWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+impl TryFrom<String> for SyncWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SyncWindow> for String {
+    fn from(window: SyncWindow) -> Self {
+        window.to_string()
+    }
+}
+
+/// Where the machine is drawing power from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+/// When `diffr sync --scheduled` may run, from `[schedule]` in
+/// `config.toml`. Manual syncs ignore it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// Only start, or keep running, inside these hours.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<SyncWindow>,
+    /// Only start, or keep running, on AC power.
+    pub require_ac_power: bool,
+    /// Only start when the machine is idle: its load average is below half
+    /// a CPU per core. A running sync is never stopped for it, since the
+    /// sync is itself load.
+    pub require_idle: bool,
+}
+
+/// What the machine is doing, as far as a [`Schedule`] cares. `None` means
+/// it couldn't be told, which never holds a sync back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostState {
+    pub time: NaiveTime,
+    pub power: Option<PowerSource>,
+    /// The 1-minute load average divided by the number of CPUs.
+    pub load_per_cpu: Option<f64>,
+}
+
+/// The load per CPU below which the machine counts as idle.
+pub const IDLE_LOAD_PER_CPU: f64 = 0.5;
+
+impl Schedule {
+    pub fn is_unconstrained(&self) -> bool {
+        self.window.is_none() && !self.require_ac_power && !self.require_idle
+    }
+
+    /// Why a scheduled sync can't start in `host`, or `None` if it can.
+    pub fn blocks_start(&self, host: &HostState) -> Option<String> {
+        if let Some(reason) = self.blocks_running(host) {
+            return Some(reason);
+        }
+        match host.load_per_cpu {
+            Some(load) if self.require_idle && load >= IDLE_LOAD_PER_CPU => {
+                Some(format!("the machine is busy (load {:.2} per CPU)", load))
+            }
+            _ => None,
+        }
+    }
+
+    /// Why a scheduled sync that's running should stop in `host`, or `None`
+    /// if it can go on.
+    pub fn blocks_running(&self, host: &HostState) -> Option<String> {
+        if let Some(window) = self.window {
+            if !window.contains(host.time) {
+                return Some(format!("outside the sync window {}", window));
+            }
+        }
+        if self.require_ac_power && host.power == Some(PowerSource::Battery) {
+            return Some("running on battery".into());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> HostState {
+        HostState {
+            time: NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+            power: None,
+            load_per_cpu: None,
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let night: SyncWindow = "23:00-06:00".parse().unwrap();
+        assert_eq!(night.to_string(), "23:00-06:00");
+        assert!(night.contains(at("23:30").time));
+        assert!(night.contains(at("05:59").time));
+        assert!(!night.contains(at("06:00").time));
+        assert!(!night.contains(at("12:00").time));
+        let early: SyncWindow = "01:00 – 06:00".parse().unwrap();
+        assert!(early.contains(at("01:00").time) && !early.contains(at("00:59").time));
+
+        let closes = |now: &str| {
+            let now = NaiveDateTime::parse_from_str(now, "%Y-%m-%d %H:%M").unwrap();
+            night.closes_after(now).format("%Y-%m-%d %H:%M").to_string()
+        };
+        assert_eq!(closes("2026-03-01 23:30"), "2026-03-02 06:00");
+        assert_eq!(closes("2026-03-02 01:00"), "2026-03-02 06:00");
+
+        assert!("01:00".parse::<SyncWindow>().is_err());
+        assert!("1am-6am".parse::<SyncWindow>().is_err());
+        assert!("03:00-03:00".parse::<SyncWindow>().is_err());
+    }
+
+    #[test]
+    fn test_blocks() {
+        let schedule = Schedule {
+            window: Some("01:00-06:00".parse().unwrap()),
+            require_ac_power: true,
+            require_idle: true,
+        };
+        assert_eq!(schedule.blocks_start(&at("02:00")), None);
+        assert_eq!(
+            schedule.blocks_start(&at("07:00")).as_deref(),
+            Some("outside the sync window 01:00-06:00")
+        );
+        let on_battery = HostState {
+            power: Some(PowerSource::Battery),
+            ..at("02:00")
+        };
+        assert_eq!(schedule.blocks_running(&on_battery).as_deref(), Some("running on battery"));
+        // Busy machines don't start syncs but don't stop them either.
+        let busy = HostState {
+            load_per_cpu: Some(1.5),
+            ..at("02:00")
+        };
+        assert!(schedule.blocks_start(&busy).is_some());
+        assert_eq!(schedule.blocks_running(&busy), None);
+        assert_eq!(Schedule::default().blocks_start(&on_battery), None);
+    }
+}
@@ -0,0 +1,529 @@
+//! Lists that move to disk once they grow too big to hold in memory.
+//!
+//! A sync of a tree with tens of millions of files plans about as many
+//! operations, and journals as many again. A [`SpillVec`] keeps them in
+//! memory while there are few, and past [`SPILL_AFTER`] moves them to a
+//! table in a temporary SQLite database a batch at a time, so only the
+//! latest batch and SQLite's page cache are in memory.
+
+use rusqlite::{params, Connection};
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::error::DiffrError;
+
+/// How many items a [`SpillVec`] holds in memory before it moves them to
+/// disk.
+pub const SPILL_AFTER: usize = 100_000;
+
+/// Items read from disk at a time.
+const PAGE: usize = 1_000;
+
+/// A list that keeps its items in memory until it has [`SPILL_AFTER`] of
+/// them, and then in a temporary database on disk. Items are read back a
+/// page at a time, in order.
+///
+/// The database is a file SQLite makes in `SQLITE_TMPDIR` or `TMPDIR`
+/// (`/var/tmp` or `/tmp` otherwise) and deletes when the list is dropped.
+/// Failing to write or read it, such as when that disk is full, panics, as
+/// running out of memory does for a `Vec`.
+pub struct SpillVec<T> {
+    /// Items moved to disk, which come before those in `tail`.
+    disk: Option<Table>,
+    tail: Vec<T>,
+    spill_after: usize,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> SpillVec<T> {
+    pub fn new() -> Self {
+        Self::spilling_after(SPILL_AFTER)
+    }
+
+    /// A list that moves to disk once it has `spill_after` items. Lists
+    /// taken from it, and sorted or filtered copies, keep the limit.
+    pub fn spilling_after(spill_after: usize) -> Self {
+        SpillVec {
+            disk: None,
+            tail: Vec::new(),
+            spill_after: spill_after.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.disk.as_ref().map_or(0, |d| d.len(0)) + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether any items have moved to disk.
+    pub fn is_spilled(&self) -> bool {
+        self.disk.is_some()
+    }
+
+    /// How many items the list holds in memory before moving them to disk.
+    pub fn spill_after(&self) -> usize {
+        self.spill_after
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.tail.push(item);
+        if self.tail.len() >= self.spill_after {
+            self.spill();
+        }
+    }
+
+    /// Move the items still in memory to disk.
+    fn spill(&mut self) {
+        let disk = self.disk.get_or_insert_with(Table::new);
+        disk.append(0, self.tail.drain(..));
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            disk: self.disk.as_ref(),
+            next: 0,
+            page: Vec::new().into_iter(),
+            tail: self.tail.iter(),
+        }
+    }
+
+    /// The items, leaving the list empty with the same limit.
+    pub fn take(&mut self) -> Self {
+        std::mem::replace(self, Self::spilling_after(self.spill_after))
+    }
+
+    /// Keep only the items `keep` returns true for, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        if self.disk.is_none() {
+            self.tail.retain(keep);
+            return;
+        }
+        for item in self.take() {
+            if keep(&item) {
+                self.push(item);
+            }
+        }
+    }
+
+    /// Take out the items `remove` returns true for, keeping the rest.
+    /// Both keep their order.
+    pub fn remove_where(&mut self, mut remove: impl FnMut(&T) -> bool) -> Self {
+        let mut removed = Self::spilling_after(self.spill_after);
+        for item in self.take() {
+            if remove(&item) {
+                removed.push(item);
+            } else {
+                self.push(item);
+            }
+        }
+        removed
+    }
+
+    /// Sort the items by `key`, keeping the order of items with equal keys.
+    /// Once on disk, they're sorted a batch at a time into runs, which are
+    /// then merged, so no more than a batch is in memory at once.
+    pub fn sort_by_cached_key<K: Ord>(&mut self, mut key: impl FnMut(&T) -> K) {
+        if self.disk.is_none() {
+            self.tail.sort_by_cached_key(key);
+            return;
+        }
+        let mut runs = Table::new();
+        let mut count = 0;
+        let mut batch = Vec::with_capacity(self.spill_after);
+        let mut sort_run = |batch: &mut Vec<T>, runs: &mut Table| {
+            batch.sort_by_cached_key(&mut key);
+            count += 1;
+            runs.append(count, batch.drain(..));
+        };
+        for item in self.take() {
+            batch.push(item);
+            if batch.len() == self.spill_after {
+                sort_run(&mut batch, &mut runs);
+            }
+        }
+        if !batch.is_empty() {
+            sort_run(&mut batch, &mut runs);
+        }
+
+        // Merge by each run's next item. A tie goes to the earlier run,
+        // whose items came first.
+        let mut cursors: Vec<Cursor<T>> = (1..=count).map(Cursor::new).collect();
+        let mut heads = BinaryHeap::new();
+        for (i, cursor) in cursors.iter_mut().enumerate() {
+            if let Some(item) = cursor.next(&runs) {
+                heads.push(Reverse((key(&item), i, Slot(item))));
+            }
+        }
+        while let Some(Reverse((_, i, Slot(item)))) = heads.pop() {
+            self.push(item);
+            if let Some(next) = cursors[i].next(&runs) {
+                heads.push(Reverse((key(&next), i, Slot(next))));
+            }
+        }
+    }
+
+    /// The items as a `Vec`, for lists known to be small.
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().map(Cow::into_owned).collect()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Default for SpillVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Clone for SpillVec<T> {
+    fn clone(&self) -> Self {
+        let mut copy = Self::spilling_after(self.spill_after);
+        copy.extend(self.iter().map(Cow::into_owned));
+        copy
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone + std::fmt::Debug> std::fmt::Debug for SpillVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> From<Vec<T>> for SpillVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        let mut list = Self::new();
+        list.tail = items;
+        if list.tail.len() >= list.spill_after {
+            list.spill();
+        }
+        list
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> FromIterator<T> for SpillVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        let mut list = Self::new();
+        list.extend(items);
+        list
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Extend<T> for SpillVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        for item in items {
+            self.push(item);
+        }
+    }
+}
+
+impl<'a, T: Serialize + DeserializeOwned + Clone> IntoIterator for &'a SpillVec<T> {
+    type Item = Cow<'a, T>;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> IntoIterator for SpillVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            cursor: Cursor::new(0),
+            disk: self.disk,
+            tail: self.tail.into_iter(),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Serialize for SpillVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T: Serialize + DeserializeOwned + Clone> Deserialize<'de> for SpillVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Items<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: Serialize + DeserializeOwned + Clone> Visitor<'de> for Items<T> {
+            type Value = SpillVec<T>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a list")
+            }
+
+            // Pushed as they're read, so a long list spills on the way in.
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SpillVec<T>, A::Error> {
+                let mut list = SpillVec::new();
+                while let Some(item) = seq.next_element()? {
+                    list.push(item);
+                }
+                Ok(list)
+            }
+        }
+
+        deserializer.deserialize_seq(Items(std::marker::PhantomData))
+    }
+}
+
+/// Iterator over a [`SpillVec`]'s items: read from disk, or borrowed from
+/// memory.
+pub struct Iter<'a, T> {
+    disk: Option<&'a Table>,
+    next: usize,
+    page: std::vec::IntoIter<T>,
+    tail: std::slice::Iter<'a, T>,
+}
+
+impl<'a, T: DeserializeOwned + Clone> Iterator for Iter<'a, T> {
+    type Item = Cow<'a, T>;
+
+    fn next(&mut self) -> Option<Cow<'a, T>> {
+        if let Some(item) = self.page.next() {
+            return Some(Cow::Owned(item));
+        }
+        if let Some(disk) = self.disk.filter(|d| self.next < d.len(0)) {
+            let page = disk.page(0, self.next);
+            self.next += page.len();
+            self.page = page.into_iter();
+            return self.page.next().map(Cow::Owned);
+        }
+        self.tail.next().map(Cow::Borrowed)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let on_disk = self.disk.map_or(0, |d| d.len(0) - self.next);
+        let left = on_disk + self.page.len() + self.tail.len();
+        (left, Some(left))
+    }
+}
+
+/// Owning iterator over a [`SpillVec`]'s items.
+pub struct IntoIter<T> {
+    disk: Option<Table>,
+    cursor: Cursor<T>,
+    tail: std::vec::IntoIter<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if let Some(item) = self.disk.as_ref().and_then(|d| self.cursor.next(d)) {
+            return Some(item);
+        }
+        // Done with the disk, so let its file go.
+        self.disk = None;
+        self.tail.next()
+    }
+}
+
+/// The database a list spilled to. Its items are numbered from 0 within
+/// each run: the list's own are run 0, and a sort writes its sorted
+/// batches as runs 1 and up.
+struct Table {
+    conn: Connection,
+    lens: Vec<usize>,
+}
+
+impl Table {
+    fn new() -> Self {
+        Self::open().expect("failed to create a temporary database to spill to")
+    }
+
+    fn open() -> rusqlite::Result<Self> {
+        // An empty name is a private database in a temporary file. Nothing
+        // in it outlives the process, so it needn't survive a crash.
+        let conn = Connection::open("")?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             CREATE TABLE items (
+                 run INTEGER NOT NULL,
+                 seq INTEGER NOT NULL,
+                 item TEXT NOT NULL,
+                 PRIMARY KEY (run, seq)
+             ) WITHOUT ROWID;",
+        )?;
+        Ok(Table { conn, lens: Vec::new() })
+    }
+
+    fn len(&self, run: usize) -> usize {
+        self.lens.get(run).copied().unwrap_or(0)
+    }
+
+    fn append<T: Serialize>(&mut self, run: usize, items: impl Iterator<Item = T>) {
+        if self.lens.len() <= run {
+            self.lens.resize(run + 1, 0);
+        }
+        let start = self.lens[run];
+        let added = self
+            .try_append(run, start, items)
+            .expect("failed to write to the temporary database spilled to");
+        self.lens[run] += added;
+    }
+
+    fn try_append<T: Serialize>(
+        &self,
+        run: usize,
+        start: usize,
+        items: impl Iterator<Item = T>,
+    ) -> Result<usize, DiffrError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut added = 0;
+        {
+            let mut insert = tx.prepare_cached("INSERT INTO items (run, seq, item) VALUES (?1, ?2, ?3)")?;
+            for item in items {
+                let json = serde_json::to_string(&item).map_err(|e| DiffrError::Serialization(e.to_string()))?;
+                insert.execute(params![run as i64, (start + added) as i64, json])?;
+                added += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Up to a page of `run`'s items from `from` on.
+    fn page<T: DeserializeOwned>(&self, run: usize, from: usize) -> Vec<T> {
+        self.try_page(run, from)
+            .expect("failed to read from the temporary database spilled to")
+    }
+
+    fn try_page<T: DeserializeOwned>(&self, run: usize, from: usize) -> Result<Vec<T>, DiffrError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT item FROM items WHERE run = ?1 AND seq >= ?2 ORDER BY seq LIMIT ?3")?;
+        let mut rows = stmt.query(params![run as i64, from as i64, PAGE as i64])?;
+        let mut page = Vec::with_capacity(PAGE);
+        while let Some(row) = rows.next()? {
+            let json = row.get::<_, String>(0)?;
+            page.push(serde_json::from_str(&json).map_err(|e| DiffrError::Serialization(e.to_string()))?);
+        }
+        Ok(page)
+    }
+}
+
+/// Where reading one run of a [`Table`] is up to.
+struct Cursor<T> {
+    run: usize,
+    next: usize,
+    page: std::vec::IntoIter<T>,
+}
+
+impl<T: DeserializeOwned> Cursor<T> {
+    fn new(run: usize) -> Self {
+        Cursor {
+            run,
+            next: 0,
+            page: Vec::new().into_iter(),
+        }
+    }
+
+    fn next(&mut self, table: &Table) -> Option<T> {
+        if let Some(item) = self.page.next() {
+            return Some(item);
+        }
+        if self.next >= table.len(self.run) {
+            return None;
+        }
+        let page: Vec<T> = table.page(self.run, self.next);
+        self.next += page.len();
+        self.page = page.into_iter();
+        self.page.next()
+    }
+}
+
+/// An item in the merge heap, which is ordered by key and run alone.
+struct Slot<T>(T);
+
+impl<T> PartialEq for Slot<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Slot<T> {}
+
+impl<T> PartialOrd for Slot<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Slot<T> {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spilled(items: impl IntoIterator<Item = u32>) -> SpillVec<u32> {
+        let mut list = SpillVec::spilling_after(3);
+        list.extend(items);
+        list
+    }
+
+    #[test]
+    fn test_spills_past_the_limit_and_reads_back_in_order() {
+        let mut list = SpillVec::spilling_after(3);
+        list.extend([1, 2]);
+        assert!(!list.is_spilled());
+        list.extend(3..=3000);
+        assert!(list.is_spilled());
+        assert_eq!(list.len(), 3000);
+
+        let read: Vec<u32> = list.iter().map(Cow::into_owned).collect();
+        assert_eq!(read, (1..=3000).collect::<Vec<_>>());
+        assert_eq!(list.iter().size_hint(), (3000, Some(3000)));
+        assert_eq!(list.clone().into_iter().collect::<Vec<_>>(), read);
+    }
+
+    #[test]
+    fn test_retain_and_take_keep_the_limit() {
+        let mut list = spilled(1..=10);
+        list.retain(|n| n % 2 == 0);
+        assert_eq!(list.to_vec(), vec![2, 4, 6, 8, 10]);
+        assert!(list.is_spilled());
+
+        let odd = list.remove_where(|n| n % 4 != 0);
+        assert_eq!((list.to_vec(), odd.to_vec()), (vec![4, 8], vec![2, 6, 10]));
+
+        let taken = list.take();
+        assert!(list.is_empty());
+        assert_eq!(taken.len(), 2);
+        list.extend([1, 2, 3]);
+        assert!(list.is_spilled());
+    }
+
+    #[test]
+    fn test_sort_merges_runs_stably() {
+        // Sorted by tens, so equal keys show whether their order was kept.
+        let items: Vec<u32> = vec![31, 12, 35, 10, 21, 18, 33, 25, 14, 11];
+        let mut list = spilled(items.clone());
+        list.sort_by_cached_key(|n| n / 10);
+
+        let mut expected = items;
+        expected.sort_by_key(|n| n / 10);
+        assert_eq!(list.to_vec(), expected);
+        assert_eq!(list.to_vec(), vec![12, 10, 18, 14, 11, 21, 25, 31, 35, 33]);
+    }
+
+    #[test]
+    fn test_serializes_as_a_list() {
+        let list = spilled(1..=5);
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(json, "[1,2,3,4,5]");
+        let back: SpillVec<u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.to_vec(), list.to_vec());
+    }
+}
@@ -0,0 +1,137 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+/// Work running on a thread of its own, such as a scan or a sync, for
+/// callers like the daemon and the RPC server that can't block on it. A
+/// `Task` is a [`Future`], so it can be awaited from any async runtime, or
+/// it can be polled with [`Task::is_finished`] and waited on with
+/// [`Task::wait`]. Scanning, hashing and copying are blocking file I/O
+/// either way; the thread is what keeps them off the caller's.
+#[derive(Debug)]
+pub struct Task<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    /// Taken once it's been returned.
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Run `f` on a new thread. If it panics, the panic is passed on to
+/// whoever waits for or awaits the task.
+pub fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Task<T> {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    let theirs = Arc::clone(&shared);
+    std::thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let mut state = theirs.lock();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        theirs.done.notify_all();
+    });
+    Task { shared }
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Task<T> {
+    pub fn is_finished(&self) -> bool {
+        self.shared.lock().result.is_some()
+    }
+
+    /// Block until the task finishes, and return what it returned.
+    pub fn wait(self) -> T {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(result) = state.result.take() {
+                return unwrap(result);
+            }
+            state = self.shared.done.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.shared.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(unwrap(result)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn unwrap<T>(result: std::thread::Result<T>) -> T {
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::task::Wake;
+
+    /// Wakes the test's thread up to poll again.
+    struct Notify(mpsc::Sender<()>);
+
+    impl Wake for Notify {
+        fn wake(self: Arc<Self>) {
+            let _ = self.0.send(());
+        }
+    }
+
+    #[test]
+    fn test_task_waits_and_awaits() {
+        let (release, gate) = mpsc::channel::<()>();
+        let task = spawn(move || {
+            gate.recv().unwrap();
+            42
+        });
+        assert!(!task.is_finished());
+        release.send(()).unwrap();
+        assert_eq!(task.wait(), 42);
+
+        let (tx, woken) = mpsc::channel();
+        let waker = Waker::from(Arc::new(Notify(tx)));
+        let mut cx = Context::from_waker(&waker);
+        let mut task = spawn(|| "done");
+        let value = loop {
+            match Pin::new(&mut task).poll(&mut cx) {
+                Poll::Ready(value) => break value,
+                Poll::Pending => woken.recv().unwrap(),
+            }
+        };
+        assert_eq!(value, "done");
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| spawn(|| panic!("boom")).wait()));
+        assert!(panicked.is_err());
+    }
+}
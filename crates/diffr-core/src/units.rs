@@ -0,0 +1,187 @@
+//! Byte sizes and durations as people write them, such as `200GB`,
+//! `1.5 MiB`, `90s` or `2h 30m`, for command-line flags and settings.
+
+use std::time::Duration;
+
+const KB: u64 = 1024;
+const MB: u64 = KB * 1024;
+const GB: u64 = MB * 1024;
+const TB: u64 = GB * 1024;
+
+/// Parse a byte size: a number of bytes, or a number with a unit such as
+/// `512K`, `50MB` or `1.5 TiB`. Units are powers of 1024, as
+/// [`format_bytes`] shows them, so `MB` and `MiB` mean the same.
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let expected = || format!("expected a size like 200GB, got '{}'", s);
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => KB,
+        "m" | "mb" | "mib" => MB,
+        "g" | "gb" | "gib" => GB,
+        "t" | "tb" | "tib" => TB,
+        _ => return Err(format!("unknown unit '{}' in '{}' (use B, KB, MB, GB or TB)", unit.trim(), s)),
+    };
+    if let Ok(n) = number.parse::<u64>() {
+        return n.checked_mul(multiplier).ok_or_else(|| format!("'{}' is too large", s));
+    }
+    let n: f64 = number.parse().map_err(|_| expected())?;
+    let bytes = (n * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("'{}' is too large", s));
+    }
+    Ok(bytes as u64)
+}
+
+/// Parse a transfer rate in bytes per second: a size as [`parse_bytes`]
+/// takes it, optionally followed by `/s`, such as `50MB/s`.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let size = text.strip_suffix("/s").unwrap_or(text);
+    match parse_bytes(size)? {
+        0 => Err(format!("'{}' would never finish; give a rate above zero", s)),
+        rate => Ok(rate),
+    }
+}
+
+/// Format a byte size for display, e.g. `512 B` or `1.5 GB`.
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes >= TB {
+        format!("{:.1} TB", bytes as f64 / TB as f64)
+    } else if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Parse a duration: a number of seconds, or one or more numbers with
+/// units `s`, `m`, `h`, `d` or `w`, such as `90s`, `7d` or `2h 30m`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(format!("expected a duration like 30m or 7d, got '{}'", s));
+    }
+    let mut total = 0u64;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let n: u64 = number
+            .parse()
+            .map_err(|_| format!("expected a duration like 30m or 7d, got '{}'", s))?;
+        let split = tail
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(split);
+        let secs = match unit {
+            "" | "s" | "sec" => 1,
+            "m" | "min" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return Err(format!("unknown unit '{}' in '{}' (use s, m, h, d or w)", unit, s)),
+        };
+        total = n
+            .checked_mul(secs)
+            .and_then(|n| total.checked_add(n))
+            .ok_or_else(|| format!("'{}' is too long", s))?;
+        rest = tail.trim_start();
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Format a duration for display, e.g. `45s`, `3m 20s` or `2h 05m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs.max(1))
+    } else if secs < 3600 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// `#[serde(with = "units::serde_opt_bytes")]` for an `Option<u64>` of
+/// bytes that hand-written files can give as a number or a string such as
+/// `"200GB"`. Written back as a number.
+pub mod serde_opt_bytes {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        bytes.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        match Option::<super::NumberOrText>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(super::NumberOrText::Number(n)) => Ok(Some(n)),
+            Some(super::NumberOrText::Text(s)) => super::parse_bytes(&s).map(Some).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// [`serde_opt_bytes`] for an `Option<u64>` of seconds, which can be given
+/// as a string such as `"10m"`.
+pub mod serde_opt_secs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(secs: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        secs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        match Option::<super::NumberOrText>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(super::NumberOrText::Number(n)) => Ok(Some(n)),
+            Some(super::NumberOrText::Text(s)) => super::parse_duration(&s)
+                .map(|d| Some(d.as_secs()))
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum NumberOrText {
+    Number(u64),
+    Text(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_units() {
+        assert_eq!(parse_bytes("4096"), Ok(4096));
+        assert_eq!(parse_bytes("200GB"), Ok(200 * GB));
+        assert_eq!(parse_bytes("1.5 MiB"), Ok(MB + MB / 2));
+        assert_eq!(parse_bytes("512k"), Ok(512 * KB));
+        assert!(parse_bytes("12 parsecs").is_err());
+        assert!(parse_bytes("GB").is_err());
+        assert_eq!(parse_bytes(&format_bytes(3 * GB)), Ok(3 * GB));
+        assert_eq!(parse_rate("50MB/s"), Ok(50 * MB));
+        assert_eq!(parse_rate("512K"), Ok(512 * KB));
+        assert!(parse_rate("0/s").is_err());
+
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_duration("2h 05m"), Ok(Duration::from_secs(7500)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("3 fortnights").is_err());
+        assert!(parse_duration("").is_err());
+
+        assert_eq!(format_duration(Duration::from_secs(13)), "13s");
+        assert_eq!(format_duration(Duration::from_secs(200)), "3m 20s");
+        assert_eq!(format_duration(Duration::from_secs(7500)), "2h 05m");
+    }
+}
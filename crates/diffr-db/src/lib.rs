@@ -1,3 +1,4 @@
+pub mod merge;
 pub mod migration;
 pub mod ops;
 pub mod schema;
@@ -9,6 +10,9 @@ use std::path::Path;
 pub fn open_db(path: &Path) -> anyhow::Result<Connection> {
     let conn = Connection::open(path)?;
     conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")?;
+    // Read-only commands still make small writes, such as refreshing drives,
+    // while a sync holds the database; wait for it rather than fail.
+    conn.busy_timeout(std::time::Duration::from_secs(30))?;
     migration::run_migrations(&conn)?;
     Ok(conn)
 }
@@ -0,0 +1,354 @@
+//! Reconcile another machine's Diffr database into the local one.
+
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::Path;
+
+use diffr_core::models::cluster::ClusterId;
+use diffr_core::models::drive::DriveId;
+
+use crate::ops;
+
+/// What a merge changed (or would change, for a dry run).
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub clusters_added: usize,
+    pub clusters_updated: usize,
+    pub drives_added: usize,
+    pub drives_updated: usize,
+    pub sync_records_added: usize,
+    pub archives_added: usize,
+    /// Entries that could not be merged, with the reason.
+    pub conflicts: Vec<String>,
+}
+
+/// Merge the database at `other_path` into `conn`.
+///
+/// The other database is copied to a temporary file with `VACUUM INTO`, which
+/// takes in what its `-wal` file holds, and migrated there, so it is never
+/// modified. With `dry_run`, all changes are rolled back.
+pub fn merge_database_file(
+    conn: &Connection,
+    other_path: &Path,
+    dry_run: bool,
+) -> anyhow::Result<MergeReport> {
+    if !other_path.exists() {
+        anyhow::bail!("database not found: {}", other_path.display());
+    }
+    let temp = std::env::temp_dir().join(format!("diffr-merge-{}.db", uuid::Uuid::new_v4()));
+    let temp_str = temp
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("path isn't valid UTF-8: {}", temp.display()))?;
+    let copied = Connection::open_with_flags(other_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .and_then(|source| source.execute("VACUUM INTO ?1", [temp_str]));
+
+    let result = copied
+        .map_err(anyhow::Error::from)
+        .and_then(|_| crate::open_db(&temp))
+        .and_then(|other| {
+            let tx = conn.unchecked_transaction()?;
+            let report = merge_from(&tx, &other)?;
+            if dry_run {
+                tx.rollback()?;
+            } else {
+                tx.commit()?;
+            }
+            Ok(report)
+        });
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", temp.display(), suffix));
+    }
+    result
+}
+
+/// Merge all clusters, drives, sync history, and archives from `other`.
+///
+/// Rules:
+/// - Clusters match by ID; the side with the newer `updated_at` wins. A
+///   different cluster with the same name is reported and skipped.
+/// - Drives match by ID, then by identity. Mount point and sync root stay
+///   local (they are machine-specific); membership, role, label, and capacity
+///   come from whichever side saw the drive most recently.
+/// - Sync records and archives are unioned by ID. An archive brings its
+///   parts along, and every drive it names is mapped onto the local one.
+pub fn merge_from(conn: &Connection, other: &Connection) -> anyhow::Result<MergeReport> {
+    let mut report = MergeReport::default();
+
+    // Clusters that exist (or now exist) locally, keyed by ID.
+    let mut merged_clusters: Vec<ClusterId> = Vec::new();
+    for cluster in ops::list_clusters(other)? {
+        match ops::get_cluster_by_id(conn, &cluster.id)? {
+            Some(local) => {
+                if cluster.updated_at > local.updated_at {
+                    if cluster.name != local.name
+                        && ops::get_cluster_by_name(conn, &cluster.name)?.is_some()
+                    {
+                        report.conflicts.push(format!(
+                            "cluster {}: cannot rename '{}' to '{}' (name taken)",
+                            cluster.id, local.name, cluster.name
+                        ));
+                    } else {
+                        ops::update_cluster(conn, &cluster)?;
+                        report.clusters_updated += 1;
+                    }
+                }
+                merged_clusters.push(cluster.id);
+            }
+            None => {
+                if let Some(existing) = ops::get_cluster_by_name(conn, &cluster.name)? {
+                    report.conflicts.push(format!(
+                        "cluster '{}': local cluster with that name has a different id ({} vs {})",
+                        cluster.name, existing.id, cluster.id
+                    ));
+                    continue;
+                }
+                ops::insert_cluster(conn, &cluster)?;
+                report.clusters_added += 1;
+                merged_clusters.push(cluster.id);
+            }
+        }
+    }
+
+    // Map the other database's drive IDs onto local ones.
+    let mut drive_map: HashMap<DriveId, DriveId> = HashMap::new();
+    for mut drive in ops::list_all_drives(other)? {
+        let cluster_known = drive
+            .cluster_id
+            .as_ref()
+            .is_none_or(|c| merged_clusters.contains(c));
+
+        let local = match ops::get_drive_by_id(conn, &drive.id)? {
+            Some(d) => Some(d),
+            None => ops::get_drive_by_identity(conn, &drive.identity)?,
+        };
+
+        match local {
+            Some(mut local) => {
+                drive_map.insert(drive.id.clone(), local.id.clone());
+                if drive.last_seen > local.last_seen {
+                    if cluster_known {
+                        local.cluster_id = drive.cluster_id;
+                    }
+                    local.label = drive.label.or(local.label);
+                    local.role = drive.role;
+                    local.is_primary = drive.is_primary;
+                    local.read_only = drive.read_only;
+                    local.quota_bytes = drive.quota_bytes;
+                    local.total_bytes = drive.total_bytes.or(local.total_bytes);
+                    local.free_bytes = drive.free_bytes.or(local.free_bytes);
+                    local.volume_guid = drive.volume_guid.or(local.volume_guid);
+                    local.filesystem = drive.filesystem.or(local.filesystem);
+                    if !drive.aliases.is_empty() {
+                        local.aliases = drive.aliases;
+                    }
+                    local.last_seen = drive.last_seen;
+                    ops::update_drive(conn, &local)?;
+                    report.drives_updated += 1;
+                }
+            }
+            None => {
+                if !cluster_known {
+                    drive.cluster_id = None;
+                }
+                drive_map.insert(drive.id.clone(), drive.id.clone());
+                ops::insert_drive(conn, &drive)?;
+                report.drives_added += 1;
+            }
+        }
+    }
+
+    for record in ops::list_all_sync_history(other, u32::MAX)? {
+        if !merged_clusters.contains(&record.cluster_id) {
+            continue;
+        }
+        if !row_exists(conn, "sync_history", &record.id.to_string())? {
+            ops::insert_sync_record(conn, &record)?;
+            report.sync_records_added += 1;
+        }
+    }
+
+    for (other_id, local_id) in &drive_map {
+        for mut entry in ops::list_archives_for_drive(other, other_id)? {
+            if ops::get_archive_by_id(conn, &entry.id)?.is_some() {
+                continue;
+            }
+            entry.drive_id = local_id.clone();
+            entry.relocated_from = entry.relocated_from.map(|d| remap(&drive_map, d));
+            ops::insert_archive(conn, &entry)?;
+            for mut part in ops::list_archive_parts(other, &entry.id)? {
+                part.drive_id = remap(&drive_map, part.drive_id);
+                ops::insert_archive_part(conn, &part)?;
+            }
+            report.archives_added += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// The local ID of one of the other database's drives. Every drive it lists
+/// is mapped, so only a dangling reference keeps its own ID.
+fn remap(drive_map: &HashMap<DriveId, DriveId>, id: DriveId) -> DriveId {
+    drive_map.get(&id).cloned().unwrap_or(id)
+}
+
+fn row_exists(conn: &Connection, table: &str, id: &str) -> anyhow::Result<bool> {
+    let count: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {} WHERE id = ?1", table),
+        [id],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open_memory_db;
+    use chrono::{Duration, Utc};
+    use diffr_core::models::archive::{ArchiveEntry, ArchivePart, ArchiveReason, CompressionFormat};
+    use diffr_core::models::cluster::{Cluster, ConflictStrategy, Topology};
+    use diffr_core::models::drive::{Drive, DriveIdentity};
+    use diffr_core::models::sync_state::{SyncRecord, SyncStatus};
+
+    fn record(cluster_id: &ClusterId) -> SyncRecord {
+        SyncRecord {
+            id: uuid::Uuid::now_v7(),
+            cluster_id: cluster_id.clone(),
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            files_synced: 1,
+            bytes_transferred: 10,
+            conflicts_resolved: 0,
+            errors: vec![],
+            status: SyncStatus::Success,
+            host: None,
+            user: None,
+            diffr_version: None,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_reconciles_by_uuid_and_identity() {
+        let local = open_memory_db().unwrap();
+        let other = open_memory_db().unwrap();
+
+        // Same cluster on both machines; the other side changed it more recently.
+        let mut cluster = Cluster::new("docs".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&local, &cluster).unwrap();
+        cluster.conflict_strategy = ConflictStrategy::KeepBoth;
+        cluster.updated_at = Utc::now() + Duration::seconds(5);
+        ops::insert_cluster(&other, &cluster).unwrap();
+
+        // Same physical drive registered independently on both machines.
+        let mut local_drive = Drive::new(DriveIdentity::new_hardware("SN1".into()), "/mnt/a".into());
+        local_drive.cluster_id = Some(cluster.id.clone());
+        ops::insert_drive(&local, &local_drive).unwrap();
+        let mut other_drive = Drive::new(DriveIdentity::new_hardware("SN1".into()), "E:\\".into());
+        other_drive.cluster_id = Some(cluster.id.clone());
+        other_drive.last_seen = Utc::now() + Duration::seconds(5);
+        ops::insert_drive(&other, &other_drive).unwrap();
+
+        // A same-named but unrelated cluster on each side, plus one only on the other.
+        let local_music = Cluster::new("music".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&local, &local_music).unwrap();
+        let other_music = Cluster::new("music".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&other, &other_music).unwrap();
+        let other_only = Cluster::new("photos".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&other, &other_only).unwrap();
+
+        let shared = record(&cluster.id);
+        ops::insert_sync_record(&local, &shared).unwrap();
+        ops::insert_sync_record(&other, &shared).unwrap();
+        ops::insert_sync_record(&other, &record(&cluster.id)).unwrap();
+
+        let report = merge_from(&local, &other).unwrap();
+        assert_eq!(report.clusters_added, 1);
+        assert_eq!(report.clusters_updated, 1);
+        assert_eq!(report.drives_added, 0);
+        assert_eq!(report.drives_updated, 1);
+        assert_eq!(report.sync_records_added, 1);
+        assert_eq!(report.conflicts.len(), 1);
+
+        let merged = ops::get_cluster_by_id(&local, &cluster.id).unwrap().unwrap();
+        assert_eq!(merged.conflict_strategy, ConflictStrategy::KeepBoth);
+        let drive = ops::get_drive_by_id(&local, &local_drive.id).unwrap().unwrap();
+        assert_eq!(drive.mount_point, std::path::PathBuf::from("/mnt/a"));
+
+        // Merging again is a no-op.
+        let again = merge_from(&local, &other).unwrap();
+        assert_eq!(again.clusters_added + again.sync_records_added, 0);
+    }
+
+    #[test]
+    fn test_merge_brings_a_spanned_archive_with_its_parts_on_local_drives() {
+        let local = open_memory_db().unwrap();
+        let other = open_memory_db().unwrap();
+
+        // Two drives known on both machines under different IDs.
+        let mut local_ids = Vec::new();
+        let mut other_ids = Vec::new();
+        for serial in ["SN1", "SN2"] {
+            let drive = Drive::new(DriveIdentity::new_hardware(serial.into()), "/mnt/x".into());
+            ops::insert_drive(&local, &drive).unwrap();
+            local_ids.push(drive.id);
+            let drive = Drive::new(DriveIdentity::new_hardware(serial.into()), "E:\\".into());
+            ops::insert_drive(&other, &drive).unwrap();
+            other_ids.push(drive.id);
+        }
+
+        // Archived from the first drive and split across both.
+        let entry = ArchiveEntry {
+            id: uuid::Uuid::now_v7(),
+            original_path: "big.iso".into(),
+            archive_path: "big.iso.zst".into(),
+            drive_id: other_ids[1].clone(),
+            original_size: 300,
+            compressed_size: 200,
+            compression: CompressionFormat::Zstd,
+            xxh3_hash: "00".into(),
+            reason: ArchiveReason::BeforeDelete,
+            archived_at: Utc::now(),
+            relocated_from: Some(other_ids[0].clone()),
+            relocated_at: None,
+        };
+        ops::insert_archive(&other, &entry).unwrap();
+        for (index, drive_id) in other_ids.iter().enumerate() {
+            let part = ArchivePart {
+                archive_id: entry.id,
+                index: index as u32,
+                drive_id: drive_id.clone(),
+                part_path: format!("big.iso.zst.{index}").into(),
+                size: 100,
+            };
+            ops::insert_archive_part(&other, &part).unwrap();
+        }
+
+        let report = merge_from(&local, &other).unwrap();
+        assert_eq!(report.archives_added, 1);
+
+        let merged = ops::get_archive_by_id(&local, &entry.id).unwrap().unwrap();
+        assert_eq!(merged.drive_id, local_ids[1]);
+        assert_eq!(merged.relocated_from, Some(local_ids[0].clone()));
+        let parts = ops::list_archive_parts(&local, &entry.id).unwrap();
+        let drives: Vec<DriveId> = parts.into_iter().map(|p| p.drive_id).collect();
+        assert_eq!(drives, local_ids);
+    }
+
+    #[test]
+    fn test_merge_reads_what_is_still_in_the_wal() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let other_path = dir.path().join("other.db");
+        // Kept open, so the cluster stays in other.db-wal rather than other.db.
+        let other = crate::open_db(&other_path).unwrap();
+        let cluster = Cluster::new("docs".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&other, &cluster).unwrap();
+
+        let local = open_memory_db().unwrap();
+        let report = merge_database_file(&local, &other_path, false).unwrap();
+        assert_eq!(report.clusters_added, 1);
+        assert!(ops::get_cluster_by_id(&local, &cluster.id).unwrap().is_some());
+    }
+}
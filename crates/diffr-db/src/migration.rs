@@ -3,7 +3,7 @@ use rusqlite::Connection;
 use crate::schema;
 
 #[cfg(test)]
-const CURRENT_VERSION: i64 = 2;
+const CURRENT_VERSION: i64 = 25;
 
 /// Run all pending migrations.
 pub fn run_migrations(conn: &Connection) -> anyhow::Result<()> {
@@ -18,6 +18,75 @@ pub fn run_migrations(conn: &Connection) -> anyhow::Result<()> {
     if current < 2 {
         migrate_v2(conn)?;
     }
+    if current < 3 {
+        migrate_v3(conn)?;
+    }
+    if current < 4 {
+        migrate_v4(conn)?;
+    }
+    if current < 5 {
+        migrate_v5(conn)?;
+    }
+    if current < 6 {
+        migrate_v6(conn)?;
+    }
+    if current < 7 {
+        migrate_v7(conn)?;
+    }
+    if current < 8 {
+        migrate_v8(conn)?;
+    }
+    if current < 9 {
+        migrate_v9(conn)?;
+    }
+    if current < 10 {
+        migrate_v10(conn)?;
+    }
+    if current < 11 {
+        migrate_v11(conn)?;
+    }
+    if current < 12 {
+        migrate_v12(conn)?;
+    }
+    if current < 13 {
+        migrate_v13(conn)?;
+    }
+    if current < 14 {
+        migrate_v14(conn)?;
+    }
+    if current < 15 {
+        migrate_v15(conn)?;
+    }
+    if current < 16 {
+        migrate_v16(conn)?;
+    }
+    if current < 17 {
+        migrate_v17(conn)?;
+    }
+    if current < 18 {
+        migrate_v18(conn)?;
+    }
+    if current < 19 {
+        migrate_v19(conn)?;
+    }
+    if current < 20 {
+        migrate_v20(conn)?;
+    }
+    if current < 21 {
+        migrate_v21(conn)?;
+    }
+    if current < 22 {
+        migrate_v22(conn)?;
+    }
+    if current < 23 {
+        migrate_v23(conn)?;
+    }
+    if current < 24 {
+        migrate_v24(conn)?;
+    }
+    if current < 25 {
+        migrate_v25(conn)?;
+    }
 
     Ok(())
 }
@@ -54,22 +123,290 @@ fn migrate_v1(conn: &Connection) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Check whether a table already has a column (fresh installs get new
+/// columns from the CREATE statements).
+fn has_column(conn: &Connection, table: &str, column: &str) -> anyhow::Result<bool> {
+    let found = conn
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|r| r.ok())
+        .any(|name| name == column);
+    Ok(found)
+}
+
 /// Migration v2: add sync_root column to drives.
 fn migrate_v2(conn: &Connection) -> anyhow::Result<()> {
     tracing::info!("applying migration v2: add sync_root to drives");
-    // Check if column already exists (fresh installs get it from CREATE_DRIVES)
-    let has_column: bool = conn
-        .prepare("PRAGMA table_info(drives)")?
-        .query_map([], |row| row.get::<_, String>(1))?
-        .filter_map(|r| r.ok())
-        .any(|name| name == "sync_root");
-    if !has_column {
+    if !has_column(conn, "drives", "sync_root")? {
         conn.execute_batch("ALTER TABLE drives ADD COLUMN sync_root TEXT")?;
     }
     set_version(conn, 2)?;
     Ok(())
 }
 
+/// Migration v3: track archive relocation between drives.
+fn migrate_v3(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v3: add relocation tracking to archives");
+    if !has_column(conn, "archives", "relocated_from")? {
+        conn.execute_batch(
+            "ALTER TABLE archives ADD COLUMN relocated_from TEXT;
+             ALTER TABLE archives ADD COLUMN relocated_at TEXT;",
+        )?;
+    }
+    set_version(conn, 3)?;
+    Ok(())
+}
+
+/// Migration v4: add per-cluster settings.
+fn migrate_v4(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v4: add settings to clusters");
+    if !has_column(conn, "clusters", "settings")? {
+        conn.execute_batch("ALTER TABLE clusters ADD COLUMN settings TEXT NOT NULL DEFAULT '{}'")?;
+    }
+    set_version(conn, 4)?;
+    Ok(())
+}
+
+/// Migration v5: add read-only flag to drives.
+fn migrate_v5(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v5: add read_only to drives");
+    if !has_column(conn, "drives", "read_only")? {
+        conn.execute_batch("ALTER TABLE drives ADD COLUMN read_only INTEGER NOT NULL DEFAULT 0")?;
+    }
+    set_version(conn, 5)?;
+    Ok(())
+}
+
+/// Migration v6: add byte quotas and last measured usage to drives.
+fn migrate_v6(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v6: add quota_bytes and used_bytes to drives");
+    if !has_column(conn, "drives", "quota_bytes")? {
+        conn.execute_batch(
+            "ALTER TABLE drives ADD COLUMN quota_bytes INTEGER;
+             ALTER TABLE drives ADD COLUMN used_bytes INTEGER;",
+        )?;
+    }
+    set_version(conn, 6)?;
+    Ok(())
+}
+
+/// Migration v7: journal of executed sync operations, for undo.
+fn migrate_v7(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v7: add sync_journal");
+    conn.execute_batch(schema::CREATE_SYNC_JOURNAL)?;
+    set_version(conn, 7)?;
+    Ok(())
+}
+
+/// Migration v8: add volume GUID and filesystem type to drives.
+fn migrate_v8(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v8: add volume_guid and filesystem to drives");
+    if !has_column(conn, "drives", "volume_guid")? {
+        conn.execute_batch(
+            "ALTER TABLE drives ADD COLUMN volume_guid TEXT;
+             ALTER TABLE drives ADD COLUMN filesystem TEXT;",
+        )?;
+    }
+    set_version(conn, 8)?;
+    Ok(())
+}
+
+/// Migration v9: add alternate identifiers (WWN, PARTUUID, UUID) to drives.
+fn migrate_v9(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v9: add aliases to drives");
+    if !has_column(conn, "drives", "aliases")? {
+        conn.execute_batch("ALTER TABLE drives ADD COLUMN aliases TEXT;")?;
+    }
+    set_version(conn, 9)?;
+    Ok(())
+}
+
+/// Migration v10: measured copy speed between drive pairs.
+fn migrate_v10(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v10: add throughput");
+    conn.execute_batch(schema::CREATE_THROUGHPUT)?;
+    set_version(conn, 10)?;
+    Ok(())
+}
+
+/// Migration v11: record where, by whom and why each sync was run.
+fn migrate_v11(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v11: add session metadata to sync_history");
+    if !has_column(conn, "sync_history", "host")? {
+        conn.execute_batch(
+            "ALTER TABLE sync_history ADD COLUMN host TEXT;
+             ALTER TABLE sync_history ADD COLUMN username TEXT;
+             ALTER TABLE sync_history ADD COLUMN diffr_version TEXT;
+             ALTER TABLE sync_history ADD COLUMN message TEXT;",
+        )?;
+    }
+    set_version(conn, 11)?;
+    Ok(())
+}
+
+/// Migration v12: key hash cache entries by file ID too, so renames keep them.
+fn migrate_v12(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v12: add file_id to hash_cache");
+    if !has_column(conn, "hash_cache", "file_id")? {
+        conn.execute_batch("ALTER TABLE hash_cache ADD COLUMN file_id TEXT;")?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_hash_cache_file_id ON hash_cache(drive_id, file_id);",
+    )?;
+    set_version(conn, 12)?;
+    Ok(())
+}
+
+/// Migration v13: BLAKE3 alongside SHA-256 as a verification hash.
+fn migrate_v13(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v13: add blake3_hash to file_index and hash_cache");
+    if !has_column(conn, "file_index", "blake3_hash")? {
+        conn.execute_batch("ALTER TABLE file_index ADD COLUMN blake3_hash TEXT;")?;
+    }
+    if !has_column(conn, "hash_cache", "blake3_hash")? {
+        conn.execute_batch("ALTER TABLE hash_cache ADD COLUMN blake3_hash TEXT;")?;
+    }
+    set_version(conn, 13)?;
+    Ok(())
+}
+
+/// Migration v14: sampled hashes of large files, for `hash = "sampled"`.
+fn migrate_v14(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v14: add sampled_hash to file_index");
+    if !has_column(conn, "file_index", "sampled_hash")? {
+        conn.execute_batch("ALTER TABLE file_index ADD COLUMN sampled_hash TEXT;")?;
+    }
+    set_version(conn, 14)?;
+    Ok(())
+}
+
+/// Migration v15: permission bits and ownership in the file index.
+fn migrate_v15(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v15: add mode, uid, gid and ctime to file_index");
+    if !has_column(conn, "file_index", "mode")? {
+        conn.execute_batch(
+            "ALTER TABLE file_index ADD COLUMN mode INTEGER;
+             ALTER TABLE file_index ADD COLUMN uid INTEGER;
+             ALTER TABLE file_index ADD COLUMN gid INTEGER;
+             ALTER TABLE file_index ADD COLUMN ctime TEXT;",
+        )?;
+    }
+    set_version(conn, 15)?;
+    Ok(())
+}
+
+/// Migration v16: the last plan generated for each cluster, for `plan diff`.
+fn migrate_v16(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v16: add last_plans");
+    conn.execute_batch(schema::CREATE_LAST_PLANS)?;
+    set_version(conn, 16)?;
+    Ok(())
+}
+
+/// Migration v17: parts of archives split across drives.
+fn migrate_v17(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v17: add archive_parts");
+    conn.execute_batch(schema::CREATE_ARCHIVE_PARTS)?;
+    set_version(conn, 17)?;
+    Ok(())
+}
+
+/// Migration v18: last plans' operations stored a row each.
+fn migrate_v18(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v18: add last_plan_ops");
+    conn.execute_batch(schema::CREATE_LAST_PLAN_OPS)?;
+    set_version(conn, 18)?;
+    Ok(())
+}
+
+/// Migration v19: drives whose metadata is kept off the drive.
+fn migrate_v19(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v19: add metadata_dir to drives");
+    if !has_column(conn, "drives", "metadata_dir")? {
+        conn.execute_batch("ALTER TABLE drives ADD COLUMN metadata_dir TEXT;")?;
+    }
+    set_version(conn, 19)?;
+    Ok(())
+}
+
+/// Paths are now stored with `path_encoding`, which writes `%` as `%25`.
+/// Escaping the `%` in existing rows is all it takes: they were written
+/// lossily, so they hold nothing else that needs escaping.
+fn migrate_v20(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v20: escape % in stored paths");
+    let columns = [
+        ("file_index", "rel_path"),
+        ("hash_cache", "rel_path"),
+        ("archives", "original_path"),
+        ("archives", "archive_path"),
+        ("archive_parts", "part_path"),
+        ("sync_journal", "rel_path"),
+        ("sync_journal", "trash_path"),
+        // Saved plan operations are JSON in which only paths can hold a `%`.
+        ("last_plan_ops", "op"),
+    ];
+    let tx = conn.unchecked_transaction()?;
+    for (table, column) in columns {
+        tx.execute(
+            &format!("UPDATE {table} SET {column} = replace({column}, '%', '%25') WHERE instr({column}, '%') > 0"),
+            [],
+        )?;
+    }
+    // Running twice would escape twice, so the version goes in with the rows.
+    set_version(&tx, 20)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn migrate_v21(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v21: keep deleted file_index entries with generations");
+    if !has_column(conn, "file_index", "generation")? {
+        conn.execute_batch(
+            "ALTER TABLE file_index ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE file_index ADD COLUMN added_generation INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE file_index ADD COLUMN changed_generation INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE file_index ADD COLUMN deleted_at TEXT;",
+        )?;
+    }
+    conn.execute_batch(schema::CREATE_FILE_INDEX_GENERATIONS)?;
+    set_version(conn, 21)?;
+    Ok(())
+}
+
+/// Migration v22: drive tags.
+fn migrate_v22(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v22: add drive_tags");
+    conn.execute_batch(schema::CREATE_DRIVE_TAGS)?;
+    set_version(conn, 22)?;
+    Ok(())
+}
+
+/// Migration v23: temporary skip lists.
+fn migrate_v23(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v23: add sync_skips");
+    conn.execute_batch(schema::CREATE_SYNC_SKIPS)?;
+    set_version(conn, 23)?;
+    Ok(())
+}
+
+/// Migration v24: why each journaled operation was planned.
+fn migrate_v24(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v24: add sync_journal.reason");
+    if !has_column(conn, "sync_journal", "reason")? {
+        conn.execute_batch("ALTER TABLE sync_journal ADD COLUMN reason TEXT;")?;
+    }
+    set_version(conn, 24)?;
+    Ok(())
+}
+
+/// Migration v25: checkpoints of interrupted scans.
+fn migrate_v25(conn: &Connection) -> anyhow::Result<()> {
+    tracing::info!("applying migration v25: add scan_checkpoints");
+    conn.execute_batch(schema::CREATE_SCAN_CHECKPOINTS)?;
+    set_version(conn, 25)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
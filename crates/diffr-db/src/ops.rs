@@ -1,12 +1,18 @@
 use chrono::{DateTime, Utc};
 use rusqlite::{params, Connection};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-use diffr_core::models::archive::{ArchiveEntry, ArchiveReason, CompressionFormat};
+use diffr_core::models::archive::{ArchiveEntry, ArchivePart, ArchiveReason, CompressionFormat};
 use diffr_core::models::cluster::{Cluster, ClusterId, ConflictStrategy, Topology};
-use diffr_core::models::drive::{Drive, DriveId, DriveIdentity, DriveRole};
-use diffr_core::models::file_entry::{FileEntry, HashCacheEntry};
-use diffr_core::models::sync_state::{SyncRecord, SyncStatus};
+use diffr_core::models::drive::{BusType, Drive, DriveId, DriveIdentity, DriveRole};
+use diffr_core::models::file_entry::{FileEntry, FilePermissions, HashCacheEntry};
+use diffr_core::models::sync_state::{
+    JournalEntry, PathSkip, SkippedFile, SyncOp, SyncOpKind, SyncPlan, SyncRecord, SyncStatus, Throughput,
+};
+use diffr_core::path_encoding;
 
 // ── Helpers ──
 
@@ -24,13 +30,14 @@ fn fmt_dt(dt: &DateTime<Utc>) -> String {
 
 pub fn insert_cluster(conn: &Connection, cluster: &Cluster) -> anyhow::Result<()> {
     conn.execute(
-        "INSERT INTO clusters (id, name, topology, conflict_strategy, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO clusters (id, name, topology, conflict_strategy, settings, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         params![
             cluster.id.0.to_string(),
             cluster.name,
             cluster.topology.to_string(),
             cluster.conflict_strategy.to_string(),
+            serde_json::to_string(&cluster.settings)?,
             fmt_dt(&cluster.created_at),
             fmt_dt(&cluster.updated_at),
         ],
@@ -40,79 +47,71 @@ pub fn insert_cluster(conn: &Connection, cluster: &Cluster) -> anyhow::Result<()
 
 pub fn get_cluster_by_name(conn: &Connection, name: &str) -> anyhow::Result<Option<Cluster>> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, topology, conflict_strategy, created_at, updated_at
+        "SELECT id, name, topology, conflict_strategy, settings, created_at, updated_at
          FROM clusters WHERE name = ?1",
     )?;
-    let mut rows = stmt.query(params![name])?;
-    match rows.next()? {
-        Some(row) => {
-            let id_str: String = row.get(0)?;
-            let topo_str: String = row.get(2)?;
-            let cs_str: String = row.get(3)?;
-            let created_str: String = row.get(4)?;
-            let updated_str: String = row.get(5)?;
-            Ok(Some(Cluster {
-                id: ClusterId::from_uuid(Uuid::parse_str(&id_str)?),
-                name: row.get(1)?,
-                topology: topo_str.parse().unwrap_or(Topology::Mesh),
-                conflict_strategy: cs_str.parse().unwrap_or(ConflictStrategy::NewestWins),
-                created_at: parse_dt(&created_str),
-                updated_at: parse_dt(&updated_str),
-            }))
-        }
+    let mut rows = stmt.query_map(params![name], row_to_cluster)?;
+    match rows.next() {
+        Some(row) => Ok(Some(row?)),
         None => Ok(None),
     }
 }
 
 pub fn get_cluster_by_id(conn: &Connection, id: &ClusterId) -> anyhow::Result<Option<Cluster>> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, topology, conflict_strategy, created_at, updated_at
+        "SELECT id, name, topology, conflict_strategy, settings, created_at, updated_at
          FROM clusters WHERE id = ?1",
     )?;
-    let mut rows = stmt.query(params![id.0.to_string()])?;
-    match rows.next()? {
-        Some(row) => {
-            let id_str: String = row.get(0)?;
-            let topo_str: String = row.get(2)?;
-            let cs_str: String = row.get(3)?;
-            let created_str: String = row.get(4)?;
-            let updated_str: String = row.get(5)?;
-            Ok(Some(Cluster {
-                id: ClusterId::from_uuid(Uuid::parse_str(&id_str)?),
-                name: row.get(1)?,
-                topology: topo_str.parse().unwrap_or(Topology::Mesh),
-                conflict_strategy: cs_str.parse().unwrap_or(ConflictStrategy::NewestWins),
-                created_at: parse_dt(&created_str),
-                updated_at: parse_dt(&updated_str),
-            }))
-        }
+    let mut rows = stmt.query_map(params![id.0.to_string()], row_to_cluster)?;
+    match rows.next() {
+        Some(row) => Ok(Some(row?)),
         None => Ok(None),
     }
 }
 
 pub fn list_clusters(conn: &Connection) -> anyhow::Result<Vec<Cluster>> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, topology, conflict_strategy, created_at, updated_at
+        "SELECT id, name, topology, conflict_strategy, settings, created_at, updated_at
          FROM clusters ORDER BY name",
     )?;
-    let rows = stmt.query_map([], |row| {
-        let id_str: String = row.get(0)?;
-        let topo_str: String = row.get(2)?;
-        let cs_str: String = row.get(3)?;
-        let created_str: String = row.get(4)?;
-        let updated_str: String = row.get(5)?;
-        Ok(Cluster {
-            id: ClusterId::from_uuid(Uuid::parse_str(&id_str).unwrap_or_default()),
-            name: row.get(1)?,
-            topology: topo_str.parse().unwrap_or(Topology::Mesh),
-            conflict_strategy: cs_str.parse().unwrap_or(ConflictStrategy::NewestWins),
-            created_at: parse_dt(&created_str),
-            updated_at: parse_dt(&updated_str),
-        })
-    })?;
+    let rows = stmt.query_map([], row_to_cluster)?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
+pub fn update_cluster(conn: &Connection, cluster: &Cluster) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE clusters SET name = ?1, topology = ?2, conflict_strategy = ?3, settings = ?4, updated_at = ?5
+         WHERE id = ?6",
+        params![
+            cluster.name,
+            cluster.topology.to_string(),
+            cluster.conflict_strategy.to_string(),
+            serde_json::to_string(&cluster.settings)?,
+            fmt_dt(&cluster.updated_at),
+            cluster.id.0.to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn row_to_cluster(row: &rusqlite::Row) -> rusqlite::Result<Cluster> {
+    let id_str: String = row.get(0)?;
+    let topo_str: String = row.get(2)?;
+    let cs_str: String = row.get(3)?;
+    let settings_str: String = row.get(4)?;
+    let created_str: String = row.get(5)?;
+    let updated_str: String = row.get(6)?;
+    Ok(Cluster {
+        id: ClusterId::from_uuid(Uuid::parse_str(&id_str).unwrap_or_default()),
+        name: row.get(1)?,
+        topology: topo_str.parse().unwrap_or(Topology::Mesh),
+        conflict_strategy: cs_str.parse().unwrap_or(ConflictStrategy::NewestWins),
+        settings: serde_json::from_str(&settings_str).unwrap_or_default(),
+        created_at: parse_dt(&created_str),
+        updated_at: parse_dt(&updated_str),
+    })
+}
+
 pub fn delete_cluster(conn: &Connection, id: &ClusterId) -> anyhow::Result<()> {
     conn.execute("DELETE FROM clusters WHERE id = ?1", params![id.0.to_string()])?;
     Ok(())
@@ -126,8 +125,8 @@ pub fn insert_drive(conn: &Connection, drive: &Drive) -> anyhow::Result<()> {
         DriveIdentity::Synthetic { id } => ("synthetic", id.clone()),
     };
     conn.execute(
-        "INSERT INTO drives (id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        "INSERT INTO drives (id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
         params![
             drive.id.0.to_string(),
             id_type,
@@ -142,6 +141,13 @@ pub fn insert_drive(conn: &Connection, drive: &Drive) -> anyhow::Result<()> {
             drive.free_bytes.map(|b| b as i64),
             fmt_dt(&drive.last_seen),
             fmt_dt(&drive.created_at),
+            drive.read_only as i32,
+            drive.quota_bytes.map(|b| b as i64),
+            drive.used_bytes.map(|b| b as i64),
+            drive.volume_guid,
+            drive.filesystem,
+            join_aliases(&drive.aliases),
+            drive.metadata_dir.as_ref().map(|p| p.to_string_lossy().to_string()),
         ],
     )?;
     Ok(())
@@ -153,7 +159,7 @@ pub fn get_drive_by_identity(conn: &Connection, identity: &DriveIdentity) -> any
         DriveIdentity::Synthetic { id } => ("synthetic", id.as_str()),
     };
     let mut stmt = conn.prepare(
-        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at
+        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir
          FROM drives WHERE identity_type = ?1 AND identity_value = ?2",
     )?;
     let mut rows = stmt.query(params![id_type, id_value])?;
@@ -163,21 +169,33 @@ pub fn get_drive_by_identity(conn: &Connection, identity: &DriveIdentity) -> any
     }
 }
 
+pub fn get_drive_by_id(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Option<Drive>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir
+         FROM drives WHERE id = ?1",
+    )?;
+    let mut rows = stmt.query(params![drive_id.0.to_string()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_drive(row)?)),
+        None => Ok(None),
+    }
+}
+
 pub fn list_drives_for_cluster(conn: &Connection, cluster_id: &ClusterId) -> anyhow::Result<Vec<Drive>> {
     let mut stmt = conn.prepare(
-        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at
+        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir
          FROM drives WHERE cluster_id = ?1 ORDER BY created_at",
     )?;
-    let rows = stmt.query_map(params![cluster_id.0.to_string()], |row| row_to_drive(row))?;
+    let rows = stmt.query_map(params![cluster_id.0.to_string()], row_to_drive)?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
 pub fn list_all_drives(conn: &Connection) -> anyhow::Result<Vec<Drive>> {
     let mut stmt = conn.prepare(
-        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at
+        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir
          FROM drives ORDER BY created_at",
     )?;
-    let rows = stmt.query_map([], |row| row_to_drive(row))?;
+    let rows = stmt.query_map([], row_to_drive)?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
@@ -197,6 +215,58 @@ pub fn delete_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<()>
     Ok(())
 }
 
+/// Tag a drive. Returns false if it already had the tag.
+pub fn add_drive_tag(conn: &Connection, drive_id: &DriveId, tag: &str) -> anyhow::Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO drive_tags (drive_id, tag) VALUES (?1, ?2)",
+        params![drive_id.0.to_string(), tag],
+    )?;
+    Ok(added > 0)
+}
+
+/// Take a tag off a drive. Returns false if it didn't have the tag.
+pub fn remove_drive_tag(conn: &Connection, drive_id: &DriveId, tag: &str) -> anyhow::Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM drive_tags WHERE drive_id = ?1 AND tag = ?2",
+        params![drive_id.0.to_string(), tag],
+    )?;
+    Ok(removed > 0)
+}
+
+/// A drive's tags, in order.
+pub fn list_drive_tags(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT tag FROM drive_tags WHERE drive_id = ?1 ORDER BY tag")?;
+    let rows = stmt.query_map(params![drive_id.0.to_string()], |row| row.get(0))?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Every tag in use, with the drives that have it.
+pub fn list_tags(conn: &Connection) -> anyhow::Result<Vec<(String, Vec<DriveId>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.tag, t.drive_id FROM drive_tags t JOIN drives d ON d.id = t.drive_id ORDER BY t.tag, d.created_at",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    let mut tags: Vec<(String, Vec<DriveId>)> = Vec::new();
+    for (tag, drive_id) in rows.filter_map(|r| r.ok()) {
+        let drive_id = DriveId::from_uuid(Uuid::parse_str(&drive_id).unwrap_or_default());
+        match tags.last_mut() {
+            Some((last, drives)) if *last == tag => drives.push(drive_id),
+            _ => tags.push((tag, vec![drive_id])),
+        }
+    }
+    Ok(tags)
+}
+
+/// The drives with a tag.
+pub fn list_drives_with_tag(conn: &Connection, tag: &str) -> anyhow::Result<Vec<Drive>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, identity_type, identity_value, label, mount_point, sync_root, cluster_id, role, is_primary, total_bytes, free_bytes, last_seen, created_at, read_only, quota_bytes, used_bytes, volume_guid, filesystem, aliases, metadata_dir
+         FROM drives WHERE id IN (SELECT drive_id FROM drive_tags WHERE tag = ?1) ORDER BY created_at",
+    )?;
+    let rows = stmt.query_map(params![tag], row_to_drive)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
 pub fn update_drive_sync_root(
     conn: &Connection,
     drive_id: &DriveId,
@@ -212,6 +282,57 @@ pub fn update_drive_sync_root(
     Ok(())
 }
 
+/// Update all mutable fields of a drive record, matched by ID.
+pub fn update_drive(conn: &Connection, drive: &Drive) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE drives SET label = ?1, mount_point = ?2, sync_root = ?3, cluster_id = ?4, role = ?5, is_primary = ?6, total_bytes = ?7, free_bytes = ?8, last_seen = ?9, read_only = ?10, quota_bytes = ?11, used_bytes = ?12, volume_guid = ?13, filesystem = ?14, aliases = ?15, metadata_dir = ?16
+         WHERE id = ?17",
+        params![
+            drive.label,
+            drive.mount_point.to_string_lossy().to_string(),
+            drive.sync_root.as_ref().map(|p| p.to_string_lossy().to_string()),
+            drive.cluster_id.as_ref().map(|c| c.0.to_string()),
+            drive.role.to_string(),
+            drive.is_primary as i32,
+            drive.total_bytes.map(|b| b as i64),
+            drive.free_bytes.map(|b| b as i64),
+            fmt_dt(&drive.last_seen),
+            drive.read_only as i32,
+            drive.quota_bytes.map(|b| b as i64),
+            drive.used_bytes.map(|b| b as i64),
+            drive.volume_guid,
+            drive.filesystem,
+            join_aliases(&drive.aliases),
+            drive.metadata_dir.as_ref().map(|p| p.to_string_lossy().to_string()),
+            drive.id.0.to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Save a drive's new identity, such as a serial that gained a partition
+/// qualifier.
+pub fn update_drive_identity(conn: &Connection, drive: &Drive) -> anyhow::Result<()> {
+    let (id_type, id_value) = match &drive.identity {
+        DriveIdentity::Hardware { serial } => ("hardware", serial.as_str()),
+        DriveIdentity::Synthetic { id } => ("synthetic", id.as_str()),
+    };
+    conn.execute(
+        "UPDATE drives SET identity_type = ?1, identity_value = ?2 WHERE id = ?3",
+        params![id_type, id_value, drive.id.0.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Aliases are stored comma-separated; none of the identifier forms contain commas.
+fn join_aliases(aliases: &[String]) -> Option<String> {
+    if aliases.is_empty() {
+        None
+    } else {
+        Some(aliases.join(","))
+    }
+}
+
 fn row_to_drive(row: &rusqlite::Row) -> rusqlite::Result<Drive> {
     let id_str: String = row.get(0)?;
     let id_type: String = row.get(1)?;
@@ -226,6 +347,13 @@ fn row_to_drive(row: &rusqlite::Row) -> rusqlite::Result<Drive> {
     let free_bytes: Option<i64> = row.get(10)?;
     let last_seen_str: String = row.get(11)?;
     let created_str: String = row.get(12)?;
+    let read_only: i32 = row.get(13)?;
+    let quota_bytes: Option<i64> = row.get(14)?;
+    let used_bytes: Option<i64> = row.get(15)?;
+    let volume_guid: Option<String> = row.get(16)?;
+    let filesystem: Option<String> = row.get(17)?;
+    let aliases: Option<String> = row.get(18)?;
+    let metadata_dir: Option<String> = row.get(19)?;
 
     let identity = match id_type.as_str() {
         "hardware" => DriveIdentity::Hardware { serial: id_value },
@@ -247,17 +375,60 @@ fn row_to_drive(row: &rusqlite::Row) -> rusqlite::Result<Drive> {
         free_bytes: free_bytes.map(|b| b as u64),
         last_seen: parse_dt(&last_seen_str),
         created_at: parse_dt(&created_str),
+        read_only: read_only != 0,
+        quota_bytes: quota_bytes.map(|b| b as u64),
+        used_bytes: used_bytes.map(|b| b as u64),
+        volume_guid,
+        filesystem,
+        aliases: aliases
+            .map(|a| a.split(',').filter(|s| !s.is_empty()).map(String::from).collect())
+            .unwrap_or_default(),
+        metadata_dir: metadata_dir.map(Into::into),
+        locked: false,
+        bus: BusType::Unknown,
     })
 }
 
 // ── File Index ──
+//
+// Each time a drive's catalog is replaced it gets a new generation. Entries
+// that disappear are kept as tombstones, marked `deleted_at`, so the catalog
+// can say what changed between generations.
 
+/// How many of a drive's generations deleted entries are remembered for.
+const INDEX_HISTORY_GENERATIONS: u64 = 30;
+
+/// Add or update one entry in its drive's current generation.
 pub fn upsert_file_entry(conn: &Connection, entry: &FileEntry) -> anyhow::Result<()> {
+    let generation = current_generation(conn, &entry.drive_id)?;
+    write_file_entry(conn, entry, generation)
+}
+
+/// A directory's mtime changes with its contents, so only files count as
+/// modified.
+fn write_file_entry(conn: &Connection, entry: &FileEntry, generation: u64) -> anyhow::Result<()> {
     conn.execute(
-        "INSERT OR REPLACE INTO file_index (rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        "INSERT INTO file_index (rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at, blake3_hash, sampled_hash, mode, uid, gid, ctime,
+                                 generation, added_generation, changed_generation, deleted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?15, ?15, NULL)
+         ON CONFLICT (rel_path, drive_id) DO UPDATE SET
+             is_dir = excluded.is_dir, size = excluded.size, mtime = excluded.mtime,
+             xxh3_hash = excluded.xxh3_hash, sha256_hash = excluded.sha256_hash, indexed_at = excluded.indexed_at,
+             blake3_hash = excluded.blake3_hash, sampled_hash = excluded.sampled_hash,
+             mode = excluded.mode, uid = excluded.uid, gid = excluded.gid, ctime = excluded.ctime,
+             generation = excluded.generation,
+             added_generation = CASE WHEN file_index.deleted_at IS NULL
+                                     THEN file_index.added_generation ELSE excluded.generation END,
+             changed_generation = CASE WHEN file_index.deleted_at IS NULL
+                                        AND file_index.is_dir = excluded.is_dir
+                                        AND (excluded.is_dir = 1
+                                             OR (file_index.size = excluded.size AND file_index.mtime = excluded.mtime
+                                                 AND (file_index.xxh3_hash IS NULL OR excluded.xxh3_hash IS NULL
+                                                      OR file_index.xxh3_hash = excluded.xxh3_hash)))
+                                       THEN file_index.changed_generation ELSE excluded.generation END,
+             deleted_at = NULL",
         params![
-            entry.rel_path.to_string_lossy().to_string(),
+            path_encoding::encode(&entry.rel_path),
             entry.drive_id.0.to_string(),
             entry.is_dir as i32,
             entry.size as i64,
@@ -265,61 +436,262 @@ pub fn upsert_file_entry(conn: &Connection, entry: &FileEntry) -> anyhow::Result
             entry.xxh3_hash,
             entry.sha256_hash,
             fmt_dt(&entry.indexed_at),
+            entry.blake3_hash,
+            entry.sampled_hash,
+            entry.permissions.map(|p| p.mode),
+            entry.permissions.map(|p| p.uid),
+            entry.permissions.map(|p| p.gid),
+            entry.permissions.map(|p| fmt_dt(&p.changed_at)),
+            generation as i64,
         ],
     )?;
     Ok(())
 }
 
+/// The drive's current catalog, without deleted entries.
 pub fn get_file_entries_for_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Vec<FileEntry>> {
     let mut stmt = conn.prepare(
-        "SELECT rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at
-         FROM file_index WHERE drive_id = ?1 ORDER BY rel_path",
+        "SELECT rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at, blake3_hash, sampled_hash, mode, uid, gid, ctime
+         FROM file_index WHERE drive_id = ?1 AND deleted_at IS NULL ORDER BY rel_path",
     )?;
-    let rows = stmt.query_map(params![drive_id.0.to_string()], |row| {
-        let rel_path: String = row.get(0)?;
-        let drive_id_str: String = row.get(1)?;
-        let is_dir: i32 = row.get(2)?;
-        let size: i64 = row.get(3)?;
-        let mtime_str: String = row.get(4)?;
-        let xxh3: Option<String> = row.get(5)?;
-        let sha256: Option<String> = row.get(6)?;
-        let indexed_str: String = row.get(7)?;
-        Ok(FileEntry {
-            rel_path: rel_path.into(),
-            drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
-            is_dir: is_dir != 0,
-            size: size as u64,
-            mtime: parse_dt(&mtime_str),
-            xxh3_hash: xxh3,
-            sha256_hash: sha256,
-            indexed_at: parse_dt(&indexed_str),
-        })
+    let rows = stmt.query_map(params![drive_id.0.to_string()], row_to_file_entry)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// One path's entry in the drive's current catalog, unless it's deleted.
+pub fn get_file_entry(conn: &Connection, drive_id: &DriveId, rel_path: &Path) -> anyhow::Result<Option<FileEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at, blake3_hash, sampled_hash, mode, uid, gid, ctime
+         FROM file_index WHERE drive_id = ?1 AND rel_path = ?2 AND deleted_at IS NULL",
+    )?;
+    let mut rows = stmt.query_map(
+        params![drive_id.0.to_string(), path_encoding::encode(rel_path)],
+        row_to_file_entry,
+    )?;
+    Ok(rows.next().transpose()?)
+}
+
+/// Every cataloged entry on every drive, with the cluster its drive is in.
+pub fn list_cataloged_files(conn: &Connection) -> anyhow::Result<Vec<(Option<ClusterId>, FileEntry)>> {
+    let mut stmt = conn.prepare(
+        "SELECT f.rel_path, f.drive_id, f.is_dir, f.size, f.mtime, f.xxh3_hash, f.sha256_hash, f.indexed_at, f.blake3_hash, f.sampled_hash, f.mode, f.uid, f.gid, f.ctime, d.cluster_id
+         FROM file_index f JOIN drives d ON d.id = f.drive_id
+         WHERE f.deleted_at IS NULL
+         ORDER BY f.xxh3_hash, f.rel_path",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let cluster_id: Option<String> = row.get(14)?;
+        let cluster_id = cluster_id
+            .and_then(|s| Uuid::parse_str(&s).ok())
+            .map(ClusterId::from_uuid);
+        Ok((cluster_id, row_to_file_entry(row)?))
     })?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
+fn row_to_file_entry(row: &rusqlite::Row) -> rusqlite::Result<FileEntry> {
+    let rel_path: String = row.get(0)?;
+    let drive_id_str: String = row.get(1)?;
+    let is_dir: i32 = row.get(2)?;
+    let size: i64 = row.get(3)?;
+    let mtime_str: String = row.get(4)?;
+    let xxh3: Option<String> = row.get(5)?;
+    let sha256: Option<String> = row.get(6)?;
+    let indexed_str: String = row.get(7)?;
+    let mode: Option<u32> = row.get(10)?;
+    let uid: Option<u32> = row.get(11)?;
+    let gid: Option<u32> = row.get(12)?;
+    let ctime: Option<String> = row.get(13)?;
+    let permissions = match (mode, uid, gid) {
+        (Some(mode), Some(uid), Some(gid)) => Some(FilePermissions {
+            mode,
+            uid,
+            gid,
+            changed_at: ctime.map(|s| parse_dt(&s)).unwrap_or_default(),
+        }),
+        _ => None,
+    };
+    Ok(FileEntry {
+        rel_path: path_encoding::decode(&rel_path),
+        drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
+        is_dir: is_dir != 0,
+        size: size as u64,
+        mtime: parse_dt(&mtime_str),
+        xxh3_hash: xxh3,
+        sampled_hash: row.get(9)?,
+        sha256_hash: sha256,
+        blake3_hash: row.get(8)?,
+        permissions,
+        indexed_at: parse_dt(&indexed_str),
+    })
+}
+
+/// Replace a drive's catalog with `entries` as a new generation, in one
+/// transaction, and return the generation. Entries not in `entries` are
+/// marked deleted rather than removed.
+pub fn replace_file_index(
+    conn: &Connection,
+    drive_id: &DriveId,
+    entries: &[FileEntry],
+) -> anyhow::Result<u64> {
+    let tx = conn.unchecked_transaction()?;
+    let generation = current_generation(&tx, drive_id)? + 1;
+    for entry in entries {
+        write_file_entry(&tx, entry, generation)?;
+    }
+    let now = fmt_dt(&Utc::now());
+    tx.execute(
+        "UPDATE file_index SET deleted_at = ?3
+         WHERE drive_id = ?1 AND generation < ?2 AND deleted_at IS NULL",
+        params![drive_id.0.to_string(), generation as i64, now],
+    )?;
+    tx.execute(
+        "INSERT INTO file_index_generations (drive_id, generation, indexed_at, entries) VALUES (?1, ?2, ?3, ?4)",
+        params![drive_id.0.to_string(), generation as i64, now, entries.len() as i64],
+    )?;
+    // A tombstone last seen before the oldest generation kept can't be
+    // asked about any more.
+    let oldest = generation.saturating_sub(INDEX_HISTORY_GENERATIONS) as i64;
+    tx.execute(
+        "DELETE FROM file_index WHERE drive_id = ?1 AND deleted_at IS NOT NULL AND generation < ?2",
+        params![drive_id.0.to_string(), oldest],
+    )?;
+    tx.execute(
+        "DELETE FROM file_index_generations WHERE drive_id = ?1 AND generation < ?2",
+        params![drive_id.0.to_string(), oldest],
+    )?;
+    tx.commit()?;
+    Ok(generation)
+}
+
+/// Forget a drive's catalog, history included.
 pub fn clear_file_index_for_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<()> {
     conn.execute(
         "DELETE FROM file_index WHERE drive_id = ?1",
         params![drive_id.0.to_string()],
     )?;
+    conn.execute(
+        "DELETE FROM file_index_generations WHERE drive_id = ?1",
+        params![drive_id.0.to_string()],
+    )?;
     Ok(())
 }
 
+/// The drive's latest generation, or 0 if it has never been cataloged.
+pub fn current_generation(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<u64> {
+    let generation: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(generation), 0) FROM file_index_generations WHERE drive_id = ?1",
+        params![drive_id.0.to_string()],
+        |row| row.get(0),
+    )?;
+    Ok(generation as u64)
+}
+
+/// One of a drive's catalogs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexGeneration {
+    pub generation: u64,
+    pub indexed_at: DateTime<Utc>,
+    pub entries: u64,
+}
+
+/// The catalogs still remembered for a drive, newest first.
+pub fn list_index_generations(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Vec<IndexGeneration>> {
+    let mut stmt = conn.prepare(
+        "SELECT generation, indexed_at, entries FROM file_index_generations
+         WHERE drive_id = ?1 ORDER BY generation DESC",
+    )?;
+    let rows = stmt.query_map(params![drive_id.0.to_string()], |row| {
+        let generation: i64 = row.get(0)?;
+        let indexed_at: String = row.get(1)?;
+        let entries: i64 = row.get(2)?;
+        Ok(IndexGeneration {
+            generation: generation as u64,
+            indexed_at: parse_dt(&indexed_at),
+            entries: entries as u64,
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl std::fmt::Display for IndexChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexChangeKind::Added => write!(f, "added"),
+            IndexChangeKind::Modified => write!(f, "modified"),
+            IndexChangeKind::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+/// An entry that differs between an earlier catalog and the current one.
+#[derive(Debug, Clone)]
+pub struct IndexChange {
+    pub kind: IndexChangeKind,
+    /// For a deletion, the entry as last cataloged.
+    pub entry: FileEntry,
+    /// When a deleted entry was found missing.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// What changed on a drive between catalog `generation` and the current
+/// one, by path. Entries added and deleted in between aren't listed.
+pub fn file_index_changes_since(
+    conn: &Connection,
+    drive_id: &DriveId,
+    generation: u64,
+) -> anyhow::Result<Vec<IndexChange>> {
+    let mut stmt = conn.prepare(
+        "SELECT rel_path, drive_id, is_dir, size, mtime, xxh3_hash, sha256_hash, indexed_at, blake3_hash, sampled_hash, mode, uid, gid, ctime,
+                added_generation, deleted_at
+         FROM file_index
+         WHERE drive_id = ?1
+           AND ((deleted_at IS NULL AND changed_generation > ?2)
+                OR (deleted_at IS NOT NULL AND generation >= ?2 AND added_generation <= ?2))
+         ORDER BY rel_path",
+    )?;
+    let rows = stmt.query_map(params![drive_id.0.to_string(), generation as i64], |row| {
+        let added: i64 = row.get(14)?;
+        let deleted_at: Option<String> = row.get(15)?;
+        let kind = if deleted_at.is_some() {
+            IndexChangeKind::Deleted
+        } else if added as u64 > generation {
+            IndexChangeKind::Added
+        } else {
+            IndexChangeKind::Modified
+        };
+        Ok(IndexChange {
+            kind,
+            entry: row_to_file_entry(row)?,
+            deleted_at: deleted_at.map(|s| parse_dt(&s)),
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
 // ── Hash Cache ──
 
 pub fn upsert_hash_cache(conn: &Connection, entry: &HashCacheEntry) -> anyhow::Result<()> {
     conn.execute(
-        "INSERT OR REPLACE INTO hash_cache (rel_path, drive_id, size, mtime, xxh3_hash, sha256_hash, cached_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        "INSERT OR REPLACE INTO hash_cache (rel_path, drive_id, size, mtime, xxh3_hash, sha256_hash, cached_at, file_id, blake3_hash)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
         params![
-            entry.rel_path.to_string_lossy().to_string(),
+            path_encoding::encode(&entry.rel_path),
             entry.drive_id.0.to_string(),
             entry.size as i64,
             fmt_dt(&entry.mtime),
             entry.xxh3_hash,
             entry.sha256_hash,
             fmt_dt(&entry.cached_at),
+            entry.file_id,
+            entry.blake3_hash,
         ],
     )?;
     Ok(())
@@ -328,43 +700,202 @@ pub fn upsert_hash_cache(conn: &Connection, entry: &HashCacheEntry) -> anyhow::R
 pub fn get_hash_cache_entry(
     conn: &Connection,
     drive_id: &DriveId,
-    rel_path: &str,
+    rel_path: &Path,
 ) -> anyhow::Result<Option<HashCacheEntry>> {
     let mut stmt = conn.prepare(
-        "SELECT rel_path, drive_id, size, mtime, xxh3_hash, sha256_hash, cached_at
+        "SELECT rel_path, drive_id, size, mtime, xxh3_hash, sha256_hash, cached_at, file_id, blake3_hash
          FROM hash_cache WHERE drive_id = ?1 AND rel_path = ?2",
     )?;
-    let mut rows = stmt.query(params![drive_id.0.to_string(), rel_path])?;
+    let mut rows = stmt.query(params![drive_id.0.to_string(), path_encoding::encode(rel_path)])?;
     match rows.next()? {
-        Some(row) => {
-            let rel_path: String = row.get(0)?;
-            let drive_id_str: String = row.get(1)?;
-            let size: i64 = row.get(2)?;
-            let mtime_str: String = row.get(3)?;
-            let xxh3: String = row.get(4)?;
-            let sha256: Option<String> = row.get(5)?;
-            let cached_str: String = row.get(6)?;
-            Ok(Some(HashCacheEntry {
-                rel_path: rel_path.into(),
-                drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
-                size: size as u64,
-                mtime: parse_dt(&mtime_str),
-                xxh3_hash: xxh3,
-                sha256_hash: sha256,
-                cached_at: parse_dt(&cached_str),
-            }))
-        }
+        Some(row) => Ok(Some(row_to_hash_cache_entry(row)?)),
         None => Ok(None),
     }
 }
 
+/// The most recent hash cache entry for a file ID on a drive, whatever
+/// path it was cached under.
+pub fn get_hash_cache_by_file_id(
+    conn: &Connection,
+    drive_id: &DriveId,
+    file_id: &str,
+) -> anyhow::Result<Option<HashCacheEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT rel_path, drive_id, size, mtime, xxh3_hash, sha256_hash, cached_at, file_id, blake3_hash
+         FROM hash_cache WHERE drive_id = ?1 AND file_id = ?2
+         ORDER BY cached_at DESC LIMIT 1",
+    )?;
+    let mut rows = stmt.query(params![drive_id.0.to_string(), file_id])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_hash_cache_entry(row)?)),
+        None => Ok(None),
+    }
+}
+
+fn row_to_hash_cache_entry(row: &rusqlite::Row) -> rusqlite::Result<HashCacheEntry> {
+    let rel_path: String = row.get(0)?;
+    let drive_id_str: String = row.get(1)?;
+    let size: i64 = row.get(2)?;
+    let mtime_str: String = row.get(3)?;
+    let cached_str: String = row.get(6)?;
+    Ok(HashCacheEntry {
+        rel_path: path_encoding::decode(&rel_path),
+        drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
+        size: size as u64,
+        mtime: parse_dt(&mtime_str),
+        file_id: row.get(7)?,
+        xxh3_hash: row.get(4)?,
+        sha256_hash: row.get(5)?,
+        blake3_hash: row.get(8)?,
+        cached_at: parse_dt(&cached_str),
+    })
+}
+
+/// Hash cache size for one drive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashCacheStats {
+    pub drive_id: DriveId,
+    pub entries: u64,
+    /// Entries for files missing from the drive's catalog. Always 0 for a
+    /// drive with no catalog, since there's nothing to compare against.
+    pub orphaned: u64,
+    pub oldest: Option<DateTime<Utc>>,
+}
+
+pub fn hash_cache_stats(conn: &Connection) -> anyhow::Result<Vec<HashCacheStats>> {
+    let mut stmt = conn.prepare(
+        "SELECT h.drive_id, COUNT(*),
+                SUM(CASE WHEN f.rel_path IS NULL
+                          AND EXISTS (SELECT 1 FROM file_index c WHERE c.drive_id = h.drive_id AND c.deleted_at IS NULL)
+                    THEN 1 ELSE 0 END),
+                MIN(h.cached_at)
+         FROM hash_cache h
+         LEFT JOIN file_index f ON f.drive_id = h.drive_id AND f.rel_path = h.rel_path AND f.deleted_at IS NULL
+         GROUP BY h.drive_id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let drive_id_str: String = row.get(0)?;
+        let entries: i64 = row.get(1)?;
+        let orphaned: i64 = row.get(2)?;
+        let oldest: Option<String> = row.get(3)?;
+        Ok(HashCacheStats {
+            drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
+            entries: entries as u64,
+            orphaned: orphaned as u64,
+            oldest: oldest.map(|s| parse_dt(&s)),
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Delete hash cache entries for one drive, or for every drive. Returns
+/// how many were removed.
+pub fn clear_hash_cache(conn: &Connection, drive_id: Option<&DriveId>) -> anyhow::Result<usize> {
+    let removed = match drive_id {
+        Some(id) => conn.execute(
+            "DELETE FROM hash_cache WHERE drive_id = ?1",
+            params![id.0.to_string()],
+        )?,
+        None => conn.execute("DELETE FROM hash_cache", [])?,
+    };
+    Ok(removed)
+}
+
+/// Delete a drive's hash cache entries for files no longer in its catalog.
+/// A drive with no catalog is left alone. Returns how many were removed.
+pub fn prune_hash_cache(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<usize> {
+    let removed = conn.execute(
+        "DELETE FROM hash_cache
+         WHERE drive_id = ?1
+           AND EXISTS (SELECT 1 FROM file_index WHERE drive_id = ?1 AND deleted_at IS NULL)
+           AND NOT EXISTS (SELECT 1 FROM file_index f
+                           WHERE f.drive_id = hash_cache.drive_id AND f.rel_path = hash_cache.rel_path
+                             AND f.deleted_at IS NULL)",
+        params![drive_id.0.to_string()],
+    )?;
+    Ok(removed)
+}
+
+// ── Scan Checkpoints ──
+
+/// What an interrupted scan had done by one of its checkpoints.
+#[derive(Debug, Clone)]
+pub struct ScanCheckpoint {
+    /// The last entry the scan finished with. Everything before it in the
+    /// scan's walk order is in `entries` or `skipped`.
+    pub cursor: PathBuf,
+    pub saved_at: DateTime<Utc>,
+    pub entries: Vec<FileEntry>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Add a checkpoint to those of the scan of `root` on `drive_id`. Its
+/// entries are the ones found since the checkpoint before.
+pub fn save_scan_checkpoint(
+    conn: &Connection,
+    drive_id: &DriveId,
+    root: &Path,
+    checkpoint: &ScanCheckpoint,
+) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO scan_checkpoints (drive_id, root, seq, cursor, saved_at, entries, skipped)
+         SELECT ?1, ?2, COALESCE(MAX(seq), 0) + 1, ?3, ?4, ?5, ?6
+         FROM scan_checkpoints WHERE drive_id = ?1 AND root = ?2",
+        params![
+            drive_id.0.to_string(),
+            path_encoding::encode(root),
+            path_encoding::encode(&checkpoint.cursor),
+            fmt_dt(&checkpoint.saved_at),
+            serde_json::to_string(&checkpoint.entries)?,
+            serde_json::to_string(&checkpoint.skipped)?,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Where the scan of `root` on `drive_id` got to: its latest checkpoint,
+/// with everything found by it and the ones before.
+pub fn load_scan_checkpoint(conn: &Connection, drive_id: &DriveId, root: &Path) -> anyhow::Result<Option<ScanCheckpoint>> {
+    let mut stmt = conn.prepare(
+        "SELECT cursor, saved_at, entries, skipped FROM scan_checkpoints
+         WHERE drive_id = ?1 AND root = ?2 ORDER BY seq",
+    )?;
+    let mut rows = stmt.query(params![drive_id.0.to_string(), path_encoding::encode(root)])?;
+    let mut checkpoint: Option<ScanCheckpoint> = None;
+    while let Some(row) = rows.next()? {
+        let mut entries: Vec<FileEntry> = serde_json::from_str(&row.get::<_, String>(2)?)?;
+        let mut skipped: Vec<SkippedFile> = serde_json::from_str(&row.get::<_, String>(3)?)?;
+        let cp = checkpoint.get_or_insert_with(|| ScanCheckpoint {
+            cursor: PathBuf::new(),
+            saved_at: Utc::now(),
+            entries: Vec::new(),
+            skipped: Vec::new(),
+        });
+        cp.cursor = path_encoding::decode(&row.get::<_, String>(0)?);
+        cp.saved_at = parse_dt(&row.get::<_, String>(1)?);
+        cp.entries.append(&mut entries);
+        cp.skipped.append(&mut skipped);
+    }
+    Ok(checkpoint)
+}
+
+/// Forget the checkpoints of the scan of `root` on `drive_id`.
+pub fn clear_scan_checkpoint(conn: &Connection, drive_id: &DriveId, root: &Path) -> anyhow::Result<()> {
+    conn.execute(
+        "DELETE FROM scan_checkpoints WHERE drive_id = ?1 AND root = ?2",
+        params![drive_id.0.to_string(), path_encoding::encode(root)],
+    )?;
+    Ok(())
+}
+
 // ── Sync History ──
 
 pub fn insert_sync_record(conn: &Connection, record: &SyncRecord) -> anyhow::Result<()> {
     let errors_json = serde_json::to_string(&record.errors).unwrap_or_else(|_| "[]".to_string());
     conn.execute(
-        "INSERT INTO sync_history (id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        "INSERT INTO sync_history (id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status,
+                                   host, username, diffr_version, message)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
         params![
             record.id.to_string(),
             record.cluster_id.0.to_string(),
@@ -375,6 +906,10 @@ pub fn insert_sync_record(conn: &Connection, record: &SyncRecord) -> anyhow::Res
             record.conflicts_resolved as i64,
             errors_json,
             record.status.to_string(),
+            record.host,
+            record.user,
+            record.diffr_version,
+            record.message,
         ],
     )?;
     Ok(())
@@ -382,50 +917,348 @@ pub fn insert_sync_record(conn: &Connection, record: &SyncRecord) -> anyhow::Res
 
 pub fn list_sync_history(conn: &Connection, cluster_id: &ClusterId, limit: u32) -> anyhow::Result<Vec<SyncRecord>> {
     let mut stmt = conn.prepare(
-        "SELECT id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status
+        "SELECT id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status,
+                host, username, diffr_version, message
          FROM sync_history WHERE cluster_id = ?1 ORDER BY started_at DESC LIMIT ?2",
     )?;
-    let rows = stmt.query_map(params![cluster_id.0.to_string(), limit], |row| {
-        let id_str: String = row.get(0)?;
-        let cluster_str: String = row.get(1)?;
-        let started_str: String = row.get(2)?;
-        let finished_str: String = row.get(3)?;
-        let files: i64 = row.get(4)?;
-        let bytes: i64 = row.get(5)?;
-        let conflicts: i64 = row.get(6)?;
-        let errors_str: String = row.get(7)?;
-        let status_str: String = row.get(8)?;
-        let errors: Vec<String> = serde_json::from_str(&errors_str).unwrap_or_default();
-        let status = match status_str.as_str() {
-            "success" => SyncStatus::Success,
-            "partial_success" => SyncStatus::PartialSuccess,
-            _ => SyncStatus::Failed,
-        };
-        Ok(SyncRecord {
-            id: Uuid::parse_str(&id_str).unwrap_or_default(),
-            cluster_id: ClusterId::from_uuid(Uuid::parse_str(&cluster_str).unwrap_or_default()),
-            started_at: parse_dt(&started_str),
-            finished_at: parse_dt(&finished_str),
-            files_synced: files as u64,
-            bytes_transferred: bytes as u64,
-            conflicts_resolved: conflicts as u64,
-            errors,
-            status,
+    let rows = stmt.query_map(params![cluster_id.0.to_string(), limit], row_to_sync_record)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Sync history across all clusters, newest first.
+pub fn list_all_sync_history(conn: &Connection, limit: u32) -> anyhow::Result<Vec<SyncRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status,
+                host, username, diffr_version, message
+         FROM sync_history ORDER BY started_at DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![limit], row_to_sync_record)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+pub fn get_sync_record(conn: &Connection, id: &Uuid) -> anyhow::Result<Option<SyncRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status,
+                host, username, diffr_version, message
+         FROM sync_history WHERE id = ?1",
+    )?;
+    let mut rows = stmt.query(params![id.to_string()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_sync_record(row)?)),
+        None => Ok(None),
+    }
+}
+
+/// The newest sync that still has journaled operations to undo.
+pub fn get_last_undoable_sync(conn: &Connection) -> anyhow::Result<Option<SyncRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, cluster_id, started_at, finished_at, files_synced, bytes_transferred, conflicts_resolved, errors, status,
+                host, username, diffr_version, message
+         FROM sync_history h
+         WHERE EXISTS (SELECT 1 FROM sync_journal j WHERE j.sync_id = h.id)
+         ORDER BY started_at DESC LIMIT 1",
+    )?;
+    let mut rows = stmt.query([])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_sync_record(row)?)),
+        None => Ok(None),
+    }
+}
+
+fn row_to_sync_record(row: &rusqlite::Row) -> rusqlite::Result<SyncRecord> {
+    let id_str: String = row.get(0)?;
+    let cluster_str: String = row.get(1)?;
+    let started_str: String = row.get(2)?;
+    let finished_str: String = row.get(3)?;
+    let files: i64 = row.get(4)?;
+    let bytes: i64 = row.get(5)?;
+    let conflicts: i64 = row.get(6)?;
+    let errors_str: String = row.get(7)?;
+    let status_str: String = row.get(8)?;
+    let errors: Vec<String> = serde_json::from_str(&errors_str).unwrap_or_default();
+    let status = match status_str.as_str() {
+        "success" => SyncStatus::Success,
+        "partial_success" => SyncStatus::PartialSuccess,
+        "cancelled" => SyncStatus::Cancelled,
+        _ => SyncStatus::Failed,
+    };
+    Ok(SyncRecord {
+        id: Uuid::parse_str(&id_str).unwrap_or_default(),
+        cluster_id: ClusterId::from_uuid(Uuid::parse_str(&cluster_str).unwrap_or_default()),
+        started_at: parse_dt(&started_str),
+        finished_at: parse_dt(&finished_str),
+        files_synced: files as u64,
+        bytes_transferred: bytes as u64,
+        conflicts_resolved: conflicts as u64,
+        errors,
+        status,
+        host: row.get(9)?,
+        user: row.get(10)?,
+        diffr_version: row.get(11)?,
+        message: row.get(12)?,
+    })
+}
+
+// ── Sync Journal ──
+
+/// Record the operations a sync carried out, in execution order.
+pub fn insert_journal(
+    conn: &Connection,
+    entries: impl IntoIterator<Item = impl Borrow<JournalEntry>>,
+) -> anyhow::Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO sync_journal (op_id, sync_id, seq, kind, rel_path, source_drive, target_drive, size_bytes, archive_id, trash_path, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for (seq, entry) in entries.into_iter().enumerate() {
+        let entry = entry.borrow();
+        stmt.execute(params![
+            entry.op.id.to_string(),
+            entry.sync_id.to_string(),
+            seq as i64,
+            entry.op.kind.to_string(),
+            path_encoding::encode(&entry.op.rel_path),
+            entry.op.source_drive.as_ref().map(|d| d.0.to_string()),
+            entry.op.target_drive.0.to_string(),
+            entry.op.size_bytes as i64,
+            entry.archive_id.map(|id| id.to_string()),
+            entry.trash_path.as_deref().map(path_encoding::encode),
+            entry.op.reason,
+        ])?;
+    }
+    Ok(())
+}
+
+/// Journaled operations for a sync, in execution order.
+pub fn list_journal(conn: &Connection, sync_id: &Uuid) -> anyhow::Result<Vec<JournalEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT op_id, sync_id, kind, rel_path, source_drive, target_drive, size_bytes, archive_id, trash_path, reason
+         FROM sync_journal WHERE sync_id = ?1 ORDER BY seq",
+    )?;
+    let rows = stmt.query_map(params![sync_id.to_string()], row_to_journal_entry)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Syncs whose journal has an operation reading from or writing to
+/// `drive_id`.
+pub fn list_sync_ids_for_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<HashSet<Uuid>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT sync_id FROM sync_journal WHERE source_drive = ?1 OR target_drive = ?1",
+    )?;
+    let rows = stmt.query_map(params![drive_id.0.to_string()], |row| row.get::<_, String>(0))?;
+    Ok(rows
+        .filter_map(|r| r.ok())
+        .filter_map(|id| Uuid::parse_str(&id).ok())
+        .collect())
+}
+
+/// Forget a journaled operation once it has been undone.
+pub fn delete_journal_entry(conn: &Connection, op_id: &Uuid) -> anyhow::Result<()> {
+    conn.execute("DELETE FROM sync_journal WHERE op_id = ?1", params![op_id.to_string()])?;
+    Ok(())
+}
+
+fn row_to_journal_entry(row: &rusqlite::Row) -> rusqlite::Result<JournalEntry> {
+    let op_id: String = row.get(0)?;
+    let sync_id: String = row.get(1)?;
+    let kind_str: String = row.get(2)?;
+    let rel_path: String = row.get(3)?;
+    let source: Option<String> = row.get(4)?;
+    let target: String = row.get(5)?;
+    let size: i64 = row.get(6)?;
+    let archive_id: Option<String> = row.get(7)?;
+    let trash_path: Option<String> = row.get(8)?;
+    let reason: Option<String> = row.get(9)?;
+    let kind = match kind_str.as_str() {
+        "copy_new" => SyncOpKind::CopyNew,
+        "overwrite" => SyncOpKind::Overwrite,
+        "delete" => SyncOpKind::Delete,
+        "local_copy" => SyncOpKind::LocalCopy,
+        "create_dir" => SyncOpKind::CreateDir,
+        "remove_dir" => SyncOpKind::RemoveDir,
+        "set_permissions" => SyncOpKind::SetPermissions,
+        "merge" => SyncOpKind::Merge,
+        _ => SyncOpKind::ResolveConflict,
+    };
+    let drive_id = |s: &str| DriveId::from_uuid(Uuid::parse_str(s).unwrap_or_default());
+    Ok(JournalEntry {
+        sync_id: Uuid::parse_str(&sync_id).unwrap_or_default(),
+        op: SyncOp {
+            id: Uuid::parse_str(&op_id).unwrap_or_default(),
+            kind,
+            rel_path: path_encoding::decode(&rel_path),
+            source_drive: source.as_deref().map(drive_id),
+            target_drive: drive_id(&target),
+            size_bytes: size as u64,
+            source_path: None,
+            reason,
+            expect: None,
+        },
+        archive_id: archive_id.and_then(|s| Uuid::parse_str(&s).ok()),
+        trash_path: trash_path.as_deref().map(path_encoding::decode),
+    })
+}
+
+// ── Skip lists ──
+
+/// Put a path on its cluster's skip list, replacing the expiry if it's
+/// already there.
+pub fn add_skip(conn: &Connection, skip: &PathSkip) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO sync_skips (cluster_id, rel_path, added_at, expires_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (cluster_id, rel_path) DO UPDATE SET added_at = excluded.added_at, expires_at = excluded.expires_at",
+        params![
+            skip.cluster_id.0.to_string(),
+            path_encoding::encode(&skip.rel_path),
+            fmt_dt(&skip.added_at),
+            fmt_dt(&skip.expires_at),
+        ],
+    )?;
+    Ok(())
+}
+
+/// A cluster's skips that haven't expired by `now`, by path.
+pub fn list_skips(conn: &Connection, cluster_id: &ClusterId, now: DateTime<Utc>) -> anyhow::Result<Vec<PathSkip>> {
+    let mut stmt = conn.prepare(
+        "SELECT rel_path, added_at, expires_at FROM sync_skips
+         WHERE cluster_id = ?1 AND expires_at > ?2 ORDER BY rel_path",
+    )?;
+    let rows = stmt.query_map(params![cluster_id.0.to_string(), fmt_dt(&now)], |row| {
+        let rel_path: String = row.get(0)?;
+        let added_at: String = row.get(1)?;
+        let expires_at: String = row.get(2)?;
+        Ok(PathSkip {
+            cluster_id: cluster_id.clone(),
+            rel_path: path_encoding::decode(&rel_path),
+            added_at: parse_dt(&added_at),
+            expires_at: parse_dt(&expires_at),
         })
     })?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
+/// Take skips off a cluster's list: the one for `rel_path`, or all of
+/// them. Returns how many were removed.
+pub fn remove_skips(conn: &Connection, cluster_id: &ClusterId, rel_path: Option<&Path>) -> anyhow::Result<usize> {
+    let removed = match rel_path {
+        Some(path) => conn.execute(
+            "DELETE FROM sync_skips WHERE cluster_id = ?1 AND rel_path = ?2",
+            params![cluster_id.0.to_string(), path_encoding::encode(path)],
+        )?,
+        None => conn.execute(
+            "DELETE FROM sync_skips WHERE cluster_id = ?1",
+            params![cluster_id.0.to_string()],
+        )?,
+    };
+    Ok(removed)
+}
+
+/// Delete every skip that expired by `now`. Returns how many there were.
+pub fn prune_expired_skips(conn: &Connection, now: DateTime<Utc>) -> anyhow::Result<usize> {
+    let removed = conn.execute("DELETE FROM sync_skips WHERE expires_at <= ?1", params![fmt_dt(&now)])?;
+    Ok(removed)
+}
+
+// ── Throughput ──
+
+/// Fold a sync's measured copy speed into the running totals for its drive
+/// pair. Older totals are halved first, so recent syncs count for more
+/// while one small or unusual sync can't swing the estimate on its own.
+pub fn record_throughput(conn: &Connection, sample: &Throughput) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO throughput (source_drive, target_drive, bytes, seconds, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (source_drive, target_drive) DO UPDATE SET
+             bytes = bytes / 2 + excluded.bytes,
+             seconds = seconds / 2 + excluded.seconds,
+             updated_at = excluded.updated_at",
+        params![
+            sample.source_drive.0.to_string(),
+            sample.target_drive.0.to_string(),
+            sample.bytes as i64,
+            sample.seconds,
+            fmt_dt(&Utc::now()),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Running throughput totals for every drive pair that has been measured.
+pub fn list_throughput(conn: &Connection) -> anyhow::Result<Vec<Throughput>> {
+    let mut stmt = conn.prepare("SELECT source_drive, target_drive, bytes, seconds FROM throughput")?;
+    let rows = stmt.query_map([], |row| {
+        let source: String = row.get(0)?;
+        let target: String = row.get(1)?;
+        let bytes: i64 = row.get(2)?;
+        Ok(Throughput {
+            source_drive: DriveId::from_uuid(Uuid::parse_str(&source).unwrap_or_default()),
+            target_drive: DriveId::from_uuid(Uuid::parse_str(&target).unwrap_or_default()),
+            bytes: bytes as u64,
+            seconds: row.get(3)?,
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+// ── Plans ──
+
+/// Remember `plan` as the latest one generated for its cluster, replacing
+/// the one before.
+pub fn save_last_plan(conn: &Connection, plan: &SyncPlan) -> anyhow::Result<()> {
+    // The plan row holds everything but the operations, which are written a
+    // row at a time.
+    let header = serde_json::json!({
+        "id": plan.id,
+        "cluster_id": plan.cluster_id,
+        "operations": [],
+        "total_bytes": plan.total_bytes,
+        "created_at": plan.created_at,
+    });
+    let cluster_id = plan.cluster_id.0.to_string();
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT OR REPLACE INTO last_plans (cluster_id, plan, created_at) VALUES (?1, ?2, ?3)",
+        params![cluster_id, header.to_string(), fmt_dt(&plan.created_at)],
+    )?;
+    tx.execute("DELETE FROM last_plan_ops WHERE cluster_id = ?1", params![cluster_id])?;
+    {
+        let mut insert =
+            tx.prepare("INSERT INTO last_plan_ops (cluster_id, seq, op) VALUES (?1, ?2, ?3)")?;
+        for (seq, op) in plan.operations.iter().enumerate() {
+            insert.execute(params![cluster_id, seq as i64, serde_json::to_string(&op)?])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// The plan saved by the cluster's last sync or dry run.
+pub fn get_last_plan(conn: &Connection, cluster_id: &ClusterId) -> anyhow::Result<Option<SyncPlan>> {
+    let mut stmt = conn.prepare("SELECT plan FROM last_plans WHERE cluster_id = ?1")?;
+    let mut rows = stmt.query(params![cluster_id.0.to_string()])?;
+    let mut plan: SyncPlan = match rows.next()? {
+        Some(row) => serde_json::from_str(&row.get::<_, String>(0)?)?,
+        None => return Ok(None),
+    };
+    // Plans saved before v18 carry their operations inline instead.
+    let mut stmt = conn.prepare("SELECT op FROM last_plan_ops WHERE cluster_id = ?1 ORDER BY seq")?;
+    let mut rows = stmt.query(params![cluster_id.0.to_string()])?;
+    while let Some(row) = rows.next()? {
+        plan.operations.push(serde_json::from_str(&row.get::<_, String>(0)?)?);
+    }
+    Ok(Some(plan))
+}
+
 // ── Archives ──
 
 pub fn insert_archive(conn: &Connection, entry: &ArchiveEntry) -> anyhow::Result<()> {
     conn.execute(
-        "INSERT INTO archives (id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        "INSERT INTO archives (id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at, relocated_from, relocated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
         params![
             entry.id.to_string(),
-            entry.original_path.to_string_lossy().to_string(),
-            entry.archive_path.to_string_lossy().to_string(),
+            path_encoding::encode(&entry.original_path),
+            path_encoding::encode(&entry.archive_path),
             entry.drive_id.0.to_string(),
             entry.original_size as i64,
             entry.compressed_size as i64,
@@ -433,43 +1266,214 @@ pub fn insert_archive(conn: &Connection, entry: &ArchiveEntry) -> anyhow::Result
             entry.xxh3_hash,
             entry.reason.to_string(),
             fmt_dt(&entry.archived_at),
+            entry.relocated_from.as_ref().map(|d| d.0.to_string()),
+            entry.relocated_at.as_ref().map(fmt_dt),
         ],
     )?;
     Ok(())
 }
 
-pub fn list_archives_for_path(conn: &Connection, original_path: &str) -> anyhow::Result<Vec<ArchiveEntry>> {
+pub fn list_archives_for_path(conn: &Connection, original_path: &Path) -> anyhow::Result<Vec<ArchiveEntry>> {
     let mut stmt = conn.prepare(
-        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at
+        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at, relocated_from, relocated_at
          FROM archives WHERE original_path = ?1 ORDER BY archived_at DESC",
     )?;
-    let rows = stmt.query_map(params![original_path], |row| row_to_archive(row))?;
+    let rows = stmt.query_map(params![path_encoding::encode(original_path)], row_to_archive)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Archives of `prefix` itself or of anything under it, newest first. An
+/// empty prefix matches every archive.
+pub fn list_archives_under(conn: &Connection, prefix: &Path) -> anyhow::Result<Vec<ArchiveEntry>> {
+    let prefix = path_encoding::encode(prefix);
+    let prefix = prefix.trim_end_matches('/');
+    let mut stmt = conn.prepare(
+        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at, relocated_from, relocated_at
+         FROM archives
+         WHERE ?1 = '' OR original_path = ?1 OR substr(original_path, 1, length(?1) + 1) = ?1 || '/'
+         ORDER BY archived_at DESC",
+    )?;
+    let rows = stmt.query_map(params![prefix], row_to_archive)?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
 pub fn list_archives_for_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Vec<ArchiveEntry>> {
     let mut stmt = conn.prepare(
-        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at
+        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at, relocated_from, relocated_at
          FROM archives WHERE drive_id = ?1 ORDER BY archived_at DESC",
     )?;
-    let rows = stmt.query_map(params![drive_id.0.to_string()], |row| row_to_archive(row))?;
+    let rows = stmt.query_map(params![drive_id.0.to_string()], row_to_archive)?;
     Ok(rows.filter_map(|r| r.ok()).collect())
 }
 
+pub fn get_archive_by_id(conn: &Connection, id: &Uuid) -> anyhow::Result<Option<ArchiveEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, original_path, archive_path, drive_id, original_size, compressed_size, compression, xxh3_hash, reason, archived_at, relocated_from, relocated_at
+         FROM archives WHERE id = ?1",
+    )?;
+    let mut rows = stmt.query(params![id.to_string()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row_to_archive(row)?)),
+        None => Ok(None),
+    }
+}
+
+/// Record that an archive now lives on `drive_id` at `archive_path`.
+pub fn update_archive_location(conn: &Connection, entry: &ArchiveEntry) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE archives SET drive_id = ?1, archive_path = ?2, relocated_from = ?3, relocated_at = ?4
+         WHERE id = ?5",
+        params![
+            entry.drive_id.0.to_string(),
+            path_encoding::encode(&entry.archive_path),
+            entry.relocated_from.as_ref().map(|d| d.0.to_string()),
+            entry.relocated_at.as_ref().map(fmt_dt),
+            entry.id.to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
 pub fn delete_archive(conn: &Connection, id: &Uuid) -> anyhow::Result<()> {
     conn.execute("DELETE FROM archives WHERE id = ?1", params![id.to_string()])?;
     Ok(())
 }
 
+pub fn insert_archive_part(conn: &Connection, part: &ArchivePart) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO archive_parts (archive_id, part_index, drive_id, part_path, size)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            part.archive_id.to_string(),
+            part.index,
+            part.drive_id.0.to_string(),
+            path_encoding::encode(&part.part_path),
+            part.size as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// The parts of a split archive in order, or none if it's stored whole.
+pub fn list_archive_parts(conn: &Connection, archive_id: &Uuid) -> anyhow::Result<Vec<ArchivePart>> {
+    let mut stmt = conn.prepare(
+        "SELECT archive_id, part_index, drive_id, part_path, size
+         FROM archive_parts WHERE archive_id = ?1 ORDER BY part_index",
+    )?;
+    let rows = stmt.query_map(params![archive_id.to_string()], row_to_archive_part)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Every archive part stored on a drive.
+pub fn list_archive_parts_for_drive(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<Vec<ArchivePart>> {
+    let mut stmt = conn.prepare(
+        "SELECT archive_id, part_index, drive_id, part_path, size
+         FROM archive_parts WHERE drive_id = ?1 ORDER BY archive_id, part_index",
+    )?;
+    let rows = stmt.query_map(params![drive_id.0.to_string()], row_to_archive_part)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+fn row_to_archive_part(row: &rusqlite::Row) -> rusqlite::Result<ArchivePart> {
+    let archive_id: String = row.get(0)?;
+    let drive_id: String = row.get(2)?;
+    let part_path: String = row.get(3)?;
+    let size: i64 = row.get(4)?;
+    Ok(ArchivePart {
+        archive_id: Uuid::parse_str(&archive_id).unwrap_or_default(),
+        index: row.get(1)?,
+        drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id).unwrap_or_default()),
+        part_path: path_encoding::decode(&part_path),
+        size: size as u64,
+    })
+}
+
+/// Bytes of archives stored on a drive, counting only its own parts of
+/// archives split across drives.
 pub fn get_total_archive_size(conn: &Connection, drive_id: &DriveId) -> anyhow::Result<u64> {
     let size: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(compressed_size), 0) FROM archives WHERE drive_id = ?1",
+        "SELECT COALESCE((SELECT SUM(compressed_size) FROM archives
+                          WHERE drive_id = ?1 AND id NOT IN (SELECT archive_id FROM archive_parts)), 0)
+              + COALESCE((SELECT SUM(size) FROM archive_parts WHERE drive_id = ?1), 0)",
         params![drive_id.0.to_string()],
         |row| row.get(0),
     )?;
     Ok(size as u64)
 }
 
+/// Archive totals for one drive, or for one file extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveStats<K> {
+    pub key: K,
+    pub archives: u64,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl<K> ArchiveStats<K> {
+    /// Original size over compressed size, e.g. 3.0 when archives take a
+    /// third of the space the files did.
+    pub fn ratio(&self) -> f64 {
+        self.original_bytes as f64 / self.compressed_bytes.max(1) as f64
+    }
+}
+
+/// Archive totals per drive, optionally for just one drive.
+pub fn archive_stats_by_drive(
+    conn: &Connection,
+    drive_id: Option<&DriveId>,
+) -> anyhow::Result<Vec<ArchiveStats<DriveId>>> {
+    let mut stmt = conn.prepare(
+        "SELECT drive_id, COUNT(*), SUM(original_size), SUM(compressed_size)
+         FROM archives WHERE ?1 IS NULL OR drive_id = ?1
+         GROUP BY drive_id",
+    )?;
+    let rows = stmt.query_map(params![drive_id.map(|d| d.0.to_string())], |row| {
+        let drive_id_str: String = row.get(0)?;
+        Ok(ArchiveStats {
+            key: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
+            archives: row.get::<_, i64>(1)? as u64,
+            original_bytes: row.get::<_, i64>(2)? as u64,
+            compressed_bytes: row.get::<_, i64>(3)? as u64,
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Archive totals per lowercased file extension of the original path,
+/// largest first. Files without an extension (dotfiles included) are
+/// grouped under an empty one.
+pub fn archive_stats_by_extension(
+    conn: &Connection,
+    drive_id: Option<&DriveId>,
+) -> anyhow::Result<Vec<ArchiveStats<String>>> {
+    // SQLite has no "find last", so the file name is what's left after
+    // trimming up to the last '/', and the extension likewise after the
+    // last '.'.
+    let mut stmt = conn.prepare(
+        "WITH paths AS (
+             SELECT replace(original_path, '\\', '/') AS p, original_size, compressed_size
+             FROM archives WHERE ?1 IS NULL OR drive_id = ?1
+         ), names AS (
+             SELECT replace(p, rtrim(p, replace(p, '/', '')), '') AS name, original_size, compressed_size
+             FROM paths
+         )
+         SELECT CASE WHEN instr(substr(name, 2), '.') = 0 THEN ''
+                     ELSE lower(replace(name, rtrim(name, replace(name, '.', '')), '')) END AS ext,
+                COUNT(*), SUM(original_size), SUM(compressed_size)
+         FROM names GROUP BY ext ORDER BY SUM(original_size) DESC, ext",
+    )?;
+    let rows = stmt.query_map(params![drive_id.map(|d| d.0.to_string())], |row| {
+        Ok(ArchiveStats {
+            key: row.get(0)?,
+            archives: row.get::<_, i64>(1)? as u64,
+            original_bytes: row.get::<_, i64>(2)? as u64,
+            compressed_bytes: row.get::<_, i64>(3)? as u64,
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
 fn row_to_archive(row: &rusqlite::Row) -> rusqlite::Result<ArchiveEntry> {
     let id_str: String = row.get(0)?;
     let original_path: String = row.get(1)?;
@@ -481,6 +1485,8 @@ fn row_to_archive(row: &rusqlite::Row) -> rusqlite::Result<ArchiveEntry> {
     let xxh3: String = row.get(7)?;
     let reason_str: String = row.get(8)?;
     let archived_str: String = row.get(9)?;
+    let relocated_from: Option<String> = row.get(10)?;
+    let relocated_at: Option<String> = row.get(11)?;
 
     let compression = match compression_str.as_str() {
         "none" => CompressionFormat::None,
@@ -494,8 +1500,8 @@ fn row_to_archive(row: &rusqlite::Row) -> rusqlite::Result<ArchiveEntry> {
 
     Ok(ArchiveEntry {
         id: Uuid::parse_str(&id_str).unwrap_or_default(),
-        original_path: original_path.into(),
-        archive_path: archive_path.into(),
+        original_path: path_encoding::decode(&original_path),
+        archive_path: path_encoding::decode(&archive_path),
         drive_id: DriveId::from_uuid(Uuid::parse_str(&drive_id_str).unwrap_or_default()),
         original_size: original_size as u64,
         compressed_size: compressed_size as u64,
@@ -503,6 +1509,10 @@ fn row_to_archive(row: &rusqlite::Row) -> rusqlite::Result<ArchiveEntry> {
         xxh3_hash: xxh3,
         reason,
         archived_at: parse_dt(&archived_str),
+        relocated_from: relocated_from
+            .and_then(|s| Uuid::parse_str(&s).ok())
+            .map(DriveId::from_uuid),
+        relocated_at: relocated_at.as_deref().map(parse_dt),
     })
 }
 
@@ -530,6 +1540,76 @@ mod tests {
         assert!(gone.is_none());
     }
 
+    #[test]
+    fn test_last_plan_round_trip() {
+        let conn = open_memory_db().unwrap();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        insert_cluster(&conn, &cluster).unwrap();
+        let op = |path: &str| SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: path.into(),
+            source_drive: Some(DriveId::new()),
+            target_drive: DriveId::new(),
+            size_bytes: 3,
+            source_path: None,
+            reason: Some(format!("only_left: only on /mnt/{}", path)),
+            expect: None,
+        };
+
+        save_last_plan(&conn, &SyncPlan::new(cluster.id.clone(), vec![op("a"), op("b"), op("c")])).unwrap();
+        let plan = SyncPlan::new(cluster.id.clone(), vec![op("z"), op("y")]);
+        save_last_plan(&conn, &plan).unwrap();
+        let loaded = get_last_plan(&conn, &cluster.id).unwrap().unwrap();
+        assert_eq!(loaded.id, plan.id);
+        assert_eq!(loaded.total_bytes, 6);
+        let paths: Vec<_> = loaded.operations.iter().map(|o| o.rel_path.clone()).collect();
+        assert_eq!(paths, vec![std::path::PathBuf::from("z"), "y".into()]);
+        assert_eq!(loaded.operations.to_vec()[0].reason.as_deref(), Some("only_left: only on /mnt/z"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/usb".into());
+        insert_drive(&conn, &drive).unwrap();
+        let latin1 = Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9 100%.txt"));
+        let entry = FileEntry {
+            rel_path: latin1.to_path_buf(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size: 1,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        };
+        upsert_file_entry(&conn, &entry).unwrap();
+        assert_eq!(get_file_entries_for_drive(&conn, &drive.id).unwrap()[0].rel_path, latin1);
+
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        insert_cluster(&conn, &cluster).unwrap();
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: latin1.to_path_buf(),
+            source_drive: Some(drive.id.clone()),
+            target_drive: DriveId::new(),
+            size_bytes: 1,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        save_last_plan(&conn, &SyncPlan::new(cluster.id.clone(), vec![op])).unwrap();
+        let loaded = get_last_plan(&conn, &cluster.id).unwrap().unwrap();
+        assert_eq!(loaded.operations.to_vec()[0].rel_path, latin1);
+    }
+
     #[test]
     fn test_drive_crud() {
         let conn = open_memory_db().unwrap();
@@ -547,4 +1627,188 @@ mod tests {
         let all = list_all_drives(&conn).unwrap();
         assert_eq!(all.len(), 1);
     }
+
+    #[test]
+    fn test_drive_tags() {
+        let conn = open_memory_db().unwrap();
+        let a = Drive::new(DriveIdentity::new_synthetic(), "/mnt/a".into());
+        let b = Drive::new(DriveIdentity::new_synthetic(), "/mnt/b".into());
+        insert_drive(&conn, &a).unwrap();
+        insert_drive(&conn, &b).unwrap();
+
+        assert!(add_drive_tag(&conn, &a.id, "offsite").unwrap());
+        assert!(!add_drive_tag(&conn, &a.id, "offsite").unwrap());
+        add_drive_tag(&conn, &a.id, "cold").unwrap();
+        add_drive_tag(&conn, &b.id, "offsite").unwrap();
+        assert_eq!(list_drive_tags(&conn, &a.id).unwrap(), vec!["cold", "offsite"]);
+        let tagged: Vec<DriveId> = list_drives_with_tag(&conn, "offsite").unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(tagged, vec![a.id.clone(), b.id.clone()]);
+        assert_eq!(list_tags(&conn).unwrap()[1], ("offsite".to_string(), vec![a.id.clone(), b.id.clone()]));
+
+        assert!(remove_drive_tag(&conn, &b.id, "offsite").unwrap());
+        assert!(!remove_drive_tag(&conn, &b.id, "offsite").unwrap());
+        delete_drive(&conn, &a.id).unwrap();
+        assert!(list_tags(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_skips() {
+        let conn = open_memory_db().unwrap();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        insert_cluster(&conn, &cluster).unwrap();
+        let now = Utc::now();
+        let skip = |path: &str, hours: i64| PathSkip {
+            cluster_id: cluster.id.clone(),
+            rel_path: path.into(),
+            added_at: now,
+            expires_at: now + chrono::Duration::hours(hours),
+        };
+        add_skip(&conn, &skip("datasets/raw", 1)).unwrap();
+        add_skip(&conn, &skip("old", -1)).unwrap();
+        add_skip(&conn, &skip("tmp", 2)).unwrap();
+        // Adding a path again moves its expiry.
+        add_skip(&conn, &skip("tmp", 48)).unwrap();
+
+        let active = list_skips(&conn, &cluster.id, now).unwrap();
+        let paths: Vec<_> = active.iter().map(|s| s.rel_path.to_string_lossy().into_owned()).collect();
+        assert_eq!(paths, vec!["datasets/raw", "tmp"]);
+        assert!(active[0].covers(Path::new("datasets/raw/part-1.bin")));
+        assert!(!active[0].covers(Path::new("datasets/rawer")));
+        assert_eq!(active[1].expires_at, skip("tmp", 48).expires_at);
+
+        assert_eq!(prune_expired_skips(&conn, now).unwrap(), 1);
+        assert_eq!(remove_skips(&conn, &cluster.id, Some(Path::new("tmp"))).unwrap(), 1);
+        assert_eq!(remove_skips(&conn, &cluster.id, None).unwrap(), 1);
+        assert!(list_skips(&conn, &cluster.id, now).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prune_hash_cache() {
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/usb".into());
+        insert_drive(&conn, &drive).unwrap();
+        for path in ["kept.txt", "gone.txt"] {
+            let cached = HashCacheEntry {
+                rel_path: path.into(),
+                drive_id: drive.id.clone(),
+                size: 1,
+                mtime: Utc::now(),
+                file_id: None,
+                xxh3_hash: "00".into(),
+                sha256_hash: None,
+                blake3_hash: None,
+                cached_at: Utc::now(),
+            };
+            upsert_hash_cache(&conn, &cached).unwrap();
+        }
+
+        // Without a catalog there's nothing to prune against.
+        assert_eq!(prune_hash_cache(&conn, &drive.id).unwrap(), 0);
+        assert_eq!(hash_cache_stats(&conn).unwrap()[0].orphaned, 0);
+
+        let kept = FileEntry {
+            rel_path: "kept.txt".into(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size: 1,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        };
+        replace_file_index(&conn, &drive.id, &[kept]).unwrap();
+        let stats = hash_cache_stats(&conn).unwrap();
+        assert_eq!((stats[0].entries, stats[0].orphaned), (2, 1));
+        assert_eq!(prune_hash_cache(&conn, &drive.id).unwrap(), 1);
+        assert!(get_hash_cache_entry(&conn, &drive.id, Path::new("kept.txt")).unwrap().is_some());
+        assert_eq!(clear_hash_cache(&conn, None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_file_index_generations() {
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/usb".into());
+        insert_drive(&conn, &drive).unwrap();
+        let file = |path: &str, size: u64| FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size,
+            mtime: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        };
+
+        let first = replace_file_index(&conn, &drive.id, &[file("kept", 1), file("edited", 1), file("gone", 1)]).unwrap();
+        let second = replace_file_index(&conn, &drive.id, &[file("kept", 1), file("edited", 2), file("new", 1)]).unwrap();
+        assert_eq!((first, second), (1, 2));
+        let live: Vec<_> = get_file_entries_for_drive(&conn, &drive.id).unwrap().into_iter().map(|e| e.rel_path).collect();
+        assert_eq!(live, vec![std::path::PathBuf::from("edited"), "kept".into(), "new".into()]);
+
+        let changes: Vec<_> = file_index_changes_since(&conn, &drive.id, first)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.entry.rel_path.display().to_string(), c.kind))
+            .collect();
+        let expected = [
+            ("edited", IndexChangeKind::Modified),
+            ("gone", IndexChangeKind::Deleted),
+            ("new", IndexChangeKind::Added),
+        ];
+        assert_eq!(changes, expected.map(|(p, k)| (p.to_string(), k)));
+        assert!(file_index_changes_since(&conn, &drive.id, second).unwrap().is_empty());
+
+        // A deleted file that comes back is new again.
+        replace_file_index(&conn, &drive.id, &[file("gone", 1)]).unwrap();
+        let back = file_index_changes_since(&conn, &drive.id, second).unwrap();
+        assert!(back.iter().any(|c| c.entry.rel_path.as_path() == Path::new("gone") && c.kind == IndexChangeKind::Added));
+        assert_eq!(list_index_generations(&conn, &drive.id).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_archive_stats() {
+        let conn = open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/mnt/usb".into());
+        insert_drive(&conn, &drive).unwrap();
+        for (path, original, compressed) in [
+            ("docs/report.TXT", 300, 100),
+            ("v1.2/notes.txt", 100, 50),
+            ("v1.2/Makefile", 40, 20),
+            (".bashrc", 10, 10),
+        ] {
+            let entry = ArchiveEntry {
+                id: Uuid::now_v7(),
+                original_path: path.into(),
+                archive_path: format!("{path}.zst").into(),
+                drive_id: drive.id.clone(),
+                original_size: original,
+                compressed_size: compressed,
+                compression: CompressionFormat::Zstd,
+                xxh3_hash: "00".into(),
+                reason: ArchiveReason::BeforeOverwrite,
+                archived_at: Utc::now(),
+                relocated_from: None,
+                relocated_at: None,
+            };
+            insert_archive(&conn, &entry).unwrap();
+        }
+
+        let by_drive = archive_stats_by_drive(&conn, Some(&drive.id)).unwrap();
+        assert_eq!(by_drive.len(), 1);
+        assert_eq!((by_drive[0].archives, by_drive[0].original_bytes), (4, 450));
+        assert_eq!(by_drive[0].compressed_bytes, 180);
+
+        let by_ext = archive_stats_by_extension(&conn, None).unwrap();
+        let keys: Vec<&str> = by_ext.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, ["txt", ""]);
+        assert_eq!(by_ext[0].ratio(), 400.0 / 150.0);
+        assert_eq!(by_ext[1].archives, 2);
+    }
 }
@@ -1,4 +1,4 @@
-/// SQL statements for creating the Diffr database schema.
+//! SQL statements for creating the Diffr database schema.
 
 pub const CREATE_CLUSTERS: &str = "
 CREATE TABLE IF NOT EXISTS clusters (
@@ -6,6 +6,7 @@ CREATE TABLE IF NOT EXISTS clusters (
     name        TEXT NOT NULL UNIQUE,
     topology    TEXT NOT NULL DEFAULT 'mesh',
     conflict_strategy TEXT NOT NULL DEFAULT 'newest_wins',
+    settings    TEXT NOT NULL DEFAULT '{}',
     created_at  TEXT NOT NULL,
     updated_at  TEXT NOT NULL
 )";
@@ -25,6 +26,13 @@ CREATE TABLE IF NOT EXISTS drives (
     free_bytes      INTEGER,
     last_seen       TEXT NOT NULL,
     created_at      TEXT NOT NULL,
+    read_only       INTEGER NOT NULL DEFAULT 0,
+    quota_bytes     INTEGER,
+    used_bytes      INTEGER,
+    volume_guid     TEXT,
+    filesystem      TEXT,
+    aliases         TEXT,
+    metadata_dir    TEXT,
     FOREIGN KEY (cluster_id) REFERENCES clusters(id) ON DELETE SET NULL,
     UNIQUE(identity_type, identity_value)
 )";
@@ -39,6 +47,16 @@ CREATE TABLE IF NOT EXISTS file_index (
     xxh3_hash   TEXT,
     sha256_hash TEXT,
     indexed_at  TEXT NOT NULL,
+    blake3_hash TEXT,
+    sampled_hash TEXT,
+    mode        INTEGER,
+    uid         INTEGER,
+    gid         INTEGER,
+    ctime       TEXT,
+    generation  INTEGER NOT NULL DEFAULT 0,
+    added_generation   INTEGER NOT NULL DEFAULT 0,
+    changed_generation INTEGER NOT NULL DEFAULT 0,
+    deleted_at  TEXT,
     PRIMARY KEY (rel_path, drive_id),
     FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
 )";
@@ -52,6 +70,8 @@ CREATE TABLE IF NOT EXISTS hash_cache (
     xxh3_hash   TEXT NOT NULL,
     sha256_hash TEXT,
     cached_at   TEXT NOT NULL,
+    file_id     TEXT,
+    blake3_hash TEXT,
     PRIMARY KEY (rel_path, drive_id),
     FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
 )";
@@ -67,6 +87,10 @@ CREATE TABLE IF NOT EXISTS sync_history (
     conflicts_resolved INTEGER NOT NULL DEFAULT 0,
     errors            TEXT NOT NULL DEFAULT '[]',
     status            TEXT NOT NULL,
+    host              TEXT,
+    username          TEXT,
+    diffr_version     TEXT,
+    message           TEXT,
     FOREIGN KEY (cluster_id) REFERENCES clusters(id) ON DELETE CASCADE
 )";
 
@@ -82,6 +106,111 @@ CREATE TABLE IF NOT EXISTS archives (
     xxh3_hash       TEXT NOT NULL,
     reason          TEXT NOT NULL,
     archived_at     TEXT NOT NULL,
+    relocated_from  TEXT,
+    relocated_at    TEXT,
+    FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_SYNC_JOURNAL: &str = "
+CREATE TABLE IF NOT EXISTS sync_journal (
+    op_id        TEXT PRIMARY KEY,
+    sync_id      TEXT NOT NULL,
+    seq          INTEGER NOT NULL,
+    kind         TEXT NOT NULL,
+    rel_path     TEXT NOT NULL,
+    source_drive TEXT,
+    target_drive TEXT NOT NULL,
+    size_bytes   INTEGER NOT NULL DEFAULT 0,
+    archive_id   TEXT,
+    trash_path   TEXT,
+    reason       TEXT,
+    FOREIGN KEY (sync_id) REFERENCES sync_history(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_THROUGHPUT: &str = "
+CREATE TABLE IF NOT EXISTS throughput (
+    source_drive TEXT NOT NULL,
+    target_drive TEXT NOT NULL,
+    bytes        INTEGER NOT NULL,
+    seconds      REAL NOT NULL,
+    updated_at   TEXT NOT NULL,
+    PRIMARY KEY (source_drive, target_drive),
+    FOREIGN KEY (source_drive) REFERENCES drives(id) ON DELETE CASCADE,
+    FOREIGN KEY (target_drive) REFERENCES drives(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_LAST_PLANS: &str = "
+CREATE TABLE IF NOT EXISTS last_plans (
+    cluster_id  TEXT PRIMARY KEY,
+    plan        TEXT NOT NULL,
+    created_at  TEXT NOT NULL,
+    FOREIGN KEY (cluster_id) REFERENCES clusters(id) ON DELETE CASCADE
+)";
+
+/// The operations of each cluster's last plan, one row each, so a plan
+/// with millions of them is never one string in memory.
+pub const CREATE_LAST_PLAN_OPS: &str = "
+CREATE TABLE IF NOT EXISTS last_plan_ops (
+    cluster_id  TEXT NOT NULL,
+    seq         INTEGER NOT NULL,
+    op          TEXT NOT NULL,
+    PRIMARY KEY (cluster_id, seq),
+    FOREIGN KEY (cluster_id) REFERENCES clusters(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_ARCHIVE_PARTS: &str = "
+CREATE TABLE IF NOT EXISTS archive_parts (
+    archive_id  TEXT NOT NULL,
+    part_index  INTEGER NOT NULL,
+    drive_id    TEXT NOT NULL,
+    part_path   TEXT NOT NULL,
+    size        INTEGER NOT NULL,
+    PRIMARY KEY (archive_id, part_index),
+    FOREIGN KEY (archive_id) REFERENCES archives(id) ON DELETE CASCADE
+)";
+
+/// One row per time a drive's catalog was replaced by a scan. The
+/// `file_index` generation columns refer to these.
+pub const CREATE_FILE_INDEX_GENERATIONS: &str = "
+CREATE TABLE IF NOT EXISTS file_index_generations (
+    drive_id    TEXT NOT NULL,
+    generation  INTEGER NOT NULL,
+    indexed_at  TEXT NOT NULL,
+    entries     INTEGER NOT NULL,
+    PRIMARY KEY (drive_id, generation),
+    FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
+)";
+
+/// Tags on drives, such as `offsite`, for picking several drives at once
+/// with `tag:<name>`.
+pub const CREATE_DRIVE_TAGS: &str = "
+CREATE TABLE IF NOT EXISTS drive_tags (
+    drive_id    TEXT NOT NULL,
+    tag         TEXT NOT NULL,
+    PRIMARY KEY (drive_id, tag),
+    FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_SYNC_SKIPS: &str = "
+CREATE TABLE IF NOT EXISTS sync_skips (
+    cluster_id  TEXT NOT NULL,
+    rel_path    TEXT NOT NULL,
+    added_at    TEXT NOT NULL,
+    expires_at  TEXT NOT NULL,
+    PRIMARY KEY (cluster_id, rel_path),
+    FOREIGN KEY (cluster_id) REFERENCES clusters(id) ON DELETE CASCADE
+)";
+
+pub const CREATE_SCAN_CHECKPOINTS: &str = "
+CREATE TABLE IF NOT EXISTS scan_checkpoints (
+    drive_id    TEXT NOT NULL,
+    root        TEXT NOT NULL,
+    seq         INTEGER NOT NULL,
+    cursor      TEXT NOT NULL,
+    saved_at    TEXT NOT NULL,
+    entries     TEXT NOT NULL,
+    skipped     TEXT NOT NULL,
+    PRIMARY KEY (drive_id, root, seq),
     FOREIGN KEY (drive_id) REFERENCES drives(id) ON DELETE CASCADE
 )";
 
@@ -100,4 +229,13 @@ pub const ALL_TABLES: &[&str] = &[
     CREATE_HASH_CACHE,
     CREATE_SYNC_HISTORY,
     CREATE_ARCHIVES,
+    CREATE_SYNC_JOURNAL,
+    CREATE_THROUGHPUT,
+    CREATE_LAST_PLANS,
+    CREATE_ARCHIVE_PARTS,
+    CREATE_LAST_PLAN_OPS,
+    CREATE_FILE_INDEX_GENERATIONS,
+    CREATE_DRIVE_TAGS,
+    CREATE_SYNC_SKIPS,
+    CREATE_SCAN_CHECKPOINTS,
 ];
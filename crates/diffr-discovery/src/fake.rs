@@ -0,0 +1,243 @@
+use diffr_core::models::drive::{BusType, Drive, DriveIdentity};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::mtp::{MtpObject, MtpObjects, MtpStorage};
+use crate::DriveDiscovery;
+
+/// Environment variable naming a fixture file of fake drives. When set,
+/// [`crate::platform::get_discovery`] uses it instead of the OS.
+pub const FAKE_DRIVES_ENV: &str = "DIFFR_FAKE_DRIVES";
+
+/// Discovery backed by a TOML fixture that presents plain directories as
+/// drives, for tests and demos without removable media:
+///
+/// ```toml
+/// [[drive]]
+/// serial = "FAKE-USB-1"   # omit to use a synthetic ID, as for a drive with no serial
+/// path = "usb1"           # relative to the fixture file
+/// label = "Backup"
+/// bus = "usb"
+/// ```
+///
+/// A drive whose directory doesn't exist is treated as unplugged.
+pub struct FakeDiscovery {
+    fixture: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    #[serde(default)]
+    drive: Vec<FakeDrive>,
+}
+
+#[derive(Deserialize)]
+struct FakeDrive {
+    serial: Option<String>,
+    path: PathBuf,
+    label: Option<String>,
+    #[serde(default)]
+    bus: BusType,
+    filesystem: Option<String>,
+    total_bytes: Option<u64>,
+    free_bytes: Option<u64>,
+    #[serde(default)]
+    locked: bool,
+}
+
+impl FakeDiscovery {
+    pub fn new(fixture: impl Into<PathBuf>) -> Self {
+        Self {
+            fixture: fixture.into(),
+        }
+    }
+}
+
+impl DriveDiscovery for FakeDiscovery {
+    fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
+        let content = std::fs::read_to_string(&self.fixture).map_err(|e| {
+            anyhow::anyhow!("cannot read {} {}: {}", FAKE_DRIVES_ENV, self.fixture.display(), e)
+        })?;
+        let fixture: Fixture = toml::from_str(&content)?;
+        let base = self.fixture.parent().unwrap_or(Path::new("."));
+
+        let mut drives = Vec::new();
+        for fake in fixture.drive {
+            let mount = base.join(&fake.path);
+            if !mount.is_dir() {
+                continue;
+            }
+            let identity = match fake.serial {
+                Some(serial) => DriveIdentity::new_hardware(serial),
+                None => crate::synthetic_identity(&mount),
+            };
+            let mut drive = Drive::new(identity, mount);
+            drive.label = fake.label;
+            drive.bus = fake.bus;
+            drive.filesystem = fake.filesystem;
+            drive.total_bytes = fake.total_bytes;
+            drive.free_bytes = fake.free_bytes;
+            drive.locked = fake.locked;
+            drives.push(drive);
+        }
+        Ok(drives)
+    }
+}
+
+/// An MTP device held in memory, for tests of what's built on
+/// [`MtpObjects`]. Clones are the same device, so a test can keep one to
+/// look at what was done through another.
+#[derive(Clone, Default)]
+pub struct FakeMtpDevice {
+    inner: Rc<RefCell<FakeObjects>>,
+}
+
+#[derive(Default)]
+struct FakeObjects {
+    storages: Vec<MtpStorage>,
+    /// Objects by ID, with their storage, parent and contents.
+    objects: HashMap<u32, (u32, u32, MtpObject, Vec<u8>)>,
+    next_id: u32,
+}
+
+impl FakeMtpDevice {
+    /// A device with a storage for each description, numbered from
+    /// 0x10001 as phones do, each 1000 bytes with 400 free.
+    pub fn new(storages: &[&str]) -> Self {
+        let storages = storages
+            .iter()
+            .enumerate()
+            .map(|(i, description)| MtpStorage {
+                id: 0x10001 + i as u32,
+                description: description.to_string(),
+                capacity: 1000,
+                free: 400,
+            })
+            .collect();
+        let objects = FakeObjects {
+            storages,
+            objects: HashMap::new(),
+            next_id: 1,
+        };
+        Self {
+            inner: Rc::new(RefCell::new(objects)),
+        }
+    }
+
+    /// Put a file holding `contents`, or a folder if there are none, in
+    /// `parent`, returning its ID.
+    pub fn add(&self, storage: u32, parent: u32, name: &str, contents: Option<&[u8]>, mtime: i64) -> u32 {
+        let mut inner = self.inner.borrow_mut();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let object = MtpObject {
+            id,
+            name: name.to_string(),
+            size: contents.map_or(0, |c| c.len() as u64),
+            mtime,
+            is_dir: contents.is_none(),
+        };
+        inner
+            .objects
+            .insert(id, (storage, parent, object, contents.unwrap_or_default().to_vec()));
+        id
+    }
+
+    /// What the file with ID `id` holds.
+    pub fn contents(&self, id: u32) -> Option<Vec<u8>> {
+        self.inner.borrow().objects.get(&id).map(|(_, _, _, c)| c.clone())
+    }
+}
+
+impl MtpObjects for FakeMtpDevice {
+    fn storages(&mut self) -> anyhow::Result<Vec<MtpStorage>> {
+        Ok(self.inner.borrow().storages.clone())
+    }
+
+    fn children(&mut self, storage: u32, parent: u32) -> anyhow::Result<Vec<MtpObject>> {
+        let mut children: Vec<MtpObject> = self
+            .inner
+            .borrow()
+            .objects
+            .values()
+            .filter(|(s, p, _, _)| *s == storage && *p == parent)
+            .map(|(_, _, o, _)| o.clone())
+            .collect();
+        children.sort_by_key(|o| o.id);
+        Ok(children)
+    }
+
+    fn download(&mut self, id: u32, dst: &Path) -> anyhow::Result<()> {
+        let contents = self.contents(id).ok_or_else(|| anyhow::anyhow!("no object {}", id))?;
+        std::fs::write(dst, contents)?;
+        Ok(())
+    }
+
+    fn upload(&mut self, src: &Path, storage: u32, parent: u32, name: &str, mtime: i64) -> anyhow::Result<u32> {
+        let contents = std::fs::read(src)?;
+        Ok(self.add(storage, parent, name, Some(&contents), mtime))
+    }
+
+    fn delete(&mut self, id: u32) -> anyhow::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.objects.values().any(|(_, p, _, _)| *p == id) {
+            anyhow::bail!("object {} has children", id);
+        }
+        inner.objects.remove(&id).ok_or_else(|| anyhow::anyhow!("no object {}", id))?;
+        Ok(())
+    }
+
+    fn create_folder(&mut self, storage: u32, parent: u32, name: &str) -> anyhow::Result<u32> {
+        Ok(self.add(storage, parent, name, None, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fake_discovery() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("usb1")).unwrap();
+        std::fs::create_dir_all(dir.path().join("usb2")).unwrap();
+        let fixture = dir.path().join("drives.toml");
+        std::fs::write(
+            &fixture,
+            r#"
+[[drive]]
+serial = "FAKE-1"
+path = "usb1"
+bus = "usb"
+
+[[drive]]
+path = "usb2"
+
+[[drive]]
+serial = "UNPLUGGED"
+path = "missing"
+"#,
+        )
+        .unwrap();
+
+        let discovery = FakeDiscovery::new(&fixture);
+        let drives = discovery.discover_drives().unwrap();
+        assert_eq!(drives.len(), 2);
+        assert_eq!(drives[0].identity.identity_string(), "FAKE-1");
+        assert_eq!(drives[0].bus, BusType::Usb);
+        assert!(matches!(drives[1].identity, DriveIdentity::Synthetic { .. }));
+
+        // The synthetic ID is stable across scans, without writing to the
+        // drive, and is kept once the drive stores it.
+        let again = discovery.discover_drives().unwrap();
+        assert_eq!(again[1].identity, drives[1].identity);
+        assert!(!dir.path().join("usb2/.diffr").exists());
+        crate::store_synthetic_id(&drives[1].mount_point, &drives[1].identity).unwrap();
+        assert_eq!(crate::read_synthetic_id(&drives[1].mount_point), Some(drives[1].identity.clone()));
+        assert!(discovery.find_by_serial("FAKE").unwrap().is_some());
+    }
+}
@@ -0,0 +1,127 @@
+use diffr_core::power::PowerReading;
+use diffr_core::schedule::{HostState, PowerSource};
+
+/// The time, power source and load now, for checking a schedule.
+pub fn host_state() -> HostState {
+    HostState {
+        time: chrono::Local::now().time(),
+        power: power_source(),
+        load_per_cpu: load_per_cpu(),
+    }
+}
+
+/// Where the machine is drawing power from, or `None` if it can't be told,
+/// as on desktops without a battery.
+pub fn power_source() -> Option<PowerSource> {
+    #[cfg(target_os = "linux")]
+    {
+        linux_power_source(std::path::Path::new("/sys/class/power_supply"))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let output = std::process::Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        if text.contains("'AC Power'") {
+            Some(PowerSource::Ac)
+        } else if text.contains("'Battery Power'") {
+            Some(PowerSource::Battery)
+        } else {
+            None
+        }
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        // SAFETY: `status` is a valid out-pointer.
+        if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+            return None;
+        }
+        match status.ACLineStatus {
+            1 => Some(PowerSource::Ac),
+            0 => Some(PowerSource::Battery),
+            _ => None,
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        None
+    }
+}
+
+/// Read the power supplies under `dir`: on AC if a mains supply is online,
+/// on battery if there are only batteries and one is discharging.
+#[cfg(target_os = "linux")]
+fn linux_power_source(dir: &std::path::Path) -> Option<PowerSource> {
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).ok().map(|s| s.trim().to_string());
+    let mut discharging = false;
+    for entry in std::fs::read_dir(dir).ok()?.filter_map(|e| e.ok()) {
+        let supply = entry.path();
+        match read(supply.join("type")).as_deref() {
+            Some("Mains" | "USB") if read(supply.join("online")).as_deref() == Some("1") => {
+                return Some(PowerSource::Ac)
+            }
+            Some("Battery") if read(supply.join("status")).as_deref() == Some("Discharging") => discharging = true,
+            _ => {}
+        }
+    }
+    discharging.then_some(PowerSource::Battery)
+}
+
+/// The power source and CPU temperature now, for a sync's
+/// [`PowerGuard`](diffr_core::power::PowerGuard).
+pub fn power_reading() -> PowerReading {
+    PowerReading {
+        power: power_source(),
+        cpu_temp: cpu_temperature(),
+    }
+}
+
+/// The hottest CPU sensor, in °C, or `None` where there's none to read, as
+/// in most virtual machines and on Windows without admin rights.
+pub fn cpu_temperature() -> Option<f32> {
+    const CPU_LABELS: &[&str] = &["cpu", "core", "package", "tctl", "tdie"];
+    sysinfo::Components::new_with_refreshed_list()
+        .iter()
+        .filter(|c| {
+            let label = c.label().to_lowercase();
+            CPU_LABELS.iter().any(|l| label.contains(l))
+        })
+        .map(|c| c.temperature())
+        .filter(|t| t.is_finite() && *t > 0.0)
+        .reduce(f32::max)
+}
+
+/// The 1-minute load average per CPU, or `None` where the OS has no load
+/// average, as on Windows.
+pub fn load_per_cpu() -> Option<f64> {
+    if cfg!(windows) {
+        return None;
+    }
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Some(sysinfo::System::load_average().one / cpus as f64)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_linux_power_source() {
+        let dir = TempDir::new().unwrap();
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            for (file, content) in files {
+                std::fs::write(path.join(file), format!("{}\n", content)).unwrap();
+            }
+        };
+        assert_eq!(linux_power_source(dir.path()), None);
+        supply("BAT0", &[("type", "Battery"), ("status", "Discharging")]);
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(linux_power_source(dir.path()), Some(PowerSource::Battery));
+        std::fs::write(dir.path().join("AC/online"), "1\n").unwrap();
+        assert_eq!(linux_power_source(dir.path()), Some(PowerSource::Ac));
+    }
+}
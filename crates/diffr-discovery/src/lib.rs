@@ -1,4 +1,10 @@
+pub mod fake;
+pub mod host;
+#[cfg(unix)]
+mod libmtp;
+pub mod mtp;
 pub mod platform;
+pub mod registry;
 
 use diffr_core::models::drive::{Drive, DriveIdentity};
 use std::path::Path;
@@ -8,24 +14,380 @@ pub trait DriveDiscovery {
     /// Discover all connected drives.
     fn discover_drives(&self) -> anyhow::Result<Vec<Drive>>;
 
-    /// Find a specific drive by its serial number.
-    fn find_by_serial(&self, serial: &str) -> anyhow::Result<Option<Drive>>;
+    /// Find a connected drive by serial, alias, or the synthetic ID stored
+    /// on it. A unique prefix of any of these is enough; an ambiguous one is
+    /// an error listing the candidates.
+    fn find_by_serial(&self, serial: &str) -> anyhow::Result<Option<Drive>> {
+        find_matching(self.discover_drives()?, serial)
+    }
+}
+
+/// Pick the drive `query` refers to; see [`DriveDiscovery::find_by_serial`].
+pub fn find_matching(drives: Vec<Drive>, query: &str) -> anyhow::Result<Option<Drive>> {
+    // A drive discovered by serial may also carry a synthetic ID file, e.g.
+    // one written before its serial was readable. Either identifies it.
+    let mut drives: Vec<(Drive, Option<DriveIdentity>)> = drives
+        .into_iter()
+        .map(|d| {
+            let stored = read_synthetic_id(&d.mount_point).filter(|id| *id != d.identity);
+            (d, stored)
+        })
+        .collect();
+
+    let take = |(mut drive, stored): (Drive, Option<DriveIdentity>)| {
+        if let Some(stored) = stored {
+            if stored.identity_string() == query {
+                let previous = std::mem::replace(&mut drive.identity, stored);
+                drive.aliases.push(previous.identity_string().to_string());
+            }
+        }
+        drive
+    };
+
+    if let Some(pos) = drives.iter().position(|(d, stored)| {
+        d.matches_id(query) || stored.as_ref().is_some_and(|s| s.identity_string() == query)
+    }) {
+        return Ok(Some(take(drives.swap_remove(pos))));
+    }
+
+    let query_lower = query.to_lowercase();
+    let is_prefix = |id: &str| id.to_lowercase().starts_with(&query_lower);
+    let mut candidates: Vec<usize> = drives
+        .iter()
+        .enumerate()
+        .filter(|(_, (d, stored))| {
+            is_prefix(d.identity.identity_string())
+                || d.aliases.iter().any(|a| is_prefix(a))
+                || stored.as_ref().is_some_and(|s| is_prefix(s.identity_string()))
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(Some(take(drives.swap_remove(candidates.remove(0))))),
+        _ => {
+            let names: Vec<String> = candidates
+                .iter()
+                .map(|&i| {
+                    let (d, _) = &drives[i];
+                    format!("  {} ({})", d.identity.identity_string(), d.mount_point.display())
+                })
+                .collect();
+            anyhow::bail!(
+                "'{}' matches more than one drive:\n{}",
+                query,
+                names.join("\n")
+            )
+        }
+    }
+}
+
+/// Read the synthetic identity file on a drive, if there is one.
+pub fn read_synthetic_id(drive_root: &Path) -> Option<DriveIdentity> {
+    let content = std::fs::read_to_string(drive_root.join(".diffr").join("drive_identity.toml")).ok()?;
+    toml::from_str(&content).ok()
 }
 
-/// Read or create a synthetic drive identity file on the drive.
-pub fn read_or_create_synthetic_id(drive_root: &Path) -> anyhow::Result<DriveIdentity> {
+/// Write `identity` to the drive's identity file, so it keeps it wherever
+/// it's mounted next.
+pub fn store_synthetic_id(drive_root: &Path, identity: &DriveIdentity) -> anyhow::Result<()> {
     let diffr_dir = drive_root.join(".diffr");
-    let identity_path = diffr_dir.join("drive_identity.toml");
-
-    if identity_path.exists() {
-        let content = std::fs::read_to_string(&identity_path)?;
-        let identity: DriveIdentity = toml::from_str(&content)?;
-        Ok(identity)
-    } else {
-        std::fs::create_dir_all(&diffr_dir)?;
-        let identity = DriveIdentity::new_synthetic();
-        let content = toml::to_string_pretty(&identity)?;
-        std::fs::write(&identity_path, content)?;
-        Ok(identity)
+    std::fs::create_dir_all(&diffr_dir)?;
+    std::fs::write(diffr_dir.join("drive_identity.toml"), toml::to_string_pretty(identity)?)?;
+    Ok(())
+}
+
+/// The identity of a drive with no serial to go by: the one stored on it,
+/// or if it has none yet, one made from where it's mounted. Discovery runs
+/// on every mount, system ones included, so it only reads; `diffr drive
+/// add` stores the identity when the drive is registered.
+pub fn synthetic_identity(drive_root: &Path) -> DriveIdentity {
+    read_synthetic_id(drive_root).unwrap_or_else(|| {
+        // FNV-1a, which is stable across runs and Rust versions.
+        let hash = drive_root
+            .as_os_str()
+            .as_encoded_bytes()
+            .iter()
+            .fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+        DriveIdentity::Synthetic {
+            id: format!("unmarked-{:016x}", hash),
+        }
+    })
+}
+
+/// Discover drives from the OS mount table alone (`/proc/mounts` and
+/// `statvfs` on Linux, `getmntinfo` on macOS), for systems without lsblk or
+/// diskutil. Sizes and filesystem types are filled in, but there are no
+/// hardware serials, so every drive gets a synthetic identity.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn discover_from_mount_table() -> Vec<Drive> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .map(|disk| {
+            let mount = disk.mount_point().to_path_buf();
+            let mut drive = Drive::new(synthetic_identity(&mount), mount);
+            let name = disk.name().to_string_lossy();
+            drive.label = Some(name.rsplit('/').next().unwrap_or(&name).to_string())
+                .filter(|l| !l.is_empty());
+            drive.filesystem = Some(disk.file_system().to_string_lossy().to_string())
+                .filter(|f| !f.is_empty());
+            drive.total_bytes = Some(disk.total_space());
+            drive.free_bytes = Some(disk.available_space());
+            // sysinfo only flags USB devices as removable.
+            if disk.is_removable() {
+                drive.bus = diffr_core::models::drive::BusType::Usb;
+            }
+            drive
+        })
+        .collect()
+}
+
+/// Capacity and space available to unprivileged users, in bytes, of the
+/// filesystem holding `path`, from `statvfs`.
+#[cfg(unix)]
+pub fn filesystem_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // Sizes are in fragments; the field widths differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    let fragment = if stat.f_frsize > 0 { stat.f_frsize } else { stat.f_bsize } as u64;
+    #[allow(clippy::unnecessary_cast)]
+    let (blocks, available) = (stat.f_blocks as u64, stat.f_bavail as u64);
+    Some((blocks.saturating_mul(fragment), available.saturating_mul(fragment)))
+}
+
+/// Replace a drive's capacity and free space with what its filesystem
+/// reports now, as the database only has what discovery saw last. Returns
+/// false, leaving the drive as it was, when it isn't connected.
+pub fn refresh_space(drive: &mut Drive) -> bool {
+    match current_space(drive.effective_root()) {
+        Some((total, free)) => {
+            drive.total_bytes = Some(total);
+            drive.free_bytes = Some(free);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(unix)]
+fn current_space(path: &Path) -> Option<(u64, u64)> {
+    filesystem_space(path)
+}
+
+/// The space on the disk mounted deepest above `path`.
+#[cfg(not(unix))]
+fn current_space(path: &Path) -> Option<(u64, u64)> {
+    if !path.exists() {
+        return None;
+    }
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| (disk.total_space(), disk.available_space()))
+}
+
+/// Fill in whatever size a drive is missing from its filesystem.
+#[cfg(unix)]
+pub(crate) fn fill_space(drive: &mut Drive) {
+    if drive.total_bytes.is_some() && drive.free_bytes.is_some() {
+        return;
+    }
+    if let Some((total, free)) = filesystem_space(&drive.mount_point) {
+        drive.total_bytes = drive.total_bytes.or(Some(total));
+        drive.free_bytes = drive.free_bytes.or(Some(free));
+    }
+}
+
+/// What [`refresh_drives`] found, by index into the registered drives.
+#[derive(Debug, Default)]
+pub struct RefreshResult {
+    /// Registered drives that are connected now.
+    pub seen: Vec<usize>,
+    /// The subset of `seen` whose mount point changed.
+    pub moved: Vec<usize>,
+    /// The subset of `seen` now known by a different identity, to be saved
+    /// with `ops::update_drive_identity`.
+    pub reidentified: Vec<usize>,
+    /// Connected drives that aren't registered.
+    pub unregistered: Vec<Drive>,
+}
+
+/// Reconcile registered drives with a fresh discovery pass. Connected drives
+/// get a new `last_seen`, sizes, lock state, filesystem and aliases, a label
+/// if they had none, and a new mount point if they turned up somewhere else
+/// (a new letter for the same volume GUID, or a new mount for a drive
+/// recognised by an alias).
+///
+/// A drive registered by the serial of a disk that's since been found to
+/// hold several filesystems is matched to the one at its mount point, and
+/// takes on that filesystem's partition-qualified identity.
+pub fn refresh_drives(registered: &mut [Drive], discovered: Vec<Drive>) -> RefreshResult {
+    let mut result = RefreshResult::default();
+    let mut claimed = vec![false; discovered.len()];
+    for (i, drive) in registered.iter_mut().enumerate() {
+        let found = discovered.iter().position(|d| match (&drive.volume_guid, &d.volume_guid) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            _ => {
+                d.identity == drive.identity
+                    || d.matches_id(drive.identity.identity_string())
+                    || drive.aliases.iter().any(|a| d.matches_id(a))
+            }
+        });
+        let unqualified = format!("{}:", drive.identity.identity_string());
+        let requalified = found.is_none().then(|| {
+            discovered.iter().position(|d| {
+                d.mount_point == drive.mount_point
+                    && matches!(d.identity, DriveIdentity::Hardware { .. })
+                    && d.identity.identity_string().starts_with(&unqualified)
+            })
+        });
+        let Some(pos) = found.or(requalified.flatten()) else {
+            continue;
+        };
+        claimed[pos] = true;
+        let found = &discovered[pos];
+        if requalified.flatten().is_some() {
+            drive.identity = found.identity.clone();
+            result.reidentified.push(i);
+        }
+
+        drive.last_seen = found.last_seen;
+        drive.total_bytes = found.total_bytes.or(drive.total_bytes);
+        drive.free_bytes = found.free_bytes.or(drive.free_bytes);
+        if drive.label.is_none() {
+            drive.label = found.label.clone();
+        }
+        drive.locked = found.locked;
+        drive.bus = found.bus;
+        if found.filesystem.is_some() {
+            drive.filesystem = found.filesystem.clone();
+        }
+        if drive.volume_guid.is_none() {
+            drive.volume_guid = found.volume_guid.clone();
+        }
+        if !found.aliases.is_empty() {
+            drive.aliases = found.aliases.clone();
+        }
+        result.seen.push(i);
+        if found.mount_point != drive.mount_point {
+            drive.remount(found.mount_point.clone());
+            result.moved.push(i);
+        }
+    }
+    result.unregistered = discovered
+        .into_iter()
+        .zip(claimed)
+        .filter(|(_, claimed)| !claimed)
+        .map(|(d, _)| d)
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_find_matching() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let stored = DriveIdentity::new_synthetic();
+        store_synthetic_id(dir.path(), &stored).unwrap();
+        let drives = || {
+            vec![
+                Drive::new(DriveIdentity::new_hardware("WD-AAA1".into()), dir.path().to_path_buf()),
+                Drive::new(DriveIdentity::new_hardware("WD-AAB2".into()), PathBuf::from("/mnt/b")),
+            ]
+        };
+
+        let found = find_matching(drives(), "wd-aab").unwrap().unwrap();
+        assert_eq!(found.identity.identity_string(), "WD-AAB2");
+
+        // The synthetic ID on the first drive's root identifies it too.
+        let found = find_matching(drives(), stored.identity_string()).unwrap().unwrap();
+        assert_eq!(found.identity, stored);
+        assert!(found.matches_id("WD-AAA1"));
+
+        let err = find_matching(drives(), "WD-AA").unwrap_err().to_string();
+        assert!(err.contains("WD-AAA1") && err.contains("WD-AAB2"));
+        assert!(find_matching(drives(), "ST-").unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filesystem_space() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (total, free) = filesystem_space(dir.path()).unwrap();
+        assert!(total > 0 && free <= total);
+        assert!(filesystem_space(Path::new("/no/such/dir")).is_none());
+
+        let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        drive.total_bytes = Some(7);
+        fill_space(&mut drive);
+        assert_eq!(drive.total_bytes, Some(7));
+        assert!(drive.free_bytes.is_some());
+
+        // Refreshing replaces a stale size; a missing drive keeps its own.
+        assert!(refresh_space(&mut drive));
+        assert_eq!(drive.total_bytes, Some(total));
+        let mut gone = Drive::new(DriveIdentity::new_synthetic(), "/no/such/dir".into());
+        gone.free_bytes = Some(7);
+        assert!(!refresh_space(&mut gone));
+        assert_eq!(gone.free_bytes, Some(7));
+    }
+
+    #[test]
+    fn test_refresh_drives_qualifies_a_bare_serial() {
+        let mut registered = Drive::new(DriveIdentity::new_hardware("WD-123".into()), PathBuf::from("/mnt/b"));
+        registered.aliases = vec!["wwn-0x5000".into()];
+        let part = |n: u32, mount: &str| {
+            let mut d = Drive::new(DriveIdentity::new_hardware(format!("WD-123:part{}", n)), PathBuf::from(mount));
+            d.aliases = vec![format!("wwn-0x5000:part{}", n)];
+            d
+        };
+
+        let mut drives = vec![registered];
+        let result = refresh_drives(&mut drives, vec![part(1, "/mnt/a"), part(2, "/mnt/b")]);
+        assert_eq!((result.seen, result.reidentified), (vec![0], vec![0]));
+        assert!(result.moved.is_empty());
+        assert_eq!(drives[0].identity.identity_string(), "WD-123:part2");
+        assert_eq!(drives[0].aliases, vec!["wwn-0x5000:part2"]);
+        assert_eq!(result.unregistered.len(), 1);
+    }
+
+    #[test]
+    fn test_refresh_drives_follows_volume_guid() {
+        let guid = r"\\?\Volume{0b1c2d3e-0000-0000-0000-000000000001}\";
+        let mut registered = Drive::new(DriveIdentity::new_hardware("S1".into()), PathBuf::from("/mnt/e"));
+        registered.sync_root = Some(PathBuf::from("/mnt/e/vault"));
+        registered.volume_guid = Some(guid.to_string());
+
+        let mut found = Drive::new(DriveIdentity::new_hardware("S1".into()), PathBuf::from("/mnt/f"));
+        found.volume_guid = Some(guid.to_lowercase());
+        found.filesystem = Some("NTFS".into());
+        found.locked = true;
+
+        let other = Drive::new(DriveIdentity::new_hardware("S2".into()), PathBuf::from("/mnt/g"));
+
+        let mut drives = vec![registered];
+        let result = refresh_drives(&mut drives, vec![found, other]);
+        assert_eq!(result.seen, vec![0]);
+        assert_eq!(result.moved, vec![0]);
+        assert_eq!(result.unregistered.len(), 1);
+        assert_eq!(drives[0].mount_point, PathBuf::from("/mnt/f"));
+        assert_eq!(drives[0].sync_root, Some(PathBuf::from("/mnt/f/vault")));
+        assert_eq!(drives[0].filesystem.as_deref(), Some("NTFS"));
+        assert!(drives[0].locked);
     }
 }
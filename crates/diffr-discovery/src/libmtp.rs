@@ -0,0 +1,389 @@
+//! libmtp, loaded when first needed instead of linked, so diffr builds and
+//! runs without it and finds no devices through it where it's missing.
+//!
+//! Only what diffr uses is bound: finding and opening devices, their
+//! storages, listing a folder, and sending, fetching, deleting and making
+//! objects. The structs are the leading fields of libmtp 1.1's, which are
+//! all that's read.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::mtp::{MtpObject, MtpObjects, MtpStorage};
+
+/// Names libmtp 1.1 is installed under.
+const LIBRARY_NAMES: &[&str] = &["libmtp.so.9", "libmtp.so", "libmtp.9.dylib", "libmtp.dylib"];
+
+const ERROR_NONE: c_int = 0;
+const ERROR_NO_DEVICE_ATTACHED: c_int = 5;
+const FILETYPE_FOLDER: c_int = 0;
+const FILETYPE_UNKNOWN: c_int = 44;
+
+#[repr(C)]
+struct DeviceEntry {
+    vendor: *mut c_char,
+    vendor_id: u16,
+    product: *mut c_char,
+    product_id: u16,
+    device_flags: u32,
+}
+
+#[repr(C)]
+struct RawDevice {
+    device_entry: DeviceEntry,
+    bus_location: u32,
+    devnum: u8,
+}
+
+/// `LIBMTP_mtpdevice_t`, up to its storage list and error stack.
+#[repr(C)]
+struct RawMtpDevice {
+    object_bitsize: u8,
+    params: *mut c_void,
+    usbinfo: *mut c_void,
+    storage: *mut RawStorage,
+    errorstack: *mut RawError,
+}
+
+#[repr(C)]
+struct RawStorage {
+    id: u32,
+    storage_type: u16,
+    filesystem_type: u16,
+    access_capability: u16,
+    max_capacity: u64,
+    free_space_in_bytes: u64,
+    free_space_in_objects: u64,
+    storage_description: *mut c_char,
+    volume_identifier: *mut c_char,
+    next: *mut RawStorage,
+    prev: *mut RawStorage,
+}
+
+#[repr(C)]
+struct RawFile {
+    item_id: u32,
+    parent_id: u32,
+    storage_id: u32,
+    filename: *mut c_char,
+    filesize: u64,
+    modificationdate: libc::time_t,
+    filetype: c_int,
+    next: *mut RawFile,
+}
+
+#[repr(C)]
+struct RawError {
+    errornumber: c_int,
+    error_text: *mut c_char,
+    next: *mut RawError,
+}
+
+type Progress = Option<unsafe extern "C" fn(u64, u64, *const c_void) -> c_int>;
+
+/// The functions diffr calls.
+struct Library {
+    detect_raw_devices: unsafe extern "C" fn(*mut *mut RawDevice, *mut c_int) -> c_int,
+    open_raw_device_uncached: unsafe extern "C" fn(*mut RawDevice) -> *mut RawMtpDevice,
+    release_device: unsafe extern "C" fn(*mut RawMtpDevice),
+    get_serialnumber: unsafe extern "C" fn(*mut RawMtpDevice) -> *mut c_char,
+    get_friendlyname: unsafe extern "C" fn(*mut RawMtpDevice) -> *mut c_char,
+    get_modelname: unsafe extern "C" fn(*mut RawMtpDevice) -> *mut c_char,
+    get_storage: unsafe extern "C" fn(*mut RawMtpDevice, c_int) -> c_int,
+    get_files_and_folders: unsafe extern "C" fn(*mut RawMtpDevice, u32, u32) -> *mut RawFile,
+    new_file_t: unsafe extern "C" fn() -> *mut RawFile,
+    destroy_file_t: unsafe extern "C" fn(*mut RawFile),
+    get_file_to_file: unsafe extern "C" fn(*mut RawMtpDevice, u32, *const c_char, Progress, *const c_void) -> c_int,
+    send_file_from_file:
+        unsafe extern "C" fn(*mut RawMtpDevice, *const c_char, *mut RawFile, Progress, *const c_void) -> c_int,
+    delete_object: unsafe extern "C" fn(*mut RawMtpDevice, u32) -> c_int,
+    create_folder: unsafe extern "C" fn(*mut RawMtpDevice, *mut c_char, u32, u32) -> u32,
+    get_errorstack: unsafe extern "C" fn(*mut RawMtpDevice) -> *mut RawError,
+    clear_errorstack: unsafe extern "C" fn(*mut RawMtpDevice),
+}
+
+/// libmtp, initialised, or `None` if it isn't installed.
+fn library() -> Option<&'static Library> {
+    static LIBRARY: OnceLock<Option<Library>> = OnceLock::new();
+    LIBRARY.get_or_init(load).as_ref()
+}
+
+fn load() -> Option<Library> {
+    let handle = LIBRARY_NAMES.iter().find_map(|name| {
+        let name = CString::new(*name).ok()?;
+        // SAFETY: loading libmtp runs no code of ours; it stays loaded for
+        // the life of the process.
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW) };
+        (!handle.is_null()).then_some(handle)
+    })?;
+    // SAFETY: each symbol is looked up by its libmtp name and given that
+    // function's signature.
+    unsafe {
+        let init: unsafe extern "C" fn() = symbol(handle, "LIBMTP_Init")?;
+        let library = Library {
+            detect_raw_devices: symbol(handle, "LIBMTP_Detect_Raw_Devices")?,
+            open_raw_device_uncached: symbol(handle, "LIBMTP_Open_Raw_Device_Uncached")?,
+            release_device: symbol(handle, "LIBMTP_Release_Device")?,
+            get_serialnumber: symbol(handle, "LIBMTP_Get_Serialnumber")?,
+            get_friendlyname: symbol(handle, "LIBMTP_Get_Friendlyname")?,
+            get_modelname: symbol(handle, "LIBMTP_Get_Modelname")?,
+            get_storage: symbol(handle, "LIBMTP_Get_Storage")?,
+            get_files_and_folders: symbol(handle, "LIBMTP_Get_Files_And_Folders")?,
+            new_file_t: symbol(handle, "LIBMTP_new_file_t")?,
+            destroy_file_t: symbol(handle, "LIBMTP_destroy_file_t")?,
+            get_file_to_file: symbol(handle, "LIBMTP_Get_File_To_File")?,
+            send_file_from_file: symbol(handle, "LIBMTP_Send_File_From_File")?,
+            delete_object: symbol(handle, "LIBMTP_Delete_Object")?,
+            create_folder: symbol(handle, "LIBMTP_Create_Folder")?,
+            get_errorstack: symbol(handle, "LIBMTP_Get_Errorstack")?,
+            clear_errorstack: symbol(handle, "LIBMTP_Clear_Errorstack")?,
+        };
+        init();
+        Some(library)
+    }
+}
+
+/// The function `name` in the library at `handle`, as an `F`.
+///
+/// # Safety
+///
+/// `F` must be a function pointer type matching the symbol's signature.
+unsafe fn symbol<F: Copy>(handle: *mut c_void, name: &str) -> Option<F> {
+    assert_eq!(std::mem::size_of::<F>(), std::mem::size_of::<*mut c_void>());
+    let name = CString::new(name).ok()?;
+    let symbol = libc::dlsym(handle, name.as_ptr());
+    if symbol.is_null() {
+        tracing::debug!("libmtp has no {}", name.to_string_lossy());
+        return None;
+    }
+    Some(std::mem::transmute_copy::<*mut c_void, F>(&symbol))
+}
+
+/// A string libmtp allocated, copied and freed.
+unsafe fn take_string(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let copy = CStr::from_ptr(s).to_string_lossy().into_owned();
+    libc::free(s.cast());
+    Some(copy)
+}
+
+fn c_path(path: &Path) -> anyhow::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// A device opened through libmtp, released when dropped. libmtp devices
+/// aren't safe to share between threads, so this stays on the one that
+/// opened it.
+pub struct Device {
+    library: &'static Library,
+    raw: *mut RawMtpDevice,
+}
+
+impl Device {
+    /// Open every MTP device attached over USB. Devices that another
+    /// program holds, such as GVFS while it has one mounted, can't be
+    /// opened and are left out. No libmtp means no devices.
+    pub fn open_all() -> anyhow::Result<Vec<Device>> {
+        let Some(library) = library() else {
+            return Ok(Vec::new());
+        };
+        let mut raw: *mut RawDevice = std::ptr::null_mut();
+        let mut count: c_int = 0;
+        // SAFETY: libmtp fills in a list of `count` devices, which is ours
+        // to free; opened devices keep copies of what they need from it.
+        unsafe {
+            match (library.detect_raw_devices)(&mut raw, &mut count) {
+                ERROR_NONE => {}
+                ERROR_NO_DEVICE_ATTACHED => return Ok(Vec::new()),
+                e => anyhow::bail!("libmtp couldn't list USB devices (error {})", e),
+            }
+            let mut devices = Vec::new();
+            for i in 0..count.max(0) as usize {
+                let device = (library.open_raw_device_uncached)(raw.add(i));
+                if device.is_null() {
+                    tracing::debug!("MTP device {} couldn't be opened; is it mounted?", i);
+                    continue;
+                }
+                devices.push(Device { library, raw: device });
+            }
+            libc::free(raw.cast());
+            Ok(devices)
+        }
+    }
+
+    /// Open the attached device with serial number `serial`.
+    pub fn open(serial: &str) -> anyhow::Result<Device> {
+        if library().is_none() {
+            anyhow::bail!("MTP device {} needs libmtp, which isn't installed", serial);
+        }
+        Self::open_all()?
+            .into_iter()
+            .find(|d| d.serial().as_deref() == Some(serial))
+            .ok_or_else(|| anyhow::anyhow!("MTP device {} isn't connected, or another program has it open", serial))
+    }
+
+    pub fn serial(&self) -> Option<String> {
+        // SAFETY: `raw` is an open device; the string is ours to free.
+        unsafe { take_string((self.library.get_serialnumber)(self.raw)) }.filter(|s| !s.is_empty())
+    }
+
+    /// The name the device's owner gave it, or its model.
+    pub fn name(&self) -> Option<String> {
+        // SAFETY: as for `serial`.
+        let friendly = unsafe { take_string((self.library.get_friendlyname)(self.raw)) };
+        friendly
+            .filter(|s| !s.is_empty())
+            .or_else(|| unsafe { take_string((self.library.get_modelname)(self.raw)) })
+    }
+
+    /// The first error on libmtp's stack for this device, which is cleared.
+    fn error(&self, doing: &str) -> anyhow::Error {
+        // SAFETY: the stack belongs to the open device and is read before
+        // it's cleared.
+        let text = unsafe {
+            let stack = (self.library.get_errorstack)(self.raw);
+            let text = match stack.is_null() || (*stack).error_text.is_null() {
+                true => None,
+                false => Some(CStr::from_ptr((*stack).error_text).to_string_lossy().into_owned()),
+            };
+            (self.library.clear_errorstack)(self.raw);
+            text
+        };
+        match text {
+            Some(text) => anyhow::anyhow!("{}: {}", doing, text.trim()),
+            None => anyhow::anyhow!("{}: the device refused", doing),
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        // SAFETY: `raw` was opened by libmtp and isn't used again.
+        unsafe { (self.library.release_device)(self.raw) }
+    }
+}
+
+impl MtpObjects for Device {
+    fn storages(&mut self) -> anyhow::Result<Vec<MtpStorage>> {
+        // SAFETY: libmtp rebuilds the storage list, which is read before
+        // anything else can change it.
+        unsafe {
+            if (self.library.get_storage)(self.raw, 0) != 0 {
+                return Err(self.error("reading the device's storage"));
+            }
+            let mut storages = Vec::new();
+            let mut next = (*self.raw).storage;
+            while !next.is_null() {
+                let s = &*next;
+                let description = match s.storage_description.is_null() {
+                    true => String::new(),
+                    false => CStr::from_ptr(s.storage_description).to_string_lossy().into_owned(),
+                };
+                storages.push(MtpStorage {
+                    id: s.id,
+                    description,
+                    capacity: s.max_capacity,
+                    free: s.free_space_in_bytes,
+                });
+                next = s.next;
+            }
+            Ok(storages)
+        }
+    }
+
+    fn children(&mut self, storage: u32, parent: u32) -> anyhow::Result<Vec<MtpObject>> {
+        // SAFETY: each node of the list is copied and then destroyed, after
+        // its successor is read.
+        unsafe {
+            // An empty folder and a failed listing both come back empty;
+            // only the failure leaves an error.
+            (self.library.clear_errorstack)(self.raw);
+            let mut next = (self.library.get_files_and_folders)(self.raw, storage, parent);
+            if next.is_null() && !(self.library.get_errorstack)(self.raw).is_null() {
+                return Err(self.error("listing a folder on the device"));
+            }
+            let mut objects = Vec::new();
+            while !next.is_null() {
+                let file = &*next;
+                if !file.filename.is_null() {
+                    objects.push(MtpObject {
+                        id: file.item_id,
+                        name: CStr::from_ptr(file.filename).to_string_lossy().into_owned(),
+                        size: file.filesize,
+                        // time_t is 32 bits on some targets.
+                        #[allow(clippy::unnecessary_cast)]
+                        mtime: file.modificationdate as i64,
+                        is_dir: file.filetype == FILETYPE_FOLDER,
+                    });
+                }
+                let done = next;
+                next = file.next;
+                (self.library.destroy_file_t)(done);
+            }
+            Ok(objects)
+        }
+    }
+
+    fn download(&mut self, id: u32, dst: &Path) -> anyhow::Result<()> {
+        let path = c_path(dst)?;
+        // SAFETY: `path` outlives the call; no progress callback is given.
+        let status = unsafe { (self.library.get_file_to_file)(self.raw, id, path.as_ptr(), None, std::ptr::null()) };
+        match status {
+            0 => Ok(()),
+            _ => Err(self.error(&format!("copying from the device to {}", dst.display()))),
+        }
+    }
+
+    fn upload(&mut self, src: &Path, storage: u32, parent: u32, name: &str, mtime: i64) -> anyhow::Result<u32> {
+        let path = c_path(src)?;
+        let size = std::fs::metadata(src)?.len();
+        let name = CString::new(name)?;
+        // SAFETY: the file struct is libmtp's, and so is the copy of `name`
+        // it frees with it.
+        unsafe {
+            let file = (self.library.new_file_t)();
+            if file.is_null() {
+                anyhow::bail!("libmtp is out of memory");
+            }
+            (*file).filename = libc::strdup(name.as_ptr());
+            (*file).filesize = size;
+            (*file).modificationdate = mtime as libc::time_t;
+            (*file).filetype = FILETYPE_UNKNOWN;
+            (*file).parent_id = parent;
+            (*file).storage_id = storage;
+            let status = (self.library.send_file_from_file)(self.raw, path.as_ptr(), file, None, std::ptr::null());
+            let id = (*file).item_id;
+            (self.library.destroy_file_t)(file);
+            match status {
+                0 => Ok(id),
+                _ => Err(self.error(&format!("copying {} to the device", src.display()))),
+            }
+        }
+    }
+
+    fn delete(&mut self, id: u32) -> anyhow::Result<()> {
+        // SAFETY: `raw` is an open device.
+        match unsafe { (self.library.delete_object)(self.raw, id) } {
+            0 => Ok(()),
+            _ => Err(self.error("deleting from the device")),
+        }
+    }
+
+    fn create_folder(&mut self, storage: u32, parent: u32, name: &str) -> anyhow::Result<u32> {
+        let name = CString::new(name)?.into_raw();
+        // SAFETY: libmtp copies the name; it's taken back and freed after.
+        let id = unsafe {
+            let id = (self.library.create_folder)(self.raw, name, parent, storage);
+            drop(CString::from_raw(name));
+            id
+        };
+        match id {
+            0 => Err(self.error("making a folder on the device")),
+            id => Ok(id),
+        }
+    }
+}
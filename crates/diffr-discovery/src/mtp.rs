@@ -0,0 +1,499 @@
+use diffr_core::models::drive::{BusType, Drive, DriveIdentity, MTP_FILESYSTEM, MTP_URL_PREFIX};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::DriveDiscovery;
+
+/// Phones and cameras that speak MTP: those reached through a FUSE mount,
+/// GVFS (`/run/user/<uid>/gvfs/mtp:host=...`) or jmtpfs, simple-mtpfs and
+/// friends listed in `/proc/mounts`, and those nothing has mounted, which
+/// diffr opens itself through libmtp where it's installed. A device that
+/// is mounted is held by the program that mounted it, so it's only found
+/// the first way.
+pub struct MtpDiscovery;
+
+impl DriveDiscovery for MtpDiscovery {
+    fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
+        Ok(discover_mtp_devices())
+    }
+}
+
+/// Every MTP device currently connected.
+pub fn discover_mtp_devices() -> Vec<Drive> {
+    let mut mounts = std::fs::read_to_string("/proc/mounts")
+        .map(|m| parse_mtp_mounts(&m))
+        .unwrap_or_default();
+
+    if let Some(runtime) = std::env::var_os("XDG_RUNTIME_DIR") {
+        let gvfs = Path::new(&runtime).join("gvfs");
+        if let Ok(entries) = std::fs::read_dir(&gvfs) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(host) = name.strip_prefix("mtp:host=") {
+                    mounts.push((entry.path(), host.to_string()));
+                }
+            }
+        }
+    }
+
+    let mut drives: Vec<Drive> = mounts
+        .into_iter()
+        .map(|(mount, name)| {
+            // Devices have no serial we can read through FUSE. The ID file
+            // `drive add` leaves on the device names it; until then, its
+            // name does.
+            let identity = crate::read_synthetic_id(&mount).unwrap_or_else(|| DriveIdentity::Synthetic {
+                id: format!("mtp-{}", name),
+            });
+            let mut drive = Drive::new(identity, mount);
+            drive.label = Some(name);
+            drive.bus = BusType::Usb;
+            drive.filesystem = Some(MTP_FILESYSTEM.to_string());
+            drive
+        })
+        .collect();
+    drives.extend(libmtp_devices());
+    drives
+}
+
+/// Devices libmtp can open, that nothing else holds.
+#[cfg(unix)]
+fn libmtp_devices() -> Vec<Drive> {
+    let devices = match crate::libmtp::Device::open_all() {
+        Ok(devices) => devices,
+        Err(e) => {
+            tracing::debug!("no MTP devices through libmtp: {:#}", e);
+            return Vec::new();
+        }
+    };
+    devices
+        .into_iter()
+        .filter_map(|mut device| {
+            // Without a serial, there's nothing to find it by next time.
+            let serial = device.serial()?;
+            let storages = device.storages().unwrap_or_default();
+            Some(device_drive(&serial, device.name(), &storages))
+        })
+        .collect()
+}
+
+/// libmtp is only loaded on Unix; Windows' WPD isn't supported.
+#[cfg(not(unix))]
+fn libmtp_devices() -> Vec<Drive> {
+    Vec::new()
+}
+
+/// A device reached through libmtp as a drive: its identity comes from its
+/// serial, and its mount point is `mtp://<serial>`, with a folder for each
+/// of its storages.
+fn device_drive(serial: &str, name: Option<String>, storages: &[MtpStorage]) -> Drive {
+    let identity = DriveIdentity::Synthetic {
+        id: format!("mtp-{}", serial),
+    };
+    let mut drive = Drive::new(identity, PathBuf::from(format!("{}{}", MTP_URL_PREFIX, serial)));
+    drive.label = name;
+    drive.bus = BusType::Usb;
+    drive.filesystem = Some(MTP_FILESYSTEM.to_string());
+    if !storages.is_empty() {
+        drive.total_bytes = Some(storages.iter().map(|s| s.capacity).sum());
+        drive.free_bytes = Some(storages.iter().map(|s| s.free).sum());
+    }
+    drive
+}
+
+/// The parent ID that lists a storage's top folder.
+pub const ROOT: u32 = 0xFFFF_FFFF;
+
+/// One of a device's storages, such as a phone's internal storage or its
+/// SD card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtpStorage {
+    pub id: u32,
+    pub description: String,
+    pub capacity: u64,
+    pub free: u64,
+}
+
+impl MtpStorage {
+    /// The folder the storage is under in paths on the device.
+    pub fn name(&self) -> String {
+        match self.description.trim() {
+            "" => format!("storage-{:08x}", self.id),
+            name => name.to_string(),
+        }
+    }
+}
+
+/// A file or folder on a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtpObject {
+    pub id: u32,
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch. Devices that don't keep one report 0.
+    pub mtime: i64,
+    pub is_dir: bool,
+}
+
+/// What [`MtpStore`] needs of a device, which MTP addresses by object ID:
+/// libmtp's, or a stand-in.
+pub trait MtpObjects {
+    fn storages(&mut self) -> anyhow::Result<Vec<MtpStorage>>;
+    /// What's in the folder `parent` of `storage`; [`ROOT`] for its top.
+    fn children(&mut self, storage: u32, parent: u32) -> anyhow::Result<Vec<MtpObject>>;
+    fn download(&mut self, id: u32, dst: &Path) -> anyhow::Result<()>;
+    /// Send `src` into `parent` as `name`, returning its ID.
+    fn upload(&mut self, src: &Path, storage: u32, parent: u32, name: &str, mtime: i64) -> anyhow::Result<u32>;
+    fn delete(&mut self, id: u32) -> anyhow::Result<()>;
+    fn create_folder(&mut self, storage: u32, parent: u32, name: &str) -> anyhow::Result<u32>;
+}
+
+/// Where a path leads on a device.
+struct Found {
+    storage: u32,
+    /// [`ROOT`] for the storage itself.
+    id: u32,
+    parent: u32,
+    object: Option<MtpObject>,
+}
+
+/// The files on an MTP device by path, for scanning and copying to and
+/// from it. A path's first component is a storage's [`MtpStorage::name`],
+/// and the rest the folders and file inside it. Folders are listed once
+/// and remembered, so the store should be dropped once what it was opened
+/// for is done.
+pub struct MtpStore {
+    device: Box<dyn MtpObjects>,
+    storages: Vec<MtpStorage>,
+    folders: HashMap<(u32, u32), Vec<MtpObject>>,
+}
+
+impl MtpStore {
+    pub fn new(mut device: Box<dyn MtpObjects>) -> anyhow::Result<Self> {
+        let storages = device.storages()?;
+        Ok(Self {
+            device,
+            storages,
+            folders: HashMap::new(),
+        })
+    }
+
+    /// The store of the connected device with serial `serial`, through
+    /// libmtp.
+    #[cfg(unix)]
+    pub fn open(serial: &str) -> anyhow::Result<Self> {
+        Self::new(Box::new(crate::libmtp::Device::open(serial)?))
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(serial: &str) -> anyhow::Result<Self> {
+        anyhow::bail!("MTP device {} can only be reached through libmtp, on Linux and macOS", serial)
+    }
+
+    pub fn storages(&self) -> &[MtpStorage] {
+        &self.storages
+    }
+
+    fn children(&mut self, storage: u32, parent: u32) -> anyhow::Result<&mut Vec<MtpObject>> {
+        if !self.folders.contains_key(&(storage, parent)) {
+            let children = self.device.children(storage, parent)?;
+            self.folders.insert((storage, parent), children);
+        }
+        Ok(self.folders.entry((storage, parent)).or_default())
+    }
+
+    /// Follow `path` to what it names, if anything does.
+    fn find(&mut self, path: &Path) -> anyhow::Result<Option<Found>> {
+        let mut names = names(path)?.into_iter();
+        let Some(first) = names.next() else {
+            anyhow::bail!("{} doesn't name one of the device's storages", path.display());
+        };
+        let Some(storage) = self.storages.iter().find(|s| s.name() == first).map(|s| s.id) else {
+            return Ok(None);
+        };
+        let mut found = Found {
+            storage,
+            id: ROOT,
+            parent: ROOT,
+            object: None,
+        };
+        for name in names {
+            if found.object.as_ref().is_some_and(|o| !o.is_dir) {
+                return Ok(None);
+            }
+            let object = self.children(storage, found.id)?.iter().find(|o| o.name == name).cloned();
+            let Some(object) = object else { return Ok(None) };
+            found = Found {
+                storage,
+                id: object.id,
+                parent: found.id,
+                object: Some(object),
+            };
+        }
+        Ok(Some(found))
+    }
+
+    /// The file or folder at `path`, if there is one. A storage itself has
+    /// no object.
+    pub fn stat(&mut self, path: &Path) -> anyhow::Result<Option<MtpObject>> {
+        Ok(self.find(path)?.and_then(|f| f.object))
+    }
+
+    /// Everything under the folder `root`, with paths relative to it, in
+    /// `Path` order. Entries `keep` turns down aren't listed, or looked
+    /// inside if they're folders.
+    pub fn walk(
+        &mut self,
+        root: &Path,
+        mut keep: impl FnMut(&Path, &MtpObject) -> bool,
+    ) -> anyhow::Result<Vec<(PathBuf, MtpObject)>> {
+        let found = self
+            .find(root)?
+            .filter(|f| f.object.as_ref().is_none_or(|o| o.is_dir))
+            .ok_or_else(|| anyhow::anyhow!("no folder {} on the device", root.display()))?;
+        let mut listed = Vec::new();
+        let mut pending = vec![(PathBuf::new(), found.id)];
+        while let Some((dir, id)) = pending.pop() {
+            for object in self.children(found.storage, id)?.clone() {
+                let rel_path = dir.join(&object.name);
+                if !keep(&rel_path, &object) {
+                    continue;
+                }
+                if object.is_dir {
+                    pending.push((rel_path.clone(), object.id));
+                }
+                listed.push((rel_path, object));
+            }
+        }
+        listed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(listed)
+    }
+
+    /// Copy the file at `path` into `dst` on this machine.
+    pub fn fetch(&mut self, path: &Path, dst: &Path) -> anyhow::Result<MtpObject> {
+        let object = self
+            .stat(path)?
+            .filter(|o| !o.is_dir)
+            .ok_or_else(|| anyhow::anyhow!("no file {} on the device", path.display()))?;
+        self.device.download(object.id, dst)?;
+        Ok(object)
+    }
+
+    /// Copy `src` from this machine to `path`, making the folders it's in.
+    /// MTP can't write over a file, so one already there is deleted first.
+    pub fn put(&mut self, src: &Path, path: &Path, mtime: i64) -> anyhow::Result<()> {
+        let name = file_name(path)?;
+        let (storage, parent) = self.create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+        if let Some(existing) = self.find(path)?.and_then(|f| f.object) {
+            if existing.is_dir {
+                anyhow::bail!("{} is a folder on the device", path.display());
+            }
+            self.device.delete(existing.id)?;
+            self.children(storage, parent)?.retain(|o| o.id != existing.id);
+        }
+        let id = self.device.upload(src, storage, parent, &name, mtime)?;
+        let object = MtpObject {
+            id,
+            name,
+            size: std::fs::metadata(src)?.len(),
+            mtime,
+            is_dir: false,
+        };
+        self.children(storage, parent)?.push(object);
+        Ok(())
+    }
+
+    /// Delete the file or empty folder at `path`. Nothing there is fine.
+    pub fn remove(&mut self, path: &Path) -> anyhow::Result<()> {
+        let Some(Found { storage, id, parent, object: Some(object) }) = self.find(path)? else {
+            return Ok(());
+        };
+        if object.is_dir && !self.children(storage, id)?.is_empty() {
+            anyhow::bail!("folder is not empty: {}", path.display());
+        }
+        self.device.delete(id)?;
+        self.children(storage, parent)?.retain(|o| o.id != id);
+        self.folders.remove(&(storage, id));
+        Ok(())
+    }
+
+    /// Make the folder `path` and any it's in that are missing, returning
+    /// its storage and ID.
+    pub fn create_dir_all(&mut self, path: &Path) -> anyhow::Result<(u32, u32)> {
+        let names = names(path)?;
+        let Some((first, rest)) = names.split_first() else {
+            anyhow::bail!("{} doesn't name one of the device's storages", path.display());
+        };
+        let storage = self
+            .storages
+            .iter()
+            .find(|s| s.name() == *first)
+            .map(|s| s.id)
+            .ok_or_else(|| anyhow::anyhow!("the device has no storage named {}", first))?;
+        let mut id = ROOT;
+        for name in rest {
+            let existing = self.children(storage, id)?.iter().find(|o| o.name == *name).cloned();
+            id = match existing {
+                Some(o) if o.is_dir => o.id,
+                Some(_) => anyhow::bail!("{} is a file on the device", path.display()),
+                None => {
+                    let made = self.device.create_folder(storage, id, name)?;
+                    self.children(storage, id)?.push(MtpObject {
+                        id: made,
+                        name: name.clone(),
+                        size: 0,
+                        mtime: 0,
+                        is_dir: true,
+                    });
+                    made
+                }
+            };
+        }
+        Ok((storage, id))
+    }
+}
+
+/// The names along `path`, which has to be relative and go only down.
+fn names(path: &Path) -> anyhow::Result<Vec<String>> {
+    path.components()
+        .map(|c| match c {
+            Component::Normal(name) => Ok(name.to_string_lossy().into_owned()),
+            _ => anyhow::bail!("{} isn't a path on the device", path.display()),
+        })
+        .collect()
+}
+
+fn file_name(path: &Path) -> anyhow::Result<String> {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow::anyhow!("{} isn't a file on the device", path.display()))
+}
+
+/// MTP FUSE mounts in `/proc/mounts` content, with a name for each.
+fn parse_mtp_mounts(mounts: &str) -> Vec<(PathBuf, String)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let spec = fields.next()?;
+            let mount = fields.next()?.replace("\\040", " ");
+            let fstype = fields.next()?;
+            if !fstype.starts_with("fuse.") || !(fstype.contains("mtp") || fstype.contains("android")) {
+                return None;
+            }
+            let mount = PathBuf::from(mount);
+            let name = mount
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| spec.to_string());
+            Some((mount, name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakeMtpDevice;
+
+    #[test]
+    fn test_parse_mtp_mounts() {
+        let mounts = "/dev/sda1 / ext4 rw 0 0\n\
+                      jmtpfs /media/Pixel\\0407 fuse.jmtpfs rw,nosuid 0 0\n\
+                      simple-mtpfs /mnt/phone fuse.simple-mtpfs rw 0 0\n\
+                      sshfs#host: /mnt/remote fuse.sshfs rw 0 0\n";
+        assert_eq!(
+            parse_mtp_mounts(mounts),
+            vec![
+                (PathBuf::from("/media/Pixel 7"), "Pixel 7".to_string()),
+                (PathBuf::from("/mnt/phone"), "phone".to_string()),
+            ]
+        );
+    }
+
+    fn phone() -> FakeMtpDevice {
+        let device = FakeMtpDevice::new(&["Internal shared storage", ""]);
+        let dcim = device.add(0x10001, ROOT, "DCIM", None, 0);
+        let camera = device.add(0x10001, dcim, "Camera", None, 0);
+        device.add(0x10001, camera, "b.jpg", Some(b"bbb"), 200);
+        device.add(0x10001, camera, "a.jpg", Some(b"a"), 100);
+        device.add(0x10001, dcim, ".thumbnails", None, 0);
+        device
+    }
+
+    #[test]
+    fn test_device_drive() {
+        let storages = FakeMtpDevice::new(&["Internal shared storage", "SD card"]).storages().unwrap();
+        let drive = device_drive("R58M12345", Some("Galaxy S10".to_string()), &storages);
+        assert_eq!(
+            drive.identity,
+            DriveIdentity::Synthetic {
+                id: "mtp-R58M12345".to_string()
+            }
+        );
+        assert_eq!(drive.mount_point, PathBuf::from("mtp://R58M12345"));
+        assert_eq!(drive.label.as_deref(), Some("Galaxy S10"));
+        assert_eq!(drive.bus, BusType::Usb);
+        assert!(drive.is_mtp());
+        assert_eq!(drive.total_bytes, Some(2000));
+        assert_eq!(drive.free_bytes, Some(800));
+    }
+
+    #[test]
+    fn test_walk_lists_a_folder_in_path_order() {
+        let mut store = MtpStore::new(Box::new(phone())).unwrap();
+        assert_eq!(store.storages()[1].name(), "storage-00010002");
+
+        let listed = store
+            .walk(Path::new("Internal shared storage/DCIM"), |path, _| {
+                !path.starts_with(".thumbnails")
+            })
+            .unwrap();
+        let paths: Vec<&Path> = listed.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![Path::new("Camera"), Path::new("Camera/a.jpg"), Path::new("Camera/b.jpg")]
+        );
+        assert_eq!(listed[2].1.size, 3);
+        assert_eq!(listed[2].1.mtime, 200);
+
+        assert!(store.walk(Path::new("Internal shared storage/Music"), |_, _| true).is_err());
+        assert!(store.walk(Path::new("../DCIM"), |_, _| true).is_err());
+    }
+
+    #[test]
+    fn test_fetch_put_and_remove() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut store = MtpStore::new(Box::new(phone())).unwrap();
+
+        let fetched = tmp.path().join("b.jpg");
+        store
+            .fetch(Path::new("Internal shared storage/DCIM/Camera/b.jpg"), &fetched)
+            .unwrap();
+        assert_eq!(std::fs::read(&fetched).unwrap(), b"bbb");
+        assert!(store.fetch(Path::new("Internal shared storage/DCIM"), &fetched).is_err());
+
+        // Sending makes the folders it goes in, and replaces a file already
+        // there.
+        let src = tmp.path().join("c.jpg");
+        std::fs::write(&src, b"cccc").unwrap();
+        let dst = Path::new("Internal shared storage/Backup/2024/c.jpg");
+        store.put(&src, dst, 300).unwrap();
+        std::fs::write(&src, b"cc").unwrap();
+        store.put(&src, dst, 400).unwrap();
+        let sent = store.stat(dst).unwrap().unwrap();
+        assert_eq!((sent.size, sent.mtime), (2, 400));
+        assert_eq!(
+            store.walk(Path::new("Internal shared storage/Backup"), |_, _| true).unwrap().len(),
+            2
+        );
+
+        let camera = Path::new("Internal shared storage/DCIM/Camera");
+        assert!(store.remove(camera).is_err());
+        store.remove(&camera.join("a.jpg")).unwrap();
+        store.remove(&camera.join("b.jpg")).unwrap();
+        store.remove(camera).unwrap();
+        store.remove(camera).unwrap();
+        assert_eq!(store.stat(camera).unwrap(), None);
+    }
+}
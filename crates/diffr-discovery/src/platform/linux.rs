@@ -1,4 +1,5 @@
-use diffr_core::models::drive::{Drive, DriveIdentity};
+use diffr_core::models::drive::{BusType, Drive, DriveIdentity};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Command;
 
@@ -10,13 +11,6 @@ impl DriveDiscovery for LinuxDiscovery {
     fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
         discover_linux_drives()
     }
-
-    fn find_by_serial(&self, serial: &str) -> anyhow::Result<Option<Drive>> {
-        let drives = self.discover_drives()?;
-        Ok(drives
-            .into_iter()
-            .find(|d| d.identity.identity_string() == serial))
-    }
 }
 
 #[cfg(target_os = "linux")]
@@ -29,75 +23,345 @@ fn discover_linux_drives() -> anyhow::Result<Vec<Drive>> {
     }
 
     #[derive(Deserialize)]
+    #[allow(dead_code)]
     struct BlockDevice {
         name: String,
         serial: Option<String>,
-        size: Option<String>,
+        /// Bytes, with `--bytes`: a number in newer lsblk, a string in older.
+        size: Option<serde_json::Value>,
         mountpoint: Option<String>,
         label: Option<String>,
         #[serde(rename = "type")]
         device_type: Option<String>,
+        wwn: Option<String>,
+        uuid: Option<String>,
+        partuuid: Option<String>,
+        fstype: Option<String>,
+        tran: Option<String>,
+        /// Boolean in newer lsblk, `"0"`/`"1"` in older releases.
+        rm: Option<serde_json::Value>,
+        hotplug: Option<serde_json::Value>,
         children: Option<Vec<BlockDevice>>,
     }
 
-    let output = Command::new("lsblk")
-        .args(["--json", "-o", "NAME,SERIAL,SIZE,MOUNTPOINT,LABEL,TYPE"])
-        .output()?;
+    let output = match Command::new("lsblk")
+        .args([
+            "--json",
+            "--bytes",
+            "-o",
+            "NAME,SERIAL,SIZE,MOUNTPOINT,LABEL,TYPE,WWN,UUID,PARTUUID,FSTYPE,TRAN,RM,HOTPLUG",
+        ])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            tracing::warn!(
+                "lsblk failed ({}), falling back to /proc/mounts",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Ok(crate::discover_from_mount_table());
+        }
+        Err(e) => {
+            tracing::warn!("lsblk unavailable ({}), falling back to /proc/mounts", e);
+            return Ok(crate::discover_from_mount_table());
+        }
+    };
 
-    if !output.status.success() {
-        anyhow::bail!("lsblk failed: {}", String::from_utf8_lossy(&output.stderr));
+    let lsblk: LsblkOutput = serde_json::from_slice(&output.stdout)?;
+    let is_mounted =
+        |d: &BlockDevice| d.mountpoint.as_deref().is_some_and(|m| m != "[SWAP]");
+    let by_id_wwns = read_by_id_wwns();
+
+    // A serial shared by several disks (common with cheap USB bridges)
+    // doesn't tell them apart on its own.
+    let mut serial_counts: HashMap<String, usize> = HashMap::new();
+    for device in &lsblk.blockdevices {
+        if let Some(serial) = non_empty(&device.serial) {
+            *serial_counts.entry(serial).or_default() += 1;
+        }
     }
 
-    let lsblk: LsblkOutput = serde_json::from_slice(&output.stdout)?;
     let mut drives = Vec::new();
 
     for device in &lsblk.blockdevices {
-        let serial = device
-            .serial
-            .as_ref()
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty());
+        let serial = non_empty(&device.serial);
+        let wwn = non_empty(&device.wwn).or_else(|| by_id_wwns.get(&device.name).cloned());
+        let sysfs = std::fs::canonicalize(format!("/sys/block/{}", device.name)).ok();
+        let bus = classify_bus(
+            &device.name,
+            non_empty(&device.tran).as_deref(),
+            json_flag(&device.rm) || json_flag(&device.hotplug),
+            sysfs.as_ref().and_then(|p| p.to_str()),
+        );
 
-        // Check children (partitions) for mount points
+        // The device itself may hold a filesystem (whole-disk), otherwise
+        // look at its partitions.
+        let mut mounted: Vec<&BlockDevice> = Vec::new();
+        if is_mounted(device) {
+            mounted.push(device);
+        }
         if let Some(children) = &device.children {
-            for child in children {
-                if let Some(ref mountpoint) = child.mountpoint {
-                    if mountpoint == "[SWAP]" {
-                        continue;
-                    }
-                    let mount = PathBuf::from(mountpoint);
-                    let identity = match &serial {
-                        Some(s) => DriveIdentity::new_hardware(s.clone()),
-                        None => crate::read_or_create_synthetic_id(&mount)
-                            .unwrap_or_else(|_| DriveIdentity::new_synthetic()),
-                    };
-                    let mut drive = Drive::new(identity, mount);
-                    drive.label = child.label.clone().or_else(|| Some(device.name.clone()));
-                    drives.push(drive);
-                }
-            }
+            mounted.extend(children.iter().filter(|c| is_mounted(c)));
         }
 
-        // Also check the device itself if it has a mount point (e.g. whole-disk filesystem)
-        if let Some(ref mountpoint) = device.mountpoint {
-            if mountpoint != "[SWAP]" {
-                let mount = PathBuf::from(mountpoint);
-                let identity = match &serial {
-                    Some(s) => DriveIdentity::new_hardware(s.clone()),
-                    None => crate::read_or_create_synthetic_id(&mount)
-                        .unwrap_or_else(|_| DriveIdentity::new_synthetic()),
-                };
-                let mut drive = Drive::new(identity, mount);
-                drive.label = device.label.clone();
-                drives.push(drive);
-            }
+        let disk_ids = DiskIds {
+            shared_serial: serial.as_ref().is_some_and(|s| serial_counts[s] > 1),
+            serial,
+            wwn,
+            filesystems: mounted.len(),
+        };
+
+        for part in mounted {
+            let mount = PathBuf::from(part.mountpoint.as_deref().unwrap_or_default());
+            let part_ids = PartIds {
+                partuuid: non_empty(&part.partuuid),
+                uuid: non_empty(&part.uuid),
+                number: partition_number(&device.name, &part.name),
+            };
+            let (primary, aliases) = layered_identity(&disk_ids, &part_ids);
+            let identity = match primary {
+                Some(id) => DriveIdentity::new_hardware(id),
+                None => crate::synthetic_identity(&mount),
+            };
+            let mut drive = Drive::new(identity, mount);
+            drive.label = if std::ptr::eq(part, device) {
+                device.label.clone()
+            } else {
+                part.label.clone().or_else(|| Some(device.name.clone()))
+            };
+            drive.filesystem = non_empty(&part.fstype);
+            drive.aliases = aliases;
+            drive.bus = bus;
+            // statvfs knows the filesystem's size and what's free; lsblk's
+            // partition size stands in if it can't be read.
+            crate::fill_space(&mut drive);
+            drive.total_bytes = drive.total_bytes.or_else(|| json_bytes(&part.size));
+            drives.push(drive);
         }
     }
 
+    drives.extend(crate::mtp::discover_mtp_devices());
     Ok(drives)
 }
 
+/// Map kernel device names (`sda`) to their `wwn-0x...` entry in
+/// `/dev/disk/by-id`, for lsblk builds that don't report WWN.
+#[cfg(target_os = "linux")]
+fn read_by_id_wwns() -> HashMap<String, String> {
+    let mut wwns = HashMap::new();
+    let Ok(entries) = std::fs::read_dir("/dev/disk/by-id") else {
+        return wwns;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(wwn) = name.strip_prefix("wwn-") else {
+            continue;
+        };
+        if wwn.contains("-part") {
+            continue;
+        }
+        if let Ok(target) = std::fs::read_link(entry.path()) {
+            if let Some(dev) = target.file_name() {
+                wwns.insert(dev.to_string_lossy().to_string(), wwn.to_string());
+            }
+        }
+    }
+    wwns
+}
+
+/// Identifiers that belong to the whole disk rather than one filesystem.
+#[derive(Default)]
+struct DiskIds {
+    wwn: Option<String>,
+    serial: Option<String>,
+    /// Other disks report the same serial.
+    shared_serial: bool,
+    /// Mounted filesystems on the disk.
+    filesystems: usize,
+}
+
+/// Identifiers of one filesystem on a disk.
+#[derive(Default)]
+struct PartIds {
+    partuuid: Option<String>,
+    uuid: Option<String>,
+    /// Partition number, `2` for `sdb2`; `None` for a whole-disk filesystem.
+    number: Option<u32>,
+}
+
+/// Pick a primary identity from the most to least specific identifier
+/// available, and keep the rest as aliases. The serial comes first so drives
+/// registered before aliases existed keep their identity; the WWN, PARTUUID
+/// and filesystem UUID survive a move to a different USB adapter.
+///
+/// The serial and WWN name the disk, so on a disk with several filesystems,
+/// or a serial other disks share, they get a partition qualifier:
+/// `WD-123:partuuid-1234-02`, or `WD-123:part2` when the partition has no
+/// UUID. A shared serial needs a UUID, since partition numbers repeat
+/// across the disks sharing it.
+fn layered_identity(disk: &DiskIds, part: &PartIds) -> (Option<String>, Vec<String>) {
+    let strong = part
+        .partuuid
+        .as_ref()
+        .map(|p| format!("partuuid-{}", p))
+        .or_else(|| part.uuid.as_ref().map(|u| format!("uuid-{}", u)));
+    let any = strong.clone().or_else(|| part.number.map(|n| format!("part{}", n)));
+    let qualified = |id: String, qualifier: &Option<String>| qualifier.as_ref().map(|q| format!("{}:{}", id, q));
+
+    let several = disk.filesystems > 1;
+    let serial = disk.serial.clone().and_then(|s| match (disk.shared_serial, several) {
+        (false, false) => Some(s),
+        (false, true) => qualified(s, &any),
+        (true, _) => qualified(s, &strong),
+    });
+    let wwn = disk.wwn.as_ref().map(|w| format!("wwn-{}", w)).and_then(|w| match several {
+        false => Some(w),
+        true => qualified(w, &any),
+    });
+
+    let mut ids: Vec<String> = serial.into_iter().collect();
+    ids.extend(wwn);
+    ids.extend(part.partuuid.as_ref().map(|p| format!("partuuid-{}", p)));
+    ids.extend(part.uuid.as_ref().map(|u| format!("uuid-{}", u)));
+
+    let mut ids = ids.into_iter();
+    let primary = ids.next();
+    (primary, ids.collect())
+}
+
+/// The number of partition `part` on disk `disk`: `2` for `sdb2` or
+/// `nvme0n1p2`. `None` when `part` is the disk itself.
+fn partition_number(disk: &str, part: &str) -> Option<u32> {
+    let suffix = part.strip_prefix(disk)?;
+    suffix.strip_prefix('p').unwrap_or(suffix).parse().ok()
+}
+
+/// Work out how a disk is attached from lsblk's transport and removable
+/// flags, and its sysfs path (Thunderbolt devices sit under a
+/// `thunderbolt` node whatever transport they report).
+fn classify_bus(name: &str, tran: Option<&str>, removable: bool, sysfs: Option<&str>) -> BusType {
+    if sysfs.is_some_and(|p| p.contains("/thunderbolt/")) {
+        return BusType::Thunderbolt;
+    }
+    match tran {
+        Some("usb") => BusType::Usb,
+        _ if name.starts_with("mmcblk") => BusType::Sd,
+        _ if removable => BusType::Unknown,
+        // SATA, NVMe, and virtio or device-mapper disks with no transport.
+        _ => BusType::Internal,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn json_flag(value: &Option<serde_json::Value>) -> bool {
+    match value {
+        Some(serde_json::Value::Bool(b)) => *b,
+        Some(serde_json::Value::String(s)) => s == "1",
+        _ => false,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn json_bytes(value: &Option<serde_json::Value>) -> Option<u64> {
+    match value {
+        Some(serde_json::Value::Number(n)) => n.as_u64(),
+        Some(serde_json::Value::String(s)) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .filter(|&b| b > 0)
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value
+        .as_ref()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
 #[cfg(not(target_os = "linux"))]
 fn discover_linux_drives() -> anyhow::Result<Vec<Drive>> {
     Ok(Vec::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layered_identity() {
+        let mut disk = DiskIds {
+            wwn: Some("0x5000c500a1b2c3d4".into()),
+            serial: Some("WD-123".into()),
+            shared_serial: false,
+            filesystems: 1,
+        };
+        let part = PartIds {
+            partuuid: Some("1234-01".into()),
+            uuid: Some("ABCD-EF01".into()),
+            number: Some(1),
+        };
+        let (primary, aliases) = layered_identity(&disk, &part);
+        assert_eq!(primary.as_deref(), Some("WD-123"));
+        assert_eq!(
+            aliases,
+            vec!["wwn-0x5000c500a1b2c3d4", "partuuid-1234-01", "uuid-ABCD-EF01"]
+        );
+
+        // Several filesystems on the disk: the serial and WWN are qualified
+        // by partition, by number when there's no UUID.
+        disk.filesystems = 2;
+        let (primary, aliases) = layered_identity(&disk, &part);
+        assert_eq!(primary.as_deref(), Some("WD-123:partuuid-1234-01"));
+        assert_eq!(aliases[0], "wwn-0x5000c500a1b2c3d4:partuuid-1234-01");
+        let numbered = PartIds {
+            number: Some(2),
+            ..PartIds::default()
+        };
+        let (primary, aliases) = layered_identity(&disk, &numbered);
+        assert_eq!(primary.as_deref(), Some("WD-123:part2"));
+        assert_eq!(aliases, vec!["wwn-0x5000c500a1b2c3d4:part2"]);
+
+        // A shared serial only counts with a UUID to set it apart.
+        disk.shared_serial = true;
+        assert_eq!(layered_identity(&disk, &part).0.as_deref(), Some("WD-123:partuuid-1234-01"));
+        assert_eq!(
+            layered_identity(&disk, &numbered).0.as_deref(),
+            Some("wwn-0x5000c500a1b2c3d4:part2")
+        );
+
+        // No disk identifiers at all: fall back through the layers.
+        let part = PartIds {
+            uuid: Some("ABCD-EF01".into()),
+            ..PartIds::default()
+        };
+        let (primary, aliases) = layered_identity(&DiskIds::default(), &part);
+        assert_eq!(primary.as_deref(), Some("uuid-ABCD-EF01"));
+        assert!(aliases.is_empty());
+        assert_eq!(layered_identity(&DiskIds::default(), &PartIds::default()).0, None);
+    }
+
+    #[test]
+    fn test_partition_number() {
+        assert_eq!(partition_number("sdb", "sdb2"), Some(2));
+        assert_eq!(partition_number("nvme0n1", "nvme0n1p3"), Some(3));
+        assert_eq!(partition_number("mmcblk0", "mmcblk0p1"), Some(1));
+        assert_eq!(partition_number("sdb", "sdb"), None);
+    }
+
+    #[test]
+    fn test_classify_bus() {
+        assert_eq!(classify_bus("sdb", Some("usb"), true, None), BusType::Usb);
+        assert_eq!(classify_bus("nvme0n1", Some("nvme"), false, None), BusType::Internal);
+        assert_eq!(
+            classify_bus(
+                "nvme1n1",
+                Some("nvme"),
+                true,
+                Some("/sys/devices/pci0000:00/0000:00:1d.4/thunderbolt/domain0/0-0/nvme/nvme1/nvme1n1")
+            ),
+            BusType::Thunderbolt
+        );
+        assert_eq!(classify_bus("mmcblk0", None, true, None), BusType::Sd);
+        assert_eq!(classify_bus("vda", None, false, None), BusType::Internal);
+    }
+}
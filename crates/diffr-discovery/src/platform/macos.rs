@@ -1,5 +1,5 @@
-use diffr_core::models::drive::{Drive, DriveIdentity};
-use std::path::PathBuf;
+use diffr_core::models::drive::{BusType, Drive, DriveIdentity};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use crate::DriveDiscovery;
@@ -10,26 +10,25 @@ impl DriveDiscovery for MacOsDiscovery {
     fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
         discover_macos_drives()
     }
-
-    fn find_by_serial(&self, serial: &str) -> anyhow::Result<Option<Drive>> {
-        let drives = self.discover_drives()?;
-        Ok(drives
-            .into_iter()
-            .find(|d| d.identity.identity_string() == serial))
-    }
 }
 
 #[cfg(target_os = "macos")]
 fn discover_macos_drives() -> anyhow::Result<Vec<Drive>> {
     // List all disks via diskutil
-    let output = Command::new("diskutil").args(["list", "-plist"]).output()?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "diskutil list failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
+    let output = match Command::new("diskutil").args(["list", "-plist"]).output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            tracing::warn!(
+                "diskutil list failed ({}), falling back to getmntinfo",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Ok(crate::discover_from_mount_table());
+        }
+        Err(e) => {
+            tracing::warn!("diskutil unavailable ({}), falling back to getmntinfo", e);
+            return Ok(crate::discover_from_mount_table());
+        }
+    };
 
     let plist: plist::Value = plist::from_bytes(&output.stdout)?;
     let mut drives = Vec::new();
@@ -52,15 +51,17 @@ fn discover_macos_drives() -> anyhow::Result<Vec<Drive>> {
                         let identity = match info.serial {
                             Some(s) if !s.is_empty() => DriveIdentity::new_hardware(s),
                             _ => {
-                                let mount = PathBuf::from(&mount_point);
-                                crate::read_or_create_synthetic_id(&mount)
-                                    .unwrap_or_else(|_| DriveIdentity::new_synthetic())
+                                crate::synthetic_identity(Path::new(&mount_point))
                             }
                         };
                         let mut drive = Drive::new(identity, PathBuf::from(&mount_point));
                         drive.label = info.volume_name;
                         drive.total_bytes = info.total_size;
                         drive.free_bytes = info.free_space;
+                        drive.bus = info.bus;
+                        // Some volumes (APFS containers among them) leave
+                        // sizes out of diskutil info.
+                        crate::fill_space(&mut drive);
                         drives.push(drive);
                     }
                 }
@@ -68,6 +69,8 @@ fn discover_macos_drives() -> anyhow::Result<Vec<Drive>> {
         }
     }
 
+    // Only devices libmtp can open: macOS doesn't mount MTP devices.
+    drives.extend(crate::mtp::discover_mtp_devices());
     Ok(drives)
 }
 
@@ -78,6 +81,7 @@ struct DiskInfo {
     volume_name: Option<String>,
     total_size: Option<u64>,
     free_space: Option<u64>,
+    bus: BusType,
 }
 
 #[cfg(target_os = "macos")]
@@ -95,7 +99,17 @@ fn get_disk_info(disk_id: &str) -> anyhow::Result<DiskInfo> {
         .as_dictionary()
         .ok_or_else(|| anyhow::anyhow!("expected dictionary"))?;
 
+    let internal = dict.get("Internal").and_then(|v| v.as_boolean());
+    let bus = match dict.get("BusProtocol").and_then(|v| v.as_string()) {
+        Some("USB") => BusType::Usb,
+        Some("Thunderbolt") => BusType::Thunderbolt,
+        Some("Secure Digital") => BusType::Sd,
+        _ if internal == Some(true) => BusType::Internal,
+        _ => BusType::Unknown,
+    };
+
     Ok(DiskInfo {
+        bus,
         serial: dict
             .get("IORegistryEntrySerialNumber")
             .and_then(|v| v.as_string())
@@ -9,8 +9,18 @@ pub mod macos;
 
 use crate::DriveDiscovery;
 
-/// Get the platform-appropriate drive discovery implementation.
+/// Get the platform-appropriate drive discovery implementation, or the
+/// fixture named by `DIFFR_FAKE_DRIVES` if it is set. Use
+/// [`crate::registry::select`] to honour a configured backend.
 pub fn get_discovery() -> Box<dyn DriveDiscovery> {
+    if let Some(fixture) = std::env::var_os(crate::fake::FAKE_DRIVES_ENV) {
+        return Box::new(crate::fake::FakeDiscovery::new(fixture));
+    }
+    native_discovery()
+}
+
+/// The built-in discovery for the current OS.
+pub fn native_discovery() -> Box<dyn DriveDiscovery> {
     #[cfg(target_os = "windows")]
     {
         Box::new(windows::WindowsDiscovery)
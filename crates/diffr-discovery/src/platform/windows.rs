@@ -1,5 +1,5 @@
-use diffr_core::models::drive::{Drive, DriveIdentity};
-use std::collections::HashMap;
+use diffr_core::models::drive::{BusType, Drive, DriveIdentity};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use crate::DriveDiscovery;
@@ -10,13 +10,6 @@ impl DriveDiscovery for WindowsDiscovery {
     fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
         discover_windows_drives()
     }
-
-    fn find_by_serial(&self, serial: &str) -> anyhow::Result<Option<Drive>> {
-        let drives = self.discover_drives()?;
-        Ok(drives
-            .into_iter()
-            .find(|d| d.identity.identity_string() == serial))
-    }
 }
 
 #[cfg(target_os = "windows")]
@@ -36,6 +29,10 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
         size: Option<u64>,
         #[serde(rename = "Model")]
         model: Option<String>,
+        #[serde(rename = "InterfaceType")]
+        interface_type: Option<String>,
+        #[serde(rename = "MediaType")]
+        media_type: Option<String>,
     }
 
     #[derive(Deserialize)]
@@ -67,6 +64,31 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
         free_space: Option<u64>,
         #[serde(rename = "VolumeName")]
         volume_name: Option<String>,
+        #[serde(rename = "FileSystem")]
+        file_system: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "Win32_Volume")]
+    struct Volume {
+        /// Volume GUID path, `\\?\Volume{...}\`.
+        #[serde(rename = "DeviceID")]
+        device_id: String,
+        #[serde(rename = "DriveLetter")]
+        drive_letter: Option<String>,
+        #[serde(rename = "FileSystem")]
+        file_system: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "Win32_EncryptableVolume")]
+    struct EncryptableVolume {
+        #[serde(rename = "DeviceID")]
+        device_id: String,
+        /// 0 = off, 1 = on, 2 = unknown, which BitLocker reports while the
+        /// volume is locked.
+        #[serde(rename = "ProtectionStatus")]
+        protection_status: Option<u32>,
     }
 
     let com = COMLibrary::new()?;
@@ -76,18 +98,51 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
     let disk_to_part: Vec<DiskToPartition> = wmi.query()?;
     let logical_to_part: Vec<LogicalToPartition> = wmi.query()?;
     let logicals: Vec<LogicalDisk> = wmi.query()?;
-
-    // Build mapping: partition -> disk device_id
+    let volumes: Vec<Volume> = wmi.query()?;
+
+    // BitLocker lives in its own namespace and needs elevation to query; if
+    // it isn't available, treat every volume as unlocked.
+    let locked_volumes: HashSet<String> = WMIConnection::with_namespace_path(
+        "ROOT\\CIMV2\\Security\\MicrosoftVolumeEncryption",
+        com,
+    )
+    .and_then(|conn| conn.query::<EncryptableVolume>())
+    .map(|encryptable| {
+        encryptable
+            .into_iter()
+            .filter(|v| v.protection_status == Some(2))
+            .map(|v| v.device_id.to_uppercase())
+            .collect()
+    })
+    .unwrap_or_default();
+
+    let volume_by_letter: HashMap<String, &Volume> = volumes
+        .iter()
+        .filter_map(|v| Some((v.drive_letter.as_ref()?.to_uppercase(), v)))
+        .collect();
+
+    // Association objects reference their endpoints by WMI object path, e.g.
+    // `\\HOST\root\cimv2:Win32_DiskPartition.DeviceID="Disk #0, Partition #0"`.
+    // Key everything by the parsed DeviceID so each letter maps to exactly
+    // one physical disk.
     let mut part_to_disk: HashMap<String, String> = HashMap::new();
     for dtp in &disk_to_part {
-        part_to_disk.insert(dtp.dependent.clone(), dtp.antecedent.clone());
+        if let (Some(disk), Some(part)) =
+            (association_key(&dtp.antecedent), association_key(&dtp.dependent))
+        {
+            part_to_disk.insert(part, disk);
+        }
     }
 
-    // Build mapping: logical drive letter -> partition
     let mut logical_to_disk: HashMap<String, String> = HashMap::new();
     for ltp in &logical_to_part {
-        if let Some(disk_ref) = part_to_disk.get(&ltp.antecedent) {
-            logical_to_disk.insert(ltp.dependent.clone(), disk_ref.clone());
+        let (Some(part), Some(letter)) =
+            (association_key(&ltp.antecedent), association_key(&ltp.dependent))
+        else {
+            continue;
+        };
+        if let Some(disk) = part_to_disk.get(&part) {
+            logical_to_disk.insert(letter.to_uppercase(), disk.clone());
         }
     }
 
@@ -100,16 +155,25 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
             .map(|s| s.trim().to_string())
             .filter(|s| !s.is_empty());
 
-        // Find logical drives mapped to this physical disk
+        // Thunderbolt enclosures show up as SCSI; only USB is named as such.
+        let external = disk
+            .media_type
+            .as_deref()
+            .is_some_and(|m| m.contains("External") || m.contains("Removable"));
+        let bus = match disk.interface_type.as_deref() {
+            Some("USB") => BusType::Usb,
+            _ if external => BusType::Unknown,
+            Some(_) => BusType::Internal,
+            None => BusType::Unknown,
+        };
+
+        // Logical drives whose partition lives on this physical disk
         let mount_points: Vec<&LogicalDisk> = logicals
             .iter()
             .filter(|ld| {
                 logical_to_disk
-                    .values()
-                    .any(|disk_ref| disk_ref.contains(&disk.device_id))
-                    && logical_to_disk
-                        .keys()
-                        .any(|k| k.contains(&ld.device_id))
+                    .get(&ld.device_id.to_uppercase())
+                    .is_some_and(|d| d.eq_ignore_ascii_case(&disk.device_id))
             })
             .collect();
 
@@ -117,11 +181,7 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
             let mount = PathBuf::from(format!("{}\\", logical.device_id));
             let identity = match &serial {
                 Some(s) => DriveIdentity::new_hardware(s.clone()),
-                None => {
-                    // Try to read synthetic ID from drive, or create one
-                    crate::read_or_create_synthetic_id(&mount)
-                        .unwrap_or_else(|_| DriveIdentity::new_synthetic())
-                }
+                None => crate::synthetic_identity(&mount),
             };
 
             let mut drive = Drive::new(identity, mount);
@@ -131,6 +191,13 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
                 .or_else(|| disk.model.clone());
             drive.total_bytes = logical.size;
             drive.free_bytes = logical.free_space;
+            drive.bus = bus;
+            drive.filesystem = logical.file_system.clone();
+            if let Some(volume) = volume_by_letter.get(&logical.device_id.to_uppercase()) {
+                drive.volume_guid = Some(volume.device_id.clone());
+                drive.filesystem = drive.filesystem.take().or_else(|| volume.file_system.clone());
+                drive.locked = locked_volumes.contains(&volume.device_id.to_uppercase());
+            }
             drives.push(drive);
         }
     }
@@ -138,7 +205,39 @@ fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
     Ok(drives)
 }
 
+/// Extract the `DeviceID` value from a WMI object path, undoing the
+/// backslash escaping WMI applies inside the quoted key.
+fn association_key(reference: &str) -> Option<String> {
+    let start = reference.find("DeviceID=\"")? + "DeviceID=\"".len();
+    let mut value = String::new();
+    let mut chars = reference[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?),
+            '"' => return Some(value),
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
 #[cfg(not(target_os = "windows"))]
 fn discover_windows_drives() -> anyhow::Result<Vec<Drive>> {
     Ok(Vec::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_association_key() {
+        let disk = r#"\\PC\root\cimv2:Win32_DiskDrive.DeviceID="\\\\.\\PHYSICALDRIVE1""#;
+        let part = r#"\\PC\root\cimv2:Win32_DiskPartition.DeviceID="Disk #1, Partition #0""#;
+        let letter = r#"\\PC\root\cimv2:Win32_LogicalDisk.DeviceID="E:""#;
+        assert_eq!(association_key(disk).as_deref(), Some(r"\\.\PHYSICALDRIVE1"));
+        assert_eq!(association_key(part).as_deref(), Some("Disk #1, Partition #0"));
+        assert_eq!(association_key(letter).as_deref(), Some("E:"));
+        assert_eq!(association_key("Win32_LogicalDisk"), None);
+    }
+}
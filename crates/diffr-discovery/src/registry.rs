@@ -0,0 +1,98 @@
+use std::sync::{Mutex, OnceLock};
+
+use crate::fake::{FakeDiscovery, FAKE_DRIVES_ENV};
+use crate::DriveDiscovery;
+
+/// Builds a discovery backend on demand.
+pub type Factory = Box<dyn Fn() -> Box<dyn DriveDiscovery> + Send + Sync>;
+
+/// Name of the built-in backend for the current OS.
+pub const NATIVE: &str = "native";
+
+fn registry() -> &'static Mutex<Vec<(String, Factory)>> {
+    static REGISTRY: OnceLock<Mutex<Vec<(String, Factory)>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtin: Vec<(String, Factory)> = vec![
+            (NATIVE.to_string(), Box::new(crate::platform::native_discovery)),
+            ("mtp".to_string(), Box::new(|| Box::new(crate::mtp::MtpDiscovery))),
+            (
+                "fake".to_string(),
+                Box::new(|| {
+                    let fixture = std::env::var_os(FAKE_DRIVES_ENV).unwrap_or_default();
+                    Box::new(FakeDiscovery::new(fixture))
+                }),
+            ),
+        ];
+        Mutex::new(builtin)
+    })
+}
+
+/// Register a discovery backend under `name`, replacing any existing one,
+/// so downstream crates can add sources such as iSCSI or MTP devices.
+pub fn register<F>(name: &str, factory: F)
+where
+    F: Fn() -> Box<dyn DriveDiscovery> + Send + Sync + 'static,
+{
+    let mut backends = registry().lock().unwrap();
+    backends.retain(|(n, _)| n != name);
+    backends.push((name.to_string(), Box::new(factory)));
+}
+
+/// Names of all registered backends, built-ins first.
+pub fn names() -> Vec<String> {
+    registry().lock().unwrap().iter().map(|(n, _)| n.clone()).collect()
+}
+
+/// Create the backend registered as `name`.
+pub fn create(name: &str) -> anyhow::Result<Box<dyn DriveDiscovery>> {
+    let backends = registry().lock().unwrap();
+    match backends.iter().find(|(n, _)| n == name) {
+        Some((_, factory)) => Ok(factory()),
+        None => anyhow::bail!(
+            "unknown discovery backend '{}' (available: {})",
+            name,
+            backends
+                .iter()
+                .map(|(n, _)| n.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// The backend to use given the configured name: `DIFFR_FAKE_DRIVES` wins,
+/// then `name`, then the native backend.
+pub fn select(name: Option<&str>) -> anyhow::Result<Box<dyn DriveDiscovery>> {
+    if std::env::var_os(FAKE_DRIVES_ENV).is_some() {
+        return create("fake");
+    }
+    create(name.unwrap_or(NATIVE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::{Drive, DriveIdentity};
+
+    struct OneDrive;
+
+    impl DriveDiscovery for OneDrive {
+        fn discover_drives(&self) -> anyhow::Result<Vec<Drive>> {
+            Ok(vec![Drive::new(
+                DriveIdentity::new_hardware("ISCSI-1".into()),
+                "/mnt/iscsi".into(),
+            )])
+        }
+    }
+
+    #[test]
+    fn test_register_backend() {
+        register("iscsi", || Box::new(OneDrive));
+        assert!(names().iter().any(|n| n == "iscsi"));
+        let drives = create("iscsi").unwrap().discover_drives().unwrap();
+        assert_eq!(drives[0].identity.identity_string(), "ISCSI-1");
+
+        let err = create("nope").err().unwrap().to_string();
+        assert!(err.contains("native") && err.contains("iscsi"));
+    }
+}
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use diffr_core::config::HashAlgorithm;
 use diffr_core::models::drive::DriveId;
 use diffr_core::models::file_entry::HashCacheEntry;
 use diffr_db::ops;
@@ -18,32 +19,89 @@ impl<'a> HashCache<'a> {
         Self { conn, drive_id }
     }
 
-    /// Get or compute the hash for a file. Uses cache if (size, mtime) match.
+    /// Get or compute the hash for a file, plus a `strong` verification hash
+    /// if asked for. Uses cache if (size, mtime) match and it has the hashes
+    /// asked for, found by path or, failing that, by `file_id` so a renamed
+    /// file keeps its hash.
     pub fn get_or_hash(
         &self,
         root: &Path,
         rel_path: &Path,
         size: u64,
         mtime: DateTime<Utc>,
-        include_sha256: bool,
+        file_id: Option<&str>,
+        strong: Option<HashAlgorithm>,
     ) -> anyhow::Result<hasher::HashResult> {
-        let rel_str = rel_path.to_string_lossy();
-
         // Check cache
-        if let Some(cached) =
-            ops::get_hash_cache_entry(self.conn, &self.drive_id, &rel_str)?
-        {
-            if cached.is_valid(size, mtime) {
+        if let Some(cached) = ops::get_hash_cache_entry(self.conn, &self.drive_id, rel_path)? {
+            if usable(&cached, size, mtime, strong) {
                 return Ok(hasher::HashResult {
                     xxh3_hex: cached.xxh3_hash,
                     sha256_hex: cached.sha256_hash,
+                    blake3_hex: cached.blake3_hash,
                 });
             }
         }
 
+        // Same file under another name: reuse its hash and cache it here too.
+        // The old path's entry is pruned once it drops out of the catalog.
+        if let Some(id) = file_id {
+            if let Some(cached) = ops::get_hash_cache_by_file_id(self.conn, &self.drive_id, id)? {
+                if usable(&cached, size, mtime, strong) {
+                    tracing::debug!(
+                        "hash cache: {} renamed to {}",
+                        cached.rel_path.display(),
+                        rel_path.display()
+                    );
+                    ops::upsert_hash_cache(
+                        self.conn,
+                        &HashCacheEntry {
+                            rel_path: rel_path.to_path_buf(),
+                            cached_at: Utc::now(),
+                            ..cached.clone()
+                        },
+                    )?;
+                    return Ok(hasher::HashResult {
+                        xxh3_hex: cached.xxh3_hash,
+                        sha256_hex: cached.sha256_hash,
+                        blake3_hex: cached.blake3_hash,
+                    });
+                }
+            }
+        }
+
         // Cache miss — compute hash
+        self.hash_and_store(root, rel_path, size, mtime, file_id, strong)
+    }
+
+    /// Hash a file whatever the cache holds for it, replacing the entry.
+    /// Returns the hashes and the entry they replaced, so a stale one can
+    /// be told apart.
+    pub fn rehash(
+        &self,
+        root: &Path,
+        rel_path: &Path,
+        size: u64,
+        mtime: DateTime<Utc>,
+        file_id: Option<&str>,
+        strong: Option<HashAlgorithm>,
+    ) -> anyhow::Result<(hasher::HashResult, Option<HashCacheEntry>)> {
+        let previous = ops::get_hash_cache_entry(self.conn, &self.drive_id, rel_path)?;
+        let result = self.hash_and_store(root, rel_path, size, mtime, file_id, strong)?;
+        Ok((result, previous))
+    }
+
+    fn hash_and_store(
+        &self,
+        root: &Path,
+        rel_path: &Path,
+        size: u64,
+        mtime: DateTime<Utc>,
+        file_id: Option<&str>,
+        strong: Option<HashAlgorithm>,
+    ) -> anyhow::Result<hasher::HashResult> {
         let full_path = root.join(rel_path);
-        let result = hasher::hash_file(&full_path, include_sha256)?;
+        let result = hasher::hash_file(&full_path, strong)?;
 
         // Store in cache
         let cache_entry = HashCacheEntry {
@@ -51,8 +109,10 @@ impl<'a> HashCache<'a> {
             drive_id: self.drive_id.clone(),
             size,
             mtime,
+            file_id: file_id.map(String::from),
             xxh3_hash: result.xxh3_hex.clone(),
             sha256_hash: result.sha256_hex.clone(),
+            blake3_hash: result.blake3_hex.clone(),
             cached_at: Utc::now(),
         };
         ops::upsert_hash_cache(self.conn, &cache_entry)?;
@@ -61,6 +121,79 @@ impl<'a> HashCache<'a> {
     }
 }
 
+fn usable(cached: &HashCacheEntry, size: u64, mtime: DateTime<Utc>, strong: Option<HashAlgorithm>) -> bool {
+    cached.is_valid(size, mtime) && strong.is_none_or(|a| cached.strong_hash(a).is_some())
+}
+
+/// An ID for the file at `path` that stays the same when it's renamed or
+/// moved within its filesystem: the inode on Unix, the NTFS file index on
+/// Windows. The device or volume number is left out: the cache is already
+/// per drive, and device numbers change when removable drives are remounted.
+pub fn file_id(path: &Path, metadata: &std::fs::Metadata) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = path;
+        Some(metadata.ino().to_string())
+    }
+    #[cfg(windows)]
+    {
+        let _ = metadata;
+        let info = file_information(path)?;
+        Some((((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64).to_string())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (path, metadata);
+        None
+    }
+}
+
+/// Whether `a` and `b` are one file reached by two paths, as through
+/// overlapping mounts, a junction or a `subst` drive. Unlike [`file_id`],
+/// the device or volume is compared too. False if either can't be read.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(windows)]
+    {
+        match (file_information(a), file_information(b)) {
+            (Some(a), Some(b)) => {
+                a.dwVolumeSerialNumber == b.dwVolumeSerialNumber
+                    && a.nFileIndexHigh == b.nFileIndexHigh
+                    && a.nFileIndexLow == b.nFileIndexLow
+            }
+            _ => false,
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (a, b);
+        false
+    }
+}
+
+#[cfg(windows)]
+fn file_information(path: &Path) -> Option<windows_sys::Win32::Storage::FileSystem::BY_HANDLE_FILE_INFORMATION> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+    let file = std::fs::File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    // SAFETY: the handle is open for the duration of the call and `info`
+    // is a valid out-pointer.
+    let ok = unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) };
+    if ok == 0 {
+        return None;
+    }
+    Some(info)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,14 +216,65 @@ mod tests {
 
         // First call: cache miss
         let r1 = cache
-            .get_or_hash(dir.path(), Path::new("test.txt"), 5, mtime, false)
+            .get_or_hash(dir.path(), Path::new("test.txt"), 5, mtime, None, None)
             .unwrap();
 
         // Second call: cache hit (same size and mtime)
         let r2 = cache
-            .get_or_hash(dir.path(), Path::new("test.txt"), 5, mtime, false)
+            .get_or_hash(dir.path(), Path::new("test.txt"), 5, mtime, None, None)
+            .unwrap();
+
+        assert_eq!(r1.xxh3_hex, r2.xxh3_hex);
+    }
+
+    #[test]
+    fn test_hash_cache_survives_rename() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/tmp/test".into());
+        diffr_db::ops::insert_drive(&conn, &drive).unwrap();
+        let cache = HashCache::new(&conn, drive.id.clone());
+
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("old.bin"), "hello").unwrap();
+        let mtime = Utc::now();
+        let r1 = cache
+            .get_or_hash(dir.path(), Path::new("old.bin"), 5, mtime, Some("42"), None)
             .unwrap();
 
+        // The new path misses but the file ID hits. Nothing is on disk at
+        // new.bin, so hashing it would fail.
+        let r2 = cache
+            .get_or_hash(dir.path(), Path::new("new.bin"), 5, mtime, Some("42"), None)
+            .unwrap();
         assert_eq!(r1.xxh3_hex, r2.xxh3_hex);
+        let moved = ops::get_hash_cache_entry(&conn, &drive.id, Path::new("new.bin")).unwrap().unwrap();
+        assert_eq!(moved.file_id.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_rehash_replaces_stale_entry() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), "/tmp/test".into());
+        diffr_db::ops::insert_drive(&conn, &drive).unwrap();
+        let cache = HashCache::new(&conn, drive.id.clone());
+
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        let mtime = Utc::now();
+        let stale = cache
+            .get_or_hash(dir.path(), Path::new("a.txt"), 5, mtime, None, None)
+            .unwrap();
+
+        // Rewritten without its size or time changing, so the cache misses it.
+        std::fs::write(dir.path().join("a.txt"), "world").unwrap();
+        let (fresh, previous) = cache
+            .rehash(dir.path(), Path::new("a.txt"), 5, mtime, None, Some(HashAlgorithm::Sha256))
+            .unwrap();
+        assert_ne!(fresh.xxh3_hex, stale.xxh3_hex);
+        assert_eq!(previous.unwrap().xxh3_hash, stale.xxh3_hex);
+        let hit = cache
+            .get_or_hash(dir.path(), Path::new("a.txt"), 5, mtime, None, Some(HashAlgorithm::Sha256))
+            .unwrap();
+        assert_eq!((hit.xxh3_hex, hit.sha256_hex), (fresh.xxh3_hex, fresh.sha256_hex));
     }
 }
@@ -0,0 +1,157 @@
+use diffr_core::models::sync_state::SkipReason;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file or directory a scan couldn't read.
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    /// Relative to the scan root; empty if the error had no path.
+    pub rel_path: PathBuf,
+    pub kind: ScanErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rel_path.as_os_str().is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.rel_path.display(), self.message)
+        }
+    }
+}
+
+/// What went wrong reading an entry, so thousands of the same error can be
+/// counted instead of listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScanErrorKind {
+    PermissionDenied,
+    /// Removed or renamed between being listed and being read.
+    Vanished,
+    /// Held open or locked by another program.
+    Locked,
+    Other,
+}
+
+impl ScanErrorKind {
+    pub fn of(e: &io::Error) -> Self {
+        // Windows' sharing and lock violations.
+        #[cfg(windows)]
+        if matches!(e.raw_os_error(), Some(32 | 33)) {
+            return ScanErrorKind::Locked;
+        }
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => ScanErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => ScanErrorKind::Vanished,
+            io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock => ScanErrorKind::Locked,
+            _ => ScanErrorKind::Other,
+        }
+    }
+
+    /// Classify an error from hashing or copying, which may wrap an I/O
+    /// error.
+    pub fn of_any(e: &anyhow::Error) -> Self {
+        e.chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(Self::of)
+            .unwrap_or(ScanErrorKind::Other)
+    }
+
+    /// How a file that failed this way shows in the skip report.
+    pub fn skip_reason(self) -> SkipReason {
+        match self {
+            ScanErrorKind::Locked => SkipReason::Locked,
+            _ => SkipReason::Unreadable,
+        }
+    }
+}
+
+impl std::fmt::Display for ScanErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanErrorKind::PermissionDenied => write!(f, "permission_denied"),
+            ScanErrorKind::Vanished => write!(f, "vanished"),
+            ScanErrorKind::Locked => write!(f, "locked"),
+            ScanErrorKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Folders each OS keeps from ordinary users, and what to do about them.
+/// Matched against consecutive path components, ignoring case.
+const PROTECTED: &[(&str, &[&str], &str)] = &[
+    ("macos", &["Library", "Mail"], MACOS_PRIVACY),
+    ("macos", &["Library", "Messages"], MACOS_PRIVACY),
+    ("macos", &["Library", "Safari"], MACOS_PRIVACY),
+    ("macos", &["Library", "Calendars"], MACOS_PRIVACY),
+    ("macos", &["Library", "Cookies"], MACOS_PRIVACY),
+    ("macos", &["Library", "Application Support", "AddressBook"], MACOS_PRIVACY),
+    ("macos", &["Library", "Application Support", "com.apple.TCC"], MACOS_PRIVACY),
+    ("macos", &[".Spotlight-V100"], VOLUME_METADATA),
+    ("macos", &[".fseventsd"], VOLUME_METADATA),
+    ("macos", &[".Trashes"], VOLUME_METADATA),
+    ("windows", &["System Volume Information"], VOLUME_METADATA),
+    ("windows", &["$Recycle.Bin"], VOLUME_METADATA),
+    ("windows", &["Program Files", "WindowsApps"], WINDOWS_ADMIN),
+    ("windows", &["ProgramData", "Microsoft", "Windows Defender"], WINDOWS_ADMIN),
+    ("windows", &["Windows", "CSC"], WINDOWS_ADMIN),
+    ("windows", &["Config.Msi"], WINDOWS_ADMIN),
+    ("windows", &["Recovery"], WINDOWS_ADMIN),
+    ("linux", &["lost+found"], VOLUME_METADATA),
+];
+
+const MACOS_PRIVACY: &str = "macOS privacy protection; give your terminal Full Disk Access \
+    (System Settings > Privacy & Security > Full Disk Access), or add the folder to .diffrignore";
+const WINDOWS_ADMIN: &str = "protected by Windows; run diffr from an Administrator prompt, \
+    or add the folder to .diffrignore";
+const VOLUME_METADATA: &str = "the file system's own metadata, not worth syncing; add it to .diffrignore";
+
+/// If a permission-denied `rel_path` is inside a folder this OS is known to
+/// protect, return that folder and what to do about it.
+pub fn permission_hint(rel_path: &Path) -> Option<(PathBuf, &'static str)> {
+    hint_for(rel_path, std::env::consts::OS)
+}
+
+fn hint_for(rel_path: &Path, os: &str) -> Option<(PathBuf, &'static str)> {
+    let components: Vec<String> = rel_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+    for (_, folder, hint) in PROTECTED.iter().filter(|(o, _, _)| *o == os) {
+        let folder: Vec<String> = folder.iter().map(|f| f.to_lowercase()).collect();
+        if let Some(start) = components.windows(folder.len()).position(|w| w == folder.as_slice()) {
+            let prefix = rel_path.components().take(start + folder.len()).collect();
+            return Some((prefix, hint));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_hints() {
+        let (folder, hint) = hint_for(Path::new("jo/library/Mail/V10/inbox"), "macos").unwrap();
+        assert_eq!(folder, PathBuf::from("jo/library/Mail"));
+        assert_eq!(hint, MACOS_PRIVACY);
+        assert!(hint_for(Path::new("System Volume Information/x"), "windows").is_some());
+        assert!(hint_for(Path::new("System Volume Information/x"), "linux").is_none());
+        assert!(hint_for(Path::new("Documents/Mail"), "macos").is_none());
+
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(ScanErrorKind::of(&denied), ScanErrorKind::PermissionDenied);
+        assert_eq!(ScanErrorKind::of_any(&anyhow::Error::from(denied)), ScanErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_locked_files_are_reported_as_locked() {
+        let busy = io::Error::from(io::ErrorKind::ResourceBusy);
+        assert_eq!(ScanErrorKind::of(&busy), ScanErrorKind::Locked);
+        let copying = anyhow::Error::from(busy).context("copying a.txt");
+        assert_eq!(ScanErrorKind::of_any(&copying), ScanErrorKind::Locked);
+        assert_eq!(ScanErrorKind::of_any(&copying).skip_reason(), SkipReason::Locked);
+        assert_eq!(ScanErrorKind::Vanished.skip_reason(), SkipReason::Unreadable);
+    }
+}
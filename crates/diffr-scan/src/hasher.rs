@@ -1,14 +1,26 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use diffr_core::cancel::{Cancelled, CancellationToken};
+use diffr_core::config::HashAlgorithm;
+use diffr_core::power::{Pacer, PowerGuard};
+use diffr_core::progress::{Progress, ProgressEvent};
+use diffr_core::task::{self, Task};
 use sha2::{Digest, Sha256};
-use std::io::Read;
-use std::path::Path;
-use xxhash_rust::xxh3::xxh3_64;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+/// Bytes read from each of the start, middle and end of a sampled file.
+pub const SAMPLE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Files up to this size are hashed in full even in sampled mode, since
+/// the samples would cover most of them anyway.
+pub const SAMPLED_MIN_SIZE: u64 = 4 * SAMPLE_BYTES;
 
 /// Hash result containing both fast and verification hashes.
 #[derive(Debug, Clone)]
 pub struct HashResult {
     pub xxh3_hex: String,
     pub sha256_hex: Option<String>,
+    pub blake3_hex: Option<String>,
 }
 
 /// Compute the XXH3-64 hash of a file.
@@ -18,6 +30,27 @@ pub fn xxh3_file(path: &Path) -> anyhow::Result<String> {
     Ok(format!("{:016x}", hash))
 }
 
+/// Compute the XXH3-64 hash of a file's size and of `SAMPLE_BYTES` from
+/// its start, middle and end. An edit between the samples that keeps the
+/// size goes unnoticed; use a full hash to rule that out.
+pub fn sampled_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = Xxh3::new();
+    hasher.update(&size.to_le_bytes());
+
+    let mut buf = vec![0u8; SAMPLE_BYTES as usize];
+    let middle = (size / 2).saturating_sub(SAMPLE_BYTES / 2);
+    let end = size.saturating_sub(SAMPLE_BYTES);
+    for offset in [0, middle, end] {
+        file.seek(SeekFrom::Start(offset))?;
+        let n = (SAMPLE_BYTES.min(size - offset)) as usize;
+        file.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
 /// Compute the SHA-256 hash of a file.
 pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
     let mut file = std::fs::File::open(path)?;
@@ -34,69 +67,136 @@ pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
     Ok(format!("{:x}", result))
 }
 
-/// Compute both XXH3 and optionally SHA-256 hash of a file.
-pub fn hash_file(path: &Path, include_sha256: bool) -> anyhow::Result<HashResult> {
+/// Compute the BLAKE3 hash of a file, memory-mapped and spread across
+/// threads for large files.
+pub fn blake3_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_mmap_rayon(path)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// A verification hash computed a chunk at a time, for hashing data as
+/// it's copied.
+pub enum StreamHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl StreamHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => StreamHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => StreamHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            StreamHasher::Sha256(h) => h.update(data),
+            StreamHasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// The hash, in the same form as [`strong_hash_file`].
+    pub fn finish(self) -> String {
+        match self {
+            StreamHasher::Sha256(h) => format!("{:x}", h.finalize()),
+            StreamHasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Compute a file's verification hash with `algorithm`.
+pub fn strong_hash_file(path: &Path, algorithm: HashAlgorithm) -> anyhow::Result<String> {
+    match algorithm {
+        HashAlgorithm::Sha256 => sha256_file(path),
+        HashAlgorithm::Blake3 => blake3_file(path),
+    }
+}
+
+/// Compute the XXH3 hash of a file and, optionally, a verification hash.
+pub fn hash_file(path: &Path, strong: Option<HashAlgorithm>) -> anyhow::Result<HashResult> {
     let data = std::fs::read(path)?;
     let xxh3_hex = format!("{:016x}", xxh3_64(&data));
 
-    let sha256_hex = if include_sha256 {
-        let mut hasher = Sha256::new();
-        hasher.update(&data);
-        Some(format!("{:x}", hasher.finalize()))
-    } else {
-        None
-    };
+    let (mut sha256_hex, mut blake3_hex) = (None, None);
+    match strong {
+        Some(HashAlgorithm::Sha256) => {
+            let mut hasher = Sha256::new();
+            hasher.update(&data);
+            sha256_hex = Some(format!("{:x}", hasher.finalize()));
+        }
+        Some(HashAlgorithm::Blake3) => {
+            blake3_hex = Some(blake3::hash(&data).to_hex().to_string());
+        }
+        None => {}
+    }
 
     Ok(HashResult {
         xxh3_hex,
         sha256_hex,
+        blake3_hex,
     })
 }
 
-/// Bulk hash a list of files with optional progress display.
+/// Bulk hash a list of files, reporting to `progress` and holding back
+/// under `power`. Once `cancel` is cancelled, the files not yet hashed get
+/// a [`Cancelled`] error.
 pub fn hash_files_bulk(
     root: &Path,
     rel_paths: &[&Path],
-    include_sha256: bool,
-    show_progress: bool,
+    strong: Option<HashAlgorithm>,
+    progress: &Progress,
+    cancel: &CancellationToken,
+    power: Option<PowerGuard>,
 ) -> Vec<(usize, anyhow::Result<HashResult>)> {
-    let pb = if show_progress {
-        let pb = ProgressBar::new(rel_paths.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files hashed ({eta})")
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        Some(pb)
-    } else {
-        None
-    };
+    let files = rel_paths.len() as u64;
+    progress.event(ProgressEvent::HashStarted { files });
 
+    let mut pacer = Pacer::new(power);
     let results: Vec<_> = rel_paths
         .iter()
         .enumerate()
         .map(|(i, rel_path)| {
-            let full_path = root.join(rel_path);
-            let result = hash_file(&full_path, include_sha256);
-            if let Some(ref pb) = pb {
-                pb.inc(1);
+            pacer.wait(cancel, |reason| tracing::info!("pausing hashing: {}", reason));
+            if cancel.is_cancelled() {
+                return (i, Err(Cancelled.into()));
             }
+            let full_path = root.join(rel_path);
+            let started = std::time::Instant::now();
+            let result = hash_file(&full_path, strong);
+            pacer.rest(started.elapsed(), cancel);
+            progress.event(ProgressEvent::HashProgress { done: i as u64 + 1, files });
             (i, result)
         })
         .collect();
 
-    if let Some(pb) = pb {
-        pb.finish_with_message("Hashing complete");
-    }
+    progress.event(ProgressEvent::HashFinished { files });
 
     results
 }
 
+/// [`hash_files_bulk`] on a thread of its own.
+pub fn hash_files_async(
+    root: PathBuf,
+    rel_paths: Vec<PathBuf>,
+    strong: Option<HashAlgorithm>,
+    progress: Progress,
+    cancel: CancellationToken,
+    power: Option<PowerGuard>,
+) -> Task<Vec<(usize, anyhow::Result<HashResult>)>> {
+    task::spawn(move || {
+        let rel_paths: Vec<&Path> = rel_paths.iter().map(PathBuf::as_path).collect();
+        hash_files_bulk(&root, &rel_paths, strong, &progress, &cancel, power)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use tempfile::{NamedTempFile, TempDir};
 
     #[test]
     fn test_xxh3_deterministic() {
@@ -108,6 +208,28 @@ mod tests {
         assert_eq!(h1.len(), 16); // 64-bit = 16 hex chars
     }
 
+    #[test]
+    fn test_sampled_hash() {
+        let size = (SAMPLED_MIN_SIZE + SAMPLE_BYTES) as usize;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("video.mkv");
+        let mut data = vec![0u8; size];
+        std::fs::write(&path, &data).unwrap();
+        let original = sampled_file(&path).unwrap();
+
+        // An edit between the start and middle samples isn't seen...
+        data[SAMPLE_BYTES as usize + 10] = 1;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(sampled_file(&path).unwrap(), original);
+
+        // ...but one in the middle, or a change of size, is.
+        data[size / 2] = 1;
+        std::fs::write(&path, &data).unwrap();
+        assert_ne!(sampled_file(&path).unwrap(), original);
+        std::fs::write(&path, &data[..size - 1]).unwrap();
+        assert_ne!(sampled_file(&path).unwrap(), original);
+    }
+
     #[test]
     fn test_sha256_known() {
         let mut f = NamedTempFile::new().unwrap();
@@ -123,8 +245,22 @@ mod tests {
     fn test_hash_file_both() {
         let mut f = NamedTempFile::new().unwrap();
         std::io::Write::write_all(&mut f, b"test data").unwrap();
-        let result = hash_file(f.path(), true).unwrap();
+        let result = hash_file(f.path(), Some(HashAlgorithm::Sha256)).unwrap();
         assert!(!result.xxh3_hex.is_empty());
         assert!(result.sha256_hex.is_some());
+        assert!(result.blake3_hex.is_none());
+    }
+
+    #[test]
+    fn test_blake3_known() {
+        let mut f = NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut f, b"hello world").unwrap();
+        let h = strong_hash_file(f.path(), HashAlgorithm::Blake3).unwrap();
+        assert_eq!(
+            h,
+            "d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24"
+        );
+        let result = hash_file(f.path(), Some(HashAlgorithm::Blake3)).unwrap();
+        assert_eq!(result.blake3_hex.as_deref(), Some(h.as_str()));
     }
 }
@@ -1,3 +1,4 @@
 pub mod cache;
+pub mod errors;
 pub mod hasher;
 pub mod scanner;
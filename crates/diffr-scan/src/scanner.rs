@@ -1,36 +1,107 @@
 use chrono::{DateTime, Utc};
+use diffr_core::cancel::CancellationToken;
+use diffr_core::config::{HashAlgorithm, HashMode, RepoConfig};
+use diffr_core::models::cluster::NestedRepos;
 use diffr_core::models::drive::DriveId;
-use diffr_core::models::file_entry::FileEntry;
-use indicatif::{ProgressBar, ProgressStyle};
+use diffr_core::models::file_entry::{FileEntry, FilePermissions};
+use diffr_core::models::sync_state::{SkipReason, SkippedFile};
+use diffr_core::power::{Pacer, PowerGuard};
+use diffr_core::progress::{Progress, ProgressEvent};
+use diffr_core::task::{self, Task};
+use diffr_discovery::mtp::MtpStore;
 use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::fs;
 use std::io::{self, BufRead};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
+use crate::cache::{self, HashCache};
+use crate::errors::{ScanError, ScanErrorKind};
+use crate::hasher;
+
+/// How often a cached scan commits the hashes it has taken so far and
+/// saves where it got to. An interrupted scan loses at most this much: run
+/// again, it picks up after the last entry it saved.
+const CHECKPOINT_EVERY: Duration = Duration::from_secs(10);
+
+/// How old a checkpoint can be and still be resumed from. Past this, what
+/// it found is too likely to have changed and the scan starts over.
+const RESUME_WITHIN: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Called before each entry a scan walks with the last entry it finished
+/// and what it has found since the last checkpoint. Returns whether that
+/// was saved; an error stops the scan.
+type Checkpoint<'a> = &'a mut dyn FnMut(&Path, &[FileEntry], &[SkippedFile]) -> anyhow::Result<bool>;
+
 /// Configuration for a scan operation.
 pub struct ScanConfig {
     /// Root directory to scan.
     pub root: PathBuf,
     /// Drive ID to associate with entries.
     pub drive_id: DriveId,
-    /// Whether to follow symlinks.
+    /// Whether to follow symlinks. Links that loop back to one of their own
+    /// ancestors are skipped.
     pub follow_symlinks: bool,
-    /// Whether to show a progress bar.
-    pub show_progress: bool,
+    /// Skip dotfiles (and, on Windows, files with the hidden attribute).
+    /// `.diffrignore` is always scanned so patterns keep syncing.
+    pub skip_hidden: bool,
+    /// Skip files with the Windows system attribute. No effect elsewhere.
+    pub skip_system: bool,
+    /// Which nested repos to treat as boundaries and not descend into.
+    pub nested_repos: NestedRepos,
+    /// Ignore patterns added to those in `.diffrignore` and the repo's
+    /// settings, such as a cluster's `ignore`.
+    pub ignore: Vec<String>,
+    /// Stop scanning once this many errors have been hit.
+    pub max_errors: Option<usize>,
+    /// Where to report how many entries have been scanned.
+    pub progress: Progress,
+    /// Hash large files in full even in repos set to `hash = "sampled"`,
+    /// for runs that verify.
+    pub full_hashes: bool,
+    /// Also take this verification hash of every file hashed in full, so
+    /// the diff compares files by it.
+    pub strong_hash: Option<HashAlgorithm>,
+    /// Stop at the next entry once cancelled.
+    pub cancel: CancellationToken,
+    /// Battery and heat limits to hash files under.
+    pub power: Option<PowerGuard>,
 }
 
 /// Result of scanning a directory tree.
 pub struct ScanResult {
+    /// What was found, in `Path` order: the walk is depth-first with each
+    /// directory's entries sorted by name, so `a/b` comes before `a.txt`.
+    /// A resumed scan's entries keep that order.
     pub entries: Vec<FileEntry>,
     pub total_files: u64,
     pub total_dirs: u64,
     pub total_bytes: u64,
-    pub errors: Vec<String>,
+    pub errors: Vec<ScanError>,
+    /// The scan stopped at `max_errors` and didn't see everything.
+    pub stopped_early: bool,
+    /// The scan was cancelled and didn't see everything.
+    pub cancelled: bool,
+    /// Followed symlinks that pointed back to an ancestor directory and were
+    /// not descended into.
+    pub symlink_cycles: Vec<PathBuf>,
+    /// Entries skipped by `skip_hidden`; a skipped directory counts once.
+    pub skipped_hidden: u64,
+    /// Entries skipped by `skip_system`.
+    pub skipped_system: u64,
+    /// Nested repo roots not descended into, per `nested_repos`.
+    pub nested_repos: Vec<PathBuf>,
+    /// Everything above that the scan left out, and files it couldn't read,
+    /// for the sync's skip report. Only the top of an ignored directory is
+    /// listed, and `.diffr` isn't.
+    pub skipped: Vec<SkippedFile>,
 }
 
-/// Load ignore patterns from `.diffrignore` file.
-fn load_ignore_patterns(root: &Path) -> HashSet<String> {
+/// Load ignore patterns from `.diffrignore`, plus any extra patterns from
+/// the repo's settings or the scan.
+fn load_ignore_patterns(root: &Path, extra: &[String]) -> HashSet<String> {
     let ignore_path = root.join(".diffrignore");
     let mut patterns = HashSet::new();
 
@@ -39,61 +110,377 @@ fn load_ignore_patterns(root: &Path) -> HashSet<String> {
 
     if let Ok(file) = fs::File::open(&ignore_path) {
         let reader = io::BufReader::new(file);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                let trimmed = line.trim();
-                if !trimmed.is_empty() && !trimmed.starts_with('#') {
-                    patterns.insert(trimmed.to_string());
-                }
-            }
+        for line in reader.lines().map_while(Result::ok) {
+            insert_pattern(&mut patterns, &line);
         }
     }
+    for pattern in extra {
+        insert_pattern(&mut patterns, pattern);
+    }
 
     patterns
 }
 
-/// Check if a path component matches any ignore pattern.
-fn should_ignore(rel_path: &Path, patterns: &HashSet<String>) -> bool {
-    for component in rel_path.components() {
-        let name = component.as_os_str().to_string_lossy();
-        if patterns.contains(name.as_ref()) {
-            return true;
+/// Why an entry is excluded by the hidden/system policy, if it is.
+#[derive(Debug, PartialEq, Eq)]
+enum HiddenKind {
+    Hidden,
+    /// Only detected on Windows.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    System,
+}
+
+fn hidden_kind(entry: &walkdir::DirEntry, metadata: &fs::Metadata, config: &ScanConfig) -> Option<HiddenKind> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+        let attrs = metadata.file_attributes();
+        if config.skip_system && attrs & FILE_ATTRIBUTE_SYSTEM != 0 {
+            return Some(HiddenKind::System);
+        }
+        if config.skip_hidden && attrs & FILE_ATTRIBUTE_HIDDEN != 0 {
+            return Some(HiddenKind::Hidden);
         }
     }
+    #[cfg(not(windows))]
+    let _ = metadata;
+
+    let name = entry.file_name().to_string_lossy();
+    if config.skip_hidden && name.starts_with('.') && name != ".diffrignore" {
+        return Some(HiddenKind::Hidden);
+    }
+    None
+}
+
+/// Whether `dir` is the root of a repo that `mode` stops at.
+fn is_nested_repo(dir: &Path, mode: NestedRepos) -> bool {
+    match mode {
+        NestedRepos::Descend => false,
+        NestedRepos::SkipDiffr => RepoConfig::path_in(dir).exists(),
+        NestedRepos::SkipAll => RepoConfig::path_in(dir).exists() || dir.join(".git").exists(),
+    }
+}
+
+fn insert_pattern(patterns: &mut HashSet<String>, line: &str) {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return;
+    }
+    // A trailing slash (`build/`) names a directory; the component match
+    // below treats it the same as `build`.
+    let pattern = trimmed.trim_end_matches('/');
+    if !pattern.is_empty() {
+        patterns.insert(pattern.trim_start_matches('/').to_string());
+    }
+}
+
+/// Check if a path component, or the full relative path, matches any ignore
+/// pattern. Patterns may use `*` and `?` wildcards.
+fn should_ignore(rel_path: &Path, patterns: &HashSet<String>) -> bool {
+    patterns.iter().any(|pattern| matches_pattern(rel_path, pattern))
+}
+
+/// A file's modification time as the catalog and hash cache store it, or
+/// now if the filesystem doesn't keep one.
+pub fn modified_time(metadata: &std::fs::Metadata) -> DateTime<Utc> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| {
+            let duration = t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            DateTime::from_timestamp(duration.as_secs() as i64, duration.subsec_nanos())
+        })
+        .unwrap_or_else(Utc::now)
+}
+
+/// Whether `rel_path` matches a `.diffrignore`-style pattern: one with a
+/// `/` against the whole path, and any other against each component.
+pub fn matches_pattern(rel_path: &Path, pattern: &str) -> bool {
+    if pattern.contains('/') {
+        let rel_str = rel_path.to_string_lossy().replace('\\', "/");
+        return glob_match(pattern.as_bytes(), rel_str.as_bytes());
+    }
+    rel_path
+        .components()
+        .any(|c| glob_match(pattern.as_bytes(), c.as_os_str().to_string_lossy().as_bytes()))
+}
 
-    // Also check full relative path
-    let rel_str = rel_path.to_string_lossy();
-    patterns.contains(rel_str.as_ref())
+/// Match `text` against a pattern where `*` matches any run of characters
+/// and `?` matches exactly one.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
 /// Scan a directory tree and return all file entries.
 pub fn scan_directory(config: &ScanConfig) -> anyhow::Result<ScanResult> {
-    let ignore_patterns = load_ignore_patterns(&config.root);
-
-    let pb = if config.show_progress {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} [{elapsed_precise}] {msg}")
-                .unwrap(),
+    scan(config, None, None, &mut |_, _, _| Ok(false))
+}
+
+/// Like [`scan_directory`], but reuse hashes from the drive's hash cache
+/// for files whose size and mtime haven't changed, and cache new ones.
+/// `config.drive_id` must be a drive in the database.
+///
+/// Every [`CHECKPOINT_EVERY`] the new hashes are committed and what the
+/// scan has found is saved. A scan that's interrupted resumes after the
+/// last entry it saved, skipping the subtrees it had finished, as long as
+/// that was within [`RESUME_WITHIN`].
+pub fn scan_directory_cached(config: &ScanConfig, conn: &rusqlite::Connection) -> anyhow::Result<ScanResult> {
+    scan_checkpointed(config, conn, CHECKPOINT_EVERY)
+}
+
+fn scan_checkpointed(config: &ScanConfig, conn: &rusqlite::Connection, every: Duration) -> anyhow::Result<ScanResult> {
+    let resume = diffr_db::ops::load_scan_checkpoint(conn, &config.drive_id, &config.root)?
+        .filter(|c| (Utc::now() - c.saved_at).to_std().is_ok_and(|age| age < RESUME_WITHIN));
+    if let Some(checkpoint) = &resume {
+        tracing::info!(
+            "resuming the scan of {} after {}",
+            config.root.display(),
+            checkpoint.cursor.display()
         );
-        pb.set_message("Scanning files...");
-        Some(pb)
-    } else {
-        None
+    }
+    // Batched into transactions, or every cache write is its own commit.
+    let mut tx = Some(conn.unchecked_transaction()?);
+    let mut last = Instant::now();
+    let cache = HashCache::new(conn, config.drive_id.clone());
+    let result = scan(config, Some(&cache), resume, &mut |cursor, entries, skipped| {
+        if last.elapsed() < every {
+            return Ok(false);
+        }
+        let checkpoint = diffr_db::ops::ScanCheckpoint {
+            cursor: cursor.to_path_buf(),
+            saved_at: Utc::now(),
+            entries: entries.to_vec(),
+            skipped: skipped.to_vec(),
+        };
+        diffr_db::ops::save_scan_checkpoint(conn, &config.drive_id, &config.root, &checkpoint)?;
+        if let Some(tx) = tx.take() {
+            tx.commit()?;
+        }
+        tx = Some(conn.unchecked_transaction()?);
+        last = Instant::now();
+        Ok(true)
+    });
+    // A scan that saw everything has nothing to resume.
+    if result.as_ref().is_ok_and(|r| !r.cancelled && !r.stopped_early) {
+        diffr_db::ops::clear_scan_checkpoint(conn, &config.drive_id, &config.root)?;
+    }
+    if let Some(tx) = tx {
+        tx.commit()?;
+    }
+    result
+}
+
+/// [`scan_directory`] on a thread of its own.
+pub fn scan_directory_async(config: ScanConfig) -> Task<anyhow::Result<ScanResult>> {
+    task::spawn(move || scan_directory(&config))
+}
+
+/// [`scan_directory_cached`] on a thread of its own, with its own
+/// connection to the database at `db_path`.
+pub fn scan_directory_cached_async(config: ScanConfig, db_path: PathBuf) -> Task<anyhow::Result<ScanResult>> {
+    task::spawn(move || scan_directory_cached(&config, &diffr_db::open_db(&db_path)?))
+}
+
+/// Scan the folder `path` of an MTP device, which `config.root` names for
+/// progress. Devices keep sizes and modification times but no permissions,
+/// and their files aren't read to hash them, so the diff compares them by
+/// size and time alone. `.diffrignore` isn't read from the device, so only
+/// the patterns in `config.ignore` apply, and there's no resuming.
+pub fn scan_mtp(config: &ScanConfig, store: &mut MtpStore, path: &Path) -> anyhow::Result<ScanResult> {
+    let mut ignore_patterns = HashSet::from([".diffr".to_string()]);
+    for pattern in &config.ignore {
+        insert_pattern(&mut ignore_patterns, pattern);
+    }
+
+    config.progress.event(ProgressEvent::ScanStarted { root: &config.root });
+
+    let mut skipped = Vec::new();
+    let mut skipped_hidden = 0u64;
+    let mut cancelled = false;
+    let skip = |rel_path: &Path, reason| SkippedFile {
+        rel_path: rel_path.to_path_buf(),
+        drive_id: config.drive_id.clone(),
+        reason,
+    };
+    let listed = store.walk(path, |rel_path, object| {
+        if cancelled || config.cancel.is_cancelled() {
+            cancelled = true;
+            return false;
+        }
+        if should_ignore(rel_path, &ignore_patterns) {
+            if rel_path.file_name() != Some(OsStr::new(".diffr")) {
+                skipped.push(skip(rel_path, SkipReason::Ignored));
+            }
+            return false;
+        }
+        if config.skip_hidden && object.name.starts_with('.') && object.name != ".diffrignore" {
+            skipped_hidden += 1;
+            skipped.push(skip(rel_path, SkipReason::Hidden));
+            return false;
+        }
+        true
+    })?;
+
+    let now = Utc::now();
+    let (mut total_files, mut total_dirs, mut total_bytes) = (0u64, 0u64, 0u64);
+    let entries: Vec<FileEntry> = listed
+        .into_iter()
+        .map(|(rel_path, object)| {
+            if object.is_dir {
+                total_dirs += 1;
+            } else {
+                total_files += 1;
+                total_bytes += object.size;
+            }
+            FileEntry {
+                rel_path,
+                drive_id: config.drive_id.clone(),
+                is_dir: object.is_dir,
+                size: if object.is_dir { 0 } else { object.size },
+                mtime: DateTime::from_timestamp(object.mtime, 0).unwrap_or(now),
+                xxh3_hash: None,
+                sampled_hash: None,
+                sha256_hash: None,
+                blake3_hash: None,
+                permissions: None,
+                indexed_at: now,
+            }
+        })
+        .collect();
+    skipped.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+
+    config.progress.event(ProgressEvent::ScanFinished {
+        files: total_files,
+        dirs: total_dirs,
+        bytes: total_bytes,
+    });
+
+    Ok(ScanResult {
+        entries,
+        total_files,
+        total_dirs,
+        total_bytes,
+        errors: Vec::new(),
+        stopped_early: false,
+        cancelled,
+        symlink_cycles: Vec::new(),
+        skipped_hidden,
+        skipped_system: 0,
+        nested_repos: Vec::new(),
+        skipped,
+    })
+}
+
+fn scan(
+    config: &ScanConfig,
+    cache: Option<&HashCache>,
+    resume: Option<diffr_db::ops::ScanCheckpoint>,
+    checkpoint: Checkpoint,
+) -> anyhow::Result<ScanResult> {
+    // A repo.toml that doesn't parse shouldn't stop the drive syncing; the
+    // scan goes on with the default settings and reports the file.
+    let (repo_settings, bad_repo_config) = match RepoConfig::load(&config.root) {
+        Ok(repo) => (repo.map(|c| c.settings).unwrap_or_default(), false),
+        Err(e) => {
+            tracing::warn!("ignoring the repo settings: {}", e);
+            (Default::default(), true)
+        }
     };
+    let mut extra = repo_settings.ignore.clone();
+    extra.extend(config.ignore.iter().cloned());
+    let ignore_patterns = load_ignore_patterns(&config.root, &extra);
+
+    config.progress.event(ProgressEvent::ScanStarted { root: &config.root });
 
     let mut entries = Vec::new();
     let mut total_files = 0u64;
     let mut total_dirs = 0u64;
     let mut total_bytes = 0u64;
     let mut errors = Vec::new();
+    let mut symlink_cycles = Vec::new();
+    let (mut skipped_hidden, mut skipped_system) = (0u64, 0u64);
+    let mut nested_repos = Vec::new();
+    let mut skipped = Vec::new();
+    let mut ignored_dir: Option<PathBuf> = None;
+    let mut pacer = Pacer::new(config.power);
+    let skip = |rel_path: &Path, reason| SkippedFile {
+        rel_path: rel_path.to_path_buf(),
+        drive_id: config.drive_id.clone(),
+        reason,
+    };
 
-    let walker = WalkDir::new(&config.root)
+    // Picking up where an interrupted scan left off: what it found, and the
+    // last entry it finished. The walk is sorted, so everything up to that
+    // entry is in what it found.
+    let mut cursor = None;
+    if let Some(resumed) = resume {
+        for entry in &resumed.entries {
+            if entry.is_dir {
+                total_dirs += 1;
+            } else {
+                total_files += 1;
+                total_bytes += entry.size;
+            }
+        }
+        for skipped in &resumed.skipped {
+            match skipped.reason {
+                SkipReason::Hidden => skipped_hidden += 1,
+                SkipReason::System => skipped_system += 1,
+                SkipReason::NestedRepo => nested_repos.push(skipped.rel_path.clone()),
+                SkipReason::SymlinkCycle => symlink_cycles.push(skipped.rel_path.clone()),
+                _ => {}
+            }
+        }
+        entries = resumed.entries;
+        skipped = resumed.skipped;
+        cursor = Some(resumed.cursor);
+    }
+    if bad_repo_config {
+        let path = RepoConfig::path_in(&config.root);
+        skipped.push(skip(path.strip_prefix(&config.root).unwrap_or(&path), SkipReason::Unreadable));
+    }
+    let resumed_at = cursor.clone();
+    let (mut saved_entries, mut saved_skipped) = (entries.len(), skipped.len());
+
+    let mut walker = WalkDir::new(&config.root)
         .follow_links(config.follow_symlinks)
+        .sort_by_file_name()
         .into_iter();
 
-    for entry in walker {
+    let (mut stopped_early, mut cancelled) = (false, false);
+    while let Some(entry) = walker.next() {
+        if let Some(cursor) = &cursor {
+            if checkpoint(cursor, &entries[saved_entries..], &skipped[saved_skipped..])? {
+                (saved_entries, saved_skipped) = (entries.len(), skipped.len());
+            }
+        }
+        if config.max_errors.is_some_and(|max| errors.len() >= max) {
+            stopped_early = true;
+            break;
+        }
+        if config.cancel.is_cancelled() {
+            cancelled = true;
+            break;
+        }
         match entry {
             Ok(entry) => {
                 let path = entry.path();
@@ -107,31 +494,71 @@ pub fn scan_directory(config: &ScanConfig) -> anyhow::Result<ScanResult> {
                     continue;
                 }
 
+                // Already done before the scan was interrupted. A directory
+                // that isn't on the way to where it stopped was finished
+                // whole.
+                if let Some(resumed_at) = resumed_at.as_ref().filter(|r| rel_path <= **r) {
+                    if entry.file_type().is_dir() {
+                        if !resumed_at.starts_with(&rel_path) {
+                            walker.skip_current_dir();
+                        } else if ignored_dir.is_none() && should_ignore(&rel_path, &ignore_patterns) {
+                            ignored_dir = Some(rel_path);
+                        }
+                    }
+                    continue;
+                }
+                cursor = Some(rel_path.clone());
+
                 // Check ignore patterns
                 if should_ignore(&rel_path, &ignore_patterns) {
+                    if !ignored_dir.as_ref().is_some_and(|d| rel_path.starts_with(d)) {
+                        if rel_path.file_name() != Some(OsStr::new(".diffr")) {
+                            skipped.push(skip(&rel_path, SkipReason::Ignored));
+                        }
+                        if entry.file_type().is_dir() {
+                            ignored_dir = Some(rel_path);
+                        }
+                    }
                     continue;
                 }
 
                 let metadata = match entry.metadata() {
                     Ok(m) => m,
                     Err(e) => {
-                        errors.push(format!("{}: {}", rel_path.display(), e));
+                        let kind = e.io_error().map(ScanErrorKind::of).unwrap_or(ScanErrorKind::Other);
+                        skipped.push(skip(&rel_path, kind.skip_reason()));
+                        errors.push(ScanError { rel_path, kind, message: e.to_string() });
                         continue;
                     }
                 };
 
                 let is_dir = metadata.is_dir();
+                if let Some(kind) = hidden_kind(&entry, &metadata, config) {
+                    let reason = match kind {
+                        HiddenKind::Hidden => {
+                            skipped_hidden += 1;
+                            SkipReason::Hidden
+                        }
+                        HiddenKind::System => {
+                            skipped_system += 1;
+                            SkipReason::System
+                        }
+                    };
+                    skipped.push(skip(&rel_path, reason));
+                    if is_dir {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+                if is_dir && is_nested_repo(path, config.nested_repos) {
+                    skipped.push(skip(&rel_path, SkipReason::NestedRepo));
+                    nested_repos.push(rel_path);
+                    walker.skip_current_dir();
+                    continue;
+                }
+
                 let size = if is_dir { 0 } else { metadata.len() };
-                let mtime = metadata
-                    .modified()
-                    .ok()
-                    .and_then(|t| {
-                        let duration = t
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default();
-                        DateTime::from_timestamp(duration.as_secs() as i64, duration.subsec_nanos())
-                    })
-                    .unwrap_or_else(Utc::now);
+                let mtime = modified_time(&metadata);
 
                 if is_dir {
                     total_dirs += 1;
@@ -140,37 +567,119 @@ pub fn scan_directory(config: &ScanConfig) -> anyhow::Result<ScanResult> {
                     total_bytes += size;
                 }
 
+                let sample = repo_settings.hash == HashMode::Sampled
+                    && !config.full_hashes
+                    && size > hasher::SAMPLED_MIN_SIZE;
+                let hashes = !is_dir && repo_settings.hash != HashMode::Metadata;
+                if hashes {
+                    pacer.wait(&config.cancel, |reason| tracing::info!("pausing the scan: {}", reason));
+                    if config.cancel.is_cancelled() {
+                        cancelled = true;
+                        break;
+                    }
+                }
+                let hashing = Instant::now();
+                let hashed = if !hashes {
+                    None
+                } else if sample {
+                    Some(hasher::sampled_file(path).map(|h| (h, None, None)))
+                } else {
+                    Some(match cache {
+                        Some(cache) => cache.get_or_hash(
+                            &config.root,
+                            &rel_path,
+                            size,
+                            mtime,
+                            cache::file_id(path, &metadata).as_deref(),
+                            config.strong_hash,
+                        ),
+                        None => hasher::hash_file(path, config.strong_hash),
+                    }
+                    .map(|r| (r.xxh3_hex, r.sha256_hex, r.blake3_hex)))
+                };
+                if hashes {
+                    pacer.rest(hashing.elapsed(), &config.cancel);
+                }
+                let (mut xxh3_hash, mut sampled_hash) = (None, None);
+                let (mut sha256_hash, mut blake3_hash) = (None, None);
+                match hashed {
+                    Some(Ok((h, _, _))) if sample => sampled_hash = Some(h),
+                    Some(Ok((h, sha256, blake3))) => {
+                        xxh3_hash = Some(h);
+                        (sha256_hash, blake3_hash) = (sha256, blake3);
+                    }
+                    Some(Err(e)) => {
+                        let kind = ScanErrorKind::of_any(&e);
+                        skipped.push(skip(&rel_path, kind.skip_reason()));
+                        errors.push(ScanError {
+                            rel_path: rel_path.clone(),
+                            kind,
+                            message: e.to_string(),
+                        });
+                    }
+                    None => {}
+                }
+
                 entries.push(FileEntry {
                     rel_path,
                     drive_id: config.drive_id.clone(),
                     is_dir,
                     size,
                     mtime,
-                    xxh3_hash: None,
-                    sha256_hash: None,
+                    xxh3_hash,
+                    sampled_hash,
+                    sha256_hash,
+                    blake3_hash,
+                    permissions: FilePermissions::from_metadata(&metadata),
                     indexed_at: Utc::now(),
                 });
 
-                if let Some(ref pb) = pb {
-                    pb.set_message(format!(
-                        "{} files, {} dirs scanned",
-                        total_files, total_dirs
-                    ));
-                    pb.tick();
+                config.progress.event(ProgressEvent::ScanProgress {
+                    files: total_files,
+                    dirs: total_dirs,
+                });
+            }
+            Err(e) if e.loop_ancestor().is_some() => {
+                let path = e.path().unwrap_or(&config.root);
+                let rel_path = path.strip_prefix(&config.root).unwrap_or(path);
+                if resumed_at.as_deref().is_some_and(|r| rel_path <= r) {
+                    continue;
                 }
+                cursor = Some(rel_path.to_path_buf());
+                tracing::debug!("skipping symlink cycle at {}", rel_path.display());
+                skipped.push(skip(rel_path, SkipReason::SymlinkCycle));
+                symlink_cycles.push(rel_path.to_path_buf());
             }
             Err(e) => {
-                errors.push(format!("walk error: {}", e));
+                let rel_path = e
+                    .path()
+                    .and_then(|p| p.strip_prefix(&config.root).ok())
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                // A directory that couldn't be read fails after it's been
+                // walked, so one interrupted just then is read again.
+                if resumed_at.as_ref().is_some_and(|r| rel_path < *r) && !rel_path.as_os_str().is_empty() {
+                    continue;
+                }
+                let kind = e.io_error().map(ScanErrorKind::of).unwrap_or(ScanErrorKind::Other);
+                if !rel_path.as_os_str().is_empty() {
+                    skipped.push(skip(&rel_path, kind.skip_reason()));
+                    cursor = Some(rel_path.clone());
+                }
+                let message = match e.io_error() {
+                    Some(io) => io.to_string(),
+                    None => format!("walk error: {}", e),
+                };
+                errors.push(ScanError { rel_path, kind, message });
             }
         }
     }
 
-    if let Some(pb) = pb {
-        pb.finish_with_message(format!(
-            "Scanned {} files, {} dirs ({} bytes)",
-            total_files, total_dirs, total_bytes
-        ));
-    }
+    config.progress.event(ProgressEvent::ScanFinished {
+        files: total_files,
+        dirs: total_dirs,
+        bytes: total_bytes,
+    });
 
     Ok(ScanResult {
         entries,
@@ -178,13 +687,20 @@ pub fn scan_directory(config: &ScanConfig) -> anyhow::Result<ScanResult> {
         total_dirs,
         total_bytes,
         errors,
+        stopped_early,
+        cancelled,
+        symlink_cycles,
+        skipped_hidden,
+        skipped_system,
+        nested_repos,
+        skipped,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use diffr_core::models::drive::DriveId;
+    use diffr_core::models::drive::{DriveId, DriveIdentity};
     use tempfile::TempDir;
 
     #[test]
@@ -199,7 +715,16 @@ mod tests {
             root: dir.path().to_path_buf(),
             drive_id: DriveId::new(),
             follow_symlinks: false,
-            show_progress: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
         };
 
         let result = scan_directory(&config).unwrap();
@@ -207,6 +732,37 @@ mod tests {
         assert_eq!(result.total_dirs, 1);
     }
 
+    #[test]
+    fn test_entries_come_in_path_order() {
+        let dir = TempDir::new().unwrap();
+        for name in ["a.txt", "a-b", "a/z.txt", "a/b/c.txt", "B.txt"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, name).unwrap();
+        }
+
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+
+        let paths: Vec<PathBuf> = scan_directory(&config).unwrap().entries.into_iter().map(|e| e.rel_path).collect();
+        // Not text order, where `a-b` and `a.txt` would come before `a/b`.
+        let expected = ["B.txt", "a", "a/b", "a/b/c.txt", "a/z.txt", "a-b", "a.txt"];
+        assert_eq!(paths, expected.map(PathBuf::from));
+    }
+
     #[test]
     fn test_diffrignore() {
         let dir = TempDir::new().unwrap();
@@ -219,7 +775,16 @@ mod tests {
             root: dir.path().to_path_buf(),
             drive_id: DriveId::new(),
             follow_symlinks: false,
-            show_progress: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
         };
 
         let result = scan_directory(&config).unwrap();
@@ -228,5 +793,390 @@ mod tests {
             .entries
             .iter()
             .all(|e| !e.rel_path.starts_with("ignore_me")));
+        // The skip report names the ignored directory, not what's in it.
+        let skipped: Vec<_> = result.skipped.iter().map(|s| (s.rel_path.clone(), s.reason)).collect();
+        assert_eq!(skipped, vec![(PathBuf::from("ignore_me"), SkipReason::Ignored)]);
+    }
+
+    #[test]
+    fn test_wildcard_ignore_and_repo_settings() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(".diffrignore"), "*.tmp\nbuild/\n").unwrap();
+        fs::create_dir_all(dir.path().join(".diffr")).unwrap();
+        fs::write(
+            dir.path().join(".diffr/repo.toml"),
+            "[repo]\ninitialized_at = \"2024-01-01T00:00:00+00:00\"\n\n\
+             [settings]\nhash = \"xxh3\"\nignore = [\"cache?\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("keep.txt"), "keep").unwrap();
+        fs::write(dir.path().join("scratch.tmp"), "x").unwrap();
+        fs::create_dir_all(dir.path().join("build")).unwrap();
+        fs::write(dir.path().join("build/out.o"), "x").unwrap();
+        fs::create_dir_all(dir.path().join("cache1")).unwrap();
+        fs::write(dir.path().join("debug.log"), "x").unwrap();
+
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: vec!["*.log".to_string()],
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+
+        let result = scan_directory(&config).unwrap();
+        let paths: Vec<_> = result.entries.iter().map(|e| e.rel_path.clone()).collect();
+        assert!(paths.contains(&PathBuf::from("keep.txt")));
+        assert!(!paths.iter().any(|p| p.starts_with("build") || p.starts_with("cache1")));
+        assert!(!paths.contains(&PathBuf::from("scratch.tmp")));
+        assert!(!paths.contains(&PathBuf::from("debug.log")));
+        let keep = result.entries.iter().find(|e| e.rel_path == Path::new("keep.txt")).unwrap();
+        assert!(keep.xxh3_hash.is_some());
+    }
+
+    #[test]
+    fn test_malformed_repo_settings_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join(".diffr")).unwrap();
+        fs::write(dir.path().join(".diffr/repo.toml"), "[settings\nhash = ").unwrap();
+        fs::write(dir.path().join("keep.txt"), "keep").unwrap();
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+
+        let result = scan_directory(&config).unwrap();
+        assert!(result.entries.iter().any(|e| e.rel_path == Path::new("keep.txt")));
+        let skipped: Vec<_> = result.skipped.iter().map(|s| (s.rel_path.clone(), s.reason)).collect();
+        assert_eq!(skipped, vec![(PathBuf::from(".diffr/repo.toml"), SkipReason::Unreadable)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_skips_cycles() {
+        let dir = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("linked.txt"), "via link").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("ext")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+
+        let mut config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+        let result = scan_directory(&config).unwrap();
+        assert!(!result.entries.iter().any(|e| e.rel_path == Path::new("ext/linked.txt")));
+
+        config.follow_symlinks = true;
+        let result = scan_directory(&config).unwrap();
+        assert!(result.entries.iter().any(|e| e.rel_path == Path::new("ext/linked.txt")));
+        assert_eq!(result.symlink_cycles, vec![PathBuf::from("sub/loop")]);
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn test_skip_hidden() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(".diffrignore"), "").unwrap();
+        fs::write(dir.path().join(".env"), "secret").unwrap();
+        fs::write(dir.path().join("visible.txt"), "hi").unwrap();
+        fs::create_dir(dir.path().join(".cache")).unwrap();
+        fs::write(dir.path().join(".cache/blob"), "x").unwrap();
+
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: true,
+            skip_system: true,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+        let result = scan_directory(&config).unwrap();
+        let mut paths: Vec<_> = result.entries.iter().map(|e| e.rel_path.clone()).collect();
+        paths.sort();
+        assert_eq!(paths, vec![PathBuf::from(".diffrignore"), PathBuf::from("visible.txt")]);
+        assert_eq!(result.skipped_hidden, 2);
+        assert!(result.skipped.iter().all(|s| s.reason == SkipReason::Hidden));
+        assert_eq!(result.skipped.len(), 2);
+    }
+
+    #[test]
+    fn test_scan_mtp() {
+        use diffr_discovery::fake::FakeMtpDevice;
+        use diffr_discovery::mtp::ROOT;
+
+        let device = FakeMtpDevice::new(&["Internal shared storage"]);
+        let dcim = device.add(0x10001, ROOT, "DCIM", None, 0);
+        let camera = device.add(0x10001, dcim, "Camera", None, 0);
+        device.add(0x10001, camera, "IMG_2.jpg", Some(b"two"), 1_700_000_200);
+        device.add(0x10001, camera, "IMG_1.jpg", Some(b"1"), 1_700_000_100);
+        device.add(0x10001, camera, "IMG_1.jpg.tmp", Some(b""), 0);
+        device.add(0x10001, dcim, ".thumbnails", None, 0);
+        let mut store = MtpStore::new(Box::new(device)).unwrap();
+
+        let config = ScanConfig {
+            root: PathBuf::from("mtp://SER/Internal shared storage/DCIM"),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: true,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: vec!["*.tmp".to_string()],
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+        let result = scan_mtp(&config, &mut store, Path::new("Internal shared storage/DCIM")).unwrap();
+        let paths: Vec<_> = result.entries.iter().map(|e| e.rel_path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("Camera"),
+                PathBuf::from("Camera/IMG_1.jpg"),
+                PathBuf::from("Camera/IMG_2.jpg")
+            ]
+        );
+        assert_eq!((result.total_files, result.total_dirs, result.total_bytes), (2, 1, 4));
+        assert_eq!(result.entries[2].mtime.timestamp(), 1_700_000_200);
+        assert_eq!(result.skipped_hidden, 1);
+        let skipped: Vec<_> = result.skipped.iter().map(|s| (s.rel_path.clone(), s.reason)).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (PathBuf::from(".thumbnails"), SkipReason::Hidden),
+                (PathBuf::from("Camera/IMG_1.jpg.tmp"), SkipReason::Ignored)
+            ]
+        );
+
+        config.cancel.cancel();
+        let result = scan_mtp(&config, &mut store, Path::new("Internal shared storage/DCIM")).unwrap();
+        assert!(result.cancelled);
+        assert!(result.entries.is_empty());
+    }
+
+    #[test]
+    fn test_nested_repos_are_boundaries() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("top.txt"), "t").unwrap();
+        fs::create_dir_all(dir.path().join("sub/.diffr")).unwrap();
+        fs::write(dir.path().join("sub/.diffr/repo.toml"), "").unwrap();
+        fs::write(dir.path().join("sub/inner.txt"), "i").unwrap();
+        fs::create_dir_all(dir.path().join("code/.git")).unwrap();
+        fs::write(dir.path().join("code/main.rs"), "m").unwrap();
+
+        let mut config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::SkipDiffr,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+        let paths = |config: &ScanConfig| {
+            let result = scan_directory(config).unwrap();
+            let mut paths: Vec<_> = result.entries.iter().map(|e| e.rel_path.clone()).collect();
+            paths.sort();
+            (paths, result.nested_repos)
+        };
+        let (found, nested) = paths(&config);
+        assert!(found.contains(&PathBuf::from("code/main.rs")));
+        assert!(!found.iter().any(|p| p.starts_with("sub")));
+        assert_eq!(nested, vec![PathBuf::from("sub")]);
+
+        config.nested_repos = NestedRepos::SkipAll;
+        let (found, nested) = paths(&config);
+        assert_eq!(found, vec![PathBuf::from("top.txt")]);
+        assert_eq!(nested.len(), 2);
+    }
+
+    #[test]
+    fn test_cancelled_scan_stops_and_says_so() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: None,
+        };
+        config.cancel.cancel();
+
+        let result = scan_directory(&config).unwrap();
+        assert!(result.cancelled);
+        assert!(!result.stopped_early);
+        assert!(result.entries.is_empty());
+    }
+
+    #[test]
+    fn test_hashing_waits_while_paused() {
+        use diffr_core::power::{BatteryPolicy, PowerReading};
+        use diffr_core::schedule::PowerSource;
+
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let mut repo = RepoConfig::new(None);
+        repo.settings.hash = HashMode::Xxh3;
+        repo.save(dir.path()).unwrap();
+        let config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: DriveId::new(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::none(),
+            full_hashes: false,
+            strong_hash: None,
+            cancel: CancellationToken::default(),
+            power: Some(PowerGuard {
+                on_battery: BatteryPolicy::Pause,
+                max_cpu_temp: None,
+                throttle_cpu_temp: None,
+                read: || PowerReading {
+                    power: Some(PowerSource::Battery),
+                    cpu_temp: None,
+                },
+            }),
+        };
+        let cancel = config.cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            cancel.cancel();
+        });
+
+        // Nothing is hashed on battery; the wait ends only with the scan.
+        let started = Instant::now();
+        let result = scan_directory(&config).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(result.cancelled);
+        assert!(result.entries.iter().all(|e| e.xxh3_hash.is_none()));
+    }
+
+    #[test]
+    fn test_interrupted_scan_resumes_after_its_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("a")).unwrap();
+        for name in ["a/1.txt", "a/2.txt", "b.txt", "c.txt"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+        let mut repo = RepoConfig::new(None);
+        repo.settings.hash = HashMode::Xxh3;
+        repo.save(dir.path()).unwrap();
+        let conn = diffr_db::open_memory_db().unwrap();
+        let drive = diffr_core::models::drive::Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        diffr_db::ops::insert_drive(&conn, &drive).unwrap();
+
+        // Interrupted once it has seen three files, checkpointing before
+        // every entry.
+        struct CancelAfter(CancellationToken);
+        impl diffr_core::progress::ProgressSink for CancelAfter {
+            fn event(&self, event: &ProgressEvent<'_>) {
+                if let ProgressEvent::ScanProgress { files: 3, .. } = event {
+                    self.0.cancel();
+                }
+            }
+        }
+        let cancel = CancellationToken::default();
+        let mut config = ScanConfig {
+            root: dir.path().to_path_buf(),
+            drive_id: drive.id.clone(),
+            follow_symlinks: false,
+            skip_hidden: false,
+            skip_system: false,
+            nested_repos: NestedRepos::Descend,
+            ignore: Vec::new(),
+            max_errors: None,
+            progress: Progress::new(CancelAfter(cancel.clone())),
+            full_hashes: false,
+            strong_hash: None,
+            cancel,
+            power: None,
+        };
+        let result = scan_checkpointed(&config, &conn, Duration::ZERO).unwrap();
+        assert!(result.cancelled);
+        assert!(conn.is_autocommit());
+        let saved = diffr_db::ops::load_scan_checkpoint(&conn, &drive.id, dir.path()).unwrap().unwrap();
+        assert_eq!(saved.cursor, Path::new("b.txt"));
+        assert!(diffr_db::ops::get_hash_cache_entry(&conn, &drive.id, Path::new("b.txt")).unwrap().is_some());
+
+        // Run again, the finished subtree isn't walked: a file added to it
+        // isn't seen, while one after the checkpoint is.
+        fs::write(dir.path().join("a/3.txt"), "late").unwrap();
+        fs::write(dir.path().join("d.txt"), "late").unwrap();
+        config.progress = Progress::none();
+        config.cancel = CancellationToken::default();
+        let result = scan_directory_cached(&config, &conn).unwrap();
+        let mut paths: Vec<_> = result.entries.iter().map(|e| e.rel_path.clone()).collect();
+        paths.sort();
+        let expected: Vec<PathBuf> = ["a", "a/1.txt", "a/2.txt", "b.txt", "c.txt", "d.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(paths, expected);
+        assert_eq!(result.total_files, 5);
+        assert!(result.entries.iter().all(|e| e.is_dir || e.xxh3_hash.is_some()));
+
+        // Finished, it leaves nothing to resume.
+        assert!(diffr_db::ops::load_scan_checkpoint(&conn, &drive.id, dir.path()).unwrap().is_none());
+        assert!(conn.is_autocommit());
     }
 }
@@ -0,0 +1,194 @@
+use diffr_core::models::drive::DriveId;
+use diffr_core::models::file_entry::FileEntry;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Drives with fewer indexed files than this are never flagged; a handful
+/// of edits on a small drive is a large share without meaning anything.
+pub const MIN_FILES: usize = 20;
+
+/// Changed files whose contents are checked for encryption-like entropy.
+const ENTROPY_SAMPLE_FILES: usize = 20;
+/// Bytes read from the start of each sampled file.
+const ENTROPY_SAMPLE_BYTES: u64 = 64 * 1024;
+/// Bits per byte above which data looks encrypted (or compressed); random
+/// data is close to 8, text and most documents well under 6.
+const ENCRYPTED_ENTROPY: f64 = 7.5;
+/// Formats that are compressed already, so high entropy is normal for them.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "avi", "bz2", "docx", "flac", "gif", "gz", "heic", "jpeg", "jpg", "m4a", "mkv", "mov",
+    "mp3", "mp4", "ogg", "png", "pptx", "rar", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// How much of a drive's content changed since the last sync indexed it.
+#[derive(Debug)]
+pub struct ChangeReport {
+    pub drive_id: DriveId,
+    /// Files both in the index and in this scan.
+    pub compared: usize,
+    /// Those whose content changed.
+    pub changed: Vec<PathBuf>,
+    /// Changed files whose entropy was checked, and how many of them look
+    /// encrypted.
+    pub sampled: usize,
+    pub encrypted_like: usize,
+}
+
+impl ChangeReport {
+    pub fn changed_percent(&self) -> f64 {
+        self.changed.len() as f64 * 100.0 / self.compared.max(1) as f64
+    }
+
+    /// Whether the change rate is over `threshold_percent` on a drive big
+    /// enough for the rate to mean something.
+    pub fn is_anomalous(&self, threshold_percent: u8) -> bool {
+        self.compared >= MIN_FILES && self.changed_percent() > threshold_percent as f64
+    }
+}
+
+/// Compare a drive's scan with its index from the last sync, and check a
+/// sample of the changed files under `root` for encryption-like content.
+pub fn check_changes(
+    drive_id: &DriveId,
+    root: &Path,
+    indexed: &[FileEntry],
+    scanned: &[FileEntry],
+) -> ChangeReport {
+    let before: HashMap<&Path, &FileEntry> = indexed
+        .iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| (e.rel_path.as_path(), e))
+        .collect();
+    let mut compared = 0;
+    let mut changed = Vec::new();
+    for entry in scanned.iter().filter(|e| !e.is_dir) {
+        if let Some(old) = before.get(entry.rel_path.as_path()) {
+            compared += 1;
+            if content_changed(old, entry) {
+                changed.push(entry.rel_path.clone());
+            }
+        }
+    }
+
+    changed.sort();
+
+    let sample: Vec<&PathBuf> = changed
+        .iter()
+        .filter(|p| !is_compressed_format(p))
+        .take(ENTROPY_SAMPLE_FILES)
+        .collect();
+    let encrypted_like = sample
+        .iter()
+        .filter(|p| sample_entropy(&root.join(p)).is_ok_and(|e| e >= ENCRYPTED_ENTROPY))
+        .count();
+
+    ChangeReport {
+        drive_id: drive_id.clone(),
+        compared,
+        sampled: sample.len(),
+        changed,
+        encrypted_like,
+    }
+}
+
+/// Whether a file's content differs from its indexed version: by hash when
+/// both have the same one, otherwise by size and mtime.
+fn content_changed(old: &FileEntry, new: &FileEntry) -> bool {
+    match (&old.xxh3_hash, &new.xxh3_hash, &old.sampled_hash, &new.sampled_hash) {
+        (Some(a), Some(b), _, _) => a != b,
+        (_, _, Some(a), Some(b)) => a != b || old.size != new.size,
+        _ => old.size != new.size || old.mtime != new.mtime,
+    }
+}
+
+fn is_compressed_format(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| COMPRESSED_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Shannon entropy, in bits per byte, of the start of a file.
+fn sample_entropy(path: &Path) -> std::io::Result<f64> {
+    let mut buf = Vec::new();
+    std::fs::File::open(path)?
+        .take(ENTROPY_SAMPLE_BYTES)
+        .read_to_end(&mut buf)?;
+    Ok(entropy(&buf))
+}
+
+/// Shannon entropy of `bytes` in bits per byte, from 0 to 8.
+pub fn entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn file(drive: &DriveId, path: &str, hash: &str) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.clone(),
+            is_dir: false,
+            size: 10,
+            mtime: Utc::now(),
+            xxh3_hash: Some(hash.into()),
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_check_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let drive = DriveId::new();
+        // Pseudo-random bytes stand in for an encrypted file.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..65536)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        assert!(entropy(&noise) > ENCRYPTED_ENTROPY);
+        assert!(entropy("plain old text, nothing to see here".as_bytes()) < 5.0);
+
+        let indexed: Vec<FileEntry> =
+            (0..40).map(|i| file(&drive, &format!("doc{i}.txt"), "old")).collect();
+        let mut scanned = indexed.clone();
+        for entry in scanned.iter_mut().take(30) {
+            entry.xxh3_hash = Some("new".into());
+            std::fs::write(dir.path().join(&entry.rel_path), &noise).unwrap();
+        }
+
+        let report = check_changes(&drive, dir.path(), &indexed, &scanned);
+        assert_eq!(report.compared, 40);
+        assert_eq!(report.changed.len(), 30);
+        assert_eq!(report.sampled, ENTROPY_SAMPLE_FILES);
+        assert_eq!(report.encrypted_like, ENTROPY_SAMPLE_FILES);
+        assert!(report.is_anomalous(50));
+        assert!(!report.is_anomalous(80));
+    }
+}
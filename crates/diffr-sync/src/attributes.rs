@@ -0,0 +1,243 @@
+use diffr_core::models::drive::DriveId;
+use diffr_core::models::sync_state::{SyncOp, SyncPlan};
+use diffr_core::spill::SpillVec;
+use diffr_scan::scanner::matches_pattern;
+
+use crate::optimize::is_transfer;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The file at a sync root that sets attributes for paths under it, in the
+/// style of `.gitattributes`.
+pub const ATTRIBUTES_FILE: &str = ".diffrattributes";
+
+/// How soon a path's operations run within a sync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "high" => Ok(Priority::High),
+            "normal" => Ok(Priority::Normal),
+            "low" => Ok(Priority::Low),
+            _ => Err(format!("unknown priority: {s} (high, normal, low)")),
+        }
+    }
+}
+
+/// What `.diffrattributes` says about one path. Unset attributes leave the
+/// sync's own settings alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathAttributes {
+    /// `verify=always` or `verify=never`: verify copies of the path, or
+    /// don't, whatever `--verify` and `verify_after_sync` say.
+    pub verify: Option<bool>,
+    /// `archive=always` or `archive=never`: archive the path before it's
+    /// overwritten or deleted, or don't, whatever `--no-archive` says.
+    pub archive: Option<bool>,
+    /// `compress=false`: store the path's archives uncompressed, for files
+    /// such as video that zstd can't shrink.
+    pub compress: Option<bool>,
+    /// `priority=high` or `priority=low`: run the path's operations before
+    /// or after the rest.
+    pub priority: Option<Priority>,
+}
+
+impl PathAttributes {
+    /// Set `key` to `value`, as written in `.diffrattributes`.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "verify" => self.verify = Some(parse_switch(value, "always", "never")?),
+            "archive" => self.archive = Some(parse_switch(value, "always", "never")?),
+            "compress" => self.compress = Some(parse_switch(value, "true", "false")?),
+            "priority" => self.priority = Some(value.parse()?),
+            _ => return Err(format!("unknown attribute: {key} (verify, archive, compress, priority)")),
+        }
+        Ok(())
+    }
+
+    /// `self`, with whatever `other` sets taking its place.
+    fn overlay(&mut self, other: &PathAttributes) {
+        self.verify = other.verify.or(self.verify);
+        self.archive = other.archive.or(self.archive);
+        self.compress = other.compress.or(self.compress);
+        self.priority = other.priority.or(self.priority);
+    }
+}
+
+fn parse_switch(value: &str, on: &str, off: &str) -> Result<bool, String> {
+    match value {
+        v if v == on => Ok(true),
+        v if v == off => Ok(false),
+        _ => Err(format!("expected {on} or {off}: {value}")),
+    }
+}
+
+/// The rules in a drive's `.diffrattributes`. Each line is a pattern, as in
+/// `.diffrignore`, followed by `key=value` attributes; where several lines
+/// match a path, later ones win.
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    rules: Vec<(String, PathAttributes)>,
+}
+
+impl Attributes {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let pattern = fields.next().unwrap_or_default();
+            let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+            let mut attrs = PathAttributes::default();
+            for field in fields {
+                let (key, value) = field
+                    .split_once('=')
+                    .ok_or_else(|| format!("line {}: expected key=value: {}", i + 1, field))?;
+                attrs.set(key, value).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            }
+            rules.push((pattern.to_string(), attrs));
+        }
+        Ok(Self { rules })
+    }
+
+    /// The attributes at `root`, or none if it has no `.diffrattributes`.
+    pub fn load(root: &Path) -> anyhow::Result<Self> {
+        let path = root.join(ATTRIBUTES_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Self::parse(&content).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn for_path(&self, rel_path: &Path) -> PathAttributes {
+        let mut attrs = PathAttributes::default();
+        for (pattern, rule) in &self.rules {
+            if matches_pattern(rel_path, pattern) {
+                attrs.overlay(rule);
+            }
+        }
+        attrs
+    }
+}
+
+/// The attributes that apply to `op`: those of the drive the change comes
+/// from, or of the drive it's made on when it has no source.
+pub fn for_op(attributes: &HashMap<DriveId, Attributes>, op: &SyncOp) -> PathAttributes {
+    let drive = op.source_drive.as_ref().unwrap_or(&op.target_drive);
+    attributes
+        .get(drive)
+        .map(|a| a.for_path(&op.rel_path))
+        .unwrap_or_default()
+}
+
+/// Move high-priority transfers ahead of the others and low-priority ones
+/// behind, keeping the order within each. Only transfers move, among the
+/// places transfers had: same-drive copies, directory creates, deletes and
+/// directory removals stay where the plan put them, as the executor needs.
+pub fn order_by_priority(plan: &mut SyncPlan, attributes: &HashMap<DriveId, Attributes>) {
+    if attributes.values().all(Attributes::is_empty) {
+        return;
+    }
+    let mut transfers: SpillVec<SyncOp> = plan
+        .operations
+        .iter()
+        .filter(|op| is_transfer(&op.kind))
+        .map(Cow::into_owned)
+        .collect();
+    transfers.sort_by_cached_key(|op| for_op(attributes, op).priority.unwrap_or_default());
+    // Then put them back in the transfers' places, in their new order.
+    let mut sorted = transfers.into_iter();
+    for op in plan.operations.take() {
+        let op = match is_transfer(&op.kind) {
+            true => sorted.next().unwrap_or(op),
+            false => op,
+        };
+        plan.operations.push(op);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::sync_state::SyncOpKind;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_later_rules_win() {
+        let attrs = Attributes::parse(
+            "# media\n*.mkv compress=false priority=low\nraw/ verify=always\n\nraw/keep.mkv priority=high archive=never\n",
+        )
+        .unwrap();
+        let video = attrs.for_path(Path::new("films/a.mkv"));
+        assert_eq!((video.compress, video.priority, video.verify), (Some(false), Some(Priority::Low), None));
+        let kept = attrs.for_path(Path::new("raw/keep.mkv"));
+        assert_eq!(
+            kept,
+            PathAttributes {
+                verify: Some(true),
+                archive: Some(false),
+                compress: Some(false),
+                priority: Some(Priority::High),
+            }
+        );
+        assert_eq!(attrs.for_path(Path::new("notes.txt")), PathAttributes::default());
+
+        let err = Attributes::parse("*.iso verify=sometimes\n").unwrap_err();
+        assert!(err.starts_with("line 1: expected always or never"), "{}", err);
+        assert!(Attributes::parse("*.iso colour=blue\n").is_err());
+    }
+
+    #[test]
+    fn test_order_by_priority() {
+        let source = DriveId::new();
+        let op = |kind: SyncOpKind, path: &str| SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: path.into(),
+            source_drive: Some(source.clone()),
+            target_drive: DriveId::new(),
+            size_bytes: 1,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let mut plan = SyncPlan::new(
+            ClusterId::new(),
+            vec![
+                op(SyncOpKind::LocalCopy, "c.iso"),
+                op(SyncOpKind::CreateDir, "new"),
+                op(SyncOpKind::CopyNew, "b.iso"),
+                op(SyncOpKind::CopyNew, "a.txt"),
+                op(SyncOpKind::CopyNew, "urgent.doc"),
+                op(SyncOpKind::Delete, "gone.iso"),
+                op(SyncOpKind::RemoveDir, "old"),
+            ],
+        );
+        let attributes = HashMap::from([(
+            source.clone(),
+            Attributes::parse("*.iso priority=low\nurgent.* priority=high\n").unwrap(),
+        )]);
+        order_by_priority(&mut plan, &attributes);
+        let paths: Vec<_> = plan.operations.iter().map(|o| o.rel_path.to_string_lossy().into_owned()).collect();
+        assert_eq!(paths, vec!["c.iso", "new", "urgent.doc", "a.txt", "b.iso", "gone.iso", "old"]);
+    }
+}
@@ -1,29 +1,223 @@
-use chrono::Utc;
-use diffr_core::models::cluster::ConflictStrategy;
-use diffr_core::models::drive::Drive;
-use diffr_core::models::sync_state::{ConflictResolution, SyncOp, SyncOpKind};
-use std::io::{self, Write};
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use diffr_core::models::cluster::{by_extension, Cluster, ConflictStrategy};
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::{ConflictResolution, SyncOp, SyncOpKind, SyncPlan};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-use crate::diff::DiffEntry;
+use crate::diff::{DiffEntry, DiffKind};
+use crate::topology::stamp;
 
-/// Resolve a conflict according to the configured strategy.
+/// Resolve a conflict by the cluster's rule for the file's extension, or
+/// else its conflict strategy. There's nobody to ask, so a conflict
+/// resolved interactively fails; use a [`ConflictResolver`] that can ask.
 pub fn resolve_conflict(
-    strategy: &ConflictStrategy,
+    cluster: &Cluster,
     entry: &DiffEntry,
     left_drive: &Drive,
     right_drive: &Drive,
 ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
-    match strategy {
-        ConflictStrategy::NewestWins => {
-            resolve_newest_wins(entry, left_drive, right_drive)
+    ConflictResolver::default().resolve(cluster, entry, left_drive, right_drive)
+}
+
+/// A conflict found while planning, which stands in the plan as the
+/// `ResolveConflict` op `op_id` until it's resolved. It keeps its own copy
+/// of both sides, as it outlives the diff entry it came from.
+#[derive(Debug, Clone)]
+pub struct PendingConflict {
+    pub op_id: Uuid,
+    pub left: DriveId,
+    pub right: DriveId,
+    pub rel_path: PathBuf,
+    pub kind: DiffKind,
+    pub left_file: Option<FileEntry>,
+    pub right_file: Option<FileEntry>,
+}
+
+impl PendingConflict {
+    pub fn new(op_id: Uuid, left: &Drive, right: &Drive, entry: &DiffEntry) -> Self {
+        Self {
+            op_id,
+            left: left.id.clone(),
+            right: right.id.clone(),
+            rel_path: entry.rel_path.to_path_buf(),
+            kind: entry.kind.clone(),
+            left_file: entry.left.cloned(),
+            right_file: entry.right.cloned(),
+        }
+    }
+
+    /// The conflict as the diff entry it was found in.
+    pub fn entry(&self) -> DiffEntry<'_> {
+        DiffEntry {
+            rel_path: &self.rel_path,
+            kind: self.kind.clone(),
+            left: self.left_file.as_ref(),
+            right: self.right_file.as_ref(),
+        }
+    }
+}
+
+/// Which version of a conflicting file to keep, when asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictChoice {
+    Left,
+    Right,
+    Both,
+}
+
+/// Asks how to resolve a conflict the cluster resolves interactively,
+/// given the conflict and its left and right drives. Returns the choice
+/// and whether to make it for every remaining conflict too.
+pub type AskFn<'f> =
+    Box<dyn FnMut(&DiffEntry, &Drive, &Drive) -> anyhow::Result<(ConflictChoice, bool)> + 'f>;
+
+/// Resolves a run of conflicts, remembering an interactive "apply to all"
+/// choice so the user is only asked once.
+#[derive(Default)]
+pub struct ConflictResolver<'f> {
+    apply_to_all: Option<ConflictChoice>,
+    merge_commands: BTreeMap<String, String>,
+    ask: Option<AskFn<'f>>,
+}
+
+impl<'f> ConflictResolver<'f> {
+    /// A resolver that merges files with a command in the config's
+    /// `[merge]` for their extension, and resolves the rest by the
+    /// cluster's strategy.
+    pub fn with_merge_commands(merge_commands: BTreeMap<String, String>) -> Self {
+        Self {
+            merge_commands,
+            ..Self::default()
         }
-        ConflictStrategy::KeepBoth => {
-            resolve_keep_both(entry, left_drive, right_drive)
+    }
+
+    /// Who the interactive strategy asks. Without it, a conflict that
+    /// needs asking about can't be resolved.
+    pub fn asking(
+        mut self,
+        ask: impl FnMut(&DiffEntry, &Drive, &Drive) -> anyhow::Result<(ConflictChoice, bool)> + 'f,
+    ) -> Self {
+        self.ask = Some(Box::new(ask));
+        self
+    }
+
+    /// Replace each of `conflicts`' `ResolveConflict` op in `plan` with the
+    /// ops that resolve it. A conflict that can't be resolved, such as one
+    /// with nobody to ask, keeps its op, which fails if it's run. Returns
+    /// how the rest were resolved.
+    pub fn resolve_plan(
+        &mut self,
+        cluster: &Cluster,
+        plan: &mut SyncPlan,
+        conflicts: &[PendingConflict],
+        drives: &[Drive],
+    ) -> Vec<ConflictResolution> {
+        let drive = |id: &DriveId| drives.iter().find(|d| &d.id == id);
+        let mut resolutions = Vec::new();
+        for op in plan.operations.take() {
+            let pending = conflicts
+                .iter()
+                .find(|c| op.kind == SyncOpKind::ResolveConflict && c.op_id == op.id);
+            let Some(conflict) = pending else {
+                plan.operations.push(op);
+                continue;
+            };
+            let (Some(left), Some(right)) = (drive(&conflict.left), drive(&conflict.right)) else {
+                plan.operations.push(op);
+                continue;
+            };
+            let entry = conflict.entry();
+            match self.resolve(cluster, &entry, left, right) {
+                Ok((mut ops, resolution)) => {
+                    // Only the conflicting file was seen by the diff; names
+                    // made up for keeping both aren't expected to exist.
+                    for op in ops.iter_mut().filter(|op| op.rel_path == entry.rel_path) {
+                        stamp(std::slice::from_mut(op), left, right, &entry);
+                    }
+                    plan.operations.extend(ops);
+                    resolutions.push(resolution);
+                }
+                Err(e) => {
+                    tracing::warn!("conflict left unresolved: {}: {:#}", conflict.rel_path.display(), e);
+                    plan.operations.push(op);
+                }
+            }
         }
-        ConflictStrategy::Interactive => {
-            resolve_interactive(entry, left_drive, right_drive)
+        plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+        resolutions
+    }
+
+    pub fn resolve(
+        &mut self,
+        cluster: &Cluster,
+        entry: &DiffEntry,
+        left_drive: &Drive,
+        right_drive: &Drive,
+    ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
+        let (mut ops, resolution, how) = self.resolve_by(cluster, entry, left_drive, right_drive)?;
+        let reason = format!("{}: {} {}", entry.kind, resolution.strategy_used, how);
+        for op in &mut ops {
+            op.reason = Some(reason.clone());
+        }
+        Ok((ops, resolution))
+    }
+
+    /// Resolve the conflict, also saying what chose how: the merge command,
+    /// a conflict rule or the cluster's strategy.
+    fn resolve_by(
+        &mut self,
+        cluster: &Cluster,
+        entry: &DiffEntry,
+        left_drive: &Drive,
+        right_drive: &Drive,
+    ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution, String)> {
+        if let Some((ext, command)) = by_extension(&self.merge_commands, entry.rel_path) {
+            let (ops, resolution) = resolve_merge(entry, left_drive, right_drive);
+            return Ok((ops, resolution, format!("with `{}`, the merge command for {}", command, ext)));
+        }
+        let how = match by_extension(&cluster.settings.conflict_rules, entry.rel_path) {
+            Some((ext, _)) => format!("by the conflict rule for {}", ext),
+            None => "by the cluster's strategy".to_string(),
+        };
+        let (ops, resolution) = self.resolve_with(cluster, entry, left_drive, right_drive)?;
+        Ok((ops, resolution, how))
+    }
+
+    fn resolve_with(
+        &mut self,
+        cluster: &Cluster,
+        entry: &DiffEntry,
+        left_drive: &Drive,
+        right_drive: &Drive,
+    ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
+        match cluster.conflict_strategy_for(entry.rel_path) {
+            ConflictStrategy::NewestWins => {
+                resolve_newest_wins(entry, left_drive, right_drive)
+            }
+            ConflictStrategy::KeepBoth => {
+                resolve_keep_both(entry, left_drive, right_drive)
+            }
+            ConflictStrategy::Interactive => {
+                let choice = match (self.apply_to_all, self.ask.as_mut()) {
+                    (Some(choice), _) => choice,
+                    (None, None) => anyhow::bail!("the conflict strategy is interactive and there's nobody to ask"),
+                    (None, Some(ask)) => {
+                        let (choice, all) = ask(entry, left_drive, right_drive)?;
+                        if all {
+                            self.apply_to_all = Some(choice);
+                        }
+                        choice
+                    }
+                };
+                match choice {
+                    ConflictChoice::Left => resolve_newest_wins_with_winner(entry, left_drive, right_drive),
+                    ConflictChoice::Right => resolve_newest_wins_with_winner(entry, right_drive, left_drive),
+                    ConflictChoice::Both => resolve_keep_both(entry, left_drive, right_drive),
+                }
+            }
         }
     }
 }
@@ -33,16 +227,16 @@ fn resolve_newest_wins(
     left_drive: &Drive,
     right_drive: &Drive,
 ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
-    let left_mtime = entry.left.as_ref().map(|e| e.mtime);
-    let right_mtime = entry.right.as_ref().map(|e| e.mtime);
+    let left_mtime = entry.left.map(|e| e.mtime);
+    let right_mtime = entry.right.map(|e| e.mtime);
 
     let (winner, loser, size) = match (left_mtime, right_mtime) {
         (Some(l), Some(r)) if l >= r => {
-            let size = entry.left.as_ref().map(|e| e.size).unwrap_or(0);
+            let size = entry.left.map(|e| e.size).unwrap_or(0);
             (left_drive, right_drive, size)
         }
         _ => {
-            let size = entry.right.as_ref().map(|e| e.size).unwrap_or(0);
+            let size = entry.right.map(|e| e.size).unwrap_or(0);
             (right_drive, left_drive, size)
         }
     };
@@ -50,14 +244,17 @@ fn resolve_newest_wins(
     let op = SyncOp {
         id: Uuid::now_v7(),
         kind: SyncOpKind::Overwrite,
-        rel_path: entry.rel_path.clone(),
+        rel_path: entry.rel_path.to_path_buf(),
         source_drive: Some(winner.id.clone()),
         target_drive: loser.id.clone(),
         size_bytes: size,
+        source_path: None,
+        reason: None,
+        expect: None,
     };
 
     let resolution = ConflictResolution {
-        rel_path: entry.rel_path.clone(),
+        rel_path: entry.rel_path.to_path_buf(),
         winner_drive: winner.id.clone(),
         loser_drive: loser.id.clone(),
         strategy_used: "newest_wins".to_string(),
@@ -72,44 +269,60 @@ fn resolve_keep_both(
     left_drive: &Drive,
     right_drive: &Drive,
 ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
-    // Generate a conflict name: file.txt -> file.conflict-<drive-label>.txt
-    let conflict_name = generate_conflict_name(&entry.rel_path, right_drive);
+    // Generate a conflict name: file.txt -> file.conflict-<label>-<time>.txt
+    let conflict_name = generate_conflict_name(
+        entry.rel_path,
+        right_drive,
+        Utc::now(),
+        &[left_drive.effective_root(), right_drive.effective_root()],
+    );
 
-    let left_size = entry.left.as_ref().map(|e| e.size).unwrap_or(0);
-    let right_size = entry.right.as_ref().map(|e| e.size).unwrap_or(0);
+    let left_size = entry.left.map(|e| e.size).unwrap_or(0);
+    let right_size = entry.right.map(|e| e.size).unwrap_or(0);
 
+    // Order matters: the right version is set aside under the conflict
+    // name before the left version overwrites it.
     let ops = vec![
-        // Copy left version to right (overwrite)
+        // Keep the right version on the right drive under the conflict name
         SyncOp {
             id: Uuid::now_v7(),
-            kind: SyncOpKind::Overwrite,
-            rel_path: entry.rel_path.clone(),
-            source_drive: Some(left_drive.id.clone()),
+            kind: SyncOpKind::LocalCopy,
+            rel_path: conflict_name.clone(),
+            source_drive: Some(right_drive.id.clone()),
             target_drive: right_drive.id.clone(),
-            size_bytes: left_size,
+            size_bytes: right_size,
+            source_path: Some(entry.rel_path.to_path_buf()),
+            reason: None,
+            expect: None,
         },
-        // Copy right version to left under conflict name
+        // Copy it to the left drive under the same name
         SyncOp {
             id: Uuid::now_v7(),
             kind: SyncOpKind::CopyNew,
-            rel_path: conflict_name.clone(),
+            rel_path: conflict_name,
             source_drive: Some(right_drive.id.clone()),
             target_drive: left_drive.id.clone(),
             size_bytes: right_size,
+            source_path: None,
+            reason: None,
+            expect: None,
         },
-        // Also keep conflict name on right
+        // Then copy the left version over the original on the right
         SyncOp {
             id: Uuid::now_v7(),
-            kind: SyncOpKind::CopyNew,
-            rel_path: conflict_name,
-            source_drive: Some(right_drive.id.clone()),
+            kind: SyncOpKind::Overwrite,
+            rel_path: entry.rel_path.to_path_buf(),
+            source_drive: Some(left_drive.id.clone()),
             target_drive: right_drive.id.clone(),
-            size_bytes: right_size,
+            size_bytes: left_size,
+            source_path: None,
+            reason: None,
+            expect: None,
         },
     ];
 
     let resolution = ConflictResolution {
-        rel_path: entry.rel_path.clone(),
+        rel_path: entry.rel_path.to_path_buf(),
         winner_drive: left_drive.id.clone(),
         loser_drive: right_drive.id.clone(),
         strategy_used: "keep_both".to_string(),
@@ -119,44 +332,60 @@ fn resolve_keep_both(
     Ok((ops, resolution))
 }
 
-fn resolve_interactive(
-    entry: &DiffEntry,
-    left_drive: &Drive,
-    right_drive: &Drive,
-) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
-    println!("\nConflict: {}", entry.rel_path.display());
-    if let Some(ref left) = entry.left {
-        println!(
-            "  [L] {} — size: {}, modified: {}",
-            left_drive.mount_point.display(),
-            left.size,
-            left.mtime
-        );
-    }
-    if let Some(ref right) = entry.right {
-        println!(
-            "  [R] {} — size: {}, modified: {}",
-            right_drive.mount_point.display(),
-            right.size,
-            right.mtime
-        );
-    }
-    print!("Choose [L]eft, [R]ight, or [B]oth: ");
-    io::stdout().flush()?;
+/// Merge both versions, which the sync does when it runs the plan: both
+/// are archived and replaced by the merge command's output. If the command
+/// fails, the op does and both versions stay as they are.
+fn resolve_merge(entry: &DiffEntry, left_drive: &Drive, right_drive: &Drive) -> (Vec<SyncOp>, ConflictResolution) {
+    let size = |side: Option<&FileEntry>| side.map_or(0, |e| e.size);
+    let op = SyncOp {
+        id: Uuid::now_v7(),
+        kind: SyncOpKind::Merge,
+        rel_path: entry.rel_path.to_path_buf(),
+        source_drive: Some(left_drive.id.clone()),
+        target_drive: right_drive.id.clone(),
+        size_bytes: size(entry.left).max(size(entry.right)),
+        source_path: None,
+        reason: None,
+        expect: None,
+    };
 
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let choice = input.trim().to_lowercase();
+    let resolution = ConflictResolution {
+        rel_path: entry.rel_path.to_path_buf(),
+        winner_drive: left_drive.id.clone(),
+        loser_drive: right_drive.id.clone(),
+        strategy_used: "merge".to_string(),
+        resolved_at: Utc::now(),
+    };
 
-    match choice.as_str() {
-        "l" | "left" => resolve_newest_wins_with_winner(entry, left_drive, right_drive),
-        "r" | "right" => resolve_newest_wins_with_winner(entry, right_drive, left_drive),
-        "b" | "both" => resolve_keep_both(entry, left_drive, right_drive),
-        _ => {
-            println!("Invalid choice, defaulting to keep-both");
-            resolve_keep_both(entry, left_drive, right_drive)
-        }
+    (vec![op], resolution)
+}
+
+/// Run a merge command on two files, as `<command> <left> <right>`, and
+/// return what it printed. It fails if the command exits with an error or
+/// prints nothing, which would otherwise empty both files.
+pub(crate) fn run_merge_command(command: &str, left: &Path, right: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty merge command"))?;
+    let output = std::process::Command::new(program)
+        .args(parts)
+        .arg(left)
+        .arg(right)
+        .stdin(std::process::Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
     }
+    if output.stdout.is_empty() {
+        anyhow::bail!("{} printed nothing", program);
+    }
+    Ok(output.stdout)
 }
 
 fn resolve_newest_wins_with_winner(
@@ -164,24 +393,22 @@ fn resolve_newest_wins_with_winner(
     winner: &Drive,
     loser: &Drive,
 ) -> anyhow::Result<(Vec<SyncOp>, ConflictResolution)> {
-    let size = entry
-        .left
-        .as_ref()
-        .or(entry.right.as_ref())
-        .map(|e| e.size)
-        .unwrap_or(0);
+    let size = entry.left.or(entry.right).map(|e| e.size).unwrap_or(0);
 
     let op = SyncOp {
         id: Uuid::now_v7(),
         kind: SyncOpKind::Overwrite,
-        rel_path: entry.rel_path.clone(),
+        rel_path: entry.rel_path.to_path_buf(),
         source_drive: Some(winner.id.clone()),
         target_drive: loser.id.clone(),
         size_bytes: size,
+        source_path: None,
+        reason: None,
+        expect: None,
     };
 
     let resolution = ConflictResolution {
-        rel_path: entry.rel_path.clone(),
+        rel_path: entry.rel_path.to_path_buf(),
         winner_drive: winner.id.clone(),
         loser_drive: loser.id.clone(),
         strategy_used: "interactive".to_string(),
@@ -191,21 +418,234 @@ fn resolve_newest_wins_with_winner(
     Ok((vec![op], resolution))
 }
 
-/// Generate a conflict-renamed path.
-/// `file.txt` -> `file.conflict-<label>.txt`
-fn generate_conflict_name(path: &PathBuf, drive: &Drive) -> PathBuf {
-    let stem = path
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_default();
-    let ext = path
-        .extension()
-        .map(|s| format!(".{}", s.to_string_lossy()))
-        .unwrap_or_default();
+/// Generate a conflict-renamed path that is free on every root in `roots`:
+/// `file.txt` -> `file.conflict-<label>-<timestamp>.txt`, with `-2`, `-3`
+/// and so on appended if that name is already taken.
+fn generate_conflict_name(
+    path: &Path,
+    drive: &Drive,
+    at: DateTime<Utc>,
+    roots: &[&Path],
+) -> PathBuf {
+    // Built as an `OsString` so a name that isn't UTF-8 keeps its bytes.
+    let stem = path.file_stem().unwrap_or_default();
+    let ext = path.extension();
     let label = drive
         .label
         .as_deref()
-        .unwrap_or("unknown");
-    let conflict_name = format!("{}.conflict-{}{}", stem, label, ext);
-    path.with_file_name(conflict_name)
+        .map(sanitize_label)
+        .filter(|l| !l.is_empty())
+        .unwrap_or_else(|| drive.identity.identity_string().chars().take(8).collect());
+    let tag = format!(".conflict-{}-{}", label, at.format("%Y%m%dT%H%M%S"));
+
+    let mut n = 1;
+    loop {
+        let mut name = stem.to_os_string();
+        name.push(&tag);
+        if n > 1 {
+            name.push(format!("-{}", n));
+        }
+        if let Some(ext) = ext {
+            name.push(".");
+            name.push(ext);
+        }
+        let candidate = path.with_file_name(name);
+        if !roots.iter().any(|r| r.join(&candidate).exists()) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Make a drive label safe to use in a file name on any platform.
+fn sanitize_label(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_keep_both_keeps_both_versions() {
+        use crate::diff::DiffKind;
+        use crate::executor::{execute_plan, ExecConfig};
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::sync_state::SyncPlan;
+
+        let (ldir, rdir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(ldir.path().join("a.txt"), "left").unwrap();
+        std::fs::write(rdir.path().join("a.txt"), "right").unwrap();
+        let left = Drive::new(DriveIdentity::new_synthetic(), ldir.path().to_path_buf());
+        let mut right = Drive::new(DriveIdentity::new_synthetic(), rdir.path().to_path_buf());
+        right.label = Some("R".into());
+
+        let entry = DiffEntry {
+            rel_path: Path::new("a.txt"),
+            kind: DiffKind::Conflict,
+            left: None,
+            right: None,
+        };
+        let (ops, _) = resolve_keep_both(&entry, &left, &right).unwrap();
+        let conflict = ops[0].rel_path.clone();
+        assert_eq!(ops[0].kind, SyncOpKind::LocalCopy);
+
+        let config = ExecConfig::default();
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        execute_plan(&plan, &[left, right], &config).unwrap();
+
+        for dir in [&ldir, &rdir] {
+            assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "left");
+            assert_eq!(std::fs::read_to_string(dir.path().join(&conflict)).unwrap(), "right");
+        }
+    }
+
+    #[test]
+    fn test_interactive_conflicts_go_to_the_asker_until_it_answers_for_all() {
+        use crate::diff::DiffKind;
+        use diffr_core::models::cluster::Topology;
+
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::Interactive);
+        let left = Drive::new(DriveIdentity::new_synthetic(), "/l".into());
+        let right = Drive::new(DriveIdentity::new_synthetic(), "/r".into());
+        let entry = |path: &'static str| DiffEntry {
+            rel_path: Path::new(path),
+            kind: DiffKind::Conflict,
+            left: None,
+            right: None,
+        };
+
+        let err = ConflictResolver::default().resolve(&cluster, &entry("a"), &left, &right);
+        assert!(err.is_err());
+
+        let mut asked = Vec::new();
+        let mut resolver = ConflictResolver::default().asking(|entry, _, _| {
+            asked.push(entry.rel_path.to_path_buf());
+            Ok((ConflictChoice::Right, entry.rel_path == Path::new("b")))
+        });
+        for path in ["a", "b", "c"] {
+            let (ops, resolution) = resolver.resolve(&cluster, &entry(path), &left, &right).unwrap();
+            assert_eq!(ops[0].target_drive, left.id);
+            assert_eq!(resolution.winner_drive, right.id);
+        }
+        drop(resolver);
+        assert_eq!(asked, [PathBuf::from("a"), PathBuf::from("b")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_merge_command_output_replaces_both() {
+        use crate::diff::DiffKind;
+        use crate::executor::{execute_plan, ExecConfig};
+        use diffr_core::models::cluster::{ClusterId, Topology};
+        use diffr_core::models::sync_state::SyncPlan;
+
+        let (ldir, rdir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(ldir.path().join("a.ics"), "left\n").unwrap();
+        std::fs::write(rdir.path().join("a.ics"), "right\n").unwrap();
+        let left = Drive::new(DriveIdentity::new_synthetic(), ldir.path().to_path_buf());
+        let right = Drive::new(DriveIdentity::new_synthetic(), rdir.path().to_path_buf());
+        let drives = [left.clone(), right.clone()];
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::KeepBoth);
+        let entry = DiffEntry {
+            rel_path: Path::new("a.ics"),
+            kind: DiffKind::Conflict,
+            left: None,
+            right: None,
+        };
+
+        let commands = BTreeMap::from([(".ics".to_string(), "cat".to_string())]);
+        let mut resolver = ConflictResolver::with_merge_commands(commands.clone());
+        let (ops, resolution) = resolver.resolve(&cluster, &entry, &left, &right).unwrap();
+        assert_eq!(resolution.strategy_used, "merge");
+        assert_eq!(ops[0].kind, SyncOpKind::Merge);
+        assert_eq!(
+            ops[0].reason,
+            Some("conflict: merge with `cat`, the merge command for .ics".to_string())
+        );
+        // Nothing is merged until the plan runs.
+        assert_eq!(std::fs::read_to_string(ldir.path().join("a.ics")).unwrap(), "left\n");
+
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        let config = ExecConfig {
+            merge_commands: commands,
+            ..ExecConfig::default()
+        };
+        let result = execute_plan(&plan, &drives, &config).unwrap();
+        for dir in [&ldir, &rdir] {
+            assert_eq!(std::fs::read_to_string(dir.path().join("a.ics")).unwrap(), "left\nright\n");
+        }
+        // Both versions were archived, and both sides can be undone.
+        assert_eq!(result.archives.len(), 2);
+        let undone: Vec<DriveId> = result.journal.iter().map(|e| e.op.target_drive.clone()).collect();
+        assert_eq!(undone, [right.id, left.id]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_merge_printing_nothing_fails() {
+        use crate::diff::DiffKind;
+        use crate::executor::{execute_plan, ExecConfig};
+        use diffr_core::models::cluster::{ClusterId, Topology};
+        use diffr_core::models::sync_state::{SyncPlan, SyncStatus};
+
+        let (ldir, rdir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(ldir.path().join("a.txt"), "left").unwrap();
+        std::fs::write(rdir.path().join("a.txt"), "right").unwrap();
+        let left = Drive::new(DriveIdentity::new_synthetic(), ldir.path().to_path_buf());
+        let right = Drive::new(DriveIdentity::new_synthetic(), rdir.path().to_path_buf());
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::KeepBoth);
+        let entry = DiffEntry {
+            rel_path: Path::new("a.txt"),
+            kind: DiffKind::Conflict,
+            left: None,
+            right: None,
+        };
+
+        // `true` exits 0 without printing a merged file.
+        let commands = BTreeMap::from([("txt".to_string(), "true".to_string())]);
+        let (ops, _) = ConflictResolver::with_merge_commands(commands.clone())
+            .resolve(&cluster, &entry, &left, &right)
+            .unwrap();
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        let config = ExecConfig {
+            merge_commands: commands,
+            ..ExecConfig::default()
+        };
+        let result = execute_plan(&plan, &[left, right], &config).unwrap();
+        assert_eq!(result.record.status, SyncStatus::Failed);
+        assert!(result.record.errors[0].contains("printed nothing"), "{:?}", result.record.errors);
+        assert!(result.archives.is_empty());
+        assert_eq!(std::fs::read_to_string(ldir.path().join("a.txt")).unwrap(), "left");
+        assert_eq!(std::fs::read_to_string(rdir.path().join("a.txt")).unwrap(), "right");
+    }
+
+    #[test]
+    fn test_conflict_name_is_safe_and_unique() {
+        let dir = TempDir::new().unwrap();
+        let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        drive.label = Some("My Disk: 2/3".into());
+        let at = "2026-03-01T12:30:00Z".parse().unwrap();
+
+        let name = generate_conflict_name(Path::new("docs/a.txt"), &drive, at, &[dir.path()]);
+        assert_eq!(name, PathBuf::from("docs/a.conflict-My_Disk__2_3-20260301T123000.txt"));
+
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join(&name), "taken").unwrap();
+        let next = generate_conflict_name(Path::new("docs/a.txt"), &drive, at, &[dir.path()]);
+        assert_eq!(next, PathBuf::from("docs/a.conflict-My_Disk__2_3-20260301T123000-2.txt"));
+    }
 }
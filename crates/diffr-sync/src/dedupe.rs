@@ -0,0 +1,143 @@
+use diffr_core::models::file_entry::FileEntry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// File and byte counts for one slice of the catalog.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DedupeTotals {
+    pub files: u64,
+    pub bytes: u64,
+    /// Bytes in files whose content is already stored somewhere else.
+    pub duplicate_bytes: u64,
+}
+
+/// How much of the cataloged data is unique and how much is duplicated.
+#[derive(Debug, Default)]
+pub struct DedupeReport {
+    /// Every hashed file.
+    pub total: DedupeTotals,
+    /// One copy of each distinct content.
+    pub unique_bytes: u64,
+    /// Extra copies inside a cluster, which is what syncing is for.
+    pub replica_bytes: u64,
+    /// Extra copies of content that more than one cluster stores.
+    pub cross_cluster_bytes: u64,
+    /// Files left out because they weren't hashed.
+    pub unhashed: DedupeTotals,
+    /// Totals by cluster name and directory.
+    pub groups: BTreeMap<(String, PathBuf), DedupeTotals>,
+}
+
+/// Group cataloged files by content hash and size and total up the
+/// duplicates, labelled by cluster name.
+///
+/// In each group, the first copy by cluster, path and drive counts as the
+/// original and every other copy as a duplicate. Directories are grouped
+/// `depth` levels deep; files above that level go under their own
+/// directory, and root-level files under `.`.
+pub fn build_report(files: &[(String, FileEntry)], depth: usize) -> DedupeReport {
+    let mut report = DedupeReport::default();
+    let mut by_content: HashMap<(&str, u64), Vec<(&str, &FileEntry)>> = HashMap::new();
+    for (cluster, entry) in files.iter().filter(|(_, e)| !e.is_dir) {
+        match &entry.xxh3_hash {
+            Some(hash) => by_content
+                .entry((hash.as_str(), entry.size))
+                .or_default()
+                .push((cluster.as_str(), entry)),
+            None => {
+                report.unhashed.files += 1;
+                report.unhashed.bytes += entry.size;
+            }
+        }
+    }
+
+    for ((_, size), mut copies) in by_content {
+        copies.sort_by(|(ca, a), (cb, b)| {
+            (ca, &a.rel_path, &a.drive_id.0).cmp(&(cb, &b.rel_path, &b.drive_id.0))
+        });
+        let clusters: HashSet<&str> = copies.iter().map(|(c, _)| *c).collect();
+        let extra = (copies.len() - 1) as u64;
+        let extra_clusters = (clusters.len() - 1) as u64;
+        report.unique_bytes += size;
+        report.cross_cluster_bytes += extra_clusters * size;
+        report.replica_bytes += (extra - extra_clusters) * size;
+
+        for (i, (cluster, entry)) in copies.iter().enumerate() {
+            let key = (cluster.to_string(), group_dir(&entry.rel_path, depth));
+            let group = report.groups.entry(key).or_default();
+            group.files += 1;
+            group.bytes += size;
+            report.total.files += 1;
+            report.total.bytes += size;
+            if i > 0 {
+                group.duplicate_bytes += size;
+                report.total.duplicate_bytes += size;
+            }
+        }
+    }
+    report
+}
+
+/// The first `depth` directories of a file's parent, or `.` for files at
+/// the root.
+pub(crate) fn group_dir(rel_path: &Path, depth: usize) -> PathBuf {
+    let dir: PathBuf = rel_path
+        .parent()
+        .map(|p| p.components().take(depth).collect())
+        .unwrap_or_default();
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use diffr_core::models::drive::DriveId;
+
+    fn file(drive: &DriveId, path: &str, size: u64, hash: Option<&str>) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: hash.map(String::from),
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let (a, b, c) = (DriveId::new(), DriveId::new(), DriveId::new());
+        let files = vec![
+            // Replicated on both drives of "docs", and also stored in "media".
+            ("docs".to_string(), file(&a, "work/report.pdf", 100, Some("01"))),
+            ("docs".to_string(), file(&b, "work/report.pdf", 100, Some("01"))),
+            ("media".to_string(), file(&c, "old/docs/report.pdf", 100, Some("01"))),
+            ("media".to_string(), file(&c, "song.mp3", 40, Some("02"))),
+            ("media".to_string(), file(&c, "notes.txt", 5, None)),
+        ];
+
+        let report = build_report(&files, 1);
+        assert_eq!(report.total.files, 4);
+        assert_eq!(report.total.bytes, 340);
+        assert_eq!(report.unique_bytes, 140);
+        assert_eq!(report.replica_bytes, 100);
+        assert_eq!(report.cross_cluster_bytes, 100);
+        assert_eq!(report.total.duplicate_bytes, 200);
+        assert_eq!(report.unhashed.files, 1);
+
+        let group = |cluster: &str, dir: &str| &report.groups[&(cluster.to_string(), dir.into())];
+        assert_eq!(group("docs", "work").duplicate_bytes, 100);
+        assert_eq!(group("media", "old").duplicate_bytes, 100);
+        assert_eq!(group("media", ".").bytes, 40);
+    }
+}
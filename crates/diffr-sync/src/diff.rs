@@ -1,14 +1,46 @@
+use chrono::{DateTime, Utc};
+use diffr_core::config::HashAlgorithm;
+use diffr_core::models::cluster::ClusterSettings;
 use diffr_core::models::file_entry::FileEntry;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
 
-/// The result of comparing two file trees.
+/// The result of comparing two file trees, borrowing the entries it
+/// compared.
 #[derive(Debug, Clone)]
-pub struct DiffEntry {
-    pub rel_path: PathBuf,
+pub struct DiffEntry<'a> {
+    pub rel_path: &'a Path,
     pub kind: DiffKind,
-    pub left: Option<FileEntry>,
-    pub right: Option<FileEntry>,
+    pub left: Option<&'a FileEntry>,
+    pub right: Option<&'a FileEntry>,
+}
+
+impl DiffEntry<'_> {
+    /// Whether this entry is a directory (on whichever side has it).
+    pub fn is_dir(&self) -> bool {
+        self.left.or(self.right).is_some_and(|e| e.is_dir)
+    }
+
+    /// Turn a `Modified` entry into a `Conflict` if both sides changed
+    /// since the last sync, given what the catalog recorded for each side
+    /// then. A file the last sync didn't leave the same on both sides, or
+    /// that changed on only one, stays `Modified`.
+    pub fn check_history(&mut self, left_before: Option<&FileEntry>, right_before: Option<&FileEntry>) {
+        let (Some(left), Some(right), Some(left_before), Some(right_before)) =
+            (self.left, self.right, left_before, right_before)
+        else {
+            return;
+        };
+        if self.kind == DiffKind::Modified
+            && classify_pair(left_before, right_before) == DiffKind::Identical
+            && classify_pair(left_before, left) != DiffKind::Identical
+            && classify_pair(right_before, right) != DiffKind::Identical
+        {
+            self.kind = DiffKind::Conflict;
+        }
+    }
 }
 
 /// Classification of a diff entry.
@@ -22,6 +54,9 @@ pub enum DiffKind {
     Modified,
     /// Both sides modified since last sync — conflict.
     Conflict,
+    /// Contents match but permission bits (or, if synced, ownership)
+    /// differ. Only reported when `DiffOptions::permissions` is set.
+    PermissionsChanged,
     /// Files are identical.
     Identical,
 }
@@ -33,60 +68,153 @@ impl std::fmt::Display for DiffKind {
             DiffKind::OnlyRight => write!(f, "only_right"),
             DiffKind::Modified => write!(f, "modified"),
             DiffKind::Conflict => write!(f, "conflict"),
+            DiffKind::PermissionsChanged => write!(f, "permissions_changed"),
             DiffKind::Identical => write!(f, "identical"),
         }
     }
 }
 
+/// What besides content counts as a difference.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+    /// Report files whose permission bits differ as `PermissionsChanged`.
+    pub permissions: bool,
+    /// With `permissions`, owner and group count too.
+    pub ownership: bool,
+}
+
+impl DiffOptions {
+    pub fn for_cluster(settings: &ClusterSettings) -> Self {
+        Self {
+            permissions: settings.sync_permissions || settings.sync_ownership,
+            ownership: settings.sync_ownership,
+        }
+    }
+}
+
 /// Compare two sets of file entries and produce a diff.
 ///
 /// `left` and `right` are the file entries from two different drives.
 /// Entries are matched by relative path.
-pub fn compute_diff(left: &[FileEntry], right: &[FileEntry]) -> Vec<DiffEntry> {
-    let left_map: HashMap<&PathBuf, &FileEntry> =
-        left.iter().map(|e| (&e.rel_path, e)).collect();
-    let right_map: HashMap<&PathBuf, &FileEntry> =
-        right.iter().map(|e| (&e.rel_path, e)).collect();
-
-    let mut diffs = Vec::new();
-
-    // Check all left entries
-    for (path, left_entry) in &left_map {
-        match right_map.get(path) {
-            Some(right_entry) => {
-                let kind = classify_pair(left_entry, right_entry);
-                diffs.push(DiffEntry {
-                    rel_path: (*path).clone(),
-                    kind,
-                    left: Some((*left_entry).clone()),
-                    right: Some((*right_entry).clone()),
-                });
-            }
-            None => {
-                diffs.push(DiffEntry {
-                    rel_path: (*path).clone(),
+pub fn compute_diff<'a>(left: &'a [FileEntry], right: &'a [FileEntry]) -> Vec<DiffEntry<'a>> {
+    compute_diff_with(left, right, DiffOptions::default())
+}
+
+/// [`compute_diff`], also comparing what `options` asks for.
+pub fn compute_diff_with<'a>(
+    left: &'a [FileEntry],
+    right: &'a [FileEntry],
+    options: DiffOptions,
+) -> Vec<DiffEntry<'a>> {
+    diff_sorted(sorted_by_path(left), sorted_by_path(right), options).collect()
+}
+
+/// References to `entries` in path order, the order [`diff_sorted`] wants.
+/// Sorting references leaves the entries themselves where they are.
+pub fn sorted_by_path(entries: &[FileEntry]) -> Vec<&FileEntry> {
+    let mut sorted: Vec<&FileEntry> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+    sorted
+}
+
+/// Compare two streams of file entries, each already sorted by
+/// `rel_path`, yielding one [`DiffEntry`] per path in path order.
+///
+/// The streams are merge-joined, and each [`DiffEntry`] borrows the entries
+/// it compared, so nothing is copied and the consumer decides what to keep.
+/// A scan's entries come in the order this wants (see
+/// [`ScanResult::entries`]); other entries can go through
+/// [`sorted_by_path`]. `Path` order isn't SQL's text order for encoded
+/// paths (`a.txt` sorts before `a/b` there). If a path repeats on one side,
+/// its last entry is used.
+///
+/// [`ScanResult::entries`]: diffr_scan::scanner::ScanResult::entries
+pub fn diff_sorted<'a, L, R>(left: L, right: R, options: DiffOptions) -> DiffStream<L::IntoIter, R::IntoIter>
+where
+    L: IntoIterator<Item = &'a FileEntry>,
+    R: IntoIterator<Item = &'a FileEntry>,
+{
+    DiffStream {
+        left: left.into_iter().peekable(),
+        right: right.into_iter().peekable(),
+        options,
+    }
+}
+
+/// The iterator returned by [`diff_sorted`].
+pub struct DiffStream<L: Iterator, R: Iterator> {
+    left: Peekable<L>,
+    right: Peekable<R>,
+    options: DiffOptions,
+}
+
+impl<'a, L, R> Iterator for DiffStream<L, R>
+where
+    L: Iterator<Item = &'a FileEntry>,
+    R: Iterator<Item = &'a FileEntry>,
+{
+    type Item = DiffEntry<'a>;
+
+    fn next(&mut self) -> Option<DiffEntry<'a>> {
+        let order = match (self.left.peek(), self.right.peek()) {
+            (None, None) => return None,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(l), Some(r)) => l.rel_path.cmp(&r.rel_path),
+        };
+        let entry = match order {
+            Ordering::Less => {
+                let left = next_distinct(&mut self.left)?;
+                DiffEntry {
+                    rel_path: &left.rel_path,
                     kind: DiffKind::OnlyLeft,
-                    left: Some((*left_entry).clone()),
+                    left: Some(left),
                     right: None,
-                });
+                }
             }
-        }
+            Ordering::Greater => {
+                let right = next_distinct(&mut self.right)?;
+                DiffEntry {
+                    rel_path: &right.rel_path,
+                    kind: DiffKind::OnlyRight,
+                    left: None,
+                    right: Some(right),
+                }
+            }
+            Ordering::Equal => {
+                let (left, right) = (next_distinct(&mut self.left)?, next_distinct(&mut self.right)?);
+                DiffEntry {
+                    rel_path: &left.rel_path,
+                    kind: classify_with(left, right, self.options),
+                    left: Some(left),
+                    right: Some(right),
+                }
+            }
+        };
+        Some(entry)
     }
+}
 
-    // Check right-only entries
-    for (path, right_entry) in &right_map {
-        if !left_map.contains_key(path) {
-            diffs.push(DiffEntry {
-                rel_path: (*path).clone(),
-                kind: DiffKind::OnlyRight,
-                left: None,
-                right: Some((*right_entry).clone()),
-            });
-        }
+/// The next entry, skipping ahead to the last of any run with its path.
+fn next_distinct<'a>(entries: &mut Peekable<impl Iterator<Item = &'a FileEntry>>) -> Option<&'a FileEntry> {
+    let mut entry = entries.next()?;
+    while let Some(next) = entries.next_if(|e| e.rel_path == entry.rel_path) {
+        entry = next;
     }
+    Some(entry)
+}
 
-    diffs.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
-    diffs
+/// Classify a pair, counting permissions as `options` says.
+fn classify_with(left: &FileEntry, right: &FileEntry, options: DiffOptions) -> DiffKind {
+    let kind = classify_pair(left, right);
+    if kind == DiffKind::Identical && options.permissions && !left.is_dir {
+        if let (Some(lp), Some(rp)) = (&left.permissions, &right.permissions) {
+            if lp.differs(rp, options.ownership) {
+                return DiffKind::PermissionsChanged;
+            }
+        }
+    }
+    kind
 }
 
 /// Classify a pair of files that exist on both drives.
@@ -96,6 +224,17 @@ fn classify_pair(left: &FileEntry, right: &FileEntry) -> DiffKind {
         return DiffKind::Identical;
     }
 
+    // A verification hash both sides have is the strongest evidence
+    for algorithm in HashAlgorithm::ALL {
+        if let (Some(lh), Some(rh)) = (left.strong_hash(algorithm), right.strong_hash(algorithm)) {
+            return if lh == rh {
+                DiffKind::Identical
+            } else {
+                DiffKind::Modified
+            };
+        }
+    }
+
     // If hashes are available, compare by hash
     if let (Some(lh), Some(rh)) = (&left.xxh3_hash, &right.xxh3_hash) {
         if lh == rh {
@@ -106,25 +245,38 @@ fn classify_pair(left: &FileEntry, right: &FileEntry) -> DiffKind {
         return DiffKind::Modified;
     }
 
+    // Sampled hashes: a mismatch is a sure change, a match is taken on trust
+    if let (Some(ls), Some(rs)) = (&left.sampled_hash, &right.sampled_hash) {
+        return if ls == rs {
+            DiffKind::Identical
+        } else {
+            DiffKind::Modified
+        };
+    }
+
     // Fall back to metadata comparison
-    if left.size == right.size && left.mtime == right.mtime {
+    if left.size == right.size && same_mtime(left.mtime, right.mtime) {
         DiffKind::Identical
     } else {
         DiffKind::Modified
     }
 }
 
+/// Whether two modification times match, to the second when either side
+/// keeps no finer, as MTP devices don't.
+fn same_mtime(left: DateTime<Utc>, right: DateTime<Utc>) -> bool {
+    if left.timestamp_subsec_nanos() == 0 || right.timestamp_subsec_nanos() == 0 {
+        left.timestamp() == right.timestamp()
+    } else {
+        left == right
+    }
+}
+
 /// Count the diff entries by kind.
 pub fn diff_summary(diffs: &[DiffEntry]) -> DiffSummary {
     let mut summary = DiffSummary::default();
     for d in diffs {
-        match d.kind {
-            DiffKind::OnlyLeft => summary.only_left += 1,
-            DiffKind::OnlyRight => summary.only_right += 1,
-            DiffKind::Modified => summary.modified += 1,
-            DiffKind::Conflict => summary.conflicts += 1,
-            DiffKind::Identical => summary.identical += 1,
-        }
+        summary.add(d);
     }
     summary
 }
@@ -135,16 +287,29 @@ pub struct DiffSummary {
     pub only_right: usize,
     pub modified: usize,
     pub conflicts: usize,
+    pub permissions: usize,
     pub identical: usize,
 }
 
 impl DiffSummary {
+    /// Count one more entry, for tallying a [`DiffStream`] as it goes.
+    pub fn add(&mut self, entry: &DiffEntry) {
+        match entry.kind {
+            DiffKind::OnlyLeft => self.only_left += 1,
+            DiffKind::OnlyRight => self.only_right += 1,
+            DiffKind::Modified => self.modified += 1,
+            DiffKind::Conflict => self.conflicts += 1,
+            DiffKind::PermissionsChanged => self.permissions += 1,
+            DiffKind::Identical => self.identical += 1,
+        }
+    }
+
     pub fn has_changes(&self) -> bool {
-        self.only_left > 0 || self.only_right > 0 || self.modified > 0 || self.conflicts > 0
+        self.total_changes() > 0
     }
 
     pub fn total_changes(&self) -> usize {
-        self.only_left + self.only_right + self.modified + self.conflicts
+        self.only_left + self.only_right + self.modified + self.conflicts + self.permissions
     }
 }
 
@@ -154,8 +319,60 @@ impl std::fmt::Display for DiffSummary {
             f,
             "{} identical, {} left-only, {} right-only, {} modified, {} conflicts",
             self.identical, self.only_left, self.only_right, self.modified, self.conflicts
-        )
+        )?;
+        if self.permissions > 0 {
+            write!(f, ", {} permission changes", self.permissions)?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences grouped under one top-level directory.
+#[derive(Debug, Default, Clone)]
+pub struct DirBreakdown {
+    /// Top-level directory name, or `None` for files directly under the root.
+    pub dir: Option<PathBuf>,
+    pub files: usize,
+    /// Bytes that differ: the larger side of each differing file.
+    pub bytes: u64,
+}
+
+/// Group the non-identical file entries by their top-level directory,
+/// sorted by differing bytes (largest first).
+pub fn breakdown_by_top_level(diffs: &[DiffEntry]) -> Vec<DirBreakdown> {
+    let mut groups: BTreeMap<Option<PathBuf>, DirBreakdown> = BTreeMap::new();
+
+    for d in diffs {
+        if d.kind == DiffKind::Identical {
+            continue;
+        }
+        if d.is_dir() {
+            continue;
+        }
+
+        let mut components = d.rel_path.components();
+        let first = components.next();
+        let dir = match (first, components.next()) {
+            (Some(c), Some(_)) => Some(PathBuf::from(c.as_os_str())),
+            _ => None,
+        };
+
+        let left = d.left.map(|e| e.size).unwrap_or(0);
+        let right = d.right.map(|e| e.size).unwrap_or(0);
+
+        let group = groups.entry(dir.clone()).or_insert_with(|| DirBreakdown {
+            dir,
+            ..Default::default()
+        });
+        group.files += 1;
+        if d.kind != DiffKind::PermissionsChanged {
+            group.bytes += left.max(right);
+        }
     }
+
+    let mut result: Vec<DirBreakdown> = groups.into_values().collect();
+    result.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.dir.cmp(&b.dir)));
+    result
 }
 
 #[cfg(test)]
@@ -172,7 +389,10 @@ mod tests {
             size,
             mtime: Utc::now(),
             xxh3_hash: None,
+            sampled_hash: None,
             sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
             indexed_at: Utc::now(),
         }
     }
@@ -187,6 +407,87 @@ mod tests {
         assert_eq!(diffs[0].kind, DiffKind::OnlyLeft);
     }
 
+    #[test]
+    fn test_changed_on_both_sides_is_a_conflict() {
+        let (d1, d2) = (DriveId::new(), DriveId::new());
+        let synced = Utc::now() - chrono::Duration::hours(1);
+        let at = |drive: &DriveId, size: u64, mtime| FileEntry {
+            mtime,
+            ..make_entry("a.txt", drive, size)
+        };
+        let (left_before, right_before) = (at(&d1, 1, synced), at(&d2, 1, synced));
+        let edited = |left: FileEntry, right: FileEntry| {
+            let (left, right) = ([left], [right]);
+            let mut entry = compute_diff(&left, &right).remove(0);
+            entry.check_history(Some(&left_before), Some(&right_before));
+            entry.kind
+        };
+
+        let now = Utc::now();
+        assert_eq!(edited(at(&d1, 2, now), at(&d2, 3, now)), DiffKind::Conflict);
+        // Only one side moved on since the sync.
+        assert_eq!(edited(at(&d1, 2, now), at(&d2, 1, synced)), DiffKind::Modified);
+        // Nothing to go on without a record of both sides.
+        let (left, right) = ([at(&d1, 2, now)], [at(&d2, 3, now)]);
+        let mut entry = compute_diff(&left, &right).remove(0);
+        entry.check_history(Some(&left_before), None);
+        assert_eq!(entry.kind, DiffKind::Modified);
+    }
+
+    #[test]
+    fn test_scanned_strong_hashes_decide() {
+        use diffr_core::cancel::CancellationToken;
+        use diffr_core::models::cluster::NestedRepos;
+        use diffr_scan::scanner::{scan_directory, ScanConfig};
+
+        let scan = |root: &std::path::Path| {
+            let config = ScanConfig {
+                root: root.to_path_buf(),
+                drive_id: DriveId::new(),
+                follow_symlinks: false,
+                skip_hidden: false,
+                skip_system: false,
+                nested_repos: NestedRepos::Descend,
+                ignore: Vec::new(),
+                max_errors: None,
+                progress: diffr_core::progress::Progress::none(),
+                full_hashes: true,
+                strong_hash: Some(HashAlgorithm::Blake3),
+                cancel: CancellationToken::default(),
+                power: None,
+            };
+            scan_directory(&config).unwrap().entries
+        };
+        let (a, b) = (tempfile::TempDir::new().unwrap(), tempfile::TempDir::new().unwrap());
+        for dir in [&a, &b] {
+            std::fs::create_dir_all(dir.path().join(".diffr")).unwrap();
+            std::fs::write(
+                dir.path().join(".diffr/repo.toml"),
+                "[repo]\ninitialized_at = \"2024-01-01T00:00:00+00:00\"\n\n[settings]\nhash = \"xxh3\"\n",
+            )
+            .unwrap();
+        }
+        std::fs::write(a.path().join("same.txt"), "same").unwrap();
+        std::fs::write(b.path().join("same.txt"), "same").unwrap();
+        std::fs::write(a.path().join("edited.txt"), "before").unwrap();
+        std::fs::write(b.path().join("edited.txt"), "after!").unwrap();
+        let (left, mut right) = (scan(a.path()), scan(b.path()));
+        assert!(left.iter().all(|e| e.blake3_hash.is_some() && e.sha256_hash.is_none()));
+
+        // The BLAKE3 hashes settle it whatever the quicker hash says.
+        for entry in &mut right {
+            entry.xxh3_hash = Some("0000000000000000".into());
+        }
+        let kinds: Vec<(String, DiffKind)> = compute_diff(&left, &right)
+            .into_iter()
+            .map(|d| (d.rel_path.display().to_string(), d.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![("edited.txt".into(), DiffKind::Modified), ("same.txt".into(), DiffKind::Identical)]
+        );
+    }
+
     #[test]
     fn test_diff_identical_by_metadata() {
         let d1 = DriveId::new();
@@ -199,7 +500,10 @@ mod tests {
             size: 100,
             mtime,
             xxh3_hash: None,
+            sampled_hash: None,
             sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
             indexed_at: Utc::now(),
         }];
         let right = vec![FileEntry {
@@ -209,11 +513,157 @@ mod tests {
             size: 100,
             mtime,
             xxh3_hash: None,
+            sampled_hash: None,
             sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
             indexed_at: Utc::now(),
         }];
         let diffs = compute_diff(&left, &right);
         assert_eq!(diffs.len(), 1);
         assert_eq!(diffs[0].kind, DiffKind::Identical);
     }
+
+    #[test]
+    fn test_diff_by_whole_seconds_against_a_device() {
+        let (d1, d2) = (DriveId::new(), DriveId::new());
+        let at = |drive: &DriveId, mtime: &str| FileEntry {
+            mtime: mtime.parse().unwrap(),
+            ..make_entry("IMG_1.jpg", drive, 100)
+        };
+        let kind = |left: FileEntry, right: FileEntry| compute_diff(&[left], &[right])[0].kind.clone();
+        assert_eq!(
+            kind(at(&d1, "2024-05-01T10:00:00.250Z"), at(&d2, "2024-05-01T10:00:00Z")),
+            DiffKind::Identical
+        );
+        assert_eq!(
+            kind(at(&d1, "2024-05-01T10:00:01.250Z"), at(&d2, "2024-05-01T10:00:00Z")),
+            DiffKind::Modified
+        );
+        assert_eq!(
+            kind(at(&d1, "2024-05-01T10:00:00.250Z"), at(&d2, "2024-05-01T10:00:00.500Z")),
+            DiffKind::Modified
+        );
+    }
+
+    #[test]
+    fn test_diff_by_sampled_hash() {
+        let sampled = |drive: &DriveId, hash: &str| FileEntry {
+            sampled_hash: Some(hash.into()),
+            mtime: Utc::now(),
+            ..make_entry("movie.mkv", drive, 1 << 30)
+        };
+        let (d1, d2) = (DriveId::new(), DriveId::new());
+        // Matching samples win over differing mtimes...
+        let kind = |left: FileEntry, right: FileEntry| compute_diff(&[left], &[right])[0].kind.clone();
+        assert_eq!(kind(sampled(&d1, "aa"), sampled(&d2, "aa")), DiffKind::Identical);
+        // ...and a full hash on both sides wins over the samples.
+        let full = |drive: &DriveId, hash: &str| FileEntry {
+            xxh3_hash: Some(hash.into()),
+            ..sampled(drive, "aa")
+        };
+        assert_eq!(kind(full(&d1, "01"), full(&d2, "02")), DiffKind::Modified);
+        assert_eq!(kind(sampled(&d1, "aa"), sampled(&d2, "bb")), DiffKind::Modified);
+    }
+
+    #[test]
+    fn test_diff_permissions() {
+        use diffr_core::models::file_entry::FilePermissions;
+
+        let mtime = Utc::now();
+        let with_mode = |drive: &DriveId, mode: u32, uid: u32| FileEntry {
+            mtime,
+            permissions: Some(FilePermissions {
+                mode,
+                uid,
+                gid: 100,
+                changed_at: mtime,
+            }),
+            ..make_entry("run.sh", drive, 10)
+        };
+        let (d1, d2) = (DriveId::new(), DriveId::new());
+        let left = [with_mode(&d1, 0o755, 1000)];
+        let right = [with_mode(&d2, 0o644, 1001)];
+
+        // Off by default.
+        assert_eq!(compute_diff(&left, &right)[0].kind, DiffKind::Identical);
+        let options = DiffOptions {
+            permissions: true,
+            ownership: false,
+        };
+        let diffs = compute_diff_with(&left, &right, options);
+        assert_eq!(diffs[0].kind, DiffKind::PermissionsChanged);
+        assert_eq!(diff_summary(&diffs).permissions, 1);
+
+        // Owner alone only counts with `ownership`.
+        let right = [with_mode(&d2, 0o755, 1001)];
+        assert_eq!(compute_diff_with(&left, &right, options)[0].kind, DiffKind::Identical);
+        let options = DiffOptions {
+            ownership: true,
+            ..options
+        };
+        assert_eq!(
+            compute_diff_with(&left, &right, options)[0].kind,
+            DiffKind::PermissionsChanged
+        );
+    }
+
+    #[test]
+    fn test_diff_sorted_merges_in_path_order() {
+        let (d1, d2) = (DriveId::new(), DriveId::new());
+        let left = vec![
+            make_entry("b/x.txt", &d1, 1),
+            make_entry("a.txt", &d1, 1),
+            make_entry("c.txt", &d1, 1),
+            make_entry("c.txt", &d1, 2),
+        ];
+        let right = vec![make_entry("c.txt", &d2, 2), make_entry("b.txt", &d2, 1)];
+
+        let diffs = compute_diff(&left, &right);
+        let got: Vec<(String, DiffKind)> = diffs
+            .iter()
+            .map(|d| (d.rel_path.display().to_string(), d.kind.clone()))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                ("a.txt".to_string(), DiffKind::OnlyLeft),
+                ("b/x.txt".to_string(), DiffKind::OnlyLeft),
+                ("b.txt".to_string(), DiffKind::OnlyRight),
+                // Of the two c.txt on the left, the later one is compared.
+                ("c.txt".to_string(), DiffKind::Modified),
+            ]
+        );
+        // The entries compared are borrowed, not copied.
+        assert!(std::ptr::eq(diffs[3].left.unwrap(), &left[3]));
+
+        // Entries already in order stream straight from where they are.
+        let mut sorted = right.clone();
+        sorted.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+        let streamed: Vec<DiffKind> = diff_sorted(sorted_by_path(&left), &sorted, DiffOptions::default())
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(streamed, diffs.iter().map(|d| d.kind.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_breakdown_by_top_level() {
+        let d1 = DriveId::new();
+        let d2 = DriveId::new();
+        let left = vec![
+            make_entry("Photos/a.jpg", &d1, 300),
+            make_entry("Photos/2024/b.jpg", &d1, 200),
+            make_entry("notes.txt", &d1, 5),
+        ];
+        let right = vec![make_entry("Docs/c.pdf", &d2, 50)];
+        let diffs = compute_diff(&left, &right);
+
+        let breakdown = breakdown_by_top_level(&diffs);
+        assert_eq!(breakdown.len(), 3);
+        assert_eq!(breakdown[0].dir, Some(PathBuf::from("Photos")));
+        assert_eq!(breakdown[0].files, 2);
+        assert_eq!(breakdown[0].bytes, 500);
+        assert_eq!(breakdown[2].dir, None);
+        assert_eq!(breakdown[2].bytes, 5);
+    }
 }
@@ -0,0 +1,1389 @@
+use chrono::{DateTime, Utc};
+use diffr_archive::replication::{replicate_archives, snapshot_catalog, ReplicationResult};
+use diffr_core::cancel::{Cancelled, CancellationToken};
+use diffr_core::power::PowerGuard;
+use diffr_core::config::{DiffrConfig, Durability};
+use diffr_core::models::cluster::{ArchiveStrategy, Cluster, QuotaMode, Topology};
+use diffr_core::models::drive::{Drive, DriveId, DriveRole};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::manifest::{ClusterManifest, DriveManifest};
+use diffr_core::models::sync_state::{
+    JournalEntry, SkipReason, SkippedFile, SyncOp, SyncOpKind, SyncPlan, SyncRecord, SyncStatus,
+};
+use diffr_core::progress::Progress;
+use diffr_core::spill::SpillVec;
+use diffr_core::task::{self, Task};
+use diffr_db::ops;
+use diffr_discovery::mtp::MtpStore;
+use diffr_scan::errors::ScanErrorKind;
+use diffr_scan::scanner::{scan_directory_cached, scan_mtp, ScanConfig, ScanResult};
+use rusqlite::Connection;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::anomaly::check_changes;
+use crate::attributes::{order_by_priority, Attributes};
+use crate::conflict::{AskFn, ConflictChoice, ConflictResolver, PendingConflict};
+use crate::diff::{diff_sorted, DiffEntry, DiffKind, DiffOptions, DiffSummary};
+use crate::estimate::estimate_duration;
+use crate::executor::{execute_plan, ExecConfig};
+use crate::filter::{drop_skipped, OpFilter};
+use crate::quota::{apply_quotas, DriveUsage};
+use crate::replicas::{add_min_copies, still_short, Replica, ShortFile};
+use crate::safety::find_mass_changes;
+use crate::stability::{hold_unstable, settled, HeldOp};
+use crate::tiering::tier_by_size;
+use crate::topology::{PlanBuilder, PlanWarning};
+
+/// How many files a warning lists before summarizing.
+const SHORT_LIST_LIMIT: usize = 10;
+
+type EventFn<'a> = Box<dyn FnMut(SyncEvent<'_>) + 'a>;
+type ConfirmFn<'a> = Box<dyn FnMut(&str) -> anyhow::Result<bool> + 'a>;
+
+/// How to run a sync, on top of the cluster's settings and the config.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Show what would happen without changing anything.
+    pub dry_run: bool,
+    /// Verify copies even if the config's and cluster's `verify_after_sync`
+    /// are off.
+    pub verify: bool,
+    /// Skip archiving before overwrite/delete.
+    pub no_archive: bool,
+    /// Include files above the cluster's `large_file_threshold`.
+    pub large_files: bool,
+    /// Which operations to run.
+    pub filter: OpFilter,
+    /// Sync only these of the cluster's drives; `None` syncs them all.
+    pub drives: Option<Vec<DriveId>>,
+    /// Proceed even if the plan exceeds the cluster's `mass_change_percent`.
+    pub allow_mass_change: bool,
+    /// Proceed without asking even if a source drive's files changed more
+    /// than the cluster's `anomaly_percent` since the last sync.
+    pub allow_anomaly: bool,
+    /// Move deleted files to `.diffr/trash` even if the config's
+    /// `delete_to_trash` is off.
+    pub trash: bool,
+    /// Overrides the config's `durability`.
+    pub durability: Option<Durability>,
+    /// Stop if scanning produces more than this many errors. Dry runs scan
+    /// everything, so they can report every error.
+    pub max_scan_errors: Option<usize>,
+    /// Follow symlinks even if the cluster's `follow_symlinks` is off.
+    pub follow_symlinks: bool,
+    /// Note to record with the sync in history.
+    pub message: Option<String>,
+    /// Machine the sync runs on, for the record.
+    pub host: Option<String>,
+    /// Version of diffr running the sync, for the record.
+    pub diffr_version: Option<String>,
+    /// Stop once the plan is built, before filters, without saving or
+    /// running it.
+    pub plan_only: bool,
+    /// Where scanning and copying report their progress.
+    pub progress: Progress,
+    /// Stop the sync once cancelled. Cancelled while scanning, it fails
+    /// with [`Cancelled`] having changed nothing; once copying has begun,
+    /// it stops before the next operation and records what was done.
+    pub cancel: CancellationToken,
+    /// Battery and heat limits to copy under, from
+    /// [`DiffrConfig::power_guard`].
+    pub power: Option<PowerGuard>,
+    /// Most bytes a second to copy, on average.
+    pub max_rate: Option<u64>,
+    /// Leave held-back files for the next sync instead of waiting for them
+    /// to settle if that would run past this time, such as when a scheduled
+    /// sync's window closes.
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+/// Something a running sync reports, for the caller to show as it likes.
+pub enum SyncEvent<'e> {
+    /// The drives that will be scanned and synced. Archive-only drives
+    /// aren't among them.
+    Started(&'e [&'e Drive]),
+    Scanning(&'e Drive),
+    /// A drive was scanned, with `kinds` counting its errors by kind.
+    Scanned {
+        drive: &'e Drive,
+        result: &'e ScanResult,
+        kinds: &'e BTreeMap<ScanErrorKind, usize>,
+    },
+    /// Scanning produced more than `max_scan_errors` errors, and the sync
+    /// fails once this is reported.
+    TooManyScanErrors {
+        errors: &'e [String],
+        denied: &'e [(DriveId, PathBuf)],
+    },
+    Diffed {
+        left: &'e Drive,
+        right: &'e Drive,
+        summary: &'e DiffSummary,
+    },
+    /// The plan that will run, how long it should take, and what was left
+    /// out of it.
+    Planned {
+        plan: &'e SyncPlan,
+        estimate: Option<Duration>,
+        filtered: usize,
+        deferred: &'e SpillVec<SyncOp>,
+        deferred_bytes: u64,
+        held: usize,
+    },
+    /// Waiting this long for held-back files to settle before retrying them.
+    Waiting(Duration),
+    /// Something the user should know about. May run to several lines.
+    Warning(String),
+}
+
+/// What a sync did, or with `plan_only`, would do.
+pub struct SyncOutcome {
+    /// Every drive in the cluster, archive-only ones included.
+    pub drives: Vec<Drive>,
+    /// The plan as run, or with `plan_only`, as built.
+    pub plan: SyncPlan,
+    pub warnings: Vec<PlanWarning>,
+    /// The sync's record as saved to history, or `None` if there was
+    /// nothing to do or the sync stopped at the plan.
+    pub record: Option<SyncRecord>,
+    pub scan_errors: Vec<String>,
+    pub error_kinds: BTreeMap<ScanErrorKind, usize>,
+    /// Files scanning wasn't allowed to read.
+    pub denied: Vec<(DriveId, PathBuf)>,
+    pub skipped: Vec<SkippedFile>,
+    /// Operations left for a run with `large_files`.
+    pub deferred: SpillVec<SyncOp>,
+    /// Operations on files still being written, left for the next sync.
+    pub held: Vec<HeldOp>,
+    pub under_replicated: Vec<ShortFile>,
+    /// Archives this sync took, copied onto archive-only drives.
+    pub replication: ReplicationResult,
+}
+
+/// Runs a cluster's sync from start to finish: scan every drive, diff
+/// them, plan, run the plan and record what happened. Progress goes to the
+/// `on_event` callback, and questions for the user to `on_confirm`, which
+/// says no unless one is given. Conflicts the cluster resolves
+/// interactively go to `on_conflict`; without one they're left as they
+/// are, to be resolved by a sync that can ask.
+pub struct SyncEngine<'a> {
+    conn: &'a Connection,
+    config: &'a DiffrConfig,
+    on_event: EventFn<'a>,
+    on_confirm: ConfirmFn<'a>,
+    on_conflict: Option<AskFn<'a>>,
+}
+
+impl<'a> SyncEngine<'a> {
+    pub fn new(conn: &'a Connection, config: &'a DiffrConfig) -> Self {
+        Self {
+            conn,
+            config,
+            on_event: Box::new(|_| {}),
+            on_confirm: Box::new(|_| Ok(false)),
+            on_conflict: None,
+        }
+    }
+
+    pub fn on_event(mut self, f: impl FnMut(SyncEvent<'_>) + 'a) -> Self {
+        self.on_event = Box::new(f);
+        self
+    }
+
+    /// Ask a yes/no question, such as whether to sync changes that look
+    /// like ransomware's. A no, or an error, stops the sync.
+    pub fn on_confirm(mut self, f: impl FnMut(&str) -> anyhow::Result<bool> + 'a) -> Self {
+        self.on_confirm = Box::new(f);
+        self
+    }
+
+    /// Ask which version of a conflicting file to keep, for clusters that
+    /// resolve conflicts interactively. See [`AskFn`].
+    pub fn on_conflict(
+        mut self,
+        f: impl FnMut(&DiffEntry, &Drive, &Drive) -> anyhow::Result<(ConflictChoice, bool)> + 'a,
+    ) -> Self {
+        self.on_conflict = Some(Box::new(f));
+        self
+    }
+
+    fn emit(&mut self, event: SyncEvent<'_>) {
+        (self.on_event)(event)
+    }
+
+    fn warn(&mut self, message: String) {
+        self.emit(SyncEvent::Warning(message))
+    }
+
+    /// Sync `cluster`'s `drives`, every one of its members. They should be
+    /// refreshed against what's connected first: the database doesn't know
+    /// which are locked, and a drive that isn't where it says fails the
+    /// sync.
+    pub fn run(&mut self, cluster: &Cluster, drives: &[Drive], options: &SyncOptions) -> anyhow::Result<SyncOutcome> {
+        let conn = self.conn;
+        let config = self.config;
+        let verify = options.verify || config.verify_after_sync || cluster.settings.verify_after_sync;
+        let drives = drives.to_vec();
+
+        let violations = crate::validate::validate_cluster(cluster, &drives, &ops::list_all_drives(conn)?);
+        if !violations.is_empty() {
+            let lines: Vec<String> = violations.iter().map(|v| format!("  - {}", v)).collect();
+            anyhow::bail!(
+                "cluster '{}' can't be synced:\n{}",
+                cluster.name,
+                lines.join("\n")
+            );
+        }
+
+        // Filter to syncable drives (not ArchiveOnly)
+        let selected = |d: &Drive| options.drives.as_ref().is_none_or(|only| only.contains(&d.id));
+        let sync_drives: Vec<&Drive> = drives
+            .iter()
+            .filter(|d| d.role != DriveRole::ArchiveOnly)
+            .filter(|d| selected(d))
+            .collect();
+        self.emit(SyncEvent::Started(&sync_drives));
+
+        // Scan all drives
+        let mut scans: Vec<(usize, Vec<FileEntry>)> = Vec::new();
+        let mut scan_errors: Vec<String> = Vec::new();
+        let mut error_kinds: BTreeMap<ScanErrorKind, usize> = BTreeMap::new();
+        let mut denied: Vec<(DriveId, PathBuf)> = Vec::new();
+        let mut skipped: Vec<SkippedFile> = Vec::new();
+        let mut attributes: HashMap<DriveId, Attributes> = HashMap::new();
+        let max_scan_errors = options.max_scan_errors.filter(|_| !options.dry_run);
+        for (idx, drive) in sync_drives.iter().enumerate() {
+            let scan_root = drive.effective_root();
+            if drive.locked {
+                anyhow::bail!(
+                    "drive {} is locked (BitLocker); unlock it and sync again",
+                    drive.identity.identity_string()
+                );
+            }
+            // A device reached through libmtp has no path here, and no
+            // `.diffrattributes` that can be read.
+            let device = drive.mtp_device();
+            if device.is_none() && !scan_root.exists() {
+                anyhow::bail!(
+                    "sync root does not exist: {} (drive {})",
+                    scan_root.display(),
+                    drive.identity.identity_string()
+                );
+            }
+            if device.is_none() {
+                attributes.insert(drive.id.clone(), Attributes::load(scan_root)?);
+            }
+            self.emit(SyncEvent::Scanning(drive));
+            let scan_config = ScanConfig {
+                root: scan_root.to_path_buf(),
+                drive_id: drive.id.clone(),
+                follow_symlinks: options.follow_symlinks || cluster.settings.follow_symlinks,
+                skip_hidden: cluster.settings.skip_hidden,
+                skip_system: cluster.settings.skip_system,
+                nested_repos: cluster.settings.nested_repos,
+                ignore: cluster.settings.ignore.clone(),
+                // Stop as soon as the drive takes the sync past the limit.
+                max_errors: max_scan_errors.map(|max| (max + 1).saturating_sub(scan_errors.len())),
+                progress: options.progress.clone(),
+                full_hashes: verify,
+                // Then the diff goes by the hash copies are verified with.
+                strong_hash: verify.then_some(config.verify_hash),
+                cancel: options.cancel.clone(),
+                power: options.power,
+            };
+            let result = match device {
+                Some((serial, path)) => scan_mtp(&scan_config, &mut MtpStore::open(serial)?, path)?,
+                None => scan_directory_cached(&scan_config, conn)?,
+            };
+            // A partial scan would look like deleted files.
+            if result.cancelled {
+                return Err(Cancelled.into());
+            }
+            let mut kinds: BTreeMap<ScanErrorKind, usize> = BTreeMap::new();
+            for e in &result.errors {
+                *kinds.entry(e.kind).or_default() += 1;
+                if e.kind == ScanErrorKind::PermissionDenied {
+                    denied.push((drive.id.clone(), e.rel_path.clone()));
+                }
+            }
+            self.emit(SyncEvent::Scanned {
+                drive,
+                result: &result,
+                kinds: &kinds,
+            });
+            for (kind, n) in kinds {
+                *error_kinds.entry(kind).or_default() += n;
+            }
+            scan_errors.extend(
+                result
+                    .errors
+                    .iter()
+                    .map(|e| format!("scan {}: {}", scan_root.display(), e)),
+            );
+            skipped.extend(result.skipped);
+            scans.push((idx, result.entries));
+
+            // Files that couldn't be read are invisible to the diff, so too
+            // many errors means the plan can't be trusted.
+            if let Some(max) = max_scan_errors.filter(|max| scan_errors.len() > *max) {
+                self.emit(SyncEvent::TooManyScanErrors {
+                    errors: &scan_errors,
+                    denied: &denied,
+                });
+                anyhow::bail!(
+                    "refusing to sync: scan errors ({}) exceed --max-scan-errors {}{}",
+                    kind_counts(&error_kinds),
+                    max,
+                    if result.stopped_early { "; stopped scanning early" } else { "" }
+                );
+            }
+        }
+
+        let drive_ids: Vec<&DriveId> = scans.iter().map(|(idx, _)| &sync_drives[*idx].id).collect();
+        bound_nested_repos(&mut scans, &drive_ids, &mut skipped);
+
+        // Diff each pair, streaming the entries into the plan so only the
+        // operations are kept, not a copy of every file. Scans come in path
+        // order already, which the diff needs.
+        debug_assert!(scans
+            .iter()
+            .all(|(_, entries)| entries.is_sorted_by(|a, b| a.rel_path <= b.rel_path)));
+        let mut builder = PlanBuilder::new(cluster, &drives);
+        let diff_options = DiffOptions::for_cluster(&cluster.settings);
+        for i in 0..scans.len() {
+            for j in (i + 1)..scans.len() {
+                let left_drive = sync_drives[scans[i].0];
+                let right_drive = sync_drives[scans[j].0];
+                let mut summary = DiffSummary::default();
+                for mut entry in diff_sorted(&scans[i].1, &scans[j].1, diff_options) {
+                    // A file that changed on both sides since the last sync
+                    // is a conflict, not just a newer version.
+                    if entry.kind == DiffKind::Modified {
+                        entry.check_history(
+                            ops::get_file_entry(conn, &left_drive.id, entry.rel_path)?.as_ref(),
+                            ops::get_file_entry(conn, &right_drive.id, entry.rel_path)?.as_ref(),
+                        );
+                    }
+                    summary.add(&entry);
+                    builder.add(left_drive, right_drive, &entry);
+                }
+                self.emit(SyncEvent::Diffed {
+                    left: left_drive,
+                    right: right_drive,
+                    summary: &summary,
+                });
+            }
+        }
+
+        let conflicts = builder.take_conflicts();
+        let (mut plan, warnings) = builder.finish();
+        for warning in &warnings {
+            self.warn(warning.to_string());
+        }
+        if warnings.iter().any(PlanWarning::blocks_sync) && !options.dry_run && !options.plan_only {
+            anyhow::bail!("refusing to sync: the plan can't be trusted (see the warnings above)");
+        }
+        // Copies on members left out with --drives still count toward
+        // min_copies, going by what the catalog last saw there.
+        let mut left_out: Vec<(&Drive, Vec<FileEntry>)> = Vec::new();
+        for drive in drives.iter().filter(|d| d.role != DriveRole::ArchiveOnly && !selected(d)) {
+            left_out.push((drive, ops::get_file_entries_for_drive(conn, &drive.id)?));
+        }
+        let replicas: Vec<Replica> = scans
+            .iter()
+            .map(|(idx, entries)| {
+                let drive = sync_drives[*idx];
+                let primary_replica = cluster.topology == Topology::PrimaryReplica;
+                let source_only = drive.read_only || (primary_replica && drive.is_primary);
+                Replica {
+                    drive,
+                    files: entries,
+                    accepts_copies: !source_only,
+                    // Copies only flow out from the primary.
+                    gives_copies: !primary_replica || drive.is_primary,
+                }
+            })
+            .chain(left_out.iter().map(|(drive, files)| Replica {
+                drive,
+                files,
+                accepts_copies: false,
+                gives_copies: false,
+            }))
+            .collect();
+        let min_copies = cluster.settings.min_copies.unwrap_or(0) as usize;
+        let mut under_replicated = add_min_copies(&mut plan, min_copies, &replicas);
+        let mut outcome = SyncOutcome {
+            drives: Vec::new(),
+            plan: SyncPlan::new(cluster.id.clone(), Vec::new()),
+            warnings,
+            record: None,
+            scan_errors,
+            error_kinds,
+            denied,
+            skipped,
+            deferred: SpillVec::new(),
+            held: Vec::new(),
+            under_replicated: Vec::new(),
+            replication: ReplicationResult::default(),
+        };
+        // `plan diff` stops here. The plan is saved before this run's
+        // filters, so plans from runs with different flags still compare.
+        if options.plan_only {
+            outcome.plan = plan;
+            outcome.under_replicated = under_replicated;
+            outcome.drives = drives;
+            return Ok(outcome);
+        }
+        ops::save_last_plan(conn, &plan)?;
+        let now = Utc::now();
+        if !options.dry_run {
+            ops::prune_expired_skips(conn, now)?;
+        }
+        let skip_listed = drop_skipped(&mut plan, &ops::list_skips(conn, &cluster.id, now)?);
+        outcome.skipped.extend(skipped_ops(&skip_listed, SkipReason::SkipListed));
+        let filtered = options.filter.apply(&mut plan);
+        outcome.skipped.extend(skipped_ops(&filtered, SkipReason::Filtered));
+        let can_ask = self.on_conflict.is_some();
+        let mut resolver = ConflictResolver::with_merge_commands(config.merge.clone());
+        if let Some(ask) = self.on_conflict.as_mut() {
+            resolver = resolver.asking(ask);
+        }
+        let resolutions = resolver.resolve_plan(cluster, &mut plan, &conflicts, &drives);
+        // It borrows `on_conflict`; warning below needs all of `self`.
+        drop(resolver);
+        let unresolved = plan
+            .operations
+            .iter()
+            .filter(|op| op.kind == SyncOpKind::ResolveConflict)
+            .count();
+        if unresolved > 0 {
+            self.warn(format!(
+                "{} conflicts were left unresolved, keeping both versions as they are{}",
+                unresolved,
+                if can_ask { "" } else { "; run `diffr sync` at a terminal to choose" }
+            ));
+        }
+        let tiered = tier_by_size(plan, &cluster.settings, options.large_files);
+        let deferred_bytes = tiered.deferred_bytes();
+        let (mut plan, deferred) = (tiered.plan, tiered.deferred);
+        order_by_priority(&mut plan, &attributes);
+        outcome.skipped.extend(skipped_ops(&deferred, SkipReason::Deferred));
+
+        let usage: HashMap<DriveId, DriveUsage> = scans
+            .iter()
+            .map(|(idx, entries)| (sync_drives[*idx].id.clone(), DriveUsage::from_entries(entries)))
+            .collect();
+        let quota_reports = apply_quotas(&mut plan, &drives, &usage);
+        for report in quota_reports.iter().filter(|r| !r.trimmed.is_empty()) {
+            outcome.skipped.extend(skipped_ops(&report.trimmed, SkipReason::OverQuota));
+            let trimmed_bytes: u64 = report.trimmed.iter().map(|op| op.size_bytes).sum();
+            self.warn(format!(
+                "{} operations ({} bytes) exceed the quota on {} ({} of {} bytes used)",
+                report.trimmed.len(),
+                trimmed_bytes,
+                drive_root_display(&drives, &report.drive_id),
+                report.used,
+                report.quota
+            ));
+            if cluster.settings.quota_mode == QuotaMode::Fail && !options.dry_run {
+                anyhow::bail!(
+                    "refusing to sync: quota exceeded (set quota_mode to trim to sync what fits)"
+                );
+            }
+        }
+
+        let dropped = still_short(&plan, min_copies, &replicas, &under_replicated);
+        under_replicated.extend(dropped);
+        if !under_replicated.is_empty() {
+            let mut message = format!(
+                "{} files will have fewer than {} copies (min_copies):",
+                under_replicated.len(),
+                min_copies
+            );
+            for file in under_replicated.iter().take(SHORT_LIST_LIMIT) {
+                message.push_str(&format!(
+                    "\n  {}: {} copies, {}",
+                    file.rel_path.display(),
+                    file.copies,
+                    file.reason
+                ));
+            }
+            if under_replicated.len() > SHORT_LIST_LIMIT {
+                message.push_str(&format!("\n  ... and {} more", under_replicated.len() - SHORT_LIST_LIMIT));
+            }
+            self.warn(message);
+        }
+
+        // Files written moments ago may still be downloading; copy them once
+        // they've settled instead of taking a torn copy now.
+        let window = cluster
+            .settings
+            .stability_window
+            .map(|secs| chrono::Duration::seconds(secs as i64));
+        let mut held = match window {
+            Some(window) => hold_unstable(&mut plan, &replicas, window, Utc::now()),
+            None => Vec::new(),
+        };
+
+        let estimate = estimate_duration(&plan, &ops::list_throughput(conn)?).filter(|_| plan.total_bytes > 0);
+        self.emit(SyncEvent::Planned {
+            plan: &plan,
+            estimate,
+            filtered: filtered.len(),
+            deferred: &deferred,
+            deferred_bytes,
+            held: held.len(),
+        });
+
+        let scanned: Vec<(DriveId, &[FileEntry])> = scans
+            .iter()
+            .map(|(idx, entries)| (sync_drives[*idx].id.clone(), entries.as_slice()))
+            .collect();
+        if plan.operations.is_empty() && held.is_empty() {
+            // Nothing will change, so the scans are the catalog as it stands.
+            update_catalog(conn, &scanned, &SpillVec::new(), &[], false)?;
+            outcome.plan = plan;
+            outcome.deferred = deferred;
+            outcome.under_replicated = under_replicated;
+            outcome.drives = drives;
+            return Ok(outcome);
+        }
+
+        if let Some(max_percent) = cluster.settings.mass_change_percent {
+            let file_counts: HashMap<DriveId, usize> = scans
+                .iter()
+                .map(|(idx, entries)| {
+                    let files = entries.iter().filter(|e| !e.is_dir).count();
+                    (sync_drives[*idx].id.clone(), files)
+                })
+                .collect();
+            let mass_changes = find_mass_changes(&plan, &file_counts, max_percent);
+            for change in &mass_changes {
+                self.warn(format!(
+                    "plan would delete or overwrite {} of {} files ({:.0}%) on {}",
+                    change.changed,
+                    change.total,
+                    change.percent(),
+                    drive_root_display(&drives, &change.drive_id)
+                ));
+            }
+            if !mass_changes.is_empty() && !options.allow_mass_change && !options.dry_run {
+                anyhow::bail!(
+                    "refusing to sync: changes exceed mass_change_percent ({}%). \
+                     Check that no drive was emptied by accident, then re-run with --allow-mass-change",
+                    max_percent
+                );
+            }
+        }
+
+        // Changes on a drive that's about to be copied from spread everywhere,
+        // so check that they look like ordinary editing first.
+        if let Some(threshold) = cluster.settings.anomaly_percent {
+            let sources: HashSet<DriveId> =
+                plan.operations.iter().filter_map(|op| op.source_drive.clone()).collect();
+            let mut anomalies = Vec::new();
+            for (idx, entries) in &scans {
+                let drive = sync_drives[*idx];
+                if !sources.contains(&drive.id) {
+                    continue;
+                }
+                let indexed = ops::get_file_entries_for_drive(conn, &drive.id)?;
+                let report = check_changes(&drive.id, drive.effective_root(), &indexed, entries);
+                if report.is_anomalous(threshold) {
+                    anomalies.push(report);
+                }
+            }
+            for report in &anomalies {
+                let mut message = format!(
+                    "{} of {} files ({:.0}%) on {} changed since the last sync",
+                    report.changed.len(),
+                    report.compared,
+                    report.changed_percent(),
+                    drive_root_display(&drives, &report.drive_id)
+                );
+                if report.encrypted_like > 0 {
+                    message.push_str(&format!(
+                        "\n  {} of {} changed files checked look encrypted, as ransomware leaves them",
+                        report.encrypted_like, report.sampled
+                    ));
+                }
+                for path in report.changed.iter().take(SHORT_LIST_LIMIT) {
+                    message.push_str(&format!("\n  {}", path.display()));
+                }
+                if report.changed.len() > SHORT_LIST_LIMIT {
+                    message.push_str(&format!("\n  ... and {} more", report.changed.len() - SHORT_LIST_LIMIT));
+                }
+                self.warn(message);
+            }
+            if !anomalies.is_empty()
+                && !options.allow_anomaly
+                && !options.dry_run
+                && !(self.on_confirm)("Sync these changes to the other drives anyway?")?
+            {
+                anyhow::bail!(
+                    "refusing to sync: changes exceed anomaly_percent ({}%). \
+                     Check the files above, then re-run with --allow-anomaly",
+                    threshold
+                );
+            }
+        }
+
+        // Execute
+        let exec_config = ExecConfig {
+            dry_run: options.dry_run,
+            verify,
+            verify_hash: config.verify_hash,
+            verify_readback: config.verify_readback,
+            archive: !options.no_archive && config.archive_on_change,
+            central_archive: cluster.settings.archive_strategy == ArchiveStrategy::Central,
+            progress: options.progress.clone(),
+            trash: options.trash || config.delete_to_trash,
+            ownership: cluster.settings.sync_ownership,
+            durability: options.durability.or(config.durability),
+            cancel: options.cancel.clone(),
+            attributes,
+            power: options.power,
+            merge_commands: config.merge.clone(),
+            max_rate: options.max_rate,
+            open_mtp: MtpStore::open,
+        };
+
+        let mut result = execute_plan(&plan, &drives, &exec_config)?;
+        let retry_held = !held.is_empty() && !options.dry_run && !options.cancel.is_cancelled();
+        if let (Some(window), true) = (window, retry_held) {
+            // Files were only held for being modified within the window,
+            // so nothing should take longer than that to settle.
+            let now = Utc::now();
+            let stable_at = held.iter().map(|h| h.stable_at(window)).max().unwrap_or(now).min(now + window);
+            // Files that can't settle before the deadline are left for the
+            // next sync rather than waited on past it.
+            let mut given_up = options.deadline.is_some_and(|deadline| deadline < stable_at);
+            if let (Ok(wait), false) = ((stable_at - now).to_std(), given_up) {
+                self.emit(SyncEvent::Waiting(wait));
+                given_up = options.cancel.sleep(wait);
+            }
+            let (ready, unstable) = match given_up {
+                true => (Vec::new(), std::mem::take(&mut held)),
+                false => settled(std::mem::take(&mut held), &drives),
+            };
+            held = unstable;
+            if !ready.is_empty() {
+                let retry = SyncPlan::new(cluster.id.clone(), ready);
+                result.absorb(execute_plan(&retry, &drives, &exec_config)?);
+            }
+        }
+        let mut record = result.record;
+        record.conflicts_resolved = resolutions.len() as u64;
+        record.host = options.host.clone();
+        record.user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .ok();
+        record.diffr_version = options.diffr_version.clone();
+        record.message = options.message.clone();
+        if !outcome.scan_errors.is_empty() {
+            if record.status == SyncStatus::Success {
+                record.status = SyncStatus::PartialSuccess;
+            }
+            record.errors.splice(0..0, outcome.scan_errors.iter().cloned());
+        }
+
+        // Save sync record and any archives taken before overwrite/delete
+        ops::insert_sync_record(conn, &record)?;
+        for entry in &result.archives {
+            ops::insert_archive(conn, entry)?;
+        }
+        // Archive-only drives aren't synced, but they keep a copy of every
+        // version this sync archived.
+        let replication = replicate_archives(&result.archives, &drives);
+        for entry in &replication.copies {
+            ops::insert_archive(conn, entry)?;
+        }
+        for e in &replication.errors {
+            self.warn(format!("archive not replicated: {}", e));
+        }
+        ops::insert_journal(conn, &result.journal)?;
+        outcome.skipped.extend(result.skipped);
+        outcome.skipped.extend(held.iter().map(|h| SkippedFile {
+            rel_path: h.op.rel_path.clone(),
+            drive_id: h.op.target_drive.clone(),
+            reason: SkipReason::Unstable,
+        }));
+        // A dry run changed nothing, and the catalog has to remember how the
+        // drives were left for the next sync to spot conflicts.
+        if !options.dry_run {
+            let unresolved = unsettled(&conflicts, &result.journal);
+            update_catalog(conn, &scanned, &result.journal, &unresolved, exec_config.ownership)?;
+        }
+        for sample in &result.throughput {
+            ops::record_throughput(conn, sample)?;
+        }
+
+        if !options.dry_run {
+            // Usage as the ops that ran left it; failed, skipped or
+            // cancelled ones changed nothing.
+            for report in &quota_reports {
+                let (Some(mut drive), Some(before)) =
+                    (ops::get_drive_by_id(conn, &report.drive_id)?, usage.get(&report.drive_id))
+                else {
+                    continue;
+                };
+                let ran = result
+                    .journal
+                    .iter()
+                    .map(|entry| entry.into_owned().op)
+                    .filter(|op| op.target_drive == report.drive_id);
+                drive.used_bytes = Some(before.after(ran));
+                ops::update_drive(conn, &drive)?;
+            }
+            // Only drives this sync touched get a manifest or have their
+            // trash emptied; the manifest still lists every member.
+            let touched: Vec<&Drive> = drives.iter().filter(|d| selected(d)).collect();
+            write_drive_manifests(cluster, &drives, &touched);
+            if cluster.settings.archive_catalog {
+                if let Err(e) = snapshot_catalog(conn, &drives) {
+                    self.warn(format!("failed to copy the catalog to archive drives: {}", e));
+                }
+            }
+            purge_expired_trash(&touched, config.retention_for(Some(cluster)).trash_days);
+        }
+
+        outcome.plan = plan;
+        outcome.record = Some(record);
+        outcome.deferred = deferred;
+        outcome.held = held;
+        outcome.under_replicated = under_replicated;
+        outcome.replication = replication;
+        outcome.drives = drives;
+        Ok(outcome)
+    }
+}
+
+/// Run a sync on a thread of its own, with its own connection to the
+/// database at `db_path`, so the caller can go on serving requests. Events
+/// go to `on_event` from the sync's thread. Nothing is asked, so a sync
+/// that would need confirming fails as if the answer were no.
+pub fn spawn_sync(
+    db_path: PathBuf,
+    config: DiffrConfig,
+    cluster: Cluster,
+    drives: Vec<Drive>,
+    options: SyncOptions,
+    on_event: impl FnMut(SyncEvent<'_>) + Send + 'static,
+) -> Task<anyhow::Result<SyncOutcome>> {
+    task::spawn(move || {
+        let conn = diffr_db::open_db(&db_path)?;
+        let outcome = SyncEngine::new(&conn, &config).on_event(on_event).run(&cluster, &drives, &options);
+        outcome
+    })
+}
+
+/// Make a nested repo found on any drive a boundary on all of them. Its
+/// `.diffr` never syncs, so on the other drives it's plain files, which
+/// would otherwise be copied into the repo or deleted from beside it.
+/// `drive_ids` are the drives of `scans`, in order.
+fn bound_nested_repos(scans: &mut [(usize, Vec<FileEntry>)], drive_ids: &[&DriveId], skipped: &mut Vec<SkippedFile>) {
+    let boundaries: BTreeSet<PathBuf> = skipped
+        .iter()
+        .filter(|s| s.reason == SkipReason::NestedRepo)
+        .map(|s| s.rel_path.clone())
+        .collect();
+    if boundaries.is_empty() {
+        return;
+    }
+    for ((_, entries), drive_id) in scans.iter_mut().zip(drive_ids) {
+        entries.retain(|entry| {
+            let Some(boundary) = boundaries.iter().find(|b| entry.rel_path.starts_with(b)) else {
+                return true;
+            };
+            if entry.rel_path == *boundary {
+                skipped.push(SkippedFile {
+                    rel_path: boundary.clone(),
+                    drive_id: (*drive_id).clone(),
+                    reason: SkipReason::NestedRepo,
+                });
+            }
+            false
+        });
+    }
+}
+
+/// Skip report entries for operations a sync left out.
+fn skipped_ops<'a>(
+    ops: impl IntoIterator<Item = impl Borrow<SyncOp>> + 'a,
+    reason: SkipReason,
+) -> impl Iterator<Item = SkippedFile> + 'a {
+    ops.into_iter().map(move |op| SkippedFile {
+        rel_path: op.borrow().rel_path.clone(),
+        drive_id: op.borrow().target_drive.clone(),
+        reason,
+    })
+}
+
+/// Scan error counts by kind, such as `3000 permission_denied, 2 other`.
+pub fn kind_counts(kinds: &BTreeMap<ScanErrorKind, usize>) -> String {
+    let counts: Vec<String> = kinds.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+    counts.join(", ")
+}
+
+/// Where the drive with `id` is, or its id if it isn't among `drives`.
+pub fn drive_root_display(drives: &[Drive], id: &DriveId) -> String {
+    drives
+        .iter()
+        .find(|d| &d.id == id)
+        .map(|d| d.effective_root().display().to_string())
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Save what each drive holds to the file index, so commands like
+/// `drive simulate-remove` can answer questions about drives that aren't
+/// connected. The scans are from before the sync; the journal brings them
+/// up to date with what it changed. Files in `unresolved` conflicts keep
+/// what was cataloged for them before, so the next sync still sees that
+/// both sides changed. Owners only change with `ownership`.
+fn update_catalog(
+    conn: &Connection,
+    scans: &[(DriveId, &[FileEntry])],
+    journal: &SpillVec<JournalEntry>,
+    unresolved: &[(DriveId, PathBuf)],
+    ownership: bool,
+) -> anyhow::Result<()> {
+    let mut catalog: HashMap<DriveId, BTreeMap<PathBuf, FileEntry>> = scans
+        .iter()
+        .map(|(id, entries)| {
+            let files = entries.iter().map(|e| (e.rel_path.clone(), e.clone())).collect();
+            (id.clone(), files)
+        })
+        .collect();
+
+    for entry in journal {
+        let op = &entry.op;
+        let from = match op.kind {
+            SyncOpKind::CopyNew | SyncOpKind::Overwrite | SyncOpKind::SetPermissions => op
+                .source_drive
+                .as_ref()
+                .and_then(|s| catalog.get(s))
+                .and_then(|files| files.get(&op.rel_path)),
+            SyncOpKind::LocalCopy => op
+                .source_path
+                .as_ref()
+                .and_then(|p| catalog.get(&op.target_drive)?.get(p)),
+            _ => None,
+        };
+        let copied = from.map(|e| FileEntry {
+            rel_path: op.rel_path.clone(),
+            drive_id: op.target_drive.clone(),
+            indexed_at: Utc::now(),
+            ..e.clone()
+        });
+        let Some(files) = catalog.get_mut(&op.target_drive) else {
+            continue;
+        };
+        match op.kind {
+            SyncOpKind::Delete | SyncOpKind::RemoveDir => {
+                files.remove(&op.rel_path);
+            }
+            // Nothing was scanned of what the merge wrote; the next sync
+            // catalogs it.
+            SyncOpKind::Merge => {
+                files.remove(&op.rel_path);
+            }
+            SyncOpKind::SetPermissions => {
+                if let (Some(entry), Some(from)) = (files.get_mut(&op.rel_path), copied) {
+                    entry.permissions = match (from.permissions, entry.permissions) {
+                        (Some(mut set), Some(had)) if !ownership => {
+                            (set.uid, set.gid) = (had.uid, had.gid);
+                            Some(set)
+                        }
+                        (set, _) => set,
+                    };
+                }
+            }
+            SyncOpKind::CreateDir => {
+                files.insert(
+                    op.rel_path.clone(),
+                    FileEntry {
+                        rel_path: op.rel_path.clone(),
+                        drive_id: op.target_drive.clone(),
+                        is_dir: true,
+                        size: 0,
+                        mtime: Utc::now(),
+                        xxh3_hash: None,
+                        sampled_hash: None,
+                        sha256_hash: None,
+                        blake3_hash: None,
+                        permissions: None,
+                        indexed_at: Utc::now(),
+                    },
+                );
+            }
+            _ => {
+                if let Some(entry) = copied {
+                    files.insert(op.rel_path.clone(), entry);
+                }
+            }
+        }
+    }
+
+    for (drive_id, rel_path) in unresolved {
+        let Some(files) = catalog.get_mut(drive_id) else {
+            continue;
+        };
+        match ops::get_file_entry(conn, drive_id, rel_path)? {
+            Some(before) => files.insert(rel_path.clone(), before),
+            None => files.remove(rel_path),
+        };
+    }
+
+    for (drive_id, files) in catalog {
+        let entries: Vec<FileEntry> = files.into_values().collect();
+        ops::replace_file_index(conn, &drive_id, &entries)?;
+        // Cached hashes for files that are gone would otherwise pile up.
+        ops::prune_hash_cache(conn, &drive_id)?;
+    }
+    Ok(())
+}
+
+/// Both sides of each conflict that no operation in `journal` settled,
+/// whether it was left unresolved, filtered out or failed.
+fn unsettled(conflicts: &[PendingConflict], journal: &SpillVec<JournalEntry>) -> Vec<(DriveId, PathBuf)> {
+    let sides = |c: &PendingConflict| [(c.left.clone(), c.rel_path.clone()), (c.right.clone(), c.rel_path.clone())];
+    // The journal is read once, crossing off each side an overwrite or
+    // merge wrote to.
+    let mut open: HashSet<(DriveId, PathBuf)> = conflicts.iter().flat_map(sides).collect();
+    for e in journal.iter().filter(|e| matches!(e.op.kind, SyncOpKind::Overwrite | SyncOpKind::Merge)) {
+        open.remove(&(e.op.target_drive.clone(), e.op.rel_path.clone()));
+    }
+    conflicts
+        .iter()
+        .filter(|c| sides(c).iter().all(|side| open.contains(side)))
+        .flat_map(sides)
+        .collect()
+}
+
+/// Apply `trash_days` retention to the trash of each of `drives` that's
+/// reachable.
+fn purge_expired_trash(drives: &[&Drive], trash_days: Option<u32>) {
+    let Some(days) = trash_days else {
+        return;
+    };
+    for drive in drives {
+        let root = drive.effective_root();
+        if drive.read_only || !root.exists() {
+            continue;
+        }
+        match diffr_archive::trash::empty_trash(drive, Some(days)) {
+            Ok(result) if result.items_removed > 0 => tracing::info!(
+                "purged {} expired trash items ({} bytes) from {}",
+                result.items_removed,
+                result.bytes_freed,
+                root.display()
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("failed to purge trash on {}: {}", root.display(), e),
+        }
+    }
+}
+
+/// Record the layout of `cluster`, made of `members`, on each of `drives`
+/// that's reachable so it can be adopted from another machine with
+/// `diffr adopt`.
+fn write_drive_manifests(cluster: &Cluster, members: &[Drive], drives: &[&Drive]) {
+    let manifest = ClusterManifest::new(cluster, members);
+    for drive in drives {
+        let root = drive.effective_root();
+        if drive.read_only || !root.exists() {
+            continue;
+        }
+        let result = DriveManifest::new(drive.identity.clone(), manifest.clone())
+            .and_then(|m| m.write_to(&drive.diffr_dir()));
+        if let Err(e) = result {
+            tracing::warn!("failed to write manifest to {}: {}", root.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::config::RepoConfig;
+    use diffr_core::models::cluster::{ConflictStrategy, NestedRepos};
+    use diffr_core::models::drive::DriveIdentity;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_engine_syncs_headlessly() {
+        let db_dir = TempDir::new().unwrap();
+        let db_path = db_dir.path().join("diffr.db");
+        let conn = diffr_db::open_db(&db_path).unwrap();
+        let config = DiffrConfig::default();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a_dir, b_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(a_dir.path().join("a.txt"), "from a").unwrap();
+        for dir in [&a_dir, &b_dir] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+        }
+
+        let drives = ops::list_drives_for_cluster(&conn, &cluster.id).unwrap();
+        let mut diffed = 0;
+        let outcome = SyncEngine::new(&conn, &config)
+            .on_event(|event| {
+                if let SyncEvent::Diffed { .. } = event {
+                    diffed += 1;
+                }
+            })
+            .run(
+                &cluster,
+                &drives,
+                &SyncOptions {
+                    host: Some("laptop".into()),
+                    message: Some("first copy".into()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(diffed, 1);
+        let record = outcome.record.expect("the sync ran");
+        assert_eq!((record.status, record.files_synced), (SyncStatus::Success, 1));
+        let saved = ops::get_sync_record(&conn, &record.id).unwrap().unwrap();
+        assert_eq!(saved.host.as_deref(), Some("laptop"));
+        assert_eq!(saved.message.as_deref(), Some("first copy"));
+        assert_eq!(std::fs::read_to_string(b_dir.path().join("a.txt")).unwrap(), "from a");
+        for drive in &outcome.drives {
+            assert_eq!(ops::get_file_entries_for_drive(&conn, &drive.id).unwrap().len(), 1);
+        }
+
+        // Nothing left to do, so nothing is recorded.
+        let again = spawn_sync(db_path, config, cluster, drives, SyncOptions::default(), |_| {}).wait().unwrap();
+        assert!(again.record.is_none());
+        assert!(again.plan.operations.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_errors_are_recorded_and_can_stop_the_sync() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a_dir, b_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(a_dir.path().join("a.txt"), "from a").unwrap();
+        // Followed, a dangling link can't be read.
+        std::os::unix::fs::symlink(a_dir.path().join("gone"), a_dir.path().join("dangling")).unwrap();
+        let mut drives = Vec::new();
+        for dir in [&a_dir, &b_dir] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+
+        let strict = SyncOptions {
+            follow_symlinks: true,
+            max_scan_errors: Some(0),
+            ..Default::default()
+        };
+        let mut stopped = false;
+        let Err(err) = SyncEngine::new(&conn, &config)
+            .on_event(|event| {
+                if let SyncEvent::TooManyScanErrors { errors, .. } = event {
+                    stopped = errors.len() == 1;
+                }
+            })
+            .run(&cluster, &drives, &strict)
+        else {
+            panic!("the sync should have been refused");
+        };
+        assert!(stopped);
+        assert!(err.to_string().contains("exceed --max-scan-errors 0"), "{}", err);
+        assert!(!b_dir.path().join("a.txt").exists());
+
+        let lenient = SyncOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let outcome = SyncEngine::new(&conn, &config).run(&cluster, &drives, &lenient).unwrap();
+        assert_eq!(outcome.scan_errors.len(), 1);
+        let record = outcome.record.expect("the sync ran");
+        assert_eq!(record.status, SyncStatus::PartialSuccess);
+        assert!(record.errors[0].starts_with("scan "), "{:?}", record.errors);
+        assert!(b_dir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_locked_drive_is_refused() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a_dir, b_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(a_dir.path().join("a.txt"), "from a").unwrap();
+        let mut drives = Vec::new();
+        for dir in [&a_dir, &b_dir] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+        // Only discovery knows the volume is locked; the database doesn't.
+        drives[1].locked = true;
+
+        let err = SyncEngine::new(&conn, &config)
+            .run(&cluster, &drives, &SyncOptions::default())
+            .err()
+            .expect("a locked drive stops the sync");
+        assert!(err.to_string().contains("locked"), "{}", err);
+        assert!(!b_dir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_changes_on_both_sides_are_resolved_as_conflicts() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::Interactive);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a_dir, b_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(a_dir.path().join("a.txt"), "synced").unwrap();
+        let mut drives = Vec::new();
+        for dir in [&a_dir, &b_dir] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+        // One file is all of each drive.
+        let options = SyncOptions {
+            allow_mass_change: true,
+            allow_anomaly: true,
+            ..SyncOptions::default()
+        };
+        let sync = |cluster: &Cluster| {
+            SyncEngine::new(&conn, &config)
+                .run(cluster, &drives, &options)
+                .unwrap()
+                .record
+                .expect("the sync ran")
+        };
+        sync(&cluster);
+        std::fs::write(a_dir.path().join("a.txt"), "edited on a").unwrap();
+        std::fs::write(b_dir.path().join("a.txt"), "edited on b too").unwrap();
+
+        // Nobody to ask, so neither version wins, now or next time.
+        for _ in 0..2 {
+            let record = sync(&cluster);
+            assert_eq!((record.status, record.conflicts_resolved), (SyncStatus::Failed, 0));
+            assert_eq!(std::fs::read_to_string(a_dir.path().join("a.txt")).unwrap(), "edited on a");
+            assert_eq!(std::fs::read_to_string(b_dir.path().join("a.txt")).unwrap(), "edited on b too");
+        }
+
+        cluster.conflict_strategy = ConflictStrategy::KeepBoth;
+        let record = sync(&cluster);
+        assert_eq!((record.status, record.conflicts_resolved), (SyncStatus::Success, 1));
+        for dir in [&a_dir, &b_dir] {
+            let mut names: Vec<String> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.'))
+                .collect();
+            names.sort();
+            assert_eq!(names.len(), 2, "{:?}", names);
+            assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "edited on a");
+            assert_eq!(std::fs::read_to_string(dir.path().join(&names[0])).unwrap(), "edited on b too");
+        }
+    }
+
+    #[test]
+    fn test_drives_left_out_are_left_alone() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap()];
+        std::fs::write(dirs[0].path().join("a.txt"), "from a").unwrap();
+        let mut drives = Vec::new();
+        for dir in &dirs {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+
+        let options = SyncOptions {
+            drives: Some(vec![drives[0].id.clone(), drives[1].id.clone()]),
+            ..SyncOptions::default()
+        };
+        SyncEngine::new(&conn, &config).run(&cluster, &drives, &options).unwrap();
+        assert!(dirs[1].path().join("a.txt").exists());
+        assert!(DriveManifest::read_from(dirs[1].path()).unwrap().is_some());
+        assert!(!dirs[2].path().join("a.txt").exists());
+        assert!(DriveManifest::read_from(dirs[2].path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_wait_for_held_files_ends_at_the_deadline() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        cluster.settings.stability_window = Some(3600);
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let (a_dir, b_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(a_dir.path().join("a.txt"), "still downloading").unwrap();
+        let mut drives = Vec::new();
+        for dir in [&a_dir, &b_dir] {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+
+        // Without the deadline this would wait out the hour-long window.
+        let options = SyncOptions {
+            deadline: Some(Utc::now()),
+            ..SyncOptions::default()
+        };
+        let started = std::time::Instant::now();
+        let outcome = SyncEngine::new(&conn, &config).run(&cluster, &drives, &options).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert_eq!(outcome.held.len(), 1);
+        assert!(!b_dir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_nested_repo_on_one_drive_bounds_both() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        cluster.settings.nested_repos = NestedRepos::SkipDiffr;
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+        RepoConfig::new(None).save(&dirs[0].path().join("sub")).unwrap();
+        std::fs::write(dirs[0].path().join("sub/inner.txt"), "repo").unwrap();
+        std::fs::create_dir(dirs[1].path().join("sub")).unwrap();
+        std::fs::write(dirs[1].path().join("sub/plain.txt"), "plain").unwrap();
+        std::fs::write(dirs[1].path().join("top.txt"), "top").unwrap();
+        let mut drives = Vec::new();
+        for dir in &dirs {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            ops::insert_drive(&conn, &drive).unwrap();
+            drives.push(drive);
+        }
+
+        let outcome = SyncEngine::new(&conn, &config)
+            .run(&cluster, &drives, &SyncOptions::default())
+            .unwrap();
+        assert!(dirs[0].path().join("top.txt").exists());
+        assert!(!dirs[0].path().join("sub/plain.txt").exists());
+        assert!(!dirs[1].path().join("sub/inner.txt").exists());
+        let bounded: Vec<_> = outcome
+            .skipped
+            .iter()
+            .filter(|s| s.reason == SkipReason::NestedRepo)
+            .map(|s| &s.drive_id)
+            .collect();
+        assert_eq!(bounded, vec![&drives[0].id, &drives[1].id]);
+    }
+
+    #[test]
+    fn test_central_archive_goes_to_the_archive_drive() {
+        let conn = diffr_db::open_memory_db().unwrap();
+        let config = DiffrConfig::default();
+        let mut cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        cluster.settings.archive_strategy = ArchiveStrategy::Central;
+        ops::insert_cluster(&conn, &cluster).unwrap();
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap()];
+        std::fs::write(dirs[0].path().join("a.txt"), "new").unwrap();
+        std::fs::write(dirs[1].path().join("a.txt"), "old").unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(dirs[1].path().join("a.txt"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let mut drives = Vec::new();
+        for dir in &dirs {
+            let mut drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+            drive.cluster_id = Some(cluster.id.clone());
+            drives.push(drive);
+        }
+        drives[2].role = DriveRole::ArchiveOnly;
+        for drive in &drives {
+            ops::insert_drive(&conn, drive).unwrap();
+        }
+
+        let options = SyncOptions {
+            allow_mass_change: true,
+            ..SyncOptions::default()
+        };
+        SyncEngine::new(&conn, &config).run(&cluster, &drives, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(dirs[1].path().join("a.txt")).unwrap(), "new");
+        let archives = ops::list_archives_for_drive(&conn, &drives[2].id).unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].relocated_from, Some(drives[1].id.clone()));
+        assert!(!dirs[1].path().join(".diffr/archive").exists());
+    }
+
+    #[test]
+    fn test_catalog_keeps_owner_unless_synced() {
+        use diffr_core::models::file_entry::FilePermissions;
+
+        let conn = diffr_db::open_memory_db().unwrap();
+        let (a, b) = (
+            Drive::new(DriveIdentity::new_synthetic(), "/a".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/b".into()),
+        );
+        ops::insert_drive(&conn, &a).unwrap();
+        ops::insert_drive(&conn, &b).unwrap();
+        let entry = |drive: &Drive, mode, owner| FileEntry {
+            rel_path: "f.sh".into(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size: 1,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: Some(FilePermissions {
+                mode,
+                uid: owner,
+                gid: owner,
+                changed_at: Utc::now(),
+            }),
+            indexed_at: Utc::now(),
+        };
+        let (a_files, b_files) = (vec![entry(&a, 0o755, 1000)], vec![entry(&b, 0o644, 1001)]);
+        let journal = SpillVec::from(vec![JournalEntry {
+            sync_id: uuid::Uuid::now_v7(),
+            op: SyncOp {
+                id: uuid::Uuid::now_v7(),
+                kind: SyncOpKind::SetPermissions,
+                rel_path: "f.sh".into(),
+                source_drive: Some(a.id.clone()),
+                target_drive: b.id.clone(),
+                size_bytes: 0,
+                source_path: None,
+                reason: None,
+                expect: None,
+            },
+            archive_id: None,
+            trash_path: None,
+        }]);
+        let scans = [(a.id.clone(), a_files.as_slice()), (b.id.clone(), b_files.as_slice())];
+
+        let cataloged = |ownership| {
+            update_catalog(&conn, &scans, &journal, &[], ownership).unwrap();
+            let entry = ops::get_file_entry(&conn, &b.id, std::path::Path::new("f.sh")).unwrap().unwrap();
+            let permissions = entry.permissions.unwrap();
+            (permissions.mode, permissions.uid)
+        };
+        assert_eq!(cataloged(false), (0o755, 1001));
+        assert_eq!(cataloged(true), (0o755, 1000));
+    }
+}
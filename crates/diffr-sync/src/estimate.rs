@@ -0,0 +1,68 @@
+use diffr_core::models::drive::DriveId;
+use diffr_core::models::sync_state::{SyncPlan, Throughput};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Estimate how long a plan's transfers will take from the throughput
+/// measured on earlier syncs. Pairs that were never measured use the
+/// average across all measured pairs. Returns `None` with no history.
+pub fn estimate_duration(plan: &SyncPlan, history: &[Throughput]) -> Option<Duration> {
+    let measured: Vec<&Throughput> = history.iter().filter(|t| t.bytes_per_sec() > 0.0).collect();
+    if measured.is_empty() {
+        return None;
+    }
+    let rates: HashMap<(&DriveId, &DriveId), f64> = measured
+        .iter()
+        .map(|t| ((&t.source_drive, &t.target_drive), t.bytes_per_sec()))
+        .collect();
+    let bytes: u64 = measured.iter().map(|t| t.bytes).sum();
+    let seconds: f64 = measured.iter().map(|t| t.seconds).sum();
+    let average = bytes as f64 / seconds;
+
+    let total: f64 = plan
+        .operations
+        .iter()
+        .filter_map(|op| {
+            let source = op.source_drive.as_ref()?;
+            let rate = rates.get(&(source, &op.target_drive)).copied().unwrap_or(average);
+            Some(op.size_bytes as f64 / rate)
+        })
+        .sum();
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::sync_state::{SyncOp, SyncOpKind};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_estimate_duration() {
+        let (a, b, c) = (DriveId::new(), DriveId::new(), DriveId::new());
+        let copy = |source: &DriveId, target: &DriveId, size: u64| SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: "f".into(),
+            source_drive: Some(source.clone()),
+            target_drive: target.clone(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![copy(&a, &b, 1000), copy(&a, &c, 300)]);
+        assert!(estimate_duration(&plan, &[]).is_none());
+
+        // a -> b runs at 100 B/s; a -> c was never measured, so it gets the
+        // overall average, which is also 100 B/s.
+        let history = [Throughput {
+            source_drive: a.clone(),
+            target_drive: b.clone(),
+            bytes: 500,
+            seconds: 5.0,
+        }];
+        assert_eq!(estimate_duration(&plan, &history), Some(Duration::from_secs(13)));
+    }
+}
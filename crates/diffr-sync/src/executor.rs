@@ -1,21 +1,98 @@
-use chrono::Utc;
-use diffr_core::models::drive::Drive;
-use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan, SyncRecord, SyncStatus};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashMap;
-use std::path::Path;
+use chrono::{DateTime, Utc};
+use diffr_archive::{archiver, placement, trash};
+use diffr_core::cancel::CancellationToken;
+use diffr_core::config::{Durability, HashAlgorithm};
+use diffr_core::models::archive::{ArchiveEntry, ArchiveReason, CompressionFormat};
+use diffr_core::models::cluster::by_extension;
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::sync_state::{
+    FileStamp, JournalEntry, SkipReason, SkippedFile, SyncOp, SyncOpKind, SyncPlan, SyncRecord, SyncStatus,
+    Throughput,
+};
+use diffr_core::power::{Pacer, PowerGuard};
+use diffr_core::progress::{Progress, ProgressEvent};
+use diffr_core::spill::SpillVec;
+use diffr_core::task::{self, Task};
+use diffr_discovery::mtp::MtpStore;
+use diffr_scan::errors::ScanErrorKind;
+use diffr_scan::{cache, hasher};
+use diffr_scan::scanner::modified_time;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::attributes::{self, Attributes, PathAttributes};
+use crate::conflict::run_merge_command;
+
+/// Buffer for copies that are hashed as they go.
+const COPY_BUF_SIZE: usize = 1024 * 1024;
+
+/// Under [`Durability::Periodic`], how much can be copied to a drive, or
+/// for how long, before it's flushed.
+const FLUSH_EVERY_BYTES: u64 = 256 * 1024 * 1024;
+const FLUSH_EVERY: Duration = Duration::from_secs(30);
+
 /// Configuration for a sync execution.
 pub struct ExecConfig {
     /// If true, don't actually copy/delete files — just report what would happen.
     pub dry_run: bool,
-    /// If true, verify file integrity after copy with SHA-256.
+    /// If true, verify file integrity after copy by hashing both ends.
     pub verify: bool,
+    /// Hash that `verify` compares.
+    pub verify_hash: HashAlgorithm,
+    /// If true, `verify` reads each copy back and compares its hash with
+    /// the one taken as the source streamed through. If false, copies are
+    /// only checked for length, with no extra reads.
+    pub verify_readback: bool,
     /// If true, archive files before overwriting/deleting.
     pub archive: bool,
-    /// Show progress bars.
-    pub show_progress: bool,
+    /// If true, archives go to a connected archive drive among the plan's
+    /// drives when one has room, and to the drive being changed otherwise.
+    pub central_archive: bool,
+    /// Where to report each operation starting and finishing.
+    pub progress: Progress,
+    /// If true, deletes move files into `.diffr/trash` instead of removing
+    /// them. Trashed files are not archived.
+    pub trash: bool,
+    /// If true, copies and permission changes also carry the source's owner
+    /// and group over. Needs root unless they match the current user.
+    pub ownership: bool,
+    /// When copies are flushed to disk. `None` flushes each file on
+    /// removable drives, where a buffered copy is lost if the drive is
+    /// pulled, and leaves it to the OS elsewhere.
+    pub durability: Option<Durability>,
+    /// Stop before the next operation once cancelled. The operations not
+    /// run are skipped, and the record's status is `Cancelled`.
+    pub cancel: CancellationToken,
+    /// Each drive's `.diffrattributes`, which can override `verify` and
+    /// `archive` for the paths it names and turn off their compression.
+    pub attributes: HashMap<DriveId, Attributes>,
+    /// Battery and heat limits, checked between operations. Ignored on a
+    /// dry run.
+    pub power: Option<PowerGuard>,
+    /// The config's `[merge]` commands by extension, which `Merge` ops run.
+    pub merge_commands: BTreeMap<String, String>,
+    /// Most bytes a second to copy, on average. Enforced between files, so
+    /// one large file still copies at full speed. Ignored on a dry run.
+    pub max_rate: Option<u64>,
+    /// Opens the MTP device with a serial, for drives reached through
+    /// libmtp rather than a mount: [`MtpStore::open`], unless a test
+    /// stands a fake in.
+    pub open_mtp: fn(&str) -> anyhow::Result<MtpStore>,
+}
+
+impl ExecConfig {
+    /// How copies to `drive` are flushed.
+    pub fn durability_for(&self, drive: &Drive) -> Durability {
+        match self.durability {
+            Some(durability) => durability,
+            None if drive.bus.is_removable() => Durability::PerFile,
+            None => Durability::None,
+        }
+    }
 }
 
 impl Default for ExecConfig {
@@ -23,8 +100,198 @@ impl Default for ExecConfig {
         Self {
             dry_run: false,
             verify: false,
+            verify_hash: HashAlgorithm::default(),
+            verify_readback: true,
             archive: true,
-            show_progress: true,
+            central_archive: false,
+            progress: Progress::none(),
+            trash: false,
+            ownership: false,
+            durability: None,
+            cancel: CancellationToken::default(),
+            attributes: HashMap::new(),
+            power: None,
+            merge_commands: BTreeMap::new(),
+            max_rate: None,
+            open_mtp: MtpStore::open,
+        }
+    }
+}
+
+/// Outcome of executing a sync plan.
+pub struct ExecResult {
+    pub record: SyncRecord,
+    /// Archive entries written before overwrites/deletes. The caller decides
+    /// whether to persist them.
+    pub archives: Vec<ArchiveEntry>,
+    /// One entry per operation that completed, for `diffr undo`. Empty on a
+    /// dry run. Like the plan, it's kept on disk once it grows large.
+    pub journal: SpillVec<JournalEntry>,
+    /// Copy speed measured for each source/target drive pair. Empty on a
+    /// dry run.
+    pub throughput: Vec<Throughput>,
+    /// Files whose operation failed or was refused, with the reason in
+    /// `record.errors`.
+    pub skipped: Vec<SkippedFile>,
+}
+
+impl ExecResult {
+    /// Fold in the result of a follow-up run of the same sync, such as a
+    /// retry of held-back files, so both are recorded as one sync.
+    pub fn absorb(&mut self, other: ExecResult) {
+        let record = &mut self.record;
+        record.finished_at = other.record.finished_at;
+        record.files_synced += other.record.files_synced;
+        record.bytes_transferred += other.record.bytes_transferred;
+        record.errors.extend(other.record.errors);
+        record.status = if record.status == SyncStatus::Cancelled || other.record.status == SyncStatus::Cancelled {
+            SyncStatus::Cancelled
+        } else if record.errors.is_empty() {
+            SyncStatus::Success
+        } else if record.files_synced > 0 {
+            SyncStatus::PartialSuccess
+        } else {
+            SyncStatus::Failed
+        };
+        let sync_id = record.id;
+        self.journal.extend(other.journal.into_iter().map(|e| JournalEntry { sync_id, ..e }));
+        self.archives.extend(other.archives);
+        self.throughput.extend(other.throughput);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+/// What a single operation left behind that an undo would need.
+#[derive(Default)]
+struct OpOutcome {
+    archive: Option<ArchiveEntry>,
+    trash_path: Option<PathBuf>,
+    /// A merge also replaces the source's version: its archive, if one
+    /// was taken, and whether it was replaced.
+    source_archive: Option<ArchiveEntry>,
+    merged: bool,
+    /// The file it wrote, if it's still to be flushed.
+    unflushed: Option<PathBuf>,
+}
+
+/// Files copied to a drive since it was last flushed.
+struct Unflushed {
+    files: Vec<PathBuf>,
+    bytes: u64,
+    since: Instant,
+}
+
+impl Unflushed {
+    fn new() -> Self {
+        Unflushed {
+            files: Vec::new(),
+            bytes: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.bytes >= FLUSH_EVERY_BYTES || self.since.elapsed() >= FLUSH_EVERY
+    }
+
+    /// Flush the files and then their directories. One that can't be
+    /// flushed doesn't stop the rest; the errors are returned.
+    fn flush(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut dirs = BTreeSet::new();
+        for file in self.files.drain(..) {
+            // Opened read-only: a copy of a read-only file can't be opened
+            // for writing, and fsync doesn't need it.
+            if let Err(e) = std::fs::File::open(&file).and_then(|f| f.sync_all()) {
+                tracing::warn!("failed to flush {}: {}", file.display(), e);
+                errors.push(format!("{}: {}", file.display(), e));
+            }
+            if let Some(parent) = file.parent() {
+                dirs.insert(parent.to_path_buf());
+            }
+        }
+        for dir in dirs {
+            if let Err(e) = sync_dir(&dir) {
+                tracing::warn!("failed to flush {}: {}", dir.display(), e);
+                errors.push(format!("{}: {}", dir.display(), e));
+            }
+        }
+        *self = Unflushed::new();
+        errors
+    }
+}
+
+/// The MTP devices a run copies to and from through libmtp, each opened
+/// the first time it's needed and held until the run ends.
+struct Devices {
+    open: fn(&str) -> anyhow::Result<MtpStore>,
+    stores: HashMap<String, MtpStore>,
+}
+
+impl Devices {
+    fn new(open: fn(&str) -> anyhow::Result<MtpStore>) -> Self {
+        Devices {
+            open,
+            stores: HashMap::new(),
+        }
+    }
+
+    /// If `drive` is a device reached through libmtp, its store and where
+    /// `rel_path` is on it.
+    fn locate(&mut self, drive: &Drive, rel_path: &Path) -> anyhow::Result<Option<(&mut MtpStore, PathBuf)>> {
+        let Some((serial, root)) = drive.mtp_device() else {
+            return Ok(None);
+        };
+        let store = match self.stores.entry(serial.to_string()) {
+            Entry::Occupied(store) => store.into_mut(),
+            Entry::Vacant(slot) => slot.insert((self.open)(serial)?),
+        };
+        Ok(Some((store, root.join(rel_path))))
+    }
+}
+
+/// Holds execution back under a [`PowerGuard`]: waiting before an
+/// operation while paused, and resting after one while throttled. Also
+/// keeps copying under `max_rate`.
+struct Governor<'a> {
+    pacer: Pacer,
+    config: &'a ExecConfig,
+    started: Instant,
+    bytes: u64,
+}
+
+impl<'a> Governor<'a> {
+    fn new(config: &'a ExecConfig) -> Self {
+        Governor {
+            pacer: Pacer::new(config.power.filter(|_| !config.dry_run)),
+            config,
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Return once the next operation can run, or the sync is cancelled.
+    fn wait(&mut self) {
+        let progress = &self.config.progress;
+        let paused = self.pacer.wait(&self.config.cancel, |reason| {
+            tracing::info!("pausing: {}", reason);
+            progress.event(ProgressEvent::ExecPaused { reason });
+        });
+        if paused {
+            progress.event(ProgressEvent::ExecResumed);
+        }
+    }
+
+    /// Rest after an operation that took `took` and moved `bytes`, while
+    /// throttled or for as long as the copying is ahead of `max_rate`.
+    fn rest(&mut self, took: Duration, bytes: u64) {
+        self.pacer.rest(took, &self.config.cancel);
+        let Some(rate) = self.config.max_rate.filter(|_| !self.config.dry_run) else { return };
+        self.bytes += bytes;
+        let due = Duration::from_secs_f64(self.bytes as f64 / rate as f64);
+        let ahead = due.saturating_sub(self.started.elapsed());
+        if !ahead.is_zero() {
+            self.config.cancel.sleep(ahead);
         }
     }
 }
@@ -34,33 +301,76 @@ pub fn execute_plan(
     plan: &SyncPlan,
     drives: &[Drive],
     config: &ExecConfig,
-) -> anyhow::Result<SyncRecord> {
+) -> anyhow::Result<ExecResult> {
+    let sync_id = Uuid::now_v7();
     let started_at = Utc::now();
+    // Everything this run trashes goes in one batch.
+    let trash_batch = trash::batch_name(started_at);
     let drive_map: HashMap<_, _> = drives.iter().map(|d| (&d.id, d)).collect();
 
-    let pb = if config.show_progress && !plan.operations.is_empty() {
-        let pb = ProgressBar::new(plan.operations.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        Some(pb)
-    } else {
-        None
-    };
+    let ops = plan.operations.len() as u64;
+    config.progress.event(ProgressEvent::ExecStarted { ops });
 
     let mut files_synced = 0u64;
     let mut bytes_transferred = 0u64;
     let mut errors = Vec::new();
+    let mut archives = Vec::new();
+    let mut journal = SpillVec::spilling_after(plan.operations.spill_after());
+    let mut measured: HashMap<(DriveId, DriveId), (u64, f64)> = HashMap::new();
+    let mut unflushed: HashMap<DriveId, Unflushed> = HashMap::new();
+    let mut skipped = Vec::new();
+    // Files this run has already written, which the plan couldn't have
+    // seen as they are now.
+    let mut written: HashSet<(DriveId, PathBuf)> = HashSet::new();
+    // Bytes archived to each drive so far, which its free space as last
+    // discovered doesn't show.
+    let mut archived: HashMap<DriveId, u64> = HashMap::new();
+    let failed = |op: &SyncOp| SkippedFile {
+        rel_path: op.rel_path.clone(),
+        drive_id: op.target_drive.clone(),
+        reason: SkipReason::Failed,
+    };
+
+    let mut governor = Governor::new(config);
+    let mut devices = Devices::new(config.open_mtp);
+    let mut cancelled = false;
+    // The plan is read an op at a time, so one kept on disk stays there.
+    for (i, op) in plan.operations.iter().enumerate() {
+        let op = op.as_ref();
+        governor.wait();
+        if config.cancel.is_cancelled() {
+            cancelled = true;
+            skipped.extend(plan.operations.iter().skip(i).map(|op| SkippedFile {
+                reason: SkipReason::Cancelled,
+                ..failed(&op)
+            }));
+            break;
+        }
+        let index = i as u64;
+        config.progress.event(ProgressEvent::OpStarted { index, op });
 
-    for op in &plan.operations {
-        if let Some(ref pb) = pb {
-            pb.set_message(format!("{}", op.rel_path.display()));
+        if let Some(reason) = crate::safety::protected_reason(op, drives) {
+            let msg = format!("refusing to {} {}: {}", op.kind, op.rel_path.display(), reason);
+            tracing::error!("{}", msg);
+            errors.push(msg);
+            skipped.push(failed(op));
+            config.progress.event(ProgressEvent::OpFinished { index, op, ok: false });
+            continue;
         }
 
-        if config.dry_run {
+        if !config.dry_run {
+            if let Some(change) = changed_since_planned(op, &drive_map, &written, &mut devices) {
+                tracing::warn!("skipping {} {}: {}", op.kind, op.rel_path.display(), change);
+                skipped.push(SkippedFile {
+                    reason: SkipReason::Changed,
+                    ..failed(op)
+                });
+                config.progress.event(ProgressEvent::OpFinished { index, op, ok: false });
+                continue;
+            }
+        }
+
+        let ok = if config.dry_run {
             tracing::info!(
                 "[dry-run] {} {} -> {}",
                 op.kind,
@@ -69,30 +379,96 @@ pub fn execute_plan(
             );
             files_synced += 1;
             bytes_transferred += op.size_bytes;
+            true
         } else {
-            match execute_op(op, &drive_map, config) {
-                Ok(()) => {
+            let op_started = Instant::now();
+            let result = execute_op(op, &drive_map, &archived, &trash_batch, config, &mut devices);
+            let took = op_started.elapsed();
+            let moved = match &result {
+                Ok(_) if matches!(op.kind, SyncOpKind::CopyNew | SyncOpKind::Overwrite) => op.size_bytes,
+                _ => 0,
+            };
+            governor.rest(took, moved);
+            match result {
+                Ok(outcome) => {
                     files_synced += 1;
                     bytes_transferred += op.size_bytes;
+                    if let (SyncOpKind::CopyNew | SyncOpKind::Overwrite, Some(source)) =
+                        (&op.kind, &op.source_drive)
+                    {
+                        let pair = measured.entry((source.clone(), op.target_drive.clone())).or_default();
+                        pair.0 += op.size_bytes;
+                        pair.1 += took.as_secs_f64();
+                    }
+                    if let Some(file) = outcome.unflushed {
+                        let pending = unflushed.entry(op.target_drive.clone()).or_insert_with(Unflushed::new);
+                        pending.files.push(file);
+                        pending.bytes += op.size_bytes;
+                        if pending.is_due() {
+                            for e in pending.flush() {
+                                errors.push(format!("flushing copies to {}: {}", op.target_drive, e));
+                            }
+                        }
+                    }
+                    written.insert((op.target_drive.clone(), op.rel_path.clone()));
+                    for entry in outcome.archive.iter().chain(&outcome.source_archive) {
+                        *archived.entry(entry.drive_id.clone()).or_default() += entry.compressed_size;
+                    }
+                    journal.push(JournalEntry {
+                        sync_id,
+                        op: op.clone(),
+                        archive_id: outcome.archive.as_ref().map(|a| a.id),
+                        trash_path: outcome.trash_path,
+                    });
+                    archives.extend(outcome.archive);
+                    // The source's side of a merge is journaled on its own,
+                    // so it can be undone like the target's.
+                    if let (true, Some(source)) = (outcome.merged, &op.source_drive) {
+                        written.insert((source.clone(), op.rel_path.clone()));
+                        journal.push(JournalEntry {
+                            sync_id,
+                            op: SyncOp {
+                                id: Uuid::now_v7(),
+                                source_drive: Some(op.target_drive.clone()),
+                                target_drive: source.clone(),
+                                ..op.clone()
+                            },
+                            archive_id: outcome.source_archive.as_ref().map(|a| a.id),
+                            trash_path: None,
+                        });
+                        archives.extend(outcome.source_archive);
+                    }
+                    true
                 }
                 Err(e) => {
                     let msg = format!("{}: {}", op.rel_path.display(), e);
                     tracing::error!("{}", msg);
                     errors.push(msg);
+                    skipped.push(SkippedFile {
+                        reason: match ScanErrorKind::of_any(&e) {
+                            ScanErrorKind::Locked => SkipReason::Locked,
+                            _ => SkipReason::Failed,
+                        },
+                        ..failed(op)
+                    });
+                    false
                 }
             }
-        }
+        };
+        config.progress.event(ProgressEvent::OpFinished { index, op, ok });
+    }
 
-        if let Some(ref pb) = pb {
-            pb.inc(1);
+    for (drive, pending) in &mut unflushed {
+        for e in pending.flush() {
+            errors.push(format!("flushing copies to {}: {}", drive, e));
         }
     }
 
-    if let Some(pb) = pb {
-        pb.finish_with_message("Sync complete");
-    }
+    config.progress.event(ProgressEvent::ExecFinished { ops });
 
-    let status = if errors.is_empty() {
+    let status = if cancelled {
+        SyncStatus::Cancelled
+    } else if errors.is_empty() {
         SyncStatus::Success
     } else if files_synced > 0 {
         SyncStatus::PartialSuccess
@@ -100,8 +476,8 @@ pub fn execute_plan(
         SyncStatus::Failed
     };
 
-    Ok(SyncRecord {
-        id: Uuid::now_v7(),
+    let record = SyncRecord {
+        id: sync_id,
         cluster_id: plan.cluster_id.clone(),
         started_at,
         finished_at: Utc::now(),
@@ -110,19 +486,170 @@ pub fn execute_plan(
         conflicts_resolved: 0,
         errors,
         status,
+        // Who ran the sync, and why, is filled in by the caller.
+        host: None,
+        user: None,
+        diffr_version: None,
+        message: None,
+    };
+
+    let throughput = measured
+        .into_iter()
+        .filter(|(_, (bytes, seconds))| *bytes > 0 && *seconds > 0.0)
+        .map(|((source_drive, target_drive), (bytes, seconds))| Throughput {
+            source_drive,
+            target_drive,
+            bytes,
+            seconds,
+        })
+        .collect();
+
+    Ok(ExecResult {
+        record,
+        archives,
+        journal,
+        throughput,
+        skipped,
     })
 }
 
+/// [`execute_plan`] on a thread of its own.
+pub fn execute_plan_async(plan: SyncPlan, drives: Vec<Drive>, config: ExecConfig) -> Task<anyhow::Result<ExecResult>> {
+    task::spawn(move || execute_plan(&plan, &drives, &config))
+}
+
+/// Archive the file an operation replaces, reporting how far along a large
+/// one is.
+fn archive(
+    target: &Drive,
+    op: &SyncOp,
+    reason: ArchiveReason,
+    attrs: &PathAttributes,
+    drives: &HashMap<&DriveId, &Drive>,
+    archived: &HashMap<DriveId, u64>,
+    config: &ExecConfig,
+) -> anyhow::Result<ArchiveEntry> {
+    let store = if config.central_archive {
+        let size = std::fs::metadata(target.effective_root().join(&op.rel_path))?.len();
+        placement::central_target(target, drives.values().copied(), size, archived).unwrap_or(target)
+    } else {
+        target
+    };
+    let compression = match attrs.compress {
+        Some(false) => CompressionFormat::None,
+        _ => CompressionFormat::Zstd,
+    };
+    archiver::archive_file_to(target, store, &op.rel_path, reason, compression, &mut |done, total| {
+        config.progress.event(ProgressEvent::OpBytes { op, done, total });
+    })
+}
+
+/// How the files `op` copies and replaces differ from what the plan saw of
+/// them, if they do. Files `written` earlier in the run are taken as they
+/// are, and a delete whose file is already gone has nothing to do.
+fn changed_since_planned(
+    op: &SyncOp,
+    drives: &HashMap<&DriveId, &Drive>,
+    written: &HashSet<(DriveId, PathBuf)>,
+    devices: &mut Devices,
+) -> Option<String> {
+    let expect = op.expect.as_ref()?;
+    let mut now = |drive_id: &DriveId| {
+        let Some(drive) = drives.get(drive_id) else {
+            return Vec::new();
+        };
+        match devices.locate(drive, &op.rel_path) {
+            Ok(Some((store, path))) => device_stamps(store, &path),
+            // Copying to or from it fails with why.
+            Err(_) => Vec::new(),
+            Ok(None) => stamps_now(&drive.effective_root().join(&op.rel_path)),
+        }
+    };
+    let describe = |stamp: Option<&FileStamp>| stamp.map_or_else(|| "no file".to_string(), |s| s.to_string());
+
+    if let (Some(source_id), Some(planned)) = (&op.source_drive, &expect.source) {
+        let found = now(source_id);
+        if !written.contains(&(source_id.clone(), op.rel_path.clone())) && !found.contains(planned) {
+            return Some(format!(
+                "source changed since the plan was made (expected {}, found {})",
+                planned,
+                describe(found.first())
+            ));
+        }
+    }
+    let found = now(&op.target_drive);
+    let unchanged = match &expect.target {
+        Some(planned) => found.contains(planned) || (op.kind == SyncOpKind::Delete && found.is_empty()),
+        None => found.is_empty(),
+    };
+    if !unchanged && !written.contains(&(op.target_drive.clone(), op.rel_path.clone())) {
+        return Some(format!(
+            "target changed since the plan was made (expected {}, found {})",
+            describe(expect.target.as_ref()),
+            describe(found.first())
+        ));
+    }
+    None
+}
+
+/// What's at `path` now: nothing, a file, or a symlink and the file it
+/// points to, since a scan records one or the other depending on whether
+/// it follows links.
+fn stamps_now(path: &Path) -> Vec<FileStamp> {
+    let stamp = |metadata: std::fs::Metadata| FileStamp {
+        size: metadata.len(),
+        mtime: modified_time(&metadata),
+    };
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Vec::new();
+    };
+    let is_symlink = metadata.file_type().is_symlink();
+    let mut stamps = vec![stamp(metadata)];
+    if is_symlink {
+        stamps.extend(std::fs::metadata(path).ok().map(stamp));
+    }
+    stamps
+}
+
+/// What's at `path` on an MTP device now, as [`stamps_now`] has it.
+fn device_stamps(store: &mut MtpStore, path: &Path) -> Vec<FileStamp> {
+    match store.stat(path) {
+        Ok(Some(object)) if !object.is_dir => vec![FileStamp {
+            size: object.size,
+            mtime: DateTime::from_timestamp(object.mtime, 0).unwrap_or_default(),
+        }],
+        _ => Vec::new(),
+    }
+}
+
 /// Execute a single sync operation.
 fn execute_op(
     op: &SyncOp,
-    drives: &HashMap<&diffr_core::models::drive::DriveId, &Drive>,
-    _config: &ExecConfig,
-) -> anyhow::Result<()> {
+    drives: &HashMap<&DriveId, &Drive>,
+    archived: &HashMap<DriveId, u64>,
+    trash_batch: &str,
+    config: &ExecConfig,
+    devices: &mut Devices,
+) -> anyhow::Result<OpOutcome> {
     let target = drives
         .get(&op.target_drive)
         .ok_or_else(|| anyhow::anyhow!("target drive not found: {}", op.target_drive))?;
+    if target.read_only {
+        anyhow::bail!(
+            "target drive {} is read-only",
+            target.identity.identity_string()
+        );
+    }
+    if target.locked {
+        anyhow::bail!(
+            "target drive {} is locked (BitLocker); unlock it and sync again",
+            target.identity.identity_string()
+        );
+    }
 
+    let attrs = attributes::for_op(&config.attributes, op);
+    let archive_first = attrs.archive.unwrap_or(config.archive);
+    let mut outcome = OpOutcome::default();
     match op.kind {
         SyncOpKind::CopyNew | SyncOpKind::Overwrite => {
             let source_id = op
@@ -132,29 +659,229 @@ fn execute_op(
             let source = drives
                 .get(source_id)
                 .ok_or_else(|| anyhow::anyhow!("source drive not found: {}", source_id))?;
+            if source.mtp_device().is_some() && target.mtp_device().is_some() {
+                anyhow::bail!("can't copy from one MTP device to another; sync each with a drive on this machine");
+            }
 
             let src_path = source.effective_root().join(&op.rel_path);
+            if let Some((store, path)) = devices.locate(target, &op.rel_path)? {
+                // Nothing can be archived on the device.
+                if archive_first && op.kind == SyncOpKind::Overwrite {
+                    anyhow::bail!(
+                        "the version on {} can't be archived; sync with --no-archive to replace it",
+                        target.identity.identity_string()
+                    );
+                }
+                let mtime = DateTime::<Utc>::from(std::fs::metadata(&src_path)?.modified()?);
+                store.put(&src_path, &path, mtime.timestamp())?;
+                return Ok(outcome);
+            }
             let dst_path = target.effective_root().join(&op.rel_path);
+            // Copying a file onto itself, through drives that overlap,
+            // would truncate it, or archive and replace it with itself.
+            if cache::same_file(&src_path, &dst_path) {
+                anyhow::bail!(
+                    "{} and {} are the same file; do the drives overlap?",
+                    src_path.display(),
+                    dst_path.display()
+                );
+            }
+
+            if archive_first && op.kind == SyncOpKind::Overwrite && dst_path.is_file() {
+                let entry = archive(target, op, ArchiveReason::BeforeOverwrite, &attrs, drives, archived, config)?;
+                outcome.archive = Some(entry);
+            }
+
+            let durability = config.durability_for(target);
+            if let Some((store, path)) = devices.locate(source, &op.rel_path)? {
+                fetch_atomic(store, &path, &dst_path, durability == Durability::PerFile)?;
+                if durability == Durability::Periodic {
+                    outcome.unflushed = Some(dst_path);
+                }
+                return Ok(outcome);
+            }
+            let verify = attrs.verify.unwrap_or(config.verify);
+            let stream_hash = verify.then_some(config.verify_hash);
+            let src_hash = if target.is_mtp() {
+                direct_copy(&src_path, &dst_path, stream_hash)?
+            } else {
+                let fsync = durability == Durability::PerFile;
+                atomic_copy(&src_path, &dst_path, stream_hash, fsync)?
+            };
+
+            if config.ownership {
+                copy_permissions(&src_path, &dst_path, true)?;
+            }
 
-            atomic_copy(&src_path, &dst_path)?;
+            if let (Some(src_hash), true) = (src_hash, config.verify_readback) {
+                verify_copy(&dst_path, &src_hash, config.verify_hash)?;
+            }
+            if durability == Durability::Periodic && !target.is_mtp() {
+                outcome.unflushed = Some(dst_path);
+            }
+        }
+        SyncOpKind::SetPermissions => {
+            let source_id = op
+                .source_drive
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no source drive for permissions op"))?;
+            let source = drives
+                .get(source_id)
+                .ok_or_else(|| anyhow::anyhow!("source drive not found: {}", source_id))?;
+            // Devices keep no permissions.
+            if source.mtp_device().is_some() || target.mtp_device().is_some() {
+                return Ok(outcome);
+            }
+            copy_permissions(
+                &source.effective_root().join(&op.rel_path),
+                &target.effective_root().join(&op.rel_path),
+                config.ownership,
+            )?;
         }
         SyncOpKind::Delete => {
+            if let Some((store, path)) = devices.locate(target, &op.rel_path)? {
+                if config.trash || archive_first {
+                    anyhow::bail!(
+                        "files on {} can't be trashed or archived; sync with --no-archive and without trash to delete them",
+                        target.identity.identity_string()
+                    );
+                }
+                store.remove(&path)?;
+                return Ok(outcome);
+            }
             let dst_path = target.effective_root().join(&op.rel_path);
             if dst_path.exists() {
+                if config.trash {
+                    outcome.trash_path = Some(trash::move_to_trash(target, &op.rel_path, trash_batch)?);
+                    return Ok(outcome);
+                }
+                if archive_first {
+                    let entry = archive(target, op, ArchiveReason::BeforeDelete, &attrs, drives, archived, config)?;
+                    outcome.archive = Some(entry);
+                }
                 std::fs::remove_file(&dst_path)?;
             }
         }
+        SyncOpKind::LocalCopy => {
+            let from = op
+                .source_path
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no source path for local copy"))?;
+            if target.mtp_device().is_some() {
+                anyhow::bail!("MTP devices can't copy files in place");
+            }
+            let src_path = target.effective_root().join(from);
+            let dst_path = target.effective_root().join(&op.rel_path);
+            if dst_path.exists() {
+                anyhow::bail!("local copy target already exists: {}", dst_path.display());
+            }
+            if target.is_mtp() {
+                direct_copy(&src_path, &dst_path, None)?;
+            } else {
+                let durability = config.durability_for(target);
+                atomic_copy(&src_path, &dst_path, None, durability == Durability::PerFile)?;
+                if durability == Durability::Periodic {
+                    outcome.unflushed = Some(dst_path);
+                }
+            }
+        }
+        SyncOpKind::CreateDir => match devices.locate(target, &op.rel_path)? {
+            Some((store, path)) => {
+                store.create_dir_all(&path)?;
+            }
+            None => std::fs::create_dir_all(target.effective_root().join(&op.rel_path))?,
+        },
+        SyncOpKind::RemoveDir => {
+            // Only ever remove a directory the plan has already emptied;
+            // anything left in it (e.g. ignored files) stays put.
+            if let Some((store, path)) = devices.locate(target, &op.rel_path)? {
+                store.remove(&path)?;
+                return Ok(outcome);
+            }
+            let dst_path = target.effective_root().join(&op.rel_path);
+            if dst_path.is_dir() {
+                if std::fs::read_dir(&dst_path)?.next().is_some() {
+                    anyhow::bail!("directory is not empty: {}", dst_path.display());
+                }
+                std::fs::remove_dir(&dst_path)?;
+            }
+        }
+        SyncOpKind::Merge => {
+            let source_id = op
+                .source_drive
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no source drive for merge op"))?;
+            let source = drives
+                .get(source_id)
+                .ok_or_else(|| anyhow::anyhow!("source drive not found: {}", source_id))?;
+            if source.read_only {
+                anyhow::bail!("source drive {} is read-only", source.identity.identity_string());
+            }
+            if source.mtp_device().is_some() || target.mtp_device().is_some() {
+                anyhow::bail!("files on MTP devices can't be merged; resolve the conflict another way");
+            }
+            let (_, command) = by_extension(&config.merge_commands, &op.rel_path)
+                .ok_or_else(|| anyhow::anyhow!("no merge command for {}", op.rel_path.display()))?;
+
+            let src_path = source.effective_root().join(&op.rel_path);
+            let dst_path = target.effective_root().join(&op.rel_path);
+            let merged = run_merge_command(command, &src_path, &dst_path)?;
+            if archive_first {
+                let entry = archive(target, op, ArchiveReason::BeforeOverwrite, &attrs, drives, archived, config)?;
+                outcome.archive = Some(entry);
+                let entry = archive(source, op, ArchiveReason::BeforeOverwrite, &attrs, drives, archived, config)?;
+                outcome.source_archive = Some(entry);
+            }
+
+            // Both sides get the same modification time, so the next sync
+            // sees them as identical.
+            let mtime = std::time::SystemTime::now();
+            for (drive, path) in [(*target, &dst_path), (*source, &src_path)] {
+                let fsync = config.durability_for(drive) == Durability::PerFile;
+                write_atomic(path, &merged, mtime, fsync)?;
+            }
+            outcome.merged = true;
+        }
         SyncOpKind::ResolveConflict => {
-            // Conflicts should be resolved before reaching the executor
-            tracing::warn!("unresolved conflict: {}", op.rel_path.display());
+            // Resolving replaces these ops, so one left here couldn't be.
+            anyhow::bail!("conflict left unresolved; both versions were kept as they are");
         }
     }
 
+    Ok(outcome)
+}
+
+/// Write `data` to `path` through a temp file beside it, keeping the
+/// permissions of the file it replaces.
+fn write_atomic(path: &Path, data: &[u8], mtime: std::time::SystemTime, fsync: bool) -> anyhow::Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+    temp.write_all(data)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        temp.as_file().set_permissions(metadata.permissions())?;
+    }
+    temp.as_file().set_modified(mtime)?;
+    if fsync {
+        temp.as_file().sync_all()?;
+    }
+    temp.persist(path)?;
+    if fsync {
+        sync_dir(parent)?;
+    }
     Ok(())
 }
 
 /// Atomic file copy: write to temp file in target directory, then rename.
-fn atomic_copy(src: &Path, dst: &Path) -> anyhow::Result<()> {
+///
+/// With `hash`, the data is copied a buffer at a time and hashed on the way
+/// through, and the source's hash is returned. With `fsync`, the copy and
+/// its directory are flushed to disk before returning.
+fn atomic_copy(
+    src: &Path,
+    dst: &Path,
+    hash: Option<HashAlgorithm>,
+    fsync: bool,
+) -> anyhow::Result<Option<String>> {
     // Verify source exists and is accessible
     if !src.exists() {
         anyhow::bail!("source file does not exist: {}", src.display());
@@ -168,11 +895,214 @@ fn atomic_copy(src: &Path, dst: &Path) -> anyhow::Result<()> {
     // Write to temp file in the same directory
     let parent = dst.parent().unwrap_or(Path::new("."));
     let temp = tempfile::NamedTempFile::new_in(parent)?;
-    std::fs::copy(src, temp.path())?;
+    let src_len = std::fs::metadata(src)?.len();
+    let src_hash = match hash {
+        Some(algorithm) => {
+            let hashed = copy_hashed(src, &mut temp.as_file(), algorithm)?;
+            temp.as_file().set_permissions(std::fs::metadata(src)?.permissions())?;
+            Some(hashed)
+        }
+        None => {
+            std::fs::copy(src, temp.path())?;
+            None
+        }
+    };
+
+    // Carry the source mtime over so metadata-only diffs see the files as identical
+    let mtime = std::fs::metadata(src)?.modified()?;
+    temp.as_file().set_modified(mtime)?;
+    if fsync {
+        temp.as_file().sync_all()?;
+    }
+
+    // A short write, or a source that changed length under the copy, never
+    // replaces `dst`.
+    let written = temp.as_file().metadata()?.len();
+    if written != src_len {
+        anyhow::bail!(
+            "copied {} bytes of {}, which had {}",
+            written,
+            src.display(),
+            src_len
+        );
+    }
+
+    // Atomic rename (same filesystem). The temp file is beside `dst`, but
+    // `dst` can still resolve onto another mount through a bind mount or
+    // junction.
+    if let Err(e) = temp.persist(dst) {
+        if !crosses_mount(&e.error) {
+            return Err(e.error.into());
+        }
+        // Syncs whatever it writes.
+        persist_across_devices(e.file, dst)?;
+    } else if fsync {
+        sync_dir(parent)?;
+    }
+
+    Ok(src_hash)
+}
+
+/// Fetch `path` from an MTP device to `dst` through a temp file beside it,
+/// with the modification time the device has for it, so the next scan
+/// sees the two as the same.
+fn fetch_atomic(store: &mut MtpStore, path: &Path, dst: &Path, fsync: bool) -> anyhow::Result<()> {
+    let parent = dst.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent)?;
+    let temp = tempfile::NamedTempFile::new_in(parent)?;
+    let object = store.fetch(path, temp.path())?;
+    let written = temp.as_file().metadata()?.len();
+    if written != object.size {
+        anyhow::bail!("fetched {} bytes of {}, which has {}", written, path.display(), object.size);
+    }
+    let mtime = DateTime::from_timestamp(object.mtime, 0).unwrap_or_default();
+    temp.as_file().set_modified(mtime.into())?;
+    if fsync {
+        temp.as_file().sync_all()?;
+    }
+    if let Err(e) = temp.persist(dst) {
+        if !crosses_mount(&e.error) {
+            return Err(e.error.into());
+        }
+        persist_across_devices(e.file, dst)?;
+    } else if fsync {
+        sync_dir(parent)?;
+    }
+    Ok(())
+}
+
+/// Put a finished temp file in place at `dst` when `dst` is on another
+/// filesystem: copy it next to where `dst` really lives and rename it
+/// there, or write through `dst` if it's a mount point itself, syncing
+/// the data and the directory either way.
+fn persist_across_devices(temp: tempfile::NamedTempFile, dst: &Path) -> anyhow::Result<()> {
+    let real = match dst.canonicalize() {
+        Ok(real) => real,
+        Err(_) => match (dst.parent(), dst.file_name()) {
+            (Some(parent), Some(name)) => parent.canonicalize()?.join(name),
+            _ => dst.to_path_buf(),
+        },
+    };
+    let real_parent = real.parent().unwrap_or(Path::new("."));
+    let mtime = temp.as_file().metadata()?.modified()?;
+
+    let staged = tempfile::NamedTempFile::new_in(real_parent)?;
+    std::fs::copy(temp.path(), staged.path())?;
+    staged.as_file().set_modified(mtime)?;
+    staged.as_file().sync_all()?;
+    if let Err(e) = staged.persist(&real) {
+        if !crosses_mount(&e.error) {
+            return Err(e.error.into());
+        }
+        // Not atomic, but nothing on this side of the mount can be.
+        let mut out = std::fs::File::create(&real)?;
+        std::io::copy(&mut std::fs::File::open(temp.path())?, &mut out)?;
+        out.set_modified(mtime)?;
+        out.sync_all()?;
+    }
+    sync_dir(real_parent)?;
+    Ok(())
+}
+
+/// Whether a rename failed because its target is on another mount: EXDEV,
+/// or EBUSY when the target is itself a mount point, such as a
+/// bind-mounted file.
+fn crosses_mount(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::CrossesDevices | std::io::ErrorKind::ResourceBusy
+    )
+}
 
-    // Atomic rename (same filesystem)
-    temp.persist(dst)?;
+/// Flush a directory's entries, so a file renamed into it survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::File::open(dir)?.sync_all()
+}
 
+/// Windows has no way to flush a directory; NTFS journals its entries.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Copy straight onto the destination, for MTP devices: they can't rename a
+/// temp file into place and ignore mtimes, so an interrupted copy can
+/// leave a partial file that the next sync overwrites.
+fn direct_copy(src: &Path, dst: &Path, hash: Option<HashAlgorithm>) -> anyhow::Result<Option<String>> {
+    if !src.exists() {
+        anyhow::bail!("source file does not exist: {}", src.display());
+    }
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match hash {
+        Some(algorithm) => Ok(Some(copy_hashed(src, &mut std::fs::File::create(dst)?, algorithm)?)),
+        None => {
+            std::fs::copy(src, dst)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Copy `src` into `out` a buffer at a time, returning its hash with
+/// `algorithm`. Fails if fewer or more bytes arrive than the source had
+/// when opened, as when it's being written to.
+fn copy_hashed(src: &Path, out: &mut impl Write, algorithm: HashAlgorithm) -> anyhow::Result<String> {
+    let mut input = std::fs::File::open(src)?;
+    let expected = input.metadata()?.len();
+    let mut hasher = hasher::StreamHasher::new(algorithm);
+    let mut buf = vec![0u8; COPY_BUF_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    if copied != expected {
+        anyhow::bail!(
+            "{} changed while being copied: expected {} bytes, read {}",
+            src.display(),
+            expected,
+            copied
+        );
+    }
+    Ok(hasher.finish())
+}
+
+/// Give `dst` the permission bits of `src`, and with `ownership` its owner
+/// and group too (Unix only). Ownership goes first, since chown can clear
+/// the setuid and setgid bits.
+fn copy_permissions(src: &Path, dst: &Path, ownership: bool) -> anyhow::Result<()> {
+    let metadata = std::fs::metadata(src)?;
+    #[cfg(unix)]
+    if ownership {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::chown(dst, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = ownership;
+    std::fs::set_permissions(dst, metadata.permissions())?;
+    Ok(())
+}
+
+/// Read a copy back and compare its hash with the source's, taken as it
+/// was copied.
+fn verify_copy(dst: &Path, src_hash: &str, algorithm: HashAlgorithm) -> anyhow::Result<()> {
+    let dst_hash = hasher::strong_hash_file(dst, algorithm)?;
+    if src_hash != dst_hash {
+        anyhow::bail!(
+            "verification failed for {}: expected {} {}, got {}",
+            dst.display(),
+            algorithm,
+            src_hash,
+            dst_hash
+        );
+    }
     Ok(())
 }
 
@@ -190,9 +1120,13 @@ mod tests {
         std::fs::write(&src_file, "hello world").unwrap();
 
         let dst_file = dst_dir.path().join("test.txt");
-        atomic_copy(&src_file, &dst_file).unwrap();
+        atomic_copy(&src_file, &dst_file, None, false).unwrap();
 
         assert_eq!(std::fs::read_to_string(&dst_file).unwrap(), "hello world");
+        assert_eq!(
+            std::fs::metadata(&dst_file).unwrap().modified().unwrap(),
+            std::fs::metadata(&src_file).unwrap().modified().unwrap()
+        );
     }
 
     #[test]
@@ -204,8 +1138,554 @@ mod tests {
         std::fs::write(&src_file, "content").unwrap();
 
         let dst_file = dst_dir.path().join("sub/dir/test.txt");
-        atomic_copy(&src_file, &dst_file).unwrap();
+        atomic_copy(&src_file, &dst_file, None, false).unwrap();
 
         assert!(dst_file.exists());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_persist_across_devices() {
+        let dir = TempDir::new().unwrap();
+        let real = dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, dir.path().join("link")).unwrap();
+
+        let temp = tempfile::NamedTempFile::new_in(dir.path()).unwrap();
+        std::fs::write(temp.path(), "copied").unwrap();
+        let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        temp.as_file().set_modified(mtime).unwrap();
+
+        // Staged beside where the target really is, through the link.
+        persist_across_devices(temp, &dir.path().join("link/a.txt")).unwrap();
+        assert_eq!(std::fs::read_to_string(real.join("a.txt")).unwrap(), "copied");
+        assert_eq!(std::fs::metadata(real.join("a.txt")).unwrap().modified().unwrap(), mtime);
+        assert_eq!(std::fs::read_dir(&real).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_copy_hashes_as_it_copies() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let src_file = dir.path().join("big.bin");
+        let data: Vec<u8> = (0..COPY_BUF_SIZE * 2 + 17).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&src_file, &data).unwrap();
+        std::fs::set_permissions(&src_file, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let dst_file = dir.path().join("copy/big.bin");
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            let hash = atomic_copy(&src_file, &dst_file, Some(algorithm), false).unwrap().unwrap();
+            assert_eq!(hash, hasher::strong_hash_file(&src_file, algorithm).unwrap());
+            verify_copy(&dst_file, &hash, algorithm).unwrap();
+        }
+        assert_eq!(std::fs::read(&dst_file).unwrap(), data);
+        let mode = std::fs::metadata(&dst_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_overwrite_archives_previous_version() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        std::fs::write(src_dir.path().join("a.txt"), "new").unwrap();
+        std::fs::write(dst_dir.path().join("a.txt"), "old").unwrap();
+
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::Overwrite,
+            rel_path: "a.txt".into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 3,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op]);
+        let config = ExecConfig {
+            verify: true,
+            progress: Progress::none(),
+            ..Default::default()
+        };
+
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert_eq!(result.record.status, SyncStatus::Success);
+        assert_eq!(result.archives.len(), 1);
+        assert_eq!(result.journal.len(), 1);
+        let journal = result.journal.to_vec();
+        assert_eq!(journal[0].sync_id, result.record.id);
+        assert_eq!(journal[0].archive_id, Some(result.archives[0].id));
+        assert_eq!(
+            std::fs::read_to_string(dst_dir.path().join("a.txt")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn test_files_changed_since_planning_are_skipped() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+        use diffr_core::models::sync_state::Precondition;
+
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let stamp_of = |path: &Path| stamps_now(path).first().copied();
+        let copy = |name: &str| {
+            std::fs::write(src_dir.path().join(name), "new").unwrap();
+            SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: name.into(),
+                source_drive: Some(src.id.clone()),
+                target_drive: dst.id.clone(),
+                size_bytes: 3,
+                source_path: None,
+                reason: None,
+                expect: Some(Precondition {
+                    source: stamp_of(&src_dir.path().join(name)),
+                    target: None,
+                }),
+            }
+        };
+        let ops = vec![copy("same.txt"), copy("edited.txt"), copy("appeared.txt")];
+        std::fs::write(src_dir.path().join("edited.txt"), "newer").unwrap();
+        std::fs::write(dst_dir.path().join("appeared.txt"), "theirs").unwrap();
+
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        let result = execute_plan(&plan, &[src, dst], &ExecConfig::default()).unwrap();
+        assert_eq!(result.record.files_synced, 1);
+        assert!(result.record.errors.is_empty());
+        let changed: Vec<_> = result.skipped.iter().map(|s| (s.rel_path.clone(), s.reason)).collect();
+        assert_eq!(
+            changed,
+            [
+                ("edited.txt".into(), SkipReason::Changed),
+                ("appeared.txt".into(), SkipReason::Changed),
+            ]
+        );
+        assert!(!dst_dir.path().join("edited.txt").exists());
+        assert_eq!(std::fs::read_to_string(dst_dir.path().join("appeared.txt")).unwrap(), "theirs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_onto_itself_is_refused() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        // The second drive's root is a link to the first's, as if the same
+        // disk were mounted twice.
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("a");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "only copy").unwrap();
+        std::os::unix::fs::symlink(&root, dir.path().join("b")).unwrap();
+
+        let src = Drive::new(DriveIdentity::new_synthetic(), root.clone());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dir.path().join("b"));
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::Overwrite,
+            rel_path: "a.txt".into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 9,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op]);
+        let result = execute_plan(&plan, &[src, dst], &ExecConfig::default()).unwrap();
+        assert_eq!(result.record.status, SyncStatus::Failed);
+        assert!(result.record.errors[0].contains("same file"));
+        assert!(result.archives.is_empty());
+        assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "only copy");
+    }
+
+    #[test]
+    fn test_attributes_override_archiving() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        for name in ["a.mkv", "b.tmp"] {
+            std::fs::write(src_dir.path().join(name), "new").unwrap();
+            std::fs::write(dst_dir.path().join(name), "old").unwrap();
+        }
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let op = |path: &str| SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::Overwrite,
+            rel_path: path.into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 3,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op("a.mkv"), op("b.tmp")]);
+        let attributes = Attributes::parse("*.mkv compress=false\n*.tmp archive=never\n").unwrap();
+        let config = ExecConfig {
+            attributes: HashMap::from([(src.id.clone(), attributes)]),
+            ..Default::default()
+        };
+
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert_eq!(result.record.status, SyncStatus::Success);
+        assert_eq!(result.archives.len(), 1);
+        let archive = &result.archives[0];
+        assert_eq!((archive.original_path.as_path(), &archive.compression), (Path::new("a.mkv"), &CompressionFormat::None));
+        assert_eq!(std::fs::read(dst_dir.path().join(&archive.archive_path)).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_cancelled_sync_skips_the_rest() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        let (src_dir, dst_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src_dir.path().join("a.txt"), "a").unwrap();
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: "a.txt".into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 1,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op]);
+        let config = ExecConfig {
+            progress: Progress::none(),
+            ..Default::default()
+        };
+        config.cancel.cancel();
+
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert_eq!((result.record.status, result.record.files_synced), (SyncStatus::Cancelled, 0));
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(result.skipped[0].reason, SkipReason::Cancelled);
+        assert!(!dst_dir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_copies_to_and_from_an_mtp_device() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::{DriveIdentity, MTP_FILESYSTEM};
+        use diffr_discovery::fake::FakeMtpDevice;
+        use diffr_discovery::mtp::ROOT;
+
+        thread_local! {
+            static PHONE: FakeMtpDevice = FakeMtpDevice::new(&["Internal shared storage"]);
+        }
+        fn open_phone(serial: &str) -> anyhow::Result<MtpStore> {
+            assert_eq!(serial, "R58M12345");
+            MtpStore::new(Box::new(PHONE.with(FakeMtpDevice::clone)))
+        }
+        let phone = PHONE.with(FakeMtpDevice::clone);
+        let dcim = phone.add(0x10001, ROOT, "DCIM", None, 0);
+        phone.add(0x10001, dcim, "IMG_1.jpg", Some(b"one"), 1_700_000_000);
+
+        let local_dir = TempDir::new().unwrap();
+        std::fs::write(local_dir.path().join("notes.txt"), "notes").unwrap();
+        let local = Drive::new(DriveIdentity::new_synthetic(), local_dir.path().to_path_buf());
+        let mut device = Drive::new(
+            DriveIdentity::new_synthetic(),
+            PathBuf::from("mtp://R58M12345/Internal shared storage"),
+        );
+        device.filesystem = Some(MTP_FILESYSTEM.to_string());
+        let op = |kind, rel_path: &str, source: Option<&Drive>, target: &Drive| SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: rel_path.into(),
+            source_drive: source.map(|d| d.id.clone()),
+            target_drive: target.id.clone(),
+            size_bytes: 0,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(
+            ClusterId::new(),
+            vec![
+                op(SyncOpKind::CopyNew, "DCIM/IMG_1.jpg", Some(&device), &local),
+                op(SyncOpKind::CreateDir, "Backup/2024", None, &device),
+                op(SyncOpKind::CopyNew, "notes.txt", Some(&local), &device),
+                // Archiving is on, and nothing can be archived on the device.
+                op(SyncOpKind::Delete, "DCIM/IMG_1.jpg", None, &device),
+            ],
+        );
+        let config = ExecConfig {
+            open_mtp: open_phone,
+            ..Default::default()
+        };
+
+        let result = execute_plan(&plan, &[local, device], &config).unwrap();
+        assert_eq!(result.record.files_synced, 3);
+        assert_eq!(result.record.errors.len(), 1);
+        assert!(result.record.errors[0].contains("--no-archive"), "{:?}", result.record.errors);
+
+        let fetched = local_dir.path().join("DCIM/IMG_1.jpg");
+        assert_eq!(std::fs::read(&fetched).unwrap(), b"one");
+        assert_eq!(
+            DateTime::<Utc>::from(std::fs::metadata(&fetched).unwrap().modified().unwrap()).timestamp(),
+            1_700_000_000
+        );
+        let mut store = MtpStore::new(Box::new(phone.clone())).unwrap();
+        let sent = store.stat(Path::new("Internal shared storage/notes.txt")).unwrap().unwrap();
+        assert_eq!(phone.contents(sent.id).unwrap(), b"notes");
+        assert!(store.stat(Path::new("Internal shared storage/Backup/2024")).unwrap().unwrap().is_dir);
+        assert!(store.stat(Path::new("Internal shared storage/DCIM/IMG_1.jpg")).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_runs_a_plan_kept_on_disk() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        let (src_dir, dst_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+        let mut ops = SpillVec::spilling_after(2);
+        for name in names {
+            std::fs::write(src_dir.path().join(name), name).unwrap();
+            ops.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: name.into(),
+                source_drive: Some(src.id.clone()),
+                target_drive: dst.id.clone(),
+                size_bytes: name.len() as u64,
+                source_path: None,
+                reason: None,
+                expect: None,
+            });
+        }
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        assert!(plan.operations.is_spilled());
+
+        let result = execute_plan(&plan, &[src, dst], &ExecConfig::default()).unwrap();
+        assert_eq!((result.record.status, result.record.files_synced), (SyncStatus::Success, 5));
+        for name in names {
+            assert_eq!(std::fs::read_to_string(dst_dir.path().join(name)).unwrap(), name);
+        }
+        // The journal follows the plan onto disk, in the order the ops ran.
+        assert!(result.journal.is_spilled());
+        let journaled: Vec<_> = result.journal.iter().map(|e| e.op.rel_path.clone()).collect();
+        assert_eq!(journaled, names.map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_paused_on_battery_until_cancelled() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+        use diffr_core::power::{BatteryPolicy, PowerReading};
+        use diffr_core::schedule::PowerSource;
+
+        let (src_dir, dst_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(src_dir.path().join("a.txt"), "a").unwrap();
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: "a.txt".into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 1,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op]);
+        let config = ExecConfig {
+            power: Some(PowerGuard {
+                on_battery: BatteryPolicy::Pause,
+                max_cpu_temp: None,
+                throttle_cpu_temp: None,
+                read: || PowerReading {
+                    power: Some(PowerSource::Battery),
+                    cpu_temp: None,
+                },
+            }),
+            ..Default::default()
+        };
+        let cancel = config.cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert_eq!((result.record.status, result.record.files_synced), (SyncStatus::Cancelled, 0));
+        assert!(!dst_dir.path().join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_copies_kept_under_max_rate() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+
+        let (src_dir, dst_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let ops = ["a.txt", "b.txt"]
+            .iter()
+            .map(|name| {
+                std::fs::write(src_dir.path().join(name), vec![b'x'; 1000]).unwrap();
+                SyncOp {
+                    id: Uuid::now_v7(),
+                    kind: SyncOpKind::CopyNew,
+                    rel_path: name.into(),
+                    source_drive: Some(src.id.clone()),
+                    target_drive: dst.id.clone(),
+                    size_bytes: 1000,
+                    source_path: None,
+                    reason: None,
+                    expect: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        let config = ExecConfig {
+            max_rate: Some(10_000),
+            ..Default::default()
+        };
+
+        // 2000 bytes at 10 KB a second take at least 0.2 seconds.
+        let started = Instant::now();
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert_eq!(result.record.files_synced, 2);
+        assert!(started.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn test_durability() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::{BusType, DriveIdentity};
+
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let mut dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+
+        let config = ExecConfig {
+            progress: Progress::none(),
+            ..Default::default()
+        };
+        assert_eq!(config.durability_for(&dst), Durability::None);
+        dst.bus = BusType::Usb;
+        assert_eq!(config.durability_for(&dst), Durability::PerFile);
+
+        let ops = ["a.txt", "sub/b.txt"]
+            .iter()
+            .map(|name| {
+                std::fs::create_dir_all(src_dir.path().join(name).parent().unwrap()).unwrap();
+                std::fs::write(src_dir.path().join(name), name).unwrap();
+                SyncOp {
+                    id: Uuid::now_v7(),
+                    kind: SyncOpKind::CopyNew,
+                    rel_path: name.into(),
+                    source_drive: Some(src.id.clone()),
+                    target_drive: dst.id.clone(),
+                    size_bytes: name.len() as u64,
+                    source_path: None,
+                    reason: None,
+                    expect: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+        for durability in [Durability::PerFile, Durability::Periodic] {
+            let config = ExecConfig {
+                durability: Some(durability),
+                progress: Progress::none(),
+                ..Default::default()
+            };
+            let result = execute_plan(&plan, &[src.clone(), dst.clone()], &config).unwrap();
+            assert_eq!(result.record.errors, Vec::<String>::new());
+            assert_eq!(
+                std::fs::read_to_string(dst_dir.path().join("sub/b.txt")).unwrap(),
+                "sub/b.txt"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_flush_carries_on_past_a_failure() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let read_only = dir.path().join("read-only.txt");
+        std::fs::write(&read_only, "kept").unwrap();
+        std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o444)).unwrap();
+        let mut pending = Unflushed::new();
+        pending.files = vec![dir.path().join("gone.txt"), read_only];
+        pending.bytes = 4;
+
+        let errors = pending.flush();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("gone.txt"));
+        assert!(pending.files.is_empty());
+        assert_eq!(pending.bytes, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_permissions() {
+        use diffr_core::models::cluster::ClusterId;
+        use diffr_core::models::drive::DriveIdentity;
+        use std::os::unix::fs::PermissionsExt;
+
+        let src_dir = TempDir::new().unwrap();
+        let dst_dir = TempDir::new().unwrap();
+        std::fs::write(src_dir.path().join("run.sh"), "new").unwrap();
+        std::fs::write(dst_dir.path().join("run.sh"), "old").unwrap();
+        let mode = std::fs::Permissions::from_mode(0o750);
+        std::fs::set_permissions(src_dir.path().join("run.sh"), mode).unwrap();
+
+        let src = Drive::new(DriveIdentity::new_synthetic(), src_dir.path().to_path_buf());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), dst_dir.path().to_path_buf());
+        let op = SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::SetPermissions,
+            rel_path: "run.sh".into(),
+            source_drive: Some(src.id.clone()),
+            target_drive: dst.id.clone(),
+            size_bytes: 0,
+            source_path: None,
+            reason: None,
+            expect: None,
+        };
+        let plan = SyncPlan::new(ClusterId::new(), vec![op]);
+        let config = ExecConfig {
+            progress: Progress::none(),
+            ..Default::default()
+        };
+        let result = execute_plan(&plan, &[src, dst], &config).unwrap();
+        assert!(result.record.errors.is_empty());
+
+        // The mode changes; the content doesn't.
+        let dst_file = dst_dir.path().join("run.sh");
+        assert_eq!(std::fs::metadata(&dst_file).unwrap().permissions().mode() & 0o7777, 0o750);
+        assert_eq!(std::fs::read_to_string(&dst_file).unwrap(), "old");
+    }
 }
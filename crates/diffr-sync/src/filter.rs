@@ -0,0 +1,127 @@
+use diffr_core::models::sync_state::{PathSkip, SyncOp, SyncOpKind, SyncPlan};
+use diffr_core::spill::SpillVec;
+
+/// Which operation kinds a sync is allowed to execute.
+#[derive(Debug, Clone, Default)]
+pub struct OpFilter {
+    /// If set, only these kinds run.
+    pub only: Option<Vec<SyncOpKind>>,
+    pub no_deletes: bool,
+    /// Also excludes conflict resolution, which overwrites one side.
+    pub no_overwrites: bool,
+}
+
+impl OpFilter {
+    pub fn allows(&self, kind: &SyncOpKind) -> bool {
+        // Directory ops go along with the copies or deletes they belong to.
+        let as_kind = match kind {
+            SyncOpKind::CreateDir => &SyncOpKind::CopyNew,
+            SyncOpKind::RemoveDir => &SyncOpKind::Delete,
+            _ => kind,
+        };
+        if let Some(only) = &self.only {
+            if !only.contains(as_kind) {
+                return false;
+            }
+        }
+        match as_kind {
+            SyncOpKind::Delete | SyncOpKind::RemoveDir => !self.no_deletes,
+            SyncOpKind::Overwrite | SyncOpKind::ResolveConflict | SyncOpKind::Merge => !self.no_overwrites,
+            SyncOpKind::CopyNew
+            | SyncOpKind::LocalCopy
+            | SyncOpKind::CreateDir
+            | SyncOpKind::SetPermissions => true,
+        }
+    }
+
+    /// Drop disallowed operations from the plan. Returns the ones removed.
+    pub fn apply(&self, plan: &mut SyncPlan) -> SpillVec<SyncOp> {
+        let removed = plan.operations.remove_where(|op| !self.allows(&op.kind));
+        plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+        removed
+    }
+}
+
+/// Drop the operations under any of `skips` from the plan. Returns the ones
+/// removed.
+pub fn drop_skipped(plan: &mut SyncPlan, skips: &[PathSkip]) -> SpillVec<SyncOp> {
+    if skips.is_empty() {
+        return SpillVec::new();
+    }
+    let removed = plan.operations.remove_where(|op| skips.iter().any(|s| s.covers(&op.rel_path)));
+    plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+    removed
+}
+
+/// Parse an `--only` value: `copies`, `overwrites`, `deletes`, `conflicts`
+/// or `permissions`.
+pub fn parse_kind(s: &str) -> Result<SyncOpKind, String> {
+    match s {
+        "copies" | "copy" | "copy_new" => Ok(SyncOpKind::CopyNew),
+        "overwrites" | "overwrite" => Ok(SyncOpKind::Overwrite),
+        "deletes" | "delete" => Ok(SyncOpKind::Delete),
+        "conflicts" | "conflict" | "resolve_conflict" => Ok(SyncOpKind::ResolveConflict),
+        "permissions" | "set_permissions" => Ok(SyncOpKind::SetPermissions),
+        _ => Err(format!("unknown operation kind: {s}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::drive::DriveId;
+    use diffr_core::models::sync_state::SyncOp;
+    use uuid::Uuid;
+
+    fn op(kind: SyncOpKind, size: u64) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: "f".into(),
+            source_drive: Some(DriveId::new()),
+            target_drive: DriveId::new(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_op_filter() {
+        let ops = vec![
+            op(SyncOpKind::CopyNew, 1),
+            op(SyncOpKind::Overwrite, 2),
+            op(SyncOpKind::Delete, 4),
+            op(SyncOpKind::ResolveConflict, 8),
+        ];
+
+        let mut plan = SyncPlan::new(ClusterId::new(), ops.clone());
+        let additive = OpFilter {
+            only: Some(vec![parse_kind("copies").unwrap()]),
+            ..Default::default()
+        };
+        assert_eq!(additive.apply(&mut plan).len(), 3);
+        assert_eq!(plan.total_bytes, 1);
+
+        let mut plan = SyncPlan::new(ClusterId::new(), ops.clone());
+        let no_overwrites = OpFilter {
+            no_overwrites: true,
+            ..Default::default()
+        };
+        assert_eq!(no_overwrites.apply(&mut plan).len(), 2);
+        assert_eq!(plan.total_bytes, 5);
+
+        // A plan that spilled to disk is filtered there.
+        let mut spilled = SpillVec::spilling_after(2);
+        spilled.extend(ops);
+        let mut plan = SyncPlan::new(ClusterId::new(), spilled);
+        assert!(plan.operations.is_spilled());
+        let removed = no_overwrites.apply(&mut plan);
+        let kinds = |ops: &SpillVec<SyncOp>| ops.iter().map(|op| op.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(kinds(&plan.operations), [SyncOpKind::CopyNew, SyncOpKind::Delete]);
+        assert_eq!(kinds(&removed), [SyncOpKind::Overwrite, SyncOpKind::ResolveConflict]);
+        assert_eq!(plan.total_bytes, 5);
+    }
+}
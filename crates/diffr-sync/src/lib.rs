@@ -1,4 +1,21 @@
+pub mod anomaly;
+pub mod attributes;
 pub mod conflict;
+pub mod dedupe;
 pub mod diff;
+pub mod engine;
+pub mod estimate;
 pub mod executor;
+pub mod filter;
+pub mod optimize;
+pub mod plan_diff;
+pub mod quota;
+pub mod removal;
+pub mod replicas;
+pub mod safety;
+pub mod stability;
+pub mod tiering;
 pub mod topology;
+pub mod undo;
+pub mod usage;
+pub mod validate;
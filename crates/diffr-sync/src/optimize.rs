@@ -0,0 +1,185 @@
+use diffr_core::models::drive::DriveId;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind};
+use diffr_core::spill::SpillVec;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Files at or below this size are batched together ahead of larger
+/// transfers to the same drive.
+pub const SMALL_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Where an operation runs in an optimized plan, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    /// Same-drive copies read files that later ops overwrite, and other
+    /// drives may copy their result, so they go first.
+    LocalCopy,
+    CreateDir,
+    Transfer,
+    Delete,
+    RemoveDir,
+}
+
+impl Phase {
+    fn of(kind: &SyncOpKind) -> Self {
+        match kind {
+            SyncOpKind::LocalCopy => Phase::LocalCopy,
+            SyncOpKind::CreateDir => Phase::CreateDir,
+            SyncOpKind::CopyNew
+            | SyncOpKind::Overwrite
+            | SyncOpKind::ResolveConflict
+            | SyncOpKind::Merge
+            | SyncOpKind::SetPermissions => Phase::Transfer,
+            SyncOpKind::Delete => Phase::Delete,
+            SyncOpKind::RemoveDir => Phase::RemoveDir,
+        }
+    }
+}
+
+/// Whether `kind` moves or changes a file's content or permissions, the
+/// phase that can be reordered without breaking what the others need.
+pub(crate) fn is_transfer(kind: &SyncOpKind) -> bool {
+    Phase::of(kind) == Phase::Transfer
+}
+
+/// Order operations so each one's prerequisites run first, and files land
+/// on each drive in as few passes as possible:
+///
+/// 1. same-drive copies, in planned order
+/// 2. directory creates, parents before children
+/// 3. transfers grouped by target drive (in the order drives first appear);
+///    within a drive, small files batched by directory, then larger files
+/// 4. file deletes, grouped by target drive
+/// 5. directory removes, children before parents
+///
+/// Directory creates that a copy into the directory makes anyway are
+/// dropped, as are duplicates from several drive pairs. Operations kept on
+/// disk are sorted there.
+pub fn optimize_ops(mut operations: SpillVec<SyncOp>) -> SpillVec<SyncOp> {
+    // Directories that some other op will create as a parent.
+    let mut made: HashSet<(DriveId, PathBuf)> = HashSet::new();
+    for op in &operations {
+        if !matches!(
+            op.kind,
+            SyncOpKind::CopyNew
+                | SyncOpKind::Overwrite
+                | SyncOpKind::LocalCopy
+                | SyncOpKind::CreateDir
+        ) {
+            continue;
+        }
+        for parent in op.rel_path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() {
+                break;
+            }
+            made.insert((op.target_drive.clone(), parent.to_path_buf()));
+        }
+    }
+    operations.retain(|op| {
+        op.kind != SyncOpKind::CreateDir || made.insert((op.target_drive.clone(), op.rel_path.clone()))
+    });
+
+    let mut rank: HashMap<DriveId, usize> = HashMap::new();
+    for op in &operations {
+        let next = rank.len();
+        rank.entry(op.target_drive.clone()).or_insert(next);
+    }
+
+    // Stable, so ops that compare equal keep their planned order.
+    operations.sort_by_cached_key(|op| {
+        let phase = Phase::of(&op.kind);
+        let depth = op.rel_path.components().count() as i64;
+        let (drive, large, depth, path) = match phase {
+            Phase::LocalCopy => (0, false, 0, PathBuf::new()),
+            Phase::CreateDir => (0, false, depth, op.rel_path.clone()),
+            Phase::Transfer => (
+                rank[&op.target_drive],
+                op.size_bytes > SMALL_FILE_BYTES,
+                0,
+                op.rel_path.clone(),
+            ),
+            Phase::Delete => (rank[&op.target_drive], false, 0, op.rel_path.clone()),
+            Phase::RemoveDir => (0, false, -depth, op.rel_path.clone()),
+        };
+        (phase, drive, large, depth, path)
+    });
+    operations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn op(kind: SyncOpKind, path: &str, target: &DriveId, size: u64) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: path.into(),
+            source_drive: None,
+            target_drive: target.clone(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    fn order(ops: &SpillVec<SyncOp>) -> Vec<String> {
+        ops.iter()
+            .map(|op| format!("{} {}", op.kind, op.rel_path.display()))
+            .collect()
+    }
+
+    #[test]
+    fn test_phases_and_drive_grouping() {
+        let (a, b) = (DriveId::new(), DriveId::new());
+        let big = SMALL_FILE_BYTES + 1;
+        let ops = vec![
+            op(SyncOpKind::Delete, "old.txt", &a, 0),
+            op(SyncOpKind::CopyNew, "z/big.iso", &a, big),
+            op(SyncOpKind::CopyNew, "b.txt", &b, 1),
+            op(SyncOpKind::RemoveDir, "gone", &a, 0),
+            op(SyncOpKind::RemoveDir, "gone/sub", &a, 0),
+            op(SyncOpKind::CopyNew, "docs/2.txt", &a, 1),
+            op(SyncOpKind::CreateDir, "empty/inner", &b, 0),
+            op(SyncOpKind::CreateDir, "empty", &b, 0),
+            op(SyncOpKind::CopyNew, "docs/1.txt", &a, 1),
+            op(SyncOpKind::LocalCopy, "a.conflict.txt", &b, 1),
+        ];
+
+        assert_eq!(
+            order(&optimize_ops(ops.into())),
+            vec![
+                "local_copy a.conflict.txt",
+                "create_dir empty/inner",
+                "copy_new docs/1.txt",
+                "copy_new docs/2.txt",
+                "copy_new z/big.iso",
+                "copy_new b.txt",
+                "delete old.txt",
+                "remove_dir gone/sub",
+                "remove_dir gone",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redundant_creates_are_dropped() {
+        let (a, b) = (DriveId::new(), DriveId::new());
+        let ops = vec![
+            op(SyncOpKind::CreateDir, "photos", &a, 0),
+            op(SyncOpKind::CopyNew, "photos/1.jpg", &a, 10),
+            // The same empty directory planned from two drive pairs.
+            op(SyncOpKind::CreateDir, "empty", &b, 0),
+            op(SyncOpKind::CreateDir, "empty", &b, 0),
+            // A copy to another drive doesn't make the directory on this one.
+            op(SyncOpKind::CreateDir, "photos", &b, 0),
+        ];
+
+        assert_eq!(
+            order(&optimize_ops(ops.into())),
+            vec!["create_dir empty", "create_dir photos", "copy_new photos/1.jpg"]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use diffr_core::models::drive::DriveId;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// How the current plan differs from an earlier one.
+#[derive(Debug, Default)]
+pub struct PlanDiff {
+    /// Operations the earlier plan didn't have, deletes and overwrites
+    /// first since those are the ones to worry about.
+    pub added: Vec<SyncOp>,
+    /// Operations the earlier plan had that are gone now, e.g. because
+    /// that sync ran them.
+    pub removed: Vec<SyncOp>,
+    /// Operations in both plans.
+    pub unchanged: usize,
+}
+
+impl PlanDiff {
+    /// Added operations counted by target drive and kind.
+    pub fn added_by_target(&self) -> HashMap<(&DriveId, String), usize> {
+        let mut counts = HashMap::new();
+        for op in &self.added {
+            *counts.entry((&op.target_drive, op.kind.to_string())).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// What makes two planned operations the same: IDs are new on every run,
+/// and sizes change as files are edited.
+type OpKey = (String, PathBuf, DriveId, Option<DriveId>);
+
+fn key(op: &SyncOp) -> OpKey {
+    (op.kind.to_string(), op.rel_path.clone(), op.target_drive.clone(), op.source_drive.clone())
+}
+
+/// Compare `current` with the plan from an earlier run.
+pub fn diff_plans(previous: &SyncPlan, current: &SyncPlan) -> PlanDiff {
+    let before: HashSet<OpKey> = previous.operations.iter().map(|op| key(&op)).collect();
+    let after: HashSet<OpKey> = current.operations.iter().map(|op| key(&op)).collect();
+
+    let mut added: Vec<SyncOp> = current
+        .operations
+        .iter()
+        .filter(|op| !before.contains(&key(op)))
+        .map(|op| op.into_owned())
+        .collect();
+    // Stable, so each group keeps plan order.
+    added.sort_by_key(|op| match op.kind {
+        SyncOpKind::Delete | SyncOpKind::RemoveDir => 0,
+        SyncOpKind::Overwrite | SyncOpKind::ResolveConflict | SyncOpKind::Merge => 1,
+        _ => 2,
+    });
+    let removed: Vec<SyncOp> = previous
+        .operations
+        .iter()
+        .filter(|op| !after.contains(&key(op)))
+        .map(|op| op.into_owned())
+        .collect();
+
+    PlanDiff {
+        unchanged: current.operations.len() - added.len(),
+        added,
+        removed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use uuid::Uuid;
+
+    fn op(kind: SyncOpKind, path: &str, target: &DriveId, size: u64) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: path.into(),
+            source_drive: None,
+            target_drive: target.clone(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_plans() {
+        let (cluster, drive) = (ClusterId::new(), DriveId::new());
+        let previous = SyncPlan::new(
+            cluster.clone(),
+            vec![
+                op(SyncOpKind::CopyNew, "a.txt", &drive, 10),
+                op(SyncOpKind::CopyNew, "gone.txt", &drive, 10),
+            ],
+        );
+        let current = SyncPlan::new(
+            cluster,
+            vec![
+                // Same op with a new ID and size still matches.
+                op(SyncOpKind::CopyNew, "a.txt", &drive, 20),
+                op(SyncOpKind::CopyNew, "b.txt", &drive, 10),
+                op(SyncOpKind::Delete, "photos/1.jpg", &drive, 0),
+            ],
+        );
+
+        let diff = diff_plans(&previous, &current);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].rel_path, PathBuf::from("gone.txt"));
+        let added: Vec<_> = diff.added.iter().map(|op| op.rel_path.clone()).collect();
+        assert_eq!(added, [PathBuf::from("photos/1.jpg"), PathBuf::from("b.txt")]);
+        assert_eq!(diff.added_by_target()[&(&drive, "delete".to_string())], 1);
+    }
+}
@@ -0,0 +1,185 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+
+/// Bytes currently stored under a drive's sync root, from a scan.
+#[derive(Debug, Default)]
+pub struct DriveUsage {
+    pub used: u64,
+    files: HashMap<PathBuf, u64>,
+}
+
+impl DriveUsage {
+    pub fn from_entries(entries: &[FileEntry]) -> Self {
+        let files: HashMap<PathBuf, u64> = entries
+            .iter()
+            .filter(|e| !e.is_dir)
+            .map(|e| (e.rel_path.clone(), e.size))
+            .collect();
+        Self {
+            used: files.values().sum(),
+            files,
+        }
+    }
+
+    /// Usage once `ops`, the operations that actually ran against this
+    /// drive, have changed it.
+    pub fn after(&self, ops: impl IntoIterator<Item = impl Borrow<SyncOp>>) -> u64 {
+        let change: i128 = ops.into_iter().map(|op| self.delta(op.borrow())).sum();
+        (self.used as i128 + change).max(0) as u64
+    }
+
+    /// Net change in usage if `op` runs against this drive.
+    fn delta(&self, op: &SyncOp) -> i128 {
+        let existing = self.files.get(&op.rel_path).copied().unwrap_or(0) as i128;
+        match op.kind {
+            SyncOpKind::Delete => -existing,
+            SyncOpKind::SetPermissions => 0,
+            _ => op.size_bytes as i128 - existing,
+        }
+    }
+}
+
+/// Quota outcome for one drive.
+#[derive(Debug)]
+pub struct QuotaReport {
+    pub drive_id: DriveId,
+    pub quota: u64,
+    /// Usage before the sync.
+    pub used: u64,
+    /// Usage after the operations that fit, if they all succeed.
+    pub projected: u64,
+    /// Operations dropped because they would exceed the quota.
+    pub trimmed: Vec<SyncOp>,
+}
+
+/// Drop operations that would push a drive past its `quota_bytes`.
+///
+/// Operations are admitted in plan order; anything that frees space (deletes,
+/// shrinking overwrites) is always kept. Drives without a quota or without
+/// usage data are left alone.
+pub fn apply_quotas(
+    plan: &mut SyncPlan,
+    drives: &[Drive],
+    usage: &HashMap<DriveId, DriveUsage>,
+) -> Vec<QuotaReport> {
+    let mut reports: HashMap<DriveId, QuotaReport> = HashMap::new();
+    for drive in drives {
+        if let (Some(quota), Some(u)) = (drive.quota_bytes, usage.get(&drive.id)) {
+            reports.insert(
+                drive.id.clone(),
+                QuotaReport {
+                    drive_id: drive.id.clone(),
+                    quota,
+                    used: u.used,
+                    projected: u.used,
+                    trimmed: Vec::new(),
+                },
+            );
+        }
+    }
+    if reports.is_empty() {
+        return Vec::new();
+    }
+
+    for op in plan.operations.take() {
+        let (Some(report), Some(u)) = (reports.get_mut(&op.target_drive), usage.get(&op.target_drive))
+        else {
+            plan.operations.push(op);
+            continue;
+        };
+        let after = (report.projected as i128 + u.delta(&op)).max(0) as u64;
+        if after <= report.quota || after <= report.projected {
+            report.projected = after;
+            plan.operations.push(op);
+        } else {
+            report.trimmed.push(op);
+        }
+    }
+    plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+
+    let mut reports: Vec<QuotaReport> = reports.into_values().collect();
+    reports.sort_by_key(|r| r.drive_id.0);
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::drive::DriveIdentity;
+    use uuid::Uuid;
+
+    fn entry(path: &str, drive_id: &DriveId, size: u64) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive_id.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    fn op(kind: SyncOpKind, path: &str, target: &DriveId, size: u64) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: path.into(),
+            source_drive: None,
+            target_drive: target.clone(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_quotas_trims_overflow() {
+        let mut shared = Drive::new(DriveIdentity::new_synthetic(), "/shared".into());
+        shared.quota_bytes = Some(1000);
+        let usage = HashMap::from([(
+            shared.id.clone(),
+            DriveUsage::from_entries(&[entry("old.bin", &shared.id, 600)]),
+        )]);
+
+        let mut plan = SyncPlan::new(
+            ClusterId::new(),
+            vec![
+                op(SyncOpKind::CopyNew, "a.bin", &shared.id, 300),
+                op(SyncOpKind::CopyNew, "b.bin", &shared.id, 300),
+                op(SyncOpKind::Overwrite, "old.bin", &shared.id, 500),
+            ],
+        );
+        let reports = apply_quotas(&mut plan, &[shared.clone()], &usage);
+
+        assert_eq!(plan.op_count(), 2);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].trimmed.len(), 1);
+        assert_eq!(reports[0].trimmed[0].rel_path, PathBuf::from("b.bin"));
+        assert_eq!(reports[0].projected, 800);
+    }
+
+    #[test]
+    fn test_usage_after_counts_only_what_ran() {
+        let drive = DriveId::new();
+        let usage = DriveUsage::from_entries(&[entry("old.bin", &drive, 600)]);
+        let copy = op(SyncOpKind::CopyNew, "a.bin", &drive, 300);
+        let delete = op(SyncOpKind::Delete, "old.bin", &drive, 0);
+
+        assert_eq!(usage.after([] as [&SyncOp; 0]), 600);
+        assert_eq!(usage.after([&copy]), 900);
+        assert_eq!(usage.after([&copy, &delete]), 300);
+    }
+}
@@ -0,0 +1,93 @@
+use diffr_core::models::file_entry::FileEntry;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Files in `index` that no other drive has a copy of, sorted by path.
+///
+/// A file counts as copied if a drive in the same cluster (`siblings`) has
+/// it at the same path and size, with the same hash when both sides were
+/// hashed, or if any drive at all (`others`) holds content with the same
+/// hash. Directories are never reported.
+pub fn last_copies<'a>(
+    index: &'a [FileEntry],
+    siblings: &[FileEntry],
+    others: &[FileEntry],
+) -> Vec<&'a FileEntry> {
+    let mut at_path: HashMap<&Path, Vec<&FileEntry>> = HashMap::new();
+    for entry in siblings.iter().filter(|e| !e.is_dir) {
+        at_path.entry(entry.rel_path.as_path()).or_default().push(entry);
+    }
+    let hashes: HashSet<&str> = others
+        .iter()
+        .chain(siblings)
+        .filter_map(|e| e.xxh3_hash.as_deref())
+        .collect();
+
+    let mut lost: Vec<&FileEntry> = index
+        .iter()
+        .filter(|e| !e.is_dir)
+        .filter(|e| {
+            if e.xxh3_hash.as_deref().is_some_and(|h| hashes.contains(h)) {
+                return false;
+            }
+            let same = |other: &&FileEntry| {
+                other.size == e.size
+                    && match (&e.xxh3_hash, &other.xxh3_hash) {
+                        (Some(a), Some(b)) => a == b,
+                        _ => true,
+                    }
+            };
+            !at_path.get(e.rel_path.as_path()).is_some_and(|c| c.iter().any(same))
+        })
+        .collect();
+    lost.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+    lost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use diffr_core::models::drive::DriveId;
+
+    fn file(drive: &DriveId, path: &str, size: u64, hash: Option<&str>) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: hash.map(String::from),
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_last_copies() {
+        let (a, b, c) = (DriveId::new(), DriveId::new(), DriveId::new());
+        let index = vec![
+            file(&a, "same.txt", 10, None),
+            file(&a, "resized.txt", 10, None),
+            file(&a, "edited.txt", 10, Some("01")),
+            file(&a, "moved.txt", 10, Some("02")),
+            file(&a, "only.txt", 10, None),
+        ];
+        let siblings = vec![
+            file(&b, "same.txt", 10, Some("03")),
+            file(&b, "resized.txt", 11, None),
+            file(&b, "edited.txt", 10, Some("04")),
+        ];
+        // Another cluster holds the moved file under a different name.
+        let others = vec![file(&c, "elsewhere/moved.txt", 10, Some("02"))];
+
+        let lost: Vec<_> = last_copies(&index, &siblings, &others)
+            .iter()
+            .map(|e| e.rel_path.display().to_string())
+            .collect();
+        assert_eq!(lost, vec!["edited.txt", "only.txt", "resized.txt"]);
+    }
+}
@@ -0,0 +1,410 @@
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::optimize::optimize_ops;
+
+/// One drive's files as scanned before the sync, or as last cataloged for
+/// a member left out of it.
+pub struct Replica<'a> {
+    pub drive: &'a Drive,
+    pub files: &'a [FileEntry],
+    /// Whether extra copies may be written here. Read-only drives and the
+    /// primary of a primary-replica cluster only give copies.
+    pub accepts_copies: bool,
+    /// Whether copies may be taken from here. Members left out of the sync,
+    /// and the replicas of a primary-replica cluster, only count toward the
+    /// copies a file has.
+    pub gives_copies: bool,
+}
+
+/// Why a file will have fewer copies than `min_copies`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortReason {
+    /// Fewer drives than `min_copies` can take the file.
+    TooFewDrives,
+    /// The drives that could take another copy don't have room for it.
+    NoSpace,
+    /// The file only appears during this sync (a conflict copy, say), so
+    /// there's no copy to replicate yet.
+    NewThisSync,
+    /// Copies were planned but dropped by filters, size tiering or quotas.
+    Dropped,
+}
+
+impl std::fmt::Display for ShortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShortReason::TooFewDrives => write!(f, "not enough drives can hold it"),
+            ShortReason::NoSpace => write!(f, "not enough free space"),
+            ShortReason::NewThisSync => write!(f, "created by this sync; copied next time"),
+            ShortReason::Dropped => write!(f, "copies skipped by filters, size tiering or quota"),
+        }
+    }
+}
+
+/// A file that will be on fewer drives than the cluster's `min_copies`.
+#[derive(Debug)]
+pub struct ShortFile {
+    pub rel_path: PathBuf,
+    /// Drives it will be on after the sync.
+    pub copies: usize,
+    pub reason: ShortReason,
+}
+
+/// Add copies so every file ends up on at least `min_copies` drives once
+/// the plan runs, and return the files that still fall short.
+///
+/// Copies come from a drive whose copy the plan leaves alone, and go to the
+/// drives with the most room first. Room is the drive's free space or what
+/// is left of its quota, whichever is smaller, less what the plan already
+/// sends there. Files the plan deletes everywhere are left deleted, and a
+/// drive the plan deletes a file from won't be given it back.
+pub fn add_min_copies(plan: &mut SyncPlan, min_copies: usize, replicas: &[Replica]) -> Vec<ShortFile> {
+    if min_copies <= 1 {
+        return Vec::new();
+    }
+    let holders = holders_after(plan, replicas);
+    let in_sync = in_sync(plan, replicas);
+    let changed: HashMap<(DriveId, PathBuf), SyncOpKind> = plan
+        .operations
+        .iter()
+        .map(|op| ((op.target_drive.clone(), op.rel_path.clone()), op.kind.clone()))
+        .collect();
+    let files: HashMap<(&DriveId, &Path), &FileEntry> = replicas
+        .iter()
+        .filter(|r| r.gives_copies)
+        .flat_map(|r| r.files.iter().map(move |f| ((&r.drive.id, f.rel_path.as_path()), f)))
+        .collect();
+
+    let mut room: HashMap<&DriveId, Option<u64>> = replicas
+        .iter()
+        .filter(|r| r.accepts_copies)
+        .map(|r| {
+            let used: u64 = r.files.iter().map(|f| f.size).sum();
+            let quota = r.drive.quota_bytes.map(|q| q.saturating_sub(used));
+            let limit = match (r.drive.free_bytes, quota) {
+                (Some(free), Some(quota)) => Some(free.min(quota)),
+                (free, quota) => free.or(quota),
+            };
+            (&r.drive.id, limit)
+        })
+        .collect();
+    for op in &plan.operations {
+        if let Some(Some(left)) = room.get_mut(&op.target_drive) {
+            *left = left.saturating_sub(op.size_bytes);
+        }
+    }
+
+    let mut added = Vec::new();
+    let mut short = Vec::new();
+    for (path, have) in &holders {
+        if have.len() >= min_copies || !in_sync.contains(path.as_path()) {
+            continue;
+        }
+        let source = replicas.iter().find_map(|r| {
+            if changed.contains_key(&(r.drive.id.clone(), path.clone())) {
+                return None;
+            }
+            files.get(&(&r.drive.id, path.as_path())).filter(|f| !f.is_dir)
+        });
+        let Some(source) = source else {
+            short.push(ShortFile {
+                rel_path: path.clone(),
+                copies: have.len(),
+                reason: ShortReason::NewThisSync,
+            });
+            continue;
+        };
+
+        let mut candidates: Vec<&DriveId> = replicas
+            .iter()
+            .filter(|r| r.accepts_copies && !have.contains(&r.drive.id))
+            .filter(|r| changed.get(&(r.drive.id.clone(), path.clone())) != Some(&SyncOpKind::Delete))
+            .map(|r| &r.drive.id)
+            .collect();
+        let possible = have.len() + candidates.len();
+        // Unlimited first, then most room; stable, so ties keep drive order.
+        candidates.sort_by_key(|id| std::cmp::Reverse(room[id].map_or(u128::MAX, u128::from)));
+
+        let mut copies = have.len();
+        for target in candidates {
+            if copies >= min_copies {
+                break;
+            }
+            let left = room.get_mut(target).expect("candidates accept copies");
+            match left {
+                Some(bytes) if *bytes < source.size => continue,
+                Some(bytes) => *bytes -= source.size,
+                None => {}
+            }
+            added.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: path.clone(),
+                source_drive: Some(source.drive_id.clone()),
+                target_drive: target.clone(),
+                size_bytes: source.size,
+                source_path: None,
+                reason: Some(format!("min_copies: on {} of {} drives", copies, min_copies)),
+                expect: None,
+            });
+            copies += 1;
+        }
+        if copies < min_copies {
+            short.push(ShortFile {
+                rel_path: path.clone(),
+                copies,
+                reason: if possible < min_copies {
+                    ShortReason::TooFewDrives
+                } else {
+                    ShortReason::NoSpace
+                },
+            });
+        }
+    }
+
+    if !added.is_empty() {
+        plan.operations.extend(added);
+        plan.operations = optimize_ops(plan.operations.take());
+        plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+    }
+    short
+}
+
+/// Files below `min_copies` in the final plan that `add_min_copies` didn't
+/// already report, because later filtering dropped copies it relied on.
+pub fn still_short(
+    plan: &SyncPlan,
+    min_copies: usize,
+    replicas: &[Replica],
+    reported: &[ShortFile],
+) -> Vec<ShortFile> {
+    if min_copies <= 1 {
+        return Vec::new();
+    }
+    let reported: HashSet<&Path> = reported.iter().map(|s| s.rel_path.as_path()).collect();
+    let in_sync = in_sync(plan, replicas);
+    holders_after(plan, replicas)
+        .into_iter()
+        .filter(|(path, have)| have.len() < min_copies && !reported.contains(path.as_path()))
+        .filter(|(path, _)| in_sync.contains(path.as_path()))
+        .map(|(rel_path, have)| ShortFile {
+            rel_path,
+            copies: have.len(),
+            reason: ShortReason::Dropped,
+        })
+        .collect()
+}
+
+/// Files on a drive copies can come from, or touched by `plan`. Those only
+/// on members left out of the sync, or only on replicas, can't be copied
+/// this time, so aren't judged.
+fn in_sync(plan: &SyncPlan, replicas: &[Replica]) -> HashSet<PathBuf> {
+    replicas
+        .iter()
+        .filter(|r| r.gives_copies)
+        .flat_map(|r| r.files.iter().map(|f| f.rel_path.clone()))
+        .chain(plan.operations.iter().map(|op| op.rel_path.clone()))
+        .collect()
+}
+
+/// The drives each file will be on once `plan` runs. Files the plan
+/// deletes from every drive are left out.
+fn holders_after(plan: &SyncPlan, replicas: &[Replica]) -> BTreeMap<PathBuf, HashSet<DriveId>> {
+    let mut holders: BTreeMap<PathBuf, HashSet<DriveId>> = BTreeMap::new();
+    for replica in replicas {
+        for file in replica.files.iter().filter(|f| !f.is_dir) {
+            holders
+                .entry(file.rel_path.clone())
+                .or_default()
+                .insert(replica.drive.id.clone());
+        }
+    }
+    for op in &plan.operations {
+        match op.kind {
+            SyncOpKind::CopyNew
+            | SyncOpKind::Overwrite
+            | SyncOpKind::LocalCopy
+            | SyncOpKind::ResolveConflict
+            | SyncOpKind::Merge => {
+                holders
+                    .entry(op.rel_path.clone())
+                    .or_default()
+                    .insert(op.target_drive.clone());
+            }
+            SyncOpKind::Delete => {
+                if let Some(have) = holders.get_mut(&op.rel_path) {
+                    have.remove(&op.target_drive);
+                }
+            }
+            SyncOpKind::CreateDir | SyncOpKind::RemoveDir | SyncOpKind::SetPermissions => {}
+        }
+    }
+    holders.retain(|_, have| !have.is_empty());
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::drive::DriveIdentity;
+
+    fn file(drive: &Drive, path: &str, size: u64) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_add_min_copies() {
+        let drive = |free: Option<u64>| {
+            let mut d = Drive::new(DriveIdentity::new_synthetic(), "/tmp".into());
+            d.free_bytes = free;
+            d
+        };
+        let (a, b, c) = (drive(None), drive(Some(100)), drive(None));
+        let a_files = vec![file(&a, "big.iso", 500), file(&a, "small.txt", 10)];
+        let b_files = vec![];
+        let c_files = vec![];
+        let replicas = vec![
+            Replica {
+                drive: &a,
+                files: &a_files,
+                accepts_copies: true,
+                gives_copies: true,
+            },
+            Replica {
+                drive: &b,
+                files: &b_files,
+                accepts_copies: true,
+                gives_copies: true,
+            },
+            // Read-only: it can't take copies.
+            Replica {
+                drive: &c,
+                files: &c_files,
+                accepts_copies: false,
+                gives_copies: true,
+            },
+        ];
+
+        let mut plan = SyncPlan::new(ClusterId::new(), Vec::new());
+        let short = add_min_copies(&mut plan, 2, &replicas);
+        // small.txt fits on b; big.iso doesn't.
+        assert_eq!(plan.op_count(), 1);
+        assert_eq!(plan.operations.to_vec()[0].rel_path, PathBuf::from("small.txt"));
+        assert_eq!(plan.operations.to_vec()[0].target_drive, b.id);
+        assert_eq!(short.len(), 1);
+        assert_eq!(short[0].rel_path, PathBuf::from("big.iso"));
+        assert_eq!(short[0].reason, ShortReason::NoSpace);
+
+        // Three copies can't be had when only a and b take them.
+        let mut plan = SyncPlan::new(ClusterId::new(), Vec::new());
+        let short = add_min_copies(&mut plan, 3, &replicas);
+        assert!(short.iter().all(|s| s.reason == ShortReason::TooFewDrives));
+
+        // Dropping the planned copy afterwards is caught too.
+        plan.operations.take();
+        let short = still_short(&plan, 3, &replicas, &[]);
+        assert_eq!(short.len(), 2);
+        assert!(short.iter().all(|s| s.reason == ShortReason::Dropped && s.copies == 1));
+    }
+
+    #[test]
+    fn test_min_copies_counts_drives_left_out() {
+        let (a, b, c) = (
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+        );
+        let a_files = vec![file(&a, "both.txt", 10), file(&a, "only_a.txt", 10)];
+        let b_files = vec![];
+        // c isn't being synced; its catalog says it has both.txt and a file
+        // nobody else has.
+        let c_files = vec![file(&c, "both.txt", 10), file(&c, "only_c.txt", 10)];
+        let replicas = vec![
+            Replica {
+                drive: &a,
+                files: &a_files,
+                accepts_copies: true,
+                gives_copies: true,
+            },
+            Replica {
+                drive: &b,
+                files: &b_files,
+                accepts_copies: true,
+                gives_copies: true,
+            },
+            Replica {
+                drive: &c,
+                files: &c_files,
+                accepts_copies: false,
+                gives_copies: false,
+            },
+        ];
+
+        let mut plan = SyncPlan::new(ClusterId::new(), Vec::new());
+        let short = add_min_copies(&mut plan, 2, &replicas);
+        assert!(short.is_empty());
+        assert_eq!(plan.op_count(), 1);
+        assert_eq!(plan.operations.to_vec()[0].rel_path, PathBuf::from("only_a.txt"));
+        assert_eq!(plan.operations.to_vec()[0].target_drive, b.id);
+        assert!(still_short(&plan, 2, &replicas, &short).is_empty());
+    }
+
+    #[test]
+    fn test_min_copies_takes_copies_from_the_primary_only() {
+        let (primary, r1, r2) = (
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+            Drive::new(DriveIdentity::new_synthetic(), "/tmp".into()),
+        );
+        let primary_files = vec![file(&primary, "photo.jpg", 10)];
+        // A file that only a replica has isn't spread to the other replica.
+        let r1_files = vec![file(&r1, "stray.txt", 10)];
+        let r2_files = vec![];
+        let replicas = vec![
+            Replica {
+                drive: &primary,
+                files: &primary_files,
+                accepts_copies: false,
+                gives_copies: true,
+            },
+            Replica {
+                drive: &r1,
+                files: &r1_files,
+                accepts_copies: true,
+                gives_copies: false,
+            },
+            Replica {
+                drive: &r2,
+                files: &r2_files,
+                accepts_copies: true,
+                gives_copies: false,
+            },
+        ];
+
+        let mut plan = SyncPlan::new(ClusterId::new(), Vec::new());
+        let short = add_min_copies(&mut plan, 2, &replicas);
+        assert!(short.is_empty());
+        assert_eq!(plan.op_count(), 1);
+        assert_eq!(plan.operations.to_vec()[0].rel_path, PathBuf::from("photo.jpg"));
+        assert_eq!(plan.operations.to_vec()[0].source_drive, Some(primary.id.clone()));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+
+/// A drive on which a plan would replace or remove an unusually large share
+/// of files.
+#[derive(Debug, Clone)]
+pub struct MassChange {
+    pub drive_id: DriveId,
+    pub changed: usize,
+    pub total: usize,
+}
+
+impl MassChange {
+    pub fn percent(&self) -> f64 {
+        self.changed as f64 / self.total as f64 * 100.0
+    }
+}
+
+/// Find drives where deletes and overwrites would touch more than
+/// `max_percent` of the files currently on them. `file_counts` holds the
+/// number of files scanned on each drive.
+pub fn find_mass_changes(
+    plan: &SyncPlan,
+    file_counts: &HashMap<DriveId, usize>,
+    max_percent: u8,
+) -> Vec<MassChange> {
+    let mut changed: HashMap<DriveId, usize> = HashMap::new();
+    for op in &plan.operations {
+        if matches!(
+            op.kind,
+            SyncOpKind::Delete | SyncOpKind::Overwrite | SyncOpKind::ResolveConflict | SyncOpKind::Merge
+        ) {
+            *changed.entry(op.target_drive.clone()).or_default() += 1;
+        }
+    }
+
+    let mut found: Vec<MassChange> = changed
+        .into_iter()
+        .filter_map(|(drive_id, changed)| {
+            let total = *file_counts.get(&drive_id)?;
+            if total == 0 || changed * 100 <= total * max_percent as usize {
+                return None;
+            }
+            Some(MassChange {
+                drive_id,
+                changed,
+                total,
+            })
+        })
+        .collect();
+    found.sort_by_key(|c| std::cmp::Reverse(c.changed));
+    found
+}
+
+/// Why `op` would read or write diffr's own metadata, if it would: a path
+/// through a `.diffr` directory (this drive's, or a nested repo's), or one
+/// inside a drive's metadata directory kept elsewhere. Scans skip these
+/// already; this keeps hand-made or hook-edited plans out of them too.
+pub fn protected_reason(op: &SyncOp, drives: &[Drive]) -> Option<String> {
+    let paths = std::iter::once(op.rel_path.as_path()).chain(op.source_path.as_deref());
+    for path in paths.clone() {
+        if path.components().any(|c| c.as_os_str() == ".diffr") {
+            return Some(format!("{} is inside a .diffr directory", path.display()));
+        }
+    }
+
+    let on = |id: &DriveId| drives.iter().find(|d| d.id == *id);
+    let roots = on(&op.target_drive)
+        .into_iter()
+        .chain(op.source_drive.as_ref().and_then(on))
+        .map(|d| d.effective_root());
+    for root in roots {
+        for path in paths.clone() {
+            let full = root.join(path);
+            if let Some(owner) = drives.iter().find(|d| in_metadata_dir(d, &full)) {
+                return Some(format!(
+                    "{} is inside the metadata directory of drive {}",
+                    full.display(),
+                    owner.identity.identity_string()
+                ));
+            }
+        }
+    }
+    None
+}
+
+fn in_metadata_dir(drive: &Drive, path: &Path) -> bool {
+    drive.metadata_dir.as_ref().is_some_and(|dir| path.starts_with(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::sync_state::SyncOp;
+    use uuid::Uuid;
+
+    fn op(kind: SyncOpKind, target: &DriveId) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: "f".into(),
+            source_drive: None,
+            target_drive: target.clone(),
+            size_bytes: 0,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_find_mass_changes() {
+        let emptied = DriveId::new();
+        let healthy = DriveId::new();
+        let mut ops: Vec<SyncOp> = (0..8).map(|_| op(SyncOpKind::Delete, &emptied)).collect();
+        ops.push(op(SyncOpKind::Overwrite, &healthy));
+        ops.push(op(SyncOpKind::CopyNew, &healthy));
+        let plan = SyncPlan::new(ClusterId::new(), ops);
+
+        let counts = HashMap::from([(emptied.clone(), 10), (healthy.clone(), 10)]);
+        let found = find_mass_changes(&plan, &counts, 50);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].drive_id, emptied);
+        assert_eq!(found[0].percent(), 80.0);
+
+        assert!(find_mass_changes(&plan, &counts, 80).is_empty());
+    }
+
+    #[test]
+    fn test_protected_reason() {
+        use diffr_core::models::drive::DriveIdentity;
+
+        let a = Drive::new(DriveIdentity::new_synthetic(), "/mnt/a".into());
+        let mut b = Drive::new(DriveIdentity::new_synthetic(), "/mnt/b".into());
+        // b keeps its metadata in a folder that a's sync root happens to hold.
+        b.metadata_dir = Some("/mnt/a/b-meta".into());
+        let drives = [a.clone(), b];
+        let to_a = |path: &str| SyncOp {
+            rel_path: path.into(),
+            ..op(SyncOpKind::CopyNew, &a.id)
+        };
+
+        assert!(protected_reason(&to_a("photos/x.jpg"), &drives).is_none());
+        assert!(protected_reason(&to_a(".diffr/archive/x.zst"), &drives).is_some());
+        assert!(protected_reason(&to_a("nested/repo/.diffr/repo.toml"), &drives).is_some());
+        assert!(protected_reason(&to_a("b-meta/archive/x.zst"), &drives).is_some());
+        let local = SyncOp {
+            kind: SyncOpKind::LocalCopy,
+            source_path: Some(".diffr/trash/x".into()),
+            reason: None,
+            ..to_a("x")
+        };
+        assert!(protected_reason(&local, &drives).is_some());
+    }
+}
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Duration, Utc};
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::replicas::Replica;
+
+/// An operation held back because a file it copies or replaces was
+/// modified within the stability window, and may still be being written.
+#[derive(Debug, Clone)]
+pub struct HeldOp {
+    pub op: SyncOp,
+    /// The file whose mtime is too recent, as scanned.
+    pub drive_id: DriveId,
+    pub rel_path: PathBuf,
+    pub mtime: DateTime<Utc>,
+}
+
+impl HeldOp {
+    /// When the file will have been left alone for `window`.
+    pub fn stable_at(&self, window: Duration) -> DateTime<Utc> {
+        self.mtime + window
+    }
+}
+
+/// Take copies and overwrites out of the plan when the file being copied,
+/// or the one being replaced, was modified less than `window` before `now`.
+/// A modification time after `now` is from a clock that's off, not a file
+/// being written, so it doesn't hold anything back.
+pub fn hold_unstable(
+    plan: &mut SyncPlan,
+    replicas: &[Replica],
+    window: Duration,
+    now: DateTime<Utc>,
+) -> Vec<HeldOp> {
+    let files: HashMap<(&DriveId, &Path), &FileEntry> = replicas
+        .iter()
+        .flat_map(|r| r.files.iter().map(move |f| ((&r.drive.id, f.rel_path.as_path()), f)))
+        .collect();
+
+    let mut held = Vec::new();
+    plan.operations.retain(|op| {
+        let recent = touched_files(op)
+            .into_iter()
+            .filter_map(|(drive, path)| files.get(&(drive, path)))
+            .filter(|f| !f.is_dir && f.mtime > now - window && f.mtime <= now)
+            .max_by_key(|f| f.mtime);
+        match recent {
+            Some(file) => {
+                held.push(HeldOp {
+                    op: op.clone(),
+                    drive_id: file.drive_id.clone(),
+                    rel_path: file.rel_path.clone(),
+                    mtime: file.mtime,
+                });
+                false
+            }
+            None => true,
+        }
+    });
+    plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+    held
+}
+
+/// Split held operations into those whose file hasn't changed since the
+/// scan, which are safe to run now, and those still being written.
+pub fn settled(held: Vec<HeldOp>, drives: &[Drive]) -> (Vec<SyncOp>, Vec<HeldOp>) {
+    let mut ready = Vec::new();
+    let mut unstable = Vec::new();
+    for h in held {
+        let unchanged = drives
+            .iter()
+            .find(|d| d.id == h.drive_id)
+            .and_then(|d| std::fs::metadata(d.effective_root().join(&h.rel_path)).ok())
+            .and_then(|m| m.modified().ok())
+            .is_some_and(|t| DateTime::<Utc>::from(t) == h.mtime);
+        if unchanged {
+            ready.push(h.op);
+        } else {
+            unstable.push(h);
+        }
+    }
+    (ready, unstable)
+}
+
+/// The files an operation reads or replaces, by drive.
+fn touched_files(op: &SyncOp) -> Vec<(&DriveId, &Path)> {
+    match op.kind {
+        SyncOpKind::CopyNew | SyncOpKind::Overwrite | SyncOpKind::Merge => {
+            let mut touched = vec![(&op.target_drive, op.rel_path.as_path())];
+            touched.extend(op.source_drive.as_ref().map(|s| (s, op.rel_path.as_path())));
+            touched
+        }
+        SyncOpKind::LocalCopy => op
+            .source_path
+            .as_ref()
+            .map(|p| vec![(&op.target_drive, p.as_path())])
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::drive::DriveIdentity;
+    use uuid::Uuid;
+
+    fn file(drive: &Drive, path: &str, mtime: DateTime<Utc>) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.id.clone(),
+            is_dir: false,
+            size: 10,
+            mtime,
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: mtime,
+        }
+    }
+
+    fn copy(path: &str, from: &Drive, to: &Drive) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind: SyncOpKind::CopyNew,
+            rel_path: path.into(),
+            source_drive: Some(from.id.clone()),
+            target_drive: to.id.clone(),
+            size_bytes: 10,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_hold_unstable() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("download.part"), "partial").unwrap();
+        let a = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let b = Drive::new(DriveIdentity::new_synthetic(), "/tmp".into());
+
+        let now = Utc::now();
+        let written: DateTime<Utc> = std::fs::metadata(dir.path().join("download.part"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .into();
+        let a_files = vec![
+            file(&a, "download.part", written),
+            file(&a, "old.txt", now - Duration::hours(1)),
+            file(&a, "skewed.txt", now + Duration::days(2)),
+        ];
+        let replicas = vec![Replica {
+            drive: &a,
+            files: &a_files,
+            accepts_copies: true,
+            gives_copies: true,
+        }];
+        let mut plan = SyncPlan::new(
+            ClusterId::new(),
+            vec![
+                copy("download.part", &a, &b),
+                copy("old.txt", &a, &b),
+                copy("skewed.txt", &a, &b),
+            ],
+        );
+
+        // A modification time in the future is clock skew and never settles,
+        // so it isn't held.
+        let held = hold_unstable(&mut plan, &replicas, Duration::seconds(30), now);
+        assert_eq!(plan.op_count(), 2);
+        assert_eq!(plan.operations.to_vec()[0].rel_path, PathBuf::from("old.txt"));
+        assert_eq!(plan.operations.to_vec()[1].rel_path, PathBuf::from("skewed.txt"));
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].rel_path, PathBuf::from("download.part"));
+
+        // Untouched since the scan, so it can go now; a second write since
+        // means it's still in progress.
+        let (ready, unstable) = settled(held.clone(), &[a.clone(), b.clone()]);
+        assert_eq!((ready.len(), unstable.len()), (1, 0));
+        let rewritten = HeldOp {
+            mtime: written - Duration::seconds(5),
+            ..held[0].clone()
+        };
+        let (ready, unstable) = settled(vec![rewritten], &[a, b]);
+        assert_eq!((ready.len(), unstable.len()), (0, 1));
+    }
+}
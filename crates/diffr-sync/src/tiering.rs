@@ -0,0 +1,111 @@
+use diffr_core::models::cluster::{ClusterSettings, SizeOrder};
+use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use diffr_core::spill::SpillVec;
+
+/// A plan split by file size: what runs now, and what waits for a
+/// `--large-files` pass.
+#[derive(Debug)]
+pub struct TieredPlan {
+    pub plan: SyncPlan,
+    pub deferred: SpillVec<SyncOp>,
+}
+
+impl TieredPlan {
+    pub fn deferred_bytes(&self) -> u64 {
+        self.deferred.iter().map(|op| op.size_bytes).sum()
+    }
+}
+
+/// Reorder and partition a plan according to the cluster's size settings.
+///
+/// Transfers above `large_file_threshold` are held back unless
+/// `include_large` is set. Deletes, directory ops, permission changes and
+/// same-drive copies are never deferred. With `SizeOrder::SmallFirst`,
+/// same-drive copies run first (a keep-both copy must be taken before the
+/// original is overwritten), then transfers smallest first, then deletes.
+pub fn tier_by_size(mut plan: SyncPlan, settings: &ClusterSettings, include_large: bool) -> TieredPlan {
+    let mut deferred = SpillVec::new();
+
+    if let (Some(threshold), false) = (settings.large_file_threshold, include_large) {
+        deferred = plan.operations.remove_where(|op| {
+            !matches!(
+                op.kind,
+                SyncOpKind::Delete
+                    | SyncOpKind::LocalCopy
+                    | SyncOpKind::CreateDir
+                    | SyncOpKind::RemoveDir
+                    | SyncOpKind::SetPermissions
+            ) && op.size_bytes > threshold
+        });
+    }
+
+    if settings.size_order == SizeOrder::SmallFirst {
+        plan.operations
+            .sort_by_cached_key(|op| {
+                (
+                    op.kind != SyncOpKind::LocalCopy,
+                    matches!(op.kind, SyncOpKind::Delete | SyncOpKind::RemoveDir),
+                    op.size_bytes,
+                )
+            });
+    }
+
+    plan.total_bytes = plan.operations.iter().map(|op| op.size_bytes).sum();
+    TieredPlan { plan, deferred }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ClusterId;
+    use diffr_core::models::drive::DriveId;
+    use uuid::Uuid;
+
+    fn op(kind: SyncOpKind, path: &str, size: u64) -> SyncOp {
+        SyncOp {
+            id: Uuid::now_v7(),
+            kind,
+            rel_path: path.into(),
+            source_drive: Some(DriveId::new()),
+            target_drive: DriveId::new(),
+            size_bytes: size,
+            source_path: None,
+            reason: None,
+            expect: None,
+        }
+    }
+
+    #[test]
+    fn test_tier_by_size() {
+        let plan = SyncPlan::new(
+            ClusterId::new(),
+            vec![
+                op(SyncOpKind::CopyNew, "big.iso", 5000),
+                op(SyncOpKind::Delete, "old.bin", 9000),
+                op(SyncOpKind::Overwrite, "mid.txt", 500),
+                op(SyncOpKind::CopyNew, "tiny.txt", 5),
+            ],
+        );
+        let settings = ClusterSettings {
+            size_order: SizeOrder::SmallFirst,
+            large_file_threshold: Some(1000),
+            ..Default::default()
+        };
+
+        let tiered = tier_by_size(plan.clone(), &settings, false);
+        let paths: Vec<_> = tiered
+            .plan
+            .operations
+            .iter()
+            .map(|op| op.rel_path.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, vec!["tiny.txt", "mid.txt", "old.bin"]);
+        assert_eq!(tiered.deferred.len(), 1);
+        assert_eq!(tiered.deferred_bytes(), 5000);
+        assert_eq!(tiered.plan.total_bytes, 9505);
+
+        let all = tier_by_size(plan, &settings, true);
+        assert!(all.deferred.is_empty());
+        assert_eq!(all.plan.op_count(), 4);
+    }
+}
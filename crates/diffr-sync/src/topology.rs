@@ -1,178 +1,750 @@
-use diffr_core::models::cluster::{Cluster, Topology};
-use diffr_core::models::drive::Drive;
-use diffr_core::models::sync_state::{SyncOp, SyncOpKind, SyncPlan};
+use diffr_core::models::cluster::{Cluster, ClusterId, Topology};
+use diffr_core::models::drive::{Drive, DriveId, DriveRole};
+use diffr_core::models::file_entry::FileEntry;
+use diffr_core::models::sync_state::{FileStamp, Precondition, SyncOp, SyncOpKind, SyncPlan};
+use diffr_core::spill::SpillVec;
 use uuid::Uuid;
 
+use crate::conflict::PendingConflict;
 use crate::diff::{DiffEntry, DiffKind};
+use crate::optimize::optimize_ops;
+use crate::safety::protected_reason;
+
+/// Something wrong with the cluster that planning worked around. The plan
+/// comes back anyway, so a dry run can show it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanWarning {
+    /// A primary-replica cluster with no primary; nothing was planned.
+    NoPrimary,
+    /// More than one drive marked primary; only the first was treated as
+    /// one.
+    MultiplePrimaries(Vec<String>),
+    /// An archive-only drive was diffed; its entries were ignored.
+    ArchiveOnlyInDiff(String),
+}
+
+impl PlanWarning {
+    /// Whether the plan is likely wrong enough that it shouldn't be run.
+    pub fn blocks_sync(&self) -> bool {
+        matches!(self, PlanWarning::NoPrimary | PlanWarning::MultiplePrimaries(_))
+    }
+}
+
+impl std::fmt::Display for PlanWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanWarning::NoPrimary => {
+                write!(f, "primary-replica cluster has no primary drive; nothing was planned")
+            }
+            PlanWarning::MultiplePrimaries(drives) => write!(
+                f,
+                "more than one primary drive ({}); only {} was used",
+                drives.join(", "),
+                drives[0]
+            ),
+            PlanWarning::ArchiveOnlyInDiff(drive) => {
+                write!(f, "archive-only drive {} was diffed; its files were left out of the plan", drive)
+            }
+        }
+    }
+}
 
 /// Generate a sync plan based on cluster topology and diff results.
 pub fn generate_plan(
     cluster: &Cluster,
     drives: &[Drive],
     diffs_per_pair: &[(&Drive, &Drive, Vec<DiffEntry>)],
+) -> (SyncPlan, Vec<PlanWarning>) {
+    let mut builder = PlanBuilder::new(cluster, drives);
+    for (left_drive, right_drive, diffs) in diffs_per_pair {
+        for entry in diffs {
+            builder.add(left_drive, right_drive, entry);
+        }
+    }
+    builder.finish()
+}
+
+/// Builds a cluster's sync plan one diff entry at a time, so a diff can be
+/// streamed into it (see [`crate::diff::diff_sorted`]) without every
+/// diff entry being held at once. Only the operations are kept, on disk
+/// once there are many of them.
+pub struct PlanBuilder<'a> {
+    cluster: &'a Cluster,
+    drives: &'a [Drive],
+    primary: Option<&'a Drive>,
+    operations: SpillVec<SyncOp>,
+    warnings: Vec<PlanWarning>,
+    conflicts: Vec<PendingConflict>,
+}
+
+impl<'a> PlanBuilder<'a> {
+    pub fn new(cluster: &'a Cluster, drives: &'a [Drive]) -> Self {
+        let mut warnings = Vec::new();
+        let primaries: Vec<&Drive> = drives.iter().filter(|d| d.is_primary).collect();
+        if cluster.topology == Topology::PrimaryReplica {
+            match primaries.len() {
+                0 => warnings.push(PlanWarning::NoPrimary),
+                1 => {}
+                _ => warnings.push(PlanWarning::MultiplePrimaries(
+                    primaries.iter().map(|d| d.identity.identity_string().to_string()).collect(),
+                )),
+            }
+        }
+        Self {
+            cluster,
+            drives,
+            primary: primaries.first().copied(),
+            operations: SpillVec::new(),
+            warnings,
+            conflicts: Vec::new(),
+        }
+    }
+
+    /// Plan for one entry of the diff between `left_drive` and `right_drive`.
+    pub fn add(&mut self, left_drive: &Drive, right_drive: &Drive, entry: &DiffEntry) {
+        if entry.kind == DiffKind::Identical {
+            return;
+        }
+        // Archive-only drives hold archives, not a copy of the files.
+        let archive_only: Vec<&Drive> = [left_drive, right_drive]
+            .into_iter()
+            .filter(|d| d.role == DriveRole::ArchiveOnly)
+            .collect();
+        for drive in &archive_only {
+            let warning = PlanWarning::ArchiveOnlyInDiff(drive.identity.identity_string().to_string());
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+        if !archive_only.is_empty() {
+            return;
+        }
+        let mut operations = Vec::new();
+        match self.cluster.topology {
+            Topology::Mesh => mesh_ops(&mut operations, left_drive, right_drive, entry),
+            Topology::PrimaryReplica => {
+                // Replicas only take the primary's files, so a diff between
+                // two replicas has nothing to say.
+                let Some(primary) = self.primary else { return };
+                if primary.id == left_drive.id || primary.id == right_drive.id {
+                    let left_is_primary = primary.id == left_drive.id;
+                    primary_replica_ops(&mut operations, left_is_primary, left_drive, right_drive, entry)
+                }
+            }
+        }
+        stamp(&mut operations, left_drive, right_drive, entry);
+        let placeholder = operations.iter().find(|op| op.kind == SyncOpKind::ResolveConflict);
+        if let Some(op) = placeholder {
+            self.conflicts.push(PendingConflict::new(op.id, left_drive, right_drive, entry));
+        }
+        self.operations.extend(operations);
+    }
+
+    /// The conflicts planned so far, each standing in the plan as a
+    /// `ResolveConflict` op until [`ConflictResolver::resolve_plan`]
+    /// replaces it.
+    ///
+    /// [`ConflictResolver::resolve_plan`]: crate::conflict::ConflictResolver::resolve_plan
+    pub fn take_conflicts(&mut self) -> Vec<PendingConflict> {
+        std::mem::take(&mut self.conflicts)
+    }
+
+    /// The plan, and what was wrong with the cluster it was planned for.
+    pub fn finish(self) -> (SyncPlan, Vec<PlanWarning>) {
+        let PlanBuilder {
+            cluster,
+            drives,
+            mut operations,
+            warnings,
+            ..
+        } = self;
+        // Read-only drives can be sources, but never targets.
+        operations.retain(|op| {
+            !drives
+                .iter()
+                .any(|d| d.id == op.target_drive && d.read_only)
+        });
+        drop_protected(&mut operations, drives);
+
+        (SyncPlan::new(cluster.id.clone(), optimize_ops(operations)), warnings)
+    }
+}
+
+/// One-way plan from `source` to `target`, used for ad-hoc copies outside a
+/// cluster. The source always wins; with `mirror`, files that exist only on
+/// the target are deleted.
+pub fn generate_one_way_plan(
+    cluster_id: ClusterId,
+    source: &Drive,
+    target: &Drive,
+    diffs: &[DiffEntry],
+    mirror: bool,
 ) -> SyncPlan {
     let mut operations = Vec::new();
 
-    match cluster.topology {
-        Topology::Mesh => {
-            generate_mesh_ops(&mut operations, diffs_per_pair);
-        }
-        Topology::PrimaryReplica => {
-            generate_primary_replica_ops(&mut operations, drives, diffs_per_pair);
+    for entry in diffs {
+        if entry.is_dir() {
+            match entry.kind {
+                DiffKind::OnlyLeft => operations.push(dir_op(
+                    SyncOpKind::CreateDir,
+                    entry,
+                    target,
+                    because(entry, one_way_reason(&SyncOpKind::CreateDir)),
+                )),
+                DiffKind::OnlyRight if mirror => operations.push(dir_op(
+                    SyncOpKind::RemoveDir,
+                    entry,
+                    target,
+                    because(entry, one_way_reason(&SyncOpKind::RemoveDir)),
+                )),
+                _ => {}
+            }
+            continue;
         }
+
+        let (kind, size) = match entry.kind {
+            DiffKind::OnlyLeft => (
+                SyncOpKind::CopyNew,
+                entry.left.map(|e| e.size).unwrap_or(0),
+            ),
+            DiffKind::Modified | DiffKind::Conflict => (
+                SyncOpKind::Overwrite,
+                entry.left.map(|e| e.size).unwrap_or(0),
+            ),
+            DiffKind::OnlyRight if mirror => (SyncOpKind::Delete, 0),
+            DiffKind::PermissionsChanged => (SyncOpKind::SetPermissions, 0),
+            _ => continue,
+        };
+
+        operations.push(SyncOp {
+            id: Uuid::now_v7(),
+            kind: kind.clone(),
+            rel_path: entry.rel_path.to_path_buf(),
+            source_drive: (kind != SyncOpKind::Delete).then(|| source.id.clone()),
+            target_drive: target.id.clone(),
+            size_bytes: size,
+            source_path: None,
+            reason: because(entry, one_way_reason(&kind)),
+            expect: None,
+        });
+        let planned = operations.len() - 1;
+        stamp(&mut operations[planned..], source, target, entry);
     }
 
-    SyncPlan::new(cluster.id.clone(), operations)
+    let mut operations = SpillVec::from(operations);
+    drop_protected(&mut operations, &[source.clone(), target.clone()]);
+    SyncPlan::new(cluster_id, optimize_ops(operations))
+}
+
+/// Leave out operations on diffr's own metadata, whatever the diff says;
+/// see [`protected_reason`].
+fn drop_protected(operations: &mut SpillVec<SyncOp>, drives: &[Drive]) {
+    operations.retain(|op| match protected_reason(op, drives) {
+        Some(reason) => {
+            tracing::warn!("leaving {} out of the plan: {}", op.rel_path.display(), reason);
+            false
+        }
+        None => true,
+    });
+}
+
+/// Record what the diff saw of the files that copies, overwrites and
+/// deletes planned for `entry` read and replace, so the executor can tell
+/// if they've changed by the time it gets to them.
+pub(crate) fn stamp(ops: &mut [SyncOp], left_drive: &Drive, right_drive: &Drive, entry: &DiffEntry) {
+    if entry.is_dir() {
+        return;
+    }
+    let seen = |drive: &DriveId| {
+        let side = if *drive == left_drive.id {
+            entry.left
+        } else if *drive == right_drive.id {
+            entry.right
+        } else {
+            return None;
+        };
+        side.map(FileStamp::of)
+    };
+    for op in ops {
+        if matches!(
+            op.kind,
+            SyncOpKind::CopyNew | SyncOpKind::Overwrite | SyncOpKind::Delete | SyncOpKind::Merge
+        ) {
+            op.expect = Some(Precondition {
+                source: op.source_drive.as_ref().and_then(seen),
+                target: seen(&op.target_drive),
+            });
+        }
+    }
 }
 
 /// Mesh topology: changes flow in all directions. Each missing/modified file
 /// is copied to the drive that doesn't have the latest version.
-fn generate_mesh_ops(
-    operations: &mut Vec<SyncOp>,
-    diffs: &[(&Drive, &Drive, Vec<DiffEntry>)],
-) {
-    for (left_drive, right_drive, diff_entries) in diffs {
-        for entry in diff_entries {
-            match entry.kind {
-                DiffKind::OnlyLeft => {
-                    // Copy from left to right
-                    let size = entry.left.as_ref().map(|e| e.size).unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::CopyNew,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(left_drive.id.clone()),
-                        target_drive: right_drive.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::OnlyRight => {
-                    // Copy from right to left
-                    let size = entry.right.as_ref().map(|e| e.size).unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::CopyNew,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(right_drive.id.clone()),
-                        target_drive: left_drive.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::Modified => {
-                    // Newer file wins; copy to the other drive
-                    let (source, target, size) = pick_newer(left_drive, right_drive, entry);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::Overwrite,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(source.id.clone()),
-                        target_drive: target.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::Conflict => {
-                    let size = entry
-                        .left
-                        .as_ref()
-                        .or(entry.right.as_ref())
-                        .map(|e| e.size)
-                        .unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::ResolveConflict,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: None,
-                        target_drive: right_drive.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::Identical => {} // Nothing to do
+fn mesh_ops(operations: &mut Vec<SyncOp>, left_drive: &Drive, right_drive: &Drive, entry: &DiffEntry) {
+    if entry.is_dir() {
+        match entry.kind {
+            DiffKind::OnlyLeft => {
+                let reason = because(entry, format!("only on {}", left_drive.effective_root().display()));
+                operations.push(dir_op(SyncOpKind::CreateDir, entry, right_drive, reason))
+            }
+            DiffKind::OnlyRight => {
+                let reason = because(entry, format!("only on {}", right_drive.effective_root().display()));
+                operations.push(dir_op(SyncOpKind::CreateDir, entry, left_drive, reason))
             }
+            _ => {}
         }
+        return;
+    }
+
+    match entry.kind {
+        DiffKind::OnlyLeft => {
+            // Copy from left to right
+            let size = entry.left.map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(left_drive.id.clone()),
+                target_drive: right_drive.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, format!("only on {}", left_drive.effective_root().display())),
+                expect: None,
+            });
+        }
+        DiffKind::OnlyRight => {
+            // Copy from right to left
+            let size = entry.right.map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(right_drive.id.clone()),
+                target_drive: left_drive.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, format!("only on {}", right_drive.effective_root().display())),
+                expect: None,
+            });
+        }
+        DiffKind::Modified => {
+            // Newer file wins; copy to the other drive
+            let (source, target, size) = pick_newer(left_drive, right_drive, entry);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::Overwrite,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(source.id.clone()),
+                target_drive: target.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, newer_reason(entry, source.id == left_drive.id, source)),
+                expect: None,
+            });
+        }
+        DiffKind::Conflict => {
+            let size = entry.left.or(entry.right).map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::ResolveConflict,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(left_drive.id.clone()),
+                target_drive: right_drive.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, "changed on both sides since the last sync"),
+                expect: None,
+            });
+        }
+        DiffKind::PermissionsChanged => {
+            // The side whose permissions changed last wins
+            let changed_at = |e: Option<&FileEntry>| e.and_then(|e| e.permissions).map(|p| p.changed_at);
+            let (source, target) = if changed_at(entry.left) >= changed_at(entry.right) {
+                (left_drive, right_drive)
+            } else {
+                (right_drive, left_drive)
+            };
+            let reason = because(entry, format!("changed last on {}", source.effective_root().display()));
+            operations.push(permissions_op(entry, source, target, reason));
+        }
+        DiffKind::Identical => {} // Nothing to do
     }
 }
 
 /// Primary/replica: only the primary's files are authoritative.
-fn generate_primary_replica_ops(
+fn primary_replica_ops(
     operations: &mut Vec<SyncOp>,
-    drives: &[Drive],
-    diffs: &[(&Drive, &Drive, Vec<DiffEntry>)],
+    left_is_primary: bool,
+    left_drive: &Drive,
+    right_drive: &Drive,
+    entry: &DiffEntry,
 ) {
-    let primary = drives.iter().find(|d| d.is_primary);
-
-    for (left_drive, right_drive, diff_entries) in diffs {
-        for entry in diff_entries {
-            // Determine which side is primary
-            let left_is_primary = primary.map(|p| p.id == left_drive.id).unwrap_or(false);
+    if entry.is_dir() {
+        match entry.kind {
+            DiffKind::OnlyLeft if left_is_primary => operations.push(dir_op(
+                SyncOpKind::CreateDir,
+                entry,
+                right_drive,
+                because(entry, "only on the primary"),
+            )),
+            DiffKind::OnlyRight if !left_is_primary => operations.push(dir_op(
+                SyncOpKind::CreateDir,
+                entry,
+                left_drive,
+                because(entry, "only on the primary"),
+            )),
+            _ => {}
+        }
+        return;
+    }
 
-            match entry.kind {
-                DiffKind::OnlyLeft if left_is_primary => {
-                    let size = entry.left.as_ref().map(|e| e.size).unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::CopyNew,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(left_drive.id.clone()),
-                        target_drive: right_drive.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::OnlyRight if !left_is_primary => {
-                    let size = entry.right.as_ref().map(|e| e.size).unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::CopyNew,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(right_drive.id.clone()),
-                        target_drive: left_drive.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                DiffKind::Modified | DiffKind::Conflict => {
-                    // Primary always wins in primary/replica topology
-                    let (source, target) = if left_is_primary {
-                        (left_drive, right_drive)
-                    } else {
-                        (right_drive, left_drive)
-                    };
-                    let size = entry
-                        .left
-                        .as_ref()
-                        .or(entry.right.as_ref())
-                        .map(|e| e.size)
-                        .unwrap_or(0);
-                    operations.push(SyncOp {
-                        id: Uuid::now_v7(),
-                        kind: SyncOpKind::Overwrite,
-                        rel_path: entry.rel_path.clone(),
-                        source_drive: Some(source.id.clone()),
-                        target_drive: target.id.clone(),
-                        size_bytes: size,
-                    });
-                }
-                _ => {} // OnlyLeft on replica side, OnlyRight on primary side — skip
-            }
+    match entry.kind {
+        DiffKind::OnlyLeft if left_is_primary => {
+            let size = entry.left.map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(left_drive.id.clone()),
+                target_drive: right_drive.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, "only on the primary"),
+                expect: None,
+            });
+        }
+        DiffKind::OnlyRight if !left_is_primary => {
+            let size = entry.right.map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::CopyNew,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(right_drive.id.clone()),
+                target_drive: left_drive.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, "only on the primary"),
+                expect: None,
+            });
+        }
+        DiffKind::Modified | DiffKind::Conflict => {
+            // Primary always wins in primary/replica topology
+            let (source, target) = if left_is_primary {
+                (left_drive, right_drive)
+            } else {
+                (right_drive, left_drive)
+            };
+            let size = entry.left.or(entry.right).map(|e| e.size).unwrap_or(0);
+            operations.push(SyncOp {
+                id: Uuid::now_v7(),
+                kind: SyncOpKind::Overwrite,
+                rel_path: entry.rel_path.to_path_buf(),
+                source_drive: Some(source.id.clone()),
+                target_drive: target.id.clone(),
+                size_bytes: size,
+                source_path: None,
+                reason: because(entry, "the primary's copy wins"),
+                expect: None,
+            });
         }
+        DiffKind::PermissionsChanged => {
+            let (source, target) = if left_is_primary {
+                (left_drive, right_drive)
+            } else {
+                (right_drive, left_drive)
+            };
+            operations.push(permissions_op(entry, source, target, because(entry, "the primary's permissions win")));
+        }
+        _ => {} // OnlyLeft on replica side, OnlyRight on primary side — skip
+    }
+}
+
+/// A directory create or remove on `target`, planned for `reason`.
+fn dir_op(kind: SyncOpKind, entry: &DiffEntry, target: &Drive, reason: Option<String>) -> SyncOp {
+    SyncOp {
+        id: Uuid::now_v7(),
+        kind,
+        rel_path: entry.rel_path.to_path_buf(),
+        source_drive: None,
+        target_drive: target.id.clone(),
+        size_bytes: 0,
+        source_path: None,
+        reason,
+        expect: None,
     }
 }
 
 /// Pick the newer file based on mtime.
+/// Copy `source`'s permissions for the entry onto `target`'s copy.
+fn permissions_op(entry: &DiffEntry, source: &Drive, target: &Drive, reason: Option<String>) -> SyncOp {
+    SyncOp {
+        id: Uuid::now_v7(),
+        kind: SyncOpKind::SetPermissions,
+        rel_path: entry.rel_path.to_path_buf(),
+        source_drive: Some(source.id.clone()),
+        target_drive: target.id.clone(),
+        size_bytes: 0,
+        source_path: None,
+        reason,
+        expect: None,
+    }
+}
+
 fn pick_newer<'a>(
     left_drive: &'a Drive,
     right_drive: &'a Drive,
     entry: &DiffEntry,
 ) -> (&'a Drive, &'a Drive, u64) {
-    let left_mtime = entry.left.as_ref().map(|e| e.mtime);
-    let right_mtime = entry.right.as_ref().map(|e| e.mtime);
+    let left_mtime = entry.left.map(|e| e.mtime);
+    let right_mtime = entry.right.map(|e| e.mtime);
 
     match (left_mtime, right_mtime) {
         (Some(l), Some(r)) if l >= r => {
-            let size = entry.left.as_ref().map(|e| e.size).unwrap_or(0);
+            let size = entry.left.map(|e| e.size).unwrap_or(0);
             (left_drive, right_drive, size)
         }
         _ => {
-            let size = entry.right.as_ref().map(|e| e.size).unwrap_or(0);
+            let size = entry.right.map(|e| e.size).unwrap_or(0);
             (right_drive, left_drive, size)
         }
     }
 }
+
+/// An op's reason: the kind of difference that called for it, then
+/// `detail`, as in `modified: the primary's copy wins`.
+fn because(entry: &DiffEntry, detail: impl std::fmt::Display) -> Option<String> {
+    Some(format!("{}: {}", entry.kind, detail))
+}
+
+/// How the modification times of a modified file's two copies decided
+/// which one won; `left_won` says which side `winner` is.
+fn newer_reason(entry: &DiffEntry, left_won: bool, winner: &Drive) -> String {
+    let mtime = |e: Option<&FileEntry>| {
+        e.map(|e| e.mtime.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "unknown".into())
+    };
+    let (won, lost) = if left_won {
+        (mtime(entry.left), mtime(entry.right))
+    } else {
+        (mtime(entry.right), mtime(entry.left))
+    };
+    if won == lost {
+        format!("same modification time ({}), kept the copy on {}", won, winner.effective_root().display())
+    } else {
+        format!("newer on {} ({} vs {})", winner.effective_root().display(), won, lost)
+    }
+}
+
+/// Why a one-way copy or mirror planned an op of `kind`.
+fn one_way_reason(kind: &SyncOpKind) -> &'static str {
+    match kind {
+        SyncOpKind::Delete | SyncOpKind::RemoveDir => "not on the source, and mirroring",
+        SyncOpKind::CopyNew | SyncOpKind::CreateDir => "not on the target yet",
+        SyncOpKind::SetPermissions => "the source's permissions win",
+        _ => "the source's copy wins",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::compute_diff;
+    use chrono::Utc;
+    use diffr_core::models::drive::{DriveId, DriveIdentity};
+    use diffr_core::models::file_entry::FileEntry;
+
+    fn make_entry(path: &str, drive_id: &DriveId, size: u64) -> FileEntry {
+        FileEntry {
+            rel_path: path.into(),
+            drive_id: drive_id.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_one_way_plan_mirror() {
+        let src = Drive::new(DriveIdentity::new_synthetic(), "/src".into());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), "/dst".into());
+        let left = vec![make_entry("new.txt", &src.id, 10)];
+        let right = vec![make_entry("stale.txt", &dst.id, 20)];
+        let diffs = compute_diff(&left, &right);
+
+        let plan = generate_one_way_plan(ClusterId::new(), &src, &dst, &diffs, false);
+        assert_eq!(plan.op_count(), 1);
+        assert_eq!(plan.operations.to_vec()[0].kind, SyncOpKind::CopyNew);
+
+        let plan = generate_one_way_plan(ClusterId::new(), &src, &dst, &diffs, true);
+        assert_eq!(plan.op_count(), 2);
+        let delete = plan
+            .operations
+            .iter()
+            .find(|op| op.kind == SyncOpKind::Delete)
+            .unwrap();
+        assert_eq!(delete.rel_path, std::path::PathBuf::from("stale.txt"));
+        assert!(delete.source_drive.is_none());
+    }
+
+    #[test]
+    fn test_ops_say_why_they_were_planned() {
+        use diffr_core::models::cluster::ConflictStrategy;
+
+        let a = Drive::new(DriveIdentity::new_synthetic(), "/a".into());
+        let b = Drive::new(DriveIdentity::new_synthetic(), "/b".into());
+        let mut old = make_entry("doc.txt", &b.id, 5);
+        old.mtime = "2026-01-01T00:00:00Z".parse().unwrap();
+        let mut new = make_entry("doc.txt", &a.id, 6);
+        new.mtime = "2026-02-01T08:30:00Z".parse().unwrap();
+        let (left, right) = ([new, make_entry("only.txt", &a.id, 1)], [old]);
+        let diffs = compute_diff(&left, &right);
+
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        let drives = [a.clone(), b.clone()];
+        let (plan, _) = generate_plan(&cluster, &drives, &[(&a, &b, diffs)]);
+        let reason = |path: &str| {
+            let op = plan.operations.iter().find(|op| op.rel_path == std::path::Path::new(path)).unwrap();
+            op.reason.clone().unwrap()
+        };
+        assert_eq!(
+            reason("doc.txt"),
+            "modified: newer on /a (2026-02-01 08:30:00 vs 2026-01-01 00:00:00)"
+        );
+        assert_eq!(reason("only.txt"), "only_left: only on /a");
+    }
+
+    #[test]
+    fn test_ops_expect_the_files_as_scanned() {
+        use diffr_core::models::cluster::ConflictStrategy;
+
+        let a = Drive::new(DriveIdentity::new_synthetic(), "/a".into());
+        let b = Drive::new(DriveIdentity::new_synthetic(), "/b".into());
+        let old = make_entry("doc.txt", &b.id, 5);
+        let mut new = make_entry("doc.txt", &a.id, 6);
+        new.mtime = old.mtime + chrono::Duration::hours(1);
+        let only = make_entry("only.txt", &a.id, 1);
+        let left = [new.clone(), only.clone()];
+        let diffs = compute_diff(&left, std::slice::from_ref(&old));
+
+        let cluster = Cluster::new("c".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+        let (plan, _) = generate_plan(&cluster, &[a.clone(), b.clone()], &[(&a, &b, diffs)]);
+        let expect = |path: &str| {
+            let op = plan.operations.iter().find(|op| op.rel_path == std::path::Path::new(path)).unwrap();
+            op.expect.clone().unwrap()
+        };
+        assert_eq!(
+            expect("doc.txt"),
+            Precondition {
+                source: Some(FileStamp::of(&new)),
+                target: Some(FileStamp::of(&old)),
+            }
+        );
+        // A copy expects there to be nothing in its way.
+        assert_eq!(
+            expect("only.txt"),
+            Precondition {
+                source: Some(FileStamp::of(&only)),
+                target: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dir_ops_are_ordered() {
+        let src = Drive::new(DriveIdentity::new_synthetic(), "/src".into());
+        let dst = Drive::new(DriveIdentity::new_synthetic(), "/dst".into());
+        let dir = |path: &str, id: &DriveId| FileEntry {
+            is_dir: true,
+            ..make_entry(path, id, 0)
+        };
+        let left = vec![
+            dir("empty", &src.id),
+            dir("full", &src.id),
+            make_entry("full/a.txt", &src.id, 1),
+        ];
+        let right = vec![
+            dir("gone", &dst.id),
+            dir("gone/sub", &dst.id),
+            make_entry("gone/sub/b.txt", &dst.id, 1),
+        ];
+        let diffs = compute_diff(&left, &right);
+
+        let plan = generate_one_way_plan(ClusterId::new(), &src, &dst, &diffs, true);
+        let ops: Vec<(String, String)> = plan
+            .operations
+            .iter()
+            .map(|op| (op.kind.to_string(), op.rel_path.display().to_string()))
+            .collect();
+        let expected = [
+            ("create_dir", "empty"),
+            ("copy_new", "full/a.txt"),
+            ("delete", "gone/sub/b.txt"),
+            ("remove_dir", "gone/sub"),
+            ("remove_dir", "gone"),
+        ];
+        assert_eq!(
+            ops,
+            expected.map(|(k, p)| (k.to_string(), p.to_string())).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_read_only_drive_is_never_a_target() {
+        let cluster = Cluster::new(
+            "c".into(),
+            Topology::Mesh,
+            diffr_core::models::cluster::ConflictStrategy::NewestWins,
+        );
+        let mut master = Drive::new(DriveIdentity::new_synthetic(), "/master".into());
+        master.read_only = true;
+        let other = Drive::new(DriveIdentity::new_synthetic(), "/other".into());
+        let left = vec![make_entry("keep.txt", &master.id, 10)];
+        let right = vec![make_entry("new.txt", &other.id, 20)];
+        let diffs = compute_diff(&left, &right);
+
+        let drives = vec![master.clone(), other.clone()];
+        let (plan, warnings) = generate_plan(&cluster, &drives, &[(&master, &other, diffs)]);
+        assert!(warnings.is_empty());
+        assert_eq!(plan.op_count(), 1);
+        assert_eq!(plan.operations.to_vec()[0].target_drive, other.id);
+    }
+
+    #[test]
+    fn test_primary_replica_warnings() {
+        let cluster = Cluster::new(
+            "c".into(),
+            Topology::PrimaryReplica,
+            diffr_core::models::cluster::ConflictStrategy::NewestWins,
+        );
+        let a = Drive::new(DriveIdentity::new_synthetic(), "/a".into());
+        let b = Drive::new(DriveIdentity::new_synthetic(), "/b".into());
+        let (left, right) = ([make_entry("a.txt", &a.id, 1)], [make_entry("b.txt", &b.id, 1)]);
+        let diffs = compute_diff(&left, &right);
+
+        // Without a primary nothing is authoritative, so nothing is planned.
+        let drives = vec![a.clone(), b.clone()];
+        let (plan, warnings) = generate_plan(&cluster, &drives, &[(&a, &b, diffs.clone())]);
+        assert_eq!(plan.op_count(), 0);
+        assert_eq!(warnings, vec![PlanWarning::NoPrimary]);
+        assert!(warnings[0].blocks_sync());
+
+        // Two replicas don't sync with each other.
+        let mut primary = Drive::new(DriveIdentity::new_synthetic(), "/p".into());
+        primary.is_primary = true;
+        let drives = vec![primary, a.clone(), b.clone()];
+        let (plan, warnings) = generate_plan(&cluster, &drives, &[(&a, &b, diffs.clone())]);
+        assert_eq!((plan.op_count(), warnings.len()), (0, 0));
+
+        let mut archive = b.clone();
+        archive.role = DriveRole::ArchiveOnly;
+        let (_, warnings) = generate_plan(&cluster, &drives, &[(&drives[0], &archive, diffs)]);
+        assert!(matches!(&warnings[..], [PlanWarning::ArchiveOnlyInDiff(_)]));
+        assert!(!warnings[0].blocks_sync());
+    }
+}
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use diffr_archive::retriever;
+use diffr_core::models::archive::ArchiveEntry;
+use diffr_core::models::drive::{Drive, DriveId};
+use diffr_core::models::sync_state::{JournalEntry, SyncOpKind};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// How one journaled operation will be reversed.
+#[derive(Debug)]
+pub enum UndoAction {
+    /// Remove a file the sync copied in.
+    Remove,
+    /// Put back the version archived before the sync replaced or deleted it.
+    RestoreArchive(ArchiveEntry),
+    /// Move a trashed file back into place.
+    RestoreTrash(PathBuf),
+    /// Recreate a directory the sync removed.
+    RecreateDir,
+    /// Nothing to restore from; the reason is reported to the user.
+    Unrecoverable(String),
+}
+
+#[derive(Debug)]
+pub struct UndoStep {
+    pub op_id: Uuid,
+    pub rel_path: PathBuf,
+    pub drive_id: DriveId,
+    pub action: UndoAction,
+}
+
+/// Work out how to reverse a sync's journal, newest operation first.
+///
+/// Files changed on disk after `synced_at` are left alone and reported as
+/// unrecoverable rather than clobbered.
+pub fn plan_undo(
+    journal: &[JournalEntry],
+    drives: &[Drive],
+    archives: &HashMap<Uuid, ArchiveEntry>,
+    synced_at: DateTime<Utc>,
+) -> Vec<UndoStep> {
+    let drive_map: HashMap<_, _> = drives.iter().map(|d| (&d.id, d)).collect();
+
+    journal
+        .iter()
+        .rev()
+        .map(|entry| {
+            let op = &entry.op;
+            let action = match drive_map.get(&op.target_drive) {
+                None => UndoAction::Unrecoverable("drive is not registered".into()),
+                Some(drive) if !drive.effective_root().exists() => {
+                    UndoAction::Unrecoverable("drive is not connected".into())
+                }
+                Some(drive) => {
+                    let path = drive.effective_root().join(&op.rel_path);
+                    let changed_since = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .map(|t| DateTime::<Utc>::from(t) > synced_at)
+                        .unwrap_or(false);
+                    match (&op.kind, &entry.trash_path) {
+                        _ if changed_since => {
+                            UndoAction::Unrecoverable("modified since the sync".into())
+                        }
+                        (
+                            SyncOpKind::CopyNew | SyncOpKind::LocalCopy | SyncOpKind::CreateDir,
+                            _,
+                        ) => UndoAction::Remove,
+                        (SyncOpKind::RemoveDir, _) => UndoAction::RecreateDir,
+                        (SyncOpKind::SetPermissions, _) => {
+                            UndoAction::Unrecoverable("old permissions weren't recorded".into())
+                        }
+                        (SyncOpKind::Delete, Some(trash_path)) => {
+                            UndoAction::RestoreTrash(trash_path.clone())
+                        }
+                        _ => match entry.archive_id.and_then(|id| archives.get(&id)) {
+                            Some(archive) => UndoAction::RestoreArchive(archive.clone()),
+                            None if entry.archive_id.is_some() => {
+                                UndoAction::Unrecoverable("archive has been pruned".into())
+                            }
+                            None => UndoAction::Unrecoverable("previous version was not archived".into()),
+                        },
+                    }
+                }
+            };
+            UndoStep {
+                op_id: op.id,
+                rel_path: op.rel_path.clone(),
+                drive_id: op.target_drive.clone(),
+                action,
+            }
+        })
+        .collect()
+}
+
+/// Carry out one undo step. `drives` must include any drive an archive was
+/// relocated to, or holds part of.
+pub fn apply_undo_step(
+    conn: &Connection,
+    step: &UndoStep,
+    drives: &[Drive],
+) -> anyhow::Result<()> {
+    let find = |id: &DriveId| {
+        drives
+            .iter()
+            .find(|d| &d.id == id)
+            .ok_or_else(|| anyhow::anyhow!("drive not found: {}", id))
+    };
+    let target = find(&step.drive_id)?;
+    let path = target.effective_root().join(&step.rel_path);
+
+    match &step.action {
+        UndoAction::Remove => {
+            if path.is_dir() {
+                std::fs::remove_dir(&path)?;
+            } else if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        UndoAction::RecreateDir => std::fs::create_dir_all(&path)?,
+        UndoAction::RestoreArchive(archive) => {
+            retriever::restore_archive(conn, drives, archive, Some(&path))?;
+        }
+        UndoAction::RestoreTrash(trash_path) => {
+            if !trash_path.exists() {
+                anyhow::bail!("trashed file is gone: {}", trash_path.display());
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(trash_path, &path)?;
+        }
+        UndoAction::Unrecoverable(reason) => anyhow::bail!("{}", reason),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::drive::DriveIdentity;
+    use diffr_core::models::sync_state::SyncOp;
+    use tempfile::TempDir;
+
+    fn entry(kind: SyncOpKind, path: &str, target: &DriveId) -> JournalEntry {
+        JournalEntry {
+            sync_id: Uuid::now_v7(),
+            op: SyncOp {
+                id: Uuid::now_v7(),
+                kind,
+                rel_path: path.into(),
+                source_drive: None,
+                target_drive: target.clone(),
+                size_bytes: 1,
+                source_path: None,
+                reason: None,
+                expect: None,
+            },
+            archive_id: None,
+            trash_path: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_undo() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("new.txt"), "n").unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let journal = vec![
+            entry(SyncOpKind::CopyNew, "new.txt", &drive.id),
+            entry(SyncOpKind::Overwrite, "changed.txt", &drive.id),
+        ];
+
+        let drives = vec![drive];
+        let steps = plan_undo(&journal, &drives, &HashMap::new(), Utc::now());
+        assert_eq!(steps.len(), 2);
+        // Newest first: the overwrite had no archive, so can't be reversed.
+        assert!(matches!(steps[0].action, UndoAction::Unrecoverable(_)));
+        assert!(matches!(steps[1].action, UndoAction::Remove));
+
+        let conn = diffr_db::open_memory_db().unwrap();
+        apply_undo_step(&conn, &steps[1], &drives).unwrap();
+        assert!(!dir.path().join("new.txt").exists());
+
+        // A file touched after the sync is left alone.
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("new.txt"), "n").unwrap();
+        let drive = Drive::new(DriveIdentity::new_synthetic(), dir.path().to_path_buf());
+        let journal = vec![entry(SyncOpKind::CopyNew, "new.txt", &drive.id)];
+        let earlier = Utc::now() - chrono::Duration::hours(1);
+        let steps = plan_undo(&journal, &[drive], &HashMap::new(), earlier);
+        assert!(matches!(steps[0].action, UndoAction::Unrecoverable(_)));
+    }
+}
@@ -0,0 +1,103 @@
+use diffr_core::models::file_entry::FileEntry;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::dedupe::group_dir;
+
+/// Files and bytes under one key of a breakdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageRow<K> {
+    pub key: K,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Where a drive's (or cluster's) space goes, largest first.
+#[derive(Debug, Default)]
+pub struct UsageBreakdown {
+    pub files: u64,
+    pub bytes: u64,
+    /// Lowercased extension without the dot; `(none)` for files without one.
+    pub by_extension: Vec<UsageRow<String>>,
+    /// Directory `depth` levels deep; `.` for files at the root.
+    pub by_directory: Vec<UsageRow<PathBuf>>,
+}
+
+/// Total up cataloged files by extension and by directory.
+pub fn breakdown(entries: &[FileEntry], depth: usize) -> UsageBreakdown {
+    let mut extensions: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut directories: HashMap<PathBuf, (u64, u64)> = HashMap::new();
+    let mut result = UsageBreakdown::default();
+    for entry in entries.iter().filter(|e| !e.is_dir) {
+        let ext = entry
+            .rel_path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "(none)".to_string());
+        for slot in [
+            extensions.entry(ext).or_default(),
+            directories.entry(group_dir(&entry.rel_path, depth)).or_default(),
+        ] {
+            slot.0 += 1;
+            slot.1 += entry.size;
+        }
+        result.files += 1;
+        result.bytes += entry.size;
+    }
+    result.by_extension = sorted(extensions);
+    result.by_directory = sorted(directories);
+    result
+}
+
+fn sorted<K: Ord>(totals: HashMap<K, (u64, u64)>) -> Vec<UsageRow<K>> {
+    let mut rows: Vec<UsageRow<K>> = totals
+        .into_iter()
+        .map(|(key, (files, bytes))| UsageRow { key, files, bytes })
+        .collect();
+    rows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use diffr_core::models::drive::DriveId;
+
+    #[test]
+    fn test_breakdown() {
+        let drive = DriveId::new();
+        let file = |path: &str, size: u64| FileEntry {
+            rel_path: path.into(),
+            drive_id: drive.clone(),
+            is_dir: false,
+            size,
+            mtime: Utc::now(),
+            xxh3_hash: None,
+            sampled_hash: None,
+            sha256_hash: None,
+            blake3_hash: None,
+            permissions: None,
+            indexed_at: Utc::now(),
+        };
+        let entries = vec![
+            file("photos/2024/a.JPG", 300),
+            file("photos/2025/b.jpg", 200),
+            file("docs/notes.txt", 50),
+            file("Makefile", 10),
+        ];
+
+        let usage = breakdown(&entries, 1);
+        assert_eq!((usage.files, usage.bytes), (4, 560));
+        let ext: Vec<_> = usage.by_extension.iter().map(|r| (r.key.as_str(), r.bytes)).collect();
+        assert_eq!(ext, vec![("jpg", 500), ("txt", 50), ("(none)", 10)]);
+        let dirs: Vec<_> = usage
+            .by_directory
+            .iter()
+            .map(|r| (r.key.display().to_string(), r.files))
+            .collect();
+        assert_eq!(dirs, vec![("photos".into(), 2), ("docs".into(), 1), (".".into(), 1)]);
+
+        assert_eq!(breakdown(&entries, 2).by_directory[0].key, PathBuf::from("photos/2024"));
+    }
+}
@@ -0,0 +1,174 @@
+use diffr_core::models::cluster::{Cluster, Topology};
+use diffr_core::models::drive::{Drive, DriveRole};
+use std::path::Path;
+
+/// A broken cluster membership rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Fewer than two drives that take part in sync; archive-only drives
+    /// don't count.
+    TooFewSyncDrives { have: usize },
+    /// A primary-replica cluster with no primary.
+    NoPrimary,
+    /// More than one drive marked primary.
+    MultiplePrimaries(Vec<String>),
+    /// Two members' sync roots are the same directory or one contains the
+    /// other.
+    NestedRoots { outer: String, inner: String },
+    /// A member's sync root overlaps a drive in another cluster.
+    OverlapsOtherCluster { drive: String, other: String },
+}
+
+impl Violation {
+    /// Expected while a cluster is still being set up, so only sync treats
+    /// it as an error.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, Violation::TooFewSyncDrives { .. } | Violation::NoPrimary)
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::TooFewSyncDrives { have } => write!(
+                f,
+                "needs at least 2 drives that sync (has {}; archive-only drives don't count)",
+                have
+            ),
+            Violation::NoPrimary => write!(f, "primary-replica cluster has no primary drive"),
+            Violation::MultiplePrimaries(drives) => {
+                write!(f, "more than one primary drive: {}", drives.join(", "))
+            }
+            Violation::NestedRoots { outer, inner } => {
+                write!(f, "sync root of {} is inside the sync root of {}", inner, outer)
+            }
+            Violation::OverlapsOtherCluster { drive, other } => write!(
+                f,
+                "sync root of {} overlaps {}, which belongs to another cluster",
+                drive, other
+            ),
+        }
+    }
+}
+
+/// Check a cluster's members against the membership rules. `all_drives`
+/// is every registered drive, used to find overlaps with other clusters.
+pub fn validate_cluster(cluster: &Cluster, members: &[Drive], all_drives: &[Drive]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let describe = |d: &Drive| {
+        format!(
+            "{} ({})",
+            d.identity.identity_string(),
+            d.effective_root().display()
+        )
+    };
+
+    let syncing = members
+        .iter()
+        .filter(|d| d.role != DriveRole::ArchiveOnly)
+        .count();
+    if syncing < 2 {
+        violations.push(Violation::TooFewSyncDrives { have: syncing });
+    }
+
+    let primaries: Vec<String> = members
+        .iter()
+        .filter(|d| d.is_primary)
+        .map(|d| d.identity.identity_string().to_string())
+        .collect();
+    if primaries.len() > 1 {
+        violations.push(Violation::MultiplePrimaries(primaries));
+    } else if primaries.is_empty() && cluster.topology == Topology::PrimaryReplica {
+        violations.push(Violation::NoPrimary);
+    }
+
+    for (i, a) in members.iter().enumerate() {
+        for b in &members[i + 1..] {
+            if let Some((outer, inner)) = nesting(a, b) {
+                violations.push(Violation::NestedRoots {
+                    outer: describe(outer),
+                    inner: describe(inner),
+                });
+            }
+        }
+        for other in all_drives {
+            let elsewhere = other.cluster_id.is_some() && other.cluster_id != Some(cluster.id.clone());
+            if elsewhere && other.id != a.id && nesting(a, other).is_some() {
+                violations.push(Violation::OverlapsOtherCluster {
+                    drive: describe(a),
+                    other: describe(other),
+                });
+            }
+        }
+    }
+
+    violations
+}
+
+/// If one drive's root is (or is inside) the other's, the outer and inner
+/// drive in that order. Relative roots, left by drives that were never
+/// mounted, can't be compared.
+fn nesting<'a>(a: &'a Drive, b: &'a Drive) -> Option<(&'a Drive, &'a Drive)> {
+    let (ra, rb): (&Path, &Path) = (a.effective_root(), b.effective_root());
+    if !ra.is_absolute() || !rb.is_absolute() {
+        return None;
+    }
+    if rb.starts_with(ra) {
+        Some((a, b))
+    } else if ra.starts_with(rb) {
+        Some((b, a))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diffr_core::models::cluster::ConflictStrategy;
+    use diffr_core::models::drive::DriveIdentity;
+    use std::path::PathBuf;
+
+    fn drive(serial: &str, root: &str, cluster: &Cluster) -> Drive {
+        let mut d = Drive::new(DriveIdentity::new_hardware(serial.into()), PathBuf::from(root));
+        d.cluster_id = Some(cluster.id.clone());
+        d
+    }
+
+    #[test]
+    fn test_validate_cluster() {
+        let cluster = Cluster::new("c".into(), Topology::PrimaryReplica, ConflictStrategy::NewestWins);
+        let other = Cluster::new("o".into(), Topology::Mesh, ConflictStrategy::NewestWins);
+
+        let mut a = drive("A", "/data/a", &cluster);
+        let mut b = drive("B", "/data/a/nested", &cluster);
+        let mut archive = drive("C", "/archive", &cluster);
+        archive.role = DriveRole::ArchiveOnly;
+        let elsewhere = drive("X", "/data/a/x", &other);
+        let members = vec![a.clone(), b.clone(), archive.clone()];
+        let all = vec![a.clone(), b.clone(), archive.clone(), elsewhere.clone()];
+
+        let violations = validate_cluster(&cluster, &members, &all);
+        assert!(violations.contains(&Violation::NoPrimary));
+        assert!(violations
+            .iter()
+            .any(|v| matches!(v, Violation::NestedRoots { inner, .. } if inner.starts_with("B"))));
+        assert!(violations
+            .iter()
+            .any(|v| matches!(v, Violation::OverlapsOtherCluster { other, .. } if other.starts_with("X"))));
+
+        a.is_primary = true;
+        b.is_primary = true;
+        b.mount_point = PathBuf::from("/data/b");
+        let members = vec![a.clone(), b.clone(), archive.clone()];
+        let violations = validate_cluster(&cluster, &members, &members);
+        assert_eq!(violations, vec![Violation::MultiplePrimaries(vec!["A".into(), "B".into()])]);
+
+        // Archive-only drives don't count towards the two needed to sync.
+        b.is_primary = false;
+        let members = vec![a, archive];
+        let violations = validate_cluster(&cluster, &members, &members);
+        assert_eq!(violations, vec![Violation::TooFewSyncDrives { have: 1 }]);
+        assert!(violations[0].is_incomplete());
+    }
+}